@@ -0,0 +1,174 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use crate::error::CliError;
+
+// The command line arguments
+// --------------------------
+//
+// The arguments of a subcommand are the positional arguments (e.g. the input files)
+// and the options, e.g.
+//
+// `$ anasm assemble main.ancasm -o main.o --target x86_64-unknown-linux-gnu`
+//
+// - the option with value can be written as `-o main.o`, `--output main.o`
+//   or `--output=main.o`, and can be repeated (e.g. `-l m -l pthread`).
+// - the option without value (i.e. the flag) can not be repeated.
+// - the arguments after `--` are passed through (e.g. to the program
+//   executed by `anasm run`).
+
+/// The specification of an option, the first name is the key.
+#[derive(Debug, Clone)]
+pub struct OptionSpec {
+    pub names: &'static [&'static str],
+    pub takes_value: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ParsedArgs {
+    pub positional: Vec<String>,
+    pub values: Vec<(&'static str, String)>,
+    pub flags: Vec<&'static str>,
+
+    /// The arguments after `--`.
+    pub rest: Vec<String>,
+}
+
+impl ParsedArgs {
+    /// Get the last value of the option.
+    pub fn get_value(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get all values of the repeated option, in order.
+    pub fn get_values(&self, key: &str) -> Vec<String> {
+        self.values
+            .iter()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    pub fn has_flag(&self, key: &str) -> bool {
+        self.flags.contains(&key)
+    }
+}
+
+pub fn parse_args(args: &[String], specs: &[OptionSpec]) -> Result<ParsedArgs, CliError> {
+    let mut parsed_args = ParsedArgs::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            parsed_args.rest = iter.cloned().collect();
+            break;
+        }
+
+        if !arg.starts_with('-') || arg == "-" {
+            parsed_args.positional.push(arg.clone());
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+
+        let spec = specs
+            .iter()
+            .find(|spec| spec.names.contains(&name))
+            .ok_or_else(|| CliError::Usage(format!("unknown option \"{}\"", name)))?;
+        let key = spec.names[0];
+
+        if spec.takes_value {
+            let value = match inline_value {
+                Some(value) => value,
+                None => iter.next().cloned().ok_or_else(|| {
+                    CliError::Usage(format!("option \"{}\" requires a value", name))
+                })?,
+            };
+            parsed_args.values.push((key, value));
+        } else if inline_value.is_some() {
+            return Err(CliError::Usage(format!(
+                "option \"{}\" does not take a value",
+                name
+            )));
+        } else if parsed_args.flags.contains(&key) {
+            return Err(CliError::Usage(format!(
+                "option \"{}\" is specified more than once",
+                name
+            )));
+        } else {
+            parsed_args.flags.push(key);
+        }
+    }
+
+    Ok(parsed_args)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::args::{parse_args, OptionSpec};
+
+    #[test]
+    fn test_parse_args() {
+        let specs = [
+            OptionSpec {
+                names: &["--output", "-o"],
+                takes_value: true,
+            },
+            OptionSpec {
+                names: &["--library", "-l"],
+                takes_value: true,
+            },
+            OptionSpec {
+                names: &["--static"],
+                takes_value: false,
+            },
+        ];
+
+        let args = [
+            "a.o",
+            "-o",
+            "app",
+            "--library=m",
+            "-l",
+            "c",
+            "--static",
+            "b.o",
+            "--",
+            "-x",
+        ]
+        .map(|arg| arg.to_owned());
+        let parsed_args = parse_args(&args, &specs).unwrap();
+
+        assert_eq!(parsed_args.positional, vec!["a.o", "b.o"]);
+        assert_eq!(parsed_args.get_value("--output"), Some("app"));
+        assert_eq!(parsed_args.get_value("--library"), Some("c"));
+        assert_eq!(parsed_args.get_values("--library"), vec!["m", "c"]);
+        assert!(parsed_args.has_flag("--static"));
+        assert_eq!(parsed_args.rest, vec!["-x"]);
+
+        assert_eq!(
+            parse_args(&["-q".to_owned()], &specs)
+                .unwrap_err()
+                .to_string(),
+            "unknown option \"-q\""
+        );
+        assert_eq!(
+            parse_args(&["-o".to_owned()], &specs)
+                .unwrap_err()
+                .to_string(),
+            "option \"-o\" requires a value"
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::path::{Path, PathBuf};
+
+use assembler::{
+    address_significance::link_address_significance_table,
+    ast,
+    branch_weight::read_branch_profile,
+    code_generator::{Generator, ObjectOptions},
+    compilation_cache::CompilationCache,
+    conditional::Conditions,
+    diagnostic::Diagnostic,
+    elf_note::ElfNote,
+    function_order::read_function_profile,
+    lowering::{assemble_module, AssembledModule},
+    resolver::{resolve_module, SourceFiles},
+};
+use cranelift_module::Module;
+use cranelift_object::ObjectModule;
+
+use crate::{
+    args::{parse_args, OptionSpec},
+    error::CliError,
+};
+
+// The subcommand "assemble"
+// -------------------------
+//
+// Assemble a source file into an object file.
+//
+// `$ anasm assemble main.ancasm [-o main.o] [--target <triple>] [--no-pic] [--icf] [--profile <path>] [--branch-profile <path>] [-I <path>]... [-F <feature>]...`
+//
+// - the output file defaults to the input file with the extension ".o".
+// - `-I` adds the search paths of the modules which are imported
+//   by `(import (module "path"))`, see `resolver.rs`.
+// - `-F` enables the features of the conditional blocks `(when-feature ...)`,
+//   and the blocks `(when-target ...)` are evaluated against the target,
+//   see `conditional.rs`.
+// - the target defaults to "x86_64-unknown-linux-gnu".
+// - `--no-pic` generates the position-dependent code, which is required
+//   by the static executables (see `Generator::new_static()`).
+// - `--icf` places each function in its own section and appends the
+//   address-significance table, so the identical functions can be folded by
+//   the linker (see `anasm link --icf`).
+// - `--profile` reads the call counts of the functions (see `function_order.rs`),
+//   and the hot functions are placed together in the order of the counts.
+// - `--branch-profile` reads the execution counts of the branches (see
+//   `branch_weight.rs`), and the cold paths of `if` and `when` are placed
+//   at the end of the functions.
+
+pub const DEFAULT_TARGET: &str = "x86_64-unknown-linux-gnu";
+
+/// The version in the manifest of the plugin (see `anasm build --plugin`),
+/// the source files have no version.
+const PLUGIN_VERSION: &str = "0.0.0";
+
+/// The targets which are supported by the generator and the linker.
+pub const SUPPORTED_TARGETS: [&str; 6] = [
+    "x86_64-unknown-linux-gnu",
+    "x86_64-unknown-linux-musl",
+    "aarch64-unknown-linux-gnu",
+    "aarch64-unknown-linux-musl",
+    "riscv64gc-unknown-linux-gnu",
+    "riscv64gc-unknown-linux-musl",
+];
+
+pub const ASSEMBLE_USAGE: &str =
+    "anasm assemble <input.ancasm> [-o <output.o>] [--target <triple>] [--no-pic] [--icf] [--profile <path>] [--branch-profile <path>] [-I <path>]... [-F <feature>]...";
+
+/// The option of the search paths of the imported modules, it is shared
+/// by the subcommands which assemble the source files.
+pub const MODULE_PATH_OPTION: OptionSpec = OptionSpec {
+    names: &["--module-path", "-I"],
+    takes_value: true,
+};
+
+/// The option of the features of the conditional compilation.
+pub const FEATURE_OPTION: OptionSpec = OptionSpec {
+    names: &["--feature", "-F"],
+    takes_value: true,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleOptions {
+    pub target: String,
+    pub is_pic: bool,
+
+    /// Prepare the object file for the identical code folding, i.e. the
+    /// function sections and the address-significance table.
+    pub icf: bool,
+
+    /// The search paths of the imported modules.
+    pub module_paths: Vec<String>,
+
+    /// The enabled features of the conditional compilation.
+    pub features: Vec<String>,
+
+    /// Generate the entry table of the plugin, see `plugin.rs` of the assembler.
+    pub plugin: bool,
+
+    /// The path of the profile file which orders the functions.
+    pub profile: Option<String>,
+
+    /// The path of the profile file of the branches.
+    pub branch_profile: Option<String>,
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        Self {
+            target: DEFAULT_TARGET.to_owned(),
+            is_pic: true,
+            icf: false,
+            module_paths: vec![],
+            features: vec![],
+            plugin: false,
+            profile: None,
+            branch_profile: None,
+        }
+    }
+}
+
+pub fn read_source_file(file_path: &str) -> Result<String, CliError> {
+    std::fs::read_to_string(file_path).map_err(|error| CliError::Io {
+        file_path: file_path.to_owned(),
+        error,
+    })
+}
+
+pub fn write_output_file(file_path: &str, content: &[u8]) -> Result<(), CliError> {
+    std::fs::write(file_path, content).map_err(|error| CliError::Io {
+        file_path: file_path.to_owned(),
+        error,
+    })
+}
+
+/// Convert the diagnostic to the error of the file which contains it.
+pub fn to_source_error(source_files: &SourceFiles, diagnostic: Diagnostic) -> CliError {
+    match source_files.locate(diagnostic.span) {
+        Some((file, span)) => CliError::Source {
+            file_path: file.file_path.clone(),
+            source: file.source.clone(),
+            diagnostic: Diagnostic { span, ..diagnostic },
+        },
+        None => CliError::Other(diagnostic.message),
+    }
+}
+
+/// Parse the source text and merge the modules which it imports, the conditional
+/// blocks are evaluated against the target and the features of the options.
+pub fn parse_source(
+    file_path: &str,
+    source: &str,
+    options: &AssembleOptions,
+) -> Result<(ast::Module, SourceFiles), CliError> {
+    let search_paths = options
+        .module_paths
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    let conditions = Conditions::new(&options.target, &options.features);
+    let mut source_files = SourceFiles::new();
+    match resolve_module(
+        file_path,
+        source,
+        &search_paths,
+        &conditions,
+        &mut source_files,
+    ) {
+        Ok(module) => Ok((module, source_files)),
+        Err(diagnostic) => Err(to_source_error(&source_files, diagnostic)),
+    }
+}
+
+/// Assemble the module with the compilation cache (if any), the cache is
+/// lent to the generator and is taken back even if the assembling fails.
+pub fn assemble_module_with_cache<T: Module>(
+    module: &ast::Module,
+    generator: &mut Generator<T>,
+    compilation_cache: Option<&mut CompilationCache>,
+) -> Result<AssembledModule, Diagnostic> {
+    let Some(compilation_cache) = compilation_cache else {
+        return assemble_module(module, generator);
+    };
+
+    generator.enable_compilation_cache(std::mem::take(compilation_cache));
+    let result = assemble_module(module, generator);
+    *compilation_cache = generator.compilation_cache.take().unwrap();
+    result
+}
+
+/// Assemble the source text and return the content of the object file.
+pub fn assemble_source(
+    file_path: &str,
+    source: &str,
+    options: &AssembleOptions,
+    compilation_cache: Option<&mut CompilationCache>,
+) -> Result<Vec<u8>, CliError> {
+    if !SUPPORTED_TARGETS.contains(&options.target.as_str()) {
+        return Err(CliError::Usage(format!(
+            "the target \"{}\" is not supported, the supported targets are: {}",
+            options.target,
+            SUPPORTED_TARGETS.join(", ")
+        )));
+    }
+
+    let (module, source_files) = parse_source(file_path, source, options)?;
+
+    let mut generator = Generator::<ObjectModule>::new_with_options(
+        &module.name,
+        Some(&options.target),
+        &ObjectOptions {
+            is_pic: options.is_pic,
+            function_sections: options.icf,
+        },
+    );
+    if options.icf {
+        generator.enable_address_significance_table();
+    }
+    if let Some(profile_file_path) = &options.profile {
+        let profile = read_function_profile(profile_file_path).map_err(|error| CliError::Io {
+            file_path: profile_file_path.clone(),
+            error,
+        })?;
+        generator.set_function_profile(profile);
+    }
+    if let Some(profile_file_path) = &options.branch_profile {
+        let profile = read_branch_profile(profile_file_path).map_err(|error| CliError::Io {
+            file_path: profile_file_path.clone(),
+            error,
+        })?;
+        generator.set_branch_profile(profile);
+    }
+    let assembled_module = assemble_module_with_cache(&module, &mut generator, compilation_cache)
+        .map_err(|diagnostic| to_source_error(&source_files, diagnostic))?;
+
+    if options.plugin {
+        generator
+            .define_plugin_entries(&module, &assembled_module, PLUGIN_VERSION)
+            .map_err(|error| {
+                CliError::Other(format!("failed to define the plugin entries: {}", error))
+            })?;
+    }
+
+    // the libraries of `extern-c` are recorded for the link step
+    for library in module.get_libraries() {
+        generator.add_elf_note(ElfNote::new_library(library));
+    }
+
+    let mut object = generator
+        .finish()
+        .map_err(|error| CliError::Other(format!("failed to write the object file: {}", error)))?
+        .emit()
+        .map_err(|error| CliError::Other(format!("failed to write the object file: {}", error)))?;
+    link_address_significance_table(&mut object);
+    Ok(object)
+}
+
+/// Get the path of the object file of the source file, e.g. "src/main.ancasm" -> "src/main.o".
+pub fn get_object_file_path(source_file_path: &str) -> String {
+    Path::new(source_file_path)
+        .with_extension("o")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn run_assemble(args: &[String]) -> Result<(), CliError> {
+    let parsed_args = parse_args(
+        args,
+        &[
+            OptionSpec {
+                names: &["--output", "-o"],
+                takes_value: true,
+            },
+            OptionSpec {
+                names: &["--target"],
+                takes_value: true,
+            },
+            OptionSpec {
+                names: &["--no-pic"],
+                takes_value: false,
+            },
+            OptionSpec {
+                names: &["--icf"],
+                takes_value: false,
+            },
+            OptionSpec {
+                names: &["--profile"],
+                takes_value: true,
+            },
+            OptionSpec {
+                names: &["--branch-profile"],
+                takes_value: true,
+            },
+            MODULE_PATH_OPTION,
+            FEATURE_OPTION,
+        ],
+    )?;
+
+    let [input_file_path] = parsed_args.positional.as_slice() else {
+        return Err(CliError::Usage(format!("usage: {}", ASSEMBLE_USAGE)));
+    };
+
+    let options = AssembleOptions {
+        target: parsed_args
+            .get_value("--target")
+            .unwrap_or(DEFAULT_TARGET)
+            .to_owned(),
+        is_pic: !parsed_args.has_flag("--no-pic"),
+        icf: parsed_args.has_flag("--icf"),
+        module_paths: parsed_args.get_values("--module-path"),
+        features: parsed_args.get_values("--feature"),
+        plugin: false,
+        profile: parsed_args.get_value("--profile").map(str::to_owned),
+        branch_profile: parsed_args.get_value("--branch-profile").map(str::to_owned),
+    };
+
+    let output_file_path = match parsed_args.get_value("--output") {
+        Some(path) => path.to_owned(),
+        None => get_object_file_path(input_file_path),
+    };
+
+    let source = read_source_file(input_file_path)?;
+    let object = assemble_source(input_file_path, &source, &options, None)?;
+    write_output_file(&output_file_path, &object)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        assemble::{get_object_file_path, run_assemble},
+        error::CliError,
+    };
+
+    #[test]
+    fn test_assemble() {
+        let folder =
+            std::env::temp_dir().join(format!("anasm_test_assemble_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let source_file_path = folder.join("main.ancasm");
+        std::fs::write(
+            &source_file_path,
+            "(module $main (function $main export (result i32) (code (imm_i32 0))))",
+        )
+        .unwrap();
+
+        let source_file_path = source_file_path.to_str().unwrap().to_owned();
+        run_assemble(std::slice::from_ref(&source_file_path)).unwrap();
+
+        let object_file_path = get_object_file_path(&source_file_path);
+        assert_eq!(object_file_path, folder.join("main.o").to_str().unwrap());
+        assert!(std::fs::read(&object_file_path)
+            .unwrap()
+            .starts_with(b"\x7fELF"));
+
+        // the profile of the functions
+        let profile_file_path = folder.join("main.profile").to_str().unwrap().to_owned();
+        std::fs::write(&profile_file_path, "# calls function\n10 main\n").unwrap();
+        let args = [
+            source_file_path.clone(),
+            "--profile".to_owned(),
+            profile_file_path.clone(),
+        ];
+        run_assemble(&args).unwrap();
+
+        std::fs::write(&profile_file_path, "main 10\n").unwrap();
+        let error = run_assemble(&args).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{}: invalid line 1 of the profile: \"main 10\", expect \"count name\"",
+                profile_file_path
+            )
+        );
+
+        // the profile of the branches
+        let branch_profile_file_path = folder.join("main.branches").to_str().unwrap().to_owned();
+        std::fs::write(&branch_profile_file_path, "10 0 main#0\n").unwrap();
+        let args = [
+            source_file_path.clone(),
+            "--branch-profile".to_owned(),
+            branch_profile_file_path.clone(),
+        ];
+        run_assemble(&args).unwrap();
+
+        std::fs::write(&branch_profile_file_path, "10 main#0\n").unwrap();
+        let error = run_assemble(&args).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{}: invalid line 1 of the branch profile: \"10 main#0\", expect \"taken not_taken function#index\"",
+                branch_profile_file_path
+            )
+        );
+
+        // the error of the source file
+        std::fs::write(
+            &source_file_path,
+            "(module $main (function $main (code (add_i32))))",
+        )
+        .unwrap();
+        let error = run_assemble(std::slice::from_ref(&source_file_path)).unwrap_err();
+        assert!(matches!(error, CliError::Source { .. }));
+        assert_eq!(error.exit_code(), 1);
+        assert!(error
+            .to_string()
+            .starts_with("error: the instruction \"add_i32\" requires 2 operand(s), found 0"));
+
+        // the invalid arguments
+        let error =
+            run_assemble(&[source_file_path, "--target".to_owned(), "z80".to_owned()]).unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Display;
+
+use assembler::diagnostic::Diagnostic;
+
+// The errors of the command line tool
+// -----------------------------------
+//
+// The errors are printed to stderr, and the exit codes are:
+//
+// - 0: success.
+// - 1: the source files contain errors, or the I/O, the code generation
+//      or the linking fails.
+// - 2: the command line arguments are invalid.
+//
+// the exit code of the program is passed through by `anasm run`.
+
+pub const EXIT_CODE_ERROR: i32 = 1;
+pub const EXIT_CODE_USAGE: i32 = 2;
+
+#[derive(Debug)]
+pub enum CliError {
+    /// The invalid command line arguments.
+    Usage(String),
+
+    /// The error of the source file.
+    Source {
+        file_path: String,
+        source: String,
+        diagnostic: Diagnostic,
+    },
+
+    Io {
+        file_path: String,
+        error: std::io::Error,
+    },
+
+    /// The other errors, e.g. the object emission and the linking.
+    Other(String),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => EXIT_CODE_USAGE,
+            _ => EXIT_CODE_ERROR,
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}", message),
+            CliError::Source {
+                file_path,
+                source,
+                diagnostic,
+            } => write!(f, "{}", diagnostic.render(file_path, source).trim_end()),
+            CliError::Io { file_path, error } => write!(f, "{}: {}", file_path, error),
+            CliError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use assembler::formatter::format_source;
+
+use crate::{
+    args::{parse_args, OptionSpec},
+    assemble::{read_source_file, write_output_file},
+    error::CliError,
+};
+
+// The subcommand "fmt"
+// --------------------
+//
+// Format the source files in the canonical style (see `formatter.rs`) in place.
+//
+// `$ anasm fmt <input.ancasm>... [--check]`
+//
+// - `--check` does not write the files, it fails (i.e. exits with 1) and lists
+//   the files if any of them is not formatted, e.g. for the pre-commit hooks.
+// - the files which are already formatted are not written, so their
+//   modification times are kept.
+
+pub const FORMAT_USAGE: &str = "anasm fmt <input.ancasm>... [--check]";
+
+pub fn run_format(args: &[String]) -> Result<(), CliError> {
+    let parsed_args = parse_args(
+        args,
+        &[OptionSpec {
+            names: &["--check"],
+            takes_value: false,
+        }],
+    )?;
+    if parsed_args.positional.is_empty() {
+        return Err(CliError::Usage(format!("usage: {}", FORMAT_USAGE)));
+    }
+
+    let is_check = parsed_args.has_flag("--check");
+    let mut unformatted_file_paths = vec![];
+
+    for file_path in &parsed_args.positional {
+        let source = read_source_file(file_path)?;
+        let formatted = format_source(&source).map_err(|diagnostic| CliError::Source {
+            file_path: file_path.clone(),
+            source: source.clone(),
+            diagnostic,
+        })?;
+
+        if formatted != source {
+            if is_check {
+                unformatted_file_paths.push(file_path.as_str());
+            } else {
+                write_output_file(file_path, formatted.as_bytes())?;
+            }
+        }
+    }
+
+    if unformatted_file_paths.is_empty() {
+        Ok(())
+    } else {
+        Err(CliError::Other(format!(
+            "the files are not formatted: {}",
+            unformatted_file_paths.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::format::run_format;
+
+    #[test]
+    fn test_format() {
+        let folder = std::env::temp_dir().join(format!("anasm_test_format_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let file_path = folder.join("main.ancasm");
+        let file_path_string = file_path.to_str().unwrap().to_owned();
+        std::fs::write(
+            &file_path,
+            "(module $main (function $main export (result i32) (code (imm_i32 0))))",
+        )
+        .unwrap();
+
+        let error = run_format(&[file_path_string.clone(), "--check".to_owned()]).unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+
+        run_format(std::slice::from_ref(&file_path_string)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&file_path).unwrap(),
+            "(module $main\n    (function $main export (result i32) (code (imm_i32 0))))\n"
+        );
+        run_format(&[file_path_string, "--check".to_owned()]).unwrap();
+
+        let error = run_format(&[]).unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,633 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use assembler::{
+    compilation_cache::CompilationCache,
+    elf_note::read_required_libraries,
+    import_check::check_imported_data,
+    linker::{get_shell_command, link_executable, LinkerMode, LinkerOptions, SymbolicBinding},
+    position_independence::find_absolute_references,
+};
+
+use crate::{
+    args::{parse_args, OptionSpec, ParsedArgs},
+    assemble::{
+        assemble_source, read_source_file, write_output_file, AssembleOptions, DEFAULT_TARGET,
+        FEATURE_OPTION, MODULE_PATH_OPTION,
+    },
+    error::CliError,
+};
+
+// The subcommands "link" and "build"
+// ----------------------------------
+//
+// Link the object files as an executable file (or a shared library):
+//
+// `$ anasm link main.o lib.o -o app [--static|--no-pie|--shared|--freestanding] [-L <path>]... [-l <name>]...`
+//
+// Assemble the source files and link them in one step, the object files
+// are written to a temporary folder:
+//
+// `$ anasm build main.ancasm lib.ancasm -o app [--target <triple>] [-I <path>]... [-F <feature>]... [link options]`
+//
+// the link options:
+//
+// - `--static`, `--no-pie`, `--shared` and `--freestanding` select the `LinkerMode`,
+//   the default mode is PIE. `anasm build` generates the position-dependent code
+//   for `--static`, `--no-pie` and `--freestanding`.
+// - `-L <path>` and `-l <name>` add the library search paths and the libraries,
+//   the libraries which are required by the object files (i.e. the `library`
+//   of `extern-c`) are added automatically.
+// - `--build-id <style>` generates the GNU build-id note.
+// - `-Bsymbolic-functions` and `-Bsymbolic` (only for `--shared`) bind the
+//   references to the exported functions (or all exported symbols) within
+//   the shared library, so the intra-library calls avoid the PLT.
+// - `--linker <path>` selects the program of the linker, it defaults to 'ld'.
+// - `--icf` folds the identical functions (i.e. `--icf=safe`), it requires a
+//   linker which supports it, e.g. `--linker ld.gold`. `anasm build` also
+//   places each function in its own section, and the object files for
+//   `anasm link` should be assembled by `anasm assemble --icf`.
+// - `--entry <symbol>` sets the entry point (i.e. `ld -e`), e.g. the bootstrap
+//   of the runtime, the freestanding executable (without the CRT files and
+//   the libc) starts from `_start` by default.
+// - `--plugin` (only for `anasm build`) builds the shared library which
+//   exports the entry table of the plugin (see `plugin.rs` of the assembler),
+//   so it can be loaded by the host application by `dlopen()`, it accepts
+//   one source file, and the exported functions are bound within the library.
+// - `--dry-run` (only for `anasm link`) prints the command line of the linker
+//   instead of running it, e.g. for wrapping the link step by the build systems.
+//
+// the object files of `--shared` should be position-independent (i.e. not
+// assembled by `anasm assemble --no-pic`), the absolute addresses in the code
+// are reported before linking, see `position_independence.rs` of the assembler.
+//
+// the expected sizes and alignments of the imported data (i.e. `(import (data ... (size n)))`)
+// are checked after linking, see `import_check.rs` of the assembler.
+
+pub const LINK_USAGE: &str =
+    "anasm link <input.o>... -o <output> [--static|--no-pie|--shared|--freestanding] [-L <path>]... [-l <name>]... [--build-id <style>] [-Bsymbolic[-functions]] [--linker <path>] [--icf] [--entry <symbol>] [--dry-run]";
+
+pub const BUILD_USAGE: &str =
+    "anasm build <input.ancasm>... -o <output> [--target <triple>] [-I <path>]... [-F <feature>]... [--static|--no-pie|--shared|--freestanding] [-L <path>]... [-l <name>]... [--linker <path>] [--icf] [--entry <symbol>] [--plugin]";
+
+const LINK_OPTION_SPECS: [OptionSpec; 13] = [
+    OptionSpec {
+        names: &["--output", "-o"],
+        takes_value: true,
+    },
+    OptionSpec {
+        names: &["--static"],
+        takes_value: false,
+    },
+    OptionSpec {
+        names: &["--no-pie"],
+        takes_value: false,
+    },
+    OptionSpec {
+        names: &["--shared"],
+        takes_value: false,
+    },
+    OptionSpec {
+        names: &["--library-path", "-L"],
+        takes_value: true,
+    },
+    OptionSpec {
+        names: &["--library", "-l"],
+        takes_value: true,
+    },
+    OptionSpec {
+        names: &["--build-id"],
+        takes_value: true,
+    },
+    OptionSpec {
+        names: &["-Bsymbolic-functions"],
+        takes_value: false,
+    },
+    OptionSpec {
+        names: &["-Bsymbolic"],
+        takes_value: false,
+    },
+    OptionSpec {
+        names: &["--linker"],
+        takes_value: true,
+    },
+    OptionSpec {
+        names: &["--icf"],
+        takes_value: false,
+    },
+    OptionSpec {
+        names: &["--freestanding"],
+        takes_value: false,
+    },
+    OptionSpec {
+        names: &["--entry", "-e"],
+        takes_value: true,
+    },
+];
+
+fn get_linker_options(parsed_args: &ParsedArgs) -> Result<LinkerOptions, CliError> {
+    let modes = [
+        ("--static", LinkerMode::Static),
+        ("--no-pie", LinkerMode::NoPie),
+        ("--shared", LinkerMode::Shared),
+        ("--freestanding", LinkerMode::Freestanding),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| parsed_args.has_flag(flag))
+    .collect::<Vec<_>>();
+
+    let mode = match modes.as_slice() {
+        [] => LinkerMode::Pie,
+        [(_, mode)] => *mode,
+        _ => {
+            return Err(CliError::Usage(
+                "the options \"--static\", \"--no-pie\", \"--shared\" and \"--freestanding\" are exclusive"
+                    .to_owned(),
+            ))
+        }
+    };
+
+    let symbolic = if parsed_args.has_flag("-Bsymbolic") {
+        SymbolicBinding::All
+    } else if parsed_args.has_flag("-Bsymbolic-functions") {
+        SymbolicBinding::Functions
+    } else {
+        SymbolicBinding::Interposable
+    };
+
+    let mut options = LinkerOptions {
+        mode,
+        build_id: parsed_args.get_value("--build-id").map(|s| s.to_owned()),
+        symbolic,
+        icf: parsed_args.has_flag("--icf"),
+        entry: parsed_args.get_value("--entry").map(|s| s.to_owned()),
+        ..LinkerOptions::default()
+    };
+    if let Some(linker) = parsed_args.get_value("--linker") {
+        options.linker = linker.to_owned();
+    }
+    options
+        .library_paths
+        .extend(parsed_args.get_values("--library-path"));
+    options
+        .libraries
+        .extend(parsed_args.get_values("--library"));
+
+    Ok(options)
+}
+
+fn get_output_file_path(parsed_args: &ParsedArgs, usage: &str) -> Result<String, CliError> {
+    parsed_args
+        .get_value("--output")
+        .map(|path| path.to_owned())
+        .ok_or_else(|| CliError::Usage(format!("the output file is required, usage: {}", usage)))
+}
+
+/// Link the object files, the libraries which are required by the object
+/// files are added, the object files of the shared library are checked for
+/// the absolute addresses, and the layouts of the imported data are checked,
+/// returns the command line of the linker.
+pub fn link_object_files(
+    object_file_paths: &[&str],
+    output_file_path: &str,
+    options: &LinkerOptions,
+) -> Result<Vec<String>, CliError> {
+    let mut options = options.clone();
+    for object_file_path in object_file_paths {
+        let object_binary = std::fs::read(object_file_path).map_err(|error| CliError::Io {
+            file_path: (*object_file_path).to_owned(),
+            error,
+        })?;
+        for library in read_required_libraries(&object_binary) {
+            if !options.libraries.contains(&library) {
+                options.libraries.push(library);
+            }
+        }
+
+        // report the text relocations before the linker does
+        if options.mode == LinkerMode::Shared {
+            let references = find_absolute_references(&object_binary).map_err(CliError::Other)?;
+            if !references.is_empty() {
+                return Err(CliError::Other(format!(
+                    "failed to link \"{}\": the object file \"{}\" is not position-independent, {}",
+                    output_file_path,
+                    object_file_path,
+                    references
+                        .iter()
+                        .map(|reference| reference.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
+        }
+    }
+
+    let command_line =
+        link_executable(object_file_paths, output_file_path, &options).map_err(|error| {
+            CliError::Other(format!(
+                "failed to link \"{}\": {}",
+                output_file_path, error
+            ))
+        })?;
+
+    if !options.dry_run {
+        let output_binary = std::fs::read(output_file_path).map_err(|error| CliError::Io {
+            file_path: output_file_path.to_owned(),
+            error,
+        })?;
+        let mismatches = check_imported_data(&output_binary).map_err(CliError::Other)?;
+        if !mismatches.is_empty() {
+            return Err(CliError::Other(format!(
+                "failed to link \"{}\": {}",
+                output_file_path,
+                mismatches
+                    .iter()
+                    .map(|mismatch| mismatch.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+    }
+
+    Ok(command_line)
+}
+
+pub fn run_link(args: &[String]) -> Result<(), CliError> {
+    let mut option_specs = LINK_OPTION_SPECS.to_vec();
+    option_specs.push(OptionSpec {
+        names: &["--dry-run"],
+        takes_value: false,
+    });
+    let parsed_args = parse_args(args, &option_specs)?;
+    if parsed_args.positional.is_empty() {
+        return Err(CliError::Usage(format!("usage: {}", LINK_USAGE)));
+    }
+
+    let output_file_path = get_output_file_path(&parsed_args, LINK_USAGE)?;
+    let options = LinkerOptions {
+        dry_run: parsed_args.has_flag("--dry-run"),
+        ..get_linker_options(&parsed_args)?
+    };
+
+    let object_file_paths = parsed_args
+        .positional
+        .iter()
+        .map(|path| path.as_str())
+        .collect::<Vec<_>>();
+    let command_line = link_object_files(&object_file_paths, &output_file_path, &options)?;
+    if options.dry_run {
+        println!("{}", get_shell_command(&command_line));
+    }
+    Ok(())
+}
+
+/// The options of `anasm build` (and `anasm watch`), i.e. the link options,
+/// `--target`, `--module-path` and `--feature`.
+pub fn get_build_option_specs() -> Vec<OptionSpec> {
+    let mut option_specs = LINK_OPTION_SPECS.to_vec();
+    option_specs.push(OptionSpec {
+        names: &["--target"],
+        takes_value: true,
+    });
+    option_specs.push(OptionSpec {
+        names: &["--plugin"],
+        takes_value: false,
+    });
+    option_specs.push(MODULE_PATH_OPTION);
+    option_specs.push(FEATURE_OPTION);
+    option_specs
+}
+
+/// Get the options of assembling and linking, the position-dependent code
+/// is generated for `--static`, `--no-pie` and `--freestanding`.
+pub fn get_build_options(
+    parsed_args: &ParsedArgs,
+) -> Result<(AssembleOptions, LinkerOptions), CliError> {
+    let mut linker_options = get_linker_options(parsed_args)?;
+    let plugin = parsed_args.has_flag("--plugin");
+    if plugin {
+        if linker_options.mode != LinkerMode::Pie {
+            return Err(CliError::Usage(
+                "the option \"--plugin\" builds a shared library, it can not be used with the other modes"
+                    .to_owned(),
+            ));
+        }
+        if parsed_args.positional.len() > 1 {
+            return Err(CliError::Usage(
+                "the option \"--plugin\" accepts only one source file".to_owned(),
+            ));
+        }
+        linker_options.mode = LinkerMode::Shared;
+        linker_options.symbolic = SymbolicBinding::Functions;
+    }
+
+    let assemble_options = AssembleOptions {
+        target: parsed_args
+            .get_value("--target")
+            .unwrap_or(DEFAULT_TARGET)
+            .to_owned(),
+        is_pic: !matches!(
+            linker_options.mode,
+            LinkerMode::Static | LinkerMode::NoPie | LinkerMode::Freestanding
+        ),
+        icf: linker_options.icf,
+        module_paths: parsed_args.get_values("--module-path"),
+        features: parsed_args.get_values("--feature"),
+        plugin,
+        profile: None,
+        branch_profile: None,
+    };
+    Ok((assemble_options, linker_options))
+}
+
+/// Assemble the source files and link them, the object files are written
+/// to a temporary folder which is removed after linking.
+pub fn build_executable(
+    source_file_paths: &[String],
+    output_file_path: &str,
+    assemble_options: &AssembleOptions,
+    linker_options: &LinkerOptions,
+    compilation_cache: Option<&mut CompilationCache>,
+) -> Result<(), CliError> {
+    // the serial number distinguishes the builds of the same process (e.g. the tests)
+    static BUILD_SERIAL: AtomicUsize = AtomicUsize::new(0);
+    let temp_folder = std::env::temp_dir().join(format!(
+        "anasm_build_{}_{}",
+        std::process::id(),
+        BUILD_SERIAL.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&temp_folder).map_err(|error| CliError::Io {
+        file_path: temp_folder.to_string_lossy().into_owned(),
+        error,
+    })?;
+
+    let result = build_in_folder(
+        source_file_paths,
+        &temp_folder,
+        output_file_path,
+        assemble_options,
+        linker_options,
+        compilation_cache,
+    );
+
+    // the temporary folder is removed even if the building fails
+    let _ = std::fs::remove_dir_all(&temp_folder);
+    result
+}
+
+fn build_in_folder(
+    source_file_paths: &[String],
+    temp_folder: &std::path::Path,
+    output_file_path: &str,
+    assemble_options: &AssembleOptions,
+    linker_options: &LinkerOptions,
+    mut compilation_cache: Option<&mut CompilationCache>,
+) -> Result<(), CliError> {
+    let mut object_file_paths: Vec<PathBuf> = vec![];
+
+    for (index, source_file_path) in source_file_paths.iter().enumerate() {
+        let source = read_source_file(source_file_path)?;
+        let object = assemble_source(
+            source_file_path,
+            &source,
+            assemble_options,
+            compilation_cache.as_deref_mut(),
+        )?;
+
+        // the index avoids the collision of the files with the same name
+        // in different folders
+        let object_file_path = temp_folder.join(format!("{}.o", index));
+        write_output_file(&object_file_path.to_string_lossy(), &object)?;
+        object_file_paths.push(object_file_path);
+    }
+
+    let object_file_paths = object_file_paths
+        .iter()
+        .map(|path| path.to_str().unwrap())
+        .collect::<Vec<_>>();
+    link_object_files(&object_file_paths, output_file_path, linker_options).map(|_| ())
+}
+
+pub fn run_build(args: &[String]) -> Result<(), CliError> {
+    let parsed_args = parse_args(args, &get_build_option_specs())?;
+    if parsed_args.positional.is_empty() {
+        return Err(CliError::Usage(format!("usage: {}", BUILD_USAGE)));
+    }
+
+    let output_file_path = get_output_file_path(&parsed_args, BUILD_USAGE)?;
+    let (assemble_options, linker_options) = get_build_options(&parsed_args)?;
+    build_executable(
+        &parsed_args.positional,
+        &output_file_path,
+        &assemble_options,
+        &linker_options,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use assembler::{
+        plugin::PLUGIN_ENTRY_TABLE_NAME,
+        symbol_listing::{read_symbols, SymbolScope},
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        assemble::run_assemble,
+        link::{run_build, run_link},
+    };
+
+    #[test]
+    fn test_link_and_build() {
+        let folder = std::env::temp_dir().join(format!("anasm_test_link_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let get_path = |name: &str| folder.join(name).to_str().unwrap().to_owned();
+
+        std::fs::write(
+            get_path("number.ancasm"),
+            "(module $number (function $get_number export (result i32) (code (imm_i32 11))))",
+        )
+        .unwrap();
+        std::fs::write(
+            get_path("main.ancasm"),
+            "(module $main
+                (import (function $get_number (result i32)))
+                (function $main export (result i32)
+                    (code (add_i32 (call $get_number) (imm_i32 2)))))",
+        )
+        .unwrap();
+
+        // assemble and link
+        run_assemble(&[get_path("number.ancasm")]).unwrap();
+        run_assemble(&[get_path("main.ancasm")]).unwrap();
+        run_link(&[
+            get_path("main.o"),
+            get_path("number.o"),
+            "-o".to_owned(),
+            get_path("app"),
+        ])
+        .unwrap();
+        assert_eq!(
+            Command::new(get_path("app")).status().unwrap().code(),
+            Some(13)
+        );
+
+        // build the static executable
+        run_build(&[
+            get_path("main.ancasm"),
+            get_path("number.ancasm"),
+            "--static".to_owned(),
+            "-o".to_owned(),
+            get_path("app_static"),
+        ])
+        .unwrap();
+        assert_eq!(
+            Command::new(get_path("app_static"))
+                .status()
+                .unwrap()
+                .code(),
+            Some(13)
+        );
+
+        // the library which is required by `extern-c` is linked automatically
+        std::fs::write(
+            get_path("round.ancasm"),
+            r#"(module $round
+                (extern-c "lround" (params f64) (results i64) (library "m"))
+                (function $main export (result i32)
+                    (code (truncate_i64_to_i32 (call $lround (imm_f64 12.6))))))"#,
+        )
+        .unwrap();
+        run_build(&[
+            get_path("round.ancasm"),
+            "-o".to_owned(),
+            get_path("app_round"),
+        ])
+        .unwrap();
+        assert_eq!(
+            Command::new(get_path("app_round")).status().unwrap().code(),
+            Some(13)
+        );
+
+        // the dry run does not run the linker
+        run_link(&[
+            get_path("main.o"),
+            "-o".to_owned(),
+            get_path("app_dry_run"),
+            "--dry-run".to_owned(),
+        ])
+        .unwrap();
+        assert!(!std::path::Path::new(&get_path("app_dry_run")).exists());
+
+        // the freestanding executable starts from the custom entry point
+        run_link(&[
+            get_path("main.o"),
+            get_path("number.o"),
+            "--freestanding".to_owned(),
+            "--entry".to_owned(),
+            "__anna_start".to_owned(),
+            "-o".to_owned(),
+            get_path("app_freestanding"),
+            "--dry-run".to_owned(),
+        ])
+        .unwrap();
+
+        // the shared library which binds the exported functions within itself
+        run_link(&[
+            get_path("number.o"),
+            "--shared".to_owned(),
+            "-Bsymbolic-functions".to_owned(),
+            "-o".to_owned(),
+            get_path("libnumber.so"),
+        ])
+        .unwrap();
+        assert!(std::path::Path::new(&get_path("libnumber.so")).exists());
+
+        // the position-dependent code can not be linked as a shared library
+        run_assemble(&[
+            get_path("main.ancasm"),
+            "--no-pic".to_owned(),
+            "-o".to_owned(),
+            get_path("main_no_pic.o"),
+        ])
+        .unwrap();
+        let error = run_link(&[
+            get_path("main_no_pic.o"),
+            "--shared".to_owned(),
+            "-o".to_owned(),
+            get_path("libmain.so"),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains(
+            "is not position-independent, the section \".text\" refers to \"get_number\""
+        ));
+
+        // the plugin exports the entry table
+        run_build(&[
+            get_path("number.ancasm"),
+            "--plugin".to_owned(),
+            "-o".to_owned(),
+            get_path("libnumber_plugin.so"),
+        ])
+        .unwrap();
+        let plugin_binary = std::fs::read(get_path("libnumber_plugin.so")).unwrap();
+        assert!(read_symbols(&plugin_binary)
+            .unwrap()
+            .iter()
+            .any(|symbol| symbol.name == PLUGIN_ENTRY_TABLE_NAME
+                && symbol.scope == SymbolScope::Export));
+
+        let error = run_build(&[
+            get_path("main.ancasm"),
+            get_path("number.ancasm"),
+            "--plugin".to_owned(),
+            "-o".to_owned(),
+            get_path("libapp_plugin.so"),
+        ])
+        .unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+
+        // fold the identical functions by 'ld.gold'
+        run_build(&[
+            get_path("main.ancasm"),
+            get_path("number.ancasm"),
+            "--linker".to_owned(),
+            "ld.gold".to_owned(),
+            "--icf".to_owned(),
+            "-o".to_owned(),
+            get_path("app_icf"),
+        ])
+        .unwrap();
+        assert_eq!(
+            Command::new(get_path("app_icf")).status().unwrap().code(),
+            Some(13)
+        );
+
+        // the undefined symbol
+        let error =
+            run_link(&[get_path("main.o"), "-o".to_owned(), get_path("app_error")]).unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+
+        let error = run_link(&[
+            get_path("main.o"),
+            "--static".to_owned(),
+            "--shared".to_owned(),
+        ])
+        .unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+mod args;
+mod assemble;
+mod error;
+mod format;
+mod link;
+mod nm;
+mod objdump;
+mod repl;
+mod run;
+mod test_runner;
+mod verify;
+mod watch;
+
+use assemble::{run_assemble, ASSEMBLE_USAGE};
+use error::{CliError, EXIT_CODE_USAGE};
+use format::{run_format, FORMAT_USAGE};
+use link::{run_build, run_link, BUILD_USAGE, LINK_USAGE};
+use nm::{run_nm, NM_USAGE};
+use objdump::{run_objdump, OBJDUMP_USAGE};
+use repl::{run_repl, REPL_USAGE};
+use run::{run_program, RUN_USAGE};
+use test_runner::{run_tests, TEST_USAGE};
+use verify::{run_verify, VERIFY_USAGE};
+use watch::{run_watch, WATCH_USAGE};
+
+// The command line tool of the XiaoXuan native assembler
+// ------------------------------------------------------
+//
+// `$ anasm <subcommand> [arguments]`
+//
+// the subcommands:
+//
+// - assemble: assemble a source file into an object file.
+// - link: link the object files as an executable file or a shared library.
+// - build: assemble the source files and link them in one step.
+// - run: execute a source file by JIT, the exit code of the program is passed through.
+// - repl: evaluate the definitions and expressions interactively.
+// - watch: rebuild or re-run when the source files change.
+// - fmt: format the source files in the canonical style.
+// - test: run the test functions of a source file by JIT.
+// - verify: compare the results of the JIT and the object file of a source file.
+// - nm: list the symbols of the object files.
+// - objdump: dump the functions of an object file, or the disassembly listing of a source file.
+
+fn print_usage() {
+    eprintln!("usage:");
+    for usage in [
+        ASSEMBLE_USAGE,
+        LINK_USAGE,
+        BUILD_USAGE,
+        RUN_USAGE,
+        REPL_USAGE,
+        WATCH_USAGE,
+        FORMAT_USAGE,
+        TEST_USAGE,
+        VERIFY_USAGE,
+        NM_USAGE,
+        OBJDUMP_USAGE,
+    ] {
+        eprintln!("    {}", usage);
+    }
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    let Some((subcommand, subcommand_args)) = args.split_first() else {
+        print_usage();
+        std::process::exit(EXIT_CODE_USAGE);
+    };
+
+    let result = match subcommand.as_str() {
+        "assemble" => run_assemble(subcommand_args).map(|_| 0),
+        "link" => run_link(subcommand_args).map(|_| 0),
+        "build" => run_build(subcommand_args).map(|_| 0),
+        "run" => run_program(subcommand_args),
+        "repl" => run_repl(subcommand_args).map(|_| 0),
+        "watch" => run_watch(subcommand_args).map(|_| 0),
+        "fmt" => run_format(subcommand_args).map(|_| 0),
+        "test" => run_tests(subcommand_args),
+        "verify" => run_verify(subcommand_args),
+        "nm" => run_nm(subcommand_args).map(|_| 0),
+        "objdump" => run_objdump(subcommand_args).map(|_| 0),
+        "help" | "--help" | "-h" => {
+            print_usage();
+            Ok(0)
+        }
+        _ => Err(CliError::Usage(format!(
+            "unknown subcommand \"{}\"",
+            subcommand
+        ))),
+    };
+
+    match result {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(error) => {
+            match error {
+                CliError::Source { .. } => eprintln!("{}", error),
+                _ => eprintln!("error: {}", error),
+            }
+            if let CliError::Usage(_) = error {
+                print_usage();
+            }
+            std::process::exit(error.exit_code());
+        }
+    }
+}
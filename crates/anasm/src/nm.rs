@@ -0,0 +1,151 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use assembler::symbol_listing::{format_symbols, read_symbols, SymbolScope};
+
+use crate::{
+    args::{parse_args, OptionSpec},
+    error::CliError,
+};
+
+// The subcommand "nm"
+// -------------------
+//
+// List the symbols of the object files (see `symbol_listing.rs`), e.g. to check
+// what a module exports and imports without the binutils.
+//
+// `$ anasm nm <input.o>... [--export|--import]`
+//
+// - `--export` lists the exported symbols only, and `--import` lists
+//   the imported symbols only.
+// - the file name is printed before the symbols of each file when there
+//   are multiple files.
+
+pub const NM_USAGE: &str = "anasm nm <input.o>... [--export|--import]";
+
+pub fn run_nm(args: &[String]) -> Result<(), CliError> {
+    let parsed_args = parse_args(
+        args,
+        &[
+            OptionSpec {
+                names: &["--export"],
+                takes_value: false,
+            },
+            OptionSpec {
+                names: &["--import"],
+                takes_value: false,
+            },
+        ],
+    )?;
+    if parsed_args.positional.is_empty() {
+        return Err(CliError::Usage(format!("usage: {}", NM_USAGE)));
+    }
+
+    let scope = match (
+        parsed_args.has_flag("--export"),
+        parsed_args.has_flag("--import"),
+    ) {
+        (false, false) => None,
+        (true, false) => Some(SymbolScope::Export),
+        (false, true) => Some(SymbolScope::Import),
+        (true, true) => {
+            return Err(CliError::Usage(
+                "the options \"--export\" and \"--import\" are exclusive".to_owned(),
+            ))
+        }
+    };
+
+    let is_multiple = parsed_args.positional.len() > 1;
+    for (index, file_path) in parsed_args.positional.iter().enumerate() {
+        let text = list_symbols(file_path, scope)?;
+        if is_multiple {
+            if index > 0 {
+                println!();
+            }
+            println!("{}:", file_path);
+        }
+        print!("{}", text);
+    }
+    Ok(())
+}
+
+/// Get the symbol listing of the object file, only the symbols of
+/// the given scope are listed if it is present.
+fn list_symbols(file_path: &str, scope: Option<SymbolScope>) -> Result<String, CliError> {
+    let object_binary = std::fs::read(file_path).map_err(|error| CliError::Io {
+        file_path: file_path.to_owned(),
+        error,
+    })?;
+    let symbols = read_symbols(&object_binary)
+        .map_err(|message| CliError::Other(format!("{}: {}", file_path, message)))?
+        .into_iter()
+        .filter(|symbol| scope.is_none_or(|scope| symbol.scope == scope))
+        .collect::<Vec<_>>();
+    Ok(format_symbols(&symbols))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use assembler::symbol_listing::SymbolScope;
+
+    use crate::{
+        assemble::run_assemble,
+        nm::{list_symbols, run_nm},
+    };
+
+    #[test]
+    fn test_nm() {
+        let folder = std::env::temp_dir().join(format!("anasm_test_nm_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let source_file_path = folder.join("main.ancasm");
+        std::fs::write(
+            &source_file_path,
+            r#"(module $main
+                (import (function $exit (param i32)))
+                (function $main export (result i32)
+                    (code (call $exit (imm_i32 0)) (imm_i32 0))))"#,
+        )
+        .unwrap();
+        run_assemble(&[source_file_path.to_str().unwrap().to_owned()]).unwrap();
+
+        let object_file_path = folder.join("main.o").to_str().unwrap().to_owned();
+        let names = |text: String| {
+            text.lines()
+                .map(|line| line.split_whitespace().last().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(list_symbols(&object_file_path, None).unwrap()),
+            vec!["exit", "main"]
+        );
+        assert_eq!(
+            names(list_symbols(&object_file_path, Some(SymbolScope::Import)).unwrap()),
+            vec!["exit"]
+        );
+
+        // not an object file
+        assert_eq!(
+            run_nm(&[source_file_path.to_str().unwrap().to_owned()])
+                .unwrap_err()
+                .exit_code(),
+            1
+        );
+
+        let error = run_nm(&[
+            object_file_path,
+            "--export".to_owned(),
+            "--import".to_owned(),
+        ])
+        .unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
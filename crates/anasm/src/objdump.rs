@@ -0,0 +1,146 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use assembler::{
+    code_generator::Generator,
+    lowering::assemble_module,
+    object_dump::{format_functions, read_functions},
+};
+use cranelift_object::ObjectModule;
+
+use crate::{
+    args::{parse_args, OptionSpec},
+    assemble::{
+        parse_source, read_source_file, to_source_error, AssembleOptions, DEFAULT_TARGET,
+        FEATURE_OPTION, MODULE_PATH_OPTION,
+    },
+    error::CliError,
+};
+
+// The subcommand "objdump"
+// ------------------------
+//
+// Dump the functions of an object file or an executable (see `object_dump.rs`),
+// i.e. the disassembly with the symbolized call targets and data references:
+//
+// `$ anasm objdump <input.o|input.elf>`
+//
+// the source file is assembled and the full disassembly listing (i.e. the CLIF,
+// the machine instructions and the machine code, see `disassembly.rs`) is
+// dumped instead:
+//
+// `$ anasm objdump <input.ancasm> [--target <triple>] [-I <path>]... [-F <feature>]...`
+//
+// - the source file is recognized by the extension ".ancasm".
+
+pub const OBJDUMP_USAGE: &str =
+    "anasm objdump <input.o|input.elf|input.ancasm> [--target <triple>] [-I <path>]... [-F <feature>]...";
+
+pub fn run_objdump(args: &[String]) -> Result<(), CliError> {
+    let parsed_args = parse_args(
+        args,
+        &[
+            OptionSpec {
+                names: &["--target"],
+                takes_value: true,
+            },
+            MODULE_PATH_OPTION,
+            FEATURE_OPTION,
+        ],
+    )?;
+
+    let [input_file_path] = parsed_args.positional.as_slice() else {
+        return Err(CliError::Usage(format!("usage: {}", OBJDUMP_USAGE)));
+    };
+
+    let text = if input_file_path.ends_with(".ancasm") {
+        let options = AssembleOptions {
+            target: parsed_args
+                .get_value("--target")
+                .unwrap_or(DEFAULT_TARGET)
+                .to_owned(),
+            module_paths: parsed_args.get_values("--module-path"),
+            features: parsed_args.get_values("--feature"),
+            ..AssembleOptions::default()
+        };
+        dump_source(input_file_path, &options)?
+    } else {
+        dump_object(input_file_path)?
+    };
+
+    print!("{}", text);
+    Ok(())
+}
+
+fn dump_object(file_path: &str) -> Result<String, CliError> {
+    let object_binary = std::fs::read(file_path).map_err(|error| CliError::Io {
+        file_path: file_path.to_owned(),
+        error,
+    })?;
+    let functions = read_functions(&object_binary)
+        .map_err(|message| CliError::Other(format!("{}: {}", file_path, message)))?;
+    Ok(format_functions(&functions))
+}
+
+fn dump_source(file_path: &str, options: &AssembleOptions) -> Result<String, CliError> {
+    let source = read_source_file(file_path)?;
+    let (module, source_files) = parse_source(file_path, &source, options)?;
+
+    let mut generator = Generator::<ObjectModule>::new(&module.name, Some(&options.target));
+    generator.enable_listing();
+    assemble_module(&module, &mut generator)
+        .map_err(|diagnostic| to_source_error(&source_files, diagnostic))?;
+
+    Ok(generator
+        .listing
+        .as_ref()
+        .unwrap()
+        .to_text(&generator.source_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assemble::run_assemble,
+        objdump::{dump_object, dump_source, run_objdump},
+    };
+
+    #[test]
+    fn test_objdump() {
+        let folder =
+            std::env::temp_dir().join(format!("anasm_test_objdump_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let source_file_path = folder.join("main.ancasm");
+        std::fs::write(
+            &source_file_path,
+            r#"(module $main
+                (import (function $exit (param i32)))
+                (function $main export (result i32)
+                    (code (call $exit (imm_i32 0)) (imm_i32 0))))"#,
+        )
+        .unwrap();
+        let source_file_path = source_file_path.to_str().unwrap().to_owned();
+        run_assemble(std::slice::from_ref(&source_file_path)).unwrap();
+
+        let text = dump_object(folder.join("main.o").to_str().unwrap()).unwrap();
+        assert!(text.starts_with("function main (section: .text, address: 0x0, size: "));
+        assert!(text.contains("  movq    exit@GOTPCREL(%rip), "));
+
+        let text = dump_source(&source_file_path, &Default::default()).unwrap();
+        assert!(text.starts_with("function main (size: "));
+        assert!(text.contains(";; disassembly\n"));
+
+        // not an object file
+        let error = dump_object(folder.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+
+        let error = run_objdump(&[]).unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+use assembler::{
+    ast::{ImportNode, Instruction, InstructionKind, ValueType},
+    code_generator::Generator,
+    lexer::{tokenize, TokenKind},
+    lowering::{assemble_module_hotswap, SymbolTable},
+    parser::parse_module,
+};
+use cranelift_jit::JITModule;
+
+use crate::{args::parse_args, error::CliError};
+
+// The subcommand "repl"
+// ---------------------
+//
+// Read the definitions and the expressions from stdin, the definitions are
+// assembled by JIT incrementally, and the expressions are evaluated, e.g.
+//
+// ```text
+// > (function $inc (param $a i32) (result i32) (code (add_i32 (local_load $a) (imm_i32 1))))
+// > (call $inc (imm_i32 41))
+// (imm_i32 42)
+// ```
+//
+// - the input which starts with `(import`, `(data` or `(function` is a definition,
+//   a function with the same name and signature replaces the former one
+//   (i.e. the hot-swap), so the functions which call it call the new definition.
+// - the other input is the instruction sequence of an expression, it is
+//   assembled as a function and called, the value is printed as an immediate
+//   instruction. The expression has zero or one value.
+// - the input can span multiple lines until the parentheses are balanced.
+// - the imported functions are resolved from the current process (i.e. libc).
+// - `:quit` (or EOF) exits.
+
+pub const REPL_USAGE: &str = "anasm repl";
+
+const REPL_FILE_PATH: &str = "<repl>";
+
+pub struct Repl {
+    generator: Generator<JITModule>,
+    symbol_table: SymbolTable,
+
+    /// The results of the imported and defined functions, for inferring
+    /// the type of the expression.
+    function_results: HashMap<String, Vec<ValueType>>,
+    expression_count: usize,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            generator: Generator::<JITModule>::new_hotswap(vec![]),
+            symbol_table: SymbolTable::default(),
+            function_results: HashMap::new(),
+            expression_count: 0,
+        }
+    }
+
+    /// Evaluate the (complete) input, returns the text of the value if the input
+    /// is an expression which has a value.
+    pub fn evaluate(&mut self, input: &str) -> Result<Option<String>, CliError> {
+        let is_definition = tokenize(input)
+            .ok()
+            .map(|tokens| {
+                let mut kinds = tokens
+                    .into_iter()
+                    .map(|token| token.kind)
+                    .filter(|kind| !matches!(kind, TokenKind::Comment(_)));
+                matches!(
+                    (kinds.next(), kinds.next()),
+                    (Some(TokenKind::LeftParen), Some(TokenKind::Identifier(keyword)))
+                        if matches!(keyword.as_str(), "import" | "data" | "function")
+                )
+            })
+            .unwrap_or(false);
+
+        if is_definition {
+            self.define(input)?;
+            Ok(None)
+        } else {
+            self.evaluate_expression(input)
+        }
+    }
+
+    fn define(&mut self, input: &str) -> Result<(), CliError> {
+        // the input is placed on its own lines, so the diagnostics show it
+        let source = format!("(module $repl\n{}\n)", input);
+        let module =
+            parse_module(&source).map_err(|diagnostic| to_source_error(&source, diagnostic))?;
+        assemble_module_hotswap(&module, &mut self.generator, &mut self.symbol_table)
+            .map_err(|diagnostic| to_source_error(&source, diagnostic))?;
+        self.finalize()?;
+
+        for import in &module.imports {
+            if let ImportNode::Function(node) = import {
+                self.function_results
+                    .insert(node.name.clone(), node.results.clone());
+            }
+        }
+        for node in &module.functions {
+            self.function_results
+                .insert(node.name.clone(), node.results.clone());
+        }
+        Ok(())
+    }
+
+    fn evaluate_expression(&mut self, input: &str) -> Result<Option<String>, CliError> {
+        let name = format!("__repl_eval_{}", self.expression_count);
+        self.expression_count += 1;
+
+        let source = format!("(module $repl (function ${} (code\n{}\n)))", name, input);
+        let mut module =
+            parse_module(&source).map_err(|diagnostic| to_source_error(&source, diagnostic))?;
+
+        let function = &mut module.functions[0];
+        let results = match function.body.last() {
+            Some(instruction) => self.infer_result_types(instruction),
+            None => vec![],
+        };
+        if results.len() > 1 {
+            return Err(CliError::Other(
+                "the expression with multiple values is not supported".to_owned(),
+            ));
+        }
+        function.results = results.clone();
+
+        let assembled_module =
+            assemble_module_hotswap(&module, &mut self.generator, &mut self.symbol_table)
+                .map_err(|diagnostic| to_source_error(&source, diagnostic))?;
+        self.finalize()?;
+
+        let func_ptr = self
+            .generator
+            .module
+            .get_finalized_function(assembled_module.get_function_id(&name).unwrap());
+
+        // SAFETY: the signature of the function is `() -> results`.
+        let text = unsafe {
+            match results.first() {
+                None => {
+                    let func: extern "C" fn() = std::mem::transmute(func_ptr);
+                    func();
+                    return Ok(None);
+                }
+                Some(ValueType::I32) => {
+                    let func: extern "C" fn() -> i32 = std::mem::transmute(func_ptr);
+                    format!("(imm_i32 {})", func())
+                }
+                Some(ValueType::I64) => {
+                    let func: extern "C" fn() -> i64 = std::mem::transmute(func_ptr);
+                    format!("(imm_i64 {})", func())
+                }
+                Some(ValueType::F32) => {
+                    let func: extern "C" fn() -> f32 = std::mem::transmute(func_ptr);
+                    format!("(imm_f32 {:?})", func())
+                }
+                Some(ValueType::F64) => {
+                    let func: extern "C" fn() -> f64 = std::mem::transmute(func_ptr);
+                    format!("(imm_f64 {:?})", func())
+                }
+            }
+        };
+
+        Ok(Some(text))
+    }
+
+    fn finalize(&mut self) -> Result<(), CliError> {
+        self.generator
+            .module
+            .finalize_definitions()
+            .map_err(|error| {
+                CliError::Other(format!("failed to finalize the functions: {}", error))
+            })
+    }
+
+    /// Infer the types of the values of the instruction, the instructions
+    /// which do not fall through or have no value return an empty list.
+    fn infer_result_types(&self, instruction: &Instruction) -> Vec<ValueType> {
+        match &instruction.kind {
+            InstructionKind::ImmI32(_) => vec![ValueType::I32],
+            InstructionKind::ImmI64(_) => vec![ValueType::I64],
+            InstructionKind::ImmF32(_) => vec![ValueType::F32],
+            InstructionKind::ImmF64(_) => vec![ValueType::F64],
+            InstructionKind::DataLoad { load_type, .. }
+            | InstructionKind::MemoryLoad { load_type, .. } => vec![load_type.value_type()],
+            InstructionKind::HostAddrFunction(_)
+            | InstructionKind::HostAddrData { .. }
+            | InstructionKind::ReadCycleCounter => vec![ValueType::I64],
+            InstructionKind::Operation { opcode, .. } => vec![opcode.result_type()],
+            InstructionKind::Do(instructions) => match instructions.last() {
+                Some(last) => self.infer_result_types(last),
+                None => vec![],
+            },
+            InstructionKind::If { results, .. }
+            | InstructionKind::For { results, .. }
+            | InstructionKind::DynCall { results, .. }
+            | InstructionKind::Clif { results, .. } => results.clone(),
+            InstructionKind::Asm { outputs, .. } => {
+                outputs.iter().map(|(_, value_type)| *value_type).collect()
+            }
+            InstructionKind::Call { name, .. } => {
+                self.function_results.get(name).cloned().unwrap_or_default()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+fn to_source_error(source: &str, diagnostic: assembler::diagnostic::Diagnostic) -> CliError {
+    CliError::Source {
+        file_path: REPL_FILE_PATH.to_owned(),
+        source: source.to_owned(),
+        diagnostic,
+    }
+}
+
+/// Check whether the parentheses of the input are balanced, the input
+/// with lexical errors is regarded as complete so the error is reported.
+fn is_complete(input: &str) -> bool {
+    match tokenize(input) {
+        Ok(tokens) => {
+            let depth = tokens.iter().fold(0, |depth, token| match token.kind {
+                TokenKind::LeftParen => depth + 1,
+                TokenKind::RightParen => depth - 1,
+                _ => depth,
+            });
+            depth <= 0
+        }
+        Err(_) => true,
+    }
+}
+
+pub fn run_repl(args: &[String]) -> Result<(), CliError> {
+    let parsed_args = parse_args(args, &[])?;
+    if !parsed_args.positional.is_empty() {
+        return Err(CliError::Usage(format!("usage: {}", REPL_USAGE)));
+    }
+
+    let mut repl = Repl::new();
+    let mut input = String::new();
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        let _ = stdout.flush();
+
+        let mut line = String::new();
+        let length = stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|error| CliError::Io {
+                file_path: "<stdin>".to_owned(),
+                error,
+            })?;
+        if length == 0 {
+            println!();
+            return Ok(());
+        }
+
+        if input.is_empty() && line.trim() == ":quit" {
+            return Ok(());
+        }
+
+        input.push_str(&line);
+        if input.trim().is_empty() {
+            input.clear();
+            continue;
+        }
+        if !is_complete(&input) {
+            continue;
+        }
+
+        match repl.evaluate(input.trim_end()) {
+            Ok(Some(text)) => println!("{}", text),
+            Ok(None) => {}
+            Err(error @ CliError::Source { .. }) => eprintln!("{}", error),
+            Err(error) => eprintln!("error: {}", error),
+        }
+        input.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::repl::{is_complete, Repl};
+
+    #[test]
+    fn test_repl() {
+        assert!(is_complete("(imm_i32 1)"));
+        assert!(!is_complete("(function $f\n(code"));
+
+        let mut repl = Repl::new();
+        let mut evaluate = |input: &str| repl.evaluate(input).map_err(|error| error.to_string());
+
+        assert_eq!(
+            evaluate("(add_i32 (imm_i32 1) (imm_i32 2))"),
+            Ok(Some("(imm_i32 3)".to_owned()))
+        );
+        assert_eq!(
+            evaluate("(imm_f64 1.5)"),
+            Ok(Some("(imm_f64 1.5)".to_owned()))
+        );
+
+        // the definitions
+        assert_eq!(
+            evaluate("(import (function $abs \"abs\" (param i32) (result i32)))"),
+            Ok(None)
+        );
+        assert_eq!(
+            evaluate("(function $get (result i32) (code (call $abs (imm_i32 -7))))"),
+            Ok(None)
+        );
+        assert_eq!(
+            evaluate("(function $twice (result i32) (code (mul_i32 (call $get) (imm_i32 2))))"),
+            Ok(None)
+        );
+        assert_eq!(
+            evaluate("(call $twice)"),
+            Ok(Some("(imm_i32 14)".to_owned()))
+        );
+
+        // the hot-swap
+        assert_eq!(
+            evaluate("(function $get (result i32) (code (imm_i32 100)))"),
+            Ok(None)
+        );
+        assert_eq!(
+            evaluate("(call $twice)"),
+            Ok(Some("(imm_i32 200)".to_owned()))
+        );
+
+        // the errors
+        assert!(evaluate("(call $missing)")
+            .unwrap_err()
+            .starts_with("error: "));
+        assert_eq!(
+            evaluate("(do (imm_i32 1) (imm_i64 2))"),
+            Ok(Some("(imm_i64 2)".to_owned()))
+        );
+    }
+}
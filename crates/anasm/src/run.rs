@@ -0,0 +1,288 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::ffi::{c_char, CString};
+
+use assembler::{
+    ast::{FunctionNode, ValueType},
+    code_generator::Generator,
+    compilation_cache::CompilationCache,
+    conditional::Conditions,
+    diagnostic::Diagnostic,
+};
+use cranelift_jit::JITModule;
+
+use crate::{
+    args::{parse_args, OptionSpec, ParsedArgs},
+    assemble::{
+        assemble_module_with_cache, parse_source, read_source_file, to_source_error,
+        AssembleOptions, FEATURE_OPTION, MODULE_PATH_OPTION,
+    },
+    error::CliError,
+};
+
+// The subcommand "run"
+// --------------------
+//
+// Assemble the source file by the JIT backend and execute the entry function
+// immediately, the arguments after `--` are passed to the program.
+//
+// `$ anasm run program.ancasm [--entry <name>] [-I <path>]... [-F <feature>]... [-- args...]`
+//
+// - the entry function defaults to "main", and the signature should be one of:
+//   `()`, `() -> i32`, `(argc: i32, argv: i64)` and `(argc: i32, argv: i64) -> i32`.
+// - the `argv[0]` is the path of the source file.
+// - the target of the conditional blocks is the current machine.
+// - the imported functions are resolved from the current process (i.e. libc)
+//   by `dlsym`.
+// - the return value of the entry function is the exit code of `anasm`,
+//   it is 0 if the entry function has no return value.
+
+pub const RUN_USAGE: &str =
+    "anasm run <input.ancasm> [--entry <name>] [-I <path>]... [-F <feature>]... [-- args...]";
+
+const DEFAULT_ENTRY: &str = "main";
+
+pub fn check_entry_signature(node: &FunctionNode) -> Result<(bool, bool), Diagnostic> {
+    let param_types = node
+        .params
+        .iter()
+        .map(|param| param.value_type)
+        .collect::<Vec<_>>();
+
+    let has_args = match param_types.as_slice() {
+        [] => false,
+        [ValueType::I32, ValueType::I64] => true,
+        _ => {
+            return Err(Diagnostic::new(
+                &format!(
+                    "the parameters of the entry function \"${}\" are invalid",
+                    node.name
+                ),
+                node.span,
+            )
+            .with_note(
+                "the parameters should be empty or \"(param $argc i32) (param $argv i64)\"",
+            ))
+        }
+    };
+
+    let has_result = match node.results.as_slice() {
+        [] => false,
+        [ValueType::I32] => true,
+        _ => {
+            return Err(Diagnostic::new(
+                &format!(
+                    "the results of the entry function \"${}\" are invalid",
+                    node.name
+                ),
+                node.span,
+            )
+            .with_note("the results should be empty or \"(result i32)\""))
+        }
+    };
+
+    Ok((has_args, has_result))
+}
+
+/// Assemble the source text by JIT and call the entry function,
+/// returns the exit code.
+pub fn run_source(
+    file_path: &str,
+    source: &str,
+    entry: &str,
+    program_args: &[String],
+    options: &AssembleOptions,
+    compilation_cache: Option<&mut CompilationCache>,
+) -> Result<i32, CliError> {
+    let (module, source_files) = parse_source(file_path, source, options)?;
+    let to_source_error = |diagnostic| to_source_error(&source_files, diagnostic);
+
+    let entry_node = module
+        .functions
+        .iter()
+        .find(|node| node.name == entry)
+        .ok_or_else(|| {
+            CliError::Other(format!(
+                "the entry function \"${}\" is not found in \"{}\"",
+                entry, file_path
+            ))
+        })?;
+    let (has_args, has_result) = check_entry_signature(entry_node).map_err(to_source_error)?;
+
+    let mut generator = Generator::<JITModule>::new(vec![]);
+    let assembled_module = assemble_module_with_cache(&module, &mut generator, compilation_cache)
+        .map_err(to_source_error)?;
+    generator
+        .module
+        .finalize_definitions()
+        .map_err(|error| CliError::Other(format!("failed to finalize the functions: {}", error)))?;
+
+    let entry_ptr = generator
+        .module
+        .get_finalized_function(assembled_module.get_function_id(entry).unwrap());
+
+    // the `argv` is a NULL terminated array of C strings
+    let arg_strings = std::iter::once(file_path)
+        .chain(program_args.iter().map(|arg| arg.as_str()))
+        .map(|arg| {
+            CString::new(arg).map_err(|_| {
+                CliError::Usage(format!("the argument \"{}\" contains the NUL byte", arg))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut arg_ptrs = arg_strings
+        .iter()
+        .map(|arg| arg.as_ptr())
+        .collect::<Vec<*const c_char>>();
+    arg_ptrs.push(std::ptr::null());
+
+    let argc = arg_strings.len() as i32;
+    let argv = arg_ptrs.as_ptr() as i64;
+
+    // SAFETY: the signature of the entry function has been checked above.
+    let exit_code = unsafe {
+        match (has_args, has_result) {
+            (false, false) => {
+                let func: extern "C" fn() = std::mem::transmute(entry_ptr);
+                func();
+                0
+            }
+            (false, true) => {
+                let func: extern "C" fn() -> i32 = std::mem::transmute(entry_ptr);
+                func()
+            }
+            (true, false) => {
+                let func: extern "C" fn(i32, i64) = std::mem::transmute(entry_ptr);
+                func(argc, argv);
+                0
+            }
+            (true, true) => {
+                let func: extern "C" fn(i32, i64) -> i32 = std::mem::transmute(entry_ptr);
+                func(argc, argv)
+            }
+        }
+    };
+
+    Ok(exit_code)
+}
+
+/// Get the options of assembling by JIT, i.e. the target is the current machine.
+pub fn get_run_options(parsed_args: &ParsedArgs) -> AssembleOptions {
+    AssembleOptions {
+        target: Conditions::host().target,
+        module_paths: parsed_args.get_values("--module-path"),
+        features: parsed_args.get_values("--feature"),
+        ..AssembleOptions::default()
+    }
+}
+
+pub fn run_program(args: &[String]) -> Result<i32, CliError> {
+    let parsed_args = parse_args(
+        args,
+        &[
+            OptionSpec {
+                names: &["--entry"],
+                takes_value: true,
+            },
+            MODULE_PATH_OPTION,
+            FEATURE_OPTION,
+        ],
+    )?;
+
+    let [input_file_path] = parsed_args.positional.as_slice() else {
+        return Err(CliError::Usage(format!("usage: {}", RUN_USAGE)));
+    };
+
+    let entry = parsed_args.get_value("--entry").unwrap_or(DEFAULT_ENTRY);
+    let source = read_source_file(input_file_path)?;
+    run_source(
+        input_file_path,
+        &source,
+        entry,
+        &parsed_args.rest,
+        &get_run_options(&parsed_args),
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{assemble::AssembleOptions, error::CliError, run::run_source};
+
+    #[test]
+    fn test_run() {
+        let source = r#"
+        (module $app
+            (import (function $strlen "strlen" (param i64) (result i64)))
+
+            (function $main (param $argc i32) (param $argv i64) (result i32)
+                (code
+                    (add_i32
+                        (mul_i32 (local_load $argc) (imm_i32 10))
+                        (truncate_i64_to_i32
+                            (call $strlen (memory_load_i64 (local_load $argv) 8))))))
+
+            (function $answer (result i32)
+                (code (imm_i32 42)))
+
+            (function $invalid (param $a i64)
+                (code)))
+        "#;
+
+        let args = ["hello".to_owned(), "world".to_owned()];
+        assert_eq!(
+            run_source(
+                "app.ancasm",
+                source,
+                "main",
+                &args,
+                &AssembleOptions::default(),
+                None
+            )
+            .unwrap(),
+            35
+        );
+        assert_eq!(
+            run_source(
+                "app.ancasm",
+                source,
+                "answer",
+                &[],
+                &AssembleOptions::default(),
+                None
+            )
+            .unwrap(),
+            42
+        );
+
+        let error = run_source(
+            "app.ancasm",
+            source,
+            "invalid",
+            &[],
+            &AssembleOptions::default(),
+            None,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("error: the parameters of the entry function \"$invalid\" are invalid"));
+
+        let error = run_source(
+            "app.ancasm",
+            source,
+            "start",
+            &[],
+            &AssembleOptions::default(),
+            None,
+        )
+        .unwrap_err();
+        assert!(matches!(error, CliError::Other(_)));
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{cell::RefCell, fmt::Write};
+
+use assembler::{
+    code_generator::Generator,
+    diagnostic::get_line_column,
+    lexer::Span,
+    test_harness::{build_test_module, get_test_function_name, ASSERT_FAILED_FUNCTION_NAME},
+};
+use cranelift_jit::JITModule;
+
+use crate::{
+    args::{parse_args, OptionSpec},
+    assemble::{
+        assemble_module_with_cache, parse_source, read_source_file, to_source_error,
+        AssembleOptions, FEATURE_OPTION, MODULE_PATH_OPTION,
+    },
+    error::{CliError, EXIT_CODE_ERROR},
+    run::get_run_options,
+};
+
+// The subcommand "test"
+// ---------------------
+//
+// Assemble the test functions `(test $name ...)` of the source file (see
+// `test_harness.rs`) by the JIT backend and run them, e.g.
+//
+// `$ anasm test main.ancasm [--filter <text>] [-I <path>]... [-F <feature>]...`
+//
+// ```text
+// test inc_one ... ok
+// test inc_wrong ... FAILED
+//     assertion failed at main.ancasm:12:21
+//
+// test result: FAILED. 1 passed; 1 failed
+// ```
+//
+// - `--filter <text>` runs the tests whose names contain the text only.
+// - the exit code is 1 if any test fails.
+// - the `panic` in a test terminates the whole test run.
+
+pub const TEST_USAGE: &str =
+    "anasm test <input.ancasm> [--filter <text>] [-I <path>]... [-F <feature>]...";
+
+thread_local! {
+    /// The source offsets of the failed assertions of the current test.
+    static FAILED_OFFSETS: RefCell<Vec<usize>> = const { RefCell::new(vec![]) };
+}
+
+extern "C" fn assert_failed(offset: i64) {
+    FAILED_OFFSETS.with(|offsets| offsets.borrow_mut().push(offset as usize));
+}
+
+/// Run the tests of the source text, returns the report and the number of
+/// the failed tests.
+pub fn run_test_source(
+    file_path: &str,
+    source: &str,
+    filter: Option<&str>,
+    options: &AssembleOptions,
+) -> Result<(String, usize), CliError> {
+    let (module, source_files) = parse_source(file_path, source, options)?;
+    let test_module = build_test_module(&module);
+
+    let mut generator = Generator::<JITModule>::new(vec![(
+        ASSERT_FAILED_FUNCTION_NAME.to_owned(),
+        assert_failed as *const u8,
+    )]);
+    let assembled_module = assemble_module_with_cache(&test_module, &mut generator, None)
+        .map_err(|diagnostic| to_source_error(&source_files, diagnostic))?;
+    generator
+        .module
+        .finalize_definitions()
+        .map_err(|error| CliError::Other(format!("failed to finalize the functions: {}", error)))?;
+
+    let mut report = String::new();
+    let mut passed_count = 0;
+    let mut failed_count = 0;
+
+    for test in module
+        .tests
+        .iter()
+        .filter(|test| filter.is_none_or(|filter| test.name.contains(filter)))
+    {
+        let func_id = assembled_module
+            .get_function_id(&get_test_function_name(&test.name))
+            .unwrap();
+        let test_ptr = generator.module.get_finalized_function(func_id);
+
+        // SAFETY: the test functions have no params and results.
+        let test_fn: extern "C" fn() = unsafe { std::mem::transmute(test_ptr) };
+        FAILED_OFFSETS.with(|offsets| offsets.borrow_mut().clear());
+        test_fn();
+        let failed_offsets = FAILED_OFFSETS.with(|offsets| offsets.take());
+
+        if failed_offsets.is_empty() {
+            passed_count += 1;
+            writeln!(report, "test {} ... ok", test.name).unwrap();
+            continue;
+        }
+
+        failed_count += 1;
+        writeln!(report, "test {} ... FAILED", test.name).unwrap();
+        for offset in failed_offsets {
+            match source_files.locate(Span::new(offset, offset)) {
+                Some((file, span)) => {
+                    let (line, column) = get_line_column(&file.source, span.start);
+                    writeln!(
+                        report,
+                        "    assertion failed at {}:{}:{}",
+                        file.file_path, line, column
+                    )
+                    .unwrap();
+                }
+                None => writeln!(report, "    assertion failed").unwrap(),
+            }
+        }
+    }
+
+    writeln!(
+        report,
+        "\ntest result: {}. {} passed; {} failed",
+        if failed_count == 0 { "ok" } else { "FAILED" },
+        passed_count,
+        failed_count
+    )
+    .unwrap();
+
+    Ok((report, failed_count))
+}
+
+/// Returns the exit code, i.e. 1 if any test fails.
+pub fn run_tests(args: &[String]) -> Result<i32, CliError> {
+    let parsed_args = parse_args(
+        args,
+        &[
+            OptionSpec {
+                names: &["--filter"],
+                takes_value: true,
+            },
+            MODULE_PATH_OPTION,
+            FEATURE_OPTION,
+        ],
+    )?;
+
+    let [input_file_path] = parsed_args.positional.as_slice() else {
+        return Err(CliError::Usage(format!("usage: {}", TEST_USAGE)));
+    };
+
+    let source = read_source_file(input_file_path)?;
+    let (report, failed_count) = run_test_source(
+        input_file_path,
+        &source,
+        parsed_args.get_value("--filter"),
+        &get_run_options(&parsed_args),
+    )?;
+    print!("{}", report);
+
+    Ok(if failed_count == 0 {
+        0
+    } else {
+        EXIT_CODE_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{assemble::AssembleOptions, test_runner::run_test_source};
+
+    #[test]
+    fn test_test_runner() {
+        let source = r#"(module $app
+    (function $inc (param $a i32) (result i32)
+        (code (add_i32 (local_load $a) (imm_i32 1))))
+    (test $inc_one
+        (code (assert_eq_i32 (call $inc (imm_i32 1)) (imm_i32 2))))
+    (test $inc_wrong
+        (code (assert_eq_i32 (call $inc (imm_i32 1)) (imm_i32 3)))))
+"#;
+
+        let (report, failed_count) =
+            run_test_source("app.ancasm", source, None, &AssembleOptions::default()).unwrap();
+        assert_eq!(failed_count, 1);
+        assert_eq!(
+            report,
+            "\
+test inc_one ... ok
+test inc_wrong ... FAILED
+    assertion failed at app.ancasm:7:15
+
+test result: FAILED. 1 passed; 1 failed
+"
+        );
+
+        let (report, failed_count) = run_test_source(
+            "app.ancasm",
+            source,
+            Some("one"),
+            &AssembleOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(failed_count, 0);
+        assert_eq!(
+            report,
+            "test inc_one ... ok\n\ntest result: ok. 1 passed; 0 failed\n"
+        );
+    }
+}
@@ -0,0 +1,419 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{
+    fmt::Write,
+    path::Path,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use assembler::{
+    ast, code_generator::Generator, elf_note::ElfNote, linker::LinkerOptions,
+    lowering::assemble_module, parser::parse_module,
+};
+use cranelift_object::ObjectModule;
+
+use crate::{
+    args::{parse_args, OptionSpec, ParsedArgs},
+    assemble::{
+        parse_source, read_source_file, to_source_error, write_output_file, AssembleOptions,
+        FEATURE_OPTION, MODULE_PATH_OPTION,
+    },
+    error::{CliError, EXIT_CODE_ERROR},
+    link::link_object_files,
+    run::{check_entry_signature, get_run_options},
+};
+
+// The subcommand "verify"
+// -----------------------
+//
+// Run the entry functions of the source file by both backends, i.e. the JIT
+// (by `anasm run`) and the object file (assembled, linked and executed), and
+// compare the exit codes and the outputs, to catch the miscompiles of one
+// backend, since the two generators use different flags (e.g. "opt_level"
+// and "tls_model"), e.g.
+//
+// `$ anasm verify main.ancasm [--entry <name>]... [-I <path>]... [-F <feature>]... [-- args...]`
+//
+// ```text
+// verify main ... ok
+// verify answer ... MISMATCH
+//     exit code: JIT 42, AOT 43
+//
+// verify result: FAILED. 1 matched; 1 mismatched
+// ```
+//
+// - the entry functions default to "main", the signatures are the same as
+//   `anasm run`.
+// - the entry function which is not "main" is called by a generated `main`
+//   function in the executable, and the `main` of the source file (if any)
+//   is not exported.
+// - the stdout and the exit code are compared, the `argv[0]` of the two
+//   backends are different (i.e. the source file and the executable).
+// - the exit code is 1 if any entry function mismatches.
+
+pub const VERIFY_USAGE: &str =
+    "anasm verify <input.ancasm> [--entry <name>]... [-I <path>]... [-F <feature>]... [-- args...]";
+
+/// The outcome of running a program, the exit code is `None` if the
+/// program is terminated by a signal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutcome {
+    pub exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+}
+
+impl RunOutcome {
+    fn from_output(output: std::process::Output) -> Self {
+        Self {
+            exit_code: output.status.code(),
+            stdout: output.stdout,
+        }
+    }
+}
+
+/// Run the entry function by `anasm run` in a child process, so the output
+/// can be captured and the crash of the program does not abort the verification.
+fn run_jit(
+    anasm_path: &Path,
+    file_path: &str,
+    entry: &str,
+    program_args: &[String],
+    options: &AssembleOptions,
+) -> Result<RunOutcome, CliError> {
+    let mut command = Command::new(anasm_path);
+    command.args(["run", file_path, "--entry", entry]);
+    for module_path in &options.module_paths {
+        command.args(["--module-path", module_path]);
+    }
+    for feature in &options.features {
+        command.args(["--feature", feature]);
+    }
+    command.arg("--").args(program_args);
+
+    let output = command.output().map_err(|error| {
+        CliError::Other(format!(
+            "failed to execute \"{}\": {}",
+            anasm_path.display(),
+            error
+        ))
+    })?;
+    Ok(RunOutcome::from_output(output))
+}
+
+/// Get the source of the module which defines the `main` function that calls
+/// the entry function, i.e. the program entry of the executable.
+fn get_entry_module_source(entry: &str, has_args: bool, has_result: bool) -> String {
+    let (params, args) = if has_args {
+        ("(param i32 i64)", "(local_load $argc) (local_load $argv)")
+    } else {
+        ("", "")
+    };
+    let (results, exit_code) = if has_result {
+        ("(result i32)", "")
+    } else {
+        ("", "(imm_i32 0)")
+    };
+
+    format!(
+        "(module $verify_entry
+            (import (function $entry \"{}\" {} {}))
+            (function $main export (param $argc i32) (param $argv i64) (result i32)
+                (code (call $entry {}) {})))",
+        entry, params, results, args, exit_code
+    )
+}
+
+fn emit_object(module: &ast::Module, target: &str) -> Result<Vec<u8>, String> {
+    let mut generator = Generator::<ObjectModule>::new(&module.name, Some(target));
+    assemble_module(module, &mut generator).map_err(|diagnostic| diagnostic.message)?;
+    for library in module.get_libraries() {
+        generator.add_elf_note(ElfNote::new_library(library));
+    }
+    generator
+        .finish()
+        .map_err(|error| error.to_string())?
+        .emit()
+        .map_err(|error| error.to_string())
+}
+
+/// Assemble the module with the entry function as the program entry, link
+/// and execute it in the folder.
+fn run_aot(
+    module: &ast::Module,
+    entry: &str,
+    program_args: &[String],
+    options: &AssembleOptions,
+    folder: &Path,
+) -> Result<RunOutcome, CliError> {
+    let entry_node = module
+        .functions
+        .iter()
+        .find(|node| node.name == entry)
+        .unwrap();
+    let (has_args, has_result) = check_entry_signature(entry_node).unwrap();
+
+    // only the entry function is exported, so the generated `main`
+    // does not conflict with the `main` of the source file
+    let mut module = module.clone();
+    for node in &mut module.functions {
+        if node.name == entry {
+            node.export = true;
+        } else if node.name == "main" {
+            node.export = false;
+        }
+    }
+
+    let mut objects = vec![emit_object(&module, &options.target)
+        .map_err(|message| CliError::Other(format!("failed to assemble: {}", message)))?];
+    if entry != "main" {
+        let entry_module =
+            parse_module(&get_entry_module_source(entry, has_args, has_result)).unwrap();
+        objects.push(
+            emit_object(&entry_module, &options.target).map_err(|message| {
+                CliError::Other(format!("failed to assemble the entry: {}", message))
+            })?,
+        );
+    }
+
+    let mut object_file_paths = vec![];
+    for (index, object) in objects.iter().enumerate() {
+        let object_file_path = folder.join(format!("{}.o", index));
+        write_output_file(&object_file_path.to_string_lossy(), object)?;
+        object_file_paths.push(object_file_path.to_string_lossy().into_owned());
+    }
+
+    let exec_file_path = folder.join(format!("{}.elf", entry));
+    link_object_files(
+        &object_file_paths
+            .iter()
+            .map(|path| path.as_str())
+            .collect::<Vec<_>>(),
+        &exec_file_path.to_string_lossy(),
+        &LinkerOptions::default(),
+    )?;
+
+    let output = Command::new(&exec_file_path)
+        .args(program_args)
+        .output()
+        .map_err(|error| CliError::Io {
+            file_path: exec_file_path.to_string_lossy().into_owned(),
+            error,
+        })?;
+    Ok(RunOutcome::from_output(output))
+}
+
+/// Compare the outcomes of the two backends, returns the differences.
+fn compare_outcomes(jit: &RunOutcome, aot: &RunOutcome) -> Vec<String> {
+    let format_exit_code = |exit_code: Option<i32>| match exit_code {
+        Some(exit_code) => exit_code.to_string(),
+        None => "(signal)".to_owned(),
+    };
+
+    let mut differences = vec![];
+    if jit.exit_code != aot.exit_code {
+        differences.push(format!(
+            "exit code: JIT {}, AOT {}",
+            format_exit_code(jit.exit_code),
+            format_exit_code(aot.exit_code)
+        ));
+    }
+    if jit.stdout != aot.stdout {
+        differences.push(format!(
+            "stdout: JIT {:?}, AOT {:?}",
+            String::from_utf8_lossy(&jit.stdout),
+            String::from_utf8_lossy(&aot.stdout)
+        ));
+    }
+    differences
+}
+
+/// Verify the entry functions of the source text, returns the report and
+/// the number of the mismatched entry functions.
+fn verify_source(
+    anasm_path: &Path,
+    file_path: &str,
+    source: &str,
+    entries: &[String],
+    program_args: &[String],
+    options: &AssembleOptions,
+) -> Result<(String, usize), CliError> {
+    let (module, source_files) = parse_source(file_path, source, options)?;
+    for entry in entries {
+        let entry_node = module
+            .functions
+            .iter()
+            .find(|node| node.name == *entry)
+            .ok_or_else(|| {
+                CliError::Other(format!(
+                    "the entry function \"${}\" is not found in \"{}\"",
+                    entry, file_path
+                ))
+            })?;
+        check_entry_signature(entry_node)
+            .map_err(|diagnostic| to_source_error(&source_files, diagnostic))?;
+    }
+
+    // the serial number distinguishes the verifications of the same process (e.g. the tests)
+    static VERIFY_SERIAL: AtomicUsize = AtomicUsize::new(0);
+    let folder = std::env::temp_dir().join(format!(
+        "anasm_verify_{}_{}",
+        std::process::id(),
+        VERIFY_SERIAL.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&folder).map_err(|error| CliError::Io {
+        file_path: folder.to_string_lossy().into_owned(),
+        error,
+    })?;
+
+    let result = (|| {
+        let mut report = String::new();
+        let mut mismatched_count = 0;
+        for entry in entries {
+            let jit = run_jit(anasm_path, file_path, entry, program_args, options)?;
+            let aot = run_aot(&module, entry, program_args, options, &folder)?;
+            let differences = compare_outcomes(&jit, &aot);
+
+            if differences.is_empty() {
+                writeln!(report, "verify {} ... ok", entry).unwrap();
+            } else {
+                mismatched_count += 1;
+                writeln!(report, "verify {} ... MISMATCH", entry).unwrap();
+                for difference in differences {
+                    writeln!(report, "    {}", difference).unwrap();
+                }
+            }
+        }
+
+        writeln!(
+            report,
+            "\nverify result: {}. {} matched; {} mismatched",
+            if mismatched_count == 0 {
+                "ok"
+            } else {
+                "FAILED"
+            },
+            entries.len() - mismatched_count,
+            mismatched_count
+        )
+        .unwrap();
+        Ok((report, mismatched_count))
+    })();
+
+    // the temporary folder is removed even if the verification fails
+    let _ = std::fs::remove_dir_all(&folder);
+    result
+}
+
+fn get_entries(parsed_args: &ParsedArgs) -> Vec<String> {
+    let entries = parsed_args.get_values("--entry");
+    if entries.is_empty() {
+        vec!["main".to_owned()]
+    } else {
+        entries
+    }
+}
+
+/// Returns the exit code, i.e. 1 if any entry function mismatches.
+pub fn run_verify(args: &[String]) -> Result<i32, CliError> {
+    let parsed_args = parse_args(
+        args,
+        &[
+            OptionSpec {
+                names: &["--entry"],
+                takes_value: true,
+            },
+            MODULE_PATH_OPTION,
+            FEATURE_OPTION,
+        ],
+    )?;
+
+    let [input_file_path] = parsed_args.positional.as_slice() else {
+        return Err(CliError::Usage(format!("usage: {}", VERIFY_USAGE)));
+    };
+
+    let anasm_path = std::env::current_exe().map_err(|error| CliError::Io {
+        file_path: "anasm".to_owned(),
+        error,
+    })?;
+    let source = read_source_file(input_file_path)?;
+    let (report, mismatched_count) = verify_source(
+        &anasm_path,
+        input_file_path,
+        &source,
+        &get_entries(&parsed_args),
+        &parsed_args.rest,
+        &get_run_options(&parsed_args),
+    )?;
+    print!("{}", report);
+
+    Ok(if mismatched_count == 0 {
+        0
+    } else {
+        EXIT_CODE_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use assembler::parser::parse_module;
+
+    use crate::{
+        assemble::AssembleOptions,
+        run::run_source,
+        verify::{compare_outcomes, run_aot, RunOutcome},
+    };
+
+    #[test]
+    fn test_verify() {
+        let source = r#"
+        (module $app
+            (import (function $puts (param i64) (result i32)))
+            (data $message (read_only bytes "hello\0"))
+            (function $main (result i32)
+                (code (call $puts (host_addr_data $message)) (imm_i32 7)))
+            (function $count (param $argc i32) (param $argv i64) (result i32)
+                (code (add_i32 (local_load $argc) (imm_i32 40))))
+            (function $quiet
+                (code (nop))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let options = AssembleOptions::default();
+        let folder = std::env::temp_dir().join(format!("anasm_test_verify_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let args = ["a".to_owned(), "b".to_owned()];
+        for (entry, expect_stdout) in [("main", "hello\n"), ("count", ""), ("quiet", "")] {
+            let aot = run_aot(&module, entry, &args, &options, &folder).unwrap();
+            let jit_exit_code =
+                run_source("app.ancasm", source, entry, &args, &options, None).unwrap();
+            assert_eq!(aot.exit_code, Some(jit_exit_code));
+            assert_eq!(String::from_utf8_lossy(&aot.stdout), expect_stdout);
+        }
+
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        let outcome = |exit_code: Option<i32>, stdout: &str| RunOutcome {
+            exit_code,
+            stdout: stdout.as_bytes().to_vec(),
+        };
+        assert_eq!(
+            compare_outcomes(&outcome(Some(1), "a"), &outcome(Some(1), "a")),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            compare_outcomes(&outcome(Some(1), "a"), &outcome(None, "b")),
+            vec![
+                "exit code: JIT 1, AOT (signal)".to_owned(),
+                "stdout: JIT \"a\", AOT \"b\"".to_owned()
+            ]
+        );
+    }
+}
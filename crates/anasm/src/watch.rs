@@ -0,0 +1,328 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use assembler::{compilation_cache::CompilationCache, linker::LinkerOptions};
+
+use crate::{
+    args::{parse_args, OptionSpec},
+    assemble::{read_source_file, AssembleOptions},
+    error::CliError,
+    link::{build_executable, get_build_option_specs, get_build_options},
+    run::{get_run_options, run_source},
+};
+
+// The subcommand "watch"
+// ----------------------
+//
+// Monitor the source files, and rebuild the executable (i.e. `anasm build`)
+// or re-run the program by JIT (i.e. `anasm run`) when any of them changes.
+//
+// `$ anasm watch <input.ancasm>... -o <output> [--cache-dir <dir>] [build options]`
+// `$ anasm watch <input.ancasm> --run [--entry <name>] [--cache-dir <dir>] [-- args...]`
+//
+// - the files are polled by the modification time, so no platform specific
+//   notification is required.
+// - the compilation cache is kept across the rebuilds, so only the changed
+//   functions are compiled again, `--cache-dir` persists the cache so it is
+//   also reused by the next `anasm watch`.
+// - the errors are printed and the watching continues, press Ctrl+C to stop.
+
+pub const WATCH_USAGE: &str =
+    "anasm watch <input.ancasm>... (-o <output> [build options] | --run [--entry <name>] [-- args...]) [--cache-dir <dir>]";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub enum WatchAction {
+    Build {
+        output_file_path: String,
+        assemble_options: AssembleOptions,
+        linker_options: LinkerOptions,
+    },
+    Run {
+        entry: String,
+        program_args: Vec<String>,
+        assemble_options: AssembleOptions,
+    },
+}
+
+pub struct Watcher {
+    source_file_paths: Vec<String>,
+    action: WatchAction,
+    compilation_cache: CompilationCache,
+
+    /// The modification times of the source files of the last build,
+    /// `None` for the missing files.
+    last_modified_times: Option<Vec<Option<SystemTime>>>,
+}
+
+impl Watcher {
+    pub fn new(
+        source_file_paths: Vec<String>,
+        action: WatchAction,
+        compilation_cache: CompilationCache,
+    ) -> Self {
+        Self {
+            source_file_paths,
+            action,
+            compilation_cache,
+            last_modified_times: None,
+        }
+    }
+
+    fn get_modified_times(&self) -> Vec<Option<SystemTime>> {
+        self.source_file_paths
+            .iter()
+            .map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Rebuild (or re-run) if any source file has changed since the last time,
+    /// returns `None` if nothing changes, otherwise returns the report.
+    pub fn poll(&mut self) -> Option<Result<String, CliError>> {
+        let modified_times = self.get_modified_times();
+        if self.last_modified_times.as_ref() == Some(&modified_times) {
+            return None;
+        }
+        self.last_modified_times = Some(modified_times);
+
+        let start = Instant::now();
+        let (hits, misses) = (
+            self.compilation_cache.hits(),
+            self.compilation_cache.misses(),
+        );
+
+        let result = match &self.action {
+            WatchAction::Build {
+                output_file_path,
+                assemble_options,
+                linker_options,
+            } => build_executable(
+                &self.source_file_paths,
+                output_file_path,
+                assemble_options,
+                linker_options,
+                Some(&mut self.compilation_cache),
+            )
+            .map(|_| format!("built \"{}\"", output_file_path)),
+            WatchAction::Run {
+                entry,
+                program_args,
+                assemble_options,
+            } => {
+                let file_path = &self.source_file_paths[0];
+                read_source_file(file_path)
+                    .and_then(|source| {
+                        run_source(
+                            file_path,
+                            &source,
+                            entry,
+                            program_args,
+                            assemble_options,
+                            Some(&mut self.compilation_cache),
+                        )
+                    })
+                    .map(|exit_code| format!("the program exits with {}", exit_code))
+            }
+        };
+
+        Some(result.map(|message| {
+            format!(
+                "{} in {} ms, {} function(s) compiled, {} reused",
+                message,
+                start.elapsed().as_millis(),
+                self.compilation_cache.misses() - misses,
+                self.compilation_cache.hits() - hits
+            )
+        }))
+    }
+}
+
+pub fn run_watch(args: &[String]) -> Result<(), CliError> {
+    let mut option_specs = get_build_option_specs();
+    option_specs.extend([
+        OptionSpec {
+            names: &["--run"],
+            takes_value: false,
+        },
+        OptionSpec {
+            names: &["--entry"],
+            takes_value: true,
+        },
+        OptionSpec {
+            names: &["--cache-dir"],
+            takes_value: true,
+        },
+    ]);
+
+    let parsed_args = parse_args(args, &option_specs)?;
+    if parsed_args.positional.is_empty() {
+        return Err(CliError::Usage(format!("usage: {}", WATCH_USAGE)));
+    }
+
+    let action = if parsed_args.has_flag("--run") {
+        if parsed_args.positional.len() != 1 || parsed_args.get_value("--output").is_some() {
+            return Err(CliError::Usage(format!(
+                "\"--run\" requires exactly one source file and no output file, usage: {}",
+                WATCH_USAGE
+            )));
+        }
+
+        WatchAction::Run {
+            entry: parsed_args
+                .get_value("--entry")
+                .unwrap_or("main")
+                .to_owned(),
+            program_args: parsed_args.rest.clone(),
+            assemble_options: get_run_options(&parsed_args),
+        }
+    } else {
+        let Some(output_file_path) = parsed_args.get_value("--output") else {
+            return Err(CliError::Usage(format!(
+                "the output file or \"--run\" is required, usage: {}",
+                WATCH_USAGE
+            )));
+        };
+
+        let (assemble_options, linker_options) = get_build_options(&parsed_args)?;
+        WatchAction::Build {
+            output_file_path: output_file_path.to_owned(),
+            assemble_options,
+            linker_options,
+        }
+    };
+
+    let compilation_cache = match parsed_args.get_value("--cache-dir") {
+        Some(directory) => CompilationCache::open(directory).map_err(|error| CliError::Io {
+            file_path: directory.to_owned(),
+            error,
+        })?,
+        None => CompilationCache::new(),
+    };
+
+    let mut watcher = Watcher::new(parsed_args.positional.clone(), action, compilation_cache);
+    eprintln!(
+        "watching {}, press Ctrl+C to stop",
+        parsed_args.positional.join(", ")
+    );
+
+    loop {
+        match watcher.poll() {
+            Some(Ok(report)) => eprintln!("[watch] {}", report),
+            Some(Err(error @ CliError::Source { .. })) => eprintln!("{}", error),
+            Some(Err(error)) => eprintln!("error: {}", error),
+            None => {}
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        process::Command,
+        time::{Duration, SystemTime},
+    };
+
+    use assembler::{compilation_cache::CompilationCache, linker::LinkerOptions};
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        assemble::AssembleOptions,
+        watch::{WatchAction, Watcher},
+    };
+
+    #[test]
+    fn test_watch() {
+        let folder = std::env::temp_dir().join(format!("anasm_test_watch_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let source_file_path = folder.join("main.ancasm");
+        let output_file_path = folder.join("app");
+
+        // the modification time is set explicitly since the resolution
+        // of the file system may be coarse
+        let write_source = |number: i32, seconds: u64| {
+            std::fs::write(
+                &source_file_path,
+                format!(
+                    "(module $main
+                        (function $get (result i32) (code (imm_i32 {})))
+                        (function $main export (result i32) (code (call $get))))",
+                    number
+                ),
+            )
+            .unwrap();
+            File::options()
+                .write(true)
+                .open(&source_file_path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+                .unwrap();
+        };
+
+        write_source(3, 1000);
+
+        let mut watcher = Watcher::new(
+            vec![source_file_path.to_str().unwrap().to_owned()],
+            WatchAction::Build {
+                output_file_path: output_file_path.to_str().unwrap().to_owned(),
+                assemble_options: AssembleOptions::default(),
+                linker_options: LinkerOptions::default(),
+            },
+            CompilationCache::new(),
+        );
+
+        let report = watcher.poll().unwrap().unwrap();
+        assert!(report.contains("2 function(s) compiled, 0 reused"));
+        assert_eq!(
+            Command::new(&output_file_path).status().unwrap().code(),
+            Some(3)
+        );
+
+        // nothing changes
+        assert!(watcher.poll().is_none());
+
+        // only the changed function is compiled
+        write_source(5, 2000);
+        let report = watcher.poll().unwrap().unwrap();
+        assert!(report.contains("1 function(s) compiled, 1 reused"));
+        assert_eq!(
+            Command::new(&output_file_path).status().unwrap().code(),
+            Some(5)
+        );
+
+        // the errors do not stop watching
+        std::fs::write(
+            &source_file_path,
+            "(module $main (function $main (code (add_i32))))",
+        )
+        .unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+
+        // re-run by JIT
+        write_source(7, 3000);
+        let mut watcher = Watcher::new(
+            vec![source_file_path.to_str().unwrap().to_owned()],
+            WatchAction::Run {
+                entry: "main".to_owned(),
+                program_args: vec![],
+                assemble_options: AssembleOptions::default(),
+            },
+            CompilationCache::new(),
+        );
+        let report = watcher.poll().unwrap().unwrap();
+        assert!(report.starts_with("the program exits with 7"));
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,18 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+#![no_main]
+
+use assembler::{fuzzing::fuzz_lower, parser::parse_module};
+use libfuzzer_sys::fuzz_target;
+
+// only the valid modules reach the lowering, so the fuzzer spends the time
+// on the lowering and the generator rather than the syntax errors.
+fuzz_target!(|source: &str| {
+    if let Ok(module) = parse_module(source) {
+        fuzz_lower(&module);
+    }
+});
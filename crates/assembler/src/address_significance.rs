@@ -0,0 +1,323 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_object::{
+    object::{
+        elf::{
+            R_AARCH64_CALL26, R_AARCH64_JUMP26, R_RISCV_CALL, R_RISCV_CALL_PLT, R_X86_64_PLT32,
+            SHF_ALLOC, SHF_EXCLUDE,
+        },
+        read::File,
+        Object, ObjectSection, ObjectSymbol, RelocationFlags, RelocationTarget, SectionFlags,
+        SectionKind, SymbolKind,
+    },
+    ObjectModule, ObjectProduct,
+};
+
+use crate::code_generator::Generator;
+
+// The address-significance table
+// ------------------------------
+//
+// The identical code folding of the linker (i.e. `--icf=safe`) merges the
+// identical functions into one, which is only safe for the functions whose
+// addresses are not significant, e.g. a function which is only called
+// directly, since the program can not tell the difference. in contrast, the
+// functions whose addresses are taken (e.g. stored in a function table) may
+// be compared, so they should be kept distinct.
+//
+// the address-significance table (the section `.llvm_addrsig`, the same as
+// `clang -faddrsig`) lists the symbols whose addresses are significant, i.e.
+//
+// - the symbols which are referenced by the relocations other than the calls,
+//   e.g. `host_addr_function`, the function tables and the GOT entries.
+// - all data symbols, since the data is accessed by address.
+//
+// the table is a sequence of the ULEB128 encoded symbol indices, e.g.
+//
+// ```rust
+// let mut generator = Generator::<ObjectModule>::new_with_options(
+//     "main",
+//     None,
+//     &ObjectOptions {
+//         function_sections: true,
+//         ..ObjectOptions::default()
+//     },
+// );
+// generator.enable_address_significance_table();
+// // ...
+// let mut binary = generator.finish().unwrap().emit().unwrap();
+// link_address_significance_table(&mut binary);
+// ```
+//
+// note that the emitted binary should be patched by `link_address_significance_table()`,
+// because the section header field `sh_link` (i.e. the index of the symbol
+// table) can not be set by the object writer, and the linkers ignore the table
+// if the field is zero.
+//
+// the functions should be placed in their own sections (see `ObjectOptions::function_sections`),
+// since the linker folds the sections rather than the functions, see also
+// `LinkerOptions::icf`.
+//
+// ref:
+// - https://maskray.me/blog/2020-11-15-explain-gnu-linker-options#icfsafe
+// - https://llvm.org/docs/Extensions.html#sht-llvm-addrsig-section-address-significance-table
+
+/// The section type of the address-significance table.
+const SHT_LLVM_ADDRSIG: u32 = 0x6fff_4c03;
+
+const ADDRSIG_SECTION_NAME: &str = ".llvm_addrsig";
+
+impl Generator<ObjectModule> {
+    /// Append the address-significance table to the object file.
+    pub fn enable_address_significance_table(&mut self) {
+        self.address_significance_table = true;
+    }
+}
+
+/// Check whether the relocation is a direct call (or a tail call), which
+/// does not take the address of the target.
+fn is_call_relocation(flags: RelocationFlags) -> bool {
+    matches!(
+        flags,
+        RelocationFlags::Elf {
+            r_type: R_X86_64_PLT32
+                | R_AARCH64_CALL26
+                | R_AARCH64_JUMP26
+                | R_RISCV_CALL
+                | R_RISCV_CALL_PLT
+        }
+    )
+}
+
+/// Get the indices of the symbols whose addresses are significant, in
+/// ascending order.
+fn get_significant_symbol_indices(binary: &[u8]) -> Vec<usize> {
+    let file = File::parse(binary).expect("failed to parse the object file");
+    let mut indices = file
+        .symbols()
+        .filter(|symbol| matches!(symbol.kind(), SymbolKind::Data | SymbolKind::Tls))
+        .map(|symbol| symbol.index().0)
+        .collect::<Vec<_>>();
+
+    // the references of the unwind information are not significant
+    let sections = file.sections().filter(|section| {
+        matches!(section.flags(), SectionFlags::Elf { sh_flags } if sh_flags & SHF_ALLOC as u64 != 0)
+            && section.name() != Ok(".eh_frame")
+    });
+
+    for section in sections {
+        for (_, relocation) in section.relocations() {
+            if let RelocationTarget::Symbol(symbol_index) = relocation.target() {
+                if !is_call_relocation(relocation.flags()) {
+                    indices.push(symbol_index.0);
+                }
+            }
+        }
+    }
+
+    indices.sort_unstable();
+    indices.dedup();
+    indices
+}
+
+pub(crate) fn write_address_significance_table_to_object(product: &mut ObjectProduct) {
+    // the symbol indices are only determined by the object writer, and the
+    // new section does not change them since it has no section symbol.
+    let binary = product
+        .object
+        .write()
+        .expect("failed to write the object file");
+
+    let mut data = vec![];
+    for index in get_significant_symbol_indices(&binary) {
+        let mut value = index;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                data.push(byte);
+                break;
+            }
+            data.push(byte | 0x80);
+        }
+    }
+
+    let section_id = product.object.add_section(
+        vec![],
+        ADDRSIG_SECTION_NAME.as_bytes().to_vec(),
+        SectionKind::Elf(SHT_LLVM_ADDRSIG),
+    );
+    let section = product.object.section_mut(section_id);
+    section.flags = SectionFlags::Elf {
+        sh_flags: SHF_EXCLUDE as u64,
+    };
+    section.set_data(data, 1);
+}
+
+/// Set the field `sh_link` of the address-significance table to the index
+/// of the symbol table, it does nothing if there is no table.
+pub fn link_address_significance_table(object_binary: &mut [u8]) {
+    let (table_index, symtab_index, is_64, is_little_endian) = {
+        let Ok(file) = File::parse(&*object_binary) else {
+            return;
+        };
+        let find_section_index = |name: &str| {
+            file.sections()
+                .find(|section| section.name() == Ok(name))
+                .map(|section| section.index().0)
+        };
+        let (Some(table_index), Some(symtab_index)) = (
+            find_section_index(ADDRSIG_SECTION_NAME),
+            find_section_index(".symtab"),
+        ) else {
+            return;
+        };
+        (
+            table_index,
+            symtab_index,
+            file.is_64(),
+            file.is_little_endian(),
+        )
+    };
+
+    let read = |offset: usize, length: usize| {
+        let mut bytes = [0u8; 8];
+        bytes[..length].copy_from_slice(&object_binary[offset..offset + length]);
+        if !is_little_endian {
+            bytes[..length].reverse();
+        }
+        u64::from_le_bytes(bytes) as usize
+    };
+
+    // the offsets of `e_shoff`, `e_shentsize` and `sh_link`, see `Elf64_Ehdr`,
+    // `Elf32_Ehdr`, `Elf64_Shdr` and `Elf32_Shdr`.
+    let (section_headers_offset, entry_size, link_offset) = if is_64 {
+        (read(0x28, 8), read(0x3a, 2), 40)
+    } else {
+        (read(0x20, 4), read(0x2e, 2), 24)
+    };
+
+    let offset = section_headers_offset + table_index * entry_size + link_offset;
+    let link = symtab_index as u32;
+    let bytes = if is_little_endian {
+        link.to_le_bytes()
+    } else {
+        link.to_be_bytes()
+    };
+    object_binary[offset..offset + 4].copy_from_slice(&bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_object::{
+        object::{
+            read::{
+                elf::{ElfFile64, SectionHeader},
+                File,
+            },
+            Endianness, Object, ObjectSection, ObjectSymbol, SymbolIndex,
+        },
+        ObjectModule,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        address_significance::link_address_significance_table,
+        code_generator::{Generator, ObjectOptions},
+        linker::{link_executable, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    #[test]
+    fn test_address_significance_table() {
+        let source = r#"
+        (module $main
+            (function $twice_a (param $x i32) (result i32)
+                (code (add_i32 (local_load $x) (local_load $x))))
+            (function $twice_b (param $x i32) (result i32)
+                (code (add_i32 (local_load $x) (local_load $x))))
+            (function $twice_c (param $x i32) (result i32)
+                (code (add_i32 (local_load $x) (local_load $x))))
+            (function $main export (result i32)
+                (code (add_i32
+                    (add_i32 (call $twice_a (imm_i32 1)) (call $twice_b (imm_i32 2)))
+                    (dyncall (param i32) (result i32)
+                        (host_addr_function $twice_c)
+                        (imm_i32 3))))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new_with_options(
+            &module.name,
+            None,
+            &ObjectOptions {
+                function_sections: true,
+                ..ObjectOptions::default()
+            },
+        );
+        generator.enable_address_significance_table();
+        assemble_module(&module, &mut generator).unwrap();
+        let mut binary = generator.finish().unwrap().emit().unwrap();
+        link_address_significance_table(&mut binary);
+
+        // check the table, the indices are less than 128, so each one is a byte
+        let file = ElfFile64::<Endianness>::parse(binary.as_slice()).unwrap();
+        let section = file.section_by_name(".llvm_addrsig").unwrap();
+        let symbol_names = section
+            .data()
+            .unwrap()
+            .iter()
+            .map(|index| {
+                let symbol = file.symbol_by_index(SymbolIndex(*index as usize)).unwrap();
+                symbol.name().unwrap().to_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(symbol_names, vec!["twice_c"]);
+
+        let symtab_section = file.section_by_name(".symtab").unwrap();
+        assert_eq!(
+            section.elf_section_header().sh_link(file.endian()),
+            symtab_section.index().0 as u32
+        );
+
+        // fold the identical functions by 'ld.gold'
+        let temp_folder = TempFolder::new("address_significance");
+        let object_file_path = temp_folder.file_path("main.o");
+        let exec_file_path = temp_folder.file_path("main.elf");
+        std::fs::write(&object_file_path, &binary).unwrap();
+
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions {
+                linker: "ld.gold".to_owned(),
+                icf: true,
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+
+        let exec_binary = std::fs::read(&exec_file_path).unwrap();
+        let exec_file = File::parse(exec_binary.as_slice()).unwrap();
+        let get_address = |name: &str| {
+            exec_file
+                .symbol_by_name(name)
+                .map(|symbol| symbol.address())
+        };
+
+        // the symbol of the folded function is discarded by 'ld.gold'
+        assert!(get_address("twice_a").is_some());
+        assert_eq!(get_address("twice_b"), None);
+        assert_ne!(get_address("twice_a"), get_address("twice_c"));
+
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(12));
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_module::{DataId, FuncId, Module, ModuleError};
+
+use crate::code_generator::{DataDefinition, Generator};
+
+// The alignment of the symbols
+// ----------------------------
+//
+// The functions are aligned by their code (e.g. 16 bytes on x86_64), and the
+// data are aligned by their definitions (usually the natural alignment of the
+// values). the front ends can request a larger alignment of a symbol before it
+// is defined, e.g. aligning the entry of a hot function to the cache line, or
+// a buffer to the width of the SIMD registers:
+//
+// ```rust
+// generator.set_function_alignment(func_id, 64)?;
+// generator.set_data_alignment(data_id, 32)?;
+// ```
+//
+// or by the attribute `(align n)` in the text format:
+//
+// ```anasm
+// (function $sum (align 64) ...)
+// (data $buffer (align 32) (uninit 256 8))
+// ```
+//
+// the alignment should be a power of two and at most 4096 (i.e. the size of a
+// page, the JIT module allocates the memory by pages), and it never lowers the
+// alignment which is required by the code or the definition. the requested
+// alignment of a data is merged into its definition (see `data_definitions`),
+// so it is kept by the deduplication (see `deduplication.rs`) and the placement
+// of the zero data (see `zero_data.rs`).
+//
+// Cranelift does not align the blocks inside the functions (e.g. the headers
+// of the loops), so a hot loop is aligned by placing it at the entry of a
+// function.
+//
+// ref:
+// - https://gcc.gnu.org/onlinedocs/gcc/Common-Function-Attributes.html#index-aligned-function-attribute
+// - https://docs.rs/cranelift-module/latest/cranelift_module/struct.DataDescription.html#structfield.align
+
+/// The maximum alignment of the functions and the data.
+pub const MAX_ALIGNMENT: u64 = 4096;
+
+fn check_alignment(align: u64, name: &str) -> Result<(), ModuleError> {
+    if align.is_power_of_two() && align <= MAX_ALIGNMENT {
+        Ok(())
+    } else {
+        Err(ModuleError::Backend(anyhow::anyhow!(
+            "the alignment {} of \"{}\" should be a power of two and at most {}",
+            align,
+            name,
+            MAX_ALIGNMENT
+        )))
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Align the entry of the function (which is about to be defined) to
+    /// at least `align` bytes.
+    pub fn set_function_alignment(
+        &mut self,
+        func_id: FuncId,
+        align: u64,
+    ) -> Result<(), ModuleError> {
+        let name = self
+            .module
+            .declarations()
+            .get_function_decl(func_id)
+            .linkage_name(func_id)
+            .into_owned();
+        check_alignment(align, &name)?;
+        self.function_alignments.insert(func_id, align);
+        Ok(())
+    }
+
+    /// Align the data (which is about to be defined) to at least `align` bytes.
+    pub fn set_data_alignment(&mut self, data_id: DataId, align: u64) -> Result<(), ModuleError> {
+        check_alignment(align, &self.get_data_name(data_id))?;
+        self.data_alignments.insert(data_id, align);
+        Ok(())
+    }
+
+    /// The alignment of the function, i.e. the larger one of the alignment
+    /// required by the code and the requested alignment.
+    pub(crate) fn get_function_alignment(&self, func_id: FuncId, code_align: u64) -> u64 {
+        self.function_alignments
+            .get(&func_id)
+            .map_or(code_align, |align| code_align.max(*align))
+    }
+
+    /// Check the alignment of the definition and merge the requested alignment
+    /// of the data into it.
+    pub(crate) fn align_data_definition(
+        &self,
+        data_id: DataId,
+        data_definition: DataDefinition,
+    ) -> Result<DataDefinition, ModuleError> {
+        let (DataDefinition::Initialized { align, .. }
+        | DataDefinition::Uninitialized { align, .. }) = &data_definition;
+        check_alignment(*align, &self.get_data_name(data_id))?;
+
+        Ok(match data_definition {
+            DataDefinition::Initialized { data, align } => DataDefinition::Initialized {
+                data,
+                align: self.get_data_alignment(data_id, align),
+            },
+            DataDefinition::Uninitialized { size, align } => DataDefinition::Uninitialized {
+                size,
+                align: self.get_data_alignment(data_id, align),
+            },
+        })
+    }
+
+    /// The alignment of the data, i.e. the larger one of the alignment of
+    /// the definition and the requested alignment.
+    pub(crate) fn get_data_alignment(&self, data_id: DataId, definition_align: u64) -> u64 {
+        self.data_alignments
+            .get(&data_id)
+            .map_or(definition_align, |align| definition_align.max(*align))
+    }
+
+    fn get_data_name(&self, data_id: DataId) -> String {
+        self.module
+            .declarations()
+            .get_data_decl(data_id)
+            .linkage_name(data_id)
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::{
+        object::{File, Object, ObjectSection, ObjectSymbol},
+        ObjectModule,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::{DataDefinition, Generator},
+        formatter::format_module,
+        lowering::assemble_module,
+        parser::parse_module,
+    };
+
+    const SOURCE: &str = r#"
+    (module $aligned
+        (data $small (read_only i32 7))
+        (data $table export (align 256) (read_only i32 11 13))
+        (function $pad export (result i32)
+            (code (imm_i32 1)))
+        (function $hot export (align 64) (result i32)
+            (code (data_load_i32 $table 4))))
+    "#;
+
+    #[test]
+    fn test_alignment() {
+        let module = parse_module(SOURCE).unwrap();
+        assert_eq!(module.functions[1].align, Some(64));
+        assert!(format_module(&module).contains("(function $hot export (align 64)"));
+
+        // JIT, the data `counter` is aligned by the generator API
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let counter_id = generator
+            .module
+            .declare_data("counter", Linkage::Local, true, false)
+            .unwrap();
+        generator.set_data_alignment(counter_id, 512).unwrap();
+        generator
+            .define_data_content(
+                counter_id,
+                DataDefinition::Initialized {
+                    data: vec![0; 8],
+                    align: 8,
+                },
+            )
+            .unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let hot_id = assembled_module.get_function_id("hot").unwrap();
+        let hot_ptr = generator.module.get_finalized_function(hot_id);
+        let func_hot = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i32>(hot_ptr) };
+        assert_eq!(func_hot(), 13);
+        assert_eq!(hot_ptr as usize % 64, 0);
+
+        let table_id = assembled_module.get_data_id("table").unwrap();
+        let (table_ptr, _) = generator.module.get_finalized_data(table_id);
+        assert_eq!(table_ptr as usize % 256, 0);
+        let (counter_ptr, _) = generator.module.get_finalized_data(counter_id);
+        assert_eq!(counter_ptr as usize % 512, 0);
+
+        // object file
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        let binary = generator.finish().unwrap().emit().unwrap();
+        let file = File::parse(binary.as_slice()).unwrap();
+        for (name, align) in [("hot", 64), ("table", 256)] {
+            let symbol = file.symbol_by_name(name).unwrap();
+            let section = file
+                .section_by_index(symbol.section_index().unwrap())
+                .unwrap();
+            assert_eq!(symbol.address() % align, 0);
+            assert!(section.align() >= align);
+        }
+
+        // the data with different alignments are not merged
+        let mut generator = Generator::<ObjectModule>::new("dedup", None);
+        let mut data_definitions = vec![];
+        for name in ["a", "b"] {
+            let data_id = generator
+                .module
+                .declare_data(name, Linkage::Local, false, false)
+                .unwrap();
+            data_definitions.push((
+                data_id,
+                DataDefinition::Initialized {
+                    data: vec![1, 0, 0, 0],
+                    align: 4,
+                },
+            ));
+        }
+        generator
+            .set_data_alignment(data_definitions[1].0, 64)
+            .unwrap();
+        assert_eq!(
+            generator.deduplicate_data(&mut [], &mut data_definitions),
+            0
+        );
+
+        // errors
+        let mut generator = Generator::<ObjectModule>::new("errors", None);
+        let data_id = generator
+            .module
+            .declare_data("odd", Linkage::Local, false, false)
+            .unwrap();
+        assert_eq!(
+            generator
+                .set_data_alignment(data_id, 48)
+                .unwrap_err()
+                .to_string(),
+            "Backend error: the alignment 48 of \"odd\" should be a power of two and at most 4096"
+        );
+        assert_eq!(
+            generator
+                .define_data_content(data_id, DataDefinition::Uninitialized { size: 4, align: 0 })
+                .unwrap_err()
+                .to_string(),
+            "Backend error: the alignment 0 of \"odd\" should be a power of two and at most 4096"
+        );
+
+        let module =
+            parse_module("(module $m (function $f (align 8192) (result i32) (code (imm_i32 0))))")
+                .unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assert_eq!(
+            assemble_module(&module, &mut generator)
+                .unwrap_err()
+                .message,
+            "Backend error: the alignment 8192 of \"f\" should be a power of two and at most 4096"
+        );
+    }
+}
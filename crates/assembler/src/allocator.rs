@@ -0,0 +1,289 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{AbiParam, FuncRef, Function, InstBuilder, Type, Value};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{Linkage, Module, ModuleError};
+
+use crate::code_generator::Generator;
+
+// The heap allocation
+// -------------------
+//
+// The front ends allocate the heap memory by `emit_alloc()` and `emit_free()`
+// instead of calling the allocator functions directly, so the same code path
+// works in both the hosted mode (libc) and the freestanding mode (the allocator
+// of the XiaoXuan runtime), e.g.
+//
+// ```rust
+// generator.set_allocator(Allocator::Custom {
+//     alloc_name: "__anna_alloc".to_owned(),
+//     free_name: "__anna_free".to_owned(),
+// });
+//
+// let allocator_refs = generator.declare_allocator_in_func(&mut func)?;
+// let mut function_builder = FunctionBuilder::new(&mut func, ...);
+// ...
+// let value_ptr = allocator_refs.emit_alloc(&mut function_builder, value_size, 64);
+// ...
+// allocator_refs.emit_free(&mut function_builder, value_ptr);
+// ```
+//
+// the alignment handling:
+//
+// - libc: `malloc(size)` is used if the alignment is not greater than the
+//   alignment which is guaranteed by `malloc()` (16 bytes on the 64-bit
+//   targets), otherwise `aligned_alloc(align, size)` is used, and the size
+//   is rounded up to a multiple of the alignment (required by C11).
+// - custom: the alignment is always passed to the allocator, i.e. `alloc(size, align)`.
+
+/// The alignment which is guaranteed by the `malloc()` of glibc (on 64-bit targets).
+const MALLOC_ALIGNMENT: u64 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Allocator {
+    /// `malloc(size)`, `aligned_alloc(align, size)` and `free(ptr)` of libc.
+    #[default]
+    Libc,
+
+    /// `alloc(size, align) -> ptr` and `free(ptr)` of a custom allocator.
+    Custom {
+        alloc_name: String,
+        free_name: String,
+    },
+}
+
+/// The references of the allocator functions in a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorRefs {
+    /// `malloc(size)` or `alloc(size, align)`.
+    pub alloc: FuncRef,
+
+    /// `aligned_alloc(align, size)`, it is `None` for the custom allocator.
+    pub aligned_alloc: Option<FuncRef>,
+    pub free: FuncRef,
+    pub pointer_type: Type,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Set the allocator of `emit_alloc()` and `emit_free()`, the default is `Allocator::Libc`.
+    pub fn set_allocator(&mut self, allocator: Allocator) {
+        self.allocator = allocator;
+    }
+
+    /// Declare the allocator functions in the function.
+    pub fn declare_allocator_in_func(
+        &mut self,
+        func: &mut Function,
+    ) -> Result<AllocatorRefs, ModuleError> {
+        let pointer_type = self.module.isa().pointer_type();
+
+        let mut alloc_sig = self.module.make_signature();
+        alloc_sig.params.push(AbiParam::new(pointer_type));
+        alloc_sig.returns.push(AbiParam::new(pointer_type));
+
+        let mut free_sig = self.module.make_signature();
+        free_sig.params.push(AbiParam::new(pointer_type));
+
+        let (alloc_name, free_name, aligned_alloc_name) = match &self.allocator {
+            Allocator::Libc => ("malloc", "free", Some("aligned_alloc")),
+            Allocator::Custom {
+                alloc_name,
+                free_name,
+            } => {
+                alloc_sig.params.push(AbiParam::new(pointer_type));
+                (alloc_name.as_str(), free_name.as_str(), None)
+            }
+        };
+
+        let alloc_id = self
+            .module
+            .declare_function(alloc_name, Linkage::Import, &alloc_sig)?;
+        let free_id = self
+            .module
+            .declare_function(free_name, Linkage::Import, &free_sig)?;
+
+        let aligned_alloc = match aligned_alloc_name {
+            Some(name) => {
+                let mut aligned_alloc_sig = self.module.make_signature();
+                aligned_alloc_sig.params.push(AbiParam::new(pointer_type));
+                aligned_alloc_sig.params.push(AbiParam::new(pointer_type));
+                aligned_alloc_sig.returns.push(AbiParam::new(pointer_type));
+
+                let aligned_alloc_id =
+                    self.module
+                        .declare_function(name, Linkage::Import, &aligned_alloc_sig)?;
+                Some(self.module.declare_func_in_func(aligned_alloc_id, func))
+            }
+            None => None,
+        };
+
+        Ok(AllocatorRefs {
+            alloc: self.module.declare_func_in_func(alloc_id, func),
+            aligned_alloc,
+            free: self.module.declare_func_in_func(free_id, func),
+            pointer_type,
+        })
+    }
+}
+
+impl AllocatorRefs {
+    /// Allocate `size` bytes (a value of the pointer type) with the given
+    /// alignment (a power of two), returns the pointer (it is zero if the
+    /// allocation fails).
+    pub fn emit_alloc(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        size: Value,
+        align: u64,
+    ) -> Value {
+        assert!(
+            align.is_power_of_two(),
+            "the alignment should be a power of two"
+        );
+
+        let call = match self.aligned_alloc {
+            None => {
+                let value_align = function_builder
+                    .ins()
+                    .iconst(self.pointer_type, align as i64);
+                function_builder
+                    .ins()
+                    .call(self.alloc, &[size, value_align])
+            }
+            Some(_) if align <= MALLOC_ALIGNMENT => {
+                function_builder.ins().call(self.alloc, &[size])
+            }
+            Some(aligned_alloc) => {
+                // size = (size + align - 1) & !(align - 1)
+                let value_0 = function_builder.ins().iadd_imm(size, align as i64 - 1);
+                let value_size = function_builder.ins().band_imm(value_0, -(align as i64));
+                let value_align = function_builder
+                    .ins()
+                    .iconst(self.pointer_type, align as i64);
+                function_builder
+                    .ins()
+                    .call(aligned_alloc, &[value_align, value_size])
+            }
+        };
+
+        function_builder.inst_results(call)[0]
+    }
+
+    /// Free the memory which is allocated by `emit_alloc()`.
+    pub fn emit_free(&self, function_builder: &mut FunctionBuilder, ptr: Value) {
+        function_builder.ins().call(self.free, &[ptr]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, MemFlags, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{FuncId, Linkage, Module};
+
+    use crate::{allocator::Allocator, code_generator::Generator};
+
+    static ALLOCATIONS: Mutex<Vec<(usize, usize)>> = Mutex::new(vec![]);
+    static mut HEAP: [u64; 16] = [0; 16];
+
+    extern "C" fn runtime_alloc(size: usize, align: usize) -> *mut u8 {
+        ALLOCATIONS.lock().unwrap().push((size, align));
+        std::ptr::addr_of_mut!(HEAP) as *mut u8
+    }
+
+    extern "C" fn runtime_free(_ptr: *mut u8) {}
+
+    // ```rust
+    // fn main() -> usize {
+    //     let ptr = alloc(24, 64);
+    //     *ptr = 11;
+    //     let result = *ptr + (ptr & 63);
+    //     free(ptr);
+    //     result
+    // }
+    // ```
+    fn build_main(generator: &mut Generator<JITModule>) -> FuncId {
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(pointer_type));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Local, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let allocator_refs = generator.declare_allocator_in_func(&mut func_main).unwrap();
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_size = function_builder.ins().iconst(pointer_type, 24);
+        let value_ptr = allocator_refs.emit_alloc(&mut function_builder, value_size, 64);
+        let value_0 = function_builder.ins().iconst(types::I64, 11);
+        function_builder
+            .ins()
+            .store(MemFlags::trusted(), value_0, value_ptr, 0);
+        let value_1 = function_builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), value_ptr, 0);
+        let value_2 = function_builder.ins().band_imm(value_ptr, 63);
+        let value_3 = function_builder.ins().iadd(value_1, value_2);
+        allocator_refs.emit_free(&mut function_builder, value_ptr);
+        function_builder.ins().return_(&[value_3]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        generator.module.finalize_definitions().unwrap();
+        func_main_id
+    }
+
+    #[test]
+    fn test_allocator() {
+        // libc, the pointer is aligned by `aligned_alloc()`
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let func_main_id = build_main(&mut generator);
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let func_main: extern "C" fn() -> usize = unsafe { std::mem::transmute(func_main_ptr) };
+        assert_eq!(func_main(), 11);
+
+        // custom
+        let symbols = vec![
+            ("runtime_alloc".to_owned(), runtime_alloc as *const u8),
+            ("runtime_free".to_owned(), runtime_free as *const u8),
+        ];
+        let mut generator = Generator::<JITModule>::new(symbols);
+        generator.set_allocator(Allocator::Custom {
+            alloc_name: "runtime_alloc".to_owned(),
+            free_name: "runtime_free".to_owned(),
+        });
+        let func_main_id = build_main(&mut generator);
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let func_main: extern "C" fn() -> usize = unsafe { std::mem::transmute(func_main_ptr) };
+        let heap_offset = std::ptr::addr_of!(HEAP) as usize & 63;
+        assert_eq!(func_main(), 11 + heap_offset);
+        assert_eq!(*ALLOCATIONS.lock().unwrap(), vec![(24, 64)]);
+    }
+}
@@ -0,0 +1,858 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use crate::{
+    branch_weight::BranchWeight, function_attribute::FunctionAttributes, lexer::Span,
+    multiversion::CpuFeature,
+};
+
+// The AST of the assembly text
+// ----------------------------
+//
+// e.g.
+//
+// ```text
+// (module $app
+//     (import (function $puts "puts" (param i64) (result i32)))
+//     (import (data $errno "errno" tls))
+//
+//     (data $count export (read_write i32 0))
+//     (data $message (read_only bytes "hello\0"))
+//     (data $buffer (uninit 64 8))                 // size and alignment
+//
+//     (function $add (param $a i32) (param $b i32) (result i32)
+//         (code
+//             (add_i32 (local_load $a) (local_load $b))
+//         )
+//     )
+//
+//     (function $main export (result i32) (local $sum i32)
+//         (code
+//             (local_store $sum (call $add (imm_i32 11) (imm_i32 2)))
+//             (local_load $sum)
+//         )
+//     )
+// )
+// ```
+//
+// the instructions are written in the "folded" form, i.e. the operands are
+// the nested instructions, and the names of the instructions follow
+// the document "xiaoxuan_native_assembly_cranelift_ir_map.ods".
+//
+// the value types are `i32`, `i64`, `f32` and `f64`, the comparisons
+// return `i32` (`0` or `1`).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValueType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "i32" => Some(ValueType::I32),
+            "i64" => Some(ValueType::I64),
+            "f32" => Some(ValueType::F32),
+            "f64" => Some(ValueType::F64),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+        }
+    }
+}
+
+/// The ABI of the results of a function, i.e. how the multiple results are
+/// passed across the FFI boundary, e.g. `(function $divmod export out_pointers ...)`.
+///
+/// The multiple results of Cranelift have no counterpart in C, so the
+/// functions which are exported to (or imported from) C should pack them:
+///
+/// - `struct_return`: the results are the fields of a struct (i.e. `#[repr(C)]`),
+///   which is returned by the hidden pointer (i.e. `sret`), e.g.
+///   `struct divmod_result divmod(int64_t a, int64_t b)`. C returns the structs
+///   of at most 16 bytes in the registers instead, so the struct should be
+///   larger than 16 bytes.
+/// - `out_pointers`: the first result is returned, and the others are written
+///   to the pointers which are appended to the params, e.g.
+///   `int32_t divmod(int32_t a, int32_t b, int32_t *remainder)`.
+///
+/// the ABI is transparent to the callers within the assembly, i.e. they call
+/// the function by the params and receive the results as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResultAbi {
+    /// The multiple results of Cranelift, i.e. the results are returned in
+    /// the registers, it is only suitable for the callers within the assembly.
+    #[default]
+    Multiple,
+    StructReturn,
+    OutPointers,
+}
+
+impl ResultAbi {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "struct_return" => Some(ResultAbi::StructReturn),
+            "out_pointers" => Some(ResultAbi::OutPointers),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            ResultAbi::Multiple => None,
+            ResultAbi::StructReturn => Some("struct_return"),
+            ResultAbi::OutPointers => Some("out_pointers"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub imports: Vec<ImportNode>,
+
+    /// The other source files which are imported by `(import (module "path"))`,
+    /// they are merged into this module by `resolve_module()`.
+    pub module_imports: Vec<ImportModuleNode>,
+
+    pub data: Vec<DataNode>,
+    pub functions: Vec<FunctionNode>,
+
+    /// The test functions `(test $name ...)`, they have no params and results,
+    /// and are assembled by the test runner only, see `test_harness.rs`.
+    pub tests: Vec<FunctionNode>,
+    pub span: Span,
+}
+
+impl Module {
+    /// The libraries which are required by the imported functions (see `extern-c`),
+    /// in the order of the first occurrence.
+    pub fn get_libraries(&self) -> Vec<&str> {
+        let mut libraries: Vec<&str> = vec![];
+        for import in &self.imports {
+            if let ImportNode::Function(ImportFunctionNode {
+                library: Some(library),
+                ..
+            }) = import
+            {
+                if !libraries.contains(&library.as_str()) {
+                    libraries.push(library);
+                }
+            }
+        }
+        libraries
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportNode {
+    /// `(import (function $name "symbol" [colocated] [noreturn] [pure|readonly] [struct_return|out_pointers] (param i32) (result i32)))`,
+    /// the symbol is the same as the name if it is omitted.
+    ///
+    /// or the C function `(extern-c "symbol" [variadic] [noreturn] [pure|readonly] [struct_return|out_pointers] (params i64) (results i32) (library "c"))`,
+    /// the name is the same as the symbol.
+    Function(ImportFunctionNode),
+
+    /// `(import (data $name "symbol"))` or `(import (data $name "symbol" tls))`,
+    /// the expected size and alignment are optional, e.g.
+    /// `(import (data $name "symbol" (size 8) (align 8)))`.
+    Data(ImportDataNode),
+}
+
+impl ImportNode {
+    pub fn get_name(&self) -> &str {
+        match self {
+            ImportNode::Function(node) => &node.name,
+            ImportNode::Data(node) => &node.name,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            ImportNode::Function(node) => node.span,
+            ImportNode::Data(node) => node.span,
+        }
+    }
+}
+
+/// `(import (module "path"))`, the extension ".ancasm" can be omitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportModuleNode {
+    pub path: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportFunctionNode {
+    pub name: String,
+    pub symbol: String,
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
+
+    /// The C variadic function (e.g. `printf`), the arguments after the
+    /// params are passed as the variadic arguments.
+    pub variadic: bool,
+
+    /// The link name of the library which provides the function, e.g. "m" of `libm`.
+    pub library: Option<String>,
+
+    /// The function ends up in the same final binary (e.g. the runtime library
+    /// objects), so it is called directly instead of through the GOT, see
+    /// `Generator::import_function_colocated()`.
+    pub colocated: bool,
+
+    /// The ABI of the results, see `ResultAbi`.
+    pub result_abi: ResultAbi,
+
+    /// `noreturn`, `pure` and `readonly`, see `function_attribute.rs`.
+    pub attributes: FunctionAttributes,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportDataNode {
+    pub name: String,
+    pub symbol: String,
+    pub tls: bool,
+
+    /// The expected size and alignment of the data, they are checked
+    /// after linking, see `import_check.rs`.
+    pub size: Option<u32>,
+    pub align: Option<u32>,
+    pub span: Span,
+}
+
+/// `(data $name [export] (align n)? (section "name")? (read_only|read_write ...))`
+/// or `(data $name [export] (align n)? (uninit size [align]))`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataNode {
+    pub name: String,
+    pub export: bool,
+    pub kind: DataKind,
+
+    /// The alignment which overrides the natural alignment of the value.
+    pub align: Option<u32>,
+
+    /// The custom section of the object file, e.g. ".rodata.table".
+    pub section: Option<String>,
+    pub span: Span,
+}
+
+impl DataNode {
+    /// Get the alignment of the data, i.e. the `(align n)` attribute,
+    /// or the natural alignment of the value.
+    pub fn get_align(&self) -> u32 {
+        match (&self.kind, self.align) {
+            (_, Some(align)) => align,
+            (DataKind::ReadOnly(value) | DataKind::ReadWrite(value), None) => value.align(),
+            (DataKind::Uninit { align, .. }, None) => *align,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataKind {
+    ReadOnly(DataValue),
+    ReadWrite(DataValue),
+    Uninit { size: u32, align: u32 },
+}
+
+/// e.g. `i32 100`, `f64 3.14`, `i16 1 2 3` (an array), `bytes "hello\0"`,
+/// `bytes 0x7f 0x45`, `cstring "hello"`, `lstring "hello"`, `zero 64`,
+/// `addr_data $table 8 $buffer` (the addresses of the data, each one can be
+/// followed by an offset) and `addr_function $add $sub`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataValue {
+    I8(u8),
+    I16(u16),
+    I32(u32),
+    I64(u64),
+    F32(f32),
+    F64(f64),
+    Bytes(Vec<u8>),
+
+    /// The NUL-terminated string, the NUL is not included in the bytes.
+    CString(Vec<u8>),
+
+    /// The length-prefixed string, the length is a 32-bit integer.
+    LString(Vec<u8>),
+
+    /// The array of the numbers of the same type.
+    Array(Vec<DataValue>),
+
+    /// The zero bytes with the size.
+    Zero(u32),
+
+    /// The 64-bit address of the data plus the offset, e.g. the address of
+    /// a struct field of an imported data, it is filled by the relocation.
+    DataAddress {
+        name: String,
+        offset: i32,
+    },
+
+    /// The 64-bit address of the function, it is filled by the relocation.
+    FunctionAddress(String),
+}
+
+impl DataValue {
+    /// Get the content in little-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            DataValue::I8(value) => vec![*value],
+            DataValue::I16(value) => value.to_le_bytes().to_vec(),
+            DataValue::I32(value) => value.to_le_bytes().to_vec(),
+            DataValue::I64(value) => value.to_le_bytes().to_vec(),
+            DataValue::F32(value) => value.to_le_bytes().to_vec(),
+            DataValue::F64(value) => value.to_le_bytes().to_vec(),
+            DataValue::Bytes(bytes) => bytes.clone(),
+            DataValue::CString(bytes) => {
+                let mut content = bytes.clone();
+                content.push(0);
+                content
+            }
+            DataValue::LString(bytes) => {
+                let mut content = (bytes.len() as u32).to_le_bytes().to_vec();
+                content.extend_from_slice(bytes);
+                content
+            }
+            DataValue::Array(items) => items.iter().flat_map(|item| item.to_bytes()).collect(),
+            DataValue::Zero(size) => vec![0; *size as usize],
+            DataValue::DataAddress { .. } | DataValue::FunctionAddress(_) => vec![0; 8],
+        }
+    }
+
+    /// Get the addresses in the content and their offsets.
+    pub fn get_addresses(&self) -> Vec<(u32, &DataValue)> {
+        match self {
+            DataValue::DataAddress { .. } | DataValue::FunctionAddress(_) => vec![(0, self)],
+            DataValue::Array(items) => items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| !item.get_addresses().is_empty())
+                .map(|(index, item)| ((index * 8) as u32, item))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn align(&self) -> u32 {
+        match self {
+            DataValue::I8(_) | DataValue::Bytes(_) | DataValue::CString(_) | DataValue::Zero(_) => {
+                1
+            }
+            DataValue::I16(_) => 2,
+            DataValue::I32(_) | DataValue::F32(_) | DataValue::LString(_) => 4,
+            DataValue::I64(_)
+            | DataValue::F64(_)
+            | DataValue::DataAddress { .. }
+            | DataValue::FunctionAddress(_) => 8,
+            DataValue::Array(items) => items.first().map(|item| item.align()).unwrap_or(1),
+        }
+    }
+}
+
+/// `(function $name [export] [struct_return|out_pointers] [(align n)] [(target_clones "avx2,fma" ...)] (param $a i32)* (result i32)* (local $b i32)* (code ...))`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionNode {
+    pub name: String,
+    pub export: bool,
+
+    /// The ABI of the results, see `ResultAbi`.
+    pub result_abi: ResultAbi,
+
+    /// The alignment of the entry of the function, see `alignment.rs`.
+    pub align: Option<u32>,
+
+    /// The CPU feature sets of the versions, see `multiversion.rs`.
+    pub target_clones: Vec<Vec<CpuFeature>>,
+    pub params: Vec<LocalNode>,
+    pub results: Vec<ValueType>,
+    pub locals: Vec<LocalNode>,
+    pub body: Vec<Instruction>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalNode {
+    pub name: String,
+    pub value_type: ValueType,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub kind: InstructionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstructionKind {
+    /// `(nop)`
+    Nop,
+
+    /// `(imm_i32 123)`, the integer is stored as the unsigned number,
+    /// e.g. `(imm_i32 -1)` is `0xffff_ffff`.
+    ImmI32(u32),
+    ImmI64(u64),
+    ImmF32(f32),
+    ImmF64(f64),
+
+    /// `(local_load $name)`, load the parameter or the local variable.
+    LocalLoad(String),
+
+    /// `(local_store $name value)`
+    LocalStore {
+        name: String,
+        value: Box<Instruction>,
+    },
+
+    /// `(data_load_i32 $name [offset])`
+    DataLoad {
+        load_type: LoadType,
+        name: String,
+        offset: i32,
+    },
+
+    /// `(data_store_i32 $name [offset] value)`
+    DataStore {
+        store_type: StoreType,
+        name: String,
+        offset: i32,
+        value: Box<Instruction>,
+    },
+
+    /// `(memory_load_i32 address [offset])`
+    MemoryLoad {
+        load_type: LoadType,
+        address: Box<Instruction>,
+        offset: i32,
+    },
+
+    /// `(memory_store_i32 address [offset] value)`
+    MemoryStore {
+        store_type: StoreType,
+        address: Box<Instruction>,
+        offset: i32,
+        value: Box<Instruction>,
+    },
+
+    /// `(host_addr_function $name)`, get the address of the function.
+    HostAddrFunction(String),
+
+    /// `(host_addr_data $name [offset])`, get the address of the data plus
+    /// the offset, e.g. the address of an item of a table.
+    HostAddrData {
+        name: String,
+        offset: i32,
+    },
+
+    /// The arithmetic, bitwise, comparison and conversion instructions,
+    /// e.g. `(add_i32 left right)`.
+    Operation {
+        opcode: Opcode,
+        operands: Vec<Instruction>,
+    },
+
+    /// `(do instruction...)`, the values of the last instruction are the results.
+    Do(Vec<Instruction>),
+
+    /// `(if [(result type...)] [(weight ...)] condition then else)`, the
+    /// weight is `(weight taken not_taken)` or `(weight probability)`,
+    /// see `branch_weight.rs`.
+    If {
+        results: Vec<ValueType>,
+        weight: Option<BranchWeight>,
+        condition: Box<Instruction>,
+        consequent: Box<Instruction>,
+        alternative: Box<Instruction>,
+    },
+
+    /// `(when [(weight ...)] condition instruction...)`, it has no results.
+    When {
+        weight: Option<BranchWeight>,
+        condition: Box<Instruction>,
+        body: Vec<Instruction>,
+    },
+
+    /// `(for (param $name type init)* (result type)* instruction...)`,
+    /// the loop, `(recur ...)` jumps to the start with the new values of
+    /// the parameters, and `(break ...)` exits with the results, the values
+    /// of the last instruction are the results if the body falls through.
+    For {
+        params: Vec<(LocalNode, Instruction)>,
+        results: Vec<ValueType>,
+        body: Vec<Instruction>,
+    },
+
+    /// `(break value...)`, exit the innermost `for`.
+    Break(Vec<Instruction>),
+
+    /// `(recur value...)`, jump to the start of the innermost `for`.
+    Recur(Vec<Instruction>),
+
+    /// `(break_fn value...)`, return from the function.
+    BreakFn(Vec<Instruction>),
+
+    /// `(recur_fn value...)`, jump to the start of the function with the
+    /// new values of the parameters (i.e. the self tail call).
+    RecurFn(Vec<Instruction>),
+
+    /// `(call $name arg...)`
+    Call {
+        name: String,
+        args: Vec<Instruction>,
+    },
+
+    /// `(dyncall (param type...) (result type...) callee arg...)`,
+    /// call the function by address.
+    DynCall {
+        params: Vec<ValueType>,
+        results: Vec<ValueType>,
+        callee: Box<Instruction>,
+        args: Vec<Instruction>,
+    },
+
+    /// `(clif (param type...) (result type...) "text" arg...)`,
+    /// the inline Cranelift IR, see `inline_clif.rs`.
+    Clif {
+        params: Vec<ValueType>,
+        results: Vec<ValueType>,
+        text: String,
+        args: Vec<Instruction>,
+    },
+
+    /// `(asm "template" (in "register" value)... (out "register")... (clobber "register"...)...)`,
+    /// the inline assembly, the outputs are the registers and the types of
+    /// their values, see `inline_asm.rs`.
+    Asm {
+        template: Vec<u8>,
+        inputs: Vec<(String, Instruction)>,
+        outputs: Vec<(String, ValueType)>,
+        clobbers: Vec<String>,
+    },
+
+    /// `(panic code)`, terminate the program, the code is 1 to 255.
+    Panic(u8),
+
+    /// `(exit code)`, terminate the process with the exit code (an i32 value),
+    /// see `process_exit.rs`.
+    Exit(Box<Instruction>),
+
+    /// `(abort)`, terminate the process abnormally.
+    Abort,
+
+    /// `(read_cycle_counter)`, read the cycle counter (an i64 value), see
+    /// `cycle_counter.rs`.
+    ReadCycleCounter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadType {
+    I8S,
+    I8U,
+    I16S,
+    I16U,
+    I32,
+    I32S,
+    I32U,
+    I64,
+    F32,
+    F64,
+}
+
+impl LoadType {
+    /// e.g. "i8_s" from "data_load_i8_s".
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "i8_s" => Some(LoadType::I8S),
+            "i8_u" => Some(LoadType::I8U),
+            "i16_s" => Some(LoadType::I16S),
+            "i16_u" => Some(LoadType::I16U),
+            "i32" => Some(LoadType::I32),
+            "i32_s" => Some(LoadType::I32S),
+            "i32_u" => Some(LoadType::I32U),
+            "i64" => Some(LoadType::I64),
+            "f32" => Some(LoadType::F32),
+            "f64" => Some(LoadType::F64),
+            _ => None,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            LoadType::I8S => "i8_s",
+            LoadType::I8U => "i8_u",
+            LoadType::I16S => "i16_s",
+            LoadType::I16U => "i16_u",
+            LoadType::I32 => "i32",
+            LoadType::I32S => "i32_s",
+            LoadType::I32U => "i32_u",
+            LoadType::I64 => "i64",
+            LoadType::F32 => "f32",
+            LoadType::F64 => "f64",
+        }
+    }
+
+    /// The type of the loaded value, the 8-bit and 16-bit integers are
+    /// extended to `i32`, and the `i32_s` and `i32_u` are extended to `i64`.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            LoadType::I8S | LoadType::I8U | LoadType::I16S | LoadType::I16U | LoadType::I32 => {
+                ValueType::I32
+            }
+            LoadType::I32S | LoadType::I32U | LoadType::I64 => ValueType::I64,
+            LoadType::F32 => ValueType::F32,
+            LoadType::F64 => ValueType::F64,
+        }
+    }
+
+    /// The number of the bytes which are read from the memory.
+    pub fn access_size(&self) -> u32 {
+        match self {
+            LoadType::I8S | LoadType::I8U => 1,
+            LoadType::I16S | LoadType::I16U => 2,
+            LoadType::I32 | LoadType::I32S | LoadType::I32U | LoadType::F32 => 4,
+            LoadType::I64 | LoadType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreType {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl StoreType {
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "i8" => Some(StoreType::I8),
+            "i16" => Some(StoreType::I16),
+            "i32" => Some(StoreType::I32),
+            "i64" => Some(StoreType::I64),
+            "f32" => Some(StoreType::F32),
+            "f64" => Some(StoreType::F64),
+            _ => None,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            StoreType::I8 => "i8",
+            StoreType::I16 => "i16",
+            StoreType::I32 => "i32",
+            StoreType::I64 => "i64",
+            StoreType::F32 => "f32",
+            StoreType::F64 => "f64",
+        }
+    }
+
+    /// Get the type of the value to be stored, the `i8` and `i16` store
+    /// the low bits of an `i32`.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            StoreType::I8 | StoreType::I16 | StoreType::I32 => ValueType::I32,
+            StoreType::I64 => ValueType::I64,
+            StoreType::F32 => ValueType::F32,
+            StoreType::F64 => ValueType::F64,
+        }
+    }
+
+    /// The number of the bytes which are written to the memory.
+    pub fn access_size(&self) -> u32 {
+        match self {
+            StoreType::I8 => 1,
+            StoreType::I16 => 2,
+            StoreType::I32 | StoreType::F32 => 4,
+            StoreType::I64 | StoreType::F64 => 8,
+        }
+    }
+}
+
+macro_rules! define_opcodes {
+    ($($variant:ident = $name:literal ($($param:ident),*) -> $result:ident,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Opcode {
+            $($variant,)*
+        }
+
+        impl Opcode {
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(Opcode::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Opcode::$variant => $name,)*
+                }
+            }
+
+            pub fn param_types(&self) -> &'static [ValueType] {
+                match self {
+                    $(Opcode::$variant => &[$(ValueType::$param),*],)*
+                }
+            }
+
+            pub fn result_type(&self) -> ValueType {
+                match self {
+                    $(Opcode::$variant => ValueType::$result,)*
+                }
+            }
+        }
+    };
+}
+
+define_opcodes! {
+    EqI32 = "eq_i32" (I32, I32) -> I32,
+    NeI32 = "ne_i32" (I32, I32) -> I32,
+    LtI32S = "lt_i32_s" (I32, I32) -> I32,
+    LtI32U = "lt_i32_u" (I32, I32) -> I32,
+    GtI32S = "gt_i32_s" (I32, I32) -> I32,
+    GtI32U = "gt_i32_u" (I32, I32) -> I32,
+    LeI32S = "le_i32_s" (I32, I32) -> I32,
+    LeI32U = "le_i32_u" (I32, I32) -> I32,
+    GeI32S = "ge_i32_s" (I32, I32) -> I32,
+    GeI32U = "ge_i32_u" (I32, I32) -> I32,
+    EqzI32 = "eqz_i32" (I32) -> I32,
+    NezI32 = "nez_i32" (I32) -> I32,
+    EqI64 = "eq_i64" (I64, I64) -> I32,
+    NeI64 = "ne_i64" (I64, I64) -> I32,
+    LtI64S = "lt_i64_s" (I64, I64) -> I32,
+    LtI64U = "lt_i64_u" (I64, I64) -> I32,
+    GtI64S = "gt_i64_s" (I64, I64) -> I32,
+    GtI64U = "gt_i64_u" (I64, I64) -> I32,
+    LeI64S = "le_i64_s" (I64, I64) -> I32,
+    LeI64U = "le_i64_u" (I64, I64) -> I32,
+    GeI64S = "ge_i64_s" (I64, I64) -> I32,
+    GeI64U = "ge_i64_u" (I64, I64) -> I32,
+    EqzI64 = "eqz_i64" (I64) -> I32,
+    NezI64 = "nez_i64" (I64) -> I32,
+    EqF32 = "eq_f32" (F32, F32) -> I32,
+    NeF32 = "ne_f32" (F32, F32) -> I32,
+    LtF32 = "lt_f32" (F32, F32) -> I32,
+    GtF32 = "gt_f32" (F32, F32) -> I32,
+    LeF32 = "le_f32" (F32, F32) -> I32,
+    GeF32 = "ge_f32" (F32, F32) -> I32,
+    EqF64 = "eq_f64" (F64, F64) -> I32,
+    NeF64 = "ne_f64" (F64, F64) -> I32,
+    LtF64 = "lt_f64" (F64, F64) -> I32,
+    GtF64 = "gt_f64" (F64, F64) -> I32,
+    LeF64 = "le_f64" (F64, F64) -> I32,
+    GeF64 = "ge_f64" (F64, F64) -> I32,
+    AddI32 = "add_i32" (I32, I32) -> I32,
+    SubI32 = "sub_i32" (I32, I32) -> I32,
+    MulI32 = "mul_i32" (I32, I32) -> I32,
+    DivI32S = "div_i32_s" (I32, I32) -> I32,
+    DivI32U = "div_i32_u" (I32, I32) -> I32,
+    RemI32S = "rem_i32_s" (I32, I32) -> I32,
+    RemI32U = "rem_i32_u" (I32, I32) -> I32,
+    NegI32 = "neg_i32" (I32) -> I32,
+    AbsI32 = "abs_i32" (I32) -> I32,
+    AddI64 = "add_i64" (I64, I64) -> I64,
+    SubI64 = "sub_i64" (I64, I64) -> I64,
+    MulI64 = "mul_i64" (I64, I64) -> I64,
+    DivI64S = "div_i64_s" (I64, I64) -> I64,
+    DivI64U = "div_i64_u" (I64, I64) -> I64,
+    RemI64S = "rem_i64_s" (I64, I64) -> I64,
+    RemI64U = "rem_i64_u" (I64, I64) -> I64,
+    NegI64 = "neg_i64" (I64) -> I64,
+    AbsI64 = "abs_i64" (I64) -> I64,
+    AndI32 = "and_i32" (I32, I32) -> I32,
+    OrI32 = "or_i32" (I32, I32) -> I32,
+    XorI32 = "xor_i32" (I32, I32) -> I32,
+    NotI32 = "not_i32" (I32) -> I32,
+    ShiftLeftI32 = "shift_left_i32" (I32, I32) -> I32,
+    ShiftRightI32S = "shift_right_i32_s" (I32, I32) -> I32,
+    ShiftRightI32U = "shift_right_i32_u" (I32, I32) -> I32,
+    RotateLeftI32 = "rotate_left_i32" (I32, I32) -> I32,
+    RotateRightI32 = "rotate_right_i32" (I32, I32) -> I32,
+    CountLeadingZerosI32 = "count_leading_zeros_i32" (I32) -> I32,
+    CountLeadingOnesI32 = "count_leading_ones_i32" (I32) -> I32,
+    CountTrailingZerosI32 = "count_trailing_zeros_i32" (I32) -> I32,
+    CountOnesI32 = "count_ones_i32" (I32) -> I32,
+    AndI64 = "and_i64" (I64, I64) -> I64,
+    OrI64 = "or_i64" (I64, I64) -> I64,
+    XorI64 = "xor_i64" (I64, I64) -> I64,
+    NotI64 = "not_i64" (I64) -> I64,
+    ShiftLeftI64 = "shift_left_i64" (I64, I32) -> I64,
+    ShiftRightI64S = "shift_right_i64_s" (I64, I32) -> I64,
+    ShiftRightI64U = "shift_right_i64_u" (I64, I32) -> I64,
+    RotateLeftI64 = "rotate_left_i64" (I64, I32) -> I64,
+    RotateRightI64 = "rotate_right_i64" (I64, I32) -> I64,
+    CountLeadingZerosI64 = "count_leading_zeros_i64" (I64) -> I64,
+    CountLeadingOnesI64 = "count_leading_ones_i64" (I64) -> I64,
+    CountTrailingZerosI64 = "count_trailing_zeros_i64" (I64) -> I64,
+    CountOnesI64 = "count_ones_i64" (I64) -> I64,
+    AddF32 = "add_f32" (F32, F32) -> F32,
+    SubF32 = "sub_f32" (F32, F32) -> F32,
+    MulF32 = "mul_f32" (F32, F32) -> F32,
+    DivF32 = "div_f32" (F32, F32) -> F32,
+    CopysignF32 = "copysign_f32" (F32, F32) -> F32,
+    MinF32 = "min_f32" (F32, F32) -> F32,
+    MaxF32 = "max_f32" (F32, F32) -> F32,
+    SqrtF32 = "sqrt_f32" (F32) -> F32,
+    NegF32 = "neg_f32" (F32) -> F32,
+    AbsF32 = "abs_f32" (F32) -> F32,
+    CeilF32 = "ceil_f32" (F32) -> F32,
+    FloorF32 = "floor_f32" (F32) -> F32,
+    TruncF32 = "trunc_f32" (F32) -> F32,
+    RoundHalfToEvenF32 = "round_half_to_even_f32" (F32) -> F32,
+    AddF64 = "add_f64" (F64, F64) -> F64,
+    SubF64 = "sub_f64" (F64, F64) -> F64,
+    MulF64 = "mul_f64" (F64, F64) -> F64,
+    DivF64 = "div_f64" (F64, F64) -> F64,
+    CopysignF64 = "copysign_f64" (F64, F64) -> F64,
+    MinF64 = "min_f64" (F64, F64) -> F64,
+    MaxF64 = "max_f64" (F64, F64) -> F64,
+    SqrtF64 = "sqrt_f64" (F64) -> F64,
+    NegF64 = "neg_f64" (F64) -> F64,
+    AbsF64 = "abs_f64" (F64) -> F64,
+    CeilF64 = "ceil_f64" (F64) -> F64,
+    FloorF64 = "floor_f64" (F64) -> F64,
+    TruncF64 = "trunc_f64" (F64) -> F64,
+    RoundHalfToEvenF64 = "round_half_to_even_f64" (F64) -> F64,
+    TruncateI64ToI32 = "truncate_i64_to_i32" (I64) -> I32,
+    ExtendI32SToI64 = "extend_i32_s_to_i64" (I32) -> I64,
+    ExtendI32UToI64 = "extend_i32_u_to_i64" (I32) -> I64,
+    PromoteF32ToF64 = "promote_f32_to_f64" (F32) -> F64,
+    DemoteF64ToF32 = "demote_f64_to_f32" (F64) -> F32,
+    ConvertF32ToI32S = "convert_f32_to_i32_s" (F32) -> I32,
+    ConvertF32ToI32U = "convert_f32_to_i32_u" (F32) -> I32,
+    ConvertF32ToI64S = "convert_f32_to_i64_s" (F32) -> I64,
+    ConvertF32ToI64U = "convert_f32_to_i64_u" (F32) -> I64,
+    ConvertF64ToI32S = "convert_f64_to_i32_s" (F64) -> I32,
+    ConvertF64ToI32U = "convert_f64_to_i32_u" (F64) -> I32,
+    ConvertF64ToI64S = "convert_f64_to_i64_s" (F64) -> I64,
+    ConvertF64ToI64U = "convert_f64_to_i64_u" (F64) -> I64,
+    ConvertI32SToF32 = "convert_i32_s_to_f32" (I32) -> F32,
+    ConvertI32SToF64 = "convert_i32_s_to_f64" (I32) -> F64,
+    ConvertI32UToF32 = "convert_i32_u_to_f32" (I32) -> F32,
+    ConvertI32UToF64 = "convert_i32_u_to_f64" (I32) -> F64,
+    ConvertI64SToF32 = "convert_i64_s_to_f32" (I64) -> F32,
+    ConvertI64SToF64 = "convert_i64_s_to_f64" (I64) -> F64,
+    ConvertI64UToF32 = "convert_i64_u_to_f32" (I64) -> F32,
+    ConvertI64UToF64 = "convert_i64_u_to_f64" (I64) -> F64,
+    StringArrayItem = "string_array_item" (I64, I32) -> I64,
+    StringArrayLength = "string_array_length" (I64) -> I32,
+    StringLength = "string_length" (I64) -> I64,
+}
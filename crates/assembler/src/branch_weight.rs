@@ -0,0 +1,328 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{collections::HashMap, path::Path};
+
+use cranelift_codegen::ir::{Block, InstBuilder, Value};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::Module;
+
+use crate::code_generator::Generator;
+
+// The branch weights
+// ------------------
+//
+// Cranelift places the cold blocks at the end of the function (see
+// `FunctionBuilder::set_cold_block()`), so the branches to them are "not taken"
+// and the hot path falls through. there is no probability of the branches
+// in CLIF, so the weights of the branches are used to decide which targets
+// are cold, a weight is the execution counts of the two targets of a `brif`
+// (e.g. from a profile), or the probability of taking the first target, e.g.
+//
+// ```rust
+// emit_brif_with_weight(
+//     &mut function_builder,
+//     condition,
+//     block_then, &[],
+//     block_else, &[],
+//     BranchWeight::Counts { taken: 1_000_000, not_taken: 3 },
+// );
+// ```
+//
+// a target is cold if its probability is less than 1% (see `COLD_PROBABILITY`),
+// e.g. the `block_else` above. the targets should be reached only by the
+// branch, otherwise the other (maybe hot) paths to them are laid out cold too.
+//
+// in the text format, the weight is annotated on `if` and `when`:
+//
+// ```anasm
+// (if (result i32) (weight 1000000 3) condition then else)
+// (when (weight 0.001) (lt_i32_s (local_load $x) (imm_i32 0)) ...)
+// ```
+//
+// or it is read from the branch profile (see `read_branch_profile()` and
+// `Generator::set_branch_profile()`), the annotations in the source take
+// precedence. each line of the profile is the counts of the two targets and
+// the branch, which is identified by the name of the function and the index
+// of the `if` or `when` (they are numbered from 0 in the order of the source
+// text of the function), e.g.
+//
+// ```text
+// # taken  not_taken  branch
+// 1000000  3          fib#0
+// 0        42         main#1
+// ```
+//
+// ref:
+// - https://llvm.org/docs/BranchWeightMetadata.html
+// - https://docs.rs/cranelift-frontend/latest/cranelift_frontend/struct.FunctionBuilder.html#method.set_cold_block
+
+/// The target of a branch is cold if its probability is less than this value.
+pub const COLD_PROBABILITY: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BranchWeight {
+    /// The execution counts of the first target (i.e. the condition is true)
+    /// and the second target.
+    Counts { taken: u64, not_taken: u64 },
+
+    /// The probability of taking the first target, from 0.0 to 1.0.
+    Probability(f64),
+}
+
+impl BranchWeight {
+    /// The probability of taking the first target, it is `None` if the
+    /// branch is never executed.
+    pub fn get_taken_probability(&self) -> Option<f64> {
+        match *self {
+            BranchWeight::Counts { taken, not_taken } => {
+                let total = taken as f64 + not_taken as f64;
+                (total > 0.0).then(|| taken as f64 / total)
+            }
+            BranchWeight::Probability(probability) => Some(probability),
+        }
+    }
+
+    /// Whether the first target is cold.
+    pub fn is_taken_cold(&self) -> bool {
+        self.get_taken_probability()
+            .is_some_and(|probability| probability < COLD_PROBABILITY)
+    }
+
+    /// Whether the second target is cold.
+    pub fn is_not_taken_cold(&self) -> bool {
+        self.get_taken_probability()
+            .is_some_and(|probability| 1.0 - probability < COLD_PROBABILITY)
+    }
+}
+
+/// Branch to `block_then` if the `condition` is non-zero, otherwise `block_else`,
+/// the cold target (by the weight) is marked as a cold block.
+pub fn emit_brif_with_weight(
+    function_builder: &mut FunctionBuilder,
+    condition: Value,
+    block_then: Block,
+    args_then: &[Value],
+    block_else: Block,
+    args_else: &[Value],
+    weight: BranchWeight,
+) {
+    if weight.is_taken_cold() {
+        function_builder.set_cold_block(block_then);
+    }
+    if weight.is_not_taken_cold() {
+        function_builder.set_cold_block(block_else);
+    }
+
+    function_builder
+        .ins()
+        .brif(condition, block_then, args_then, block_else, args_else);
+}
+
+/// Jump to the block, it is marked as a cold block if the execution count is 0.
+pub fn emit_jump_with_count(
+    function_builder: &mut FunctionBuilder,
+    block: Block,
+    args: &[Value],
+    count: u64,
+) {
+    if count == 0 {
+        function_builder.set_cold_block(block);
+    }
+    function_builder.ins().jump(block, args);
+}
+
+/// The execution counts of the branches, the key is the name of the function
+/// and the index of the branch, and the value is the counts of the two targets.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BranchProfile {
+    pub counts: HashMap<(String, u32), (u64, u64)>,
+}
+
+impl BranchProfile {
+    /// Parse the text of the branch profile, the counts of the same branch
+    /// are added up.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut counts = HashMap::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            match (
+                fields.next().and_then(|field| field.parse::<u64>().ok()),
+                fields.next().and_then(|field| field.parse::<u64>().ok()),
+                fields
+                    .next()
+                    .and_then(|field| field.rsplit_once('#'))
+                    .map(|(name, index)| (name, index.parse::<u32>())),
+                fields.next(),
+            ) {
+                (Some(taken), Some(not_taken), Some((name, Ok(branch_index))), None)
+                    if !name.is_empty() =>
+                {
+                    let total: &mut (u64, u64) = counts
+                        .entry((name.to_owned(), branch_index))
+                        .or_default();
+                    total.0 = total.0.saturating_add(taken);
+                    total.1 = total.1.saturating_add(not_taken);
+                }
+                _ => {
+                    return Err(format!(
+                        "invalid line {} of the branch profile: \"{}\", expect \"taken not_taken function#index\"",
+                        index + 1,
+                        line
+                    ))
+                }
+            }
+        }
+
+        Ok(Self { counts })
+    }
+
+    /// The weight of the branch, it is `None` if the branch is not in the profile.
+    pub fn get_weight(&self, function_name: &str, index: u32) -> Option<BranchWeight> {
+        self.counts
+            .get(&(function_name.to_owned(), index))
+            .map(|(taken, not_taken)| BranchWeight::Counts {
+                taken: *taken,
+                not_taken: *not_taken,
+            })
+    }
+}
+
+/// Read the branch profile file.
+pub fn read_branch_profile<P: AsRef<Path>>(file_path: P) -> std::io::Result<BranchProfile> {
+    let text = std::fs::read_to_string(file_path)?;
+    BranchProfile::parse(&text)
+        .map_err(|message| std::io::Error::new(std::io::ErrorKind::InvalidData, message))
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Lay out the cold targets of the `if` and `when` of the assembly by
+    /// the counts of the profile.
+    pub fn set_branch_profile(&mut self, profile: BranchProfile) {
+        self.branch_profile = Some(profile);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::InstructionKind,
+        branch_weight::{BranchProfile, BranchWeight},
+        code_generator::Generator,
+        formatter::format_module,
+        lowering::assemble_module,
+        parser::parse_module,
+    };
+
+    const SOURCE: &str = r#"
+    (module $branches
+        (function $abs export (param $x i32) (result i32) (local $y i32)
+            (code
+                (local_store $y (local_load $x))
+                (when (lt_i32_s (local_load $x) (imm_i32 0))
+                    (local_store $y (sub_i32 (imm_i32 0) (local_load $x))))
+                (if (result i32) (weight 1 999) (eqz_i32 (local_load $y))
+                    (imm_i32 100)
+                    (local_load $y)))))
+    "#;
+
+    /// Assemble the module by the profile, returns the number of the cold blocks.
+    fn assemble_and_check(profile: Option<&str>) -> usize {
+        let module = parse_module(SOURCE).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        if let Some(text) = profile {
+            generator.set_branch_profile(BranchProfile::parse(text).unwrap());
+        }
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let clif = generator.dump_clif();
+        generator.module.finalize_definitions().unwrap();
+
+        let func_abs = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(
+                generator
+                    .module
+                    .get_finalized_function(assembled_module.get_function_id("abs").unwrap()),
+            )
+        };
+        assert_eq!([func_abs(-5), func_abs(0), func_abs(7)], [5, 100, 7]);
+
+        clif.lines().filter(|line| line.ends_with(" cold:")).count()
+    }
+
+    #[test]
+    fn test_branch_weight() {
+        let weight = BranchWeight::Counts {
+            taken: 1,
+            not_taken: 999,
+        };
+        assert_eq!(weight.get_taken_probability(), Some(0.001));
+        assert!(weight.is_taken_cold() && !weight.is_not_taken_cold());
+        assert!(BranchWeight::Probability(0.995).is_not_taken_cold());
+        assert_eq!(
+            BranchWeight::Counts {
+                taken: 0,
+                not_taken: 0
+            }
+            .get_taken_probability(),
+            None
+        );
+
+        // the annotation
+        let module = parse_module(SOURCE).unwrap();
+        let InstructionKind::If { weight, .. } = &module.functions[0].body[2].kind else {
+            panic!()
+        };
+        assert_eq!(
+            weight,
+            &Some(BranchWeight::Counts {
+                taken: 1,
+                not_taken: 999
+            })
+        );
+        assert!(format_module(&module).contains("(if (result i32) (weight 1 999)"));
+
+        // the consequent of `if` is cold
+        assert_eq!(assemble_and_check(None), 1);
+
+        // the body of `when` is cold, and the annotation of `if` takes precedence
+        assert_eq!(
+            assemble_and_check(Some(
+                "# taken not_taken branch\n0 1000 abs#0\n1000 0 abs#1\n"
+            )),
+            2
+        );
+
+        // the path which skips the body of `when` is cold
+        assert_eq!(assemble_and_check(Some("600 0 abs#0\n400 0 abs#0\n")), 2);
+
+        // the branch which is not executed
+        assert_eq!(assemble_and_check(Some("0 0 abs#0\n")), 1);
+
+        // errors
+        assert_eq!(
+            BranchProfile::parse("1 2 abs\n").unwrap_err(),
+            "invalid line 1 of the branch profile: \"1 2 abs\", expect \"taken not_taken function#index\""
+        );
+        assert_eq!(
+            parse_module("(module $m (function $f (code (when (weight 1.5) (imm_i32 1) (nop)))))")
+                .unwrap_err()
+                .message,
+            "the probability \"1.5\" of the branch is out of range of [0, 1]"
+        );
+    }
+}
@@ -4,17 +4,58 @@
 // the Mozilla Public License version 2.0 and additional exceptions,
 // more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
 
+use std::collections::HashMap;
+
 use cranelift_codegen::{
-    isa,
+    control::ControlPlane,
+    ir::{ExternalName, Function, Signature, UserExternalNameRef, UserFuncName},
+    isa::{self, unwind::UnwindInfo, TargetIsa},
     settings::{self, Configurable},
-    Context,
+    CompiledCode, Context, FinalizedMachReloc, FinalizedRelocTarget,
 };
 use cranelift_frontend::FunctionBuilderContext;
+#[cfg(feature = "jit")]
 use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::{
-    default_libcall_names, DataDescription, DataId, Linkage, Module, ModuleError,
+    default_libcall_names, DataDescription, DataId, FuncId, FuncOrDataId, Linkage, Module,
+    ModuleError,
+};
+use cranelift_object::{ObjectBuilder, ObjectModule, ObjectProduct};
+
+use crate::{
+    address_significance::write_address_significance_table_to_object,
+    allocator::Allocator,
+    branch_weight::BranchProfile,
+    compilation_cache::CompilationCache,
+    coverage::Coverage,
+    data_bytes::ToDataBytes,
+    debug_info::DebugInfo,
+    disassembly::Listing,
+    elf_note::{write_elf_notes_to_object, ElfNote},
+    exception::ExceptionSymbols,
+    function_attribute::FunctionAttributes,
+    function_order::FunctionProfile,
+    function_pass::FunctionPass,
+    inliner::InlineAttribute,
+    instrumentation::InstrumentationHooks,
+    multiversion::CpuFeature,
+    null_check::{NullCheckMode, NullCheckSites},
+    object_post_processing::ObjectPostProcessor,
+    patchable_entry::write_patchable_entries_to_object,
+    process_exit::ExitMode,
+    producer::{get_producer, write_comment_to_object},
+    safepoint::SafepointPollSymbols,
+    sandbox::SandboxMode,
+    size_budget::FunctionSize,
+    source_location::{FunctionSourceMap, LineMapping, SourceMap},
+    stack_map::FunctionStackMap,
+    unwind_info::UnwindTable,
+    validation::SymbolReferences,
+    visibility::write_protected_visibility_to_object,
 };
-use cranelift_object::{ObjectBuilder, ObjectModule};
+
+#[cfg(feature = "jit")]
+use crate::source_location::SourceLocation;
 
 // Documents of the Cranelift
 //
@@ -49,8 +90,184 @@ where
     /// A description of a data object.
     #[allow(dead_code)]
     pub data_description: DataDescription,
+
+    /// The source files and the "machine code -> source location" mappings
+    /// of the functions.
+    pub source_map: SourceMap,
+
+    /// The debug information (DWARF) of the module, it is `None` by default,
+    /// call `enable_debug_info()` to enable it.
+    pub debug_info: Option<DebugInfo>,
+
+    /// The unwind information (the FDEs of `.eh_frame`) of the functions.
+    pub unwind_table: UnwindTable,
+
+    /// The disassembly listing of the functions, it is `None` by default,
+    /// call `enable_listing()` to enable it.
+    pub listing: Option<Listing>,
+
+    /// The CLIF text of the defined functions (before compilation),
+    /// in the order of definition. See `dump_clif()`.
+    pub clif_functions: Vec<(FuncId, String)>,
+
+    /// The content of the defined data objects, in the order of definition,
+    /// it is used for generating the intermediate file (.ancir).
+    pub data_definitions: Vec<(DataId, DataDefinition)>,
+
+    /// Generate byte-identical objects for the same input, it is `false` by default,
+    /// call `enable_reproducible_build()` to enable it.
+    pub reproducible: bool,
+
+    /// The incremental compilation cache, it is `None` by default,
+    /// call `enable_compilation_cache()` to enable it.
+    pub compilation_cache: Option<CompilationCache>,
+
+    /// The inline attributes of the declared functions, see `inline_functions()`.
+    pub inline_attributes: HashMap<FuncId, InlineAttribute>,
+
+    /// The sizes of the compiled functions, in the order of definition.
+    /// See `check_size_budget()`.
+    pub function_sizes: Vec<FunctionSize>,
+
+    /// The maximum size of the stack frames, it is `None` (unlimited) by default,
+    /// call `set_frame_size_limit()` to set it.
+    pub frame_size_limit: Option<u32>,
+
+    /// The NOPs which are inserted at the entry of each function, it is empty
+    /// by default, call `enable_patchable_function_entry()` to enable it.
+    pub patchable_entry: Vec<u8>,
+
+    /// The hook functions of the function entry/exit instrumentation, it is `None`
+    /// by default, call `enable_function_instrumentation()` to enable it.
+    pub instrumentation: Option<InstrumentationHooks>,
+
+    /// The `mcount()` function of the gprof profiling, it is `None` by default,
+    /// call `enable_profiling()` to enable it.
+    pub profiling: Option<FuncId>,
+
+    /// The basic-block coverage instrumentation, it is `None` by default,
+    /// call `enable_coverage()` to enable it.
+    pub coverage: Option<Coverage>,
+
+    /// The GC stack maps of the compiled functions which have GC references,
+    /// in the order of definition. See `get_stack_map()`.
+    pub stack_maps: Vec<FunctionStackMap>,
+
+    /// The poll flag and the poll handler of the safepoint polling, it is `None`
+    /// by default, call `enable_safepoint_polls()` to enable it.
+    pub safepoint_poll: Option<SafepointPollSymbols>,
+
+    /// The allocator of `emit_alloc()` and `emit_free()`, see `set_allocator()`.
+    pub allocator: Allocator,
+
+    /// The symbols of the exception handling, it is `None` by default,
+    /// call `enable_exceptions()` to enable it.
+    pub exceptions: Option<ExceptionSymbols>,
+
+    /// The imported functions which are called directly (i.e. PC-relative)
+    /// instead of through the GOT, see `import_function_colocated()`. It is `None` for
+    /// the JIT module, since the host functions may be out of the range of
+    /// the PC-relative calls.
+    pub colocated_imports: Option<Vec<FuncId>>,
+
+    /// Mark the exported functions as protected visibility, it is `false` by
+    /// default, call `enable_protected_exports()` to enable it.
+    pub protected_exports: bool,
+
+    /// Append the address-significance table (`.llvm_addrsig`) to the object
+    /// file, it is `false` by default, call `enable_address_significance_table()`
+    /// to enable it.
+    pub address_significance_table: bool,
+
+    /// The ELF notes of the object file, see `add_elf_note()`.
+    pub elf_notes: Vec<ElfNote>,
+
+    /// The null checks of `emit_checked_load()` and `emit_checked_store()`, it is
+    /// `Explicit` by default, call `enable_guard_page_null_checks()` to elide them.
+    pub null_check_mode: NullCheckMode,
+
+    /// The null-pointer traps of the compiled functions which have them,
+    /// in the order of definition. See `get_null_check_sites()`.
+    pub null_check_sites: Vec<NullCheckSites>,
+
+    /// The passes which transform the functions before compilation,
+    /// see `add_function_pass()`.
+    pub function_passes: Vec<Box<dyn FunctionPass + Send>>,
+
+    /// The symbols which are referenced by the defined functions and data
+    /// objects, in the order of definition. See `validate()`.
+    pub symbol_references: Vec<SymbolReferences>,
+
+    /// The implementation of `exit()` and `abort()`, see `set_exit_mode()`.
+    pub exit_mode: ExitMode,
+
+    /// The attributes of the declared functions, see `set_function_attributes()`.
+    pub function_attributes: HashMap<FuncId, FunctionAttributes>,
+
+    /// The post-processors of the object, see `add_object_post_processor()`.
+    pub object_post_processors: Vec<ObjectPostProcessor>,
+
+    /// The memory accesses of the lowered functions are confined to the
+    /// linear memory, it is `None` by default, call `enable_sandbox()`.
+    pub sandbox_mode: Option<SandboxMode>,
+
+    /// Reject the absolute addresses in the compiled functions, it is `false`
+    /// by default, call `enable_position_independence_check()` to enable it.
+    pub position_independence_check: bool,
+
+    /// The CPU feature sets of the multi-versioned functions, see `set_function_versions()`.
+    pub function_versions: HashMap<FuncId, Vec<Vec<CpuFeature>>>,
+
+    /// The minimum size of the zero data which is moved to `.bss`, it is `None`
+    /// by default, call `enable_zero_data_in_bss()` to enable it.
+    pub zero_data_min_size: Option<usize>,
+
+    /// The call counts of the functions which order the definitions of the
+    /// assembly, it is `None` by default, call `set_function_profile()`.
+    pub function_profile: Option<FunctionProfile>,
+
+    /// The execution counts of the branches which decide the cold targets of
+    /// `if` and `when`, it is `None` by default, call `set_branch_profile()`.
+    pub branch_profile: Option<BranchProfile>,
+
+    /// The removed duplicated data and the data which the references to them
+    /// are redirected to, see `deduplicate_data()`.
+    pub data_redirects: HashMap<DataId, DataId>,
+
+    /// The requested alignments of the functions and the data, see `alignment.rs`.
+    pub function_alignments: HashMap<FuncId, u64>,
+    pub data_alignments: HashMap<DataId, u64>,
 }
 
+/// The options of the object module, see `Generator::new_with_options()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectOptions {
+    /// Generate the position-independent code, see `Generator::new_static()`
+    /// for the position-dependent code.
+    pub is_pic: bool,
+
+    /// Place each function in its own section (i.e. `.text.<name>`, the same as
+    /// `gcc -ffunction-sections`), so the linker can discard (`--gc-sections`) or
+    /// fold (`--icf`) the functions individually.
+    pub function_sections: bool,
+}
+
+impl Default for ObjectOptions {
+    fn default() -> Self {
+        Self {
+            is_pic: true,
+            function_sections: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataDefinition {
+    Initialized { data: Vec<u8>, align: u64 },
+    Uninitialized { size: usize, align: u64 },
+}
+
+#[cfg(feature = "jit")]
 impl Generator<JITModule> {
     // Documents of JITModule
     //
@@ -63,6 +280,18 @@ impl Generator<JITModule> {
     // - https://github.com/bytecodealliance/cranelift-jit-demo/blob/main/src/jit.rs
     #[allow(dead_code)]
     pub fn new(symbols: Vec<(String, *const u8)>) -> Self {
+        Self::new_with_hotswap(symbols, false)
+    }
+
+    /// Create the generator which allows redefining the finalized functions,
+    /// i.e. `module.prepare_for_function_redefine()`, the functions are called
+    /// through the GOT and PLT entries, so the callers (including the finalized
+    /// ones) call the new definitions, e.g. the REPL.
+    pub fn new_hotswap(symbols: Vec<(String, *const u8)>) -> Self {
+        Self::new_with_hotswap(symbols, true)
+    }
+
+    fn new_with_hotswap(symbols: Vec<(String, *const u8)>, hotswap: bool) -> Self {
         // the building flow:
         //
         // flag builder -> isa builder -> jit builder -> jit module
@@ -150,18 +379,31 @@ impl Generator<JITModule> {
         // to add single symbol:
         // `jit_builder.symbol(name:String, ptr:*const u8)`
         jit_builder.symbols(symbols);
+        jit_builder.hotswap(hotswap);
 
         let module = JITModule::new(jit_builder);
-        let context = module.make_context();
-        let function_builder_context = FunctionBuilderContext::new();
-        let data_description = DataDescription::new();
+        Self::with_module(module)
+    }
 
-        Self {
-            module,
-            context,
-            function_builder_context,
-            data_description,
-        }
+    /// Find the function and the source location of the given address
+    /// of the machine code, e.g. the return addresses of a stack trace.
+    ///
+    /// Note that it is only available after 'module.finalize_definitions()'.
+    pub fn lookup_source_location(&self, address: usize) -> Option<(FuncId, SourceLocation)> {
+        self.source_map.get_functions().iter().find_map(|function| {
+            let start = self.module.get_finalized_function(function.func_id) as usize;
+            let end = start + function.code_size as usize;
+
+            if (start..end).contains(&address) {
+                let location = self
+                    .source_map
+                    .lookup(function.func_id, (address - start) as u32)
+                    .copied()?;
+                Some((function.func_id, location))
+            } else {
+                None
+            }
+        })
     }
 }
 
@@ -176,9 +418,37 @@ impl Generator<ObjectModule> {
     // https://github.com/bytecodealliance/wasmtime/blob/main/cranelift/object/tests/basic.rs
     #[allow(dead_code)]
     pub fn new(module_name: &str, opt_platform: Option<&str>) -> Self {
+        Self::new_with_options(module_name, opt_platform, &ObjectOptions::default())
+    }
+
+    /// Create the generator for the static (position-dependent) executables,
+    /// the PIC is disabled, i.e. the imported functions and data are referenced
+    /// by the absolute relocations (e.g. `R_X86_64_64`) rather than
+    /// the GOT entries, and no PLT entry is required.
+    ///
+    /// The object file should be linked with `LinkerMode::NoPie`
+    /// or `LinkerMode::Static`.
+    pub fn new_static(module_name: &str, opt_platform: Option<&str>) -> Self {
+        Self::new_with_options(
+            module_name,
+            opt_platform,
+            &ObjectOptions {
+                is_pic: false,
+                ..ObjectOptions::default()
+            },
+        )
+    }
+
+    pub fn new_with_options(
+        module_name: &str,
+        opt_platform: Option<&str>,
+        options: &ObjectOptions,
+    ) -> Self {
         let mut flag_builder = settings::builder();
         flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        flag_builder.enable("is_pic").unwrap();
+        flag_builder
+            .set("is_pic", if options.is_pic { "true" } else { "false" })
+            .unwrap();
         flag_builder.set("opt_level", "none").unwrap();
         flag_builder.set("preserve_frame_pointers", "true").unwrap();
         flag_builder.set("tls_model", "elf_gd").unwrap();
@@ -196,20 +466,136 @@ impl Generator<ObjectModule> {
             .finish(settings::Flags::new(flag_builder))
             .unwrap();
 
-        let object_builder = ObjectBuilder::new(isa, module_name, default_libcall_names()).unwrap();
+        let mut object_builder =
+            ObjectBuilder::new(isa, module_name, default_libcall_names()).unwrap();
+        object_builder.per_function_section(options.function_sections);
 
         let module = ObjectModule::new(object_builder);
-        let context = module.make_context();
-        let function_builder_context = FunctionBuilderContext::new();
-        let data_description = DataDescription::new();
 
         Self {
-            module,
-            context,
-            function_builder_context,
-            data_description,
+            colocated_imports: Some(vec![]),
+            ..Self::with_module(module)
+        }
+    }
+
+    /// Enable the reproducible build, i.e. two builds of the same input
+    /// generate byte-identical object files.
+    ///
+    /// The object file of `ObjectModule` contains no timestamp, and the symbols and
+    /// sections are added in the order of declaration/definition (rather than
+    /// the order of any hash map), so the only host-dependent content is the paths
+    /// in the debug information, they are made relative to the compilation directory
+    /// in this mode.
+    pub fn enable_reproducible_build(&mut self) {
+        self.reproducible = true;
+    }
+
+    /// Finish the module and return the object product.
+    ///
+    /// The `.eh_frame`, `.comment` sections and the ELF notes are appended to
+    /// the object, and the DWARF sections are also appended if the debug
+    /// information is enabled.
+    ///
+    /// The post-processors (see `add_object_post_processor()`) are run after
+    /// them.
+    ///
+    /// It fails if the coverage data can not be defined (e.g. its name is
+    /// declared by the user), or the unwind or the debug information can not
+    /// be written.
+    ///
+    /// Note that the emitted binary should be patched by `link_address_significance_table()`
+    /// if the address-significance table is enabled.
+    pub fn finish(mut self) -> Result<ObjectProduct, ModuleError> {
+        self.define_coverage_data()?;
+
+        let pointer_bytes = self.module.isa().pointer_bytes() as usize;
+        let producer = get_producer(self.module.isa());
+        let mut object_product = self.module.finish();
+
+        self.unwind_table
+            .write_to_object(&mut object_product)
+            .map_err(|error| ModuleError::Backend(error.into()))?;
+        if !self.patchable_entry.is_empty() {
+            let func_ids = self
+                .function_sizes
+                .iter()
+                .map(|item| item.func_id)
+                .collect::<Vec<_>>();
+            write_patchable_entries_to_object(&mut object_product, &func_ids, pointer_bytes);
+        }
+
+        if self.protected_exports {
+            write_protected_visibility_to_object(&mut object_product);
+        }
+
+        write_elf_notes_to_object(&mut object_product, &self.elf_notes);
+        write_comment_to_object(&mut object_product, &producer);
+
+        if let Some(debug_info) = &mut self.debug_info {
+            if self.reproducible {
+                let comp_dir = debug_info.comp_dir.clone();
+                debug_info.add_path_prefix_map(&comp_dir, ".");
+            }
+
+            debug_info
+                .write_to_object(&mut object_product, &self.source_map)
+                .map_err(|error| ModuleError::Backend(error.into()))?;
+        }
+
+        for post_processor in std::mem::take(&mut self.object_post_processors) {
+            post_processor(&mut object_product);
+        }
+
+        // the table refers to the symbols of the final object file, so it
+        // should be the last one.
+        if self.address_significance_table {
+            write_address_significance_table_to_object(&mut object_product);
+        }
+
+        Ok(object_product)
+    }
+}
+
+/// Generate the CLIF text of the function.
+///
+/// The default writer of Cranelift prints the external names of the global values
+/// as "userextnameN" (i.e. the index of `user_named_funcs`), which can not be
+/// parsed back without the name table, so they are resolved to the form "uX:Y" here.
+pub fn function_to_clif(func: &Function) -> String {
+    const PREFIX: &str = "userextname";
+
+    let text = func.display().to_string();
+    let user_named_funcs = func.params.user_named_funcs();
+
+    let mut output = String::with_capacity(text.len());
+    let mut remain = text.as_str();
+
+    while let Some(pos) = remain.find(PREFIX) {
+        output.push_str(&remain[..pos]);
+        remain = &remain[pos + PREFIX.len()..];
+
+        let digits_len = remain
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(remain.len());
+
+        let opt_name = remain[..digits_len]
+            .parse::<u32>()
+            .ok()
+            .and_then(|idx| user_named_funcs.get(UserExternalNameRef::from_u32(idx)));
+
+        match opt_name {
+            Some(name) => output.push_str(&format!("u{}:{}", name.namespace, name.index)),
+            None => {
+                output.push_str(PREFIX);
+                output.push_str(&remain[..digits_len]);
+            }
         }
+
+        remain = &remain[digits_len..];
     }
+
+    output.push_str(remain);
+    output
 }
 
 // obtaining the pointer of function and data
@@ -241,6 +627,349 @@ impl<T> Generator<T>
 where
     T: Module,
 {
+    /// Create the generator of the module with the default options, the
+    /// constructors of the specific modules override the fields which
+    /// differ, e.g. `colocated_imports`.
+    fn with_module(module: T) -> Self {
+        let context = module.make_context();
+        let function_builder_context = FunctionBuilderContext::new();
+        let data_description = DataDescription::new();
+        let unwind_table = UnwindTable::new(module.isa());
+
+        Self {
+            module,
+            context,
+            function_builder_context,
+            data_description,
+            source_map: SourceMap::new(),
+            debug_info: None,
+            unwind_table,
+            listing: None,
+            clif_functions: vec![],
+            data_definitions: vec![],
+            reproducible: false,
+            compilation_cache: None,
+            inline_attributes: HashMap::new(),
+            function_sizes: vec![],
+            frame_size_limit: None,
+            patchable_entry: vec![],
+            instrumentation: None,
+            profiling: None,
+            coverage: None,
+            stack_maps: vec![],
+            safepoint_poll: None,
+            allocator: Allocator::default(),
+            exceptions: None,
+            elf_notes: vec![],
+            colocated_imports: None,
+            protected_exports: false,
+            address_significance_table: false,
+            null_check_mode: NullCheckMode::Explicit,
+            null_check_sites: vec![],
+            function_passes: vec![],
+            symbol_references: vec![],
+            exit_mode: ExitMode::default(),
+            function_attributes: HashMap::new(),
+            object_post_processors: vec![],
+            sandbox_mode: None,
+            position_independence_check: false,
+            function_versions: HashMap::new(),
+            zero_data_min_size: None,
+            function_profile: None,
+            branch_profile: None,
+            data_redirects: HashMap::new(),
+            function_alignments: HashMap::new(),
+            data_alignments: HashMap::new(),
+        }
+    }
+
+    /// Enable the debug information generation.
+    ///
+    /// - `name`: the name of the compile unit, it is usually the path of the main source file.
+    /// - `comp_dir`: the current working directory of compilation.
+    pub fn enable_debug_info(&mut self, name: &str, comp_dir: &str) {
+        self.debug_info = Some(DebugInfo::new(self.module.isa(), name, comp_dir));
+    }
+
+    /// Enable the incremental compilation cache, the functions which are unchanged
+    /// (compared to the previous builds which use the same cache) are not recompiled.
+    pub fn enable_compilation_cache(&mut self, compilation_cache: CompilationCache) {
+        self.compilation_cache = Some(compilation_cache);
+    }
+
+    /// Enable the disassembly listing, see `Listing::to_text()`.
+    pub fn enable_listing(&mut self) {
+        self.listing = Some(Listing::new());
+    }
+
+    /// Generate the (machine/native) code of the function, it is equivalent to:
+    ///
+    /// ```text
+    /// generator.context.func = func;
+    /// generator.module.define_function(func_id, &mut generator.context)?;
+    /// generator.module.clear_context(&mut generator.context);
+    /// ```
+    ///
+    /// and the mapping of "machine code -> source location" is collected
+    /// into the `source_map`, the unwind information is collected into
+    /// the `unwind_table`, the listing is collected into the `listing`
+    /// if it is enabled.
+    pub fn define_function(&mut self, func_id: FuncId, func: Function) -> Result<(), ModuleError> {
+        let mut func = func;
+        self.run_function_passes(func_id, &mut func);
+        self.instrument_function(func_id, &mut func);
+        self.colocate_imported_functions(&mut func);
+        self.redirect_deduplicated_data(&mut func);
+
+        if let Some(versions) = self.function_versions.get(&func_id).cloned() {
+            return self.define_function_versions(func_id, func, &versions);
+        }
+        self.compile_function(func_id, func, None)
+    }
+
+    /// Compile the function by the given ISA (or the ISA of the module if it
+    /// is `None`) and append it to the module, the function passes are not run.
+    pub(crate) fn compile_function(
+        &mut self,
+        func_id: FuncId,
+        func: Function,
+        opt_isa: Option<&dyn TargetIsa>,
+    ) -> Result<(), ModuleError> {
+        // keep the IR before compilation for the listing and the CLIF dump.
+        let func_source = func.clone();
+
+        self.context.func = func;
+        self.context.set_disasm(self.listing.is_some());
+
+        // it is equivalent to `Module::define_function()` except that the compiled
+        // artifacts are reused if they are cached, and the patchable entry is inserted.
+        let isa = opt_isa.unwrap_or_else(|| self.module.isa());
+        let compile_result = match &mut self.compilation_cache {
+            Some(compilation_cache) => self
+                .context
+                .compile_with_cache(isa, compilation_cache, &mut ControlPlane::default())
+                .map(|(_, hit)| compilation_cache.record(hit)),
+            None => self
+                .context
+                .compile(isa, &mut ControlPlane::default())
+                .map(|_| ()),
+        }
+        .map_err(|e| ModuleError::Compilation(e.inner));
+
+        let result = compile_result.and_then(|_| {
+            let compiled_code = self.context.take_compiled_code().unwrap();
+            self.define_compiled_function(func_id, &func_source, &compiled_code)
+        });
+
+        self.module.clear_context(&mut self.context);
+        result
+    }
+
+    /// Append the compiled code to the module, and collect the information
+    /// of the function (see `record_compiled_function()`).
+    pub(crate) fn define_compiled_function(
+        &mut self,
+        func_id: FuncId,
+        func_source: &Function,
+        compiled_code: &CompiledCode,
+    ) -> Result<(), ModuleError> {
+        self.check_frame_size_limit(func_id, compiled_code)?;
+        self.check_position_independence(func_id, func_source, compiled_code.buffer.relocs())?;
+        let align = self.get_function_alignment(func_id, compiled_code.buffer.alignment as u64);
+
+        if self.patchable_entry.is_empty() {
+            self.module.define_function_bytes(
+                func_id,
+                func_source,
+                align,
+                compiled_code.code_buffer(),
+                compiled_code.buffer.relocs(),
+            )?;
+        } else {
+            // the NOPs are placed before the first instruction (i.e. the prologue),
+            // so the offsets of the relocations are shifted.
+            let entry_offset = self.patchable_entry.len() as u32;
+            let relocs = compiled_code
+                .buffer
+                .relocs()
+                .iter()
+                .map(|reloc| FinalizedMachReloc {
+                    offset: reloc.offset + entry_offset,
+                    kind: reloc.kind,
+                    target: match &reloc.target {
+                        FinalizedRelocTarget::Func(offset) => {
+                            FinalizedRelocTarget::Func(offset + entry_offset)
+                        }
+                        target => target.clone(),
+                    },
+                    addend: reloc.addend,
+                })
+                .collect::<Vec<_>>();
+
+            self.module.define_function_bytes(
+                func_id,
+                func_source,
+                align,
+                &[self.patchable_entry.as_slice(), compiled_code.code_buffer()].concat(),
+                &relocs,
+            )?;
+        }
+
+        self.record_compiled_function(func_id, func_source, compiled_code);
+        Ok(())
+    }
+
+    /// Collect the source map, debug information, unwind information, listing
+    /// and CLIF text of a compiled function.
+    ///
+    /// - `func_source`: the IR before compilation.
+    pub(crate) fn record_compiled_function(
+        &mut self,
+        func_id: FuncId,
+        func_source: &Function,
+        compiled_code: &CompiledCode,
+    ) {
+        let entry_offset = self.patchable_entry.len() as u32;
+
+        // note that the `SourceLoc` of `MachSrcLoc` has already been expanded
+        // with the base source location of the function.
+        let line_mappings = compiled_code
+            .buffer
+            .get_srclocs_sorted()
+            .iter()
+            .filter(|item| !item.loc.is_default())
+            .map(|item| LineMapping {
+                start: item.start + entry_offset,
+                end: item.end + entry_offset,
+                source_loc: item.loc,
+            })
+            .collect::<Vec<_>>();
+
+        self.source_map.set_function(FunctionSourceMap {
+            func_id,
+            code_size: compiled_code.buffer.total_size() + entry_offset,
+            line_mappings,
+        });
+
+        if let Some(debug_info) = &mut self.debug_info {
+            debug_info.set_function_frame(func_id, self.module.isa(), compiled_code, entry_offset);
+        }
+
+        if let Ok(Some(UnwindInfo::SystemV(unwind_info))) =
+            compiled_code.create_unwind_info(self.module.isa())
+        {
+            self.unwind_table
+                .add_function(func_id, unwind_info, entry_offset);
+        }
+
+        if let Some(listing) = &mut self.listing {
+            let name = self
+                .module
+                .declarations()
+                .get_function_decl(func_id)
+                .linkage_name(func_id)
+                .into_owned();
+            let machine_code =
+                [self.patchable_entry.as_slice(), compiled_code.code_buffer()].concat();
+            listing.add_function(func_id, &name, func_source, compiled_code, machine_code);
+        }
+
+        let mut function_size = FunctionSize::new(func_id, func_source, compiled_code);
+        function_size.code_size += entry_offset;
+        match self
+            .function_sizes
+            .iter_mut()
+            .find(|item| item.func_id == func_id)
+        {
+            Some(item) => *item = function_size,
+            None => self.function_sizes.push(function_size),
+        }
+
+        self.stack_maps.retain(|item| item.func_id != func_id);
+        if let Some(stack_map) = FunctionStackMap::new(func_id, compiled_code, entry_offset) {
+            self.stack_maps.push(stack_map);
+        }
+
+        self.null_check_sites.retain(|item| item.func_id != func_id);
+        if let Some(sites) = NullCheckSites::new(func_id, compiled_code, entry_offset) {
+            self.null_check_sites.push(sites);
+        }
+
+        self.set_symbol_references(SymbolReferences::from_function(func_id, func_source));
+
+        let clif_text = function_to_clif(func_source);
+        match self
+            .clif_functions
+            .iter_mut()
+            .find(|(id, _)| *id == func_id)
+        {
+            Some(item) => item.1 = clif_text,
+            None => self.clif_functions.push((func_id, clif_text)),
+        }
+    }
+
+    /// Generate the textual IR (CLIF) of all defined functions of the module.
+    ///
+    /// The function names are in the form of "u0:N", where "N" is the `FuncId`,
+    /// and the external functions and data are referenced in the same way
+    /// (i.e. "u0:N" for functions and "u1:N" for data), so the text can be
+    /// imported by `define_function_from_clif()`.
+    pub fn dump_clif(&self) -> String {
+        self.clif_functions
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Parse the CLIF text and define the function(s), the functions should be
+    /// declared first.
+    ///
+    /// The function is matched by its name:
+    ///
+    /// - "u0:N": the function with `FuncId` N.
+    /// - "%name": the function which is declared with the name "name".
+    ///
+    /// ref:
+    /// - https://github.com/bytecodealliance/wasmtime/blob/main/cranelift/docs/ir.md
+    pub fn define_function_from_clif(&mut self, text: &str) -> Result<Vec<FuncId>, ModuleError> {
+        let functions = cranelift_reader::parse_functions(text)
+            .map_err(|e| ModuleError::Backend(anyhow::anyhow!("Failed to parse CLIF: {}", e)))?;
+
+        let mut func_ids = vec![];
+
+        for function in functions {
+            let func_id = match &function.name {
+                UserFuncName::User(name) if name.namespace == 0 => {
+                    let func_id = FuncId::from_u32(name.index);
+                    if !self
+                        .module
+                        .declarations()
+                        .get_functions()
+                        .any(|(id, _)| id == func_id)
+                    {
+                        return Err(ModuleError::Undeclared(function.name.to_string()));
+                    }
+                    func_id
+                }
+                UserFuncName::Testcase(name) => {
+                    // the `Display` of `TestcaseName` adds the prefix "%"
+                    let name = name.to_string().trim_start_matches('%').to_owned();
+                    match self.module.get_name(&name) {
+                        Some(FuncOrDataId::Func(func_id)) => func_id,
+                        _ => return Err(ModuleError::Undeclared(name)),
+                    }
+                }
+                _ => return Err(ModuleError::Undeclared(function.name.to_string())),
+            };
+
+            self.define_function(func_id, function)?;
+            func_ids.push(func_id);
+        }
+
+        Ok(func_ids)
+    }
+
     // The process reading a data (which is inside .data/.ro_data/.bss):
     // 1. let gv = construct a GlobalValue object
     // 2. let target_address = ins().symbol_value(gv)
@@ -261,21 +990,30 @@ where
             Linkage::Local
         };
 
-        // https://docs.rs/cranelift-module/latest/cranelift_module/struct.DataDescription.html
-        self.data_description.define(data.into_boxed_slice());
-        self.data_description.set_align(align);
-
         let data_id = self
             .module
             .declare_data(name, linkage, writable, thread_local)?;
 
-        self.module.define_data(data_id, &self.data_description)?;
-
-        self.data_description.clear();
+        self.define_data_content(data_id, DataDefinition::Initialized { data, align })?;
 
         Ok(data_id)
     }
 
+    /// Define the initialized data object with the content of the Rust
+    /// value, the bytes are in the endianness of the target, see `data_bytes.rs`.
+    pub fn define_initialized_data_from<V: ToDataBytes + ?Sized>(
+        &mut self,
+        name: &str,
+        value: &V,
+        export: bool,
+        writable: bool,
+        thread_local: bool,
+    ) -> Result<DataId, ModuleError> {
+        let mut data = vec![];
+        value.write_data_bytes(self.module.isa().endianness(), &mut data);
+        self.define_initialized_data(name, data, V::DATA_ALIGN, export, writable, thread_local)
+    }
+
     #[allow(dead_code)]
     pub fn define_uninitialized_data(
         &mut self,
@@ -291,19 +1029,93 @@ where
             Linkage::Local
         };
 
-        self.data_description.define_zeroinit(size);
-        self.data_description.set_align(align);
-
         let data_id = self
             .module
             .declare_data(name, linkage, true, thread_local)?;
-        self.module.define_data(data_id, &self.data_description)?;
 
-        self.data_description.clear();
+        self.define_data_content(data_id, DataDefinition::Uninitialized { size, align })?;
+
+        Ok(data_id)
+    }
+
+    /// Define the data object in the custom section of the object file
+    /// (e.g. ".rodata.table"), the section is ignored by the JIT module.
+    pub fn define_data_in_section(
+        &mut self,
+        name: &str,
+        data_definition: DataDefinition,
+        export: bool,
+        writable: bool,
+        section: &str,
+    ) -> Result<DataId, ModuleError> {
+        let linkage = if export {
+            Linkage::Export
+        } else {
+            Linkage::Local
+        };
+
+        let data_id = self.module.declare_data(name, linkage, writable, false)?;
+
+        // the segment name is only used by Mach-O
+        self.data_description.set_segment_section("", section);
+        self.define_data_content(data_id, data_definition)?;
 
         Ok(data_id)
     }
 
+    /// Define the content of a declared data object.
+    pub fn define_data_content(
+        &mut self,
+        data_id: DataId,
+        data_definition: DataDefinition,
+    ) -> Result<(), ModuleError> {
+        let data_definition = self.align_data_definition(data_id, data_definition)?;
+        let data_definition = self.place_zero_data(data_definition);
+
+        // https://docs.rs/cranelift-module/latest/cranelift_module/struct.DataDescription.html
+        match &data_definition {
+            DataDefinition::Initialized { data, align } => {
+                self.data_description
+                    .define(data.clone().into_boxed_slice());
+                self.data_description.set_align(*align);
+            }
+            DataDefinition::Uninitialized { size, align } => {
+                self.data_description.define_zeroinit(*size);
+                self.data_description.set_align(*align);
+            }
+        }
+
+        let result = self.module.define_data(data_id, &self.data_description);
+        let symbol_references = SymbolReferences::from_data(data_id, &self.data_description);
+        self.data_description.clear();
+        result?;
+
+        self.set_symbol_references(symbol_references);
+        self.data_definitions.push((data_id, data_definition));
+        Ok(())
+    }
+
+    /// Write the address of the data plus the addend at the offset of the
+    /// content, which is defined next by `define_data_content()`, e.g. the
+    /// address of an item of a table.
+    pub fn write_data_address(&mut self, offset: u32, data_id: DataId, addend: i64) {
+        let data_id = self.get_deduplicated_data(data_id);
+        let global_value = self
+            .module
+            .declare_data_in_data(data_id, &mut self.data_description);
+        self.data_description
+            .write_data_addr(offset, global_value, addend);
+    }
+
+    /// Write the address of the function at the offset of the content,
+    /// which is defined next by `define_data_content()`.
+    pub fn write_function_address(&mut self, offset: u32, func_id: FuncId) {
+        let func_ref = self
+            .module
+            .declare_func_in_data(func_id, &mut self.data_description);
+        self.data_description.write_function_addr(offset, func_ref);
+    }
+
     #[allow(dead_code)]
     pub fn import_data(
         &mut self,
@@ -314,18 +1126,73 @@ where
         self.module
             .declare_data(name, Linkage::Import, writable, thread_local)
     }
+
+    /// Import the function which is known to end up in the same final binary
+    /// (e.g. the objects of the runtime library which are linked alongside),
+    /// the calls are compiled to the direct PC-relative calls instead of the
+    /// indirect calls through the GOT (or the PLT).
+    ///
+    /// Note that the function should not be provided by a shared library,
+    /// and it is imported as the normal function by the JIT module.
+    pub fn import_function_colocated(
+        &mut self,
+        name: &str,
+        signature: &Signature,
+    ) -> Result<FuncId, ModuleError> {
+        let func_id = self
+            .module
+            .declare_function(name, Linkage::Import, signature)?;
+        if let Some(colocated_imports) = &mut self.colocated_imports {
+            if !colocated_imports.contains(&func_id) {
+                colocated_imports.push(func_id);
+            }
+        }
+        Ok(func_id)
+    }
+
+    /// Mark the references of the colocated imported functions as colocated,
+    /// since `Module::declare_func_in_func()` marks only the defined functions.
+    pub(crate) fn colocate_imported_functions(&self, func: &mut Function) {
+        let Some(colocated_imports) = &self.colocated_imports else {
+            return;
+        };
+        if colocated_imports.is_empty() {
+            return;
+        }
+
+        // borrow the fields separately, since `func.dfg` borrows the whole function
+        let Function {
+            stencil, params, ..
+        } = func;
+        for ext_func in stencil.dfg.ext_funcs.values_mut() {
+            if let ExternalName::User(name_ref) = ext_func.name {
+                let name = &params.user_named_funcs()[name_ref];
+                // the namespace of the functions is 0, see `Module::declare_func_in_func()`.
+                if name.namespace == 0 && colocated_imports.contains(&FuncId::from_u32(name.index))
+                {
+                    ext_func.colocated = true;
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::process::Command;
+
     use cranelift_codegen::ir::{
         types, AbiParam, Function, InstBuilder, StackSlotData, StackSlotKind, UserFuncName,
     };
     use cranelift_frontend::FunctionBuilder;
     use cranelift_jit::JITModule;
-    use cranelift_module::{Linkage, Module};
+    use cranelift_module::{Linkage, Module, ModuleError};
+    use cranelift_object::ObjectModule;
 
-    use crate::code_generator::Generator;
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerMode, LinkerOptions},
+    };
 
     #[test]
     fn test_code_generator_jit() {
@@ -903,4 +1770,315 @@ mod tests {
         assert_eq!(buf_as_i32x2[0], 53);
         assert_eq!(buf_as_i32x2[1], 59);
     }
+
+    #[test]
+    fn test_code_generator_source_location() {
+        let mut generator = Generator::<JITModule>::new(vec![]);
+
+        let file_id = generator.source_map.add_source_file("main.anca");
+
+        // build function "inc"
+        //
+        // ```rust
+        // fn inc (a:i32) -> i32 {  // line 1
+        //    a+11                  // line 2
+        // }                        // line 3
+        // ```
+
+        let mut func_inc_sig = generator.module.make_signature();
+        func_inc_sig.params.push(AbiParam::new(types::I32));
+        func_inc_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_inc_id = generator
+            .module
+            .declare_function("inc", Linkage::Local, &func_inc_sig)
+            .unwrap();
+
+        let mut func_inc = Function::with_name_signature(
+            UserFuncName::user(0, func_inc_id.as_u32()),
+            func_inc_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_inc, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 2, 4);
+        let value_0 = function_builder.ins().iconst(types::I32, 11);
+        let value_1 = function_builder.block_params(block)[0];
+        let value_2 = function_builder.ins().iadd(value_0, value_1);
+
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 3, 1);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_inc_id, func_inc).unwrap();
+
+        // linking
+        generator.module.finalize_definitions().unwrap();
+
+        let func_inc_ptr = generator.module.get_finalized_function(func_inc_id);
+        let func_inc: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(func_inc_ptr) };
+        assert_eq!(func_inc(13), 24);
+
+        let function_source_map = generator.source_map.get_function(func_inc_id).unwrap();
+        assert!(!function_source_map.line_mappings.is_empty());
+
+        let lines = function_source_map
+            .line_mappings
+            .iter()
+            .map(|mapping| {
+                let address = func_inc_ptr as usize + mapping.start as usize;
+                let (func_id, location) = generator.lookup_source_location(address).unwrap();
+                assert_eq!(func_id, func_inc_id);
+                assert_eq!(location.file_id, file_id);
+                location.line
+            })
+            .collect::<Vec<_>>();
+
+        // note that the instructions may be merged by the optimizer, so
+        // not every line necessarily has its own machine code.
+        assert!(lines.iter().all(|line| *line == 2 || *line == 3));
+
+        // the address outside of functions
+        assert!(generator
+            .lookup_source_location(func_inc_ptr as usize + function_source_map.code_size as usize)
+            .is_none());
+    }
+
+    #[test]
+    fn test_code_generator_clif_dump_and_import() {
+        let mut generator = Generator::<JITModule>::new(vec![]);
+
+        // build function "inc"
+        //
+        // ```rust
+        // fn inc (a:i32) -> i32 {
+        //    a+11
+        // }
+        // ```
+        let mut func_inc_sig = generator.module.make_signature();
+        func_inc_sig.params.push(AbiParam::new(types::I32));
+        func_inc_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_inc_id = generator
+            .module
+            .declare_function("inc", Linkage::Local, &func_inc_sig)
+            .unwrap();
+
+        let mut func_inc = Function::with_name_signature(
+            UserFuncName::user(0, func_inc_id.as_u32()),
+            func_inc_sig.clone(),
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_inc, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.ins().iconst(types::I32, 11);
+        let value_1 = function_builder.block_params(block)[0];
+        let value_2 = function_builder.ins().iadd(value_0, value_1);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_inc_id, func_inc).unwrap();
+
+        let text = generator.dump_clif();
+        assert!(text.starts_with("function u0:0(i32) -> i32"));
+        assert!(text.contains("iadd"));
+
+        // import the CLIF text into another module
+        let mut generator2 = Generator::<JITModule>::new(vec![]);
+        let func_inc_id2 = generator2
+            .module
+            .declare_function("inc", Linkage::Local, &func_inc_sig)
+            .unwrap();
+
+        let func_ids = generator2.define_function_from_clif(&text).unwrap();
+        assert_eq!(func_ids, vec![func_inc_id2]);
+
+        // also match the function by name, and the IR can be hand-patched
+        let func_dec_id = generator2
+            .module
+            .declare_function("dec", Linkage::Local, &func_inc_sig)
+            .unwrap();
+
+        let patched_text = text
+            .replace("function u0:0", "function %dec")
+            .replace("iadd", "isub");
+        assert_eq!(
+            generator2.define_function_from_clif(&patched_text).unwrap(),
+            vec![func_dec_id]
+        );
+
+        generator2.module.finalize_definitions().unwrap();
+
+        let func_inc_ptr = generator2.module.get_finalized_function(func_inc_id2);
+        let func_inc: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(func_inc_ptr) };
+        assert_eq!(func_inc(13), 24);
+
+        let func_dec_ptr = generator2.module.get_finalized_function(func_dec_id);
+        let func_dec: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(func_dec_ptr) };
+        assert_eq!(func_dec(3), 8); // 11 - 3
+
+        // undeclared function
+        let undeclared_text = text.replace("function u0:0", "function %foo");
+        assert!(matches!(
+            generator2.define_function_from_clif(&undeclared_text),
+            Err(ModuleError::Undeclared(_))
+        ));
+    }
+
+    #[test]
+    fn test_code_generator_reproducible_build() {
+        fn build(comp_dir: &str) -> Vec<u8> {
+            let mut generator = Generator::<ObjectModule>::new("main", None);
+            generator.enable_reproducible_build();
+            generator.enable_debug_info(&format!("{}/main.anc", comp_dir), comp_dir);
+
+            let file_id = generator
+                .source_map
+                .add_source_file(&format!("{}/main.anc", comp_dir));
+
+            let mut func_main_sig = generator.module.make_signature();
+            func_main_sig.returns.push(AbiParam::new(types::I32));
+
+            let func_main_id = generator
+                .module
+                .declare_function("main", Linkage::Export, &func_main_sig)
+                .unwrap();
+
+            generator.debug_info.as_mut().unwrap().declare_function(
+                func_main_id,
+                "main",
+                file_id,
+                1,
+                vec![],
+                vec![types::I32],
+            );
+
+            let mut func_main = Function::with_name_signature(
+                UserFuncName::user(0, func_main_id.as_u32()),
+                func_main_sig,
+            );
+
+            let mut function_builder =
+                FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+            let block = function_builder.create_block();
+            function_builder.switch_to_block(block);
+
+            generator
+                .source_map
+                .set_source_location(&mut function_builder, file_id, 2, 4);
+            let value_0 = function_builder.ins().iconst(types::I32, 11);
+            function_builder.ins().return_(&[value_0]);
+
+            function_builder.seal_all_blocks();
+            function_builder.finalize();
+
+            generator.define_function(func_main_id, func_main).unwrap();
+            generator.finish().unwrap().emit().unwrap()
+        }
+
+        let binary_0 = build("/home/foo/project");
+        let binary_1 = build("/tmp/build/bar");
+
+        assert_eq!(binary_0, binary_1);
+        assert!(!binary_0.windows(4).any(|w| w == b"/tmp"));
+    }
+
+    #[test]
+    fn test_code_generator_static() {
+        // ```rust
+        // extern "C" {
+        //     fn abs(i32) -> i32;
+        // }
+        //
+        // fn main() -> i32 {
+        //     abs(-13)
+        // }
+        // ```
+        let mut generator = Generator::<ObjectModule>::new_static("main", None);
+
+        let mut func_abs_sig = generator.module.make_signature();
+        func_abs_sig.params.push(AbiParam::new(types::I32));
+        func_abs_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_abs_id = generator
+            .module
+            .declare_function("abs", Linkage::Import, &func_abs_sig)
+            .unwrap();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let func_abs_ref = generator
+            .module
+            .declare_func_in_func(func_abs_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.ins().iconst(types::I32, -13);
+        let call_0 = function_builder.ins().call(func_abs_ref, &[value_0]);
+        let value_1 = function_builder.inst_results(call_0)[0];
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        let folder = std::env::temp_dir();
+        let object_file_path = folder.join(format!("anc_test_static_{}.o", std::process::id()));
+        let exec_file_path = folder.join(format!("anc_test_static_{}.elf", std::process::id()));
+        std::fs::write(&object_file_path, &module_binary).unwrap();
+
+        for mode in [LinkerMode::NoPie, LinkerMode::Static] {
+            link_executable(
+                &[object_file_path.to_str().unwrap()],
+                exec_file_path.to_str().unwrap(),
+                &LinkerOptions {
+                    mode,
+                    ..LinkerOptions::default()
+                },
+            )
+            .unwrap();
+
+            let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+            assert_eq!(exit_code_opt, Some(13));
+        }
+
+        std::fs::remove_file(&object_file_path).unwrap();
+        std::fs::remove_file(&exec_file_path).unwrap();
+    }
 }
@@ -0,0 +1,208 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use cranelift_codegen::incremental_cache::CacheKvStore;
+
+// The incremental compilation cache
+// ---------------------------------
+//
+// Cranelift can reuse the compiled artifacts of a function if the function
+// (its CLIF) and the target ISA (including the flags) are unchanged, the key of
+// the cache is the SHA-256 hash of them, and the value is a serialized blob.
+//
+// The `CompilationCache` keeps the entries in memory, and optionally persists
+// them in a directory (one file per entry, named by the hex of the key), so
+// that rebuilding a module which only has one function changed does not
+// recompile the other functions.
+//
+// e.g.
+//
+// ```rust
+// let cache = CompilationCache::open("target/anc_cache")?;
+// generator.enable_compilation_cache(cache);
+// ```
+//
+// ref:
+// - https://docs.rs/cranelift-codegen/latest/cranelift_codegen/incremental_cache/index.html
+
+const CACHE_FILE_EXTENSION: &str = "clifcache";
+
+#[derive(Debug, Default)]
+pub struct CompilationCache {
+    /// the directory to persist the entries, `None` for memory only.
+    directory: Option<PathBuf>,
+    entries: HashMap<Vec<u8>, Vec<u8>>,
+    hits: usize,
+    misses: usize,
+}
+
+impl CompilationCache {
+    /// Create a cache which is kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a cache which is persisted in the given directory, the directory
+    /// is created if it does not exist.
+    pub fn open<P: AsRef<Path>>(directory: P) -> std::io::Result<Self> {
+        std::fs::create_dir_all(directory.as_ref())?;
+
+        Ok(Self {
+            directory: Some(directory.as_ref().to_path_buf()),
+            ..Self::default()
+        })
+    }
+
+    /// The number of functions which reuse the cached artifacts.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of functions which are compiled.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub(crate) fn record(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+
+    fn get_entry_path(&self, key: &[u8]) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        let name = key
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        Some(directory.join(format!("{}.{}", name, CACHE_FILE_EXTENSION)))
+    }
+}
+
+impl CacheKvStore for CompilationCache {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, [u8]>> {
+        if let Some(value) = self.entries.get(key) {
+            return Some(Cow::Borrowed(value));
+        }
+
+        let path = self.get_entry_path(key)?;
+        std::fs::read(path).ok().map(Cow::Owned)
+    }
+
+    fn insert(&mut self, key: &[u8], val: Vec<u8>) {
+        if let Some(path) = self.get_entry_path(key) {
+            // the cache is an optimization only, so the I/O errors are ignored.
+            let _ = std::fs::write(path, &val);
+        }
+
+        self.entries.insert(key.to_vec(), val);
+    }
+}
+
+/// Share the cache between the worker threads of the parallel compilation.
+pub(crate) struct SharedCompilationCache<'a> {
+    pub cache: &'a Mutex<&'a mut CompilationCache>,
+}
+
+impl CacheKvStore for SharedCompilationCache<'_> {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, [u8]>> {
+        let cache = self.cache.lock().unwrap();
+        cache.get(key).map(|value| Cow::Owned(value.into_owned()))
+    }
+
+    fn insert(&mut self, key: &[u8], val: Vec<u8>) {
+        self.cache.lock().unwrap().insert(key, val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{code_generator::Generator, compilation_cache::CompilationCache};
+
+    fn build_module(cache: CompilationCache, number: i64) -> (CompilationCache, Vec<u8>) {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_compilation_cache(cache);
+
+        let mut func_sig = generator.module.make_signature();
+        func_sig.params.push(AbiParam::new(types::I32));
+        func_sig.returns.push(AbiParam::new(types::I32));
+
+        // build functions "inc" and "dec"
+        //
+        // ```rust
+        // fn inc (a:i32) -> i32 { a + 1 }
+        // fn dec (a:i32) -> i32 { a - number }
+        // ```
+        for (name, imm) in [("inc", 1), ("dec", -number)] {
+            let func_id = generator
+                .module
+                .declare_function(name, Linkage::Export, &func_sig)
+                .unwrap();
+
+            let mut func = Function::with_name_signature(
+                UserFuncName::user(0, func_id.as_u32()),
+                func_sig.clone(),
+            );
+
+            let mut function_builder =
+                FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+            let block = function_builder.create_block();
+            function_builder.append_block_params_for_function_params(block);
+            function_builder.switch_to_block(block);
+
+            let value_0 = function_builder.block_params(block)[0];
+            let value_1 = function_builder.ins().iadd_imm(value_0, imm);
+            function_builder.ins().return_(&[value_1]);
+
+            function_builder.seal_all_blocks();
+            function_builder.finalize();
+
+            generator.define_function(func_id, func).unwrap();
+        }
+
+        let cache = generator.compilation_cache.take().unwrap();
+        let binary = generator.finish().unwrap().emit().unwrap();
+        (cache, binary)
+    }
+
+    #[test]
+    fn test_compilation_cache() {
+        let directory =
+            std::env::temp_dir().join(format!("anc_test_compilation_cache_{}", std::process::id()));
+
+        // the first build
+        let cache = CompilationCache::open(&directory).unwrap();
+        let (cache, binary_0) = build_module(cache, 1);
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+
+        // rebuild with the persisted cache
+        let cache = CompilationCache::open(&directory).unwrap();
+        let (cache, binary_1) = build_module(cache, 1);
+        assert_eq!((cache.hits(), cache.misses()), (2, 0));
+        assert_eq!(binary_0, binary_1);
+
+        // only function "dec" is changed
+        let (cache, _) = build_module(cache, 2);
+        assert_eq!((cache.hits(), cache.misses()), (3, 1));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use crate::{
+    diagnostic::Diagnostic,
+    lexer::{Token, TokenKind},
+    parser::SExpr,
+};
+
+// The conditional compilation
+// ---------------------------
+//
+// The nodes in the blocks `(when-target condition node...)` and
+// `(when-feature condition node...)` are kept (i.e. spliced into the parent
+// list) only if the condition is satisfied, otherwise they are removed, e.g.
+//
+// ```text
+// (module $app
+//     (when-target "aarch64"
+//         (import (function $cycle_counter "read_cntvct" (result i64))))
+//     (when-target (not "aarch64")
+//         (import (function $cycle_counter "read_tsc" (result i64))))
+//
+//     (function $main (result i32)
+//         (code
+//             (when-feature "debug" (call $log_start))
+//             (imm_i32 0))))
+// ```
+//
+// - the condition is a string or `(not "string")`.
+// - the target condition is satisfied if the string equals the target triple
+//   or any component of it, e.g. "x86_64", "linux" and "gnu" all match
+//   "x86_64-unknown-linux-gnu".
+// - the feature condition is satisfied if the feature is enabled, the features
+//   are supplied by the user (e.g. `anasm build --feature debug`).
+// - the blocks can be used in any list (the module items, the instructions and
+//   the operands), and can be nested. They are evaluated before the macros
+//   are expanded, so the macro definitions can be conditional too.
+
+/// The target and the enabled features which the conditions are evaluated against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conditions {
+    /// The target triple, e.g. "x86_64-unknown-linux-gnu".
+    pub target: String,
+    pub features: Vec<String>,
+}
+
+impl Conditions {
+    pub fn new(target: &str, features: &[String]) -> Self {
+        Self {
+            target: target.to_owned(),
+            features: features.to_vec(),
+        }
+    }
+
+    /// The conditions of the current machine (i.e. the JIT target) without features.
+    pub fn host() -> Self {
+        let target = cranelift_native::builder()
+            .map(|builder| builder.triple().to_string())
+            .unwrap_or_else(|_| "x86_64-unknown-linux-gnu".to_owned());
+        Self {
+            target,
+            features: vec![],
+        }
+    }
+
+    pub fn matches_target(&self, pattern: &str) -> bool {
+        self.target == pattern || self.target.split('-').any(|part| part == pattern)
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|item| item == feature)
+    }
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+/// Remove the conditional blocks whose conditions are not satisfied,
+/// and splice the nodes of the others into their parent lists.
+pub fn evaluate_conditionals(sexpr: &SExpr, conditions: &Conditions) -> Result<SExpr, Diagnostic> {
+    let SExpr::List { items, span } = sexpr else {
+        return Ok(sexpr.clone());
+    };
+
+    let mut evaluated_items = vec![];
+    for item in items {
+        match item.get_head_keyword() {
+            Some(keyword @ ("when-target" | "when-feature")) => {
+                let SExpr::List {
+                    items: block_items, ..
+                } = item
+                else {
+                    unreachable!()
+                };
+
+                let condition = block_items.get(1).ok_or_else(|| {
+                    Diagnostic::new(
+                        &format!(
+                            "expect the condition, e.g. \"({} \\\"name\\\" ...)\"",
+                            keyword
+                        ),
+                        item.span(),
+                    )
+                })?;
+
+                let is_satisfied = evaluate_condition(condition, &|name| {
+                    if keyword == "when-target" {
+                        conditions.matches_target(name)
+                    } else {
+                        conditions.has_feature(name)
+                    }
+                })?;
+
+                if is_satisfied {
+                    // the block is evaluated as a list, so the nested blocks are spliced too
+                    let block = evaluate_conditionals(
+                        &SExpr::List {
+                            items: block_items[2..].to_vec(),
+                            span: item.span(),
+                        },
+                        conditions,
+                    )?;
+                    if let SExpr::List { items, .. } = block {
+                        evaluated_items.extend(items);
+                    }
+                }
+            }
+            _ => evaluated_items.push(evaluate_conditionals(item, conditions)?),
+        }
+    }
+
+    Ok(SExpr::List {
+        items: evaluated_items,
+        span: *span,
+    })
+}
+
+fn evaluate_condition(
+    condition: &SExpr,
+    is_matched: &dyn Fn(&str) -> bool,
+) -> Result<bool, Diagnostic> {
+    match condition {
+        SExpr::Atom(Token {
+            kind: TokenKind::String(bytes),
+            ..
+        }) => Ok(is_matched(&String::from_utf8_lossy(bytes))),
+        SExpr::List { items, .. } if condition.get_head_keyword() == Some("not") => {
+            match items.as_slice() {
+                [_, operand] => Ok(!evaluate_condition(operand, is_matched)?),
+                _ => Err(Diagnostic::new(
+                    "the condition \"not\" requires exactly one operand",
+                    condition.span(),
+                )),
+            }
+        }
+        _ => Err(Diagnostic::new(
+            &format!(
+                "expect a string or \"(not ...)\" as the condition, found {}",
+                condition.describe()
+            ),
+            condition.span(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::InstructionKind,
+        conditional::Conditions,
+        diagnostic::Diagnostic,
+        parser::{parse_module, parse_module_with_conditions},
+    };
+
+    #[test]
+    fn test_evaluate_conditionals() {
+        let source = r#"
+        (module $app
+            (when-target "aarch64"
+                (function $arch (result i32) (code (imm_i32 64))))
+            (when-target (not "aarch64")
+                (function $arch (result i32) (code (imm_i32 86))))
+
+            (function $main (result i32)
+                (code
+                    (when-feature "debug"
+                        (call $arch)
+                        (when-target "linux" (call $arch)))
+                    (imm_i32 0))))
+        "#;
+
+        let parse = |target: &str, features: &[&str]| {
+            let features = features
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>();
+            parse_module_with_conditions(source, 0, &Conditions::new(target, &features)).unwrap()
+        };
+
+        let module = parse("x86_64-unknown-linux-gnu", &[]);
+        assert_eq!(module.functions.len(), 2);
+        assert_eq!(
+            module.functions[0].body[0].kind,
+            InstructionKind::ImmI32(86)
+        );
+        assert_eq!(module.functions[1].body.len(), 1);
+
+        let module = parse("aarch64-unknown-linux-gnu", &["debug"]);
+        assert_eq!(
+            module.functions[0].body[0].kind,
+            InstructionKind::ImmI32(64)
+        );
+        assert_eq!(module.functions[1].body.len(), 3);
+
+        let module = parse("aarch64-apple-darwin", &["debug"]);
+        assert_eq!(module.functions[1].body.len(), 2);
+
+        let Diagnostic { message, .. } =
+            parse_module("(module $a (when-feature debug (function $f (code))))").unwrap_err();
+        assert_eq!(
+            message,
+            "expect a string or \"(not ...)\" as the condition, found identifier \"debug\""
+        );
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::types;
+
+use crate::{
+    diagnostic::Diagnostic,
+    layout::{DataType, StructLayout},
+    lexer::{Span, Token, TokenKind},
+    parser::SExpr,
+    struct_type::StructTypes,
+};
+
+// The constant expressions
+// ------------------------
+//
+// The named constants are defined in the module by `(const $NAME expr)`, and
+// the constant expressions can be used wherever a number is expected, i.e. the
+// immediates, the data values, the sizes and alignments of data, the offsets
+// and the panic codes, e.g.
+//
+// ```text
+// (module $app
+//     (const $COUNT 16)
+//     (const $ENTRY_SIZE (sizeof (struct i8 i64)))
+//     (data $table (uninit (mul $COUNT $ENTRY_SIZE) (alignof i64)))
+//     (function $get_last (result i64)
+//         (code (data_load_i64 $table (add (mul (sub $COUNT 1) $ENTRY_SIZE) 8)))))
+// ```
+//
+// the expressions:
+//
+// - the number literals, and the names of the constants which are defined
+//   before (so there is no cycle).
+// - `(add a b)`, `(sub a b)`, `(mul a b)`, `(div a b)`, `(rem a b)` and `(neg a)`,
+//   the result is a float if any operand is a float.
+// - `(shl a b)`, `(shr a b)` (arithmetic), `(and a b)`, `(or a b)` and `(xor a b)`,
+//   the operands should be integers.
+// - `(sizeof type)` and `(alignof type)`, the layout is calculated by `layout.rs`,
+//   the types are the scalars `i8`, `i16`, `i32`, `i64`, `f32`, `f64`, and
+//   `(array type length)`, `(struct type...)` and `(union type...)`, and the
+//   names of the struct types which are declared before (see `struct_type.rs`).
+//
+// the integers are evaluated with 128 bits and checked for overflow, the range
+// of the final value is checked by the instruction (or the data) which uses it.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConstValue {
+    Integer(i128),
+    Float(f64),
+}
+
+impl ConstValue {
+    /// The text of the value in the format of the number token.
+    pub fn to_number_text(&self) -> String {
+        match self {
+            ConstValue::Integer(value) => value.to_string(),
+            ConstValue::Float(value) => format!("{:?}", value),
+        }
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            ConstValue::Integer(value) => *value as f64,
+            ConstValue::Float(value) => *value,
+        }
+    }
+}
+
+const BINARY_OPERATORS: [&str; 10] = [
+    "add", "sub", "mul", "div", "rem", "shl", "shr", "and", "or", "xor",
+];
+
+/// Check whether the node is a constant expression (except the number literal),
+/// i.e. the name of a constant or an operation.
+pub fn is_const_expression(sexpr: &SExpr, constants: &Constants) -> bool {
+    match sexpr {
+        SExpr::Atom(Token {
+            kind: TokenKind::Name(name),
+            ..
+        }) => constants.get(name).is_some(),
+        SExpr::List { .. } => sexpr.get_head_keyword().is_some_and(|keyword| {
+            BINARY_OPERATORS.contains(&keyword) || matches!(keyword, "neg" | "sizeof" | "alignof")
+        }),
+        SExpr::Atom(_) => false,
+    }
+}
+
+/// The named constants of a module, in the order of definition.
+///
+/// The struct types are declared in the same pass (i.e. in the order of the
+/// module items), so that the constants and the struct types can refer to
+/// each other.
+#[derive(Debug, Default)]
+pub struct Constants {
+    items: Vec<(String, ConstValue)>,
+    structs: StructTypes,
+}
+
+impl Constants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<ConstValue> {
+        self.items
+            .iter()
+            .find(|(item_name, _)| item_name == name)
+            .map(|(_, value)| *value)
+    }
+
+    pub fn structs(&self) -> &StructTypes {
+        &self.structs
+    }
+
+    /// `(struct $Name ...)` or `(union $Name ...)`, see `struct_type.rs`.
+    pub fn declare_struct(&mut self, sexpr: &SExpr) -> Result<(), Diagnostic> {
+        let (name, struct_type) = self.structs.convert_declaration(sexpr, self)?;
+        self.structs.insert(&name, struct_type);
+        Ok(())
+    }
+
+    /// `(const $NAME expr)`
+    pub fn define(&mut self, sexpr: &SExpr) -> Result<(), Diagnostic> {
+        let SExpr::List { items, span } = sexpr else {
+            unreachable!()
+        };
+
+        let (name, name_span) = match items.get(1) {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::Name(name),
+                span,
+            })) => (name, *span),
+            _ => {
+                return Err(Diagnostic::new(
+                    "expect the name of the constant, e.g. \"(const $NAME expr)\"",
+                    *span,
+                ))
+            }
+        };
+
+        let [_, _, expression] = items.as_slice() else {
+            return Err(Diagnostic::new(
+                &format!("the constant \"${}\" requires exactly one expression", name),
+                *span,
+            ));
+        };
+
+        if self.get(name).is_some() {
+            return Err(Diagnostic::new(
+                &format!("duplicate constant \"${}\"", name),
+                name_span,
+            ));
+        }
+
+        let value = self.evaluate(expression)?;
+        self.items.push((name.to_owned(), value));
+        Ok(())
+    }
+
+    pub fn evaluate(&self, sexpr: &SExpr) -> Result<ConstValue, Diagnostic> {
+        let span = sexpr.span();
+        match sexpr {
+            SExpr::Atom(Token {
+                kind: TokenKind::Number(number),
+                ..
+            }) => parse_number(number)
+                .ok_or_else(|| Diagnostic::new(&format!("invalid number \"{}\"", number), span)),
+            SExpr::Atom(Token {
+                kind: TokenKind::Name(name),
+                ..
+            }) => self.get(name).ok_or_else(|| {
+                Diagnostic::new(&format!("the constant \"${}\" is not defined", name), span)
+                    .with_note("the constants should be defined before they are used")
+            }),
+            SExpr::List { items, .. } => {
+                let keyword = sexpr.get_head_keyword().ok_or_else(|| {
+                    Diagnostic::new("expect a constant expression, e.g. \"(add ...)\"", span)
+                })?;
+                let operands = &items[1..];
+
+                match keyword {
+                    "sizeof" | "alignof" => {
+                        let [operand] = operands else {
+                            return Err(error_operand_count(keyword, 1, operands.len(), span));
+                        };
+                        let data_type = self.evaluate_type(operand)?;
+                        Ok(ConstValue::Integer(if keyword == "sizeof" {
+                            data_type.size()
+                        } else {
+                            data_type.align()
+                        } as i128))
+                    }
+                    "neg" => {
+                        let [operand] = operands else {
+                            return Err(error_operand_count(keyword, 1, operands.len(), span));
+                        };
+                        match self.evaluate(operand)? {
+                            ConstValue::Integer(value) => value
+                                .checked_neg()
+                                .map(ConstValue::Integer)
+                                .ok_or_else(|| error_overflow(span)),
+                            ConstValue::Float(value) => Ok(ConstValue::Float(-value)),
+                        }
+                    }
+                    _ if BINARY_OPERATORS.contains(&keyword) => {
+                        let [left, right] = operands else {
+                            return Err(error_operand_count(keyword, 2, operands.len(), span));
+                        };
+                        let left = self.evaluate(left)?;
+                        let right = self.evaluate(right)?;
+                        evaluate_binary(keyword, left, right, span)
+                    }
+                    _ => Err(Diagnostic::new(
+                        &format!("unknown constant operator \"{}\"", keyword),
+                        items[0].span(),
+                    )
+                    .with_note("the operators are \"add\", \"sub\", \"mul\", \"div\", \"rem\", \"neg\", \"shl\", \"shr\", \"and\", \"or\", \"xor\", \"sizeof\" and \"alignof\"")),
+                }
+            }
+            SExpr::Atom(_) => Err(Diagnostic::new(
+                &format!("expect a constant expression, found {}", sexpr.describe()),
+                span,
+            )),
+        }
+    }
+
+    fn evaluate_type(&self, sexpr: &SExpr) -> Result<DataType, Diagnostic> {
+        let span = sexpr.span();
+        match sexpr {
+            SExpr::Atom(Token {
+                kind: TokenKind::Identifier(identifier),
+                ..
+            }) => {
+                let ty = match identifier.as_str() {
+                    "i8" => types::I8,
+                    "i16" => types::I16,
+                    "i32" => types::I32,
+                    "i64" => types::I64,
+                    "f32" => types::F32,
+                    "f64" => types::F64,
+                    _ => {
+                        return Err(Diagnostic::new(
+                            &format!(
+                                "unknown scalar type \"{}\", expect \"i8\", \"i16\", \"i32\", \"i64\", \"f32\" or \"f64\"",
+                                identifier
+                            ),
+                            span,
+                        ))
+                    }
+                };
+                Ok(DataType::Scalar(ty))
+            }
+            SExpr::Atom(Token {
+                kind: TokenKind::Name(name),
+                ..
+            }) => match self.structs.get(name) {
+                Some(struct_type) => Ok(DataType::Struct(struct_type.layout.clone())),
+                None => Err(Diagnostic::new(
+                    &format!("the struct type \"${}\" is not declared", name),
+                    span,
+                )),
+            },
+            SExpr::List { items, .. } => {
+                let keyword = sexpr.get_head_keyword().ok_or_else(|| {
+                    Diagnostic::new(
+                        "expect a type, e.g. \"i32\", \"$Name\", \"(array ...)\", \"(struct ...)\" or \"(union ...)\"",
+                        span,
+                    )
+                })?;
+                let operands = &items[1..];
+
+                match keyword {
+                    "array" => {
+                        let [element, length] = operands else {
+                            return Err(error_operand_count("array", 2, operands.len(), span));
+                        };
+                        let element = self.evaluate_type(element)?;
+                        let length = match self.evaluate(length)? {
+                            ConstValue::Integer(value) if (0..=u32::MAX as i128).contains(&value) => {
+                                value as u32
+                            }
+                            _ => {
+                                return Err(Diagnostic::new(
+                                    "the length of the array should be an unsigned 32-bit integer",
+                                    length.span(),
+                                ))
+                            }
+                        };
+                        Ok(DataType::Array(Box::new(element), length))
+                    }
+                    "struct" | "union" => {
+                        let fields = operands
+                            .iter()
+                            .enumerate()
+                            .map(|(index, item)| {
+                                self.evaluate_type(item)
+                                    .map(|data_type| (index.to_string(), data_type))
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(DataType::Struct(if keyword == "struct" {
+                            StructLayout::new_struct(fields)
+                        } else {
+                            StructLayout::new_union(fields)
+                        }))
+                    }
+                    _ => Err(Diagnostic::new(
+                        "expect a type, e.g. \"i32\", \"$Name\", \"(array ...)\", \"(struct ...)\" or \"(union ...)\"",
+                        span,
+                    )),
+                }
+            }
+            SExpr::Atom(_) => Err(Diagnostic::new(
+                &format!("expect a type, found {}", sexpr.describe()),
+                span,
+            )),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<ConstValue> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let integer = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i128::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i128::from_str_radix(binary, 2).ok()
+    } else {
+        digits.parse::<i128>().ok()
+    };
+
+    match integer {
+        Some(value) => Some(ConstValue::Integer(if negative { -value } else { value })),
+        None => text.parse::<f64>().ok().map(ConstValue::Float),
+    }
+}
+
+fn error_operand_count(keyword: &str, expected: usize, actual: usize, span: Span) -> Diagnostic {
+    Diagnostic::new(
+        &format!(
+            "the operator \"{}\" requires {} operand(s), found {}",
+            keyword, expected, actual
+        ),
+        span,
+    )
+}
+
+fn error_overflow(span: Span) -> Diagnostic {
+    Diagnostic::new("the constant expression overflows", span)
+}
+
+fn evaluate_binary(
+    keyword: &str,
+    left: ConstValue,
+    right: ConstValue,
+    span: Span,
+) -> Result<ConstValue, Diagnostic> {
+    match (left, right) {
+        (ConstValue::Integer(left), ConstValue::Integer(right)) => {
+            if matches!(keyword, "div" | "rem") && right == 0 {
+                return Err(Diagnostic::new("division by zero", span));
+            }
+            let value = match keyword {
+                "add" => left.checked_add(right),
+                "sub" => left.checked_sub(right),
+                "mul" => left.checked_mul(right),
+                "div" => left.checked_div(right),
+                "rem" => left.checked_rem(right),
+                "shl" | "shr" => {
+                    if !(0..128).contains(&right) {
+                        return Err(Diagnostic::new("the shift amount should be 0 to 127", span));
+                    }
+                    if keyword == "shl" {
+                        let value = left << right;
+                        (value >> right == left).then_some(value)
+                    } else {
+                        Some(left >> right)
+                    }
+                }
+                "and" => Some(left & right),
+                "or" => Some(left | right),
+                "xor" => Some(left ^ right),
+                _ => unreachable!(),
+            };
+            value
+                .map(ConstValue::Integer)
+                .ok_or_else(|| error_overflow(span))
+        }
+        (left, right) => {
+            let (left, right) = (left.as_float(), right.as_float());
+            let value = match keyword {
+                "add" => left + right,
+                "sub" => left - right,
+                "mul" => left * right,
+                "div" => left / right,
+                "rem" => left % right,
+                _ => {
+                    return Err(Diagnostic::new(
+                        &format!("the operator \"{}\" requires integer operands", keyword),
+                        span,
+                    ))
+                }
+            };
+            Ok(ConstValue::Float(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::{DataKind, DataValue, InstructionKind, LoadType},
+        diagnostic::Diagnostic,
+        parser::parse_module,
+    };
+
+    #[test]
+    fn test_constants() {
+        let source = r#"
+        (module $app
+            (const $COUNT 16)
+            (const $ENTRY_SIZE (sizeof (struct i8 i64)))
+            (const $RECORD_SIZE (sizeof (array (union i32 (struct i16 i8)) (shl 1 2))))
+            (const $HALF (div 1.0 2))
+
+            (data $table (uninit (mul $COUNT $ENTRY_SIZE) (alignof (struct i8 i64))))
+            (data $ratio (read_only f64 (sub 1 $HALF)))
+
+            (function $get_last (result i64)
+                (code
+                    (data_load_i64 $table (add (mul (sub $COUNT 1) $ENTRY_SIZE) 8))))
+
+            (function $get_mask (result i32)
+                (code (imm_i32 (xor (neg 1) (sub (shl 1 $COUNT) 1))))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        assert_eq!(
+            module.data[0].kind,
+            DataKind::Uninit {
+                size: 256,
+                align: 8
+            }
+        );
+        assert_eq!(module.data[1].kind, DataKind::ReadOnly(DataValue::F64(0.5)));
+        assert_eq!(
+            module.functions[0].body[0].kind,
+            InstructionKind::DataLoad {
+                load_type: LoadType::I64,
+                name: "table".to_owned(),
+                offset: 248
+            }
+        );
+        assert_eq!(
+            module.functions[1].body[0].kind,
+            InstructionKind::ImmI32(0xffff_0000)
+        );
+
+        // the errors
+        fn parse_error(source: &str) -> (String, &str) {
+            let Diagnostic { message, span, .. } = parse_module(source).unwrap_err();
+            (message, &source[span.start..span.end])
+        }
+
+        assert_eq!(
+            parse_error("(module $a (function $f (code (imm_i32 $SIZE))))"),
+            ("the constant \"$SIZE\" is not defined".to_owned(), "$SIZE")
+        );
+        assert_eq!(
+            parse_error("(module $a (const $A (div 1 (sub 2 2))))"),
+            ("division by zero".to_owned(), "(div 1 (sub 2 2))")
+        );
+        assert_eq!(
+            parse_error("(module $a (const $B $C) (const $C 1))"),
+            ("the constant \"$C\" is not defined".to_owned(), "$C")
+        );
+        assert_eq!(
+            parse_error(
+                "(module $a (const $A 0x1_0000) (function $f (code (imm_i32 (mul $A $A)))))"
+            ),
+            (
+                "the number \"4294967296\" is out of range of 32-bit integer".to_owned(),
+                "(mul $A $A)"
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (const $A (shl 1.5 2)))"),
+            (
+                "the operator \"shl\" requires integer operands".to_owned(),
+                "(shl 1.5 2)"
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (const $A (sizeof ())))"),
+            (
+                "expect a type, e.g. \"i32\", \"$Name\", \"(array ...)\", \"(struct ...)\" or \"(union ...)\"".to_owned(),
+                "()"
+            )
+        );
+    }
+}
@@ -0,0 +1,512 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use cranelift_codegen::{
+    cursor::{Cursor, FuncCursor},
+    ir::{
+        condcodes::IntCC, types, AbiParam, Function, InstBuilder, MemFlags, SourceLoc, UserFuncName,
+    },
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module, ModuleError};
+use cranelift_object::ObjectModule;
+
+use crate::{
+    code_generator::{DataDefinition, Generator},
+    source_location::SourceMap,
+    validation::SymbolReferences,
+};
+
+// The basic-block coverage
+// ------------------------
+//
+// When it is enabled, each block of the functions (which are defined afterwards)
+// is instrumented with a counter increment:
+//
+// ```clif
+// block0:
+//     v0 = symbol_value.i64 gv0       ; the counters
+//     v1 = load.i64 notrap aligned v0+N
+//     v2 = iadd_imm v1, 1
+//     store notrap aligned v2, v0+N
+//     ...
+// ```
+//
+// the counters (u64 array) are placed in the data object `__anna_coverage_counters`,
+// and a dump routine `__anna_coverage_dump()` is generated when the module
+// is finished, it is registered in the section `.fini_array`, so it is
+// executed at exit (i.e. after the `main()` returns or `exit()` is called,
+// the same as the `atexit()` handlers), and writes the counters (in the
+// native endian) to the coverage file.
+//
+// the `Coverage` records the function and the source location of each counter,
+// combining it with the counters read from the coverage file and the source
+// map, the "which lines executed" report is generated, e.g.
+//
+// ```rust
+// let coverage = generator.coverage.clone().unwrap();
+// ...
+// let counts = read_coverage_file("anna.cov")?;
+// let line_coverages = coverage.get_line_coverages(&counts, &source_map);
+// ```
+
+const COUNTERS_DATA_NAME: &str = "__anna_coverage_counters";
+const FILE_PATH_DATA_NAME: &str = "__anna_coverage_file_path";
+const DUMP_FUNCTION_NAME: &str = "__anna_coverage_dump";
+const FINI_ARRAY_DATA_NAME: &str = "__anna_coverage_fini_array";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageCounter {
+    pub func_id: FuncId,
+
+    /// the index of the block.
+    pub block: u32,
+
+    /// the source location of the first instruction of the block.
+    pub source_loc: SourceLoc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineCoverage {
+    pub file_id: u32,
+    pub line: u32,
+
+    /// the maximum count of the blocks which start at this line.
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// the path of the coverage file which is written at exit.
+    pub file_path: String,
+    pub counters: Vec<CoverageCounter>,
+
+    /// the symbol names of the instrumented functions, see `get_function_profile()`.
+    pub function_names: HashMap<FuncId, String>,
+    pub(crate) counters_data_id: DataId,
+}
+
+impl Coverage {
+    /// Combine the counts (read from the coverage file) with the source
+    /// locations, the result is sorted by the file and line.
+    ///
+    /// The blocks without source location are ignored.
+    pub fn get_line_coverages(&self, counts: &[u64], source_map: &SourceMap) -> Vec<LineCoverage> {
+        let mut lines: BTreeMap<(u32, u32), u64> = BTreeMap::new();
+
+        for (counter, count) in self.counters.iter().zip(counts) {
+            if let Some(location) = source_map.get_source_location(counter.source_loc) {
+                let line_count = lines.entry((location.file_id, location.line)).or_default();
+                *line_count = (*line_count).max(*count);
+            }
+        }
+
+        lines
+            .into_iter()
+            .map(|((file_id, line), count)| LineCoverage {
+                file_id,
+                line,
+                count,
+            })
+            .collect()
+    }
+}
+
+/// Read the counts from the coverage file.
+pub fn read_coverage_file<P: AsRef<Path>>(file_path: P) -> std::io::Result<Vec<u64>> {
+    let bytes = std::fs::read(file_path)?;
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+impl Generator<ObjectModule> {
+    /// Enable the basic-block coverage instrumentation.
+    ///
+    /// - `file_path`: the path of the coverage file, the relative path is
+    ///   relative to the working directory of the program.
+    pub fn enable_coverage(&mut self, file_path: &str) -> Result<(), ModuleError> {
+        let counters_data_id =
+            self.module
+                .declare_data(COUNTERS_DATA_NAME, Linkage::Local, true, false)?;
+
+        self.coverage = Some(Coverage {
+            file_path: file_path.to_owned(),
+            counters: vec![],
+            function_names: HashMap::new(),
+            counters_data_id,
+        });
+        Ok(())
+    }
+
+    /// Define the counters, the dump routine and its `.fini_array` entry,
+    /// it is called by `finish()`.
+    pub(crate) fn define_coverage_data(&mut self) -> Result<(), ModuleError> {
+        let Some(coverage) = &self.coverage else {
+            return Ok(());
+        };
+
+        let counters_data_id = coverage.counters_data_id;
+        let counters_size = coverage.counters.len() * 8;
+
+        let mut path_data = coverage.file_path.as_bytes().to_vec();
+        path_data.push(0);
+
+        let path_data_id =
+            self.module
+                .declare_data(FILE_PATH_DATA_NAME, Linkage::Local, false, false)?;
+
+        // the data object can not be empty.
+        self.define_data_content(
+            counters_data_id,
+            DataDefinition::Uninitialized {
+                size: counters_size.max(8),
+                align: 8,
+            },
+        )?;
+        self.define_data_content(
+            path_data_id,
+            DataDefinition::Initialized {
+                data: path_data,
+                align: 1,
+            },
+        )?;
+
+        // ```c
+        // void __anna_coverage_dump() {
+        //     int fd = creat(file_path, 0644);
+        //     if (fd >= 0) {
+        //         write(fd, counters, counters_size);
+        //         close(fd);
+        //     }
+        // }
+        // ```
+        let pointer_type = self.module.isa().pointer_type();
+
+        let mut creat_sig = self.module.make_signature();
+        creat_sig.params.push(AbiParam::new(pointer_type));
+        creat_sig.params.push(AbiParam::new(types::I32));
+        creat_sig.returns.push(AbiParam::new(types::I32));
+
+        let mut write_sig = self.module.make_signature();
+        write_sig.params.push(AbiParam::new(types::I32));
+        write_sig.params.push(AbiParam::new(pointer_type));
+        write_sig.params.push(AbiParam::new(pointer_type));
+        write_sig.returns.push(AbiParam::new(pointer_type));
+
+        let mut close_sig = self.module.make_signature();
+        close_sig.params.push(AbiParam::new(types::I32));
+        close_sig.returns.push(AbiParam::new(types::I32));
+
+        let creat_id = self
+            .module
+            .declare_function("creat", Linkage::Import, &creat_sig)?;
+        let write_id = self
+            .module
+            .declare_function("write", Linkage::Import, &write_sig)?;
+        let close_id = self
+            .module
+            .declare_function("close", Linkage::Import, &close_sig)?;
+
+        let dump_sig = self.module.make_signature();
+        let dump_id =
+            self.module
+                .declare_function(DUMP_FUNCTION_NAME, Linkage::Local, &dump_sig)?;
+
+        let mut func_dump =
+            Function::with_name_signature(UserFuncName::user(0, dump_id.as_u32()), dump_sig);
+
+        let creat_ref = self.module.declare_func_in_func(creat_id, &mut func_dump);
+        let write_ref = self.module.declare_func_in_func(write_id, &mut func_dump);
+        let close_ref = self.module.declare_func_in_func(close_id, &mut func_dump);
+        let path_gv = self
+            .module
+            .declare_data_in_func(path_data_id, &mut func_dump);
+        let counters_gv = self
+            .module
+            .declare_data_in_func(counters_data_id, &mut func_dump);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_dump, &mut self.function_builder_context);
+
+        let block_0 = function_builder.create_block();
+        let block_1 = function_builder.create_block();
+        let block_2 = function_builder.create_block();
+
+        function_builder.switch_to_block(block_0);
+        let value_path = function_builder.ins().symbol_value(pointer_type, path_gv);
+        let value_mode = function_builder.ins().iconst(types::I32, 0o644);
+        let call_creat = function_builder
+            .ins()
+            .call(creat_ref, &[value_path, value_mode]);
+        let value_fd = function_builder.inst_results(call_creat)[0];
+        let value_failed = function_builder
+            .ins()
+            .icmp_imm(IntCC::SignedLessThan, value_fd, 0);
+        function_builder
+            .ins()
+            .brif(value_failed, block_2, &[], block_1, &[]);
+
+        function_builder.switch_to_block(block_1);
+        let value_counters = function_builder
+            .ins()
+            .symbol_value(pointer_type, counters_gv);
+        let value_size = function_builder
+            .ins()
+            .iconst(pointer_type, counters_size as i64);
+        function_builder
+            .ins()
+            .call(write_ref, &[value_fd, value_counters, value_size]);
+        function_builder.ins().call(close_ref, &[value_fd]);
+        function_builder.ins().jump(block_2, &[]);
+
+        function_builder.switch_to_block(block_2);
+        function_builder.ins().return_(&[]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        // the dump routine itself is not instrumented.
+        let coverage = self.coverage.take();
+        let result = self.define_function(dump_id, func_dump);
+        self.coverage = coverage;
+        result?;
+
+        // the `.fini_array` entry
+        let fini_array_data_id =
+            self.module
+                .declare_data(FINI_ARRAY_DATA_NAME, Linkage::Local, true, false)?;
+
+        let mut data_description = DataDescription::new();
+        data_description.define(vec![0; pointer_type.bytes() as usize].into_boxed_slice());
+        data_description.set_segment_section("", ".fini_array");
+        data_description.set_align(pointer_type.bytes() as u64);
+        let dump_ref = self
+            .module
+            .declare_func_in_data(dump_id, &mut data_description);
+        data_description.write_function_addr(0, dump_ref);
+
+        self.module
+            .define_data(fini_array_data_id, &data_description)?;
+        self.set_symbol_references(SymbolReferences::from_data(
+            fini_array_data_id,
+            &data_description,
+        ));
+        Ok(())
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Insert the counter increments into each block of the function.
+    pub(crate) fn insert_coverage_counters(&mut self, func_id: FuncId, func: &mut Function) {
+        let Some(coverage) = &mut self.coverage else {
+            return;
+        };
+
+        let pointer_type = self.module.isa().pointer_type();
+        let counters_gv = self
+            .module
+            .declare_data_in_func(coverage.counters_data_id, func);
+
+        let name = self
+            .module
+            .declarations()
+            .get_function_decl(func_id)
+            .linkage_name(func_id)
+            .into_owned();
+        coverage.function_names.insert(func_id, name);
+
+        let blocks = func.layout.blocks().collect::<Vec<_>>();
+        let mut cursor = FuncCursor::new(func);
+
+        for block in blocks {
+            cursor.goto_first_insertion_point(block);
+
+            let source_loc = cursor
+                .current_inst()
+                .map(|inst| cursor.func.srcloc(inst))
+                .unwrap_or_default();
+            cursor.set_srcloc(source_loc);
+
+            let offset = (coverage.counters.len() * 8) as i32;
+            coverage.counters.push(CoverageCounter {
+                func_id,
+                block: block.as_u32(),
+                source_loc,
+            });
+
+            let flags = MemFlags::trusted();
+            let value_0 = cursor.ins().symbol_value(pointer_type, counters_gv);
+            let value_1 = cursor.ins().load(types::I64, flags, value_0, offset);
+            let value_2 = cursor.ins().iadd_imm(value_1, 1);
+            cursor.ins().store(flags, value_2, value_0, offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write, process::Command};
+
+    use cranelift_codegen::ir::{
+        condcodes::IntCC, types, AbiParam, Function, InstBuilder, UserFuncName,
+    };
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{
+        code_generator::Generator,
+        coverage::{read_coverage_file, LineCoverage},
+        linker::{link_executable, LinkerOptions},
+    };
+
+    #[test]
+    fn test_coverage() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_coverage("anna.cov").unwrap();
+
+        let file_id = generator.source_map.add_source_file("main.anc");
+
+        // ```rust
+        // fn main() -> i32 {       // line 1
+        //     if 1 == 2 {          // line 2
+        //         return 11;       // line 3
+        //     }
+        //     13                   // line 5
+        // }
+        // ```
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block_0 = function_builder.create_block();
+        let block_1 = function_builder.create_block();
+        let block_2 = function_builder.create_block();
+
+        function_builder.switch_to_block(block_0);
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 2, 5);
+        let value_0 = function_builder.ins().iconst(types::I32, 1);
+        let value_1 = function_builder.ins().icmp_imm(IntCC::Equal, value_0, 2);
+        function_builder
+            .ins()
+            .brif(value_1, block_1, &[], block_2, &[]);
+
+        function_builder.switch_to_block(block_1);
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 3, 9);
+        let value_2 = function_builder.ins().iconst(types::I32, 11);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.switch_to_block(block_2);
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 5, 5);
+        let value_3 = function_builder.ins().iconst(types::I32, 13);
+        function_builder.ins().return_(&[value_3]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        // the report is generated after running, so keep the coverage
+        // information and the source map before finishing.
+        let coverage = generator.coverage.clone().unwrap();
+        let source_map = generator.source_map.clone();
+        assert_eq!(coverage.counters.len(), 3);
+
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        // link and run in a separate folder, since the coverage file
+        // is written to the current directory.
+        let folder = std::env::temp_dir().join(format!("anc_test_coverage_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let object_file_path = folder.join("anna.o");
+        let exec_file_path = folder.join("anna.elf");
+
+        let mut file = File::create(&object_file_path).unwrap();
+        file.write_all(&module_binary).unwrap();
+
+        link_executable(
+            &[object_file_path.to_str().unwrap()],
+            exec_file_path.to_str().unwrap(),
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+
+        let exit_code_opt = Command::new(&exec_file_path)
+            .current_dir(&folder)
+            .status()
+            .unwrap()
+            .code();
+        assert_eq!(exit_code_opt, Some(13));
+
+        let counts = read_coverage_file(folder.join("anna.cov")).unwrap();
+        assert_eq!(counts, vec![1, 0, 1]);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(
+            coverage.get_line_coverages(&counts, &source_map),
+            vec![
+                LineCoverage {
+                    file_id,
+                    line: 2,
+                    count: 1
+                },
+                LineCoverage {
+                    file_id,
+                    line: 3,
+                    count: 0
+                },
+                LineCoverage {
+                    file_id,
+                    line: 5,
+                    count: 1
+                },
+            ]
+        );
+
+        // the coverage data can not be defined when its name
+        // is declared by the user.
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_coverage("anna.cov").unwrap();
+
+        let func_sig = generator.module.make_signature();
+        generator
+            .module
+            .declare_function("__anna_coverage_file_path", Linkage::Import, &func_sig)
+            .unwrap();
+
+        assert!(generator.finish().is_err());
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    ir::{
+        types, AbiParam, FuncRef, Function, InstBuilder, Signature, StackSlotData, StackSlotKind,
+        UserFuncName, Value,
+    },
+    Context,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{FuncId, FuncOrDataId, Linkage, Module, ModuleError};
+
+use crate::{code_generator::Generator, validation::SymbolReferences};
+
+// The cycle counter
+// -----------------
+//
+// The generated code times itself by reading the cycle counter, i.e. the
+// instruction `(read_cycle_counter)`, or `emit_read()` for the front ends, e.g.
+//
+// ```rust
+// let cycle_counter_ref = generator.declare_cycle_counter_in_func(&mut func)?;
+// let mut function_builder = FunctionBuilder::new(&mut func, ...);
+// ...
+// let start = cycle_counter_ref.emit_read(&mut function_builder);
+// ```
+//
+// the counter is read by the local function `__anna_read_cycle_counter() -> i64`,
+// which is implemented by:
+//
+// - x86_64: `rdtsc`, the time-stamp counter.
+// - aarch64: `mrs x0, cntvct_el0`, the virtual counter, its frequency is
+//   in `cntfrq_el0` (which is not the frequency of the CPU).
+// - the other targets: `clock_gettime(CLOCK_MONOTONIC)` of libc in nanoseconds.
+//
+// the unit of the counter depends on the target, so only the differences
+// between the readings of the same target are meaningful. the instructions
+// are not serializing, i.e. the CPU may execute the nearby instructions out
+// of order around the reading.
+//
+// ref:
+// - https://www.felixcloutier.com/x86/rdtsc
+// - https://developer.arm.com/documentation/ddi0601/latest/AArch64-Registers/CNTVCT-EL0--Counter-timer-Virtual-Count-Register
+// - https://man7.org/linux/man-pages/man3/clock_gettime.3.html
+
+pub const CYCLE_COUNTER_FUNCTION_NAME: &str = "__anna_read_cycle_counter";
+
+/// The clock ID of `CLOCK_MONOTONIC` on Linux.
+const CLOCK_MONOTONIC: i64 = 1;
+
+/// The reference of the cycle counter function in a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CycleCounterRef {
+    pub read: FuncRef,
+}
+
+/// The machine code of `__anna_read_cycle_counter()`.
+fn get_cycle_counter_code(isa_name: &str) -> Option<&'static [u8]> {
+    match isa_name {
+        "x64" => Some(&[
+            0x0f, 0x31, // rdtsc
+            0x48, 0xc1, 0xe2, 0x20, // shl rdx, 32
+            0x48, 0x09, 0xd0, // or rax, rdx
+            0xc3, // ret
+        ]),
+        "aarch64" => Some(&[
+            0x40, 0xe0, 0x3b, 0xd5, // mrs x0, cntvct_el0
+            0xc0, 0x03, 0x5f, 0xd6, // ret
+        ]),
+        _ => None,
+    }
+}
+
+/// Declare (and define, once) the function `__anna_read_cycle_counter() -> i64`.
+pub(crate) fn declare_cycle_counter_function<T: Module>(
+    module: &mut T,
+) -> Result<FuncId, ModuleError> {
+    if let Some(FuncOrDataId::Func(func_id)) = module.get_name(CYCLE_COUNTER_FUNCTION_NAME) {
+        return Ok(func_id);
+    }
+
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let func_id = module.declare_function(CYCLE_COUNTER_FUNCTION_NAME, Linkage::Local, &sig)?;
+
+    match get_cycle_counter_code(module.isa().name()) {
+        Some(code) => {
+            let func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+            module.define_function_bytes(func_id, &func, 16, code, &[])?;
+        }
+        None => define_clock_gettime_function(module, func_id, sig)?,
+    }
+    Ok(func_id)
+}
+
+/// Define the fallback of the cycle counter function, i.e.
+/// `clock_gettime(CLOCK_MONOTONIC, &ts)` and returns `ts.tv_sec * 10^9 + ts.tv_nsec`.
+fn define_clock_gettime_function<T: Module>(
+    module: &mut T,
+    func_id: FuncId,
+    sig: Signature,
+) -> Result<(), ModuleError> {
+    let pointer_type = module.isa().pointer_type();
+    let mut clock_gettime_sig = module.make_signature();
+    clock_gettime_sig.params.push(AbiParam::new(types::I32));
+    clock_gettime_sig.params.push(AbiParam::new(pointer_type));
+    clock_gettime_sig.returns.push(AbiParam::new(types::I32));
+    let clock_gettime_id =
+        module.declare_function("clock_gettime", Linkage::Import, &clock_gettime_sig)?;
+
+    let mut func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let clock_gettime = module.declare_func_in_func(clock_gettime_id, &mut func);
+
+    let mut function_builder_context = FunctionBuilderContext::new();
+    let mut function_builder = FunctionBuilder::new(&mut func, &mut function_builder_context);
+    let block = function_builder.create_block();
+    function_builder.switch_to_block(block);
+    function_builder.seal_block(block);
+
+    // struct timespec { time_t tv_sec; long tv_nsec; }
+    let stack_slot = function_builder.create_sized_stack_slot(StackSlotData::new(
+        StackSlotKind::ExplicitSlot,
+        16,
+        3,
+    ));
+    let timespec = function_builder
+        .ins()
+        .stack_addr(pointer_type, stack_slot, 0);
+    let clock_id = function_builder.ins().iconst(types::I32, CLOCK_MONOTONIC);
+    function_builder
+        .ins()
+        .call(clock_gettime, &[clock_id, timespec]);
+
+    let seconds = function_builder.ins().stack_load(types::I64, stack_slot, 0);
+    let nanoseconds = function_builder.ins().stack_load(types::I64, stack_slot, 8);
+    let value = function_builder.ins().imul_imm(seconds, 1_000_000_000);
+    let value = function_builder.ins().iadd(value, nanoseconds);
+    function_builder.ins().return_(&[value]);
+    function_builder.finalize();
+
+    let mut context = Context::for_function(func);
+    module.define_function(func_id, &mut context)
+}
+
+/// Read the cycle counter by calling the function, returns an i64 value.
+pub(crate) fn emit_read_cycle_counter(
+    function_builder: &mut FunctionBuilder,
+    func_ref: FuncRef,
+) -> Value {
+    let call = function_builder.ins().call(func_ref, &[]);
+    function_builder.inst_results(call)[0]
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Declare the cycle counter function in the function.
+    pub fn declare_cycle_counter_in_func(
+        &mut self,
+        func: &mut Function,
+    ) -> Result<CycleCounterRef, ModuleError> {
+        let func_id = declare_cycle_counter_function(&mut self.module)?;
+        self.record_cycle_counter_function();
+
+        Ok(CycleCounterRef {
+            read: self.module.declare_func_in_func(func_id, func),
+        })
+    }
+
+    /// Record the definition of the cycle counter function for `validate()`.
+    pub(crate) fn record_cycle_counter_function(&mut self) {
+        let Some(FuncOrDataId::Func(func_id)) = self.module.get_name(CYCLE_COUNTER_FUNCTION_NAME)
+        else {
+            return;
+        };
+
+        // the fallback refers to `clock_gettime()`
+        let functions = match self.module.get_name("clock_gettime") {
+            Some(FuncOrDataId::Func(clock_gettime_id))
+                if get_cycle_counter_code(self.module.isa().name()).is_none() =>
+            {
+                let signature = self
+                    .module
+                    .declarations()
+                    .get_function_decl(clock_gettime_id)
+                    .signature
+                    .clone();
+                vec![(clock_gettime_id, Some(signature))]
+            }
+            _ => vec![],
+        };
+
+        self.set_symbol_references(SymbolReferences {
+            definition: FuncOrDataId::Func(func_id),
+            functions,
+            data: vec![],
+        });
+    }
+}
+
+impl CycleCounterRef {
+    /// Read the cycle counter, returns an i64 value.
+    pub fn emit_read(&self, function_builder: &mut FunctionBuilder) -> Value {
+        emit_read_cycle_counter(function_builder, self.read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, time::Duration};
+
+    use cranelift_codegen::ir::{types, AbiParam};
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        cycle_counter::define_clock_gettime_function,
+        linker::{link_executable, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    const SOURCE: &str = r#"
+    (module $timing
+        (function $read export (result i64)
+            (code (read_cycle_counter)))
+        (function $main export (result i32) (local $start i64)
+            (code
+                (local_store $start (read_cycle_counter))
+                (ge_i64_s
+                    (sub_i64 (read_cycle_counter) (local_load $start))
+                    (imm_i64 0)))))
+    "#;
+
+    #[test]
+    fn test_cycle_counter() {
+        let module = parse_module(SOURCE).unwrap();
+
+        // JIT
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        assert!(generator.validate().is_ok());
+        generator.module.finalize_definitions().unwrap();
+
+        let func_read = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn() -> i64>(
+                generator
+                    .module
+                    .get_finalized_function(assembled_module.get_function_id("read").unwrap()),
+            )
+        };
+        let first = func_read();
+        let second = func_read();
+        assert!(first > 0);
+        assert!(second >= first);
+
+        // object file, the readings share the function
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        assert!(generator.validate().is_ok());
+
+        let temp_folder = TempFolder::new("cycle_counter");
+        let object_file_path = temp_folder.file_path("timing.o");
+        let exec_file_path = temp_folder.file_path("timing.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(1));
+
+        // the fallback, in nanoseconds
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let mut sig = generator.module.make_signature();
+        sig.returns.push(AbiParam::new(types::I64));
+        let func_id = generator
+            .module
+            .declare_function("fallback", Linkage::Local, &sig)
+            .unwrap();
+        define_clock_gettime_function(&mut generator.module, func_id, sig).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let func_fallback = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn() -> i64>(
+                generator.module.get_finalized_function(func_id),
+            )
+        };
+        let first = func_fallback();
+        std::thread::sleep(Duration::from_millis(10));
+        let elapsed = func_fallback() - first;
+        assert!((10_000_000..10_000_000_000).contains(&elapsed));
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::Endianness;
+
+// The data bytes of the Rust values
+// ---------------------------------
+//
+// The Rust values are converted into the content of the data objects
+// in the endianness of the target, e.g.
+//
+// ```rust
+// let data_id = generator.define_initialized_data_from(
+//     "table", &[1u32, 2, 3, 5, 8], false, false, false)?;
+// ```
+//
+// the values:
+//
+// - the integers and the floating-point numbers.
+// - the arrays, the slices and the vectors of the values.
+// - the tuples (up to 6 elements) are laid out as the `#[repr(C)]` structs,
+//   i.e. the fields are padded to their alignment, and the size is a multiple
+//   of the alignment of the tuple, e.g. `(u8, u32)` occupies 8 bytes.
+// - the `#[repr(C)]` structs which implement `bytemuck::Pod` (with the feature
+//   `bytemuck`), wrapped by `PodData`.
+//
+// note that the alignment of the numbers is their size, e.g. the alignment
+// of `u64` is 8 even if the target is 32-bit.
+
+pub trait ToDataBytes {
+    /// The alignment of the value in the data object.
+    const DATA_ALIGN: u64;
+
+    /// Append the bytes of the value to `data` in the given endianness.
+    fn write_data_bytes(&self, endianness: Endianness, data: &mut Vec<u8>);
+}
+
+macro_rules! impl_to_data_bytes_for_number {
+    ($($t:ty),*) => {
+        $(
+            impl ToDataBytes for $t {
+                const DATA_ALIGN: u64 = std::mem::size_of::<$t>() as u64;
+
+                fn write_data_bytes(&self, endianness: Endianness, data: &mut Vec<u8>) {
+                    match endianness {
+                        Endianness::Little => data.extend_from_slice(&self.to_le_bytes()),
+                        Endianness::Big => data.extend_from_slice(&self.to_be_bytes()),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_to_data_bytes_for_number!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, f32, f64);
+
+impl<T: ToDataBytes> ToDataBytes for [T] {
+    const DATA_ALIGN: u64 = T::DATA_ALIGN;
+
+    fn write_data_bytes(&self, endianness: Endianness, data: &mut Vec<u8>) {
+        for item in self {
+            item.write_data_bytes(endianness, data);
+        }
+    }
+}
+
+impl<T: ToDataBytes, const N: usize> ToDataBytes for [T; N] {
+    const DATA_ALIGN: u64 = T::DATA_ALIGN;
+
+    fn write_data_bytes(&self, endianness: Endianness, data: &mut Vec<u8>) {
+        self.as_slice().write_data_bytes(endianness, data);
+    }
+}
+
+impl<T: ToDataBytes> ToDataBytes for Vec<T> {
+    const DATA_ALIGN: u64 = T::DATA_ALIGN;
+
+    fn write_data_bytes(&self, endianness: Endianness, data: &mut Vec<u8>) {
+        self.as_slice().write_data_bytes(endianness, data);
+    }
+}
+
+/// Append the zero bytes until the length of `data` (which starts at
+/// `start`) is a multiple of `align`.
+fn pad_data_bytes(data: &mut Vec<u8>, start: usize, align: u64) {
+    let length = data.len() - start;
+    let padded_length = length.next_multiple_of(align as usize);
+    data.resize(start + padded_length, 0);
+}
+
+const fn max_align(aligns: &[u64]) -> u64 {
+    let mut align = 1;
+    let mut index = 0;
+    while index < aligns.len() {
+        if aligns[index] > align {
+            align = aligns[index];
+        }
+        index += 1;
+    }
+    align
+}
+
+macro_rules! impl_to_data_bytes_for_tuple {
+    ($($t:ident $index:tt),*) => {
+        impl<$($t: ToDataBytes),*> ToDataBytes for ($($t,)*) {
+            const DATA_ALIGN: u64 = max_align(&[$($t::DATA_ALIGN),*]);
+
+            fn write_data_bytes(&self, endianness: Endianness, data: &mut Vec<u8>) {
+                let start = data.len();
+                $(
+                    pad_data_bytes(data, start, $t::DATA_ALIGN);
+                    self.$index.write_data_bytes(endianness, data);
+                )*
+                pad_data_bytes(data, start, Self::DATA_ALIGN);
+            }
+        }
+    };
+}
+
+impl_to_data_bytes_for_tuple!(A 0, B 1);
+impl_to_data_bytes_for_tuple!(A 0, B 1, C 2);
+impl_to_data_bytes_for_tuple!(A 0, B 1, C 2, D 3);
+impl_to_data_bytes_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_to_data_bytes_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// The `#[repr(C)]` struct which implements `bytemuck::Pod`, its bytes
+/// are copied as they are in the memory of the host.
+///
+/// # Panics
+///
+/// The bytes are in the endianness of the host, so writing them for
+/// a target with the different endianness panics, use the tuples instead
+/// for the cross-compiling.
+#[cfg(feature = "bytemuck")]
+pub struct PodData<T: bytemuck::Pod>(pub T);
+
+#[cfg(feature = "bytemuck")]
+impl<T: bytemuck::Pod> ToDataBytes for PodData<T> {
+    const DATA_ALIGN: u64 = std::mem::align_of::<T>() as u64;
+
+    fn write_data_bytes(&self, endianness: Endianness, data: &mut Vec<u8>) {
+        let host_endianness = if cfg!(target_endian = "little") {
+            Endianness::Little
+        } else {
+            Endianness::Big
+        };
+        assert!(
+            endianness == host_endianness,
+            "the endianness of the target differs from the host"
+        );
+        data.extend_from_slice(bytemuck::bytes_of(&self.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::Endianness;
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::{DataDefinition, Generator},
+        data_bytes::ToDataBytes,
+    };
+
+    fn to_bytes<V: ToDataBytes + ?Sized>(value: &V, endianness: Endianness) -> Vec<u8> {
+        let mut data = vec![];
+        value.write_data_bytes(endianness, &mut data);
+        data
+    }
+
+    #[test]
+    fn test_data_bytes() {
+        assert_eq!(to_bytes(&0x1122u16, Endianness::Little), vec![0x22, 0x11]);
+        assert_eq!(to_bytes(&0x1122u16, Endianness::Big), vec![0x11, 0x22]);
+        assert_eq!(
+            to_bytes(&1.0f32, Endianness::Big),
+            vec![0x3f, 0x80, 0x00, 0x00]
+        );
+        assert_eq!(
+            to_bytes(&[1i16, -1], Endianness::Little),
+            vec![0x01, 0x00, 0xff, 0xff]
+        );
+        assert_eq!(<[i16; 2]>::DATA_ALIGN, 2);
+
+        // `#[repr(C)] struct { a: u8, b: u32, c: u16 }`
+        let value = (0x11u8, 0x22334455u32, 0x6677u16);
+        assert_eq!(<(u8, u32, u16)>::DATA_ALIGN, 4);
+        assert_eq!(
+            to_bytes(&value, Endianness::Big),
+            vec![0x11, 0, 0, 0, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0, 0]
+        );
+
+        // the tuples are padded in the array
+        assert_eq!(
+            to_bytes(&vec![(1u16, 2u8), (3u16, 4u8)], Endianness::Little),
+            vec![1, 0, 2, 0, 3, 0, 4, 0]
+        );
+
+        let mut generator = Generator::<ObjectModule>::new("app", None);
+        let data_id = generator
+            .define_initialized_data_from("table", &[1u64, 2], false, false, false)
+            .unwrap();
+        assert_eq!(
+            generator.data_definitions,
+            vec![(
+                data_id,
+                DataDefinition::Initialized {
+                    data: to_bytes(&[1u64, 2], Endianness::Little),
+                    align: 8
+                }
+            )]
+        );
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::HashSet;
+
+use cranelift_codegen::ir::{ExternalName, Function, GlobalValueData, InstructionData};
+use cranelift_module::{DataId, FuncId, FuncOrDataId, Linkage, Module};
+
+use crate::code_generator::{DataDefinition, Generator};
+
+// The dead code elimination
+// -------------------------
+//
+// The front ends may generate speculative helper functions and data which are
+// never used, `Generator::eliminate_dead_code()` is an optional pass over the
+// collected functions and data (i.e. before calling `define_function()` and
+// `define_data_content()`), it removes the ones which are unreachable, so they
+// are neither compiled nor emitted.
+//
+// the reachability is tracked from the roots:
+//
+// - the functions and data with non-local linkage (i.e. `Export`, `Preemptible`
+//   and `Hidden`), since they can be referenced by the other objects.
+// - the names listed in `DeadCodeOptions::keep_names`.
+//
+// a function references the functions and data which are imported into it,
+// i.e. the `ext_funcs` which are used (by `call` and `func_addr`) and the symbol global values
+// (by `symbol_value` and `tls_value`), the unused calls of the `pure` and
+// `readonly` functions (see `function_attribute.rs`) are removed first, so
+// their callees may be unreachable.
+//
+// Note that the removed functions and data are still declared in the module,
+// they just have no definitions.
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeadCodeOptions {
+    /// Keep all functions and data, i.e. disable the elimination.
+    pub keep_all: bool,
+
+    /// The names of the extra roots, e.g. the local functions which
+    /// are looked up by the JIT host.
+    pub keep_names: Vec<String>,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Remove the unreachable functions and data from the given collections.
+    ///
+    /// Returns the number of the removed functions and data.
+    pub fn eliminate_dead_code(
+        &self,
+        functions: &mut Vec<(FuncId, Function)>,
+        data_definitions: &mut Vec<(DataId, DataDefinition)>,
+        options: &DeadCodeOptions,
+    ) -> usize {
+        if options.keep_all {
+            return 0;
+        }
+
+        for (_, func) in functions.iter_mut() {
+            self.remove_unused_calls(func);
+        }
+
+        let declarations = self.module.declarations();
+
+        let mut reachable_funcs = HashSet::new();
+        let mut reachable_data = HashSet::new();
+        let mut pending_funcs = vec![];
+
+        let mut mark = |id: FuncOrDataId, pending_funcs: &mut Vec<FuncId>| match id {
+            FuncOrDataId::Func(func_id) => {
+                if reachable_funcs.insert(func_id) {
+                    pending_funcs.push(func_id);
+                }
+            }
+            FuncOrDataId::Data(data_id) => {
+                reachable_data.insert(data_id);
+            }
+        };
+
+        for (func_id, _) in functions.iter() {
+            if declarations.get_function_decl(*func_id).linkage != Linkage::Local {
+                mark(FuncOrDataId::Func(*func_id), &mut pending_funcs);
+            }
+        }
+
+        for (data_id, _) in data_definitions.iter() {
+            if declarations.get_data_decl(*data_id).linkage != Linkage::Local {
+                mark(FuncOrDataId::Data(*data_id), &mut pending_funcs);
+            }
+        }
+
+        for name in &options.keep_names {
+            if let Some(id) = self.module.get_name(name) {
+                mark(id, &mut pending_funcs);
+            }
+        }
+
+        while let Some(func_id) = pending_funcs.pop() {
+            let Some((_, func)) = functions.iter().find(|(id, _)| *id == func_id) else {
+                // declared only, e.g. an imported function.
+                continue;
+            };
+
+            for id in get_referenced_ids(func) {
+                mark(id, &mut pending_funcs);
+            }
+        }
+
+        let count = functions.len() + data_definitions.len();
+
+        functions.retain(|(func_id, _)| reachable_funcs.contains(func_id));
+        data_definitions.retain(|(data_id, _)| reachable_data.contains(data_id));
+
+        count - functions.len() - data_definitions.len()
+    }
+}
+
+/// Get the functions and data which are referenced by the function, the
+/// imported functions which are no longer used (e.g. the removed calls) are excluded.
+fn get_referenced_ids(func: &Function) -> Vec<FuncOrDataId> {
+    let used_func_refs =
+        func.layout
+            .blocks()
+            .flat_map(|block| func.layout.block_insts(block))
+            .filter_map(|inst| match func.dfg.insts[inst] {
+                InstructionData::Call { func_ref, .. }
+                | InstructionData::FuncAddr { func_ref, .. } => Some(func_ref),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+    let func_names = func
+        .dfg
+        .ext_funcs
+        .iter()
+        .filter(|(func_ref, _)| used_func_refs.contains(func_ref))
+        .map(|(_, ext_func)| &ext_func.name);
+    let data_names = func
+        .global_values
+        .values()
+        .filter_map(|global_value| match global_value {
+            GlobalValueData::Symbol { name, .. } => Some(name),
+            _ => None,
+        });
+
+    func_names
+        .chain(data_names)
+        .filter_map(|name| {
+            let ExternalName::User(name_ref) = name else {
+                return None;
+            };
+
+            let name = &func.params.user_named_funcs()[*name_ref];
+            match name.namespace {
+                0 => Some(FuncOrDataId::Func(FuncId::from_u32(name.index))),
+                1 => Some(FuncOrDataId::Data(DataId::from_u32(name.index))),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, MemFlags, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{
+        code_generator::{DataDefinition, Generator},
+        dead_code::DeadCodeOptions,
+        test_support::run_executable_binary_and_get_exit_code,
+    };
+
+    fn build_module(options: &DeadCodeOptions) -> (usize, Vec<u8>) {
+        // ```rust
+        // static NUMBER: i32 = 13;           // local, used by "get_number"
+        // static UNUSED_NUMBER: i32 = 17;    // local, unused
+        // fn get_number() -> i32 {           // local, used by "main"
+        //     NUMBER
+        // }
+        // fn unused_helper() -> i32 {        // local, unused
+        //     UNUSED_NUMBER
+        // }
+        // fn main() -> i32 {
+        //     get_number()
+        // }
+        // ```
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let mut func_sig = generator.module.make_signature();
+        func_sig.returns.push(AbiParam::new(types::I32));
+
+        let mut functions = vec![];
+        let mut data_definitions = vec![];
+
+        for (func_name, data_name, number) in [
+            ("get_number", "number", 13i32),
+            ("unused_helper", "unused_number", 17i32),
+        ] {
+            let data_id = generator
+                .module
+                .declare_data(data_name, Linkage::Local, false, false)
+                .unwrap();
+            data_definitions.push((
+                data_id,
+                DataDefinition::Initialized {
+                    data: number.to_le_bytes().to_vec(),
+                    align: 4,
+                },
+            ));
+
+            let func_id = generator
+                .module
+                .declare_function(func_name, Linkage::Local, &func_sig)
+                .unwrap();
+
+            let mut func = Function::with_name_signature(
+                UserFuncName::user(0, func_id.as_u32()),
+                func_sig.clone(),
+            );
+
+            let data_gv = generator.module.declare_data_in_func(data_id, &mut func);
+
+            let mut function_builder =
+                FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+            let block = function_builder.create_block();
+            function_builder.switch_to_block(block);
+
+            let value_0 = function_builder.ins().symbol_value(pointer_type, data_gv);
+            let value_1 = function_builder
+                .ins()
+                .load(types::I32, MemFlags::new(), value_0, 0);
+            function_builder.ins().return_(&[value_1]);
+
+            function_builder.seal_all_blocks();
+            function_builder.finalize();
+
+            functions.push((func_id, func));
+        }
+
+        let func_get_number_id = functions[0].0;
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_sig)
+            .unwrap();
+
+        let mut func_main =
+            Function::with_name_signature(UserFuncName::user(0, func_main_id.as_u32()), func_sig);
+
+        let func_get_number_ref = generator
+            .module
+            .declare_func_in_func(func_get_number_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let call_0 = function_builder.ins().call(func_get_number_ref, &[]);
+        let value_0 = function_builder.inst_results(call_0)[0];
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        functions.push((func_main_id, func_main));
+
+        let count = generator.eliminate_dead_code(&mut functions, &mut data_definitions, options);
+
+        for (data_id, data_definition) in data_definitions {
+            generator
+                .define_data_content(data_id, data_definition)
+                .unwrap();
+        }
+
+        for (func_id, func) in functions {
+            generator.define_function(func_id, func).unwrap();
+        }
+
+        assert_eq!(generator.clif_functions.len(), 3 - count / 2);
+
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+        (count, module_binary)
+    }
+
+    #[test]
+    fn test_eliminate_dead_code() {
+        let (count, module_binary) = build_module(&DeadCodeOptions::default());
+        assert_eq!(count, 2);
+
+        let exit_code_opt = run_executable_binary_and_get_exit_code(
+            &module_binary,
+            "test_eliminate_dead_code",
+            false,
+        );
+        assert_eq!(exit_code_opt, Some(13));
+
+        // keep the extra root
+        let (count, _) = build_module(&DeadCodeOptions {
+            keep_all: false,
+            keep_names: vec!["unused_helper".to_owned()],
+        });
+        assert_eq!(count, 0);
+
+        // keep everything
+        let (count, module_binary_keep_all) = build_module(&DeadCodeOptions {
+            keep_all: true,
+            keep_names: vec![],
+        });
+        assert_eq!(count, 0);
+        assert!(module_binary_keep_all.len() > module_binary.len());
+    }
+}
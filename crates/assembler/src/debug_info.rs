@@ -0,0 +1,1099 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    ir::{types, StackSlot, Type, Value, ValueLabel},
+    isa::TargetIsa,
+    CompiledCode, LabelValueLoc,
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::FuncId;
+use cranelift_object::{
+    object::{
+        write::{Object, Relocation, SectionId as ObjectSectionId, SymbolId},
+        RelocationEncoding, RelocationFlags, RelocationKind, SectionKind, SymbolScope,
+    },
+    ObjectProduct,
+};
+use gimli::{
+    write::{
+        Address, AttributeValue, DwarfUnit, EndianVec, Expression, LineProgram, LineString,
+        Location, LocationList, Range, RangeList, Sections, Writer,
+    },
+    Encoding, Format, LineEncoding, Register, RunTimeEndian, SectionId,
+};
+
+use crate::{producer::get_producer, source_location::SourceMap};
+
+// Documents of DWARF
+//
+// - DWARF Debugging Information Format Version 4: https://dwarfstd.org/doc/DWARF4.pdf
+// - gimli (write): https://docs.rs/gimli/latest/gimli/write/index.html
+// - the demo of generating DWARF with gimli:
+//   https://github.com/gimli-rs/gimli/blob/master/crates/examples/src/bin/simple_write.rs
+//
+// the sections generated:
+//
+// - .debug_abbrev   the abbreviations (the "schema") of the DIEs
+// - .debug_info     the DIEs (compile unit, functions, parameters and types)
+// - .debug_line     the line number program, i.e. the mapping of code address -> source line
+// - .debug_str      the strings which are referenced by the DIEs
+// - .debug_ranges   the code ranges of the compile unit
+// - .debug_loc      the location lists of the local variables
+//
+// the locations of local variables
+// --------------------------------
+//
+// - the variables stored in the stack slots: the address is "SP + offset of slot",
+//   because the stack pointer is not changed after the function prologue
+//   (the outgoing arguments area is allocated in the prologue too).
+// - the SSA values (which are labeled by `ValueLabel`): the register or the
+//   stack location (CFA + offset) of the value vary across the function, so they
+//   are described by location lists, the lists come from the register allocator.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParameterDebugInfo {
+    pub name: String,
+    pub value_type: Type,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalVariableLocation {
+    /// The variable is stored in a stack slot.
+    ///
+    /// - `offset`: the offset of the variable within the stack slot.
+    StackSlot { stack_slot: StackSlot, offset: u32 },
+
+    /// The variable is an SSA value (or a frontend `Variable`) which is
+    /// labeled by `DebugInfo::set_value_label()`.
+    ValueLabel(ValueLabel),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalVariableDebugInfo {
+    pub name: String,
+    pub value_type: Type,
+    pub location: LocalVariableLocation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueLocation {
+    /// the DWARF register number.
+    Register(u16),
+
+    /// the offset from the CFA (Canonical Frame Address).
+    CfaOffset(i64),
+}
+
+/// The location of a labeled value within a range of the machine code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueLocationRange {
+    pub start: u32,
+    pub end: u32,
+    pub location: ValueLocation,
+}
+
+/// The frame layout of a compiled function, it is collected by
+/// `Generator::define_function()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionFrameDebugInfo {
+    /// the offsets of the stack slots, relative to the stack pointer (SP)
+    /// after the function prologue.
+    pub stack_slot_offsets: Vec<u32>,
+
+    pub value_label_ranges: Vec<(ValueLabel, Vec<ValueLocationRange>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDebugInfo {
+    pub func_id: FuncId,
+    pub name: String,
+    pub file_id: u32,
+    pub line: u32,
+    pub params: Vec<ParameterDebugInfo>,
+    pub returns: Vec<Type>,
+    pub locals: Vec<LocalVariableDebugInfo>,
+    pub frame: FunctionFrameDebugInfo,
+}
+
+/// Collecting the debug information of a module, and writing
+/// them as DWARF sections into the object file.
+///
+/// The source files and the line mappings come from the `SourceMap`
+/// of the generator.
+pub struct DebugInfo {
+    /// the name of the compile unit, it is usually the path of the main source file.
+    pub name: String,
+
+    /// the current working directory of compilation.
+    pub comp_dir: String,
+
+    /// the value of attribute `DW_AT_producer`.
+    pub producer: String,
+
+    address_size: u8,
+    endian: RunTimeEndian,
+
+    /// the DWARF register number of the stack pointer.
+    sp_register: Option<u16>,
+    functions: Vec<FunctionDebugInfo>,
+
+    /// the path prefixes to be replaced, e.g. `("/home/yang/project", ".")`,
+    /// see `add_path_prefix_map()`.
+    path_prefix_map: Vec<(String, String)>,
+}
+
+impl DebugInfo {
+    pub fn new(isa: &dyn TargetIsa, name: &str, comp_dir: &str) -> Self {
+        let endian = get_endian(isa);
+
+        // ref:
+        // - System V AMD64 ABI, "DWARF Register Number Mapping"
+        // - DWARF for the Arm 64-bit Architecture (AArch64)
+        // - RISC-V ELF psABI, "DWARF Register Numbers"
+        let sp_register = match isa.name() {
+            "x64" => Some(7),
+            "aarch64" => Some(31),
+            "riscv64" => Some(2),
+            "s390x" => Some(15),
+            _ => None,
+        };
+
+        Self {
+            name: name.to_owned(),
+            comp_dir: comp_dir.to_owned(),
+            producer: get_producer(isa),
+            address_size: isa.pointer_bytes(),
+            endian,
+            sp_register,
+            functions: vec![],
+            path_prefix_map: vec![],
+        }
+    }
+
+    /// Replace the prefix `from` of the paths (the compilation directory,
+    /// the name of the compile unit and the source files) with `to` when
+    /// writing the DWARF sections, it is similar to the option
+    /// `-fdebug-prefix-map=old=new` of GCC.
+    ///
+    /// It is used to remove the host-dependent paths from the object file.
+    pub fn add_path_prefix_map(&mut self, from: &str, to: &str) {
+        self.path_prefix_map.push((from.to_owned(), to.to_owned()));
+    }
+
+    /// Apply the path prefix map, the first matched prefix wins.
+    pub fn remap_path(&self, path: &str) -> String {
+        for (from, to) in &self.path_prefix_map {
+            if let Some(remain) = path.strip_prefix(from.as_str()) {
+                if remain.is_empty() {
+                    return to.to_owned();
+                }
+
+                if let Some(relative) = remain.strip_prefix('/') {
+                    return format!("{}/{}", to, relative);
+                }
+            }
+        }
+
+        path.to_owned()
+    }
+
+    pub fn declare_function(
+        &mut self,
+        func_id: FuncId,
+        name: &str,
+        file_id: u32,
+        line: u32,
+        params: Vec<ParameterDebugInfo>,
+        returns: Vec<Type>,
+    ) {
+        self.functions.push(FunctionDebugInfo {
+            func_id,
+            name: name.to_owned(),
+            file_id,
+            line,
+            params,
+            returns,
+            locals: vec![],
+            frame: FunctionFrameDebugInfo::default(),
+        });
+    }
+
+    /// Declare a named local variable of the function, the function should be
+    /// declared by `declare_function()` first.
+    pub fn declare_local_variable(
+        &mut self,
+        func_id: FuncId,
+        name: &str,
+        value_type: Type,
+        location: LocalVariableLocation,
+    ) {
+        if let Some(function) = self
+            .functions
+            .iter_mut()
+            .find(|item| item.func_id == func_id)
+        {
+            function.locals.push(LocalVariableDebugInfo {
+                name: name.to_owned(),
+                value_type,
+                location,
+            });
+        }
+    }
+
+    /// Label the SSA value, so that its locations can be tracked.
+    ///
+    /// It should be called each time a new value is assigned to the variable, e.g.
+    ///
+    /// ```text
+    /// function_builder.def_var(x, value);
+    /// DebugInfo::set_value_label(&mut function_builder, value, label_x);
+    /// ```
+    pub fn set_value_label(
+        function_builder: &mut FunctionBuilder,
+        value: Value,
+        label: ValueLabel,
+    ) {
+        function_builder.func.dfg.collect_debug_info();
+        function_builder.set_val_label(value, label);
+    }
+
+    pub(crate) fn set_function_frame(
+        &mut self,
+        func_id: FuncId,
+        isa: &dyn TargetIsa,
+        compiled_code: &CompiledCode,
+        entry_offset: u32,
+    ) {
+        let Some(function) = self
+            .functions
+            .iter_mut()
+            .find(|item| item.func_id == func_id)
+        else {
+            return;
+        };
+
+        let stack_slot_offsets = compiled_code
+            .sized_stackslot_offsets
+            .values()
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut value_label_ranges = compiled_code
+            .value_labels_ranges
+            .iter()
+            .map(|(label, ranges)| {
+                let location_ranges = ranges
+                    .iter()
+                    .filter_map(|range| {
+                        let location = match range.loc {
+                            LabelValueLoc::Reg(reg) => {
+                                ValueLocation::Register(isa.map_regalloc_reg_to_dwarf(reg).ok()?)
+                            }
+                            LabelValueLoc::CFAOffset(offset) => ValueLocation::CfaOffset(offset),
+                        };
+
+                        Some(ValueLocationRange {
+                            start: range.start + entry_offset,
+                            end: range.end + entry_offset,
+                            location,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                (*label, location_ranges)
+            })
+            .collect::<Vec<_>>();
+
+        value_label_ranges.sort_by_key(|(label, _)| label.as_u32());
+
+        function.frame = FunctionFrameDebugInfo {
+            stack_slot_offsets,
+            value_label_ranges,
+        };
+    }
+
+    pub fn get_function(&self, func_id: FuncId) -> Option<&FunctionDebugInfo> {
+        self.functions.iter().find(|item| item.func_id == func_id)
+    }
+
+    /// Generate the DWARF sections and append them to the object.
+    ///
+    /// Note that all functions should be defined before calling this method.
+    pub fn write_to_object(
+        &self,
+        product: &mut ObjectProduct,
+        source_map: &SourceMap,
+    ) -> gimli::write::Result<()> {
+        // only the defined functions can be described
+        let functions = self
+            .functions
+            .iter()
+            .filter(|item| matches!(product.functions[item.func_id], Some((_, true))))
+            .collect::<Vec<_>>();
+
+        // the relocation targets of `Address::Symbol { symbol, .. }`,
+        // i.e. the `symbol` is the index of this list.
+        let symbols = functions
+            .iter()
+            .map(|item| product.function_symbol(item.func_id))
+            .collect::<Vec<_>>();
+
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: self.address_size,
+        };
+
+        let mut dwarf = DwarfUnit::new(encoding);
+
+        let comp_dir_path = self.remap_path(&self.comp_dir);
+        let comp_name_path = self.remap_path(&self.name);
+
+        let comp_dir = LineString::new(comp_dir_path.as_bytes(), encoding, &mut dwarf.line_strings);
+        let comp_name =
+            LineString::new(comp_name_path.as_bytes(), encoding, &mut dwarf.line_strings);
+        dwarf.unit.line_program =
+            LineProgram::new(encoding, LineEncoding::default(), comp_dir, comp_name, None);
+
+        let file_ids = source_map
+            .get_source_files()
+            .iter()
+            .map(|path| {
+                let line_program = &mut dwarf.unit.line_program;
+                let directory = line_program.default_directory();
+                let file_name = LineString::new(
+                    self.remap_path(path).as_bytes(),
+                    encoding,
+                    &mut dwarf.line_strings,
+                );
+                line_program.add_file(file_name, directory, None)
+            })
+            .collect::<Vec<_>>();
+
+        // the compile unit
+        let root_id = dwarf.unit.root();
+        let producer_id = dwarf.strings.add(self.producer.as_bytes());
+        let name_id = dwarf.strings.add(comp_name_path.as_bytes());
+        let comp_dir_id = dwarf.strings.add(comp_dir_path.as_bytes());
+
+        let range_list = RangeList(
+            symbols
+                .iter()
+                .enumerate()
+                .map(|(idx, symbol_id)| Range::StartLength {
+                    begin: Address::Symbol {
+                        symbol: idx,
+                        addend: 0,
+                    },
+                    length: product.object.symbol(*symbol_id).size,
+                })
+                .collect(),
+        );
+        let range_list_id = dwarf.unit.ranges.add(range_list);
+
+        let root = dwarf.unit.get_mut(root_id);
+        root.set(
+            gimli::DW_AT_producer,
+            AttributeValue::StringRef(producer_id),
+        );
+        root.set(gimli::DW_AT_name, AttributeValue::StringRef(name_id));
+        root.set(
+            gimli::DW_AT_comp_dir,
+            AttributeValue::StringRef(comp_dir_id),
+        );
+        root.set(
+            gimli::DW_AT_language,
+            AttributeValue::Language(gimli::DW_LANG_Mips_Assembler),
+        );
+        root.set(
+            gimli::DW_AT_low_pc,
+            AttributeValue::Address(Address::Constant(0)),
+        );
+        root.set(
+            gimli::DW_AT_ranges,
+            AttributeValue::RangeListRef(range_list_id),
+        );
+
+        // the base types
+        let mut base_types = vec![];
+
+        for (idx, function) in functions.iter().enumerate() {
+            let symbol = &product.object.symbol(symbols[idx]);
+            let function_size = symbol.size;
+            let is_external = matches!(symbol.scope, SymbolScope::Linkage | SymbolScope::Dynamic);
+
+            // the subprogram DIE
+            let subprogram_id = dwarf.unit.add(root_id, gimli::DW_TAG_subprogram);
+
+            let return_type_id = if let [return_type] = function.returns[..] {
+                Some(get_or_add_base_type(
+                    &mut dwarf,
+                    &mut base_types,
+                    return_type,
+                ))
+            } else {
+                None
+            };
+
+            let param_type_ids = function
+                .params
+                .iter()
+                .map(|param| get_or_add_base_type(&mut dwarf, &mut base_types, param.value_type))
+                .collect::<Vec<_>>();
+
+            let function_name_id = dwarf.strings.add(function.name.as_bytes());
+            let file_id = file_ids.get(function.file_id as usize).copied();
+
+            let subprogram = dwarf.unit.get_mut(subprogram_id);
+            subprogram.set(
+                gimli::DW_AT_name,
+                AttributeValue::StringRef(function_name_id),
+            );
+            subprogram.set(gimli::DW_AT_external, AttributeValue::Flag(is_external));
+            subprogram.set(gimli::DW_AT_decl_file, AttributeValue::FileIndex(file_id));
+            subprogram.set(
+                gimli::DW_AT_decl_line,
+                AttributeValue::Udata(function.line as u64),
+            );
+            subprogram.set(
+                gimli::DW_AT_low_pc,
+                AttributeValue::Address(Address::Symbol {
+                    symbol: idx,
+                    addend: 0,
+                }),
+            );
+            subprogram.set(gimli::DW_AT_high_pc, AttributeValue::Udata(function_size));
+
+            let mut frame_base = Expression::new();
+            frame_base.op(gimli::DW_OP_call_frame_cfa);
+            subprogram.set(gimli::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+
+            if let Some(type_id) = return_type_id {
+                subprogram.set(gimli::DW_AT_type, AttributeValue::UnitRef(type_id));
+            }
+
+            // the parameter DIEs
+            for (param, type_id) in function.params.iter().zip(param_type_ids) {
+                let param_name_id = dwarf.strings.add(param.name.as_bytes());
+                let param_id = dwarf
+                    .unit
+                    .add(subprogram_id, gimli::DW_TAG_formal_parameter);
+                let param_entry = dwarf.unit.get_mut(param_id);
+                param_entry.set(gimli::DW_AT_name, AttributeValue::StringRef(param_name_id));
+                param_entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(type_id));
+            }
+
+            // the local variable DIEs
+            for local in &function.locals {
+                let Some(location) = self.get_local_variable_location(idx, function, local) else {
+                    continue;
+                };
+
+                let type_id = get_or_add_base_type(&mut dwarf, &mut base_types, local.value_type);
+                let location = match location {
+                    LocalVariableLocationValue::Expression(expression) => {
+                        AttributeValue::Exprloc(expression)
+                    }
+                    LocalVariableLocationValue::List(location_list) => {
+                        AttributeValue::LocationListRef(dwarf.unit.locations.add(location_list))
+                    }
+                };
+
+                let local_name_id = dwarf.strings.add(local.name.as_bytes());
+                let local_id = dwarf.unit.add(subprogram_id, gimli::DW_TAG_variable);
+                let local_entry = dwarf.unit.get_mut(local_id);
+                local_entry.set(gimli::DW_AT_name, AttributeValue::StringRef(local_name_id));
+                local_entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(type_id));
+                local_entry.set(gimli::DW_AT_location, location);
+            }
+
+            // the line number program, one sequence per function.
+            let line_program = &mut dwarf.unit.line_program;
+            line_program.begin_sequence(Some(Address::Symbol {
+                symbol: idx,
+                addend: 0,
+            }));
+
+            // the first row points to the declaration of the function,
+            // so that the debugger can set breakpoint by function name.
+            if let Some(file_id) = file_id {
+                let row = line_program.row();
+                row.address_offset = 0;
+                row.file = file_id;
+                row.line = function.line as u64;
+                row.column = 0;
+                line_program.generate_row();
+            }
+
+            let line_mappings = source_map
+                .get_function(function.func_id)
+                .map(|item| item.line_mappings.as_slice())
+                .unwrap_or_default();
+
+            for mapping in line_mappings {
+                let Some(location) = source_map.get_source_location(mapping.source_loc) else {
+                    continue;
+                };
+
+                let Some(location_file_id) = file_ids.get(location.file_id as usize) else {
+                    continue;
+                };
+
+                let row = line_program.row();
+                row.address_offset = mapping.start as u64;
+                row.file = *location_file_id;
+                row.line = location.line as u64;
+                row.column = location.column as u64;
+                line_program.generate_row();
+            }
+
+            line_program.end_sequence(function_size);
+        }
+
+        // write sections
+        let mut sections = Sections::new(RelocatableWriter::new(self.endian));
+        dwarf.write(&mut sections)?;
+
+        write_sections_to_object(&mut product.object, &sections, &symbols);
+
+        Ok(())
+    }
+
+    /// - `symbol`: the relocation target of the function (the index of symbol list).
+    fn get_local_variable_location(
+        &self,
+        symbol: usize,
+        function: &FunctionDebugInfo,
+        local: &LocalVariableDebugInfo,
+    ) -> Option<LocalVariableLocationValue> {
+        match local.location {
+            LocalVariableLocation::StackSlot { stack_slot, offset } => {
+                let sp_register = self.sp_register?;
+                let slot_offset = function
+                    .frame
+                    .stack_slot_offsets
+                    .get(stack_slot.as_u32() as usize)?;
+
+                let mut expression = Expression::new();
+                expression.op_breg(Register(sp_register), (slot_offset + offset) as i64);
+                Some(LocalVariableLocationValue::Expression(expression))
+            }
+            LocalVariableLocation::ValueLabel(label) => {
+                let (_, ranges) = function
+                    .frame
+                    .value_label_ranges
+                    .iter()
+                    .find(|(item, _)| *item == label)?;
+
+                let locations = ranges
+                    .iter()
+                    .filter(|range| range.start < range.end)
+                    .map(|range| {
+                        let mut expression = Expression::new();
+                        match range.location {
+                            ValueLocation::Register(register) => {
+                                expression.op_reg(Register(register))
+                            }
+                            ValueLocation::CfaOffset(offset) => expression.op_fbreg(offset),
+                        }
+
+                        Location::StartEnd {
+                            begin: Address::Symbol {
+                                symbol,
+                                addend: range.start as i64,
+                            },
+                            end: Address::Symbol {
+                                symbol,
+                                addend: range.end as i64,
+                            },
+                            data: expression,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                if locations.is_empty() {
+                    None
+                } else {
+                    Some(LocalVariableLocationValue::List(LocationList(locations)))
+                }
+            }
+        }
+    }
+}
+
+enum LocalVariableLocationValue {
+    Expression(Expression),
+    List(LocationList),
+}
+
+pub(crate) fn get_endian(isa: &dyn TargetIsa) -> RunTimeEndian {
+    match isa.endianness() {
+        cranelift_codegen::ir::Endianness::Little => RunTimeEndian::Little,
+        cranelift_codegen::ir::Endianness::Big => RunTimeEndian::Big,
+    }
+}
+
+fn get_or_add_base_type(
+    dwarf: &mut DwarfUnit,
+    base_types: &mut Vec<(Type, gimli::write::UnitEntryId)>,
+    value_type: Type,
+) -> gimli::write::UnitEntryId {
+    if let Some((_, type_id)) = base_types.iter().find(|(t, _)| *t == value_type) {
+        return *type_id;
+    }
+
+    let encoding = match value_type {
+        types::F32 | types::F64 => gimli::DW_ATE_float,
+        _ => gimli::DW_ATE_signed,
+    };
+
+    let root_id = dwarf.unit.root();
+    let type_id = dwarf.unit.add(root_id, gimli::DW_TAG_base_type);
+    let type_name_id = dwarf.strings.add(value_type.to_string());
+
+    let type_entry = dwarf.unit.get_mut(type_id);
+    type_entry.set(gimli::DW_AT_name, AttributeValue::StringRef(type_name_id));
+    type_entry.set(gimli::DW_AT_encoding, AttributeValue::Encoding(encoding));
+    type_entry.set(
+        gimli::DW_AT_byte_size,
+        AttributeValue::Udata(value_type.bytes() as u64),
+    );
+
+    base_types.push((value_type, type_id));
+    type_id
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RelocationTarget {
+    /// the index of the symbol list which is passed to `write_sections_to_object()`.
+    Symbol(usize),
+
+    /// the start of another DWARF section.
+    Section(SectionId),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DebugRelocation {
+    pub offset: u64,
+    pub size: u8,
+    pub target: RelocationTarget,
+    pub addend: i64,
+
+    /// PC-relative relocation, it is used by the `.eh_frame` section.
+    pub relative: bool,
+}
+
+/// A gimli `Writer` which records the relocations of the addresses
+/// and section offsets instead of writing the absolute values,
+/// because the final addresses are only known after linking.
+#[derive(Clone)]
+pub(crate) struct RelocatableWriter {
+    pub writer: EndianVec<RunTimeEndian>,
+    pub relocations: Vec<DebugRelocation>,
+}
+
+impl RelocatableWriter {
+    pub fn new(endian: RunTimeEndian) -> Self {
+        Self {
+            writer: EndianVec::new(endian),
+            relocations: vec![],
+        }
+    }
+}
+
+impl Writer for RelocatableWriter {
+    type Endian = RunTimeEndian;
+
+    fn endian(&self) -> Self::Endian {
+        self.writer.endian()
+    }
+
+    fn len(&self) -> usize {
+        self.writer.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> gimli::write::Result<()> {
+        self.writer.write(bytes)
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> gimli::write::Result<()> {
+        self.writer.write_at(offset, bytes)
+    }
+
+    fn write_address(&mut self, address: Address, size: u8) -> gimli::write::Result<()> {
+        match address {
+            Address::Constant(value) => self.writer.write_udata(value, size),
+            Address::Symbol { symbol, addend } => {
+                self.relocations.push(DebugRelocation {
+                    offset: self.writer.len() as u64,
+                    size,
+                    target: RelocationTarget::Symbol(symbol),
+                    addend,
+                    relative: false,
+                });
+                self.writer.write_udata(0, size)
+            }
+        }
+    }
+
+    fn write_eh_pointer(
+        &mut self,
+        address: Address,
+        eh_pe: gimli::DwEhPe,
+        size: u8,
+    ) -> gimli::write::Result<()> {
+        let Address::Symbol { symbol, addend } = address else {
+            return self.writer.write_eh_pointer(address, eh_pe, size);
+        };
+
+        let relocation_size = match eh_pe.format() {
+            gimli::DW_EH_PE_absptr => size,
+            gimli::DW_EH_PE_udata4 | gimli::DW_EH_PE_sdata4 => 4,
+            gimli::DW_EH_PE_udata8 | gimli::DW_EH_PE_sdata8 => 8,
+            _ => return Err(gimli::write::Error::UnsupportedPointerEncoding(eh_pe)),
+        };
+
+        let relative = match eh_pe.application() {
+            gimli::DW_EH_PE_absptr => false,
+            gimli::DW_EH_PE_pcrel => true,
+            _ => return Err(gimli::write::Error::UnsupportedPointerEncoding(eh_pe)),
+        };
+
+        self.relocations.push(DebugRelocation {
+            offset: self.writer.len() as u64,
+            size: relocation_size,
+            target: RelocationTarget::Symbol(symbol),
+            addend,
+            relative,
+        });
+        self.writer.write_udata(0, relocation_size)
+    }
+
+    fn write_offset(
+        &mut self,
+        value: usize,
+        section: SectionId,
+        size: u8,
+    ) -> gimli::write::Result<()> {
+        self.relocations.push(DebugRelocation {
+            offset: self.writer.len() as u64,
+            size,
+            target: RelocationTarget::Section(section),
+            addend: value as i64,
+            relative: false,
+        });
+        self.writer.write_udata(0, size)
+    }
+
+    fn write_offset_at(
+        &mut self,
+        offset: usize,
+        value: usize,
+        section: SectionId,
+        size: u8,
+    ) -> gimli::write::Result<()> {
+        self.relocations.push(DebugRelocation {
+            offset: offset as u64,
+            size,
+            target: RelocationTarget::Section(section),
+            addend: value as i64,
+            relative: false,
+        });
+        self.writer.write_udata_at(offset, 0, size)
+    }
+}
+
+/// Append the non-empty sections to the object and apply relocations.
+pub(crate) fn write_sections_to_object(
+    object: &mut Object,
+    sections: &Sections<RelocatableWriter>,
+    symbols: &[SymbolId],
+) {
+    let mut writers: Vec<(SectionId, &RelocatableWriter)> = vec![];
+    sections
+        .for_each(|id, writer| {
+            if writer.len() > 0 {
+                writers.push((id, writer));
+            }
+            Ok::<(), ()>(())
+        })
+        .unwrap();
+
+    // create sections
+    let section_ids = writers
+        .iter()
+        .map(|(id, writer)| {
+            let kind = if *id == SectionId::EhFrame {
+                SectionKind::ReadOnlyData
+            } else {
+                SectionKind::Debug
+            };
+
+            let section_id = object.add_section(vec![], id.name().as_bytes().to_vec(), kind);
+            object.set_section_data(section_id, writer.writer.slice().to_vec(), 8);
+            (*id, section_id)
+        })
+        .collect::<Vec<(SectionId, ObjectSectionId)>>();
+
+    // apply relocations
+    for ((id, writer), (_, section_id)) in writers.iter().zip(section_ids.iter()) {
+        for relocation in &writer.relocations {
+            let mut addend = relocation.addend;
+            let symbol = match relocation.target {
+                // the `.eh_frame` is loaded, so it refers to the functions by the
+                // section symbols, otherwise the linker complains about the
+                // relocations against the preemptible symbols in a shared library.
+                RelocationTarget::Symbol(idx) if *id == SectionId::EhFrame => {
+                    match object.symbol_section_and_offset(symbols[idx]) {
+                        Some((section_symbol, offset)) => {
+                            addend += offset as i64;
+                            section_symbol
+                        }
+                        None => symbols[idx],
+                    }
+                }
+                RelocationTarget::Symbol(idx) => symbols[idx],
+                RelocationTarget::Section(target_id) => {
+                    let (_, target_section_id) = section_ids
+                        .iter()
+                        .find(|(item, _)| *item == target_id)
+                        .expect("the target section of relocation should not be empty");
+                    object.section_symbol(*target_section_id)
+                }
+            };
+
+            let kind = if relocation.relative {
+                RelocationKind::Relative
+            } else {
+                RelocationKind::Absolute
+            };
+
+            object
+                .add_relocation(
+                    *section_id,
+                    Relocation {
+                        offset: relocation.offset,
+                        symbol,
+                        addend,
+                        flags: RelocationFlags::Generic {
+                            kind,
+                            encoding: RelocationEncoding::Generic,
+                            size: relocation.size * 8,
+                        },
+                    },
+                )
+                .expect("failed to add relocation for the debug section");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{
+        types, AbiParam, Function, InstBuilder, StackSlotData, StackSlotKind, UserFuncName,
+        ValueLabel,
+    };
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::{
+        object::{
+            read::elf::ElfFile64, Endianness, Object, ObjectSection, ObjectSymbol, RelocationTarget,
+        },
+        ObjectModule,
+    };
+
+    use crate::{
+        code_generator::Generator,
+        debug_info::{DebugInfo, LocalVariableLocation, ParameterDebugInfo},
+    };
+
+    #[test]
+    fn test_debug_info_sections() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_debug_info("main.anc", "/tmp");
+
+        // build function "inc"
+        //
+        // ```rust
+        // fn inc (a:i32) -> i32 {  // line 1
+        //    a+11                  // line 2
+        // }
+        // ```
+
+        let mut func_inc_sig = generator.module.make_signature();
+        func_inc_sig.params.push(AbiParam::new(types::I32));
+        func_inc_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_inc_id = generator
+            .module
+            .declare_function("inc", Linkage::Export, &func_inc_sig)
+            .unwrap();
+
+        let file_id = generator.source_map.add_source_file("main.anc");
+
+        let debug_info = generator.debug_info.as_mut().unwrap();
+        debug_info.declare_function(
+            func_inc_id,
+            "inc",
+            file_id,
+            1,
+            vec![ParameterDebugInfo {
+                name: "a".to_owned(),
+                value_type: types::I32,
+            }],
+            vec![types::I32],
+        );
+
+        let mut func_inc = Function::with_name_signature(
+            UserFuncName::user(0, func_inc_id.as_u32()),
+            func_inc_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_inc, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 2, 4);
+        let value_0 = function_builder.ins().iconst(types::I32, 11);
+        let value_1 = function_builder.block_params(block)[0];
+        let value_2 = function_builder.ins().iadd(value_0, value_1);
+
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 3, 1);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_inc_id, func_inc).unwrap();
+
+        let object_product = generator.finish().unwrap();
+        let module_binary = object_product.emit().unwrap();
+
+        // check the object file
+        let object_file = ElfFile64::<Endianness>::parse(module_binary.as_slice()).unwrap();
+
+        for name in [".debug_abbrev", ".debug_info", ".debug_line", ".debug_str"] {
+            assert!(object_file.section_by_name(name).is_some());
+        }
+
+        let debug_str = object_file.section_by_name(".debug_str").unwrap();
+        let debug_str_data = debug_str.data().unwrap();
+        assert!(debug_str_data.windows(4).any(|w| w == b"inc\0"));
+
+        // the address of subprogram "inc" should be relocated against the function symbol
+        let func_inc_symbol_index = object_file
+            .symbols()
+            .find(|s| s.name() == Ok("inc"))
+            .unwrap()
+            .index();
+
+        let debug_info_section = object_file.section_by_name(".debug_info").unwrap();
+        assert!(debug_info_section
+            .relocations()
+            .any(|(_, r)| { r.target() == RelocationTarget::Symbol(func_inc_symbol_index) }));
+    }
+
+    #[test]
+    fn test_debug_info_local_variables() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_debug_info("main.anc", "/tmp");
+
+        // build function "swap"
+        //
+        // ```rust
+        // fn swap (a:i32) -> i32 {    // line 1
+        //    let buf:[i32;2] = [a, 0] // line 2, stack slot
+        //    let b = buf[0] + 1       // line 3, SSA value
+        //    b
+        // }
+        // ```
+
+        let mut func_swap_sig = generator.module.make_signature();
+        func_swap_sig.params.push(AbiParam::new(types::I32));
+        func_swap_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_swap_id = generator
+            .module
+            .declare_function("swap", Linkage::Export, &func_swap_sig)
+            .unwrap();
+
+        let file_id = generator.source_map.add_source_file("main.anc");
+
+        let mut func_swap = Function::with_name_signature(
+            UserFuncName::user(0, func_swap_id.as_u32()),
+            func_swap_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_swap, &mut generator.function_builder_context);
+
+        let stack_slot = function_builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            8,
+            2,
+        ));
+        let label_b = ValueLabel::from_u32(0);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        function_builder.ins().stack_store(value_0, stack_slot, 0);
+        let value_1 = function_builder.ins().stack_load(types::I32, stack_slot, 0);
+        let value_2 = function_builder.ins().iadd_imm(value_1, 1);
+        DebugInfo::set_value_label(&mut function_builder, value_2, label_b);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        let debug_info = generator.debug_info.as_mut().unwrap();
+        debug_info.declare_function(func_swap_id, "swap", file_id, 1, vec![], vec![types::I32]);
+        debug_info.declare_local_variable(
+            func_swap_id,
+            "buf",
+            types::I32,
+            LocalVariableLocation::StackSlot {
+                stack_slot,
+                offset: 0,
+            },
+        );
+        debug_info.declare_local_variable(
+            func_swap_id,
+            "b",
+            types::I32,
+            LocalVariableLocation::ValueLabel(label_b),
+        );
+
+        generator.define_function(func_swap_id, func_swap).unwrap();
+
+        // the frame layout should be collected after compilation
+        let function = generator
+            .debug_info
+            .as_ref()
+            .unwrap()
+            .get_function(func_swap_id)
+            .unwrap();
+        assert_eq!(function.locals.len(), 2);
+        assert_eq!(function.frame.stack_slot_offsets.len(), 1);
+
+        let object_product = generator.finish().unwrap();
+        let module_binary = object_product.emit().unwrap();
+
+        let object_file = ElfFile64::<Endianness>::parse(module_binary.as_slice()).unwrap();
+        let debug_str = object_file.section_by_name(".debug_str").unwrap();
+        let debug_str_data = debug_str.data().unwrap();
+        assert!(debug_str_data.windows(4).any(|w| w == b"buf\0"));
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::{HashMap, HashSet};
+
+use cranelift_codegen::ir::{Function, UserExternalName};
+use cranelift_module::{DataId, FuncId, Linkage, Module};
+
+use crate::code_generator::{DataDefinition, Generator};
+
+// The constant data deduplication
+// -------------------------------
+//
+// The front ends may generate lots of identical constants, e.g. the format
+// strings and the lookup tables, `Generator::deduplicate_data()` is an optional
+// pass over the collected functions and data (i.e. before calling
+// `define_function()` and `define_data_content()`), it keeps only the first one
+// of the identical data, and redirects the references (i.e. the symbol global
+// values and the `user_named_funcs` of the functions) to it.
+//
+// the references which are made after the pass are redirected too, i.e. the
+// functions which are defined by `define_function()` (or
+// `define_functions_in_parallel()`) and the addresses which are written by
+// `write_data_address()`.
+//
+// the data is deduplicated only if:
+//
+// - its linkage is `Local`, the exported data has its own identity (address).
+// - it is read-only and not thread-local.
+// - it is initialized, and the content and the alignment are the same.
+// - it is not referenced by the functions and data which are already defined,
+//   since their relocations can not be changed.
+//
+// Note that the removed data is still declared in the module,
+// it just has no definition.
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Remove the duplicated read-only data from the given collection, and
+    /// update the references of the functions.
+    ///
+    /// Returns the number of the removed data.
+    pub fn deduplicate_data(
+        &mut self,
+        functions: &mut [(FuncId, Function)],
+        data_definitions: &mut Vec<(DataId, DataDefinition)>,
+    ) -> usize {
+        let declarations = self.module.declarations();
+        let defined_references = self
+            .symbol_references
+            .iter()
+            .flat_map(|symbol_references| symbol_references.data.iter().copied())
+            .collect::<HashSet<_>>();
+
+        // duplicated id -> canonical id
+        let mut redirects = HashMap::new();
+        let mut canonical_ids: HashMap<(&[u8], u64), DataId> = HashMap::new();
+
+        for (data_id, data_definition) in data_definitions.iter() {
+            let declaration = declarations.get_data_decl(*data_id);
+            if declaration.linkage != Linkage::Local || declaration.writable || declaration.tls {
+                continue;
+            }
+
+            let DataDefinition::Initialized { data, align } = data_definition else {
+                continue;
+            };
+
+            // the data with different alignments are not merged
+            let align = self.get_data_alignment(*data_id, *align);
+            let canonical_id = *canonical_ids.entry((data, align)).or_insert(*data_id);
+            if canonical_id != *data_id && !defined_references.contains(data_id) {
+                redirects.insert(*data_id, canonical_id);
+            }
+        }
+
+        if redirects.is_empty() {
+            return 0;
+        }
+
+        for (_, func) in functions.iter_mut() {
+            redirect_data_references(&redirects, func);
+        }
+
+        data_definitions.retain(|(data_id, _)| !redirects.contains_key(data_id));
+
+        let count = redirects.len();
+        self.data_redirects.extend(redirects);
+        count
+    }
+
+    /// Redirect the references of the function to the removed data, for the
+    /// functions which are defined after `deduplicate_data()`.
+    pub(crate) fn redirect_deduplicated_data(&self, func: &mut Function) {
+        if !self.data_redirects.is_empty() {
+            redirect_data_references(&self.data_redirects, func);
+        }
+    }
+
+    /// Get the data which the references to the given data are redirected to.
+    pub(crate) fn get_deduplicated_data(&self, data_id: DataId) -> DataId {
+        self.data_redirects
+            .get(&data_id)
+            .copied()
+            .unwrap_or(data_id)
+    }
+}
+
+fn redirect_data_references(redirects: &HashMap<DataId, DataId>, func: &mut Function) {
+    let updated_names = func
+        .params
+        .user_named_funcs()
+        .iter()
+        .filter(|(_, name)| name.namespace == 1)
+        .filter_map(|(name_ref, name)| {
+            redirects
+                .get(&DataId::from_u32(name.index))
+                .map(|canonical_id| (name_ref, canonical_id.as_u32()))
+        })
+        .collect::<Vec<_>>();
+
+    for (name_ref, index) in updated_names {
+        func.params
+            .reset_user_func_name(name_ref, UserExternalName::new(1, index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, MemFlags, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{
+        code_generator::{DataDefinition, Generator},
+        test_support::run_executable_binary_and_get_exit_code,
+    };
+
+    #[test]
+    fn test_deduplicate_data() {
+        // ```rust
+        // static A: i32 = 6;
+        // static B: i32 = 6;     // the same as "A"
+        // static C: i32 = 1;
+        // static mut D: i32 = 6; // writable
+        // static F: i32 = 6;     // referenced by the defined data "R"
+        // static R: &i32 = &F;
+        // static P: &i32 = &B;   // defined after the deduplication
+        // fn main() -> i32 {
+        //     A + B + C + *P
+        // }
+        // ```
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let mut data_definitions = vec![];
+        for (name, number, writable) in [
+            ("a", 6i32, false),
+            ("b", 6, false),
+            ("c", 1, false),
+            ("d", 6, true),
+            ("f", 6, false),
+        ] {
+            let data_id = generator
+                .module
+                .declare_data(name, Linkage::Local, writable, false)
+                .unwrap();
+            data_definitions.push((
+                data_id,
+                DataDefinition::Initialized {
+                    data: number.to_le_bytes().to_vec(),
+                    align: 4,
+                },
+            ));
+        }
+
+        let pointer_definition = DataDefinition::Initialized {
+            data: vec![0; 8],
+            align: 8,
+        };
+        let data_r_id = generator
+            .module
+            .declare_data("r", Linkage::Local, false, false)
+            .unwrap();
+        generator.write_data_address(0, data_definitions[4].0, 0);
+        generator
+            .define_data_content(data_r_id, pointer_definition.clone())
+            .unwrap();
+
+        let data_p_id = generator
+            .module
+            .declare_data("p", Linkage::Local, false, false)
+            .unwrap();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let data_gvs = data_definitions[0..3]
+            .iter()
+            .map(|(data_id, _)| {
+                generator
+                    .module
+                    .declare_data_in_func(*data_id, &mut func_main)
+            })
+            .collect::<Vec<_>>();
+        let data_p_gv = generator
+            .module
+            .declare_data_in_func(data_p_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let mut value_sum = function_builder.ins().iconst(types::I32, 0);
+        for data_gv in data_gvs {
+            let value_0 = function_builder.ins().symbol_value(pointer_type, data_gv);
+            let value_1 = function_builder
+                .ins()
+                .load(types::I32, MemFlags::new(), value_0, 0);
+            value_sum = function_builder.ins().iadd(value_sum, value_1);
+        }
+        let value_2 = function_builder.ins().symbol_value(pointer_type, data_p_gv);
+        let value_3 = function_builder
+            .ins()
+            .load(pointer_type, MemFlags::new(), value_2, 0);
+        let value_4 = function_builder
+            .ins()
+            .load(types::I32, MemFlags::new(), value_3, 0);
+        value_sum = function_builder.ins().iadd(value_sum, value_4);
+        function_builder.ins().return_(&[value_sum]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        let data_b_id = data_definitions[1].0;
+        let mut functions = vec![(func_main_id, func_main)];
+        let count = generator.deduplicate_data(&mut functions, &mut data_definitions);
+        // "f" is kept since it is referenced by the defined data "r"
+        assert_eq!(count, 1);
+        assert_eq!(data_definitions.len(), 4);
+
+        // the global values of "a" and "b" refer to the same data
+        let user_named_funcs = functions[0].1.params.user_named_funcs();
+        assert_eq!(
+            user_named_funcs
+                .values()
+                .filter(|name| name.index == 0)
+                .count(),
+            2
+        );
+
+        for (data_id, data_definition) in data_definitions {
+            generator
+                .define_data_content(data_id, data_definition)
+                .unwrap();
+        }
+
+        // the address of the removed data "b" is redirected to "a"
+        generator.write_data_address(0, data_b_id, 0);
+        generator
+            .define_data_content(data_p_id, pointer_definition)
+            .unwrap();
+
+        for (func_id, func) in functions {
+            generator.define_function(func_id, func).unwrap();
+        }
+
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+        let exit_code_opt =
+            run_executable_binary_and_get_exit_code(&module_binary, "test_deduplicate_data", false);
+        assert_eq!(exit_code_opt, Some(19));
+    }
+}
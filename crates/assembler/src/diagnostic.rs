@@ -0,0 +1,177 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Display;
+
+use crate::lexer::Span;
+
+// The diagnostics
+// ---------------
+//
+// The errors of the lexer, the parser and the lowering carry the byte range
+// (i.e. the `Span`) of the source text, and can be rendered in the style of rustc:
+//
+// ```text
+// error: unknown instruction "add_i33"
+//  --> main.ancasm:4:14
+//   |
+// 4 |             (add_i33 (local_load $a) (imm_i32 1))
+//   |              ^^^^^^^
+//   |
+//   = note: the instruction names are listed in the document "xiaoxuan_native_assembly_cranelift_ir_map.ods"
+// ```
+//
+// the line and column numbers are 1-based, and the column is counted in characters.
+// only the first line is shown if the span covers multiple lines.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: &str, span: Span) -> Self {
+        Self {
+            message: message.to_owned(),
+            span,
+            notes: vec![],
+        }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_owned());
+        self
+    }
+
+    /// Render the message with the source snippet, the `file_path` is
+    /// only used for display.
+    pub fn render(&self, file_path: &str, source: &str) -> String {
+        let (line, column) = get_line_column(source, self.span.start);
+
+        let line_start = source[..self.span.start.min(source.len())]
+            .rfind('\n')
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        let line_end = source[line_start..]
+            .find('\n')
+            .map(|index| line_start + index)
+            .unwrap_or(source.len());
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+
+        // the caret covers the span within the first line, at least one character
+        let caret_end = self.span.end.clamp(self.span.start, line_end);
+        let caret_width = source[self.span.start.min(line_end)..caret_end]
+            .chars()
+            .count()
+            .max(1);
+
+        let line_number = line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let mut text = format!("error: {}\n", self.message);
+        text.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter, file_path, line, column
+        ));
+        text.push_str(&format!("{} |\n", gutter));
+        text.push_str(&format!("{} | {}\n", line_number, line_text));
+        text.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(caret_width)
+        ));
+
+        if !self.notes.is_empty() {
+            text.push_str(&format!("{} |\n", gutter));
+            for note in &self.notes {
+                text.push_str(&format!("{} = note: {}\n", gutter, note));
+            }
+        }
+
+        text
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (at {}..{})",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+/// Get the 1-based line and column numbers of the byte offset.
+pub fn get_line_column(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let text = &source[..offset];
+    let line = text.matches('\n').count() + 1;
+    let line_start = text.rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let column = text[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator, diagnostic::get_line_column, lowering::assemble_module,
+        parser::parse_module,
+    };
+
+    #[test]
+    fn test_diagnostic_render() {
+        assert_eq!(get_line_column("ab\ncd", 0), (1, 1));
+        assert_eq!(get_line_column("ab\ncd", 4), (2, 2));
+        assert_eq!(get_line_column("文字\nx", 6), (1, 3));
+
+        let source = "(module $app
+    (function $main (result i32)
+        (code
+            (add_i33 (imm_i32 1) (imm_i32 2))))
+)";
+        let diagnostic = parse_module(source).unwrap_err();
+        assert_eq!(
+            diagnostic.render("main.ancasm", source),
+            r#"error: unknown instruction "add_i33"
+ --> main.ancasm:4:14
+  |
+4 |             (add_i33 (imm_i32 1) (imm_i32 2))))
+  |              ^^^^^^^
+  |
+  = note: the instruction names are listed in the document "xiaoxuan_native_assembly_cranelift_ir_map.ods"
+"#
+        );
+
+        // the span covers multiple lines
+        let source = "(module $app
+    (data $d (read_only i32 1))
+    (function $main
+        (code
+            (data_store_i32 $d
+                (imm_i32 2)))))";
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let diagnostic = assemble_module(&module, &mut generator).unwrap_err();
+        assert_eq!(
+            diagnostic.render("main.ancasm", source),
+            r#"error: the data "$d" is read-only
+ --> main.ancasm:5:13
+  |
+5 |             (data_store_i32 $d
+  |             ^^^^^^^^^^^^^^^^^^
+  |
+  = note: declare the data by "(read_write ...)" to make it writable
+"#
+        );
+    }
+}
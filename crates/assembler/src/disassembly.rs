@@ -0,0 +1,535 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{collections::HashMap, fmt::Write};
+
+use cranelift_codegen::{
+    ir::{Function, SourceLoc},
+    CompiledCode,
+};
+use cranelift_module::FuncId;
+use cranelift_object::object::Architecture;
+use iced_x86::{
+    Decoder, DecoderOptions, Formatter, GasFormatter, Instruction, OpKind, SymbolResolver,
+    SymbolResult,
+};
+
+use crate::source_location::{SourceLocation, SourceMap};
+
+// The disassembly listing
+// -----------------------
+//
+// The listing is similar to the output of `$ objdump -d -S anna.o`, it shows
+// the following parts of each function:
+//
+// - the CLIF (Cranelift IR) instructions, annotated with the source lines.
+// - the disassembly of the machine instructions (the "VCode" text of Cranelift,
+//   i.e. the instructions after register allocation).
+// - the machine code bytes, grouped by the source lines.
+//
+// e.g.
+//
+// ```text
+// function inc (size: 17 bytes)
+//
+// ;; CLIF
+// block0(v0: i32):
+//     ; main.anc:2:4  a+11
+//     v1 = iconst.i32 11
+//     v2 = iadd v1, v0
+//     ; main.anc:3:1  }
+//     return v2
+//
+// ;; disassembly
+//   pushq   %rbp
+//   ...
+//
+// ;; machine code
+//   ; main.anc:2:4
+//   00000004  8d 47 0b
+//   ...
+// ```
+//
+// The listing is collected by `Generator::define_function()` when it is enabled
+// by `Generator::enable_listing()`.
+//
+// The machine code which is not generated in the current process (e.g. the
+// functions of an object file, see `object_dump.rs`) is decoded into the
+// instructions by `decode_machine_code()`, e.g.
+//
+// ```text
+// 00000004  48 89 e5                movq    %rsp, %rbp
+// 00000007  e8 00 00 00 00          callq   inc
+// ```
+//
+// the addresses of the operands (i.e. the branch targets, the PC-relative
+// memory addresses and the immediates) are resolved into the symbols by the
+// caller. only x86 and x86_64 are decoded currently.
+//
+// ref:
+// - https://docs.rs/cranelift-codegen/latest/cranelift_codegen/struct.Context.html#method.set_disasm
+// - https://docs.rs/iced-x86/latest/iced_x86/
+
+/// A line of CLIF instruction (or block header) and its source location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClifLine {
+    pub source_loc: SourceLoc,
+    pub text: String,
+
+    /// the line is a block header, e.g. "block0(v0: i32):".
+    pub is_block: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionListing {
+    pub func_id: FuncId,
+    pub name: String,
+    pub clif_lines: Vec<ClifLine>,
+
+    /// the disassembly text of the machine instructions.
+    pub disassembly: String,
+    pub machine_code: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct Listing {
+    functions: Vec<FunctionListing>,
+}
+
+impl Listing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_function(&self, func_id: FuncId) -> Option<&FunctionListing> {
+        self.functions.iter().find(|item| item.func_id == func_id)
+    }
+
+    pub fn get_functions(&self) -> &[FunctionListing] {
+        &self.functions
+    }
+
+    /// Collect the listing of a function.
+    ///
+    /// - `func`: the CLIF function before compilation (i.e. without the optimization
+    ///   and legalization), so the instructions match the assembly source.
+    pub(crate) fn add_function(
+        &mut self,
+        func_id: FuncId,
+        name: &str,
+        func: &Function,
+        compiled_code: &CompiledCode,
+        machine_code: Vec<u8>,
+    ) {
+        let mut clif_lines = vec![];
+
+        for block in func.layout.blocks() {
+            let params = func
+                .dfg
+                .block_params(block)
+                .iter()
+                .map(|value| format!("{}: {}", value, func.dfg.value_type(*value)))
+                .collect::<Vec<_>>();
+
+            clif_lines.push(ClifLine {
+                source_loc: SourceLoc::default(),
+                text: if params.is_empty() {
+                    format!("{}:", block)
+                } else {
+                    format!("{}({}):", block, params.join(", "))
+                },
+                is_block: true,
+            });
+
+            for inst in func.layout.block_insts(block) {
+                clif_lines.push(ClifLine {
+                    source_loc: func.srcloc(inst),
+                    text: func.dfg.display_inst(inst).to_string(),
+                    is_block: false,
+                });
+            }
+        }
+
+        let function_listing = FunctionListing {
+            func_id,
+            name: name.to_owned(),
+            clif_lines,
+            disassembly: compiled_code.vcode.clone().unwrap_or_default(),
+            machine_code,
+        };
+
+        match self
+            .functions
+            .iter_mut()
+            .find(|item| item.func_id == func_id)
+        {
+            Some(item) => *item = function_listing,
+            None => self.functions.push(function_listing),
+        }
+    }
+
+    /// Generate the listing text of all functions.
+    ///
+    /// The source lines are read from the source files (which are added by
+    /// `SourceMap::add_source_file()`), only the locations are shown if
+    /// the files are unavailable.
+    pub fn to_text(&self, source_map: &SourceMap) -> String {
+        let mut source_reader = SourceReader::new(source_map);
+        let mut text = String::new();
+
+        for function in &self.functions {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            write_function(&mut text, function, source_map, &mut source_reader);
+        }
+
+        text
+    }
+}
+
+fn write_function(
+    text: &mut String,
+    function: &FunctionListing,
+    source_map: &SourceMap,
+    source_reader: &mut SourceReader,
+) {
+    writeln!(
+        text,
+        "function {} (size: {} bytes)",
+        function.name,
+        function.machine_code.len()
+    )
+    .unwrap();
+
+    // CLIF
+    text.push_str("\n;; CLIF\n");
+
+    let mut last_location: Option<SourceLocation> = None;
+    for line in &function.clif_lines {
+        if line.is_block {
+            writeln!(text, "{}", line.text).unwrap();
+            continue;
+        }
+
+        let location = source_map.get_source_location(line.source_loc).copied();
+        if let Some(current) = &location {
+            if location != last_location {
+                writeln!(text, "    ; {}", source_reader.format_location(current)).unwrap();
+            }
+        }
+        last_location = location;
+
+        writeln!(text, "    {}", line.text).unwrap();
+    }
+
+    // disassembly
+    if !function.disassembly.is_empty() {
+        text.push_str("\n;; disassembly\n");
+        for line in function.disassembly.lines() {
+            writeln!(text, "  {}", line).unwrap();
+        }
+    }
+
+    // machine code
+    text.push_str("\n;; machine code\n");
+
+    let mut ranges: Vec<(u32, u32, Option<SourceLocation>)> = vec![];
+    let mut offset = 0;
+
+    if let Some(function_source_map) = source_map.get_function(function.func_id) {
+        for mapping in &function_source_map.line_mappings {
+            if mapping.start > offset {
+                ranges.push((offset, mapping.start, None));
+            }
+            ranges.push((
+                mapping.start,
+                mapping.end,
+                source_map.get_source_location(mapping.source_loc).copied(),
+            ));
+            offset = mapping.end;
+        }
+    }
+
+    let code_size = function.machine_code.len() as u32;
+    if offset < code_size {
+        ranges.push((offset, code_size, None));
+    }
+
+    for (start, end, location) in ranges {
+        if let Some(location) = location {
+            writeln!(text, "  ; {}", source_reader.format_location(&location)).unwrap();
+        }
+
+        for (idx, chunk) in function.machine_code[start as usize..end as usize]
+            .chunks(16)
+            .enumerate()
+        {
+            let bytes = chunk
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>();
+            writeln!(
+                text,
+                "  {:08x}  {}",
+                start as usize + idx * 16,
+                bytes.join(" ")
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// A machine instruction which is decoded from the machine code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineInstruction {
+    pub address: u64,
+    pub size: usize,
+
+    /// the AT&T syntax text, e.g. "callq   inc".
+    pub text: String,
+}
+
+/// The operand of a machine instruction which refers to an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperandAddress {
+    pub instruction_address: u64,
+    pub instruction_size: usize,
+
+    /// the target of a branch, the address of a PC-relative memory operand
+    /// or the value of an immediate.
+    pub address: u64,
+    pub is_immediate: bool,
+}
+
+/// Decode the machine code (which is located at `address`) into the instructions,
+/// the addresses of the operands are resolved into the symbols (e.g. "inc",
+/// "message+4") by `symbolize`.
+///
+/// It returns `None` if the architecture is not supported.
+pub fn decode_machine_code(
+    architecture: Architecture,
+    machine_code: &[u8],
+    address: u64,
+    symbolize: &mut dyn FnMut(&OperandAddress) -> Option<String>,
+) -> Option<Vec<MachineInstruction>> {
+    let bitness = match architecture {
+        Architecture::X86_64 => 64,
+        Architecture::I386 => 32,
+        _ => return None,
+    };
+
+    let instructions = Decoder::with_ip(bitness, machine_code, address, DecoderOptions::NONE)
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    // the symbols are resolved before formatting since the resolver of
+    // the formatter is required to be `'static`.
+    let mut symbols = HashMap::new();
+    for instruction in &instructions {
+        for operand in 0..instruction.op_count() {
+            let (operand_address, is_immediate) = match instruction.op_kind(operand) {
+                OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+                    (instruction.near_branch_target(), false)
+                }
+                OpKind::Memory if instruction.is_ip_rel_memory_operand() => {
+                    (instruction.ip_rel_memory_address(), false)
+                }
+                OpKind::Immediate32 | OpKind::Immediate32to64 | OpKind::Immediate64 => {
+                    (instruction.immediate(operand), true)
+                }
+                _ => continue,
+            };
+
+            if let Some(mut symbol) = symbolize(&OperandAddress {
+                instruction_address: instruction.ip(),
+                instruction_size: instruction.len(),
+                address: operand_address,
+                is_immediate,
+            }) {
+                // the formatter omits the base register of the PC-relative
+                // memory operand when it is shown as a symbol.
+                if instruction.op_kind(operand) == OpKind::Memory {
+                    symbol.push_str("(%rip)");
+                }
+                symbols.insert((instruction.ip(), operand_address), symbol);
+            }
+        }
+    }
+
+    let mut formatter = GasFormatter::with_options(Some(Box::new(OperandSymbols(symbols))), None);
+    formatter.options_mut().set_first_operand_char_index(8);
+    formatter
+        .options_mut()
+        .set_space_after_operand_separator(true);
+    formatter
+        .options_mut()
+        .set_gas_show_mnemonic_size_suffix(true);
+
+    Some(
+        instructions
+            .iter()
+            .map(|instruction| {
+                let mut text = String::new();
+                if instruction.is_invalid() {
+                    text.push_str("(bad)");
+                } else {
+                    formatter.format(instruction, &mut text);
+                }
+
+                MachineInstruction {
+                    address: instruction.ip(),
+                    size: instruction.len(),
+                    text,
+                }
+            })
+            .collect(),
+    )
+}
+
+/// The resolved symbols of the operands, the key is the address of
+/// the instruction and the address of the operand.
+struct OperandSymbols(HashMap<(u64, u64), String>);
+
+impl SymbolResolver for OperandSymbols {
+    fn symbol(
+        &mut self,
+        instruction: &Instruction,
+        _operand: u32,
+        _instruction_operand: Option<u32>,
+        address: u64,
+        _address_size: u32,
+    ) -> Option<SymbolResult<'_>> {
+        self.0
+            .get(&(instruction.ip(), address))
+            .map(|symbol| SymbolResult::with_string(address, symbol.clone()))
+    }
+}
+
+/// Read and cache the lines of the source files.
+struct SourceReader<'a> {
+    source_map: &'a SourceMap,
+    files: HashMap<u32, Option<Vec<String>>>,
+}
+
+impl<'a> SourceReader<'a> {
+    fn new(source_map: &'a SourceMap) -> Self {
+        Self {
+            source_map,
+            files: HashMap::new(),
+        }
+    }
+
+    fn get_line(&mut self, file_id: u32, line: u32) -> Option<&str> {
+        let source_map = self.source_map;
+        let lines = self.files.entry(file_id).or_insert_with(|| {
+            let path = source_map.get_source_file(file_id)?;
+            std::fs::read_to_string(path)
+                .ok()
+                .map(|content| content.lines().map(|s| s.to_owned()).collect())
+        });
+
+        lines
+            .as_ref()?
+            .get((line as usize).checked_sub(1)?)
+            .map(|s| s.trim())
+    }
+
+    /// Format the location as "file:line:column  source text".
+    fn format_location(&mut self, location: &SourceLocation) -> String {
+        let path = self
+            .source_map
+            .get_source_file(location.file_id)
+            .unwrap_or("?")
+            .to_owned();
+
+        match self.get_line(location.file_id, location.line) {
+            Some(source_line) if !source_line.is_empty() => format!(
+                "{}:{}:{}  {}",
+                path, location.line, location.column, source_line
+            ),
+            _ => format!("{}:{}:{}", path, location.line, location.column),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::code_generator::Generator;
+
+    #[test]
+    fn test_disassembly_listing() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_listing();
+
+        // build function "inc"
+        //
+        // ```rust
+        // fn inc (a:i32) -> i32 {  // line 1
+        //    a+11                  // line 2
+        // }                        // line 3
+        // ```
+        let mut func_inc_sig = generator.module.make_signature();
+        func_inc_sig.params.push(AbiParam::new(types::I32));
+        func_inc_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_inc_id = generator
+            .module
+            .declare_function("inc", Linkage::Export, &func_inc_sig)
+            .unwrap();
+
+        let file_id = generator.source_map.add_source_file("main.anc");
+
+        let mut func_inc = Function::with_name_signature(
+            UserFuncName::user(0, func_inc_id.as_u32()),
+            func_inc_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_inc, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 2, 4);
+        let value_0 = function_builder.ins().iconst(types::I32, 11);
+        let value_1 = function_builder.block_params(block)[0];
+        let value_2 = function_builder.ins().iadd(value_0, value_1);
+
+        generator
+            .source_map
+            .set_source_location(&mut function_builder, file_id, 3, 1);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_inc_id, func_inc).unwrap();
+
+        let listing = generator.listing.as_ref().unwrap();
+        let function_listing = listing.get_function(func_inc_id).unwrap();
+        assert_eq!(function_listing.name, "inc");
+        assert!(!function_listing.disassembly.is_empty());
+        assert!(!function_listing.machine_code.is_empty());
+
+        let text = listing.to_text(&generator.source_map);
+        assert!(text.starts_with("function inc (size: "));
+        assert!(text.contains("block0(v0: i32):"));
+        assert!(text.contains("    ; main.anc:2:4\n    v1 = iconst.i32 11\n"));
+        assert!(text.contains("    ; main.anc:3:1\n    return v2\n"));
+        assert!(text.contains(";; disassembly\n"));
+        assert!(text.contains(";; machine code\n"));
+        assert!(text.contains("\n  ; main.anc:2:4\n  0000"));
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_object::{
+    object::{
+        elf::SHF_ALLOC, read::File, write::SectionId, Object, ObjectSection, SectionFlags,
+        SectionKind,
+    },
+    ObjectModule, ObjectProduct,
+};
+
+use crate::code_generator::Generator;
+
+// The ELF notes
+// -------------
+//
+// The notes are the (vendor-specific) metadata of the binary, each note is
+// placed in a `SHT_NOTE` section and consists of:
+//
+// ```text
+// namesz: u32          ; the length of the owner name, including the trailing '\0'
+// descsz: u32          ; the length of the descriptor
+// type:   u32          ; the note type, defined by the owner
+// name:   [u8; namesz] ; the owner name, padded to 4 bytes
+// desc:   [u8; descsz] ; the descriptor, padded to 4 bytes
+// ```
+//
+// the note sections are loadable (i.e. `SHF_ALLOC`), so the linker places them in
+// the `PT_NOTE` segment of the executable, and they can be read by `$ readelf -n`.
+//
+// the GNU build-id note (`.note.gnu.build-id`, `NT_GNU_BUILD_ID`) is a hash of
+// the linked output, so it is generated by the linker, see `LinkerOptions::build_id`.
+//
+// ref:
+// - https://man7.org/linux/man-pages/man5/elf.5.html (section "Notes (Nhdr)")
+// - https://refspecs.linuxfoundation.org/LSB_1.2.0/gLSB/noteabitag.html
+
+/// The owner name of the XiaoXuan notes.
+pub const XIAOXUAN_NOTE_OWNER: &str = "XiaoXuan";
+
+/// The note type of the toolchain version, the descriptor is the version string.
+pub const NT_XIAOXUAN_TOOLCHAIN_VERSION: u32 = 1;
+
+/// The note type of the library which is required by the object file,
+/// the descriptor is the link name of the library, e.g. "m".
+pub const NT_XIAOXUAN_LIBRARY: u32 = 2;
+
+/// The note type of the expected layout of the imported data, the descriptor is
+/// `size: u64, align: u64, symbol: [u8]` (0 for the unspecified size or alignment).
+pub const NT_XIAOXUAN_IMPORTED_DATA: u32 = 3;
+
+const LIBRARY_NOTE_SECTION_NAME: &str = ".note.xiaoxuan.library";
+const IMPORTED_DATA_NOTE_SECTION_NAME: &str = ".note.xiaoxuan.import";
+
+pub const GNU_NOTE_OWNER: &str = "GNU";
+pub const NT_GNU_ABI_TAG: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfNote {
+    /// The name of the section, e.g. ".note.ABI-tag".
+    pub section_name: String,
+    pub owner: String,
+    pub note_type: u32,
+    pub desc: Vec<u8>,
+}
+
+impl ElfNote {
+    /// The ABI tag note (`.note.ABI-tag`), i.e. the earliest compatible kernel version,
+    /// the `os` is 0 for Linux.
+    pub fn new_abi_tag(os: u32, major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            section_name: ".note.ABI-tag".to_owned(),
+            owner: GNU_NOTE_OWNER.to_owned(),
+            note_type: NT_GNU_ABI_TAG,
+            desc: [os, major, minor, patch]
+                .iter()
+                .flat_map(|value| value.to_ne_bytes())
+                .collect(),
+        }
+    }
+
+    /// The version of the XiaoXuan toolchain (`.note.xiaoxuan.version`).
+    pub fn new_toolchain_version(version: &str) -> Self {
+        Self {
+            section_name: ".note.xiaoxuan.version".to_owned(),
+            owner: XIAOXUAN_NOTE_OWNER.to_owned(),
+            note_type: NT_XIAOXUAN_TOOLCHAIN_VERSION,
+            desc: version.as_bytes().to_vec(),
+        }
+    }
+
+    /// The library which is required by the object file (`.note.xiaoxuan.library`),
+    /// the link step adds it to the libraries, see `read_required_libraries()`.
+    pub fn new_library(name: &str) -> Self {
+        Self {
+            section_name: LIBRARY_NOTE_SECTION_NAME.to_owned(),
+            owner: XIAOXUAN_NOTE_OWNER.to_owned(),
+            note_type: NT_XIAOXUAN_LIBRARY,
+            desc: name.as_bytes().to_vec(),
+        }
+    }
+
+    /// The expected layout of the imported data (`.note.xiaoxuan.import`),
+    /// see `check_imported_data()`.
+    pub fn new_imported_data(symbol: &str, size: Option<u64>, align: Option<u64>) -> Self {
+        let mut desc = vec![];
+        desc.extend_from_slice(&size.unwrap_or(0).to_ne_bytes());
+        desc.extend_from_slice(&align.unwrap_or(0).to_ne_bytes());
+        desc.extend_from_slice(symbol.as_bytes());
+
+        Self {
+            section_name: IMPORTED_DATA_NOTE_SECTION_NAME.to_owned(),
+            owner: XIAOXUAN_NOTE_OWNER.to_owned(),
+            note_type: NT_XIAOXUAN_IMPORTED_DATA,
+            desc,
+        }
+    }
+
+    /// Serialize the note in the native endianness.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut name = self.owner.as_bytes().to_vec();
+        name.push(0);
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+        bytes.extend_from_slice(&(self.desc.len() as u32).to_ne_bytes());
+        bytes.extend_from_slice(&self.note_type.to_ne_bytes());
+
+        bytes.extend_from_slice(&name);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes.extend_from_slice(&self.desc);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+}
+
+impl Generator<ObjectModule> {
+    /// Add a note to the object file, the notes with the same section name
+    /// are placed in the same section.
+    pub fn add_elf_note(&mut self, note: ElfNote) {
+        self.elf_notes.push(note);
+    }
+}
+
+/// Read the descriptors of the XiaoXuan notes with the given type in the section,
+/// the invalid (or non-ELF) binary has no notes.
+fn read_xiaoxuan_notes(binary: &[u8], section_name: &str, expected_note_type: u32) -> Vec<Vec<u8>> {
+    let Ok(file) = File::parse(binary) else {
+        return vec![];
+    };
+    let Some(data) = file
+        .section_by_name(section_name)
+        .and_then(|section| section.data().ok())
+    else {
+        return vec![];
+    };
+
+    let read_u32 = |offset: usize| -> Option<u32> {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+    };
+
+    let mut descs = vec![];
+    let mut offset = 0;
+    while let (Some(name_size), Some(desc_size), Some(note_type)) =
+        (read_u32(offset), read_u32(offset + 4), read_u32(offset + 8))
+    {
+        let name_start = offset + 12;
+        let desc_start = name_start + (name_size as usize).next_multiple_of(4);
+        let desc_end = desc_start + desc_size as usize;
+        let (Some(name), Some(desc)) = (
+            data.get(name_start..name_start + name_size as usize),
+            data.get(desc_start..desc_end),
+        ) else {
+            break;
+        };
+
+        if note_type == expected_note_type
+            && name.strip_suffix(b"\0") == Some(XIAOXUAN_NOTE_OWNER.as_bytes())
+        {
+            descs.push(desc.to_vec());
+        }
+        offset = desc_end.next_multiple_of(4);
+    }
+    descs
+}
+
+/// Read the libraries which are required by the object file (see `ElfNote::new_library()`),
+/// the invalid (or non-ELF) object file has no libraries.
+pub fn read_required_libraries(object_binary: &[u8]) -> Vec<String> {
+    let mut libraries = vec![];
+    for desc in read_xiaoxuan_notes(
+        object_binary,
+        LIBRARY_NOTE_SECTION_NAME,
+        NT_XIAOXUAN_LIBRARY,
+    ) {
+        let library = String::from_utf8_lossy(&desc).into_owned();
+        if !libraries.contains(&library) {
+            libraries.push(library);
+        }
+    }
+    libraries
+}
+
+/// Read the expected layouts of the imported data (see `ElfNote::new_imported_data()`),
+/// i.e. `(symbol, size, align)`, in the object file or the linked binary.
+pub fn read_imported_data_layouts(binary: &[u8]) -> Vec<(String, Option<u64>, Option<u64>)> {
+    read_xiaoxuan_notes(
+        binary,
+        IMPORTED_DATA_NOTE_SECTION_NAME,
+        NT_XIAOXUAN_IMPORTED_DATA,
+    )
+    .iter()
+    .filter(|desc| desc.len() >= 16)
+    .map(|desc| {
+        let read_u64 = |offset: usize| {
+            let value = u64::from_ne_bytes(desc[offset..offset + 8].try_into().unwrap());
+            (value != 0).then_some(value)
+        };
+        (
+            String::from_utf8_lossy(&desc[16..]).into_owned(),
+            read_u64(0),
+            read_u64(8),
+        )
+    })
+    .collect()
+}
+
+pub(crate) fn write_elf_notes_to_object(product: &mut ObjectProduct, notes: &[ElfNote]) {
+    let object = &mut product.object;
+    let mut sections: Vec<(&str, SectionId)> = vec![];
+
+    for note in notes {
+        let section_id = match sections
+            .iter()
+            .find(|(section_name, _)| *section_name == note.section_name)
+        {
+            Some((_, section_id)) => *section_id,
+            None => {
+                let section_id = object.add_section(
+                    vec![],
+                    note.section_name.as_bytes().to_vec(),
+                    SectionKind::Note,
+                );
+                object.section_mut(section_id).flags = SectionFlags::Elf {
+                    sh_flags: SHF_ALLOC as u64,
+                };
+                sections.push((&note.section_name, section_id));
+                section_id
+            }
+        };
+
+        object.append_section_data(section_id, &note.to_bytes(), 4);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        elf_note::{read_required_libraries, ElfNote},
+        linker::{link_executable, LinkerOptions},
+    };
+
+    #[test]
+    fn test_elf_note() {
+        assert_eq!(
+            ElfNote::new_toolchain_version("1.0").to_bytes(),
+            [
+                9u32.to_ne_bytes().as_slice(),
+                3u32.to_ne_bytes().as_slice(),
+                1u32.to_ne_bytes().as_slice(),
+                b"XiaoXuan\0\0\0\0",
+                b"1.0\0"
+            ]
+            .concat()
+        );
+
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.add_elf_note(ElfNote::new_toolchain_version("1.0.2"));
+        generator.add_elf_note(ElfNote::new_abi_tag(0, 3, 2, 0));
+        generator.add_elf_note(ElfNote::new_library("m"));
+        generator.add_elf_note(ElfNote::new_library("dl"));
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+        let value_0 = function_builder.ins().iconst(types::I32, 0);
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+        assert_eq!(read_required_libraries(&module_binary), vec!["m", "dl"]);
+
+        let folder = std::env::temp_dir().join(format!("anc_test_elf_note_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let object_file_path = folder.join("anna.o");
+        let exec_file_path = folder.join("anna.elf");
+        std::fs::write(&object_file_path, module_binary).unwrap();
+
+        link_executable(
+            &[object_file_path.to_str().unwrap()],
+            exec_file_path.to_str().unwrap(),
+            &LinkerOptions {
+                build_id: Some("sha1".to_owned()),
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+
+        let output = Command::new("readelf")
+            .arg("-n")
+            .arg(&exec_file_path)
+            .output()
+            .unwrap();
+        let text = String::from_utf8_lossy(&output.stdout);
+
+        assert!(text.contains(".note.gnu.build-id"));
+        assert!(text.contains("NT_GNU_BUILD_ID"));
+        assert!(text.contains(".note.xiaoxuan.version"));
+        assert!(text.contains("XiaoXuan"));
+        assert!(text.contains("OS: Linux, ABI: 3.2.0"));
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
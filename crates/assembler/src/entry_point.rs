@@ -0,0 +1,203 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::binemit::Reloc;
+use cranelift_module::{FuncId, FuncOrDataId, Module, ModuleError};
+use cranelift_object::ObjectModule;
+
+use crate::{code_generator::Generator, machine_code::MachineCodeRelocation};
+
+// The entry point
+// ---------------
+//
+// The executables which are linked with the CRT files (e.g. 'Scrt1.o') start
+// from the `_start` of the CRT, which initializes the libc and then calls `main()`.
+// the freestanding executables (i.e. without the CRT and the libc, see
+// `LinkerMode::Freestanding`), and the runtimes which want their own
+// bootstrap (e.g. initializing the runtime before the user `main()`), define
+// the entry point themselves, e.g.
+//
+// ```rust
+// let func_start_id = generator.define_start_function("__anna_start", func_anna_main_id)?;
+// ```
+//
+// and the linker option `LinkerOptions::entry` (i.e. `ld -e __anna_start`) selects
+// it, the default entry of the linker is `_start`.
+//
+// the start function is written in the machine code since the stack is not
+// aligned as a normal function at the entry point, it:
+//
+// 1. clears the frame pointer (and the link register), which marks the
+//    outermost frame for the debuggers and the stack walkers.
+// 2. calls the "main" function with the initial stack pointer, which points
+//    to `argc`, then `argv[0..argc]`, `NULL`, `envp[..]` and `NULL`, i.e.
+//    `extern "C" fn(stack: *const usize) -> i32`, the parameter can be omitted.
+// 3. exits the process with the returned value by the system call `exit_group`,
+//    so the libc is not required.
+//
+// e.g. x86_64:
+//
+// ```asm
+// _start:
+//     xor     ebp, ebp
+//     mov     rdi, rsp
+//     and     rsp, -16         ; align the stack for the call
+//     call    main
+//     mov     edi, eax
+//     mov     eax, 231         ; exit_group
+//     syscall
+//     ud2
+// ```
+//
+// ref:
+// - https://refspecs.linuxfoundation.org/elf/x86_64-abi-0.99.pdf (3.4 Process Initialization)
+// - https://sourceware.org/binutils/docs/ld/Entry-Point.html
+
+/// The machine code of the start function and the relocation of the call,
+/// i.e. (code, the offset of the relocation, the kind, the addend).
+fn get_start_code(isa_name: &str) -> Option<(&'static [u8], u32, Reloc, i64)> {
+    match isa_name {
+        "x64" => Some((
+            &[
+                0x31, 0xed, // xor ebp, ebp
+                0x48, 0x89, 0xe7, // mov rdi, rsp
+                0x48, 0x83, 0xe4, 0xf0, // and rsp, -16
+                0xe8, 0x00, 0x00, 0x00, 0x00, // call main
+                0x89, 0xc7, // mov edi, eax
+                0xb8, 0xe7, 0x00, 0x00, 0x00, // mov eax, 231
+                0x0f, 0x05, // syscall
+                0x0f, 0x0b, // ud2
+            ],
+            10,
+            Reloc::X86CallPCRel4,
+            -4,
+        )),
+        // the stack is always 16-byte aligned on AArch64
+        "aarch64" => Some((
+            &[
+                0x1d, 0x00, 0x80, 0xd2, // mov x29, #0
+                0x1e, 0x00, 0x80, 0xd2, // mov x30, #0
+                0xe0, 0x03, 0x00, 0x91, // mov x0, sp
+                0x00, 0x00, 0x00, 0x94, // bl main
+                0xc8, 0x0b, 0x80, 0xd2, // mov x8, #94
+                0x01, 0x00, 0x00, 0xd4, // svc #0
+                0x00, 0x00, 0x20, 0xd4, // brk #0
+            ],
+            12,
+            Reloc::Arm64Call,
+            0,
+        )),
+        _ => None,
+    }
+}
+
+impl Generator<ObjectModule> {
+    /// Define the entry point `name` (e.g. "_start") which calls the function
+    /// `main_id` and exits the process with its return value.
+    pub fn define_start_function(
+        &mut self,
+        name: &str,
+        main_id: FuncId,
+    ) -> Result<FuncId, ModuleError> {
+        let isa = self.module.isa();
+        let (code, reloc_offset, reloc_kind, reloc_addend) = get_start_code(isa.name())
+            .ok_or_else(|| {
+                ModuleError::Backend(anyhow::anyhow!(
+                    "The start function is not supported on \"{}\".",
+                    isa.triple()
+                ))
+            })?;
+
+        let func_start_sig = self.module.make_signature();
+        self.define_function_from_bytes(
+            name,
+            &func_start_sig,
+            code,
+            &[MachineCodeRelocation {
+                offset: reloc_offset,
+                kind: reloc_kind,
+                target: FuncOrDataId::Func(main_id),
+                addend: reloc_addend,
+            }],
+            true,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{get_linker_args, link_executable, LinkerMode, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    #[test]
+    fn test_start_function() {
+        // the `argc` is 3
+        let source = r#"
+        (module $main
+            (function $anna_main (param $stack i64) (result i32)
+                (code (add_i32
+                    (imm_i32 10)
+                    (memory_load_i32 (local_load $stack))))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let func_anna_main_id = assembled_module.get_function_id("anna_main").unwrap();
+        generator
+            .define_start_function("__anna_start", func_anna_main_id)
+            .unwrap();
+        assert!(generator.validate().is_ok());
+
+        let temp_folder = TempFolder::new("entry_point");
+        let object_file_path = temp_folder.file_path("main.o");
+        let exec_file_path = temp_folder.file_path("main.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+
+        let options = LinkerOptions {
+            mode: LinkerMode::Freestanding,
+            entry: Some("__anna_start".to_owned()),
+            library_paths: vec![],
+            ..LinkerOptions::default()
+        };
+
+        // no CRT files and no libc
+        let args = get_linker_args(&[&object_file_path], &exec_file_path, &options);
+        assert_eq!(
+            args,
+            vec![
+                "-static".to_owned(),
+                "-e".to_owned(),
+                "__anna_start".to_owned(),
+                "-o".to_owned(),
+                exec_file_path.clone(),
+                object_file_path.clone(),
+            ]
+        );
+
+        link_executable(&[&object_file_path], &exec_file_path, &options).unwrap();
+
+        let exit_code_opt = Command::new(&exec_file_path)
+            .args(["foo", "bar"])
+            .status()
+            .unwrap()
+            .code();
+        assert_eq!(exit_code_opt, Some(13));
+    }
+}
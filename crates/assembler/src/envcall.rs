@@ -0,0 +1,190 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::BTreeMap;
+
+use cranelift_codegen::ir::{AbiParam, Function, InstBuilder, MemFlags, UserFuncName};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{FuncId, Linkage, Module, ModuleError};
+
+use crate::{
+    code_generator::Generator,
+    vm_bridge::{create_operand_buffer, emit_load_operands, emit_store_operands, VmFunctionType},
+};
+
+// The environment call stubs
+// --------------------------
+//
+// The XiaoXuan programs interact with the host environment by the "environment
+// calls" (envcall), each envcall has a number (id) and a signature, the interpreter
+// dispatches the envcalls to the handlers of the runtime, and the compiled modules
+// call the same handlers through the stubs, e.g.
+//
+// ```rust
+// extern "C" fn __anna_envcall_3(a: i32, b: i64) -> i64 {
+//     let operands = [a, b];
+//     __anna_envcall_handlers[3](&operands, &mut operands);
+//     operands[0]
+// }
+// ```
+//
+// the handler table (`__anna_envcall_handlers` by default) is an array of the
+// function pointers which is provided by the runtime, indexed by the envcall id,
+// the signature of the handlers is:
+//
+// `extern "C" fn handler(params: *const u64, results: *mut u64)`
+//
+// the arguments and the results are marshalled as the operands of the
+// VM (see `vm_bridge.rs`), so the handlers are shared with the interpreter.
+
+pub const ENVCALL_HANDLER_TABLE_NAME: &str = "__anna_envcall_handlers";
+
+/// Get the name of the stub of the envcall.
+pub fn get_envcall_stub_name(envcall_id: u32) -> String {
+    format!("__anna_envcall_{}", envcall_id)
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Define the stubs of the given envcalls, the stubs are local functions
+    /// which are named by `get_envcall_stub_name()`.
+    ///
+    /// Returns the map of "envcall id -> stub function".
+    pub fn define_envcall_stubs(
+        &mut self,
+        handler_table_name: &str,
+        envcalls: &BTreeMap<u32, VmFunctionType>,
+    ) -> Result<BTreeMap<u32, FuncId>, ModuleError> {
+        let pointer_type = self.module.isa().pointer_type();
+
+        let handler_table_id =
+            self.module
+                .declare_data(handler_table_name, Linkage::Import, false, false)?;
+
+        let mut handler_sig = self.module.make_signature();
+        handler_sig.params.push(AbiParam::new(pointer_type));
+        handler_sig.params.push(AbiParam::new(pointer_type));
+
+        let mut stubs = BTreeMap::new();
+
+        for (envcall_id, vm_function_type) in envcalls {
+            let stub_sig = self.make_native_signature(vm_function_type);
+            let stub_id = self.module.declare_function(
+                &get_envcall_stub_name(*envcall_id),
+                Linkage::Local,
+                &stub_sig,
+            )?;
+
+            let mut func_stub =
+                Function::with_name_signature(UserFuncName::user(0, stub_id.as_u32()), stub_sig);
+            let handler_table = self
+                .module
+                .declare_data_in_func(handler_table_id, &mut func_stub);
+            let handler_sig_ref = func_stub.import_signature(handler_sig.clone());
+
+            let mut function_builder =
+                FunctionBuilder::new(&mut func_stub, &mut self.function_builder_context);
+
+            let stack_slot = create_operand_buffer(&mut function_builder, vm_function_type);
+
+            let block = function_builder.create_block();
+            function_builder.append_block_params_for_function_params(block);
+            function_builder.switch_to_block(block);
+
+            let args = function_builder.block_params(block).to_vec();
+            emit_store_operands(&mut function_builder, stack_slot, &args);
+
+            let value_table = function_builder
+                .ins()
+                .symbol_value(pointer_type, handler_table);
+            let value_handler = function_builder.ins().load(
+                pointer_type,
+                MemFlags::trusted(),
+                value_table,
+                (*envcall_id * pointer_type.bytes()) as i32,
+            );
+            let value_buffer = function_builder
+                .ins()
+                .stack_addr(pointer_type, stack_slot, 0);
+            function_builder.ins().call_indirect(
+                handler_sig_ref,
+                value_handler,
+                &[value_buffer, value_buffer],
+            );
+
+            let results =
+                emit_load_operands(&mut function_builder, stack_slot, &vm_function_type.results);
+            function_builder.ins().return_(&results);
+
+            function_builder.seal_all_blocks();
+            function_builder.finalize();
+
+            self.define_function(stub_id, func_stub)?;
+            stubs.insert(*envcall_id, stub_id);
+        }
+
+        Ok(stubs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use cranelift_jit::JITModule;
+
+    use crate::{
+        code_generator::Generator,
+        envcall::ENVCALL_HANDLER_TABLE_NAME,
+        vm_bridge::{VmFunctionType, VmOperandType},
+    };
+
+    extern "C" fn handler_add(params: *const u64, results: *mut u64) {
+        // fn (a: i32, b: i64) -> i64
+        let (a, b) = unsafe { (*(params as *const i32), *(params.add(1) as *const i64)) };
+        unsafe { *(results as *mut i64) = a as i64 + b };
+    }
+
+    extern "C" fn handler_nop(_params: *const u64, _results: *mut u64) {}
+
+    static HANDLERS: [extern "C" fn(*const u64, *mut u64); 2] = [handler_nop, handler_add];
+
+    #[test]
+    fn test_envcall_stubs() {
+        let symbols = vec![(
+            ENVCALL_HANDLER_TABLE_NAME.to_owned(),
+            HANDLERS.as_ptr() as *const u8,
+        )];
+        let mut generator = Generator::<JITModule>::new(symbols);
+
+        let mut envcalls = BTreeMap::new();
+        envcalls.insert(0, VmFunctionType::default());
+        envcalls.insert(
+            1,
+            VmFunctionType {
+                params: vec![VmOperandType::I32, VmOperandType::I64],
+                results: vec![VmOperandType::I64],
+            },
+        );
+
+        let stubs = generator
+            .define_envcall_stubs(ENVCALL_HANDLER_TABLE_NAME, &envcalls)
+            .unwrap();
+        assert_eq!(stubs.len(), 2);
+
+        generator.module.finalize_definitions().unwrap();
+
+        let stub_nop: extern "C" fn() =
+            unsafe { std::mem::transmute(generator.module.get_finalized_function(stubs[&0])) };
+        stub_nop();
+
+        let stub_add: extern "C" fn(i32, i64) -> i64 =
+            unsafe { std::mem::transmute(generator.module.get_finalized_function(stubs[&1])) };
+        assert_eq!(stub_add(-3, 10), 7);
+    }
+}
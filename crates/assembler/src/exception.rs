@@ -0,0 +1,413 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, FuncRef, Function, GlobalValue, InstBuilder, MemFlags, StackSlot,
+    StackSlotData, StackSlotKind, TrapCode, Type, Value,
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{DataId, FuncId, Linkage, Module, ModuleError};
+
+use crate::{
+    code_generator::{DataDefinition, Generator},
+    safety_check::emit_null_check,
+};
+
+// The exception handling (setjmp/longjmp)
+// ---------------------------------------
+//
+// The error propagation across the generated frames is implemented by
+// `_setjmp()` and `_longjmp()` of libc, each active `try` has a handler frame
+// (a stack slot) in the function:
+//
+// ```text
+// | previous handler frame (pointer) |
+// | jmp_buf                          |
+// ```
+//
+// the handler frames are linked as a stack, and the top of the stack is stored
+// in the data `__anna_exception_handler` (it is a weak symbol, so the modules
+// share the same one after linking, and it is thread-local optionally):
+//
+// - `emit_try()` pushes the handler frame and calls `_setjmp()`, then the builder
+//   is switched to the body block.
+// - `emit_end_try()` pops the handler frame, it should be called before leaving
+//   the body (by the normal flow).
+// - `emit_raise(code)` calls `_longjmp()` with the top handler frame, then the
+//   execution continues at the catch block (the handler frame has been popped)
+//   with the error code as the block parameter. It traps if there is no handler.
+//
+// e.g.
+//
+// ```rust
+// generator.enable_exceptions(true)?;
+// let exception_refs = generator.declare_exceptions_in_func(&mut func).unwrap();
+// ...
+// let try_frame = exception_refs.emit_try(&mut function_builder);
+// let call_0 = function_builder.ins().call(...);    // may raise
+// exception_refs.emit_end_try(&mut function_builder, &try_frame);
+// function_builder.ins().jump(block_next, &[]);
+//
+// function_builder.switch_to_block(try_frame.catch_block);
+// let value_code = function_builder.block_params(try_frame.catch_block)[0];
+// ...
+// ```
+//
+// Note that the values which are modified in the body and used in the catch
+// block should be stored in stack slots (like the `volatile` variables in C),
+// since the registers and spill slots may be reused after `_setjmp()`.
+//
+// the native unwinding (by the `.eh_frame`) may take place of it in the future.
+
+const HANDLER_DATA_NAME: &str = "__anna_exception_handler";
+
+/// The trap code of raising an exception when there is no handler.
+pub const TRAP_CODE_UNCAUGHT_EXCEPTION: TrapCode = TrapCode::unwrap_user(3);
+
+/// The size of the handler frame, it is greater than the size of
+/// `jmp_buf` (plus the previous handler pointer) of the common targets,
+/// e.g. 200 bytes on x86_64 and 312 bytes on aarch64 (glibc).
+const HANDLER_FRAME_SIZE: u32 = 512;
+
+/// The offset of `jmp_buf` in the handler frame.
+const JMP_BUF_OFFSET: i32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionSymbols {
+    pub handler_data: DataId,
+    pub thread_local: bool,
+    pub setjmp: FuncId,
+    pub longjmp: FuncId,
+}
+
+/// The references of the exception handling symbols in a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionRefs {
+    pub handler_data: GlobalValue,
+    pub thread_local: bool,
+    pub setjmp: FuncRef,
+    pub longjmp: FuncRef,
+    pub pointer_type: Type,
+}
+
+/// An active `try`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TryFrame {
+    pub stack_slot: StackSlot,
+
+    /// The block which is executed when an exception is raised, it has
+    /// a parameter (i32) which is the error code.
+    pub catch_block: Block,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Enable the exception handling, the `__anna_exception_handler` is defined,
+    /// and the `_setjmp()` and `_longjmp()` are declared.
+    ///
+    /// Note that the thread-local data is not supported by the JIT module.
+    pub fn enable_exceptions(&mut self, thread_local: bool) -> Result<(), ModuleError> {
+        let pointer_type = self.module.isa().pointer_type();
+
+        let handler_data = self.module.declare_data(
+            HANDLER_DATA_NAME,
+            Linkage::Preemptible,
+            true,
+            thread_local,
+        )?;
+        self.define_data_content(
+            handler_data,
+            DataDefinition::Uninitialized {
+                size: pointer_type.bytes() as usize,
+                align: pointer_type.bytes() as u64,
+            },
+        )?;
+
+        let mut setjmp_sig = self.module.make_signature();
+        setjmp_sig.params.push(AbiParam::new(pointer_type));
+        setjmp_sig.returns.push(AbiParam::new(types::I32));
+
+        let mut longjmp_sig = self.module.make_signature();
+        longjmp_sig.params.push(AbiParam::new(pointer_type));
+        longjmp_sig.params.push(AbiParam::new(types::I32));
+
+        let setjmp = self
+            .module
+            .declare_function("_setjmp", Linkage::Import, &setjmp_sig)?;
+        let longjmp = self
+            .module
+            .declare_function("_longjmp", Linkage::Import, &longjmp_sig)?;
+
+        self.exceptions = Some(ExceptionSymbols {
+            handler_data,
+            thread_local,
+            setjmp,
+            longjmp,
+        });
+        Ok(())
+    }
+
+    /// Declare the exception handling symbols in the function, returns `None`
+    /// if the exception handling is not enabled.
+    pub fn declare_exceptions_in_func(&mut self, func: &mut Function) -> Option<ExceptionRefs> {
+        let symbols = self.exceptions?;
+        Some(ExceptionRefs {
+            handler_data: self.module.declare_data_in_func(symbols.handler_data, func),
+            thread_local: symbols.thread_local,
+            setjmp: self.module.declare_func_in_func(symbols.setjmp, func),
+            longjmp: self.module.declare_func_in_func(symbols.longjmp, func),
+            pointer_type: self.module.isa().pointer_type(),
+        })
+    }
+}
+
+impl ExceptionRefs {
+    fn emit_handler_data_addr(&self, function_builder: &mut FunctionBuilder) -> Value {
+        if self.thread_local {
+            function_builder
+                .ins()
+                .tls_value(self.pointer_type, self.handler_data)
+        } else {
+            function_builder
+                .ins()
+                .symbol_value(self.pointer_type, self.handler_data)
+        }
+    }
+
+    /// Push a handler frame and switch the builder to the body block.
+    pub fn emit_try(&self, function_builder: &mut FunctionBuilder) -> TryFrame {
+        let flags = MemFlags::trusted();
+
+        let stack_slot = function_builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            HANDLER_FRAME_SIZE,
+            4,
+        ));
+
+        let block_body = function_builder.create_block();
+        let block_unwind = function_builder.create_block();
+        let catch_block = function_builder.create_block();
+        function_builder.append_block_param(block_unwind, types::I32);
+        function_builder.append_block_param(catch_block, types::I32);
+
+        // frame.previous = handler; handler = &frame
+        let value_handler_addr = self.emit_handler_data_addr(function_builder);
+        let value_frame = function_builder
+            .ins()
+            .stack_addr(self.pointer_type, stack_slot, 0);
+        let value_previous =
+            function_builder
+                .ins()
+                .load(self.pointer_type, flags, value_handler_addr, 0);
+        function_builder
+            .ins()
+            .stack_store(value_previous, stack_slot, 0);
+        function_builder
+            .ins()
+            .store(flags, value_frame, value_handler_addr, 0);
+
+        let value_jmp_buf = function_builder
+            .ins()
+            .iadd_imm(value_frame, JMP_BUF_OFFSET as i64);
+        let call_setjmp = function_builder.ins().call(self.setjmp, &[value_jmp_buf]);
+        let value_code = function_builder.inst_results(call_setjmp)[0];
+        function_builder
+            .ins()
+            .brif(value_code, block_unwind, &[value_code], block_body, &[]);
+        function_builder.seal_block(block_unwind);
+        function_builder.seal_block(block_body);
+
+        // the handler frame is popped before entering the catch block,
+        // the values are reloaded since `_longjmp()` returns here.
+        function_builder.switch_to_block(block_unwind);
+        let value_code = function_builder.block_params(block_unwind)[0];
+        self.emit_pop_handler(function_builder, stack_slot);
+        function_builder.ins().jump(catch_block, &[value_code]);
+        function_builder.seal_block(catch_block);
+
+        function_builder.switch_to_block(block_body);
+
+        TryFrame {
+            stack_slot,
+            catch_block,
+        }
+    }
+
+    /// Pop the handler frame, it should be called before leaving the body block.
+    pub fn emit_end_try(&self, function_builder: &mut FunctionBuilder, try_frame: &TryFrame) {
+        self.emit_pop_handler(function_builder, try_frame.stack_slot);
+    }
+
+    fn emit_pop_handler(&self, function_builder: &mut FunctionBuilder, stack_slot: StackSlot) {
+        let value_previous = function_builder
+            .ins()
+            .stack_load(self.pointer_type, stack_slot, 0);
+        let value_handler_addr = self.emit_handler_data_addr(function_builder);
+        function_builder
+            .ins()
+            .store(MemFlags::trusted(), value_previous, value_handler_addr, 0);
+    }
+
+    /// Raise an exception with the error code (i32, it should not be zero),
+    /// the current block is terminated.
+    pub fn emit_raise(&self, function_builder: &mut FunctionBuilder, code: Value) {
+        let value_handler_addr = self.emit_handler_data_addr(function_builder);
+        let value_frame = function_builder.ins().load(
+            self.pointer_type,
+            MemFlags::trusted(),
+            value_handler_addr,
+            0,
+        );
+        emit_null_check(function_builder, value_frame, TRAP_CODE_UNCAUGHT_EXCEPTION);
+
+        let value_jmp_buf = function_builder
+            .ins()
+            .iadd_imm(value_frame, JMP_BUF_OFFSET as i64);
+        function_builder
+            .ins()
+            .call(self.longjmp, &[value_jmp_buf, code]);
+
+        // `_longjmp()` never returns
+        function_builder.ins().trap(TRAP_CODE_UNCAUGHT_EXCEPTION);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{
+        condcodes::IntCC, types, AbiParam, Function, InstBuilder, UserFuncName,
+    };
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+
+    use crate::code_generator::Generator;
+
+    #[test]
+    fn test_exceptions() {
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        generator.enable_exceptions(false).unwrap();
+
+        // ```rust
+        // fn check(a: i32) -> i32 {
+        //     if a > 10 {
+        //         raise(a);
+        //     }
+        //     a
+        // }
+        //
+        // fn main(a: i32) -> i32 {
+        //     try {
+        //         check(a)
+        //     } catch (code) {
+        //         code + 100
+        //     }
+        // }
+        // ```
+        let mut func_check_sig = generator.module.make_signature();
+        func_check_sig.params.push(AbiParam::new(types::I32));
+        func_check_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_check_id = generator
+            .module
+            .declare_function("check", Linkage::Local, &func_check_sig)
+            .unwrap();
+
+        let mut func_check = Function::with_name_signature(
+            UserFuncName::user(0, func_check_id.as_u32()),
+            func_check_sig.clone(),
+        );
+
+        let exception_refs = generator
+            .declare_exceptions_in_func(&mut func_check)
+            .unwrap();
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_check, &mut generator.function_builder_context);
+
+        let block_0 = function_builder.create_block();
+        let block_1 = function_builder.create_block();
+        let block_2 = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block_0);
+
+        function_builder.switch_to_block(block_0);
+        let value_0 = function_builder.block_params(block_0)[0];
+        let value_1 = function_builder
+            .ins()
+            .icmp_imm(IntCC::SignedGreaterThan, value_0, 10);
+        function_builder
+            .ins()
+            .brif(value_1, block_1, &[], block_2, &[]);
+
+        function_builder.switch_to_block(block_1);
+        exception_refs.emit_raise(&mut function_builder, value_0);
+
+        function_builder.switch_to_block(block_2);
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator
+            .define_function(func_check_id, func_check)
+            .unwrap();
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Local, &func_check_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_check_sig,
+        );
+
+        let func_check_ref = generator
+            .module
+            .declare_func_in_func(func_check_id, &mut func_main);
+        let exception_refs = generator
+            .declare_exceptions_in_func(&mut func_main)
+            .unwrap();
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block_0 = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block_0);
+
+        function_builder.switch_to_block(block_0);
+        let value_0 = function_builder.block_params(block_0)[0];
+        let try_frame = exception_refs.emit_try(&mut function_builder);
+
+        let call_0 = function_builder.ins().call(func_check_ref, &[value_0]);
+        let value_1 = function_builder.inst_results(call_0)[0];
+        exception_refs.emit_end_try(&mut function_builder, &try_frame);
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.switch_to_block(try_frame.catch_block);
+        let value_code = function_builder.block_params(try_frame.catch_block)[0];
+        let value_2 = function_builder.ins().iadd_imm(value_code, 100);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let func_main: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(func_main_ptr) };
+
+        assert_eq!(func_main(5), 5);
+        assert_eq!(func_main(20), 120);
+
+        // the handler frames are balanced
+        assert_eq!(func_main(7), 7);
+        assert_eq!(func_main(30), 130);
+    }
+}
@@ -0,0 +1,891 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use crate::{
+    ast::{
+        DataKind, DataNode, DataValue, FunctionNode, ImportNode, Instruction, InstructionKind,
+        LocalNode, Module, ValueType,
+    },
+    branch_weight::BranchWeight,
+    diagnostic::Diagnostic,
+    function_attribute::FunctionAttributes,
+    inline_asm::format_template,
+    lexer::{tokenize, Span, TokenKind},
+};
+
+// The formatter
+// -------------
+//
+// Print the assembly text in the canonical style, e.g.
+//
+// ```text
+// (module $app
+//     // the entry
+//     (function $main export (result i32)
+//         (code
+//             (call $print (host_addr_data $message)) // trailing comment
+//             (add_i32 (imm_i32 11) (imm_i32 2)))))
+// ```
+//
+// - the indentation is 4 spaces, and the closing parentheses are placed at the
+//   end of the last line (unless the line ends with a line comment).
+// - a list is printed in one line if it fits in `MAX_WIDTH` columns and
+//   contains no comments, except the `module` which is always broken.
+// - a broken list keeps the keyword and the leading arguments (i.e. the atoms
+//   and the flat lists such as `(param $a i32)`) in the first line, and the
+//   remaining items are placed in the following lines, one per line.
+// - the comments are kept, the comment at the end of a line stays at the end
+//   of the line, and the others are placed in their own lines.
+// - the blank lines between items are kept, and the consecutive blank lines are
+//   merged into one.
+//
+// `format_source()` formats the source text (the atoms are kept verbatim),
+// and `format_module()` prints the AST (e.g. a module which is generated by
+// a program) in the same style.
+
+const INDENT: &str = "    ";
+
+/// The maximum width of a line, the longer lists are broken into lines.
+pub const MAX_WIDTH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+enum FormatNode {
+    List(Vec<FormatNode>),
+    Atom(String),
+    Comment {
+        text: String,
+
+        /// The line comment (i.e. `// ...`), it should be followed by a new line.
+        is_line: bool,
+
+        /// The comment is at the end of the line of the previous node.
+        is_trailing: bool,
+    },
+
+    /// The blank line(s) between nodes.
+    BlankLine,
+}
+
+/// Format the source text, the result is the same if it is formatted again.
+pub fn format_source(source: &str) -> Result<String, Diagnostic> {
+    let nodes = build_nodes(source)?;
+    let mut printer = Printer::default();
+    printer.write_top_level(&nodes);
+    Ok(printer.output)
+}
+
+/// Print the module in the canonical style.
+///
+/// Note that the AST does not contain the comments, the macros, the constants
+/// and the conditional blocks, i.e. they are printed in the expanded form.
+pub fn format_module(module: &Module) -> String {
+    let mut printer = Printer::default();
+    printer.write_top_level(&[convert_module(module)]);
+    printer.output
+}
+
+fn build_nodes(source: &str) -> Result<Vec<FormatNode>, Diagnostic> {
+    let tokens = tokenize(source)?;
+
+    // the stack of the unclosed lists, i.e. (the start position, the items)
+    let mut stack: Vec<(usize, Vec<FormatNode>)> = vec![(0, vec![])];
+    let mut previous_end: Option<usize> = None;
+
+    for token in &tokens {
+        let span = token.span;
+        let line_breaks = previous_end
+            .map(|end| source[end..span.start].matches('\n').count())
+            .unwrap_or(0);
+        previous_end = Some(span.end);
+
+        let items = &mut stack.last_mut().unwrap().1;
+        if line_breaks > 1
+            && !matches!(token.kind, TokenKind::RightParen)
+            && !matches!(items.last(), None | Some(FormatNode::BlankLine))
+        {
+            items.push(FormatNode::BlankLine);
+        }
+
+        match &token.kind {
+            TokenKind::Comment(_) => {
+                let text = source[span.start..span.end].trim_end().to_owned();
+                items.push(FormatNode::Comment {
+                    is_line: text.starts_with("//"),
+                    is_trailing: line_breaks == 0
+                        && !matches!(items.last(), None | Some(FormatNode::BlankLine)),
+                    text,
+                });
+            }
+            TokenKind::LeftParen => stack.push((span.start, vec![])),
+            TokenKind::RightParen => {
+                if stack.len() == 1 {
+                    return Err(Diagnostic::new("unexpected ')'", span));
+                }
+                let (_, mut items) = stack.pop().unwrap();
+                if items.last() == Some(&FormatNode::BlankLine) {
+                    items.pop();
+                }
+                stack.last_mut().unwrap().1.push(FormatNode::List(items));
+            }
+            _ => items.push(FormatNode::Atom(source[span.start..span.end].to_owned())),
+        }
+    }
+
+    if stack.len() > 1 {
+        let (start, _) = stack.pop().unwrap();
+        return Err(Diagnostic::new(
+            "the list is not closed, expect ')'",
+            Span::new(start, start + 1),
+        ));
+    }
+
+    Ok(stack.pop().unwrap().1)
+}
+
+#[derive(Default)]
+struct Printer {
+    output: String,
+
+    /// The output ends with a line comment, so the next item (including
+    /// the closing parenthesis) should be placed in a new line.
+    ends_with_line_comment: bool,
+}
+
+impl Printer {
+    fn write_top_level(&mut self, nodes: &[FormatNode]) {
+        let mut has_blank_line = false;
+        for node in nodes {
+            match node {
+                FormatNode::BlankLine => has_blank_line = true,
+                FormatNode::Comment {
+                    text,
+                    is_line,
+                    is_trailing: true,
+                } => {
+                    self.output.push(' ');
+                    self.output.push_str(text);
+                    self.ends_with_line_comment = *is_line;
+                }
+                _ => {
+                    if !self.output.is_empty() {
+                        self.output.push('\n');
+                        if has_blank_line {
+                            self.output.push('\n');
+                        }
+                    }
+                    has_blank_line = false;
+                    self.write_node(node, 0);
+                }
+            }
+        }
+
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+    }
+
+    /// Write the node at the current position, which is at the indentation
+    /// of the level.
+    fn write_node(&mut self, node: &FormatNode, level: usize) {
+        match node {
+            FormatNode::List(items) => match get_inline_text(node) {
+                Some(text) if level * INDENT.len() + text.len() <= MAX_WIDTH => {
+                    self.output.push_str(&text);
+                    self.ends_with_line_comment = false;
+                }
+                _ => self.write_broken_list(items, level),
+            },
+            FormatNode::Atom(text) => {
+                self.output.push_str(text);
+                self.ends_with_line_comment = false;
+            }
+            FormatNode::Comment { text, is_line, .. } => {
+                self.output.push_str(text);
+                self.ends_with_line_comment = *is_line;
+            }
+            FormatNode::BlankLine => unreachable!(),
+        }
+    }
+
+    fn write_broken_list(&mut self, items: &[FormatNode], level: usize) {
+        self.output.push('(');
+        let mut width = level * INDENT.len() + 1;
+
+        // the first line, i.e. the keyword and the leading arguments
+        let mut index = 0;
+        while let Some(item) = items.get(index) {
+            let text = match item {
+                FormatNode::Atom(text) if !text.contains('\n') || index == 0 => text.clone(),
+                FormatNode::List(children)
+                    if children
+                        .iter()
+                        .all(|child| matches!(child, FormatNode::Atom(_))) =>
+                {
+                    match get_inline_text(item) {
+                        Some(text) => text,
+                        None => break,
+                    }
+                }
+                _ => break,
+            };
+            if index > 0 {
+                if width + 1 + text.len() > MAX_WIDTH {
+                    break;
+                }
+                self.output.push(' ');
+                width += 1;
+            }
+            self.output.push_str(&text);
+            width += text.len();
+            self.ends_with_line_comment = false;
+            index += 1;
+        }
+
+        // the remaining items, one per line
+        let mut has_blank_line = false;
+        for item in &items[index..] {
+            match item {
+                FormatNode::BlankLine => has_blank_line = true,
+                FormatNode::Comment {
+                    text,
+                    is_line,
+                    is_trailing: true,
+                } => {
+                    self.output.push(' ');
+                    self.output.push_str(text);
+                    self.ends_with_line_comment = *is_line;
+                }
+                _ => {
+                    self.output.push('\n');
+                    if has_blank_line {
+                        self.output.push('\n');
+                    }
+                    has_blank_line = false;
+                    self.write_indent(level + 1);
+                    self.write_node(item, level + 1);
+                }
+            }
+        }
+
+        if self.ends_with_line_comment {
+            self.output.push('\n');
+            self.write_indent(level);
+        }
+        self.output.push(')');
+        self.ends_with_line_comment = false;
+    }
+
+    fn write_indent(&mut self, level: usize) {
+        for _ in 0..level {
+            self.output.push_str(INDENT);
+        }
+    }
+}
+
+/// Get the text of the node in one line, or `None` if the node contains
+/// comments or multiple-line strings, or it is a `module`.
+fn get_inline_text(node: &FormatNode) -> Option<String> {
+    match node {
+        FormatNode::Atom(text) => (!text.contains('\n')).then(|| text.clone()),
+        FormatNode::List(items) => {
+            if matches!(items.first(), Some(FormatNode::Atom(keyword)) if keyword == "module") {
+                return None;
+            }
+            let texts = items
+                .iter()
+                .filter(|item| **item != FormatNode::BlankLine)
+                .map(get_inline_text)
+                .collect::<Option<Vec<_>>>()?;
+            Some(format!("({})", texts.join(" ")))
+        }
+        FormatNode::Comment { .. } | FormatNode::BlankLine => None,
+    }
+}
+
+fn atom(text: &str) -> FormatNode {
+    FormatNode::Atom(text.to_owned())
+}
+
+fn name(name: &str) -> FormatNode {
+    FormatNode::Atom(format!("${}", name))
+}
+
+fn number(value: impl ToString) -> FormatNode {
+    FormatNode::Atom(value.to_string())
+}
+
+fn float(value: f64) -> FormatNode {
+    FormatNode::Atom(format!("{:?}", value))
+}
+
+/// The string literal, the bytes which are not the printable characters are escaped.
+fn string(bytes: &[u8]) -> FormatNode {
+    let mut text = String::from("\"");
+    match std::str::from_utf8(bytes) {
+        Ok(content) => {
+            for c in content.chars() {
+                match c {
+                    '"' => text.push_str("\\\""),
+                    '\\' => text.push_str("\\\\"),
+                    '\n' => text.push_str("\\n"),
+                    '\r' => text.push_str("\\r"),
+                    '\t' => text.push_str("\\t"),
+                    '\0' => text.push_str("\\0"),
+                    _ if c.is_control() => text.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                    _ => text.push(c),
+                }
+            }
+        }
+        Err(_) => {
+            for byte in bytes {
+                match byte {
+                    b'"' => text.push_str("\\\""),
+                    b'\\' => text.push_str("\\\\"),
+                    0x20..=0x7e => text.push(*byte as char),
+                    _ => text.push_str(&format!("\\x{:02x}", byte)),
+                }
+            }
+        }
+    }
+    text.push('"');
+    FormatNode::Atom(text)
+}
+
+fn list(keyword: &str, mut items: Vec<FormatNode>) -> FormatNode {
+    items.insert(0, atom(keyword));
+    FormatNode::List(items)
+}
+
+/// The weight of the branch, e.g. `(weight 1000 3)` or `(weight 0.99)`.
+fn weight_list(weight: BranchWeight) -> FormatNode {
+    match weight {
+        BranchWeight::Counts { taken, not_taken } => {
+            list("weight", vec![number(taken), number(not_taken)])
+        }
+        BranchWeight::Probability(probability) => list("weight", vec![float(probability)]),
+    }
+}
+
+/// The type list, e.g. `(param i32 i64)`, nothing if there is no type.
+fn type_list(keyword: &str, value_types: &[ValueType]) -> Option<FormatNode> {
+    (!value_types.is_empty()).then(|| {
+        list(
+            keyword,
+            value_types
+                .iter()
+                .map(|value_type| atom(value_type.name()))
+                .collect(),
+        )
+    })
+}
+
+fn local_list(keyword: &str, local: &LocalNode) -> FormatNode {
+    list(
+        keyword,
+        vec![name(&local.name), atom(local.value_type.name())],
+    )
+}
+
+fn convert_module(module: &Module) -> FormatNode {
+    let mut items = vec![name(&module.name)];
+
+    let groups = [
+        module
+            .module_imports
+            .iter()
+            .map(|node| {
+                list(
+                    "import",
+                    vec![list("module", vec![string(node.path.as_bytes())])],
+                )
+            })
+            .collect::<Vec<_>>(),
+        module.imports.iter().map(convert_import).collect(),
+        module.data.iter().map(convert_data).collect(),
+    ];
+    for group in groups.into_iter().filter(|group| !group.is_empty()) {
+        if items.len() > 1 {
+            items.push(FormatNode::BlankLine);
+        }
+        items.extend(group);
+    }
+
+    for function in &module.functions {
+        if items.len() > 1 {
+            items.push(FormatNode::BlankLine);
+        }
+        items.push(convert_function(function));
+    }
+
+    for test in &module.tests {
+        if items.len() > 1 {
+            items.push(FormatNode::BlankLine);
+        }
+        items.push(convert_test(test));
+    }
+
+    list("module", items)
+}
+
+fn attribute_items(attributes: &FunctionAttributes) -> Vec<FormatNode> {
+    let mut items = vec![];
+    if attributes.noreturn {
+        items.push(atom("noreturn"));
+    }
+    if let Some(effect) = attributes.effect.name() {
+        items.push(atom(effect));
+    }
+    items
+}
+
+fn convert_import(node: &ImportNode) -> FormatNode {
+    match node {
+        ImportNode::Function(node) if node.variadic || node.library.is_some() => {
+            let mut items = vec![string(node.symbol.as_bytes())];
+            if node.variadic {
+                items.push(atom("variadic"));
+            }
+            items.extend(attribute_items(&node.attributes));
+            if let Some(result_abi) = node.result_abi.name() {
+                items.push(atom(result_abi));
+            }
+            items.extend(type_list("params", &node.params));
+            items.extend(type_list("results", &node.results));
+            if let Some(library) = &node.library {
+                items.push(list("library", vec![string(library.as_bytes())]));
+            }
+            list("extern-c", items)
+        }
+        ImportNode::Function(node) => {
+            let mut items = vec![name(&node.name)];
+            if node.symbol != node.name {
+                items.push(string(node.symbol.as_bytes()));
+            }
+            if node.colocated {
+                items.push(atom("colocated"));
+            }
+            items.extend(attribute_items(&node.attributes));
+            if let Some(result_abi) = node.result_abi.name() {
+                items.push(atom(result_abi));
+            }
+            items.extend(type_list("param", &node.params));
+            items.extend(type_list("result", &node.results));
+            list("import", vec![list("function", items)])
+        }
+        ImportNode::Data(node) => {
+            let mut items = vec![name(&node.name)];
+            if node.symbol != node.name {
+                items.push(string(node.symbol.as_bytes()));
+            }
+            if node.tls {
+                items.push(atom("tls"));
+            }
+            if let Some(size) = node.size {
+                items.push(list("size", vec![number(size)]));
+            }
+            if let Some(align) = node.align {
+                items.push(list("align", vec![number(align)]));
+            }
+            list("import", vec![list("data", items)])
+        }
+    }
+}
+
+fn convert_data(node: &DataNode) -> FormatNode {
+    let mut items = vec![name(&node.name)];
+    if node.export {
+        items.push(atom("export"));
+    }
+    if let Some(align) = node.align {
+        items.push(list("align", vec![number(align)]));
+    }
+    if let Some(section) = &node.section {
+        items.push(list("section", vec![string(section.as_bytes())]));
+    }
+    items.push(match &node.kind {
+        DataKind::ReadOnly(value) => list("read_only", convert_data_value(value)),
+        DataKind::ReadWrite(value) => list("read_write", convert_data_value(value)),
+        DataKind::Uninit { size, align } => list("uninit", vec![number(size), number(align)]),
+    });
+    list("data", items)
+}
+
+/// The type name and the values, e.g. `i32 100`.
+fn convert_data_value(value: &DataValue) -> Vec<FormatNode> {
+    let convert_item = |value: &DataValue| match value {
+        DataValue::I8(value) => vec![number(*value as i8)],
+        DataValue::I16(value) => vec![number(*value as i16)],
+        DataValue::I32(value) => vec![number(*value as i32)],
+        DataValue::I64(value) => vec![number(*value as i64)],
+        DataValue::F32(value) => vec![float(*value as f64)],
+        DataValue::F64(value) => vec![float(*value)],
+        DataValue::DataAddress {
+            name: data_name,
+            offset,
+        } => std::iter::once(name(data_name))
+            .chain((*offset != 0).then(|| number(offset)))
+            .collect(),
+        DataValue::FunctionAddress(function_name) => vec![name(function_name)],
+        _ => unreachable!(),
+    };
+    let type_name = |value: &DataValue| match value {
+        DataValue::I8(_) => "i8",
+        DataValue::I16(_) => "i16",
+        DataValue::I32(_) => "i32",
+        DataValue::I64(_) => "i64",
+        DataValue::F32(_) => "f32",
+        DataValue::DataAddress { .. } => "addr_data",
+        DataValue::FunctionAddress(_) => "addr_function",
+        _ => "f64",
+    };
+
+    match value {
+        DataValue::Bytes(bytes) => vec![atom("bytes"), string(bytes)],
+        DataValue::CString(bytes) => vec![atom("cstring"), string(bytes)],
+        DataValue::LString(bytes) => vec![atom("lstring"), string(bytes)],
+        DataValue::Zero(size) => vec![atom("zero"), number(size)],
+        DataValue::Array(values) => std::iter::once(atom(type_name(&values[0])))
+            .chain(values.iter().flat_map(convert_item))
+            .collect(),
+        _ => std::iter::once(atom(type_name(value)))
+            .chain(convert_item(value))
+            .collect(),
+    }
+}
+
+fn convert_function(node: &FunctionNode) -> FormatNode {
+    let mut items = vec![name(&node.name)];
+    if node.export {
+        items.push(atom("export"));
+    }
+    if let Some(result_abi) = node.result_abi.name() {
+        items.push(atom(result_abi));
+    }
+    if let Some(align) = node.align {
+        items.push(list("align", vec![number(align)]));
+    }
+    if !node.target_clones.is_empty() {
+        let feature_sets = node
+            .target_clones
+            .iter()
+            .map(|features| {
+                let names = features
+                    .iter()
+                    .map(|feature| feature.name())
+                    .collect::<Vec<_>>();
+                string(names.join(",").as_bytes())
+            })
+            .collect();
+        items.push(list("target_clones", feature_sets));
+    }
+    items.extend(node.params.iter().map(|param| local_list("param", param)));
+    items.extend(type_list("result", &node.results));
+    items.extend(node.locals.iter().map(|local| local_list("local", local)));
+    items.push(list(
+        "code",
+        node.body.iter().map(convert_instruction).collect(),
+    ));
+    list("function", items)
+}
+
+/// The assertions of the test are kept in the converted form, see `test_harness.rs`.
+fn convert_test(node: &FunctionNode) -> FormatNode {
+    let mut items = vec![name(&node.name)];
+    items.extend(node.locals.iter().map(|local| local_list("local", local)));
+    items.push(list(
+        "code",
+        node.body.iter().map(convert_instruction).collect(),
+    ));
+    list("test", items)
+}
+
+fn convert_instructions(instructions: &[Instruction]) -> impl Iterator<Item = FormatNode> + '_ {
+    instructions.iter().map(convert_instruction)
+}
+
+fn convert_instruction(instruction: &Instruction) -> FormatNode {
+    let offset_items = |offset: i32| (offset != 0).then(|| number(offset));
+
+    match &instruction.kind {
+        InstructionKind::Nop => list("nop", vec![]),
+        InstructionKind::ImmI32(value) => list("imm_i32", vec![number(*value as i32)]),
+        InstructionKind::ImmI64(value) => list("imm_i64", vec![number(*value as i64)]),
+        InstructionKind::ImmF32(value) => list("imm_f32", vec![float(*value as f64)]),
+        InstructionKind::ImmF64(value) => list("imm_f64", vec![float(*value)]),
+        InstructionKind::LocalLoad(local_name) => list("local_load", vec![name(local_name)]),
+        InstructionKind::LocalStore {
+            name: local_name,
+            value,
+        } => list(
+            "local_store",
+            vec![name(local_name), convert_instruction(value)],
+        ),
+        InstructionKind::DataLoad {
+            load_type,
+            name: data_name,
+            offset,
+        } => {
+            let mut items = vec![name(data_name)];
+            items.extend(offset_items(*offset));
+            list(&format!("data_load_{}", load_type.suffix()), items)
+        }
+        InstructionKind::DataStore {
+            store_type,
+            name: data_name,
+            offset,
+            value,
+        } => {
+            let mut items = vec![name(data_name)];
+            items.extend(offset_items(*offset));
+            items.push(convert_instruction(value));
+            list(&format!("data_store_{}", store_type.suffix()), items)
+        }
+        InstructionKind::MemoryLoad {
+            load_type,
+            address,
+            offset,
+        } => {
+            let mut items = vec![convert_instruction(address)];
+            items.extend(offset_items(*offset));
+            list(&format!("memory_load_{}", load_type.suffix()), items)
+        }
+        InstructionKind::MemoryStore {
+            store_type,
+            address,
+            offset,
+            value,
+        } => {
+            let mut items = vec![convert_instruction(address)];
+            items.extend(offset_items(*offset));
+            items.push(convert_instruction(value));
+            list(&format!("memory_store_{}", store_type.suffix()), items)
+        }
+        InstructionKind::HostAddrFunction(function_name) => {
+            list("host_addr_function", vec![name(function_name)])
+        }
+        InstructionKind::HostAddrData {
+            name: data_name,
+            offset,
+        } => {
+            let mut items = vec![name(data_name)];
+            items.extend(offset_items(*offset));
+            list("host_addr_data", items)
+        }
+        InstructionKind::Operation { opcode, operands } => {
+            list(opcode.name(), convert_instructions(operands).collect())
+        }
+        InstructionKind::Do(instructions) => {
+            list("do", convert_instructions(instructions).collect())
+        }
+        InstructionKind::If {
+            results,
+            weight,
+            condition,
+            consequent,
+            alternative,
+        } => {
+            let mut items = type_list("result", results).into_iter().collect::<Vec<_>>();
+            items.extend(weight.map(weight_list));
+            items.extend([
+                convert_instruction(condition),
+                convert_instruction(consequent),
+                convert_instruction(alternative),
+            ]);
+            list("if", items)
+        }
+        InstructionKind::When {
+            weight,
+            condition,
+            body,
+        } => {
+            let mut items = weight.map(weight_list).into_iter().collect::<Vec<_>>();
+            items.push(convert_instruction(condition));
+            items.extend(convert_instructions(body));
+            list("when", items)
+        }
+        InstructionKind::For {
+            params,
+            results,
+            body,
+        } => {
+            let mut items = params
+                .iter()
+                .map(|(local, init)| {
+                    list(
+                        "param",
+                        vec![
+                            name(&local.name),
+                            atom(local.value_type.name()),
+                            convert_instruction(init),
+                        ],
+                    )
+                })
+                .collect::<Vec<_>>();
+            items.extend(type_list("result", results));
+            items.extend(convert_instructions(body));
+            list("for", items)
+        }
+        InstructionKind::Break(values) => list("break", convert_instructions(values).collect()),
+        InstructionKind::Recur(values) => list("recur", convert_instructions(values).collect()),
+        InstructionKind::BreakFn(values) => {
+            list("break_fn", convert_instructions(values).collect())
+        }
+        InstructionKind::RecurFn(values) => {
+            list("recur_fn", convert_instructions(values).collect())
+        }
+        InstructionKind::Call {
+            name: function_name,
+            args,
+        } => {
+            let mut items = vec![name(function_name)];
+            items.extend(convert_instructions(args));
+            list("call", items)
+        }
+        InstructionKind::DynCall {
+            params,
+            results,
+            callee,
+            args,
+        } => {
+            let mut items = type_list("param", params).into_iter().collect::<Vec<_>>();
+            items.extend(type_list("result", results));
+            items.push(convert_instruction(callee));
+            items.extend(convert_instructions(args));
+            list("dyncall", items)
+        }
+        InstructionKind::Clif {
+            params,
+            results,
+            text,
+            args,
+        } => {
+            let mut items = type_list("param", params).into_iter().collect::<Vec<_>>();
+            items.extend(type_list("result", results));
+            items.push(string(text.as_bytes()));
+            items.extend(convert_instructions(args));
+            list("clif", items)
+        }
+        InstructionKind::Asm {
+            template,
+            inputs,
+            outputs,
+            clobbers,
+        } => {
+            let mut items = vec![string(format_template(template).as_bytes())];
+            items.extend(inputs.iter().map(|(register, value)| {
+                list(
+                    "in",
+                    vec![string(register.as_bytes()), convert_instruction(value)],
+                )
+            }));
+            items.extend(
+                outputs
+                    .iter()
+                    .map(|(register, _)| list("out", vec![string(register.as_bytes())])),
+            );
+            if !clobbers.is_empty() {
+                items.push(list(
+                    "clobber",
+                    clobbers
+                        .iter()
+                        .map(|register| string(register.as_bytes()))
+                        .collect(),
+                ));
+            }
+            list("asm", items)
+        }
+        InstructionKind::Panic(code) => list("panic", vec![number(code)]),
+        InstructionKind::Exit(code) => list("exit", vec![convert_instruction(code)]),
+        InstructionKind::Abort => list("abort", vec![]),
+        InstructionKind::ReadCycleCounter => list("read_cycle_counter", vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        diagnostic::Diagnostic,
+        formatter::{format_module, format_source},
+        parser::parse_module,
+    };
+
+    #[test]
+    fn test_format() {
+        let source = r#"
+// the header comment
+(module $app   (import (function $print (param i64)   (result i32)))
+  (data $message (read_only cstring "Hello\n"))
+
+
+  (function $main export (result i32) (local $count i32) // the entry
+    (code (call $print (host_addr_data $message))
+          (local_store $count (add_i32 (imm_i32 0x10) (imm_i32 1_000)))
+          /* the result */
+          (add_i32 (local_load $count) (imm_i32 2)) // trailing
+    )
+  )
+  (function $long (param $first_argument i64) (param $second_argument i64) (result i64) (code (add_i64 (local_load $first_argument) (local_load $second_argument)))))
+"#;
+
+        let expected = r#"// the header comment
+(module $app
+    (import (function $print (param i64) (result i32)))
+    (data $message (read_only cstring "Hello\n"))
+
+    (function $main export (result i32) (local $count i32) // the entry
+        (code
+            (call $print (host_addr_data $message))
+            (local_store $count (add_i32 (imm_i32 0x10) (imm_i32 1_000)))
+            /* the result */
+            (add_i32 (local_load $count) (imm_i32 2)) // trailing
+        ))
+    (function $long (param $first_argument i64) (param $second_argument i64) (result i64)
+        (code (add_i64 (local_load $first_argument) (local_load $second_argument)))))
+"#;
+
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+
+        // the formatting is idempotent
+        assert_eq!(format_source(&formatted).unwrap(), expected);
+
+        // print the AST
+        let module = parse_module(source).unwrap();
+        let printed = format_module(&module);
+        assert_eq!(
+            printed,
+            r#"(module $app
+    (import (function $print (param i64) (result i32)))
+
+    (data $message (read_only cstring "Hello\n"))
+
+    (function $main export (result i32) (local $count i32)
+        (code
+            (call $print (host_addr_data $message))
+            (local_store $count (add_i32 (imm_i32 16) (imm_i32 1000)))
+            (add_i32 (local_load $count) (imm_i32 2))))
+
+    (function $long (param $first_argument i64) (param $second_argument i64) (result i64)
+        (code (add_i64 (local_load $first_argument) (local_load $second_argument)))))
+"#
+        );
+        assert_eq!(parse_module(&printed).unwrap().functions.len(), 2);
+
+        // the addresses and the offsets
+        let source = r#"(module $app
+    (data $table (read_only addr_data $message 2 $buffer))
+    (data $handlers (read_only addr_function $main))
+
+    (function $main (result i64) (code (host_addr_data $table 8))))
+"#;
+        assert_eq!(format_module(&parse_module(source).unwrap()), source);
+
+        let Diagnostic { message, .. } = format_source("(module $a (function $f)").unwrap_err();
+        assert_eq!(message, "the list is not closed, expect ')'");
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::HashSet;
+
+use cranelift_codegen::ir::Function;
+use cranelift_module::{FuncId, Module};
+
+use crate::{code_generator::Generator, inliner::get_callee_id};
+
+// The function attributes
+// -----------------------
+//
+// The attributes describe the behavior of the declared functions (usually
+// the imported runtime helpers and the C functions) which can not be seen by
+// the assembler, e.g.
+//
+// ```clojure
+// (import (function $rt_panic noreturn (param i32)))
+// (extern-c "sqrt" pure (params f64) (results f64) (library "m"))
+// (extern-c "strlen" readonly (params i64) (results i64))
+// ```
+//
+// or `generator.set_function_attributes(func_id, attributes)` for the front ends.
+//
+// - `noreturn`: the function never returns (e.g. `exit()` and the panic
+//   handlers), the call is followed by a `trap` (see `process_exit.rs`), so
+//   Cranelift removes the code after it, and the function is never inlined.
+// - `pure`: the function has no side effects and does not read the memory,
+//   i.e. the results depend only on the arguments, e.g. `sqrt()`.
+// - `readonly`: the function has no side effects but may read the memory,
+//   e.g. `strlen()`.
+//
+// Cranelift does not model the effects of the calls, so the calls of the
+// `pure` and `readonly` functions whose results are unused are removed by
+// the generator before the function passes (see `function_pass.rs`) and by
+// `eliminate_dead_code()` (so the callees may become unreachable).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FunctionEffect {
+    /// The function may have any side effects.
+    #[default]
+    Any,
+
+    /// The function only reads the memory.
+    ReadOnly,
+
+    /// The function neither writes nor reads the memory.
+    Pure,
+}
+
+impl FunctionEffect {
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            FunctionEffect::Any => None,
+            FunctionEffect::ReadOnly => Some("readonly"),
+            FunctionEffect::Pure => Some("pure"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FunctionAttributes {
+    /// The function never returns.
+    pub noreturn: bool,
+    pub effect: FunctionEffect,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Set the attributes of the declared function.
+    pub fn set_function_attributes(&mut self, func_id: FuncId, attributes: FunctionAttributes) {
+        self.function_attributes.insert(func_id, attributes);
+    }
+
+    pub fn get_function_attributes(&self, func_id: FuncId) -> FunctionAttributes {
+        self.function_attributes
+            .get(&func_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Remove the calls of the `pure` and `readonly` functions whose results
+    /// are unused.
+    ///
+    /// Returns the number of the removed calls.
+    pub fn remove_unused_calls(&self, func: &mut Function) -> usize {
+        if self.function_attributes.is_empty() {
+            return 0;
+        }
+
+        let mut count = 0;
+
+        // the arguments of a removed call may be the results of another call
+        loop {
+            let insts = func
+                .layout
+                .blocks()
+                .flat_map(|block| func.layout.block_insts(block))
+                .collect::<Vec<_>>();
+
+            let used_values = insts
+                .iter()
+                .flat_map(|inst| func.dfg.inst_values(*inst))
+                .map(|value| func.dfg.resolve_aliases(value))
+                .collect::<HashSet<_>>();
+
+            let unused_calls = insts
+                .into_iter()
+                .filter(|inst| {
+                    let Some(callee_id) = get_callee_id(func, *inst) else {
+                        return false;
+                    };
+
+                    let attributes = self.get_function_attributes(callee_id);
+                    attributes.effect != FunctionEffect::Any
+                        && !attributes.noreturn
+                        && func
+                            .dfg
+                            .inst_results(*inst)
+                            .iter()
+                            .all(|value| !used_values.contains(value))
+                })
+                .collect::<Vec<_>>();
+
+            if unused_calls.is_empty() {
+                return count;
+            }
+
+            for inst in unused_calls {
+                func.layout.remove_inst(inst);
+                count += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{code_generator::Generator, lowering::assemble_module, parser::parse_module};
+
+    static ANSWER_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn get_answer() -> i32 {
+        ANSWER_CALLS.fetch_add(1, Ordering::SeqCst);
+        42
+    }
+
+    extern "C" fn fail(code: i32) {
+        std::process::exit(code);
+    }
+
+    #[test]
+    fn test_function_attributes() {
+        let source = r#"
+        (module $test
+            (import (function $get_answer pure (result i32)))
+            (import (function $fail noreturn (param i32)))
+            (function $test (param $x i32) (result i32)
+                (code
+                    (call $get_answer)
+                    (when (eqz_i32 (local_load $x))
+                        (call $fail (imm_i32 1)))
+                    (add_i32 (local_load $x) (call $get_answer)))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![
+            ("get_answer".to_owned(), get_answer as *const u8),
+            ("fail".to_owned(), fail as *const u8),
+        ]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+
+        // the unused call is removed, and the call of `fail` is followed by a trap
+        let clif = generator.dump_clif();
+        assert_eq!(clif.matches("call fn0").count(), 1);
+        assert!(clif.contains("trap user5"));
+
+        generator.module.finalize_definitions().unwrap();
+        let func_test_ptr = generator
+            .module
+            .get_finalized_function(assembled_module.get_function_id("test").unwrap());
+        let func_test: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(func_test_ptr) };
+        assert_eq!(func_test(5), 47);
+        assert_eq!(ANSWER_CALLS.load(Ordering::SeqCst), 1);
+    }
+}
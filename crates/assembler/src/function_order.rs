@@ -0,0 +1,358 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{cmp::Reverse, collections::HashMap, path::Path};
+
+use cranelift_module::{FuncId, Module};
+
+use crate::{code_generator::Generator, coverage::Coverage};
+
+// The profile-guided function order
+// ---------------------------------
+//
+// The functions of a large program are laid out in the order of the source by
+// default, so the hot functions are scattered among the cold ones, which wastes
+// the instruction cache and the TLB. the call counts of the functions (i.e. a
+// profile) put the hot functions together:
+//
+// 1. the generator defines the functions of the assembly (see `assemble_module()`
+//    and `define_functions_in_parallel()`) in the descending order of the
+//    call counts, i.e. the functions are placed in `.text` in this order, the
+//    functions which are never called or not in the profile follow in their
+//    original order, e.g.
+//
+//    ```rust
+//    let profile = read_function_profile("anna.profile")?;
+//    generator.set_function_profile(profile);
+//    ```
+//
+// 2. the linker orders the functions across the object files by the ordering
+//    file, see `LinkerOptions::ordering_file` and `FunctionProfile::write_ordering_file()`.
+//
+// the profile file is a text file, each line is the call count and the symbol
+// name of a function, and the lines which start with `#` are comments, e.g.
+//
+// ```text
+// # calls  function
+// 1048576  fib
+// 1        main
+// ```
+//
+// it can be written by the hooks of the function instrumentation (see
+// `enable_function_instrumentation()`) or converted from the samples of
+// `perf report`, and the coverage counts can be converted to a profile by
+// `Coverage::get_function_profile()`.
+//
+// ref:
+// - https://lld.llvm.org/ELF/linker_script.html
+// - https://sourceware.org/binutils/docs/ld/Options.html
+// - https://dl.acm.org/doi/10.1145/93548.93550 (Pettis and Hansen, Profile Guided Code Positioning)
+
+/// The call counts of the functions, the key is the symbol name.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FunctionProfile {
+    pub counts: HashMap<String, u64>,
+}
+
+/// The format of the ordering file of the linker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderingFileFormat {
+    /// The symbol names, i.e. the `--symbol-ordering-file` of 'ld.lld'.
+    Symbols,
+
+    /// The section names of the functions (i.e. `.text.<name>`), i.e. the
+    /// `--section-ordering-file` of 'ld.gold', the functions should be placed
+    /// in their own sections (see `ObjectOptions::function_sections`).
+    Sections,
+}
+
+impl FunctionProfile {
+    /// Parse the text of the profile file, the counts of the same function
+    /// are added up.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut counts = HashMap::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            match (
+                fields.next().and_then(|field| field.parse::<u64>().ok()),
+                fields.next(),
+                fields.next(),
+            ) {
+                (Some(count), Some(name), None) => {
+                    let total: &mut u64 = counts.entry(name.to_owned()).or_default();
+                    *total = total.saturating_add(count);
+                }
+                _ => {
+                    return Err(format!(
+                        "invalid line {} of the profile: \"{}\", expect \"count name\"",
+                        index + 1,
+                        line
+                    ))
+                }
+            }
+        }
+
+        Ok(Self { counts })
+    }
+
+    /// The call count of the function, it is 0 if the function is not in the profile.
+    pub fn get_count(&self, name: &str) -> u64 {
+        self.counts.get(name).copied().unwrap_or(0)
+    }
+
+    /// The names of the called functions in the descending order of the
+    /// call counts, the functions with the same count are ordered by the names.
+    pub fn get_hot_functions(&self) -> Vec<&str> {
+        let mut functions = self
+            .counts
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(name, count)| (name.as_str(), *count))
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|(name, count)| (Reverse(*count), *name));
+        functions.into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Write the ordering file of the hot functions for the linker.
+    pub fn write_ordering_file<P: AsRef<Path>>(
+        &self,
+        file_path: P,
+        format: OrderingFileFormat,
+    ) -> std::io::Result<()> {
+        let mut text = String::new();
+        for name in self.get_hot_functions() {
+            match format {
+                OrderingFileFormat::Symbols => text.push_str(name),
+                OrderingFileFormat::Sections => {
+                    text.push_str(".text.");
+                    text.push_str(name);
+                }
+            }
+            text.push('\n');
+        }
+        std::fs::write(file_path, text)
+    }
+}
+
+/// Read the profile file.
+pub fn read_function_profile<P: AsRef<Path>>(file_path: P) -> std::io::Result<FunctionProfile> {
+    let text = std::fs::read_to_string(file_path)?;
+    FunctionProfile::parse(&text)
+        .map_err(|message| std::io::Error::new(std::io::ErrorKind::InvalidData, message))
+}
+
+impl Coverage {
+    /// Convert the counts (read from the coverage file) to the profile, the
+    /// call count of a function is the count of its entry block (i.e. the
+    /// first counter of the function).
+    pub fn get_function_profile(&self, counts: &[u64]) -> FunctionProfile {
+        let mut profile = FunctionProfile::default();
+        let mut last_func_id = None;
+
+        for (counter, count) in self.counters.iter().zip(counts) {
+            if last_func_id == Some(counter.func_id) {
+                continue;
+            }
+            last_func_id = Some(counter.func_id);
+
+            if let Some(name) = self.function_names.get(&counter.func_id) {
+                profile.counts.insert(name.clone(), *count);
+            }
+        }
+        profile
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Define the functions of the assembly in the order of the call counts
+    /// of the profile.
+    pub fn set_function_profile(&mut self, profile: FunctionProfile) {
+        self.function_profile = Some(profile);
+    }
+
+    /// Sort the functions (which are about to be defined) by the profile,
+    /// the hot functions go first, and the order of the others is kept.
+    pub(crate) fn sort_by_function_profile<F>(
+        &self,
+        items: &mut [F],
+        get_func_id: fn(&F) -> FuncId,
+    ) {
+        let Some(profile) = &self.function_profile else {
+            return;
+        };
+
+        let declarations = self.module.declarations();
+        items.sort_by_cached_key(|item| {
+            let func_id = get_func_id(item);
+            let name = declarations
+                .get_function_decl(func_id)
+                .linkage_name(func_id);
+            Reverse(profile.get_count(&name))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_object::{
+        object::{File, Object, ObjectSymbol},
+        ObjectModule,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::{Generator, ObjectOptions},
+        coverage::read_coverage_file,
+        function_order::{read_function_profile, FunctionProfile, OrderingFileFormat},
+        linker::{link_executable, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    const SOURCE: &str = r#"
+    (module $app
+        (function $cold export (result i32)
+            (code (imm_i32 1)))
+        (function $fib export (param $n i32) (result i32)
+            (code
+                (if (result i32) (lt_i32_u (local_load $n) (imm_i32 2))
+                    (local_load $n)
+                    (add_i32
+                        (call $fib (sub_i32 (local_load $n) (imm_i32 1)))
+                        (call $fib (sub_i32 (local_load $n) (imm_i32 2)))))))
+        (function $square export (param $x i32) (result i32)
+            (code (mul_i32 (local_load $x) (local_load $x))))
+        (function $main export (result i32)
+            (code (add_i32 (call $fib (imm_i32 10)) (call $square (imm_i32 3))))))
+    "#;
+
+    const NAMES: [&str; 4] = ["cold", "fib", "square", "main"];
+
+    /// Get the names of the functions in the order of the addresses.
+    fn get_function_order(binary: &[u8]) -> Vec<String> {
+        let file = File::parse(binary).unwrap();
+        let mut symbols = file
+            .symbols()
+            .filter(|symbol| NAMES.contains(&symbol.name().unwrap()))
+            .map(|symbol| (symbol.address(), symbol.name().unwrap().to_owned()))
+            .collect::<Vec<_>>();
+        symbols.sort();
+        symbols.into_iter().map(|(_, name)| name).collect()
+    }
+
+    #[test]
+    fn test_function_order() {
+        let module = parse_module(SOURCE).unwrap();
+        let temp_folder = TempFolder::new("function_order");
+
+        // collect the call counts by the coverage
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let coverage_file_path = temp_folder.file_path("app.cov");
+        generator.enable_coverage(&coverage_file_path).unwrap();
+        assemble_module(&module, &mut generator).unwrap();
+        let coverage = generator.coverage.clone().unwrap();
+
+        let binary = generator.finish().unwrap().emit().unwrap();
+        assert_eq!(get_function_order(&binary), NAMES);
+
+        let object_file_path = temp_folder.file_path("coverage.o");
+        let exec_file_path = temp_folder.file_path("coverage.elf");
+        std::fs::write(&object_file_path, &binary).unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(64));
+
+        let counts = read_coverage_file(&coverage_file_path).unwrap();
+        let profile = coverage.get_function_profile(&counts);
+        assert_eq!(NAMES.map(|name| profile.get_count(name)), [0, 177, 1, 1]);
+        assert_eq!(profile.get_hot_functions(), vec!["fib", "main", "square"]);
+
+        // the profile file
+        let profile_file_path = temp_folder.file_path("app.profile");
+        std::fs::write(
+            &profile_file_path,
+            "# calls  function\n100  fib\n77   fib\n1    square\n\n1    main\n0    cold\n",
+        )
+        .unwrap();
+        assert_eq!(read_function_profile(&profile_file_path).unwrap(), profile);
+        assert_eq!(
+            FunctionProfile::parse("1 main\nfib\n").unwrap_err(),
+            "invalid line 2 of the profile: \"fib\", expect \"count name\""
+        );
+
+        // the functions are defined in the order of the profile
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        generator.set_function_profile(profile.clone());
+        assemble_module(&module, &mut generator).unwrap();
+        let binary = generator.finish().unwrap().emit().unwrap();
+        assert_eq!(
+            get_function_order(&binary),
+            ["fib", "square", "main", "cold"]
+        );
+
+        // the linker orders the function sections
+        let ordering_file_path = temp_folder.file_path("app.order");
+        profile
+            .write_ordering_file(&ordering_file_path, OrderingFileFormat::Sections)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&ordering_file_path).unwrap(),
+            ".text.fib\n.text.main\n.text.square\n"
+        );
+
+        let mut generator = Generator::<ObjectModule>::new_with_options(
+            &module.name,
+            None,
+            &ObjectOptions {
+                function_sections: true,
+                ..ObjectOptions::default()
+            },
+        );
+        assemble_module(&module, &mut generator).unwrap();
+        let object_file_path = temp_folder.file_path("sections.o");
+        let exec_file_path = temp_folder.file_path("sections.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions {
+                linker: "ld.gold".to_owned(),
+                ordering_file: Some(ordering_file_path.clone()),
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+        // 'ld.gold' places the sections which are not in the file first
+        let exec_binary = std::fs::read(&exec_file_path).unwrap();
+        assert_eq!(
+            get_function_order(&exec_binary),
+            ["cold", "fib", "main", "square"]
+        );
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(64));
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    cursor::{Cursor, FuncCursor},
+    ir::{Function, Inst},
+};
+use cranelift_module::{FuncId, Module};
+
+use crate::code_generator::Generator;
+
+// The function passes
+// -------------------
+//
+// The embedders can transform the IR (CLIF) of each function before it is
+// compiled, e.g. the domain-specific strength reductions and the peephole
+// optimizations, without modifying the front end (e.g. the lowering) or
+// forking this crate.
+//
+// a pass implements the trait `FunctionPass`, either `run()` for the whole
+// function, or `visit_instruction()` for each instruction (the default `run()`
+// walks the instructions in the layout order), e.g.
+//
+// ```rust
+// struct MulToShift;
+//
+// impl FunctionPass for MulToShift {
+//     fn visit_instruction(&mut self, cursor: &mut FuncCursor, inst: Inst) {
+//         // `imul x, 8` -> `ishl_imm x, 3`
+//         cursor.func.dfg.replace(inst).ishl_imm(x, 3);
+//     }
+// }
+//
+// generator.add_function_pass(MulToShift);
+// ```
+//
+// the passes are run in the order of addition by `define_function()` (and
+// `define_functions_in_parallel()`), after removing the unused calls of the
+// `pure` and `readonly` functions (see `function_attribute.rs`) and before the
+// instrumentation, so the CLIF dump and the listing show the transformed functions.
+//
+// note that the transformed function should still be valid, it is checked
+// by the verifier of Cranelift when it is compiled.
+//
+// ref:
+// - https://docs.rs/cranelift-codegen/latest/cranelift_codegen/cursor/struct.FuncCursor.html
+// - https://docs.rs/cranelift-codegen/latest/cranelift_codegen/ir/dfg/struct.DataFlowGraph.html#method.replace
+
+pub trait FunctionPass {
+    /// Transform the function.
+    fn run(&mut self, _func_id: FuncId, func: &mut Function) {
+        let mut cursor = FuncCursor::new(func);
+        while cursor.next_block().is_some() {
+            while let Some(inst) = cursor.next_inst() {
+                self.visit_instruction(&mut cursor, inst);
+            }
+        }
+    }
+
+    /// Visit (and rewrite) an instruction, the cursor is at the instruction,
+    /// so the new instructions can be inserted before it.
+    fn visit_instruction(&mut self, _cursor: &mut FuncCursor, _inst: Inst) {}
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Add a pass which is run on each function defined afterwards.
+    pub fn add_function_pass(&mut self, pass: impl FunctionPass + Send + 'static) {
+        self.function_passes.push(Box::new(pass));
+    }
+
+    pub(crate) fn run_function_passes(&mut self, func_id: FuncId, func: &mut Function) {
+        self.remove_unused_calls(func);
+        for pass in &mut self.function_passes {
+            pass.run(func_id, func);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::{
+        cursor::FuncCursor,
+        ir::{Inst, InstBuilder, InstructionData, Opcode},
+    };
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator, function_pass::FunctionPass, lowering::assemble_module,
+        parser::parse_module,
+    };
+
+    /// Replace the multiplication by a power of two with a shift.
+    struct MulToShift;
+
+    impl FunctionPass for MulToShift {
+        fn visit_instruction(&mut self, cursor: &mut FuncCursor, inst: Inst) {
+            let dfg = &cursor.func.dfg;
+            let InstructionData::Binary {
+                opcode: Opcode::Imul,
+                args,
+            } = dfg.insts[inst]
+            else {
+                return;
+            };
+
+            let Some(InstructionData::UnaryImm {
+                opcode: Opcode::Iconst,
+                imm,
+            }) = dfg.value_def(args[1]).inst().map(|def| dfg.insts[def])
+            else {
+                return;
+            };
+
+            let bits = imm.bits();
+            if bits > 0 && bits.count_ones() == 1 {
+                cursor
+                    .func
+                    .dfg
+                    .replace(inst)
+                    .ishl_imm(args[0], bits.trailing_zeros() as i64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_function_pass() {
+        let source = r#"
+        (module $test
+            (function $scale (param $x i32) (result i32)
+                (code (add_i32
+                    (mul_i32 (local_load $x) (imm_i32 8))
+                    (mul_i32 (local_load $x) (imm_i32 3))))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        generator.add_function_pass(MulToShift);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+
+        let clif = generator.dump_clif();
+        assert_eq!(clif.matches("ishl_imm").count(), 1);
+        assert_eq!(clif.matches("imul").count(), 1);
+
+        generator.module.finalize_definitions().unwrap();
+        let func_scale_ptr = generator
+            .module
+            .get_finalized_function(assembled_module.get_function_id("scale").unwrap());
+        let func_scale: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(func_scale_ptr) };
+        assert_eq!(func_scale(5), 55);
+    }
+}
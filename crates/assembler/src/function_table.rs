@@ -0,0 +1,538 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{
+    types, Endianness, GlobalValue, Inst, InstBuilder, MemFlags, SigRef, Signature, TrapCode, Type,
+    Value,
+};
+use cranelift_frontend::FunctionBuilder;
+#[cfg(feature = "jit")]
+use cranelift_jit::JITModule;
+use cranelift_module::{DataId, FuncId, Linkage, Module, ModuleError};
+
+use crate::{
+    code_generator::Generator,
+    safety_check::{emit_bounds_check, emit_equality_check, TRAP_CODE_INDEX_OUT_OF_BOUNDS},
+    validation::SymbolReferences,
+};
+
+// The function table
+// ------------------
+//
+// It mirrors the function table of the XiaoXuan Core VM, the functions are
+// registered into the table and are called by the index, the table is emitted
+// as a (writable) data object which is an array of the function pointers, e.g.
+//
+// ```rust
+// let mut function_table = generator.declare_function_table("__anna_function_table", Linkage::Local)?;
+// let index_inc = function_table.register(func_inc_id);
+// let index_dec = function_table.register(func_dec_id);
+// generator.define_function_table(&function_table)?;
+// ```
+//
+// and `emit_call_by_index()` checks the bounds of the index, loads the entry
+// and calls it by `call_indirect`:
+//
+// ```clif
+// v2 = icmp ult v0, 2
+// brif v2, block2, block1         ; block1 traps
+// ...
+// v4 = uextend.i64 v0
+// v5 = imul_imm v4, 8
+// v6 = symbol_value.i64 gv0
+// v7 = iadd v6, v5
+// v8 = load.i64 notrap aligned v7
+// v9 = call_indirect sig0, v8(v1)
+// ```
+//
+// since the entries are loaded at runtime, the JIT functions can be replaced
+// after finalizing (i.e. the hot-swap), see `set_function_table_entry()`.
+//
+// the signature of the callee is not checked by `call_indirect`, so calling
+// a function through the wrong signature (e.g. registering the function in a
+// wrong table, or a wrong index) silently corrupts the arguments. the signature
+// check is a debugging option which catches it:
+//
+// ```rust
+// let mut function_table = generator.declare_function_table("__anna_function_table", Linkage::Local)?;
+// function_table.enable_signature_check();
+// ```
+//
+// each entry is followed by the hash of the signature of the function (see
+// `get_signature_hash()`, it occupies a pointer-sized slot), and the hash of
+// the call site is compared with the hash of the entry before the call:
+//
+// ```clif
+// v9 = load.i32 notrap aligned v7+8
+// v10 = iconst.i32 0x1234_5678
+// v11 = icmp eq v9, v10
+// brif v11, block4, block3        ; block3 traps with user4
+// ```
+//
+// note that the layout of the table is changed by the option, so all call
+// sites should be generated with the same `FunctionTable`.
+
+/// The trap code of the mismatched signature of the function table entry.
+pub const TRAP_CODE_SIGNATURE_MISMATCH: TrapCode = TrapCode::unwrap_user(4);
+
+/// Get the hash of the signature (i.e. the FNV-1a hash of the text of the
+/// signature, which includes the types and the calling convention).
+pub fn get_signature_hash(signature: &Signature) -> u32 {
+    signature
+        .to_string()
+        .bytes()
+        .fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionTable {
+    pub data_id: DataId,
+    pub pointer_type: Type,
+
+    /// The functions of the entries, in the order of the index.
+    pub func_ids: Vec<FuncId>,
+
+    /// Check the signature of the entry before calling, it is `false` by
+    /// default, call `enable_signature_check()` to enable it.
+    pub signature_check: bool,
+}
+
+impl FunctionTable {
+    /// Append the hash of the signature to each entry, and check it by
+    /// `emit_call_by_index()`.
+    ///
+    /// It should be called before defining the table.
+    pub fn enable_signature_check(&mut self) {
+        self.signature_check = true;
+    }
+
+    /// Get the size of an entry, i.e. the function pointer and the
+    /// (optional) hash of the signature.
+    pub fn get_entry_size(&self) -> u32 {
+        if self.signature_check {
+            self.pointer_type.bytes() * 2
+        } else {
+            self.pointer_type.bytes()
+        }
+    }
+
+    /// Append the function to the table and return its index.
+    pub fn register(&mut self, func_id: FuncId) -> u32 {
+        self.func_ids.push(func_id);
+        (self.func_ids.len() - 1) as u32
+    }
+
+    /// Call the function of the table by the index (an i32 value), it traps
+    /// with `TRAP_CODE_INDEX_OUT_OF_BOUNDS` if the index is out of bounds, and
+    /// with `TRAP_CODE_SIGNATURE_MISMATCH` if the signature check is enabled
+    /// and the signature of the entry is not `sig_ref`.
+    ///
+    /// - `table`: the global value of the table, i.e. the result
+    ///   of `module.declare_data_in_func(function_table.data_id, func)`.
+    /// - `sig_ref`: the signature of the callee, all functions which are called
+    ///   by this index should have the same signature.
+    pub fn emit_call_by_index(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        table: GlobalValue,
+        sig_ref: SigRef,
+        index: Value,
+        args: &[Value],
+    ) -> Inst {
+        let value_length = function_builder
+            .ins()
+            .iconst(types::I32, self.func_ids.len() as i64);
+        emit_bounds_check(
+            function_builder,
+            index,
+            value_length,
+            TRAP_CODE_INDEX_OUT_OF_BOUNDS,
+        );
+
+        let value_index = function_builder.ins().uextend(self.pointer_type, index);
+        let value_offset = function_builder
+            .ins()
+            .imul_imm(value_index, self.get_entry_size() as i64);
+        let value_table = function_builder
+            .ins()
+            .symbol_value(self.pointer_type, table);
+        let value_entry = function_builder.ins().iadd(value_table, value_offset);
+
+        if self.signature_check {
+            let hash = get_signature_hash(&function_builder.func.dfg.signatures[sig_ref]);
+            let value_hash = function_builder.ins().load(
+                types::I32,
+                MemFlags::trusted(),
+                value_entry,
+                self.pointer_type.bytes() as i32,
+            );
+            let value_expected_hash = function_builder.ins().iconst(types::I32, hash as i64);
+            emit_equality_check(
+                function_builder,
+                value_hash,
+                value_expected_hash,
+                TRAP_CODE_SIGNATURE_MISMATCH,
+            );
+        }
+
+        let value_callee =
+            function_builder
+                .ins()
+                .load(self.pointer_type, MemFlags::trusted(), value_entry, 0);
+
+        function_builder
+            .ins()
+            .call_indirect(sig_ref, value_callee, args)
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    pub fn declare_function_table(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+    ) -> Result<FunctionTable, ModuleError> {
+        let data_id = self.module.declare_data(name, linkage, true, false)?;
+        Ok(FunctionTable {
+            data_id,
+            pointer_type: self.module.isa().pointer_type(),
+            func_ids: vec![],
+            signature_check: false,
+        })
+    }
+
+    /// Define the content of the function table, i.e. the addresses of the
+    /// registered functions (and the hashes of their signatures).
+    pub fn define_function_table(
+        &mut self,
+        function_table: &FunctionTable,
+    ) -> Result<(), ModuleError> {
+        let pointer_bytes = function_table.pointer_type.bytes() as usize;
+        let entry_size = function_table.get_entry_size() as usize;
+
+        // the data object can not be empty, and it should not be placed in
+        // the '.bss' section since it has relocations.
+        let mut content = vec![0; (function_table.func_ids.len() * entry_size).max(entry_size)];
+        if function_table.signature_check {
+            let endianness = self.module.isa().endianness();
+            for (idx, func_id) in function_table.func_ids.iter().enumerate() {
+                let offset = idx * entry_size + pointer_bytes;
+                content[offset..offset + 4]
+                    .copy_from_slice(&self.get_function_signature_hash_bytes(*func_id, endianness));
+            }
+        }
+        self.data_description.define(content.into_boxed_slice());
+        self.data_description.set_align(pointer_bytes as u64);
+
+        for (idx, func_id) in function_table.func_ids.iter().enumerate() {
+            let func_ref = self
+                .module
+                .declare_func_in_data(*func_id, &mut self.data_description);
+            self.data_description
+                .write_function_addr((idx * entry_size) as u32, func_ref);
+        }
+
+        let result = self
+            .module
+            .define_data(function_table.data_id, &self.data_description);
+        let symbol_references =
+            SymbolReferences::from_data(function_table.data_id, &self.data_description);
+        self.data_description.clear();
+        result?;
+
+        self.set_symbol_references(symbol_references);
+        Ok(())
+    }
+
+    fn get_function_signature_hash_bytes(
+        &self,
+        func_id: FuncId,
+        endianness: Endianness,
+    ) -> [u8; 4] {
+        let signature = &self
+            .module
+            .declarations()
+            .get_function_decl(func_id)
+            .signature;
+        let hash = get_signature_hash(signature);
+        match endianness {
+            Endianness::Little => hash.to_le_bytes(),
+            Endianness::Big => hash.to_be_bytes(),
+        }
+    }
+}
+
+#[cfg(feature = "jit")]
+impl Generator<JITModule> {
+    /// Replace the entry of the function table, the subsequent calls by
+    /// the index call the new function.
+    ///
+    /// Note that it is only available after 'module.finalize_definitions()',
+    /// and the new function should have been finalized.
+    pub fn set_function_table_entry(
+        &mut self,
+        function_table: &mut FunctionTable,
+        index: u32,
+        func_id: FuncId,
+    ) {
+        let (table_ptr, _) = self.module.get_finalized_data(function_table.data_id);
+        let func_ptr = self.module.get_finalized_function(func_id);
+
+        function_table.func_ids[index as usize] = func_id;
+
+        let entry_ptr = unsafe {
+            table_ptr.add(index as usize * function_table.get_entry_size() as usize)
+                as *mut *const u8
+        };
+
+        // the new function may have a different signature, note that the hash
+        // and the pointer are not updated atomically.
+        if function_table.signature_check {
+            let hash_bytes =
+                self.get_function_signature_hash_bytes(func_id, self.module.isa().endianness());
+            unsafe {
+                (entry_ptr.add(1) as *mut [u8; 4]).write_volatile(hash_bytes);
+            }
+        }
+
+        unsafe {
+            entry_ptr.write_volatile(func_ptr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, Type, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{FuncId, Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{code_generator::Generator, test_support::run_executable_binary_and_get_exit_code};
+
+    fn define_function<T: Module>(generator: &mut Generator<T>, name: &str, delta: i64) -> FuncId {
+        define_function_with_type(generator, name, types::I32, delta)
+    }
+
+    /// Define the function `fn(value: ty) -> ty { value + delta }`.
+    fn define_function_with_type<T: Module>(
+        generator: &mut Generator<T>,
+        name: &str,
+        ty: Type,
+        delta: i64,
+    ) -> FuncId {
+        let mut func_sig = generator.module.make_signature();
+        func_sig.params.push(AbiParam::new(ty));
+        func_sig.returns.push(AbiParam::new(ty));
+
+        let func_id = generator
+            .module
+            .declare_function(name, Linkage::Local, &func_sig)
+            .unwrap();
+
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), func_sig);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        let value_1 = function_builder.ins().iadd_imm(value_0, delta);
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_id, func).unwrap();
+        func_id
+    }
+
+    #[test]
+    fn test_function_table() {
+        let mut generator = Generator::<JITModule>::new(vec![]);
+
+        let func_inc_id = define_function(&mut generator, "inc", 1);
+        let func_dec_id = define_function(&mut generator, "dec", -1);
+        let func_double_inc_id = define_function(&mut generator, "double_inc", 2);
+
+        let mut function_table = generator
+            .declare_function_table("function_table", Linkage::Local)
+            .unwrap();
+        assert_eq!(function_table.register(func_inc_id), 0);
+        assert_eq!(function_table.register(func_dec_id), 1);
+        generator.define_function_table(&function_table).unwrap();
+
+        // ```rust
+        // fn main(index: i32, a: i32) -> i32 {
+        //     function_table[index](a)
+        // }
+        // ```
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.params.push(AbiParam::new(types::I32));
+        func_main_sig.params.push(AbiParam::new(types::I32));
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Local, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let table = generator
+            .module
+            .declare_data_in_func(function_table.data_id, &mut func_main);
+
+        let mut callee_sig = generator.module.make_signature();
+        callee_sig.params.push(AbiParam::new(types::I32));
+        callee_sig.returns.push(AbiParam::new(types::I32));
+        let sig_ref = func_main.import_signature(callee_sig);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_index = function_builder.block_params(block)[0];
+        let value_0 = function_builder.block_params(block)[1];
+        let call_0 = function_table.emit_call_by_index(
+            &mut function_builder,
+            table,
+            sig_ref,
+            value_index,
+            &[value_0],
+        );
+        let value_1 = function_builder.inst_results(call_0)[0];
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let func_main: extern "C" fn(i32, i32) -> i32 =
+            unsafe { std::mem::transmute(func_main_ptr) };
+
+        assert_eq!(func_main(0, 10), 11);
+        assert_eq!(func_main(1, 10), 9);
+
+        // hot-swap
+        generator.set_function_table_entry(&mut function_table, 0, func_double_inc_id);
+        assert_eq!(func_main(0, 10), 12);
+        assert_eq!(
+            function_table.func_ids,
+            vec![func_double_inc_id, func_dec_id]
+        );
+    }
+
+    // ```rust
+    // fn main() -> i32 {
+    //     function_table[INDEX](10)   // the callee should be `fn(i32) -> i32`
+    // }
+    // ```
+    fn build_main(index: i64) -> Vec<u8> {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+
+        let func_inc_id = define_function(&mut generator, "inc", 1);
+        let func_wide_inc_id = define_function_with_type(&mut generator, "wide_inc", types::I64, 1);
+
+        let mut function_table = generator
+            .declare_function_table("function_table", Linkage::Local)
+            .unwrap();
+        function_table.enable_signature_check();
+        function_table.register(func_inc_id);
+        function_table.register(func_wide_inc_id);
+        generator.define_function_table(&function_table).unwrap();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let table = generator
+            .module
+            .declare_data_in_func(function_table.data_id, &mut func_main);
+
+        let mut callee_sig = generator.module.make_signature();
+        callee_sig.params.push(AbiParam::new(types::I32));
+        callee_sig.returns.push(AbiParam::new(types::I32));
+        let sig_ref = func_main.import_signature(callee_sig);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_index = function_builder.ins().iconst(types::I32, index);
+        let value_0 = function_builder.ins().iconst(types::I32, 10);
+        let call_0 = function_table.emit_call_by_index(
+            &mut function_builder,
+            table,
+            sig_ref,
+            value_index,
+            &[value_0],
+        );
+        let value_1 = function_builder.inst_results(call_0)[0];
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        assert!(generator.dump_clif().contains("trap user4"));
+
+        generator.finish().unwrap().emit().unwrap()
+    }
+
+    #[test]
+    fn test_function_table_signature_check() {
+        assert_eq!(
+            run_executable_binary_and_get_exit_code(
+                &build_main(0),
+                "test_function_table_signature_pass",
+                false
+            ),
+            Some(11)
+        );
+
+        // the process is killed by the signal SIGILL, since the entry 1
+        // is `fn(i64) -> i64`
+        assert_eq!(
+            run_executable_binary_and_get_exit_code(
+                &build_main(1),
+                "test_function_table_signature_trap",
+                false
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_object::ObjectModule;
+
+use crate::{
+    ast::Module, code_generator::Generator, lowering::assemble_module, parser::parse_module,
+};
+
+// The fuzzing entry points
+// ------------------------
+//
+// The pipeline (i.e. the lexer, the parser, the lowering and the generator)
+// should report the errors of the invalid inputs as the diagnostics rather than
+// panicking, the entry points exercise it with the arbitrary inputs, and they
+// panic only if the pipeline panics, e.g. (the target of cargo-fuzz)
+//
+// ```rust
+// fuzz_target!(|data: &[u8]| {
+//     fuzz_parse(data);
+// });
+// ```
+//
+// - `fuzz_parse()` parses the bytes as the source text, and the parsed module
+//   is lowered as well.
+// - `fuzz_lower()` assembles the module into an object file, the module can be
+//   constructed by the fuzzer directly (e.g. mutated from a parsed module).
+// - the output object file is never executed.
+//
+// the targets are located in the folder 'fuzz' of this crate, run them by
+// `$ cargo fuzz run parse` and `$ cargo fuzz run lower` (requires the nightly
+// toolchain and `cargo-fuzz`).
+
+/// Parse the bytes as the source text, and lower the module if it is valid,
+/// the errors are ignored.
+pub fn fuzz_parse(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(module) = parse_module(source) {
+        fuzz_lower(&module);
+    }
+}
+
+/// Assemble the module into an object file, the errors are ignored.
+pub fn fuzz_lower(module: &Module) {
+    let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+    if assemble_module(module, &mut generator).is_ok() {
+        let _ = generator.finish().map(|product| product.emit());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzzing::fuzz_parse;
+
+    #[test]
+    fn test_fuzzing() {
+        let source = r#"
+        (module $app
+            (import (function $puts (param i64) (result i32)))
+            (data $message (read_only bytes "hello\0"))
+            (data $count (read_write i32 11))
+            (function $inc (param $a i32) (result i32)
+                (code (add_i32 (local_load $a) (data_load_i32 $count))))
+            (function $main export (result i32)
+                (local $b f64)
+                (code
+                    (call $puts (host_addr_data $message))
+                    (local_store $b (imm_f64 1.5))
+                    (if (result i32) (gt_i32_s (call $inc (imm_i32 1)) (imm_i32 0))
+                        (imm_i32 1)
+                        (imm_i32 0)))))
+        "#;
+        fuzz_parse(source.as_bytes());
+
+        // the truncated sources
+        for end in 0..source.len() {
+            fuzz_parse(&source.as_bytes()[..end]);
+        }
+
+        // the invalid UTF-8 and the unbalanced parentheses
+        fuzz_parse(&[0xff, 0xfe, b'(']);
+        fuzz_parse(b"((((((((((((((((");
+        fuzz_parse(b"))");
+        fuzz_parse(b"(module $a (function $f (code (local_load $x))))");
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::HashSet;
+
+use cranelift_jit::JITModule;
+
+use crate::{
+    ast::{
+        ImportFunctionNode, ImportNode, Instruction, InstructionKind, Module, ResultAbi, ValueType,
+    },
+    code_generator::Generator,
+    diagnostic::Diagnostic,
+    function_attribute::FunctionAttributes,
+    lowering::{assemble_module, AssembledModule},
+    visitor::{walk_instruction, Visitor},
+};
+
+// The host functions
+// ------------------
+//
+// The application which embeds the JIT exposes its API (i.e. the Rust
+// functions) to the modules by a table of the host functions, e.g.
+//
+// ```rust
+// extern "C" fn print_i32(value: i32) { ... }
+//
+// let host_functions = [
+//     HostFunction::new("print_i32", print_i32 as *const u8, &[ValueType::I32], &[]),
+// ];
+// let (mut generator, assembled_module) = assemble_module_with_host_functions(&module, &host_functions)?;
+// ```
+//
+// the host functions are registered as the symbols of the JIT module, and
+// the functions which are called by the module (but not imported or defined)
+// are imported automatically, so the module calls them without the
+// `(import (function ...))` declarations, e.g.
+//
+// ```clojure
+// (module $script
+//     (function $main (code (call $print_i32 (imm_i32 42)))))
+// ```
+//
+// the host functions which are imported explicitly must have the same signature
+// as the table, the mismatch is reported at the import, since the JIT can not
+// check the signatures of the Rust functions.
+
+#[derive(Debug, Clone)]
+pub struct HostFunction {
+    pub name: String,
+
+    /// The address of the `extern "C"` function.
+    pub pointer: *const u8,
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
+    pub attributes: FunctionAttributes,
+}
+
+impl HostFunction {
+    pub fn new(
+        name: &str,
+        pointer: *const u8,
+        params: &[ValueType],
+        results: &[ValueType],
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            pointer,
+            params: params.to_vec(),
+            results: results.to_vec(),
+            attributes: FunctionAttributes::default(),
+        }
+    }
+}
+
+impl Generator<JITModule> {
+    /// Create the generator which registers the host functions as the symbols.
+    pub fn new_with_host_functions(host_functions: &[HostFunction]) -> Self {
+        Self::new(
+            host_functions
+                .iter()
+                .map(|host_function| (host_function.name.clone(), host_function.pointer))
+                .collect(),
+        )
+    }
+}
+
+/// Import the host functions which are called by the module but not imported
+/// or defined, and check the signatures of the imported host functions.
+pub fn import_host_functions(
+    module: &Module,
+    host_functions: &[HostFunction],
+) -> Result<Module, Diagnostic> {
+    struct CalleeCollector<'ast>(HashSet<&'ast str>);
+
+    impl<'ast> Visitor<'ast> for CalleeCollector<'ast> {
+        fn visit_instruction(&mut self, instruction: &'ast Instruction) {
+            if let InstructionKind::Call { name, .. } = &instruction.kind {
+                self.0.insert(name);
+            }
+            walk_instruction(self, instruction);
+        }
+    }
+
+    let mut collector = CalleeCollector(HashSet::new());
+    for function_node in &module.functions {
+        collector.visit_function(function_node);
+    }
+
+    let mut imported_module = module.clone();
+
+    for host_function in host_functions {
+        let imported = module
+            .imports
+            .iter()
+            .find_map(|import_node| match import_node {
+                ImportNode::Function(node) if node.symbol == host_function.name => Some(node),
+                _ => None,
+            });
+
+        if let Some(node) = imported {
+            if node.params != host_function.params || node.results != host_function.results {
+                return Err(Diagnostic::new(
+                    &format!(
+                        "the signature of the host function \"{}\" is different from the import",
+                        host_function.name
+                    ),
+                    node.span,
+                ));
+            }
+            continue;
+        }
+
+        let declared = module
+            .imports
+            .iter()
+            .any(|import_node| import_node.get_name() == host_function.name)
+            || module
+                .functions
+                .iter()
+                .any(|function_node| function_node.name == host_function.name);
+
+        if declared || !collector.0.contains(host_function.name.as_str()) {
+            continue;
+        }
+
+        imported_module
+            .imports
+            .push(ImportNode::Function(ImportFunctionNode {
+                name: host_function.name.clone(),
+                symbol: host_function.name.clone(),
+                params: host_function.params.clone(),
+                results: host_function.results.clone(),
+                variadic: false,
+                library: None,
+                colocated: false,
+                result_abi: ResultAbi::Multiple,
+                attributes: host_function.attributes,
+                span: module.span,
+            }));
+    }
+
+    Ok(imported_module)
+}
+
+/// Create the JIT generator with the host functions and assemble the module,
+/// the called host functions are imported automatically.
+pub fn assemble_module_with_host_functions(
+    module: &Module,
+    host_functions: &[HostFunction],
+) -> Result<(Generator<JITModule>, AssembledModule), Diagnostic> {
+    let imported_module = import_host_functions(module, host_functions)?;
+    let mut generator = Generator::<JITModule>::new_with_host_functions(host_functions);
+    let assembled_module = assemble_module(&imported_module, &mut generator)?;
+    Ok((generator, assembled_module))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::ValueType,
+        host_function::{assemble_module_with_host_functions, import_host_functions, HostFunction},
+        parser::parse_module,
+    };
+
+    static TOTAL: AtomicI64 = AtomicI64::new(0);
+
+    extern "C" fn add_to_total(value: i64) -> i64 {
+        TOTAL.fetch_add(value, Ordering::SeqCst) + value
+    }
+
+    extern "C" fn get_scale() -> i32 {
+        3
+    }
+
+    extern "C" fn unused() {}
+
+    #[test]
+    fn test_host_functions() {
+        let host_functions = [
+            HostFunction::new(
+                "add_to_total",
+                add_to_total as *const u8,
+                &[ValueType::I64],
+                &[ValueType::I64],
+            ),
+            HostFunction::new("get_scale", get_scale as *const u8, &[], &[ValueType::I32]),
+            HostFunction::new("unused", unused as *const u8, &[], &[]),
+        ];
+
+        // `add_to_total` is imported automatically, and `get_scale` explicitly
+        let source = r#"
+        (module $script
+            (import (function $scale "get_scale" (result i32)))
+            (function $main (param $n i32) (result i64)
+                (code
+                    (call $add_to_total (extend_i32_s_to_i64 (local_load $n)))
+                    (call $add_to_total
+                        (extend_i32_s_to_i64 (mul_i32 (local_load $n) (call $scale)))))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let imported_module = import_host_functions(&module, &host_functions).unwrap();
+        assert_eq!(
+            imported_module
+                .imports
+                .iter()
+                .map(|import_node| import_node.get_name())
+                .collect::<Vec<_>>(),
+            vec!["scale", "add_to_total"]
+        );
+
+        let (mut generator, assembled_module) =
+            assemble_module_with_host_functions(&module, &host_functions).unwrap();
+
+        generator.module.finalize_definitions().unwrap();
+        let func_main_ptr = generator
+            .module
+            .get_finalized_function(assembled_module.get_function_id("main").unwrap());
+        let func_main: extern "C" fn(i32) -> i64 = unsafe { std::mem::transmute(func_main_ptr) };
+        assert_eq!(func_main(2), 8);
+        assert_eq!(TOTAL.load(Ordering::SeqCst), 8);
+
+        // the import with a different signature
+        let source = r#"
+        (module $script
+            (import (function $get_scale (result i64)))
+            (function $main (result i64) (code (call $get_scale))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let diagnostic = assemble_module_with_host_functions(&module, &host_functions)
+            .err()
+            .unwrap();
+        assert_eq!(
+            diagnostic.message,
+            "the signature of the host function \"get_scale\" is different from the import"
+        );
+    }
+}
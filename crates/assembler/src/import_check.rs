@@ -0,0 +1,221 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Display;
+
+use cranelift_module::{DataId, Module, ModuleError};
+
+use crate::{
+    code_generator::Generator,
+    elf_note::{read_imported_data_layouts, ElfNote},
+    symbol_listing::{read_symbols, SymbolScope},
+};
+
+// The layout checking of the imported data
+// ----------------------------------------
+//
+// The imported data has no type in the object file, so a mismatched
+// declaration (e.g. importing an `i64` which is defined as an `i32`)
+// silently corrupts the memory. The expected size and alignment can be
+// attached to the import, e.g.
+//
+// `(import (data $counter "counter" (size 8) (align 8)))`
+//
+// they are recorded in the object file as the notes (see `ElfNote::new_imported_data()`),
+// the linker keeps the notes in the output, and then `check_imported_data()`
+// compares them with the symbols of the output:
+//
+// - the size of the symbol should equal the expected size.
+// - the address of the symbol should be a multiple of the expected alignment.
+//
+// note that only the symbols which are defined in the output (i.e. the data of
+// the static objects, and the data of the shared libraries which is copied into
+// the executable by the copy relocations) are checked, the symbols which are
+// still undefined, or the stripped outputs are skipped.
+//
+// the expectations are ignored by the JIT module.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportedDataMismatch {
+    Size {
+        symbol: String,
+        expected: u64,
+        actual: u64,
+    },
+    Align {
+        symbol: String,
+        expected: u64,
+        address: u64,
+    },
+}
+
+impl Display for ImportedDataMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportedDataMismatch::Size {
+                symbol,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "the imported data \"{}\" is expected to be {} bytes, but it is {} bytes",
+                symbol, expected, actual
+            ),
+            ImportedDataMismatch::Align {
+                symbol,
+                expected,
+                address,
+            } => write!(
+                f,
+                "the imported data \"{}\" is expected to be aligned to {} bytes, but its address is 0x{:x}",
+                symbol, expected, address
+            ),
+        }
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Import the data with the expected size and alignment, the expectation
+    /// is checked after linking, see `check_imported_data()`.
+    pub fn import_data_with_layout(
+        &mut self,
+        name: &str,
+        writable: bool,
+        thread_local: bool,
+        size: Option<u64>,
+        align: Option<u64>,
+    ) -> Result<DataId, ModuleError> {
+        let data_id = self.import_data(name, writable, thread_local)?;
+        if size.is_some() || align.is_some() {
+            self.elf_notes
+                .push(ElfNote::new_imported_data(name, size, align));
+        }
+        Ok(data_id)
+    }
+}
+
+/// Check the expected layouts of the imported data against the symbols of
+/// the linked binary (i.e. the executable file or the shared library).
+pub fn check_imported_data(binary: &[u8]) -> Result<Vec<ImportedDataMismatch>, String> {
+    let layouts = read_imported_data_layouts(binary);
+    if layouts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let symbols = read_symbols(binary)?;
+    let mut mismatches = vec![];
+
+    for (symbol_name, opt_size, opt_align) in layouts {
+        let Some(symbol) = symbols
+            .iter()
+            .find(|symbol| symbol.name == symbol_name && symbol.scope != SymbolScope::Import)
+        else {
+            continue;
+        };
+
+        let mismatch = if let Some(size) = opt_size.filter(|size| *size != symbol.size) {
+            ImportedDataMismatch::Size {
+                symbol: symbol_name,
+                expected: size,
+                actual: symbol.size,
+            }
+        } else if let Some(align) = opt_align.filter(|align| symbol.address % align != 0) {
+            ImportedDataMismatch::Align {
+                symbol: symbol_name,
+                expected: align,
+                address: symbol.address,
+            }
+        } else {
+            continue;
+        };
+
+        if !mismatches.contains(&mismatch) {
+            mismatches.push(mismatch);
+        }
+    }
+
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        elf_note::read_imported_data_layouts,
+        import_check::{check_imported_data, ImportedDataMismatch},
+        linker::{link_executable, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    fn emit(source: &str) -> Vec<u8> {
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        generator.finish().unwrap().emit().unwrap()
+    }
+
+    #[test]
+    fn test_check_imported_data() {
+        let lib_binary = emit(
+            r#"
+            (module $lib
+                (data $counter export (read_write i32 11))
+                (data $total export (align 8) (read_write i64 13)))
+            "#,
+        );
+        let main_binary = emit(
+            r#"
+            (module $main
+                (import (data $counter (size 8)))
+                (import (data $total (size 8) (align 8)))
+                (function $main export (result i32)
+                    (code (add_i32
+                        (data_load_i32 $counter)
+                        (truncate_i64_to_i32 (data_load_i64 $total))))))
+            "#,
+        );
+
+        assert_eq!(
+            read_imported_data_layouts(&main_binary),
+            vec![
+                ("counter".to_owned(), Some(8), None),
+                ("total".to_owned(), Some(8), Some(8))
+            ]
+        );
+
+        let temp_folder = TempFolder::new("import_check");
+        let lib_file_path = temp_folder.file_path("lib.o");
+        let main_file_path = temp_folder.file_path("main.o");
+        let exec_file_path = temp_folder.file_path("main.elf");
+        std::fs::write(&lib_file_path, lib_binary).unwrap();
+        std::fs::write(&main_file_path, main_binary).unwrap();
+
+        link_executable(
+            &[&main_file_path, &lib_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+
+        let exec_binary = std::fs::read(&exec_file_path).unwrap();
+        assert_eq!(
+            check_imported_data(&exec_binary).unwrap(),
+            vec![ImportedDataMismatch::Size {
+                symbol: "counter".to_owned(),
+                expected: 8,
+                actual: 4
+            }]
+        );
+    }
+}
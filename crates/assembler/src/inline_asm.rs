@@ -0,0 +1,526 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    ir::{types, AbiParam, Function, UserFuncName},
+    isa::CallConv,
+};
+use cranelift_module::{FuncId, FuncOrDataId, Linkage, Module, ModuleError};
+
+use crate::{ast::ValueType, code_generator::Generator, validation::SymbolReferences};
+
+// The inline assembly
+// -------------------
+//
+// The instructions which are unreachable through the Cranelift IR, e.g.
+// `cpuid`, `rdtsc`, `xgetbv` and the privileged instructions, can be embedded
+// in a function by
+// `(asm "template" (in "register" value)... (out "register")... (clobber "register"...)...)`,
+// e.g. reading the time-stamp counter:
+//
+// ```text
+// (function $read_tsc (result i64 i64)
+//     (code
+//         // rdtsc, the low and high 32 bits of the counter
+//         (asm "0f 31" (out "rax") (out "rdx"))))
+// ```
+//
+// - the template is the machine code in hex (the bytes can be separated by
+//   the spaces), it is copied as it is, so it should not refer to the stack
+//   (i.e. `rsp`) or the other functions and data.
+// - `in` places the value in the register before the code, and `out` takes
+//   the value of the register after the code, they are the values of the
+//   instruction in order.
+// - the registers are named as `rax`, `rcx` ... `r15`, the 64-bit names
+//   (e.g. `rax`) take or produce the i64 values, and the 32-bit names (e.g.
+//   `eax`) take or produce the i32 values, `rsp` can not be used.
+// - the other registers which are changed by the code should be declared by
+//   `clobber`.
+//
+// the instruction is lowered to a call of an out-of-line shim, i.e. a local
+// function which is defined by the machine code (the instructions with the
+// same template and registers share one shim):
+//
+// ```asm
+// __anna_asm.0f31..rax_rdx.:
+//     push    rbx             ; the used callee-saved registers
+//     push    rdi             ; the pointer of the outputs (the last param)
+//     push    <param 0>       ; move the params to the input registers
+//     ...                     ; through the stack, so the registers can overlap
+//     pop     <input 0>
+//     <template>
+//     push    <output 0>      ; collect the outputs
+//     ...
+//     mov     rax, [rsp + 8 * outputs]
+//     pop     rcx
+//     mov     [rax + 8 * (outputs - 1)], rcx
+//     ...
+//     add     rsp, 8
+//     pop     rbx
+//     ret
+// ```
+//
+// and the caller passes the inputs and the address of a stack slot, which
+// receives the outputs, so the register allocation of Cranelift is not
+// affected by the constraints, at the cost of a call.
+//
+// only x86_64 with the System V calling convention is supported currently.
+//
+// ref:
+// - https://gcc.gnu.org/onlinedocs/gcc/Extended-Asm.html
+// - https://www.felixcloutier.com/x86/cpuid
+// - https://www.felixcloutier.com/x86/rdtsc
+
+pub const INLINE_ASM_FUNCTION_PREFIX: &str = "__anna_asm.";
+
+const REGISTERS_64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
+const REGISTERS_32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+
+const REGISTER_RSP: u8 = 4;
+
+/// The registers of the integer params of System V, i.e. `rdi`, `rsi`,
+/// `rdx`, `rcx`, `r8` and `r9`.
+const PARAM_REGISTERS: [u8; 6] = [7, 6, 2, 1, 8, 9];
+
+/// The callee-saved registers of System V, i.e. `rbx`, `rbp` and `r12` to `r15`.
+const CALLEE_SAVED_REGISTERS: [u8; 6] = [3, 5, 12, 13, 14, 15];
+
+/// Get the number of the register and the type of its value, e.g.
+/// `(0, ValueType::I32)` for "eax".
+pub fn parse_register(name: &str) -> Result<(u8, ValueType), String> {
+    let (number, value_type) = if let Some(index) = REGISTERS_64.iter().position(|r| *r == name) {
+        (index as u8, ValueType::I64)
+    } else if let Some(index) = REGISTERS_32.iter().position(|r| *r == name) {
+        (index as u8, ValueType::I32)
+    } else {
+        return Err(format!("unknown register \"{}\"", name));
+    };
+
+    if number == REGISTER_RSP {
+        return Err(format!(
+            "the register \"{}\" can not be used in the inline assembly",
+            name
+        ));
+    }
+    Ok((number, value_type))
+}
+
+/// Parse the hex template, e.g. "0f a2" to `[0x0f, 0xa2]`.
+pub fn parse_template(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<Vec<_>>();
+
+    if digits.len() % 2 != 0 {
+        return Err(
+            "the template of the inline assembly has an odd number of hex digits".to_owned(),
+        );
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let text = pair.iter().collect::<String>();
+            u8::from_str_radix(&text, 16).map_err(|_| {
+                format!(
+                    "invalid hex byte \"{}\" in the template of the inline assembly",
+                    text
+                )
+            })
+        })
+        .collect()
+}
+
+/// Format the template as the hex bytes which are separated by the spaces.
+pub fn format_template(template: &[u8]) -> String {
+    template
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn emit_push(code: &mut Vec<u8>, register: u8) {
+    if register >= 8 {
+        code.push(0x41);
+    }
+    code.push(0x50 + (register & 7));
+}
+
+fn emit_pop(code: &mut Vec<u8>, register: u8) {
+    if register >= 8 {
+        code.push(0x41);
+    }
+    code.push(0x58 + (register & 7));
+}
+
+/// Generate the machine code of the shim, see the module doc.
+fn generate_shim_code(template: &[u8], inputs: &[u8], outputs: &[u8], clobbers: &[u8]) -> Vec<u8> {
+    let saved_registers = CALLEE_SAVED_REGISTERS
+        .iter()
+        .copied()
+        .filter(|register| {
+            inputs.contains(register) || outputs.contains(register) || clobbers.contains(register)
+        })
+        .collect::<Vec<_>>();
+
+    let mut code = vec![];
+    for register in &saved_registers {
+        emit_push(&mut code, *register);
+    }
+
+    if !outputs.is_empty() {
+        emit_push(&mut code, PARAM_REGISTERS[inputs.len()]);
+    }
+
+    for param_register in &PARAM_REGISTERS[..inputs.len()] {
+        emit_push(&mut code, *param_register);
+    }
+    for input_register in inputs.iter().rev() {
+        emit_pop(&mut code, *input_register);
+    }
+
+    code.extend_from_slice(template);
+
+    if !outputs.is_empty() {
+        for output_register in outputs {
+            emit_push(&mut code, *output_register);
+        }
+
+        // mov rax, [rsp + disp8]
+        code.extend_from_slice(&[0x48, 0x8b, 0x44, 0x24, (outputs.len() * 8) as u8]);
+
+        for index in (0..outputs.len()).rev() {
+            emit_pop(&mut code, 1);
+            // mov [rax + disp8], rcx
+            code.extend_from_slice(&[0x48, 0x89, 0x48, (index * 8) as u8]);
+        }
+
+        // add rsp, 8
+        code.extend_from_slice(&[0x48, 0x83, 0xc4, 0x08]);
+    }
+
+    for register in saved_registers.iter().rev() {
+        emit_pop(&mut code, *register);
+    }
+
+    // ret
+    code.push(0xc3);
+    code
+}
+
+/// Define the shim of the inline assembly once, the later calls with the
+/// same template and registers return the defined one.
+///
+/// The params of the shim are the inputs and the pointer of the outputs (if
+/// there are outputs), each output takes 8 bytes.
+pub(crate) fn define_inline_asm_function<T: Module>(
+    module: &mut T,
+    template: &[u8],
+    inputs: &[&str],
+    outputs: &[&str],
+    clobbers: &[&str],
+) -> Result<FuncId, ModuleError> {
+    let isa = module.isa();
+    if isa.name() != "x64" || isa.default_call_conv() != CallConv::SystemV {
+        return Err(ModuleError::Backend(anyhow::anyhow!(
+            "the inline assembly is not supported on \"{}\"",
+            isa.triple()
+        )));
+    }
+
+    let param_count = inputs.len() + usize::from(!outputs.is_empty());
+    if param_count > PARAM_REGISTERS.len() {
+        return Err(ModuleError::Backend(anyhow::anyhow!(
+            "the inline assembly has too many inputs, at most {} inputs are supported",
+            PARAM_REGISTERS.len() - usize::from(!outputs.is_empty())
+        )));
+    }
+
+    let parse_registers = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| parse_register(name))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|message| ModuleError::Backend(anyhow::anyhow!(message)))
+    };
+    let input_registers = parse_registers(inputs)?;
+    let output_registers = parse_registers(outputs)?;
+    let clobber_registers = parse_registers(clobbers)?;
+
+    let name = format!(
+        "{}{}.{}.{}.{}",
+        INLINE_ASM_FUNCTION_PREFIX,
+        template
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>(),
+        inputs.join("_"),
+        outputs.join("_"),
+        clobbers.join("_")
+    );
+    if let Some(FuncOrDataId::Func(func_id)) = module.get_name(&name) {
+        return Ok(func_id);
+    }
+
+    let mut sig = module.make_signature();
+    for (_, value_type) in &input_registers {
+        let param_type = match value_type {
+            ValueType::I32 => types::I32,
+            _ => types::I64,
+        };
+        sig.params.push(AbiParam::new(param_type));
+    }
+    if !outputs.is_empty() {
+        sig.params.push(AbiParam::new(types::I64));
+    }
+
+    let take_numbers = |registers: &[(u8, ValueType)]| {
+        registers
+            .iter()
+            .map(|(number, _)| *number)
+            .collect::<Vec<_>>()
+    };
+    let code = generate_shim_code(
+        template,
+        &take_numbers(&input_registers),
+        &take_numbers(&output_registers),
+        &take_numbers(&clobber_registers),
+    );
+
+    let func_id = module.declare_function(&name, Linkage::Local, &sig)?;
+    let func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    module.define_function_bytes(func_id, &func, 16, &code, &[])?;
+    Ok(func_id)
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Record the definitions of the shims of the inline assembly for `validate()`.
+    pub(crate) fn record_inline_asm_functions(&mut self, func_ids: &[FuncId]) {
+        for func_id in func_ids {
+            self.set_symbol_references(SymbolReferences {
+                definition: FuncOrDataId::Func(*func_id),
+                functions: vec![],
+                data: vec![],
+            });
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_jit::JITModule;
+    use cranelift_module::Module;
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        diagnostic::Diagnostic,
+        formatter::format_module,
+        linker::{link_executable, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        sandbox::SandboxMode,
+        test_support::TempFolder,
+    };
+
+    const SOURCE: &str = r#"
+    (module $cpu
+        (function $cpuid export out_pointers (param $leaf i32) (result i32 i32 i32 i32)
+            (code
+                (asm "0f a2" (in "eax" (local_load $leaf)) (in "ecx" (imm_i32 0))
+                    (out "eax") (out "ebx") (out "ecx") (out "edx"))))
+        (function $rotate export out_pointers (param $a i64) (param $b i64) (param $c i64)
+            (result i64 i64 i64)
+            (code
+                (asm "" (in "rsi" (local_load $a)) (in "rdx" (local_load $b))
+                    (in "rdi" (local_load $c)) (out "rdi") (out "rsi") (out "rdx"))))
+        (function $add_saved export (param $a i64) (param $b i64) (result i64)
+            (code
+                // add rbx, r12
+                (asm "4c 01 e3" (in "rbx" (local_load $a)) (in "r12" (local_load $b))
+                    (out "rbx"))))
+        (function $clear_saved export (param $a i64) (result i64)
+            (code
+                // xor ebx, ebx; xor ebp, ebp
+                (asm "31 db 31 ed" (clobber "rbx" "rbp"))
+                (local_load $a)))
+        (function $main export (result i32)
+            (code
+                // lea rax, [rsi + rdi]
+                (truncate_i64_to_i32
+                    (asm "48 8d 04 3e" (in "rdi" (imm_i64 40)) (in "rsi" (imm_i64 2))
+                        (out "rax"))))))
+    "#;
+
+    #[test]
+    fn test_inline_asm() {
+        let module = parse_module(SOURCE).unwrap();
+
+        // JIT
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        assert!(generator.validate().is_ok());
+        generator.module.finalize_definitions().unwrap();
+
+        let get_function = |name: &str| {
+            generator
+                .module
+                .get_finalized_function(assembled_module.get_function_id(name).unwrap())
+        };
+        let func_cpuid = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn(i32, *mut i32, *mut i32, *mut i32) -> i32>(
+                get_function("cpuid"),
+            )
+        };
+        let func_rotate = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn(i64, i64, i64, *mut i64, *mut i64) -> i64>(
+                get_function("rotate"),
+            )
+        };
+        let func_add_saved = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(get_function(
+                "add_saved",
+            ))
+        };
+        let func_clear_saved = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get_function("clear_saved"))
+        };
+
+        // the vendor and the highest leaf
+        let (mut ebx, mut ecx, mut edx) = (0, 0, 0);
+        let eax = func_cpuid(0, &mut ebx, &mut ecx, &mut edx);
+        // `__cpuid()` is safe since Rust 1.87
+        #[allow(unused_unsafe)]
+        let expected = unsafe { std::arch::x86_64::__cpuid(0) };
+        assert_eq!(
+            (eax as u32, ebx as u32, ecx as u32, edx as u32),
+            (expected.eax, expected.ebx, expected.ecx, expected.edx)
+        );
+
+        let (mut second, mut third) = (0, 0);
+        assert_eq!(func_rotate(1, 2, 3, &mut second, &mut third), 3);
+        assert_eq!((second, third), (1, 2));
+
+        assert_eq!(func_add_saved(40, 2), 42);
+        assert_eq!(func_clear_saved(7), 7);
+
+        // object file, the same instructions share the shim
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        assert!(generator
+            .module
+            .get_name("__anna_asm.0fa2.eax_ecx.eax_ebx_ecx_edx.")
+            .is_some());
+
+        let temp_folder = TempFolder::new("inline_asm");
+        let object_file_path = temp_folder.file_path("cpu.o");
+        let exec_file_path = temp_folder.file_path("cpu.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(42));
+
+        // the formatter
+        let formatted = format_module(&parse_module(
+            r#"(module $a (function $f (code (asm "0F31" (out "eax") (out "edx") (clobber "rcx")))))"#,
+        )
+        .unwrap());
+        assert!(formatted.contains(r#"(asm "0f 31" (out "eax") (out "edx") (clobber "rcx"))"#));
+
+        // the errors
+        let get_error = |source: &str| {
+            let source = format!("(module $a (function $f (code {})))", source);
+            let Diagnostic { message, span, .. } = parse_module(&source).unwrap_err();
+            (message, source[span.start..span.end].to_owned())
+        };
+        assert_eq!(
+            get_error(r#"(asm "0f a" (out "eax"))"#),
+            (
+                "the template of the inline assembly has an odd number of hex digits".to_owned(),
+                r#""0f a""#.to_owned()
+            )
+        );
+        assert_eq!(
+            get_error(r#"(asm "0f zz")"#),
+            (
+                "invalid hex byte \"zz\" in the template of the inline assembly".to_owned(),
+                r#""0f zz""#.to_owned()
+            )
+        );
+        assert_eq!(
+            get_error(r#"(asm "90" (out "xmm0"))"#),
+            (
+                "unknown register \"xmm0\"".to_owned(),
+                r#""xmm0""#.to_owned()
+            )
+        );
+        assert_eq!(
+            get_error(r#"(asm "90" (in "rsp" (imm_i64 0)))"#),
+            (
+                "the register \"rsp\" can not be used in the inline assembly".to_owned(),
+                r#""rsp""#.to_owned()
+            )
+        );
+        assert_eq!(
+            get_error(r#"(asm "90" (out "eax") (out "rax"))"#),
+            (
+                "the register \"rax\" is used more than once in \"(out ...)\"".to_owned(),
+                r#""rax""#.to_owned()
+            )
+        );
+
+        let assemble = |source: &str, sandbox_mode: Option<SandboxMode>| {
+            let module =
+                parse_module(&format!("(module $a (function $f (code {})))", source)).unwrap();
+            let mut generator = Generator::<JITModule>::new(vec![]);
+            if let Some(sandbox_mode) = sandbox_mode {
+                generator.enable_sandbox(sandbox_mode);
+            }
+            assemble_module(&module, &mut generator)
+                .unwrap_err()
+                .message
+        };
+        assert_eq!(
+            assemble(
+                r#"(asm "90" (in "rax" (imm_i64 0)) (in "rbx" (imm_i64 0)) (in "rcx" (imm_i64 0))
+                    (in "rdx" (imm_i64 0)) (in "rsi" (imm_i64 0)) (in "rdi" (imm_i64 0)) (out "r8"))"#,
+                None
+            ),
+            "Backend error: the inline assembly has too many inputs, at most 5 inputs are supported"
+        );
+        assert_eq!(
+            assemble(
+                r#"(asm "0f 31" (clobber "rax" "rdx"))"#,
+                Some(SandboxMode::BoundsChecked)
+            ),
+            "the instruction \"asm\" is not allowed in the sandbox"
+        );
+    }
+}
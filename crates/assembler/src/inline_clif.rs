@@ -0,0 +1,272 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::HashMap;
+
+use cranelift_codegen::ir::{
+    Function, InstBuilderBase, InstructionData, JumpTables, Value, ValueListPool,
+};
+use cranelift_frontend::FunctionBuilder;
+
+use crate::ast::ValueType;
+
+// The inline CLIF
+// ---------------
+//
+// The Cranelift IR snippet can be embedded in a function by
+// `(clif (param type...) (result type...) "text" arg...)`, it is the escape
+// hatch for the instructions which are not covered by the assembly text, e.g.
+//
+// ```text
+// (function $rotate (param $value i32) (param $count i32) (result i32)
+//     (code
+//         (clif (param i32 i32) (result i32)
+//             "block0(v0: i32, v1: i32):
+//                  v2 = rotl v0, v1
+//                  return v2"
+//             (local_load $value)
+//             (local_load $count))))
+// ```
+//
+// - the text is the body of a CLIF function whose params and results are
+//   the `param` and `result` types, it is parsed by `cranelift-reader`.
+// - the args are passed as the params of the entry block, and the values of
+//   the `return` are the values of the `clif` instruction.
+// - the instructions are copied into the current block of the function, so the
+//   snippet should consist of a single block, and it can not refer to the other
+//   entities of the function, i.e. the branches, the calls, the stack slots, the
+//   global values and the constant pool are not allowed.
+
+/// Parse the CLIF text as the body of a function with the params and results.
+pub fn parse_inline_clif(
+    text: &str,
+    params: &[ValueType],
+    results: &[ValueType],
+) -> Result<Function, String> {
+    let format_types = |value_types: &[ValueType]| {
+        value_types
+            .iter()
+            .map(|value_type| value_type.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let returns = if results.is_empty() {
+        String::new()
+    } else {
+        format!(" -> {}", format_types(results))
+    };
+    let function_text = format!(
+        "function %inline({}){} {{\n{}\n}}",
+        format_types(params),
+        returns,
+        text
+    );
+
+    let mut functions = cranelift_reader::parse_functions(&function_text)
+        .map_err(|error| format!("failed to parse the CLIF: {}", error))?;
+    let function = functions.remove(0);
+
+    let mut blocks = function.layout.blocks();
+    match (blocks.next(), blocks.next()) {
+        (Some(block), None) => {
+            let param_types = function
+                .dfg
+                .block_params(block)
+                .iter()
+                .map(|value| function.dfg.value_type(*value))
+                .collect::<Vec<_>>();
+            if param_types
+                != function
+                    .signature
+                    .params
+                    .iter()
+                    .map(|param| param.value_type)
+                    .collect::<Vec<_>>()
+            {
+                return Err(format!(
+                    "the params of the block should be ({})",
+                    format_types(params)
+                ));
+            }
+            Ok(function)
+        }
+        _ => Err("the CLIF should consist of a single block".to_owned()),
+    }
+}
+
+/// Copy the instructions of the parsed CLIF function into the current block
+/// of the builder, returns the values of the `return`.
+pub fn splice_inline_clif(
+    function_builder: &mut FunctionBuilder,
+    function: &Function,
+    args: &[Value],
+) -> Result<Vec<Value>, String> {
+    let dfg = &function.dfg;
+    let block = function.layout.entry_block().unwrap();
+
+    let mut value_map: HashMap<Value, Value> = dfg
+        .block_params(block)
+        .iter()
+        .copied()
+        .zip(args.iter().copied())
+        .collect();
+
+    for inst in function.layout.block_insts(block) {
+        let opcode = dfg.insts[inst].opcode();
+        let map_value = |value: Value| value_map[&dfg.resolve_aliases(value)];
+
+        if opcode.is_return() {
+            return Ok(dfg
+                .inst_args(inst)
+                .iter()
+                .map(|value| map_value(*value))
+                .collect());
+        }
+
+        let data = &dfg.insts[inst];
+        if opcode.is_terminator()
+            || matches!(
+                data,
+                InstructionData::BranchTable { .. }
+                    | InstructionData::Brif { .. }
+                    | InstructionData::Call { .. }
+                    | InstructionData::CallIndirect { .. }
+                    | InstructionData::DynamicStackLoad { .. }
+                    | InstructionData::DynamicStackStore { .. }
+                    | InstructionData::FuncAddr { .. }
+                    | InstructionData::Jump { .. }
+                    | InstructionData::MultiAry { .. }
+                    | InstructionData::Shuffle { .. }
+                    | InstructionData::StackLoad { .. }
+                    | InstructionData::StackStore { .. }
+                    | InstructionData::UnaryConst { .. }
+                    | InstructionData::UnaryGlobalValue { .. }
+            )
+        {
+            return Err(format!(
+                "the instruction \"{}\" is not supported in the inline CLIF",
+                opcode
+            ));
+        }
+
+        // the remaining formats keep the arguments inline (i.e. no value list),
+        // so the pools are not used
+        let mut data = *data;
+        data.map_values(&mut ValueListPool::new(), &mut JumpTables::new(), map_value);
+
+        let (new_inst, new_dfg) = function_builder.ins().build(data, dfg.ctrl_typevar(inst));
+        for (value, new_value) in dfg
+            .inst_results(inst)
+            .iter()
+            .zip(new_dfg.inst_results(new_inst))
+        {
+            value_map.insert(*value, *new_value);
+        }
+    }
+
+    Err("the CLIF should end with \"return\"".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{code_generator::Generator, lowering::assemble_module, parser::parse_module};
+
+    #[test]
+    fn test_inline_clif() {
+        let source = r#"
+        (module $app
+            (function $rotate_and_count (param $value i32) (param $count i32) (result i32)
+                (code
+                    (add_i32
+                        (clif (param i32 i32) (result i32)
+                            "block0(v0: i32, v1: i32):
+                                v2 = rotl v0, v1
+                                return v2"
+                            (local_load $value)
+                            (local_load $count))
+                        (clif (param i32) (result i32)
+                            "block0(v0: i32):
+                                v1 = popcnt v0
+                                v2 = iconst.i32 1000
+                                v3 = imul v1, v2
+                                return v3"
+                            (local_load $value))))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+        let rotate_and_count: extern "C" fn(i32, i32) -> i32 = unsafe {
+            std::mem::transmute(
+                generator.module.get_finalized_function(
+                    assembled_module
+                        .get_function_id("rotate_and_count")
+                        .unwrap(),
+                ),
+            )
+        };
+        // 0x8000_0001 rotl 4 = 0x18, popcnt = 2
+        assert_eq!(rotate_and_count(0x8000_0001_u32 as i32, 4), 0x18 + 2000);
+
+        // the errors
+        fn lowering_error(source: &str) -> String {
+            let module = parse_module(source).unwrap();
+            let mut generator = Generator::<JITModule>::new(vec![]);
+            assemble_module(&module, &mut generator)
+                .unwrap_err()
+                .message
+        }
+
+        assert_eq!(
+            lowering_error(
+                r#"(module $a (function $f (result i32)
+                    (code (clif (result i32) "block0:
+                        v0 = iconst.i32 1
+                        jump block1
+                    block1:
+                        return v0"))))"#
+            ),
+            "the CLIF should consist of a single block"
+        );
+        assert_eq!(
+            lowering_error(
+                r#"(module $a (function $f (result i32)
+                    (code (clif (result i32) "block0:
+                        v0 = iconst.i32 1"))))"#
+            ),
+            "the CLIF should end with \"return\""
+        );
+        assert_eq!(
+            lowering_error(
+                r#"(module $a (function $f (result i64)
+                    (code (clif (result i64) "ss0 = explicit_slot 8
+                    block0:
+                        v0 = stack_load.i64 ss0
+                        return v0"))))"#
+            ),
+            "the instruction \"stack_load\" is not supported in the inline CLIF"
+        );
+        assert!(lowering_error(
+            r#"(module $a (function $f (code (clif "block0:
+                v0 = unknown_op 1
+                return"))))"#
+        )
+        .starts_with("failed to parse the CLIF"));
+        assert_eq!(
+            lowering_error(
+                r#"(module $a (function $f (param $x i64) (result i64)
+                    (code (clif (param i64) (result i64) "block0(v0: i32):
+                        return v0" (local_load $x)))))"#
+            ),
+            "the params of the block should be (i64)"
+        );
+    }
+}
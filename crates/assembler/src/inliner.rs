@@ -0,0 +1,518 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::HashMap;
+
+use cranelift_codegen::ir::{
+    ExternalName, Function, GlobalValue, GlobalValueData, Inst, InstructionData, Opcode, StackSlot,
+    Value,
+};
+use cranelift_module::{FuncId, Module};
+
+use crate::code_generator::Generator;
+
+// The function inliner
+// --------------------
+//
+// Cranelift compiles the functions one by one, so it never inlines a function
+// into another, the call overhead dominates when the callee is a tiny function,
+// e.g. an accessor.
+//
+// `Generator::inline_functions()` is an optional pass over the collected
+// functions (i.e. before calling `define_function()`), it replaces the calls
+// to the small functions of the collection with the bodies of the callees.
+//
+// a callee is inlined if:
+//
+// - its inline attribute is not `Never` (see `set_inline_attribute()`), and
+//   it is not a `noreturn` function (see `set_function_attributes()`).
+// - its size (the number of instructions) is not greater than the threshold,
+//   or its inline attribute is `Always`.
+// - it is a "leaf" function with a single block, i.e. the body consists of
+//   straight-line instructions and ends with `return`, and it does not contain
+//   calls, branches, and the instructions which depend on the frame of the
+//   callee itself (e.g. `get_frame_pointer`).
+//
+// the global values, stack slots and constants which are used by the callee
+// are copied into the caller.
+//
+// e.g.
+//
+// ```rust
+// generator.set_inline_attribute(func_get_id, InlineAttribute::Always);
+// generator.inline_functions(&mut functions, &InlineOptions::default());
+// for (func_id, func) in functions {
+//     generator.define_function(func_id, func)?;
+// }
+// ```
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InlineAttribute {
+    /// Inline the function if its size is within the threshold.
+    #[default]
+    Auto,
+
+    /// Always inline the function (if it is inlinable).
+    Always,
+
+    /// Never inline the function.
+    Never,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineOptions {
+    /// The maximum number of instructions (excluding the `return`)
+    /// of the callee to be inlined.
+    pub size_threshold: usize,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        Self { size_threshold: 16 }
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Set the inline attribute of the declared function.
+    pub fn set_inline_attribute(&mut self, func_id: FuncId, inline_attribute: InlineAttribute) {
+        self.inline_attributes.insert(func_id, inline_attribute);
+    }
+
+    /// Inline the calls between the given functions.
+    ///
+    /// Returns the number of the inlined call sites.
+    pub fn inline_functions(
+        &self,
+        functions: &mut [(FuncId, Function)],
+        options: &InlineOptions,
+    ) -> usize {
+        let callees = functions
+            .iter()
+            .filter(|(func_id, func)| {
+                let Some(size) = get_inlinable_size(func) else {
+                    return false;
+                };
+
+                // the `noreturn` functions are cold, see `function_attribute.rs`
+                if self.get_function_attributes(*func_id).noreturn {
+                    return false;
+                }
+
+                match self
+                    .inline_attributes
+                    .get(func_id)
+                    .copied()
+                    .unwrap_or_default()
+                {
+                    InlineAttribute::Auto => size <= options.size_threshold,
+                    InlineAttribute::Always => true,
+                    InlineAttribute::Never => false,
+                }
+            })
+            .map(|(func_id, func)| (*func_id, func.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let mut count = 0;
+
+        for (caller_id, caller) in functions.iter_mut() {
+            let call_sites = caller
+                .layout
+                .blocks()
+                .flat_map(|block| caller.layout.block_insts(block))
+                .filter_map(|inst| {
+                    let callee_id = get_callee_id(caller, inst)?;
+                    if callee_id == *caller_id {
+                        return None;
+                    }
+
+                    let callee = callees.get(&callee_id)?;
+                    let InstructionData::Call { func_ref, .. } = caller.dfg.insts[inst] else {
+                        unreachable!()
+                    };
+                    let signature =
+                        &caller.dfg.signatures[caller.dfg.ext_funcs[func_ref].signature];
+
+                    (signature == &callee.signature).then_some((inst, callee))
+                })
+                .collect::<Vec<_>>();
+
+            for (call, callee) in call_sites {
+                inline_call(caller, call, callee);
+                count += 1;
+            }
+        }
+
+        count
+    }
+}
+
+/// Get the `FuncId` of the callee if the instruction is a direct call to
+/// a function of the module.
+pub(crate) fn get_callee_id(func: &Function, inst: Inst) -> Option<FuncId> {
+    let InstructionData::Call { func_ref, .. } = func.dfg.insts[inst] else {
+        return None;
+    };
+
+    let ExternalName::User(name_ref) = func.dfg.ext_funcs[func_ref].name else {
+        return None;
+    };
+
+    let name = &func.params.user_named_funcs()[name_ref];
+    (name.namespace == 0).then(|| FuncId::from_u32(name.index))
+}
+
+/// Get the number of instructions (excluding the `return`) of the function,
+/// returns `None` if the function can not be inlined.
+fn get_inlinable_size(func: &Function) -> Option<usize> {
+    let mut blocks = func.layout.blocks();
+    let block = blocks.next()?;
+    if blocks.next().is_some() {
+        return None;
+    }
+
+    let last_inst = func.layout.last_inst(block)?;
+    if func.dfg.insts[last_inst].opcode() != Opcode::Return {
+        return None;
+    }
+
+    for inst in func.layout.block_insts(block) {
+        if inst == last_inst {
+            break;
+        }
+
+        let inlinable = match &func.dfg.insts[inst] {
+            InstructionData::MultiAry { .. }
+            | InstructionData::Call { .. }
+            | InstructionData::CallIndirect { .. }
+            | InstructionData::FuncAddr { .. }
+            | InstructionData::Jump { .. }
+            | InstructionData::Brif { .. }
+            | InstructionData::BranchTable { .. }
+            | InstructionData::Shuffle { .. }
+            | InstructionData::DynamicStackLoad { .. }
+            | InstructionData::DynamicStackStore { .. } => false,
+            InstructionData::UnaryGlobalValue { global_value, .. } => {
+                !uses_vmctx(func, *global_value)
+            }
+            instruction_data => !matches!(
+                instruction_data.opcode(),
+                Opcode::GetFramePointer | Opcode::GetStackPointer | Opcode::GetReturnAddress
+            ),
+        };
+
+        if !inlinable {
+            return None;
+        }
+    }
+
+    Some(func.layout.block_insts(block).count() - 1)
+}
+
+/// The `vmctx` global value refers to the parameter of the callee itself.
+fn uses_vmctx(func: &Function, global_value: GlobalValue) -> bool {
+    match &func.global_values[global_value] {
+        GlobalValueData::VMContext => true,
+        GlobalValueData::Load { base, .. } | GlobalValueData::IAddImm { base, .. } => {
+            uses_vmctx(func, *base)
+        }
+        _ => false,
+    }
+}
+
+/// The mapping of the entities, i.e. callee entity -> caller entity.
+#[derive(Default)]
+struct EntityMapping {
+    values: HashMap<Value, Value>,
+    global_values: HashMap<GlobalValue, GlobalValue>,
+    stack_slots: HashMap<StackSlot, StackSlot>,
+}
+
+/// Replace the call instruction with the body of the callee.
+fn inline_call(caller: &mut Function, call: Inst, callee: &Function) {
+    let mut mapping = EntityMapping::default();
+
+    let callee_block = callee.layout.entry_block().unwrap();
+    let call_args = caller.dfg.inst_args(call).to_vec();
+    for (param, arg) in callee.dfg.block_params(callee_block).iter().zip(call_args) {
+        mapping.values.insert(*param, arg);
+    }
+
+    let srcloc = caller.srcloc(call);
+
+    for inst in callee.layout.block_insts(callee_block) {
+        let mut instruction_data = callee.dfg.insts[inst];
+
+        if instruction_data.opcode() == Opcode::Return {
+            let return_values = callee
+                .dfg
+                .inst_args(inst)
+                .iter()
+                .map(|value| mapping.values[&callee.dfg.resolve_aliases(*value)])
+                .collect::<Vec<_>>();
+
+            let results = caller.dfg.inst_results(call).to_vec();
+            caller.dfg.clear_results(call);
+            caller.layout.remove_inst(call);
+
+            for (result, return_value) in results.into_iter().zip(return_values) {
+                caller.dfg.change_to_alias(result, return_value);
+            }
+            break;
+        }
+
+        match &mut instruction_data {
+            InstructionData::UnaryGlobalValue { global_value, .. } => {
+                *global_value = import_global_value(caller, callee, *global_value, &mut mapping);
+            }
+            InstructionData::StackLoad { stack_slot, .. }
+            | InstructionData::StackStore { stack_slot, .. } => {
+                *stack_slot = *mapping.stack_slots.entry(*stack_slot).or_insert_with(|| {
+                    caller.create_sized_stack_slot(callee.sized_stack_slots[*stack_slot].clone())
+                });
+            }
+            InstructionData::UnaryConst {
+                constant_handle, ..
+            } => {
+                let constant_data = callee.dfg.constants.get(*constant_handle).clone();
+                *constant_handle = caller.dfg.constants.insert(constant_data);
+            }
+            _ => {}
+        }
+
+        let dfg = &mut caller.dfg;
+        instruction_data.map_values(&mut dfg.value_lists, &mut dfg.jump_tables, |value| {
+            mapping.values[&callee.dfg.resolve_aliases(value)]
+        });
+
+        let new_inst = caller.dfg.make_inst(instruction_data);
+        caller
+            .dfg
+            .make_inst_results(new_inst, callee.dfg.ctrl_typevar(inst));
+        caller.layout.insert_inst(new_inst, call);
+        caller.set_srcloc(new_inst, srcloc);
+
+        for (result, new_result) in callee
+            .dfg
+            .inst_results(inst)
+            .iter()
+            .zip(caller.dfg.inst_results(new_inst))
+        {
+            mapping.values.insert(*result, *new_result);
+        }
+    }
+}
+
+/// Copy the global value (and its base) of the callee into the caller.
+fn import_global_value(
+    caller: &mut Function,
+    callee: &Function,
+    global_value: GlobalValue,
+    mapping: &mut EntityMapping,
+) -> GlobalValue {
+    if let Some(new_global_value) = mapping.global_values.get(&global_value) {
+        return *new_global_value;
+    }
+
+    let new_global_value_data = match callee.global_values[global_value].clone() {
+        GlobalValueData::Load {
+            base,
+            offset,
+            global_type,
+            flags,
+        } => GlobalValueData::Load {
+            base: import_global_value(caller, callee, base, mapping),
+            offset,
+            global_type,
+            flags,
+        },
+        GlobalValueData::IAddImm {
+            base,
+            offset,
+            global_type,
+        } => GlobalValueData::IAddImm {
+            base: import_global_value(caller, callee, base, mapping),
+            offset,
+            global_type,
+        },
+        GlobalValueData::Symbol {
+            name,
+            offset,
+            colocated,
+            tls,
+        } => {
+            let name = match name {
+                ExternalName::User(name_ref) => {
+                    let user_name = callee.params.user_named_funcs()[name_ref].clone();
+                    ExternalName::User(caller.declare_imported_user_function(user_name))
+                }
+                _ => name,
+            };
+            GlobalValueData::Symbol {
+                name,
+                offset,
+                colocated,
+                tls,
+            }
+        }
+        global_value_data => global_value_data,
+    };
+
+    let new_global_value = caller.create_global_value(new_global_value_data);
+    mapping.global_values.insert(global_value, new_global_value);
+    new_global_value
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, MemFlags, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{
+        code_generator::{function_to_clif, Generator},
+        inliner::{InlineAttribute, InlineOptions},
+        test_support::run_executable_binary_and_get_exit_code,
+    };
+
+    fn build_module(inline_attribute: InlineAttribute) -> (usize, String, Vec<u8>) {
+        // ```rust
+        // static NUMBER: i32 = 11;
+        // fn get_number(offset: i32) -> i32 {
+        //     NUMBER + offset
+        // }
+        // fn main() -> i32 {
+        //     get_number(2)
+        // }
+        // ```
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let data_number_id = generator
+            .define_initialized_data(
+                "number",
+                11i32.to_le_bytes().to_vec(),
+                4,
+                false,
+                false,
+                false,
+            )
+            .unwrap();
+
+        let mut func_get_number_sig = generator.module.make_signature();
+        func_get_number_sig.params.push(AbiParam::new(types::I32));
+        func_get_number_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_get_number_id = generator
+            .module
+            .declare_function("get_number", Linkage::Local, &func_get_number_sig)
+            .unwrap();
+        generator.set_inline_attribute(func_get_number_id, inline_attribute);
+
+        let mut func_get_number = Function::with_name_signature(
+            UserFuncName::user(0, func_get_number_id.as_u32()),
+            func_get_number_sig.clone(),
+        );
+
+        let data_number_gv = generator
+            .module
+            .declare_data_in_func(data_number_id, &mut func_get_number);
+
+        let mut function_builder = FunctionBuilder::new(
+            &mut func_get_number,
+            &mut generator.function_builder_context,
+        );
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        let value_1 = function_builder
+            .ins()
+            .symbol_value(pointer_type, data_number_gv);
+        let value_2 = function_builder
+            .ins()
+            .load(types::I32, MemFlags::new(), value_1, 0);
+        let value_3 = function_builder.ins().iadd(value_2, value_0);
+        function_builder.ins().return_(&[value_3]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let func_get_number_ref = generator
+            .module
+            .declare_func_in_func(func_get_number_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.ins().iconst(types::I32, 2);
+        let call_0 = function_builder.ins().call(func_get_number_ref, &[value_0]);
+        let value_1 = function_builder.inst_results(call_0)[0];
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        let mut functions = vec![
+            (func_get_number_id, func_get_number),
+            (func_main_id, func_main),
+        ];
+        let count = generator.inline_functions(&mut functions, &InlineOptions::default());
+        let clif_main = function_to_clif(&functions[1].1);
+
+        for (func_id, func) in functions {
+            generator.define_function(func_id, func).unwrap();
+        }
+
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+        (count, clif_main, module_binary)
+    }
+
+    #[test]
+    fn test_inline_functions() {
+        let (count, clif_main, module_binary) = build_module(InlineAttribute::Auto);
+        assert_eq!(count, 1);
+        assert!(!clif_main.contains("call"));
+        assert!(clif_main.contains("symbol_value"));
+
+        let exit_code_opt =
+            run_executable_binary_and_get_exit_code(&module_binary, "test_inline_functions", false);
+        assert_eq!(exit_code_opt, Some(13));
+
+        // the attribute `Never`
+        let (count, clif_main, module_binary) = build_module(InlineAttribute::Never);
+        assert_eq!(count, 0);
+        assert!(clif_main.contains("call fn0(v0)"));
+
+        let exit_code_opt = run_executable_binary_and_get_exit_code(
+            &module_binary,
+            "test_inline_functions_never",
+            false,
+        );
+        assert_eq!(exit_code_opt, Some(13));
+    }
+}
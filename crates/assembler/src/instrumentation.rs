@@ -0,0 +1,243 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    cursor::{Cursor, FuncCursor},
+    ir::{types, AbiParam, Function, InstBuilder, Opcode},
+};
+use cranelift_module::{FuncId, Linkage, Module, ModuleError};
+
+use crate::code_generator::Generator;
+
+// The function entry/exit instrumentation
+// ---------------------------------------
+//
+// It is similar to the option `-finstrument-functions` of GCC and Clang, when
+// it is enabled, the calls to the hook functions are inserted into each function
+// which is defined afterwards:
+//
+// - `enter(func_id)` at the entry of the function.
+// - `exit(func_id)` before each `return` (and the tail calls).
+//
+// the hook functions are supplied by the user (e.g. a runtime library or the
+// symbols of the JIT host), their signature is `extern "C" fn(func_id: u32)`,
+// so the call tracing and the flamegraphs can be generated without external tools.
+//
+// e.g.
+//
+// ```rust
+// generator.enable_function_instrumentation("__anna_enter", "__anna_exit")?;
+// ```
+//
+// ref:
+// - https://gcc.gnu.org/onlinedocs/gcc/Instrumentation-Options.html#index-finstrument-functions
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstrumentationHooks {
+    pub enter: FuncId,
+    pub exit: FuncId,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Enable the function entry/exit instrumentation, the hook functions
+    /// are declared as imported functions.
+    pub fn enable_function_instrumentation(
+        &mut self,
+        enter_name: &str,
+        exit_name: &str,
+    ) -> Result<(), ModuleError> {
+        let mut hook_sig = self.module.make_signature();
+        hook_sig.params.push(AbiParam::new(types::I32));
+
+        let enter = self
+            .module
+            .declare_function(enter_name, Linkage::Import, &hook_sig)?;
+        let exit = self
+            .module
+            .declare_function(exit_name, Linkage::Import, &hook_sig)?;
+
+        self.instrumentation = Some(InstrumentationHooks { enter, exit });
+        Ok(())
+    }
+
+    /// Insert the calls to the hook functions (and the `mcount()` of the
+    /// profiling, see `enable_profiling()`, and the block counters of the
+    /// coverage, see `enable_coverage()`) into the function.
+    pub(crate) fn instrument_function(&mut self, func_id: FuncId, func: &mut Function) {
+        self.insert_coverage_counters(func_id, func);
+        self.insert_entry_hook_calls(func_id, func);
+
+        // it is inserted at last, so that it precedes the other calls.
+        self.insert_profiling_call(func);
+    }
+
+    fn insert_entry_hook_calls(&mut self, func_id: FuncId, func: &mut Function) {
+        let Some(hooks) = self.instrumentation else {
+            return;
+        };
+
+        // the hook functions themselves are not instrumented
+        if func_id == hooks.enter || func_id == hooks.exit {
+            return;
+        }
+
+        let Some(entry_block) = func.layout.entry_block() else {
+            return;
+        };
+
+        let enter_ref = self.module.declare_func_in_func(hooks.enter, func);
+        let exit_ref = self.module.declare_func_in_func(hooks.exit, func);
+
+        let exit_points = func
+            .layout
+            .blocks()
+            .filter_map(|block| func.layout.last_inst(block))
+            .filter(|inst| {
+                matches!(
+                    func.dfg.insts[*inst].opcode(),
+                    Opcode::Return | Opcode::ReturnCall | Opcode::ReturnCallIndirect
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut cursor = FuncCursor::new(func);
+
+        cursor.goto_first_insertion_point(entry_block);
+        let value_func_id = cursor.ins().iconst(types::I32, func_id.as_u32() as i64);
+        cursor.ins().call(enter_ref, &[value_func_id]);
+
+        for inst in exit_points {
+            cursor.goto_inst(inst);
+            let value_func_id = cursor.ins().iconst(types::I32, func_id.as_u32() as i64);
+            cursor.ins().call(exit_ref, &[value_func_id]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+
+    use crate::code_generator::Generator;
+
+    static TRACE: Mutex<Vec<(bool, u32)>> = Mutex::new(vec![]);
+
+    extern "C" fn hook_enter(func_id: u32) {
+        TRACE.lock().unwrap().push((true, func_id));
+    }
+
+    extern "C" fn hook_exit(func_id: u32) {
+        TRACE.lock().unwrap().push((false, func_id));
+    }
+
+    #[test]
+    fn test_function_instrumentation() {
+        let symbols = vec![
+            ("hook_enter".to_owned(), hook_enter as *const u8),
+            ("hook_exit".to_owned(), hook_exit as *const u8),
+        ];
+
+        let mut generator = Generator::<JITModule>::new(symbols);
+        generator
+            .enable_function_instrumentation("hook_enter", "hook_exit")
+            .unwrap();
+
+        // ```rust
+        // fn inc(a: i32) -> i32 {
+        //     a + 1
+        // }
+        // fn main() -> i32 {
+        //     inc(10)
+        // }
+        // ```
+        let mut func_inc_sig = generator.module.make_signature();
+        func_inc_sig.params.push(AbiParam::new(types::I32));
+        func_inc_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_inc_id = generator
+            .module
+            .declare_function("inc", Linkage::Local, &func_inc_sig)
+            .unwrap();
+
+        let mut func_inc = Function::with_name_signature(
+            UserFuncName::user(0, func_inc_id.as_u32()),
+            func_inc_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_inc, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        let value_1 = function_builder.ins().iadd_imm(value_0, 1);
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_inc_id, func_inc).unwrap();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Local, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let func_inc_ref = generator
+            .module
+            .declare_func_in_func(func_inc_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.ins().iconst(types::I32, 10);
+        let call_0 = function_builder.ins().call(func_inc_ref, &[value_0]);
+        let value_1 = function_builder.inst_results(call_0)[0];
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        // the instrumented IR is dumped
+        assert!(generator.dump_clif().contains("call fn1(v"));
+
+        generator.module.finalize_definitions().unwrap();
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let func_main: extern "C" fn() -> i32 = unsafe { std::mem::transmute(func_main_ptr) };
+        assert_eq!(func_main(), 11);
+
+        let inc = func_inc_id.as_u32();
+        let main = func_main_id.as_u32();
+        assert_eq!(
+            *TRACE.lock().unwrap(),
+            vec![(true, main), (true, inc), (false, inc), (false, main)]
+        );
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Write;
+
+use cranelift_codegen::ir::{Function, UserFuncName};
+use cranelift_module::{Linkage, Module, ModuleError};
+use cranelift_object::ObjectModule;
+
+use crate::code_generator::{function_to_clif, DataDefinition, Generator};
+
+// The intermediate file (*.ancir)
+// -------------------------------
+//
+// The intermediate file contains the module before emission, it allows the
+// front-end (which generates the IR) and the back-end (which generates the
+// machine code) to run in separate processes, and it can be used as the
+// cache of the build.
+//
+// The file is a text file, e.g.
+//
+// ```text
+// ancir 1
+// target x86_64-unknown-linux-gnu
+// function 0 export main
+// function 1 import add
+// data 0 local readonly - message
+// data 1 export writable tls counter
+// data_content 0 align 1 init 48656c6c6f
+// data_content 1 align 8 zeroinit 8
+// clif
+// function u0:0() -> i32 system_v {
+//     ...
+// }
+//
+// function u0:1(i32, i32) -> i32 system_v {
+// }
+// ```
+//
+// - the header lines declare the functions and data objects in the order of their ids,
+//   the last field is the name, and "-" means anonymous.
+// - the section after the line "clif" contains the CLIF of all functions, the functions
+//   which are declared only (e.g. the imported functions) have an empty body, which is
+//   used to carry the signature.
+//
+// Note that the data relocations (i.e. the addresses of functions and data within
+// the data objects) are not supported yet, since `Generator` does not create them.
+
+pub const IR_FILE_EXTENSION: &str = "ancir";
+
+const IR_VERSION: u32 = 1;
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Generate the content of the intermediate file (.ancir) of the module.
+    pub fn to_ir(&self) -> String {
+        let mut text = String::new();
+        let declarations = self.module.declarations();
+
+        writeln!(text, "ancir {}", IR_VERSION).unwrap();
+        writeln!(text, "target {}", self.module.isa().triple()).unwrap();
+
+        for (func_id, declaration) in declarations.get_functions() {
+            writeln!(
+                text,
+                "function {} {} {}",
+                func_id.as_u32(),
+                linkage_to_str(declaration.linkage),
+                declaration.name.as_deref().unwrap_or("-")
+            )
+            .unwrap();
+        }
+
+        for (data_id, declaration) in declarations.get_data_objects() {
+            writeln!(
+                text,
+                "data {} {} {} {} {}",
+                data_id.as_u32(),
+                linkage_to_str(declaration.linkage),
+                if declaration.writable {
+                    "writable"
+                } else {
+                    "readonly"
+                },
+                if declaration.tls { "tls" } else { "-" },
+                declaration.name.as_deref().unwrap_or("-")
+            )
+            .unwrap();
+        }
+
+        for (data_id, data_definition) in &self.data_definitions {
+            match data_definition {
+                DataDefinition::Initialized { data, align } => {
+                    let hex = data
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect::<String>();
+                    writeln!(
+                        text,
+                        "data_content {} align {} init {}",
+                        data_id.as_u32(),
+                        align,
+                        hex
+                    )
+                    .unwrap();
+                }
+                DataDefinition::Uninitialized { size, align } => {
+                    writeln!(
+                        text,
+                        "data_content {} align {} zeroinit {}",
+                        data_id.as_u32(),
+                        align,
+                        size
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        text.push_str("clif\n");
+
+        for (func_id, declaration) in declarations.get_functions() {
+            match self.clif_functions.iter().find(|(id, _)| *id == func_id) {
+                Some((_, clif_text)) => text.push_str(clif_text),
+                None => {
+                    let function = Function::with_name_signature(
+                        UserFuncName::user(0, func_id.as_u32()),
+                        declaration.signature.clone(),
+                    );
+                    text.push_str(&function_to_clif(&function));
+                }
+            }
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /// Re-create the functions and data objects of the intermediate file
+    /// in the current module, the module should be empty.
+    pub fn import_ir(&mut self, ir: &str) -> Result<(), ModuleError> {
+        let (header, clif_text) = split_ir(ir)?;
+
+        let functions = cranelift_reader::parse_functions(clif_text)
+            .map_err(|e| ir_error(&format!("Failed to parse CLIF: {}", e)))?;
+
+        // declare functions
+        let mut func_ids = vec![];
+        for line in header.lines().filter(|line| line.starts_with("function ")) {
+            let fields = line.splitn(4, ' ').collect::<Vec<_>>();
+            if fields.len() != 4 {
+                return Err(ir_error(&format!("Invalid function declaration: {}", line)));
+            }
+
+            let func_idx = func_ids.len() as u32;
+            let function = functions
+                .iter()
+                .find(|function| {
+                    matches!(&function.name, UserFuncName::User(name)
+                        if name.namespace == 0 && name.index == func_idx)
+                })
+                .ok_or_else(|| ir_error(&format!("Missing the CLIF of function: {}", line)))?;
+
+            let linkage = str_to_linkage(fields[2])?;
+            let func_id = match fields[3] {
+                "-" => self
+                    .module
+                    .declare_anonymous_function(&function.signature)?,
+                name => self
+                    .module
+                    .declare_function(name, linkage, &function.signature)?,
+            };
+            func_ids.push(func_id);
+        }
+
+        // declare data objects
+        let mut data_ids = vec![];
+        for line in header.lines().filter(|line| line.starts_with("data ")) {
+            let fields = line.splitn(6, ' ').collect::<Vec<_>>();
+            if fields.len() != 6 {
+                return Err(ir_error(&format!("Invalid data declaration: {}", line)));
+            }
+
+            let linkage = str_to_linkage(fields[2])?;
+            let writable = fields[3] == "writable";
+            let thread_local = fields[4] == "tls";
+            let data_id = match fields[5] {
+                "-" => self.module.declare_anonymous_data(writable, thread_local)?,
+                name => self
+                    .module
+                    .declare_data(name, linkage, writable, thread_local)?,
+            };
+            data_ids.push(data_id);
+        }
+
+        // define data objects
+        for line in header
+            .lines()
+            .filter(|line| line.starts_with("data_content "))
+        {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            let (data_id, data_definition) = match fields[..] {
+                ["data_content", idx, "align", align, kind, content] => {
+                    let data_id = parse_number::<u32>(idx)
+                        .ok()
+                        .and_then(|idx| data_ids.get(idx as usize).copied())
+                        .ok_or_else(|| ir_error(&format!("Undeclared data: {}", line)))?;
+                    let align = parse_number::<u64>(align)?;
+
+                    let data_definition = match kind {
+                        "init" => DataDefinition::Initialized {
+                            data: parse_hex(content)?,
+                            align,
+                        },
+                        "zeroinit" => DataDefinition::Uninitialized {
+                            size: parse_number::<usize>(content)?,
+                            align,
+                        },
+                        _ => {
+                            return Err(ir_error(&format!("Invalid data content: {}", line)));
+                        }
+                    };
+
+                    (data_id, data_definition)
+                }
+                _ => return Err(ir_error(&format!("Invalid data content: {}", line))),
+            };
+
+            self.define_data_content(data_id, data_definition)?;
+        }
+
+        // define functions
+        for function in functions {
+            let UserFuncName::User(name) = &function.name else {
+                return Err(ir_error(&format!(
+                    "Invalid function name: {}",
+                    function.name
+                )));
+            };
+
+            let func_id = func_ids
+                .get(name.index as usize)
+                .copied()
+                .ok_or_else(|| ModuleError::Undeclared(function.name.to_string()))?;
+
+            // the functions which have no body are declarations only
+            if function.layout.entry_block().is_some() {
+                self.define_function(func_id, function)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Generator<ObjectModule> {
+    /// Create a generator from the content of the intermediate file (.ancir),
+    /// the target platform is the one recorded in the file.
+    pub fn from_ir(module_name: &str, ir: &str) -> Result<Self, ModuleError> {
+        let (header, _) = split_ir(ir)?;
+
+        let target = header
+            .lines()
+            .find_map(|line| line.strip_prefix("target "))
+            .ok_or_else(|| ir_error("Missing the target"))?;
+
+        let mut generator = Generator::<ObjectModule>::new(module_name, Some(target));
+        generator.import_ir(ir)?;
+        Ok(generator)
+    }
+}
+
+/// Split the IR into the header part and the CLIF part.
+fn split_ir(ir: &str) -> Result<(&str, &str), ModuleError> {
+    let Some(first_line) = ir.lines().next() else {
+        return Err(ir_error("Empty intermediate file"));
+    };
+
+    match first_line.strip_prefix("ancir ") {
+        Some(version) if parse_number::<u32>(version)? == IR_VERSION => {}
+        _ => return Err(ir_error("Unsupported intermediate file version")),
+    }
+
+    if let Some(pos) = ir.find("\nclif\n") {
+        Ok((&ir[..pos], &ir[pos + "\nclif\n".len()..]))
+    } else {
+        Err(ir_error("Missing the CLIF section"))
+    }
+}
+
+fn linkage_to_str(linkage: Linkage) -> &'static str {
+    match linkage {
+        Linkage::Import => "import",
+        Linkage::Local => "local",
+        Linkage::Preemptible => "preemptible",
+        Linkage::Hidden => "hidden",
+        Linkage::Export => "export",
+    }
+}
+
+fn str_to_linkage(s: &str) -> Result<Linkage, ModuleError> {
+    let linkage = match s {
+        "import" => Linkage::Import,
+        "local" => Linkage::Local,
+        "preemptible" => Linkage::Preemptible,
+        "hidden" => Linkage::Hidden,
+        "export" => Linkage::Export,
+        _ => return Err(ir_error(&format!("Invalid linkage: {}", s))),
+    };
+    Ok(linkage)
+}
+
+fn parse_number<N: std::str::FromStr>(s: &str) -> Result<N, ModuleError> {
+    s.parse::<N>()
+        .map_err(|_| ir_error(&format!("Invalid number: {}", s)))
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, ModuleError> {
+    if !s.len().is_multiple_of(2) {
+        return Err(ir_error(&format!("Invalid hex data: {}", s)));
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&s[idx..idx + 2], 16)
+                .map_err(|_| ir_error(&format!("Invalid hex data: {}", s)))
+        })
+        .collect()
+}
+
+fn ir_error(message: &str) -> ModuleError {
+    ModuleError::Backend(anyhow::anyhow!("{}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, MemFlags, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{code_generator::Generator, test_support::run_executable_binary_and_get_exit_code};
+
+    #[test]
+    fn test_intermediate_save_and_load() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+
+        // build function "main"
+        //
+        // ```rust
+        // static NUMBER: i32 = 11;
+        // static mut BUF: [u8; 16] = [0; 16];
+        // extern "C" fn abs(i32) -> i32;
+        //
+        // fn main () -> i32 {
+        //    NUMBER + 2
+        // }
+        // ```
+        let data_number_id = generator
+            .define_initialized_data(
+                "number",
+                11i32.to_le_bytes().to_vec(),
+                4,
+                false,
+                false,
+                false,
+            )
+            .unwrap();
+        generator
+            .define_uninitialized_data("buf", 16, 8, true, false)
+            .unwrap();
+
+        let mut func_abs_sig = generator.module.make_signature();
+        func_abs_sig.params.push(AbiParam::new(types::I32));
+        func_abs_sig.returns.push(AbiParam::new(types::I32));
+        generator
+            .module
+            .declare_function("abs", Linkage::Import, &func_abs_sig)
+            .unwrap();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let data_number_gv = generator
+            .module
+            .declare_data_in_func(data_number_id, &mut func_main);
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder
+            .ins()
+            .symbol_value(pointer_type, data_number_gv);
+        let value_1 = function_builder
+            .ins()
+            .load(types::I32, MemFlags::new(), value_0, 0);
+        let value_2 = function_builder.ins().iadd_imm(value_1, 2);
+        function_builder.ins().return_(&[value_2]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        let ir = generator.to_ir();
+        assert!(ir.starts_with("ancir 1\ntarget x86_64-unknown-linux-gnu\n"));
+        assert!(ir.contains("\nfunction 0 import abs\n"));
+        assert!(ir.contains("\nfunction 1 export main\n"));
+        assert!(ir.contains("\ndata 0 local readonly - number\n"));
+        assert!(ir.contains("\ndata 1 export writable - buf\n"));
+        assert!(ir.contains("\ndata_content 0 align 4 init 0b000000\n"));
+        assert!(ir.contains("\ndata_content 1 align 8 zeroinit 16\n"));
+
+        // load
+        let generator2 = Generator::<ObjectModule>::from_ir("main", &ir).unwrap();
+        assert_eq!(generator2.to_ir(), ir);
+
+        let object_product = generator2.finish().unwrap();
+        let module_binary = object_product.emit().unwrap();
+
+        let exit_code_opt = run_executable_binary_and_get_exit_code(
+            &module_binary,
+            "test_intermediate_save_and_load",
+            false,
+        );
+        assert_eq!(exit_code_opt, Some(13));
+
+        // invalid file
+        assert!(Generator::<ObjectModule>::from_ir("main", "ancir 99\nclif\n").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{InstBuilder, MemFlags, Type, Value};
+use cranelift_frontend::FunctionBuilder;
+
+// The layout of the composite data
+// --------------------------------
+//
+// The sizes, alignments and field offsets of the C-style structs, unions and
+// arrays, the rules are the same as the C compilers (on the targets which are
+// supported by Cranelift):
+//
+// - the size and the alignment of a scalar are its natural size, e.g. the
+//   `i64` and `f64` are aligned to 8 bytes, the pointer type of the target
+//   is `module.isa().pointer_type()`.
+// - the fields of a struct are placed in the order of declaration, each field
+//   is placed at the next offset which is a multiple of its alignment.
+// - the fields of a union are placed at offset 0.
+// - the alignment of a struct/union is the maximum alignment of its fields,
+//   and the size is rounded up to a multiple of the alignment.
+// - the size of an array is `element size * length`, its alignment is the
+//   alignment of the element.
+//
+// e.g.
+//
+// ```rust
+// // struct { a: u8, b: i64, c: [i32; 3] }
+// let layout = StructLayout::new_struct(vec![
+//     ("a".to_owned(), DataType::Scalar(types::I8)),
+//     ("b".to_owned(), DataType::Scalar(types::I64)),
+//     ("c".to_owned(), DataType::Array(Box::new(DataType::Scalar(types::I32)), 3)),
+// ]);
+// assert_eq!(layout.get_field("b").unwrap().offset, 8);
+//
+// let value_b = layout.emit_load_field(&mut function_builder, value_ptr, "b");
+// ```
+//
+// ref:
+// - https://en.cppreference.com/w/c/language/object#Alignment
+// - System V ABI, "Aggregates and Unions"
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataType {
+    Scalar(Type),
+    Array(Box<DataType>, u32),
+    Struct(StructLayout),
+}
+
+impl DataType {
+    pub fn size(&self) -> u32 {
+        match self {
+            DataType::Scalar(ty) => ty.bytes(),
+            DataType::Array(element, length) => element.size() * length,
+            DataType::Struct(layout) => layout.size,
+        }
+    }
+
+    pub fn align(&self) -> u32 {
+        match self {
+            DataType::Scalar(ty) => ty.bytes(),
+            DataType::Array(element, _) => element.align(),
+            DataType::Struct(layout) => layout.align,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub name: String,
+    pub offset: u32,
+    pub data_type: DataType,
+}
+
+/// The layout of a struct or a union.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructLayout {
+    pub size: u32,
+    pub align: u32,
+    pub fields: Vec<FieldLayout>,
+}
+
+impl StructLayout {
+    pub fn new_struct(fields: Vec<(String, DataType)>) -> Self {
+        let mut offset: u32 = 0;
+        let mut align: u32 = 1;
+
+        let fields = fields
+            .into_iter()
+            .map(|(name, data_type)| {
+                let field_align = data_type.align();
+                let field_offset = offset.next_multiple_of(field_align);
+
+                offset = field_offset + data_type.size();
+                align = align.max(field_align);
+
+                FieldLayout {
+                    name,
+                    offset: field_offset,
+                    data_type,
+                }
+            })
+            .collect();
+
+        Self {
+            size: offset.next_multiple_of(align),
+            align,
+            fields,
+        }
+    }
+
+    pub fn new_union(fields: Vec<(String, DataType)>) -> Self {
+        let size = fields
+            .iter()
+            .map(|(_, item)| item.size())
+            .max()
+            .unwrap_or(0);
+        let align = fields
+            .iter()
+            .map(|(_, item)| item.align())
+            .max()
+            .unwrap_or(1);
+
+        let fields = fields
+            .into_iter()
+            .map(|(name, data_type)| FieldLayout {
+                name,
+                offset: 0,
+                data_type,
+            })
+            .collect();
+
+        Self {
+            size: size.next_multiple_of(align),
+            align,
+            fields,
+        }
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Get the offset and the type of the scalar field by the path, e.g. "a.b".
+    pub fn get_scalar_field(&self, path: &str) -> Option<(u32, Type)> {
+        let (name, remain) = match path.split_once('.') {
+            Some((name, remain)) => (name, Some(remain)),
+            None => (path, None),
+        };
+
+        let field = self.get_field(name)?;
+        match (&field.data_type, remain) {
+            (DataType::Scalar(ty), None) => Some((field.offset, *ty)),
+            (DataType::Struct(layout), Some(remain)) => layout
+                .get_scalar_field(remain)
+                .map(|(offset, ty)| (field.offset + offset, ty)),
+            _ => None,
+        }
+    }
+
+    fn expect_scalar_field(&self, path: &str) -> (u32, Type) {
+        self.get_scalar_field(path)
+            .unwrap_or_else(|| panic!("the scalar field \"{}\" is not found", path))
+    }
+
+    /// Load the scalar field (e.g. "a" or "a.b") of the struct which is pointed
+    /// by `ptr`, it panics if the field does not exist.
+    pub fn emit_load_field(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        ptr: Value,
+        path: &str,
+    ) -> Value {
+        let (offset, ty) = self.expect_scalar_field(path);
+        function_builder
+            .ins()
+            .load(ty, MemFlags::trusted(), ptr, offset as i32)
+    }
+
+    /// Store the value to the scalar field of the struct which is pointed by `ptr`.
+    pub fn emit_store_field(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        ptr: Value,
+        path: &str,
+        value: Value,
+    ) {
+        let (offset, _) = self.expect_scalar_field(path);
+        function_builder
+            .ins()
+            .store(MemFlags::trusted(), value, ptr, offset as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{
+        types, AbiParam, Function, InstBuilder, StackSlotData, StackSlotKind, UserFuncName,
+    };
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        layout::{DataType, StructLayout},
+    };
+
+    #[repr(C)]
+    struct Inner {
+        x: u8,
+        y: f64,
+    }
+
+    #[repr(C)]
+    struct Outer {
+        a: u8,
+        b: i64,
+        c: [i32; 3],
+        d: Inner,
+        e: u16,
+    }
+
+    #[repr(C)]
+    union Number {
+        _i: i32,
+        _f: f64,
+        _b: [u8; 12],
+    }
+
+    #[test]
+    fn test_layout() {
+        let inner_layout = StructLayout::new_struct(vec![
+            ("x".to_owned(), DataType::Scalar(types::I8)),
+            ("y".to_owned(), DataType::Scalar(types::F64)),
+        ]);
+
+        let outer_layout = StructLayout::new_struct(vec![
+            ("a".to_owned(), DataType::Scalar(types::I8)),
+            ("b".to_owned(), DataType::Scalar(types::I64)),
+            (
+                "c".to_owned(),
+                DataType::Array(Box::new(DataType::Scalar(types::I32)), 3),
+            ),
+            ("d".to_owned(), DataType::Struct(inner_layout)),
+            ("e".to_owned(), DataType::Scalar(types::I16)),
+        ]);
+
+        // compare with the layout of Rust `repr(C)`
+        assert_eq!(outer_layout.size as usize, std::mem::size_of::<Outer>());
+        assert_eq!(outer_layout.align as usize, std::mem::align_of::<Outer>());
+        assert_eq!(
+            outer_layout
+                .fields
+                .iter()
+                .map(|field| field.offset as usize)
+                .collect::<Vec<_>>(),
+            vec![
+                std::mem::offset_of!(Outer, a),
+                std::mem::offset_of!(Outer, b),
+                std::mem::offset_of!(Outer, c),
+                std::mem::offset_of!(Outer, d),
+                std::mem::offset_of!(Outer, e),
+            ]
+        );
+        assert_eq!(
+            outer_layout.get_scalar_field("d.y"),
+            Some((
+                (std::mem::offset_of!(Outer, d) + std::mem::offset_of!(Inner, y)) as u32,
+                types::F64
+            ))
+        );
+        assert_eq!(outer_layout.get_scalar_field("c"), None);
+        assert_eq!(outer_layout.get_scalar_field("z"), None);
+
+        let union_layout = StructLayout::new_union(vec![
+            ("i".to_owned(), DataType::Scalar(types::I32)),
+            ("f".to_owned(), DataType::Scalar(types::F64)),
+            (
+                "b".to_owned(),
+                DataType::Array(Box::new(DataType::Scalar(types::I8)), 12),
+            ),
+        ]);
+        assert_eq!(union_layout.size as usize, std::mem::size_of::<Number>());
+        assert_eq!(union_layout.align as usize, std::mem::align_of::<Number>());
+
+        // ```rust
+        // fn main(a: i64) -> i64 {
+        //     let outer: Outer;
+        //     outer.b = a;
+        //     outer.d.x = 3;
+        //     outer.b + outer.d.x
+        // }
+        // ```
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.params.push(AbiParam::new(types::I64));
+        func_main_sig.returns.push(AbiParam::new(types::I64));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Local, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let stack_slot = function_builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            outer_layout.size,
+            outer_layout.align.trailing_zeros() as u8,
+        ));
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        let value_ptr = function_builder
+            .ins()
+            .stack_addr(pointer_type, stack_slot, 0);
+        outer_layout.emit_store_field(&mut function_builder, value_ptr, "b", value_0);
+        let value_1 = function_builder.ins().iconst(types::I8, 3);
+        outer_layout.emit_store_field(&mut function_builder, value_ptr, "d.x", value_1);
+
+        let value_2 = outer_layout.emit_load_field(&mut function_builder, value_ptr, "b");
+        let value_3 = outer_layout.emit_load_field(&mut function_builder, value_ptr, "d.x");
+        let value_4 = function_builder.ins().uextend(types::I64, value_3);
+        let value_5 = function_builder.ins().iadd(value_2, value_4);
+        function_builder.ins().return_(&[value_5]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let func_main: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(func_main_ptr) };
+        assert_eq!(func_main(10), 13);
+    }
+}
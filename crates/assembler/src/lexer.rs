@@ -0,0 +1,403 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use crate::diagnostic::Diagnostic;
+
+// The lexer of the assembly text
+// ------------------------------
+//
+// The XiaoXuan native assembly text (`*.ancasm`) is a S-expression format, e.g.
+//
+// ```text
+// (module $app
+//     // line comment
+//     (function $main export (result i32)
+//         (code
+//             (add_i32 (imm_i32 11) (imm_i32 2))
+//         )
+//     )
+// )
+// ```
+//
+// the tokens:
+//
+// - `(` and `)`.
+// - identifier: the keywords, the instruction names and the type names,
+//   e.g. `module`, `add_i32`, `i32`, `define-macro`, it starts with a letter
+//   or `_`, and consists of letters, digits, `_`, `.` and `-`.
+// - name: the names of the modules, functions, data and locals, it starts
+//   with `$`, e.g. `$main`, `$std::io::print`, the `$` is not part of the name.
+// - number: the integer (decimal, hexadecimal `0x`, binary `0b`) and the floating
+//   point number, with an optional sign, and the `_` can be used as the separator,
+//   e.g. `-123`, `0xff_ff`, `3.14`, `1e-3`. The value is not parsed by the lexer
+//   since the type is unknown at this stage.
+// - string: e.g. `"hello\n"`, the escapes `\n`, `\r`, `\t`, `\0`, `\\`, `\"`, `\'`,
+//   `\xHH` (a byte) and `\u{HHHH}` (a Unicode character) are supported.
+// - comment: the line comment `// ...` and the block comment `/* ... */`
+//   (can be nested).
+
+/// The byte range of a token or a node in the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Get the span which covers both spans.
+    pub fn merge(&self, other: &Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// Move the span by the offset, e.g. from the local offsets of a file to
+    /// the offsets of the `SourceFiles`, or back with the negative offset.
+    pub fn shift(&self, offset: isize) -> Span {
+        Span::new(
+            self.start.saturating_add_signed(offset),
+            self.end.saturating_add_signed(offset),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    LeftParen,
+    RightParen,
+    Identifier(String),
+    Name(String),
+
+    /// The text of the number without the separators `_`.
+    Number(String),
+
+    /// The bytes of the (unescaped) string.
+    String(Vec<u8>),
+    Comment(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '-')
+}
+
+fn is_name_char(c: char) -> bool {
+    is_identifier_char(c) || c == ':'
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek_char(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn peek_char_at(&self, n: usize) -> Option<char> {
+        self.source[self.position..].chars().nth(n)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        while let Some(c) = self.peek_char() {
+            if !predicate(c) {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+        &self.source[start..self.position]
+    }
+
+    fn error(&self, message: &str, start: usize) -> Diagnostic {
+        Diagnostic::new(message, Span::new(start, self.position.max(start + 1)))
+    }
+
+    fn lex_line_comment(&mut self) -> TokenKind {
+        self.position += 2;
+        let text = self.take_while(|c| c != '\n');
+        TokenKind::Comment(text.to_owned())
+    }
+
+    fn lex_block_comment(&mut self, start: usize) -> Result<TokenKind, Diagnostic> {
+        self.position += 2;
+        let text_start = self.position;
+        let mut depth = 1;
+
+        loop {
+            if self.source[self.position..].starts_with("*/") {
+                depth -= 1;
+                if depth == 0 {
+                    let text = &self.source[text_start..self.position];
+                    self.position += 2;
+                    return Ok(TokenKind::Comment(text.to_owned()));
+                }
+                self.position += 2;
+            } else if self.source[self.position..].starts_with("/*") {
+                depth += 1;
+                self.position += 2;
+            } else if self.next_char().is_none() {
+                return Err(self.error("the block comment is not closed", start));
+            }
+        }
+    }
+
+    fn lex_number(&mut self) -> TokenKind {
+        let mut text = String::new();
+
+        if let Some(sign @ ('+' | '-')) = self.peek_char() {
+            self.position += 1;
+            if sign == '-' {
+                text.push('-');
+            }
+        }
+
+        let is_hex_or_binary = matches!(
+            (self.peek_char(), self.peek_char_at(1)),
+            (Some('0'), Some('x' | 'X' | 'b' | 'B'))
+        );
+
+        loop {
+            match self.peek_char() {
+                Some('_') => {
+                    self.position += 1;
+                }
+                // the exponent sign of the floating point number, e.g. `1e-3`
+                Some(c @ ('+' | '-'))
+                    if !is_hex_or_binary && matches!(text.chars().last(), Some('e' | 'E')) =>
+                {
+                    self.position += 1;
+                    text.push(c);
+                }
+                Some(c) if c.is_ascii_alphanumeric() || c == '.' => {
+                    self.position += 1;
+                    text.push(c);
+                }
+                _ => break,
+            }
+        }
+
+        TokenKind::Number(text)
+    }
+
+    fn lex_string(&mut self, start: usize) -> Result<TokenKind, Diagnostic> {
+        self.position += 1;
+        let mut bytes = vec![];
+
+        loop {
+            let escape_start = self.position;
+            match self.next_char() {
+                None => return Err(self.error("the string is not closed", start)),
+                Some('"') => break,
+                Some('\\') => match self.next_char() {
+                    Some('n') => bytes.push(b'\n'),
+                    Some('r') => bytes.push(b'\r'),
+                    Some('t') => bytes.push(b'\t'),
+                    Some('0') => bytes.push(0),
+                    Some('\\') => bytes.push(b'\\'),
+                    Some('"') => bytes.push(b'"'),
+                    Some('\'') => bytes.push(b'\''),
+                    Some('x') => {
+                        let hex = self.source.get(self.position..self.position + 2);
+                        let byte = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                        match byte {
+                            Some(byte) => {
+                                self.position += 2;
+                                bytes.push(byte);
+                            }
+                            None => {
+                                return Err(self.error(
+                                    "expect two hexadecimal digits after '\\x'",
+                                    escape_start,
+                                ))
+                            }
+                        }
+                    }
+                    Some('u') => {
+                        let opt_char = if self.next_char() == Some('{') {
+                            let hex = self.take_while(|c| c.is_ascii_hexdigit());
+                            let opt_code = u32::from_str_radix(hex, 16).ok();
+                            if self.next_char() == Some('}') {
+                                opt_code.and_then(char::from_u32)
+                            } else {
+                                None
+                            }
+                        } else {
+                            None
+                        };
+
+                        match opt_char {
+                            Some(c) => {
+                                let mut buf = [0; 4];
+                                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                            }
+                            None => {
+                                return Err(self.error(
+                                    "invalid Unicode escape, expect '\\u{HHHH}'",
+                                    escape_start,
+                                ))
+                            }
+                        }
+                    }
+                    _ => return Err(self.error("unknown escape character", escape_start)),
+                },
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+
+        Ok(TokenKind::String(bytes))
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, Diagnostic> {
+        self.take_while(|c| c.is_whitespace());
+
+        let start = self.position;
+        let Some(c) = self.peek_char() else {
+            return Ok(None);
+        };
+
+        let kind = match c {
+            '(' => {
+                self.position += 1;
+                TokenKind::LeftParen
+            }
+            ')' => {
+                self.position += 1;
+                TokenKind::RightParen
+            }
+            '/' if self.peek_char_at(1) == Some('/') => self.lex_line_comment(),
+            '/' if self.peek_char_at(1) == Some('*') => self.lex_block_comment(start)?,
+            '"' => self.lex_string(start)?,
+            '$' => {
+                self.position += 1;
+                let name = self.take_while(is_name_char);
+                if name.is_empty() {
+                    return Err(self.error("expect a name after '$'", start));
+                }
+                TokenKind::Name(name.to_owned())
+            }
+            '0'..='9' => self.lex_number(),
+            '+' | '-' if matches!(self.peek_char_at(1), Some('0'..='9')) => self.lex_number(),
+            c if c.is_alphabetic() || c == '_' => {
+                let identifier = self.take_while(is_identifier_char);
+                TokenKind::Identifier(identifier.to_owned())
+            }
+            _ => {
+                self.next_char();
+                return Err(self.error(&format!("unexpected character '{}'", c), start));
+            }
+        };
+
+        Ok(Some(Token {
+            kind,
+            span: Span::new(start, self.position),
+        }))
+    }
+}
+
+/// Split the source text into tokens, the comments are included.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Diagnostic> {
+    let mut lexer = Lexer {
+        source,
+        position: 0,
+    };
+
+    let mut tokens = vec![];
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::lexer::{tokenize, Span, TokenKind};
+
+    fn tokenize_kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize_kinds("(module $app::main) // comment\n/* block /* nested */ */"),
+            vec![
+                TokenKind::LeftParen,
+                TokenKind::Identifier("module".to_owned()),
+                TokenKind::Name("app::main".to_owned()),
+                TokenKind::RightParen,
+                TokenKind::Comment(" comment".to_owned()),
+                TokenKind::Comment(" block /* nested */ ".to_owned()),
+            ]
+        );
+
+        assert_eq!(
+            tokenize_kinds("123 -45 +6 0xff_ff 0b1010 3.14 -1e-3 2.5E+2 add_i32 when-target"),
+            vec![
+                TokenKind::Number("123".to_owned()),
+                TokenKind::Number("-45".to_owned()),
+                TokenKind::Number("6".to_owned()),
+                TokenKind::Number("0xffff".to_owned()),
+                TokenKind::Number("0b1010".to_owned()),
+                TokenKind::Number("3.14".to_owned()),
+                TokenKind::Number("-1e-3".to_owned()),
+                TokenKind::Number("2.5E+2".to_owned()),
+                TokenKind::Identifier("add_i32".to_owned()),
+                TokenKind::Identifier("when-target".to_owned()),
+            ]
+        );
+
+        assert_eq!(
+            tokenize_kinds(r#""a\n\t\0\\\"\x41\u{6587}文""#),
+            vec![TokenKind::String(
+                b"a\n\t\0\\\"A\xe6\x96\x87\xe6\x96\x87".to_vec()
+            )]
+        );
+
+        // spans
+        let tokens = tokenize("(imm_i32  10)").unwrap();
+        assert_eq!(
+            tokens.iter().map(|token| token.span).collect::<Vec<_>>(),
+            vec![
+                Span::new(0, 1),
+                Span::new(1, 8),
+                Span::new(10, 12),
+                Span::new(12, 13)
+            ]
+        );
+
+        // errors
+        assert_eq!(tokenize("\"abc").unwrap_err().span, Span::new(0, 4));
+        assert_eq!(tokenize("(a /* b").unwrap_err().span, Span::new(3, 7));
+        assert_eq!(tokenize("(a #)").unwrap_err().span, Span::new(3, 4));
+        assert_eq!(tokenize("\"\\q\"").unwrap_err().span, Span::new(1, 3));
+        assert_eq!(tokenize("$ ").unwrap_err().span, Span::new(0, 1));
+    }
+}
@@ -4,9 +4,96 @@
 // the Mozilla Public License version 2.0 and additional exceptions,
 // more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
 
-mod code_generator;
+// the functions of the generator return `cranelift_module::ModuleError` as
+// the methods of `cranelift_module::Module` do, so the errors of Cranelift
+// are passed on by `?`. it is large (136 bytes), but boxing it would change
+// the error type of the public API for the failure paths only.
+#![allow(clippy::result_large_err)]
+
+pub mod address_significance;
+pub mod alignment;
+pub mod allocator;
+pub mod ast;
+pub mod branch_weight;
+pub mod code_generator;
+pub mod compilation_cache;
+pub mod conditional;
+pub mod constant;
+pub mod coverage;
+pub mod cycle_counter;
+pub mod data_bytes;
+pub mod dead_code;
+pub mod debug_info;
+pub mod deduplication;
+pub mod diagnostic;
+pub mod disassembly;
+pub mod elf_note;
+pub mod entry_point;
+pub mod envcall;
+pub mod exception;
+pub mod formatter;
+pub mod function_attribute;
+pub mod function_order;
+pub mod function_pass;
+pub mod function_table;
+pub mod fuzzing;
+#[cfg(feature = "jit")]
+pub mod host_function;
+pub mod import_check;
+pub mod inline_asm;
+pub mod inline_clif;
+pub mod inliner;
+pub mod instrumentation;
+pub mod intermediate;
+pub mod layout;
+pub mod lexer;
+#[cfg(feature = "linker")]
+pub mod linker;
+pub mod lowering;
+pub mod machine_code;
+pub mod macro_expander;
+pub mod mangling;
+pub mod merge;
+pub mod module_manifest;
+pub mod multiversion;
+pub mod null_check;
+pub mod object_dump;
+pub mod object_post_processing;
+pub mod parallel;
+pub mod parser;
+pub mod patchable_entry;
+pub mod plugin;
+pub mod position_independence;
+pub mod process_exit;
+pub mod producer;
+pub mod profiling;
+pub mod program_args;
+pub mod project;
+#[cfg(any(test, feature = "test-support"))]
+pub mod property_testing;
+pub mod resolver;
+pub mod safepoint;
+pub mod safety_check;
+pub mod sandbox;
+pub mod size_budget;
+pub mod snapshot;
+pub mod source_location;
+pub mod stack_map;
+pub mod struct_type;
+pub mod symbol_index;
+pub mod symbol_listing;
+pub mod tagged_union;
+pub mod test_harness;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod unwind_info;
+pub mod validation;
+pub mod visibility;
+pub mod visitor;
+pub mod vm_bridge;
+pub mod zero_data;
 
 // https://doc.rust-lang.org/reference/conditional-compilation.html#debug_assertions
 // https://doc.rust-lang.org/reference/conditional-compilation.html#test
-#[cfg(debug_assertions)]
-mod utils;
\ No newline at end of file
+#[cfg(test)]
+mod utils;
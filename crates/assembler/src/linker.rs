@@ -0,0 +1,909 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{fmt::Display, process::Command};
+
+// The linker
+// ----------
+//
+// Link the object files as an executable file with binutils 'ld', the arguments
+// are the same as GCC (check the result of command `$ gcc -v -o anna.elf anna.o`):
+//
+// ```sh
+// ld \
+//     --dynamic-linker /lib64/ld-linux-x86-64.so.2 \
+//     -pie \
+//     -o anna.elf \
+//     /usr/lib/Scrt1.o \
+//     /usr/lib/crti.o \
+//     -L/lib/ \
+//     -L/usr/lib \
+//     anna.o \
+//     -lc \
+//     /usr/lib/crtn.o
+// ```
+//
+// the 'crtbeginS.o' and 'crtendS.o' of GCC are linked (before the user objects
+// and after the libraries respectively) if they are found, they define the
+// symbol `__dso_handle` which is required by `atexit()`.
+//
+// the profiling profile (i.e. `gcc -pg`) replaces the 'Scrt1.o' with 'gcrt1.o',
+// which calls the `__monstartup()` at startup and writes the profiling data
+// file 'gmon.out' at exit (by `atexit()`), the functions should be compiled
+// with the `mcount` calls, see `Generator::enable_profiling()`.
+//
+// the position-dependent modes (see `LinkerMode`) replace the 'Scrt1.o',
+// 'crtbeginS.o' and 'crtendS.o' with 'crt1.o', 'crtbegin.o' (or 'crtbeginT.o'
+// for the static executable) and 'crtend.o', the object files should be
+// generated by `Generator::new_static()` since they contain absolute
+// relocations, e.g. (the static executable, i.e. `gcc -static`):
+//
+// ```sh
+// ld \
+//     -static \
+//     -o anna.elf \
+//     /usr/lib/crt1.o \
+//     /usr/lib/crti.o \
+//     /usr/lib/gcc/x86_64-linux-gnu/12/crtbeginT.o \
+//     -L/usr/lib/gcc/x86_64-linux-gnu/12 \
+//     -L/lib/ \
+//     -L/usr/lib \
+//     anna.o \
+//     --start-group -lgcc -lgcc_eh -lc --end-group \
+//     /usr/lib/gcc/x86_64-linux-gnu/12/crtend.o \
+//     /usr/lib/crtn.o
+// ```
+//
+// the "partial link" (i.e. `ld -r`) combines multiple object files into one
+// relocatable object file, e.g. one object file per package, the output can be
+// linked as a normal object file later:
+//
+// `$ ld -r -o package.o module_0.o module_1.o`
+//
+// the symbols which are defined by one input object and imported by another are
+// resolved, and the `Local` symbols are still private to their input object,
+// see also `Generator::merge()` for merging before emission.
+//
+// the failure of the linker is reported as `LinkError`, which contains the
+// command line, the exit code and the stderr of the linker, and it can generate
+// the shell script for reproducing the link step. the invocations are also
+// logged through `tracing`, i.e. the command lines at the "debug" level, and
+// the failures at the "error" level. the command line can also be inspected
+// without running the linker, see `LinkerOptions::dry_run`.
+//
+// the exported symbols of a shared library can be interposed by default, so
+// the calls between them go through the PLT, they can be bound within the
+// library by `-Bsymbolic-functions` (see `LinkerOptions::symbolic`) or by the
+// protected visibility (see `Generator::enable_protected_exports()`).
+//
+// the identical functions can be folded into one by the identical code folding
+// (i.e. `--icf=safe`, see `LinkerOptions::icf`), it requires a linker which
+// supports it (e.g. 'ld.gold' or 'ld.lld', see `LinkerOptions::linker`), and the
+// functions should be placed in their own sections (see `ObjectOptions::function_sections`).
+// the "safe" folding keeps the functions whose addresses are taken (since the
+// program may compare the addresses), 'ld.gold' finds them by the relocations,
+// and 'ld.lld' reads the address-significance table, see
+// `Generator::enable_address_significance_table()`.
+//
+// the hot functions can be placed together by the ordering file (see
+// `LinkerOptions::ordering_file` and `function_order.rs`), 'ld.lld' reads the
+// symbol names and 'ld.gold' reads the section names.
+//
+// the freestanding executable (see `LinkerMode::Freestanding`) is linked without
+// the CRT files and the libc, e.g.
+//
+// `$ ld -static -e __anna_start -o anna.elf anna.o`
+//
+// the entry point (`_start` by default, or the symbol of `LinkerOptions::entry`)
+// is defined by the object files, see `Generator::define_start_function()`.
+//
+// see also the notes about the CRT files in `test_support.rs`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkerMode {
+    /// The position-independent executable (i.e. `gcc -pie`), it is the
+    /// default mode of most Linux distributions.
+    #[default]
+    Pie,
+
+    /// The position-dependent executable (i.e. `gcc -no-pie`), the program
+    /// is loaded at a fixed address, and the shared libraries are still
+    /// linked dynamically.
+    NoPie,
+
+    /// The static executable (i.e. `gcc -static`), all libraries are linked
+    /// statically, and there is no dynamic linker.
+    Static,
+
+    /// The shared library (i.e. `gcc -shared`), there is no start file
+    /// (e.g. 'Scrt1.o') and no program interpreter, the object files should
+    /// be generated by `Generator::new()` (i.e. the PIC).
+    Shared,
+
+    /// The static executable without the CRT files and the libc (i.e.
+    /// `gcc -static -nostdlib`), the object files should define the entry
+    /// point, see `Generator::define_start_function()` and `LinkerOptions::entry`.
+    Freestanding,
+}
+
+/// The binding of the references to the exported symbols within the shared
+/// library, see `LinkerOptions::symbolic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymbolicBinding {
+    /// The exported symbols can be interposed (i.e. preempted) by the
+    /// definitions of the executable or the former libraries, so the
+    /// references within the library go through the PLT and the GOT.
+    #[default]
+    Interposable,
+
+    /// The references to the exported functions are bound to the definitions
+    /// within the library (i.e. `-Bsymbolic-functions`), so the intra-library
+    /// calls avoid the PLT, and the functions are still exported.
+    Functions,
+
+    /// The references to all exported symbols (including the data) are bound
+    /// to the definitions within the library (i.e. `-Bsymbolic`).
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkerOptions {
+    pub mode: LinkerMode,
+
+    /// The folder of the CRT object files (e.g. 'Scrt1.o', 'crti.o').
+    pub crt_folder: String,
+
+    /// The folder of the CRT object files of GCC (e.g. 'crtbeginS.o'),
+    /// e.g. '/usr/lib/gcc/x86_64-linux-gnu/12'.
+    pub gcc_crt_folder: Option<String>,
+
+    /// The path of the dynamic linker (i.e. the program interpreter).
+    pub dynamic_linker: String,
+
+    /// The search paths of libraries, i.e. the `-L` arguments.
+    pub library_paths: Vec<String>,
+
+    /// The link names of libraries, i.e. the `-l` arguments,
+    /// the "libc" is always linked.
+    pub libraries: Vec<String>,
+
+    /// Link with the profiling startup file 'gcrt1.o', so the
+    /// executable generates 'gmon.out' for gprof.
+    pub profiling: bool,
+
+    /// Generate the GNU build-id note (`.note.gnu.build-id`), i.e. the `--build-id`
+    /// argument, the style is one of "sha1", "md5", "uuid" and "0x<hex string>".
+    pub build_id: Option<String>,
+
+    /// Do not run the linker, `link_executable()` only returns the command
+    /// line, so the build systems can inspect, cache or wrap the link step.
+    pub dry_run: bool,
+
+    /// The binding of the exported symbols within the shared library, it only
+    /// takes effect in the `LinkerMode::Shared` mode. See also
+    /// `Generator::enable_protected_exports()`.
+    pub symbolic: SymbolicBinding,
+
+    /// The program of the linker, e.g. "ld", "ld.gold" or "ld.lld".
+    pub linker: String,
+
+    /// Fold the identical functions whose addresses are not significant,
+    /// i.e. the `--icf=safe` argument, it is not supported by 'ld.bfd'.
+    pub icf: bool,
+
+    /// The entry point symbol (i.e. the `-e` argument), it defaults to
+    /// `_start`, which is defined by the start file (e.g. 'Scrt1.o') in the
+    /// modes other than `LinkerMode::Freestanding`.
+    pub entry: Option<String>,
+
+    /// The ordering file of the functions, i.e. the `--symbol-ordering-file`
+    /// argument of 'ld.lld' or the `--section-ordering-file` argument of
+    /// 'ld.gold', see `FunctionProfile::write_ordering_file()`.
+    pub ordering_file: Option<String>,
+}
+
+impl Default for LinkerOptions {
+    fn default() -> Self {
+        Self {
+            mode: LinkerMode::default(),
+            crt_folder: "/usr/lib".to_owned(),
+            gcc_crt_folder: find_gcc_crt_folder(),
+            dynamic_linker: "/lib64/ld-linux-x86-64.so.2".to_owned(),
+            library_paths: vec!["/lib/".to_owned(), "/usr/lib".to_owned()],
+            libraries: vec![],
+            profiling: false,
+            build_id: None,
+            dry_run: false,
+            symbolic: SymbolicBinding::default(),
+            linker: "ld".to_owned(),
+            icf: false,
+            entry: None,
+            ordering_file: None,
+        }
+    }
+}
+
+/// Find the folder of the CRT object files of GCC, i.e. '/usr/lib/gcc/<triple>/<version>',
+/// the latest version is selected if there are multiple versions.
+pub fn find_gcc_crt_folder() -> Option<String> {
+    let mut folders = std::fs::read_dir("/usr/lib/gcc")
+        .ok()?
+        .filter_map(|entry| std::fs::read_dir(entry.ok()?.path()).ok())
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.join("crtbeginS.o").exists())
+        .collect::<Vec<_>>();
+
+    // sort by the version numbers, e.g. "9" < "12"
+    folders.sort_by_key(|path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| {
+                name.split('.')
+                    .map(|number| number.parse::<u32>().unwrap_or(0))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    });
+
+    folders
+        .pop()
+        .and_then(|path| path.to_str().map(|path| path.to_owned()))
+}
+
+/// Get the arguments of 'ld' for linking the object files as an executable file
+/// (or a shared library, see `LinkerMode::Shared`).
+pub fn get_linker_args(
+    object_file_paths: &[&str],
+    output_file_path: &str,
+    options: &LinkerOptions,
+) -> Vec<String> {
+    let crt_folder = &options.crt_folder;
+    let (start_file, crtbegin_file, crtend_file) = match options.mode {
+        LinkerMode::Pie => (Some("Scrt1.o"), "crtbeginS.o", "crtendS.o"),
+        LinkerMode::NoPie => (Some("crt1.o"), "crtbegin.o", "crtend.o"),
+        LinkerMode::Static => (Some("crt1.o"), "crtbeginT.o", "crtend.o"),
+        LinkerMode::Shared => (None, "crtbeginS.o", "crtendS.o"),
+        LinkerMode::Freestanding => {
+            return get_freestanding_linker_args(object_file_paths, output_file_path, options);
+        }
+    };
+    let start_file = if options.profiling && start_file.is_some() {
+        Some("gcrt1.o")
+    } else {
+        start_file
+    };
+
+    let mut args = match options.mode {
+        LinkerMode::Pie => vec![
+            "--dynamic-linker".to_owned(),
+            options.dynamic_linker.clone(),
+            "-pie".to_owned(),
+        ],
+        LinkerMode::NoPie => vec![
+            "--dynamic-linker".to_owned(),
+            options.dynamic_linker.clone(),
+            "-no-pie".to_owned(),
+        ],
+        LinkerMode::Static => vec!["-static".to_owned()],
+        LinkerMode::Shared => vec!["-shared".to_owned()],
+        LinkerMode::Freestanding => unreachable!(),
+    };
+
+    args.extend(get_common_linker_args(output_file_path, options));
+    if let Some(start_file) = start_file {
+        args.push(format!("{crt_folder}/{start_file}"));
+    }
+    args.push(format!("{crt_folder}/crti.o"));
+
+    if let Some(gcc_crt_folder) = &options.gcc_crt_folder {
+        args.push(format!("{gcc_crt_folder}/{crtbegin_file}"));
+    }
+
+    // the static executable links the static library of GCC runtime 'libgcc.a',
+    // which is located in the folder of the CRT object files of GCC.
+    let static_gcc_crt_folder = match options.mode {
+        LinkerMode::Static => options.gcc_crt_folder.as_ref(),
+        _ => None,
+    };
+
+    if let Some(gcc_crt_folder) = static_gcc_crt_folder {
+        args.push(format!("-L{}", gcc_crt_folder));
+    }
+
+    for library_path in &options.library_paths {
+        args.push(format!("-L{}", library_path));
+    }
+
+    for object_file_path in object_file_paths {
+        args.push((*object_file_path).to_owned());
+    }
+
+    for library in &options.libraries {
+        args.push(format!("-l{}", library));
+    }
+
+    if static_gcc_crt_folder.is_some() {
+        // the libc and libgcc depend on each other
+        args.extend([
+            "--start-group".to_owned(),
+            "-lgcc".to_owned(),
+            "-lgcc_eh".to_owned(),
+            "-lc".to_owned(),
+            "--end-group".to_owned(),
+        ]);
+    } else {
+        args.push("-lc".to_owned());
+    }
+
+    if let Some(gcc_crt_folder) = &options.gcc_crt_folder {
+        args.push(format!("{gcc_crt_folder}/{crtend_file}"));
+    }
+
+    args.push(format!("{crt_folder}/crtn.o"));
+
+    args
+}
+
+/// Get the arguments of the options which are independent of the mode,
+/// and the output file.
+fn get_common_linker_args(output_file_path: &str, options: &LinkerOptions) -> Vec<String> {
+    let mut args = vec![];
+
+    if let Some(build_id) = &options.build_id {
+        args.push(format!("--build-id={}", build_id));
+    }
+
+    if options.mode == LinkerMode::Shared {
+        match options.symbolic {
+            SymbolicBinding::Interposable => {}
+            SymbolicBinding::Functions => args.push("-Bsymbolic-functions".to_owned()),
+            SymbolicBinding::All => args.push("-Bsymbolic".to_owned()),
+        }
+    }
+
+    if options.icf {
+        args.push("--icf=safe".to_owned());
+    }
+
+    if let Some(ordering_file) = &options.ordering_file {
+        let argument = if options.linker.ends_with("lld") {
+            "--symbol-ordering-file"
+        } else {
+            "--section-ordering-file"
+        };
+        args.push(format!("{}={}", argument, ordering_file));
+    }
+
+    if let Some(entry) = &options.entry {
+        args.extend(["-e".to_owned(), entry.clone()]);
+    }
+
+    args.extend(["-o".to_owned(), output_file_path.to_owned()]);
+    args
+}
+
+/// Get the arguments of the freestanding executable, i.e. only the user
+/// objects and libraries.
+fn get_freestanding_linker_args(
+    object_file_paths: &[&str],
+    output_file_path: &str,
+    options: &LinkerOptions,
+) -> Vec<String> {
+    let mut args = vec!["-static".to_owned()];
+    args.extend(get_common_linker_args(output_file_path, options));
+
+    for library_path in &options.library_paths {
+        args.push(format!("-L{}", library_path));
+    }
+
+    for object_file_path in object_file_paths {
+        args.push((*object_file_path).to_owned());
+    }
+
+    for library in &options.libraries {
+        args.push(format!("-l{}", library));
+    }
+
+    args
+}
+
+/// The failure of the linker.
+#[derive(Debug)]
+pub struct LinkError {
+    /// The program and the arguments, e.g. `["ld", "-pie", "-o", "anna.elf", ...]`.
+    pub command_line: Vec<String>,
+
+    /// It is `None` if the linker is terminated by a signal or is not started.
+    pub exit_code: Option<i32>,
+
+    /// The captured stderr of the linker.
+    pub stderr: String,
+
+    /// The error of starting the linker, e.g. the program is not found.
+    pub start_error: Option<std::io::Error>,
+}
+
+/// Quote the argument for the POSIX shell if it contains the special characters.
+fn quote_shell_arg(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./=+,:@%".contains(c));
+    if is_plain {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Join the program and the arguments as the command line which can be
+/// pasted into the shell.
+pub fn get_shell_command(command_line: &[String]) -> String {
+    command_line
+        .iter()
+        .map(|arg| quote_shell_arg(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl LinkError {
+    /// Get the command line which can be pasted into the shell.
+    pub fn get_shell_command(&self) -> String {
+        get_shell_command(&self.command_line)
+    }
+
+    /// Get the shell script for reproducing the link step, it should be run
+    /// in the same working folder.
+    pub fn get_repro_script(&self) -> String {
+        let current_dir = std::env::current_dir()
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        format!(
+            "#!/bin/sh\n# reproduce the link step\ncd {}\n{}\n",
+            quote_shell_arg(&current_dir),
+            self.get_shell_command()
+        )
+    }
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(error) = &self.start_error {
+            return write!(
+                f,
+                "failed to execute the linker \"{}\": {}",
+                self.command_line[0], error
+            );
+        }
+
+        match self.exit_code {
+            Some(exit_code) => write!(f, "the linker exits with code {}", exit_code)?,
+            None => write!(f, "the linker is terminated by a signal")?,
+        }
+        write!(f, ", command line: {}", self.get_shell_command())?;
+        if !self.stderr.is_empty() {
+            write!(f, "\n{}", self.stderr.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Run the linker (e.g. 'ld' or the cross linker) with the arguments, the
+/// stderr is captured for the error.
+pub fn run_linker(linker: &str, args: &[String]) -> Result<(), LinkError> {
+    let mut command_line = vec![linker.to_owned()];
+    command_line.extend_from_slice(args);
+    let mut error = LinkError {
+        command_line,
+        exit_code: None,
+        stderr: String::new(),
+        start_error: None,
+    };
+
+    tracing::debug!(command_line = %error.get_shell_command(), "invoke the linker");
+
+    let output = match Command::new(linker).args(args).output() {
+        Ok(output) => output,
+        Err(start_error) => {
+            error.start_error = Some(start_error);
+            tracing::error!("{}", error);
+            return Err(error);
+        }
+    };
+
+    error.stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if output.status.success() {
+        if !error.stderr.is_empty() {
+            // e.g. the warnings of the deprecated features
+            tracing::warn!(stderr = %error.stderr.trim_end(), "the linker reports warnings");
+        }
+        Ok(())
+    } else {
+        error.exit_code = output.status.code();
+        tracing::error!(
+            command_line = %error.get_shell_command(),
+            stderr = %error.stderr.trim_end(),
+            "the linker failed"
+        );
+        Err(error)
+    }
+}
+
+/// Link the object files as an executable file (or a shared library),
+/// returns the command line (i.e. the program and the arguments).
+///
+/// The linker is not run if `options.dry_run` is true.
+pub fn link_executable(
+    object_file_paths: &[&str],
+    output_file_path: &str,
+    options: &LinkerOptions,
+) -> Result<Vec<String>, LinkError> {
+    let args = get_linker_args(object_file_paths, output_file_path, options);
+    if !options.dry_run {
+        run_linker(&options.linker, &args)?;
+    }
+
+    let mut command_line = vec![options.linker.clone()];
+    command_line.extend(args);
+    Ok(command_line)
+}
+
+/// Get the arguments of 'ld' for combining the object files into one
+/// relocatable object file.
+pub fn get_relocatable_linker_args(
+    object_file_paths: &[&str],
+    output_file_path: &str,
+) -> Vec<String> {
+    let mut args = vec![
+        "-r".to_owned(),
+        "-o".to_owned(),
+        output_file_path.to_owned(),
+    ];
+
+    for object_file_path in object_file_paths {
+        args.push((*object_file_path).to_owned());
+    }
+
+    args
+}
+
+/// Combine the object files into one relocatable object file (the partial link).
+pub fn link_relocatable(
+    object_file_paths: &[&str],
+    output_file_path: &str,
+) -> Result<(), LinkError> {
+    run_linker(
+        "ld",
+        &get_relocatable_linker_args(object_file_paths, output_file_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use std::process::Command;
+
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{
+            get_linker_args, get_relocatable_linker_args, get_shell_command, link_executable,
+            link_relocatable, run_linker, LinkerMode, LinkerOptions, SymbolicBinding,
+        },
+    };
+
+    #[test]
+    fn test_linker_args() {
+        let options = LinkerOptions {
+            gcc_crt_folder: None,
+            libraries: vec!["test0".to_owned()],
+            ..LinkerOptions::default()
+        };
+
+        assert_eq!(
+            get_linker_args(&["anna.o"], "anna.elf", &options).join(" "),
+            "--dynamic-linker /lib64/ld-linux-x86-64.so.2 -pie -o anna.elf \
+            /usr/lib/Scrt1.o /usr/lib/crti.o -L/lib/ -L/usr/lib \
+            anna.o -ltest0 -lc /usr/lib/crtn.o"
+        );
+
+        let options = LinkerOptions {
+            gcc_crt_folder: Some("/usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1".to_owned()),
+            profiling: true,
+            ..LinkerOptions::default()
+        };
+
+        assert_eq!(
+            get_linker_args(&["anna.o"], "anna.elf", &options).join(" "),
+            "--dynamic-linker /lib64/ld-linux-x86-64.so.2 -pie -o anna.elf \
+            /usr/lib/gcrt1.o /usr/lib/crti.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtbeginS.o \
+            -L/lib/ -L/usr/lib anna.o -lc \
+            /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtendS.o /usr/lib/crtn.o"
+        );
+
+        let options = LinkerOptions {
+            mode: LinkerMode::NoPie,
+            gcc_crt_folder: Some("/usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1".to_owned()),
+            ..LinkerOptions::default()
+        };
+
+        assert_eq!(
+            get_linker_args(&["anna.o"], "anna.elf", &options).join(" "),
+            "--dynamic-linker /lib64/ld-linux-x86-64.so.2 -no-pie -o anna.elf \
+            /usr/lib/crt1.o /usr/lib/crti.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtbegin.o \
+            -L/lib/ -L/usr/lib anna.o -lc \
+            /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtend.o /usr/lib/crtn.o"
+        );
+
+        let options = LinkerOptions {
+            mode: LinkerMode::Static,
+            gcc_crt_folder: Some("/usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1".to_owned()),
+            ..LinkerOptions::default()
+        };
+
+        assert_eq!(
+            get_linker_args(&["anna.o"], "anna.elf", &options).join(" "),
+            "-static -o anna.elf \
+            /usr/lib/crt1.o /usr/lib/crti.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtbeginT.o \
+            -L/usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1 -L/lib/ -L/usr/lib anna.o \
+            --start-group -lgcc -lgcc_eh -lc --end-group \
+            /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtend.o /usr/lib/crtn.o"
+        );
+
+        let options = LinkerOptions {
+            mode: LinkerMode::Shared,
+            gcc_crt_folder: Some("/usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1".to_owned()),
+            ..LinkerOptions::default()
+        };
+
+        assert_eq!(
+            get_linker_args(&["anna.o"], "libanna.so", &options).join(" "),
+            "-shared -o libanna.so \
+            /usr/lib/crti.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtbeginS.o \
+            -L/lib/ -L/usr/lib anna.o -lc \
+            /usr/lib/gcc/x86_64-pc-linux-gnu/14.1.1/crtendS.o /usr/lib/crtn.o"
+        );
+
+        let options = LinkerOptions {
+            mode: LinkerMode::Shared,
+            gcc_crt_folder: None,
+            symbolic: SymbolicBinding::Functions,
+            ..LinkerOptions::default()
+        };
+
+        assert_eq!(
+            get_linker_args(&["anna.o"], "libanna.so", &options).join(" "),
+            "-shared -Bsymbolic-functions -o libanna.so \
+            /usr/lib/crti.o -L/lib/ -L/usr/lib anna.o -lc /usr/lib/crtn.o"
+        );
+
+        let options = LinkerOptions {
+            gcc_crt_folder: None,
+            icf: true,
+            ..LinkerOptions::default()
+        };
+
+        assert_eq!(
+            get_linker_args(&["anna.o"], "anna.elf", &options).join(" "),
+            "--dynamic-linker /lib64/ld-linux-x86-64.so.2 -pie --icf=safe -o anna.elf \
+            /usr/lib/Scrt1.o /usr/lib/crti.o -L/lib/ -L/usr/lib anna.o -lc /usr/lib/crtn.o"
+        );
+    }
+
+    #[test]
+    fn test_link_error() {
+        let error = link_relocatable(&["/nonexistent/it's.o"], "package.o").unwrap_err();
+        assert_eq!(
+            error.command_line,
+            vec!["ld", "-r", "-o", "package.o", "/nonexistent/it's.o"]
+        );
+        assert_eq!(error.exit_code, Some(1));
+        assert!(error.stderr.contains("it's.o"));
+        assert!(error.start_error.is_none());
+
+        assert_eq!(
+            error.get_shell_command(),
+            "ld -r -o package.o '/nonexistent/it'\\''s.o'"
+        );
+        assert!(error
+            .get_repro_script()
+            .ends_with("\nld -r -o package.o '/nonexistent/it'\\''s.o'\n"));
+        assert!(error
+            .to_string()
+            .starts_with("the linker exits with code 1, command line: ld -r "));
+
+        // the dry run returns the command line only
+        let command_line = link_executable(
+            &["/nonexistent/anna.o"],
+            "anna.elf",
+            &LinkerOptions {
+                gcc_crt_folder: None,
+                dry_run: true,
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            get_shell_command(&command_line),
+            "ld --dynamic-linker /lib64/ld-linux-x86-64.so.2 -pie -o anna.elf \
+            /usr/lib/Scrt1.o /usr/lib/crti.o -L/lib/ -L/usr/lib /nonexistent/anna.o -lc \
+            /usr/lib/crtn.o"
+        );
+
+        let error = run_linker("nonexistent-ld", &["-v".to_owned()]).unwrap_err();
+        assert_eq!(error.exit_code, None);
+        assert!(error
+            .to_string()
+            .starts_with("failed to execute the linker \"nonexistent-ld\": "));
+    }
+
+    /// Build an object file which contains the function `name`, it returns
+    /// `value` if `callee` is None, otherwise it returns `callee() + value`.
+    fn build_object(name: &str, linkage: Linkage, callee: Option<&str>, value: i64) -> Vec<u8> {
+        let mut generator = Generator::<ObjectModule>::new(name, None);
+
+        let mut func_sig = generator.module.make_signature();
+        func_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_id = generator
+            .module
+            .declare_function(name, linkage, &func_sig)
+            .unwrap();
+
+        let opt_callee_id = callee.map(|callee_name| {
+            generator
+                .module
+                .declare_function(callee_name, Linkage::Import, &func_sig)
+                .unwrap()
+        });
+
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), func_sig);
+        let opt_callee_ref = opt_callee_id
+            .map(|callee_id| generator.module.declare_func_in_func(callee_id, &mut func));
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = match opt_callee_ref {
+            Some(callee_ref) => {
+                let call_0 = function_builder.ins().call(callee_ref, &[]);
+                let value_1 = function_builder.inst_results(call_0)[0];
+                function_builder.ins().iadd_imm(value_1, value)
+            }
+            None => function_builder.ins().iconst(types::I32, value),
+        };
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_id, func).unwrap();
+        generator.finish().unwrap().emit().unwrap()
+    }
+
+    #[test]
+    fn test_link_relocatable() {
+        assert_eq!(
+            get_relocatable_linker_args(&["a.o", "b.o"], "package.o").join(" "),
+            "-r -o package.o a.o b.o"
+        );
+
+        // ```rust
+        // // module "number"
+        // pub fn get_number() -> i32 { 11 }
+        //
+        // // module "main"
+        // pub fn main() -> i32 { get_number() + 2 }
+        // ```
+        let folder =
+            std::env::temp_dir().join(format!("anc_test_link_relocatable_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let number_file_path = folder.join("number.o");
+        let main_file_path = folder.join("main.o");
+        let package_file_path = folder.join("package.o");
+        let exec_file_path = folder.join("anna.elf");
+
+        std::fs::write(
+            &number_file_path,
+            build_object("get_number", Linkage::Export, None, 11),
+        )
+        .unwrap();
+        std::fs::write(
+            &main_file_path,
+            build_object("main", Linkage::Export, Some("get_number"), 2),
+        )
+        .unwrap();
+
+        link_relocatable(
+            &[
+                number_file_path.to_str().unwrap(),
+                main_file_path.to_str().unwrap(),
+            ],
+            package_file_path.to_str().unwrap(),
+        )
+        .unwrap();
+
+        link_executable(
+            &[package_file_path.to_str().unwrap()],
+            exec_file_path.to_str().unwrap(),
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(13));
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn test_link_shared_library() {
+        let folder =
+            std::env::temp_dir().join(format!("anc_test_link_shared_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let number_file_path = folder.join("number.o");
+        let main_file_path = folder.join("main.o");
+        let library_file_path = folder.join("libnumber.so");
+        let exec_file_path = folder.join("anna.elf");
+
+        std::fs::write(
+            &number_file_path,
+            build_object("get_number", Linkage::Export, None, 11),
+        )
+        .unwrap();
+        std::fs::write(
+            &main_file_path,
+            build_object("main", Linkage::Export, Some("get_number"), 2),
+        )
+        .unwrap();
+
+        link_executable(
+            &[number_file_path.to_str().unwrap()],
+            library_file_path.to_str().unwrap(),
+            &LinkerOptions {
+                mode: LinkerMode::Shared,
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+
+        let mut options = LinkerOptions::default();
+        options
+            .library_paths
+            .push(folder.to_str().unwrap().to_owned());
+        options.libraries.push("number".to_owned());
+
+        link_executable(
+            &[main_file_path.to_str().unwrap()],
+            exec_file_path.to_str().unwrap(),
+            &options,
+        )
+        .unwrap();
+
+        let exit_code_opt = Command::new(&exec_file_path)
+            .env("LD_LIBRARY_PATH", &folder)
+            .status()
+            .unwrap()
+            .code();
+        assert_eq!(exit_code_opt, Some(13));
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
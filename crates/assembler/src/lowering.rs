@@ -0,0 +1,2696 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::HashMap;
+
+use cranelift_codegen::{
+    ir::{
+        condcodes::{FloatCC, IntCC},
+        immediates::Imm64,
+        types, AbiParam, ArgumentPurpose, Block, FuncRef, Function, GlobalValue, GlobalValueData,
+        InstBuilder, MemFlags, Signature, StackSlotData, StackSlotKind, TrapCode, Type,
+        UserFuncName, Value,
+    },
+    isa::CallConv,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+#[cfg(feature = "jit")]
+use cranelift_jit::JITModule;
+use cranelift_module::{DataId, FuncId, Linkage, Module};
+
+use crate::{
+    ast::{
+        self, DataKind, DataValue, FunctionNode, ImportNode, Instruction, InstructionKind,
+        LoadType, Opcode, ResultAbi, StoreType, ValueType,
+    },
+    branch_weight::{emit_brif_with_weight, BranchProfile, BranchWeight},
+    code_generator::{DataDefinition, Generator},
+    cycle_counter::{declare_cycle_counter_function, emit_read_cycle_counter},
+    diagnostic::Diagnostic,
+    inline_asm::{define_inline_asm_function, parse_register},
+    inline_clif::{parse_inline_clif, splice_inline_clif},
+    layout::{DataType, StructLayout},
+    lexer::Span,
+    process_exit::{
+        declare_abort_function, declare_exit_function, emit_noreturn_call, ExitMode,
+        TRAP_CODE_UNREACHABLE,
+    },
+    program_args::{emit_string_array_item, emit_string_array_length, emit_string_length},
+    sandbox::{declare_sandbox_memory, emit_sandbox_address, SandboxMode},
+};
+
+// The lowering
+// ------------
+//
+// Walk the AST of a module (see `parser.rs`) and generate the Cranelift IR
+// by the `Generator`, i.e. the "assembler":
+//
+// 1. declare the imported functions and data, the data and the functions of
+//    the module, so the functions can be called before they are defined.
+// 2. define the data.
+// 3. lower all functions, then define them, so nothing is defined if
+//    a function fails to lower.
+//
+// e.g.
+//
+// ```rust
+// let module = parse_module(source)?;
+// let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+// let assembled_module = assemble_module(&module, &mut generator)?;
+// let binary = generator.finish()?.emit()?;
+// ```
+//
+// the lowering of the control flow:
+//
+// - the parameters and the locals are the Cranelift `Variable`s, the locals
+//   are initialized with zero.
+// - the function body is placed in a "body block" after the entry block, so
+//   `recur_fn` can jump to the start of the function.
+// - `if` and `for` create a block with parameters for the results, `for` also
+//   creates a "header block" which is the target of `recur`.
+// - after the instructions which do not fall through (i.e. `break`, `recur`,
+//   `break_fn`, `recur_fn`, `panic`, `exit`, `abort` and the calls of the
+//   `noreturn` functions), the builder switches to a new block
+//   which is unreachable, so the following instructions can still be lowered
+//   (and checked), they are removed by Cranelift.
+//
+// the comparisons return `i32` (`0` or `1`), and the `panic` traps with the user
+// trap code, i.e. `(panic 100)` is `trap user100`.
+//
+// the multiple results of the functions which are declared with `struct_return`
+// or `out_pointers` (see `ResultAbi`) are packed by the callee, i.e. they are
+// stored to the hidden pointers before returning, and the callers within the
+// module allocate the stack slots for them and load the results after the call.
+
+/// The ids of the functions and data of the assembled module.
+#[derive(Debug, Default)]
+pub struct AssembledModule {
+    pub functions: Vec<(String, FuncId)>,
+    pub data: Vec<(String, DataId)>,
+}
+
+impl AssembledModule {
+    pub fn get_function_id(&self, name: &str) -> Option<FuncId> {
+        self.functions
+            .iter()
+            .find(|(function_name, _)| function_name == name)
+            .map(|(_, func_id)| *func_id)
+    }
+
+    pub fn get_data_id(&self, name: &str) -> Option<DataId> {
+        self.data
+            .iter()
+            .find(|(data_name, _)| data_name == name)
+            .map(|(_, data_id)| *data_id)
+    }
+}
+
+#[derive(Clone)]
+struct FunctionSymbol {
+    func_id: FuncId,
+    params: Vec<ValueType>,
+    results: Vec<ValueType>,
+    result_abi: ResultAbi,
+
+    /// The C variadic function, see `lower_variadic_call()`.
+    variadic: bool,
+
+    /// The call is followed by a `trap`, see `function_attribute.rs`.
+    noreturn: bool,
+}
+
+#[derive(Clone)]
+struct DataSymbol {
+    data_id: DataId,
+    writable: bool,
+    tls: bool,
+
+    /// The size of the data, `None` if the imported data has no size.
+    size: Option<u64>,
+}
+
+/// The functions and data which can be referred to by name, it is kept
+/// by the caller of `assemble_module_hotswap()` so the modules can be
+/// assembled incrementally.
+#[derive(Default, Clone)]
+pub struct SymbolTable {
+    functions: HashMap<String, FunctionSymbol>,
+    data: HashMap<String, DataSymbol>,
+}
+
+impl SymbolTable {
+    pub fn get_function_id(&self, name: &str) -> Option<FuncId> {
+        self.functions.get(name).map(|symbol| symbol.func_id)
+    }
+}
+
+/// Assemble the module, i.e. declare and define the functions and data of
+/// the module by the generator.
+pub fn assemble_module<T>(
+    module: &ast::Module,
+    generator: &mut Generator<T>,
+) -> Result<AssembledModule, Diagnostic>
+where
+    T: Module,
+{
+    let mut symbol_table = SymbolTable::default();
+    let (assembled_module, functions) = lower_module(module, generator, &mut symbol_table, &[])?;
+    define_functions(generator, module, functions)?;
+    Ok(assembled_module)
+}
+
+/// Assemble the module into the generator which contains the former modules,
+/// e.g. the inputs of the REPL. The module can refer to the functions and data
+/// of the former modules, and a function with the same name and signature as
+/// a former one replaces it, the finalized callers call the new definition.
+///
+/// The `symbol_table` is updated only if the module is assembled successfully,
+/// `module.finalize_definitions()` should be called after each module.
+#[cfg(feature = "jit")]
+pub fn assemble_module_hotswap(
+    module: &ast::Module,
+    generator: &mut Generator<JITModule>,
+    symbol_table: &mut SymbolTable,
+) -> Result<AssembledModule, Diagnostic> {
+    let former_functions = symbol_table
+        .functions
+        .iter()
+        .filter(|(_, symbol)| {
+            generator
+                .module
+                .declarations()
+                .get_function_decl(symbol.func_id)
+                .linkage
+                .is_definable()
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    let mut new_symbol_table = symbol_table.clone();
+    let (assembled_module, mut functions) =
+        lower_module(module, generator, &mut new_symbol_table, &former_functions)?;
+
+    // the functions and data are referred to by the GOT and PLT entries
+    // (i.e. not colocated), which is required by the hot-swap.
+    for (_, function) in &mut functions {
+        for ext_func in function.dfg.ext_funcs.values_mut() {
+            ext_func.colocated = false;
+        }
+        for global_value in function.global_values.values_mut() {
+            if let GlobalValueData::Symbol { colocated, .. } = global_value {
+                *colocated = false;
+            }
+        }
+    }
+
+    for node in &module.functions {
+        if former_functions.contains(&node.name) {
+            let func_id = new_symbol_table.functions[&node.name].func_id;
+            generator
+                .module
+                .prepare_for_function_redefine(func_id)
+                .map_err(|e| Diagnostic::new(&e.to_string(), node.span))?;
+        }
+    }
+
+    define_functions(generator, module, functions)?;
+    *symbol_table = new_symbol_table;
+    Ok(assembled_module)
+}
+
+/// Declare the imports, data and functions, define the data and lower the functions,
+/// the functions in `redefinable` (i.e. the names of the former functions) can be
+/// declared again with the same signature.
+fn lower_module<T: Module>(
+    module: &ast::Module,
+    generator: &mut Generator<T>,
+    symbol_table: &mut SymbolTable,
+    redefinable: &[String],
+) -> Result<(AssembledModule, Vec<(FuncId, Function)>), Diagnostic> {
+    if let Some(node) = module.module_imports.first() {
+        return Err(Diagnostic::new(
+            &format!("the imported module \"{}\" is not resolved", node.path),
+            node.span,
+        )
+        .with_note(
+            "the module imports should be merged by \"resolve_module()\" before assembling",
+        ));
+    }
+
+    let mut assembled_module = AssembledModule::default();
+
+    for import in &module.imports {
+        let span = import.span();
+        match import {
+            ImportNode::Function(node) => {
+                check_duplicate_function(symbol_table, &node.name, span)?;
+                if node.variadic && node.result_abi != ResultAbi::Multiple {
+                    return Err(Diagnostic::new(
+                        "the variadic function can not pack the results",
+                        span,
+                    ));
+                }
+                if node.attributes.noreturn && !node.results.is_empty() {
+                    return Err(Diagnostic::new(
+                        "the noreturn function can not have results",
+                        span,
+                    ));
+                }
+                check_result_abi(&generator.module, &node.results, node.result_abi, span)?;
+                let signature = make_abi_signature(
+                    &generator.module,
+                    &node.params,
+                    &node.results,
+                    node.result_abi,
+                );
+                let func_id = if node.colocated {
+                    generator.import_function_colocated(&node.symbol, &signature)
+                } else {
+                    generator
+                        .module
+                        .declare_function(&node.symbol, Linkage::Import, &signature)
+                }
+                .map_err(|e| Diagnostic::new(&e.to_string(), span))?;
+                generator.set_function_attributes(func_id, node.attributes);
+                symbol_table.functions.insert(
+                    node.name.clone(),
+                    FunctionSymbol {
+                        func_id,
+                        params: node.params.clone(),
+                        results: node.results.clone(),
+                        result_abi: node.result_abi,
+                        variadic: node.variadic,
+                        noreturn: node.attributes.noreturn,
+                    },
+                );
+            }
+            ImportNode::Data(node) => {
+                check_duplicate_data(symbol_table, &node.name, span)?;
+                let data_id = generator
+                    .import_data_with_layout(
+                        &node.symbol,
+                        true,
+                        node.tls,
+                        node.size.map(u64::from),
+                        node.align.map(u64::from),
+                    )
+                    .map_err(|e| Diagnostic::new(&e.to_string(), span))?;
+                symbol_table.data.insert(
+                    node.name.clone(),
+                    DataSymbol {
+                        data_id,
+                        writable: true,
+                        tls: node.tls,
+                        size: node.size.map(u64::from),
+                    },
+                );
+            }
+        }
+    }
+
+    let mut data_with_addresses = vec![];
+    for node in &module.data {
+        check_duplicate_data(symbol_table, &node.name, node.span)?;
+        let align = node.get_align() as u64;
+        let (data_definition, writable) = match &node.kind {
+            DataKind::ReadOnly(value) | DataKind::ReadWrite(value) => (
+                DataDefinition::Initialized {
+                    data: value.to_bytes(),
+                    align,
+                },
+                matches!(node.kind, DataKind::ReadWrite(_)),
+            ),
+            DataKind::Uninit { size, .. } => (
+                DataDefinition::Uninitialized {
+                    size: *size as usize,
+                    align,
+                },
+                true,
+            ),
+        };
+
+        let size = match &data_definition {
+            DataDefinition::Initialized { data, .. } => data.len(),
+            DataDefinition::Uninitialized { size, .. } => *size,
+        } as u64;
+
+        let has_addresses = match &node.kind {
+            DataKind::ReadOnly(value) | DataKind::ReadWrite(value) => {
+                !value.get_addresses().is_empty()
+            }
+            DataKind::Uninit { .. } => false,
+        };
+
+        let result = match (&node.section, data_definition) {
+            // the addresses may refer to the functions and data which are declared
+            // later, so the content is defined after all symbols are declared
+            (_, data_definition) if has_addresses => {
+                let linkage = if node.export {
+                    Linkage::Export
+                } else {
+                    Linkage::Local
+                };
+                generator
+                    .module
+                    .declare_data(&node.name, linkage, writable, false)
+                    .inspect(|data_id| data_with_addresses.push((node, *data_id, data_definition)))
+            }
+            (Some(section), data_definition) => generator.define_data_in_section(
+                &node.name,
+                data_definition,
+                node.export,
+                writable,
+                section,
+            ),
+            (None, DataDefinition::Initialized { data, align }) => generator
+                .define_initialized_data(&node.name, data, align, node.export, writable, false),
+            (None, DataDefinition::Uninitialized { size, align }) => {
+                generator.define_uninitialized_data(&node.name, size, align, node.export, false)
+            }
+        };
+
+        let data_id = result.map_err(|e| Diagnostic::new(&e.to_string(), node.span))?;
+        symbol_table.data.insert(
+            node.name.clone(),
+            DataSymbol {
+                data_id,
+                writable,
+                tls: false,
+                size: Some(size),
+            },
+        );
+        assembled_module.data.push((node.name.clone(), data_id));
+    }
+
+    for node in &module.functions {
+        let is_redefinition = redefinable.contains(&node.name)
+            && !assembled_module
+                .functions
+                .iter()
+                .any(|(name, _)| *name == node.name);
+        if !is_redefinition {
+            check_duplicate_function(symbol_table, &node.name, node.span)?;
+        }
+
+        let params = node
+            .params
+            .iter()
+            .map(|param| param.value_type)
+            .collect::<Vec<_>>();
+        check_result_abi(&generator.module, &node.results, node.result_abi, node.span)?;
+        let signature =
+            make_abi_signature(&generator.module, &params, &node.results, node.result_abi);
+        let linkage = if node.export {
+            Linkage::Export
+        } else {
+            Linkage::Local
+        };
+        let func_id = generator
+            .module
+            .declare_function(&node.name, linkage, &signature)
+            .map_err(|e| Diagnostic::new(&e.to_string(), node.span))?;
+        if let Some(align) = node.align {
+            generator
+                .set_function_alignment(func_id, align as u64)
+                .map_err(|e| Diagnostic::new(&e.to_string(), node.span))?;
+        }
+        if !node.target_clones.is_empty() {
+            generator.set_function_versions(func_id, node.target_clones.clone());
+        }
+        symbol_table.functions.insert(
+            node.name.clone(),
+            FunctionSymbol {
+                func_id,
+                params,
+                results: node.results.clone(),
+                result_abi: node.result_abi,
+                variadic: false,
+                noreturn: false,
+            },
+        );
+        assembled_module
+            .functions
+            .push((node.name.clone(), func_id));
+    }
+
+    for (node, data_id, data_definition) in data_with_addresses {
+        define_data_with_addresses(generator, symbol_table, node, data_id, data_definition)?;
+    }
+
+    // all functions are lowered before defining, so nothing is
+    // defined if there are errors
+    let functions = module
+        .functions
+        .iter()
+        .map(|node| {
+            let func_id = symbol_table.functions[&node.name].func_id;
+            lower_function(generator, symbol_table, node, func_id)
+                .map(|function| (func_id, function))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((assembled_module, functions))
+}
+
+/// Define the data which contains the addresses (i.e. `addr_data` and
+/// `addr_function`), the addresses are filled by the relocations, and the
+/// offsets of `addr_data` are the addends.
+fn define_data_with_addresses<T: Module>(
+    generator: &mut Generator<T>,
+    symbol_table: &SymbolTable,
+    node: &ast::DataNode,
+    data_id: DataId,
+    data_definition: DataDefinition,
+) -> Result<(), Diagnostic> {
+    let (DataKind::ReadOnly(value) | DataKind::ReadWrite(value)) = &node.kind else {
+        unreachable!()
+    };
+
+    if generator.module.isa().pointer_bytes() != 8 {
+        return Err(Diagnostic::new(
+            "the addresses in the data require the 64-bit target",
+            node.span,
+        ));
+    }
+
+    for (offset, item) in value.get_addresses() {
+        match item {
+            DataValue::DataAddress {
+                name,
+                offset: addend,
+            } => {
+                let data_symbol = symbol_table.data.get(name).ok_or_else(|| {
+                    Diagnostic::new(&format!("unknown data \"${}\"", name), node.span)
+                })?;
+                if data_symbol.tls {
+                    return Err(Diagnostic::new(
+                        &format!(
+                            "the address of the thread-local data \"${}\" can not be stored in the data",
+                            name
+                        ),
+                        node.span,
+                    ));
+                }
+                generator.write_data_address(offset, data_symbol.data_id, *addend as i64);
+            }
+            DataValue::FunctionAddress(name) => {
+                let function_symbol = symbol_table.functions.get(name).ok_or_else(|| {
+                    Diagnostic::new(&format!("unknown function \"${}\"", name), node.span)
+                })?;
+                generator.write_function_address(offset, function_symbol.func_id);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if let Some(section) = &node.section {
+        // the segment name is only used by Mach-O
+        generator.data_description.set_segment_section("", section);
+    }
+    generator
+        .define_data_content(data_id, data_definition)
+        .map_err(|e| Diagnostic::new(&e.to_string(), node.span))
+}
+
+fn define_functions<T: Module>(
+    generator: &mut Generator<T>,
+    module: &ast::Module,
+    functions: Vec<(FuncId, Function)>,
+) -> Result<(), Diagnostic> {
+    let mut functions = module.functions.iter().zip(functions).collect::<Vec<_>>();
+    generator.sort_by_function_profile(&mut functions, |(_, (func_id, _))| *func_id);
+
+    for (node, (func_id, function)) in functions {
+        generator
+            .define_function(func_id, function)
+            .map_err(|e| Diagnostic::new(&e.to_string(), node.span))?;
+    }
+    Ok(())
+}
+
+fn check_duplicate_function(
+    symbol_table: &SymbolTable,
+    name: &str,
+    span: Span,
+) -> Result<(), Diagnostic> {
+    if symbol_table.functions.contains_key(name) {
+        Err(Diagnostic::new(
+            &format!("the function \"${}\" is defined more than once", name),
+            span,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_duplicate_data(
+    symbol_table: &SymbolTable,
+    name: &str,
+    span: Span,
+) -> Result<(), Diagnostic> {
+    if symbol_table.data.contains_key(name) {
+        Err(Diagnostic::new(
+            &format!("the data \"${}\" is defined more than once", name),
+            span,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn make_signature<T: Module>(
+    module: &T,
+    params: &[ValueType],
+    results: &[ValueType],
+) -> cranelift_codegen::ir::Signature {
+    let mut signature = module.make_signature();
+    signature.params.extend(
+        params
+            .iter()
+            .map(|value_type| AbiParam::new(to_ir_type(*value_type))),
+    );
+    signature.returns.extend(
+        results
+            .iter()
+            .map(|value_type| AbiParam::new(to_ir_type(*value_type))),
+    );
+    signature
+}
+
+/// Make the signature of the function by the ABI of the results, i.e. the
+/// struct-return pointer is prepended to the params for `struct_return`, and
+/// the pointers of the results except the first one are appended for `out_pointers`.
+fn make_abi_signature<T: Module>(
+    module: &T,
+    params: &[ValueType],
+    results: &[ValueType],
+    result_abi: ResultAbi,
+) -> Signature {
+    let pointer_type = module.isa().pointer_type();
+    match result_abi {
+        ResultAbi::Multiple => make_signature(module, params, results),
+        ResultAbi::StructReturn => {
+            let mut signature = make_signature(module, params, &[]);
+            signature.params.insert(
+                0,
+                AbiParam::special(pointer_type, ArgumentPurpose::StructReturn),
+            );
+            signature
+        }
+        ResultAbi::OutPointers => {
+            let (first_results, other_results) = results.split_at(results.len().min(1));
+            let mut signature = make_signature(module, params, first_results);
+            signature
+                .params
+                .extend(other_results.iter().map(|_| AbiParam::new(pointer_type)));
+            signature
+        }
+    }
+}
+
+/// Get the layout of the struct of `struct_return`, the fields are named
+/// by the indices of the results, i.e. "0", "1", ...
+fn get_results_layout(results: &[ValueType]) -> StructLayout {
+    StructLayout::new_struct(
+        results
+            .iter()
+            .enumerate()
+            .map(|(index, value_type)| {
+                (index.to_string(), DataType::Scalar(to_ir_type(*value_type)))
+            })
+            .collect(),
+    )
+}
+
+/// Check whether the struct of `struct_return` is returned by the hidden pointer
+/// in C, the small structs (at most 16 bytes on x86_64, aarch64 and riscv64,
+/// and the homogeneous floating-point aggregates of up to 4 members on aarch64)
+/// are returned in the registers instead.
+fn check_result_abi<T: Module>(
+    module: &T,
+    results: &[ValueType],
+    result_abi: ResultAbi,
+    span: Span,
+) -> Result<(), Diagnostic> {
+    if result_abi != ResultAbi::StructReturn {
+        return Ok(());
+    }
+
+    let is_small_struct = get_results_layout(results).size <= 16;
+    let is_homogeneous_float_aggregate = module.isa().name() == "aarch64"
+        && results.len() <= 4
+        && matches!(results.first(), Some(ValueType::F32 | ValueType::F64))
+        && results
+            .iter()
+            .all(|value_type| Some(value_type) == results.first());
+
+    if is_small_struct || is_homogeneous_float_aggregate {
+        Err(Diagnostic::new(
+            &format!(
+                "the results {} are returned in the registers by the C calling convention",
+                format_types(results)
+            ),
+            span,
+        )
+        .with_note("use \"out_pointers\" to pass the results by the pointers"))
+    } else {
+        Ok(())
+    }
+}
+
+fn to_ir_type(value_type: ValueType) -> Type {
+    match value_type {
+        ValueType::I32 => types::I32,
+        ValueType::I64 => types::I64,
+        ValueType::F32 => types::F32,
+        ValueType::F64 => types::F64,
+    }
+}
+
+fn from_ir_type(ir_type: Type) -> ValueType {
+    match ir_type {
+        types::I32 => ValueType::I32,
+        types::I64 => ValueType::I64,
+        types::F32 => ValueType::F32,
+        types::F64 => ValueType::F64,
+        _ => unreachable!(),
+    }
+}
+
+fn format_types(value_types: &[ValueType]) -> String {
+    let names = value_types
+        .iter()
+        .map(|value_type| value_type.name())
+        .collect::<Vec<_>>();
+    format!("({})", names.join(", "))
+}
+
+fn lower_function<T: Module>(
+    generator: &mut Generator<T>,
+    symbol_table: &SymbolTable,
+    node: &FunctionNode,
+    func_id: FuncId,
+) -> Result<Function, Diagnostic> {
+    let signature = generator
+        .module
+        .declarations()
+        .get_function_decl(func_id)
+        .signature
+        .clone();
+    let mut function =
+        Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), signature);
+
+    let function_builder =
+        FunctionBuilder::new(&mut function, &mut generator.function_builder_context);
+    let pointer_type = generator.module.isa().pointer_type();
+
+    let mut lowerer = FunctionLowerer {
+        module: &mut generator.module,
+        symbol_table,
+        function_builder,
+        pointer_type,
+        results: node.results.clone(),
+        result_abi: node.result_abi,
+        result_pointers: vec![],
+        params: vec![],
+        body_block: Block::from_u32(0),
+        locals: vec![],
+        loops: vec![],
+        func_refs: HashMap::new(),
+        data_refs: HashMap::new(),
+        next_variable: 0,
+        exit_mode: generator.exit_mode,
+        sandbox_mode: generator.sandbox_mode,
+        asm_functions: vec![],
+        function_name: node.name.clone(),
+        branch_profile: generator.branch_profile.as_ref(),
+        next_branch_index: 0,
+    };
+
+    if let Err(diagnostic) = lowerer.lower_body(node) {
+        // the context is left dirty by the unfinished function, reset
+        // it so the generator can still be used (e.g. by the REPL)
+        drop(lowerer);
+        generator.function_builder_context = FunctionBuilderContext::new();
+        return Err(diagnostic);
+    }
+
+    lowerer.function_builder.seal_all_blocks();
+    lowerer.function_builder.finalize();
+    let asm_functions = std::mem::take(&mut lowerer.asm_functions);
+    generator.record_syscall_functions();
+    generator.record_cycle_counter_function();
+    generator.record_inline_asm_functions(&asm_functions);
+
+    Ok(function)
+}
+
+/// The local variable, i.e. the parameter and the local of the function
+/// and the parameter of `for`.
+struct Local {
+    name: String,
+    variable: Variable,
+    value_type: ValueType,
+}
+
+/// The innermost `for`.
+struct Loop {
+    header_block: Block,
+    exit_block: Block,
+    params: Vec<(Variable, ValueType)>,
+    results: Vec<ValueType>,
+}
+
+struct FunctionLowerer<'a, 'b, T: Module> {
+    module: &'a mut T,
+    symbol_table: &'a SymbolTable,
+    function_builder: FunctionBuilder<'b>,
+    pointer_type: Type,
+    results: Vec<ValueType>,
+
+    /// The ABI of the results, and the hidden pointers of the results, i.e. the
+    /// struct-return pointer or the out pointers, see `emit_return()`.
+    result_abi: ResultAbi,
+    result_pointers: Vec<Value>,
+    params: Vec<(Variable, ValueType)>,
+    body_block: Block,
+
+    // the locals in scope, the inner ones are at the end
+    locals: Vec<Local>,
+    loops: Vec<Loop>,
+    func_refs: HashMap<FuncId, FuncRef>,
+    data_refs: HashMap<DataId, GlobalValue>,
+    next_variable: u32,
+    exit_mode: ExitMode,
+    sandbox_mode: Option<SandboxMode>,
+
+    // the shims of the inline assembly which are called by the function
+    asm_functions: Vec<FuncId>,
+
+    // the `if` and `when` are numbered in the order of the source text
+    // to look up the branch profile, see `branch_weight.rs`
+    function_name: String,
+    branch_profile: Option<&'a BranchProfile>,
+    next_branch_index: u32,
+}
+
+/// The values of an instruction, `None` if the instruction does not fall through.
+type LoweredValues = Option<Vec<Value>>;
+
+impl<'a, 'b, T: Module> FunctionLowerer<'a, 'b, T> {
+    fn lower_body(&mut self, node: &FunctionNode) -> Result<(), Diagnostic> {
+        let entry_block = self.function_builder.create_block();
+        self.function_builder
+            .append_block_params_for_function_params(entry_block);
+        self.function_builder.switch_to_block(entry_block);
+
+        let mut block_params = self.function_builder.block_params(entry_block).to_vec();
+        self.result_pointers = match self.result_abi {
+            ResultAbi::Multiple => vec![],
+            ResultAbi::StructReturn => vec![block_params.remove(0)],
+            ResultAbi::OutPointers => block_params.split_off(node.params.len()),
+        };
+
+        for (param, value) in node.params.iter().zip(block_params) {
+            let variable = self.declare_local(&param.name, param.value_type, value);
+            self.params.push((variable, param.value_type));
+        }
+
+        for local in &node.locals {
+            let value = self.emit_zero(local.value_type);
+            self.declare_local(&local.name, local.value_type, value);
+        }
+
+        self.body_block = self.function_builder.create_block();
+        self.function_builder.ins().jump(self.body_block, &[]);
+        self.function_builder.switch_to_block(self.body_block);
+
+        let values = self.lower_sequence(&node.body)?;
+        let values = self.check_fall_through(values, &node.results, node.span)?;
+        self.emit_return(&values);
+        Ok(())
+    }
+
+    /// Return the results by the ABI of the function.
+    fn emit_return(&mut self, values: &[Value]) {
+        match self.result_abi {
+            ResultAbi::Multiple => {
+                self.function_builder.ins().return_(values);
+            }
+            ResultAbi::StructReturn => {
+                let layout = get_results_layout(&self.results);
+                for (index, value) in values.iter().enumerate() {
+                    layout.emit_store_field(
+                        &mut self.function_builder,
+                        self.result_pointers[0],
+                        &index.to_string(),
+                        *value,
+                    );
+                }
+                self.function_builder.ins().return_(&[]);
+            }
+            ResultAbi::OutPointers => {
+                let (first_values, other_values) = values.split_at(values.len().min(1));
+                for (pointer, value) in self.result_pointers.iter().zip(other_values) {
+                    self.function_builder
+                        .ins()
+                        .store(MemFlags::trusted(), *value, *pointer, 0);
+                }
+                self.function_builder.ins().return_(first_values);
+            }
+        }
+    }
+
+    /// Call the function by the ABI of its results, the stack slots are allocated
+    /// for the packed results, and the results are loaded after the call.
+    fn emit_call(&mut self, function_symbol: &FunctionSymbol, mut args: Vec<Value>) -> Vec<Value> {
+        let func_ref = self.get_func_ref(function_symbol.func_id);
+        let results = &function_symbol.results;
+
+        let layout = match function_symbol.result_abi {
+            ResultAbi::Multiple => {
+                let call = self.function_builder.ins().call(func_ref, &args);
+                return self.function_builder.inst_results(call).to_vec();
+            }
+            ResultAbi::StructReturn => get_results_layout(results),
+            ResultAbi::OutPointers => get_results_layout(&results[results.len().min(1)..]),
+        };
+
+        let stack_slot = self
+            .function_builder
+            .create_sized_stack_slot(StackSlotData::new(
+                StackSlotKind::ExplicitSlot,
+                layout.size,
+                layout.align.trailing_zeros() as u8,
+            ));
+        let pointer = self
+            .function_builder
+            .ins()
+            .stack_addr(self.pointer_type, stack_slot, 0);
+
+        let mut values = match function_symbol.result_abi {
+            ResultAbi::StructReturn => {
+                args.insert(0, pointer);
+                self.function_builder.ins().call(func_ref, &args);
+                vec![]
+            }
+            _ => {
+                for field in &layout.fields {
+                    let field_pointer = self
+                        .function_builder
+                        .ins()
+                        .iadd_imm(pointer, field.offset as i64);
+                    args.push(field_pointer);
+                }
+                let call = self.function_builder.ins().call(func_ref, &args);
+                self.function_builder.inst_results(call).to_vec()
+            }
+        };
+
+        for field in &layout.fields {
+            values.push(layout.emit_load_field(&mut self.function_builder, pointer, &field.name));
+        }
+        values
+    }
+
+    fn declare_local(&mut self, name: &str, value_type: ValueType, value: Value) -> Variable {
+        let variable = Variable::from_u32(self.next_variable);
+        self.next_variable += 1;
+
+        self.function_builder
+            .declare_var(variable, to_ir_type(value_type));
+        self.function_builder.def_var(variable, value);
+        self.locals.push(Local {
+            name: name.to_owned(),
+            variable,
+            value_type,
+        });
+        variable
+    }
+
+    fn get_local(&self, name: &str, span: Span) -> Result<(Variable, ValueType), Diagnostic> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+            .map(|local| (local.variable, local.value_type))
+            .ok_or_else(|| Diagnostic::new(&format!("unknown local variable \"${}\"", name), span))
+    }
+
+    fn get_function(&self, name: &str, span: Span) -> Result<&'a FunctionSymbol, Diagnostic> {
+        let symbol_table: &'a SymbolTable = self.symbol_table;
+        symbol_table
+            .functions
+            .get(name)
+            .ok_or_else(|| Diagnostic::new(&format!("unknown function \"${}\"", name), span))
+    }
+
+    fn get_data(&mut self, name: &str, span: Span) -> Result<(Value, bool), Diagnostic> {
+        let (global_value, data_symbol) = self.get_data_global_value(name, span)?;
+        let address = if data_symbol.tls {
+            self.function_builder
+                .ins()
+                .tls_value(self.pointer_type, global_value)
+        } else {
+            self.function_builder
+                .ins()
+                .symbol_value(self.pointer_type, global_value)
+        };
+        Ok((address, data_symbol.writable))
+    }
+
+    fn get_data_global_value(
+        &mut self,
+        name: &str,
+        span: Span,
+    ) -> Result<(GlobalValue, &'a DataSymbol), Diagnostic> {
+        let symbol_table: &'a SymbolTable = self.symbol_table;
+        let data_symbol = symbol_table
+            .data
+            .get(name)
+            .ok_or_else(|| Diagnostic::new(&format!("unknown data \"${}\"", name), span))?;
+
+        let global_value = *self
+            .data_refs
+            .entry(data_symbol.data_id)
+            .or_insert_with(|| {
+                self.module
+                    .declare_data_in_func(data_symbol.data_id, self.function_builder.func)
+            });
+        Ok((global_value, data_symbol))
+    }
+
+    /// Get the address of the data plus the offset, the offset is the addend
+    /// of the relocation, except for the thread-local data.
+    fn get_data_address(
+        &mut self,
+        name: &str,
+        offset: i32,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        let (global_value, data_symbol) = self.get_data_global_value(name, span)?;
+        if offset == 0 || data_symbol.tls {
+            let (address, _) = self.get_data(name, span)?;
+            return Ok(if offset == 0 {
+                address
+            } else {
+                self.function_builder.ins().iadd_imm(address, offset as i64)
+            });
+        }
+
+        let GlobalValueData::Symbol {
+            name: symbol_name,
+            colocated,
+            tls,
+            ..
+        } = self.function_builder.func.global_values[global_value].clone()
+        else {
+            unreachable!()
+        };
+        let global_value = self
+            .function_builder
+            .create_global_value(GlobalValueData::Symbol {
+                name: symbol_name,
+                offset: Imm64::new(offset as i64),
+                colocated,
+                tls,
+            });
+        Ok(self
+            .function_builder
+            .ins()
+            .symbol_value(self.pointer_type, global_value))
+    }
+
+    fn get_func_ref(&mut self, func_id: FuncId) -> FuncRef {
+        *self.func_refs.entry(func_id).or_insert_with(|| {
+            self.module
+                .declare_func_in_func(func_id, self.function_builder.func)
+        })
+    }
+
+    fn emit_zero(&mut self, value_type: ValueType) -> Value {
+        let ins = self.function_builder.ins();
+        match value_type {
+            ValueType::I32 => ins.iconst(types::I32, 0),
+            ValueType::I64 => ins.iconst(types::I64, 0),
+            ValueType::F32 => ins.f32const(0.0),
+            ValueType::F64 => ins.f64const(0.0),
+        }
+    }
+
+    /// Translate the address of `memory_load_*` and `memory_store_*` into
+    /// the native address in the sandbox mode, see `sandbox.rs`.
+    fn emit_memory_address(
+        &mut self,
+        address: Value,
+        offset: i32,
+        access_size: u32,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        let Some(sandbox_mode) = self.sandbox_mode else {
+            return Ok(address);
+        };
+
+        if offset < 0 {
+            return Err(Diagnostic::new(
+                "the offset of the memory access is negative in the sandbox",
+                span,
+            ));
+        }
+        if sandbox_mode == SandboxMode::Guarded && self.pointer_type != types::I64 {
+            return Err(Diagnostic::new(
+                "the guarded sandbox requires the 64-bit target",
+                span,
+            ));
+        }
+
+        let data_id = declare_sandbox_memory(self.module)
+            .map_err(|e| Diagnostic::new(&e.to_string(), span))?;
+        let memory = *self.data_refs.entry(data_id).or_insert_with(|| {
+            self.module
+                .declare_data_in_func(data_id, self.function_builder.func)
+        });
+
+        Ok(emit_sandbox_address(
+            &mut self.function_builder,
+            sandbox_mode,
+            memory,
+            address,
+            offset as u32,
+            access_size,
+        ))
+    }
+
+    /// Check the static offset of the data access in the sandbox mode.
+    fn check_sandbox_data_access(
+        &self,
+        name: &str,
+        offset: i32,
+        access_size: u32,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        if self.sandbox_mode.is_none() {
+            return Ok(());
+        }
+
+        // the unknown data is reported by `get_data()`
+        let Some(data_symbol) = self.symbol_table.data.get(name) else {
+            return Ok(());
+        };
+
+        match data_symbol.size {
+            Some(size) if offset >= 0 && offset as u64 + access_size as u64 <= size => Ok(()),
+            Some(_) => Err(Diagnostic::new(
+                &format!(
+                    "the access of the data \"${}\" is out of bounds in the sandbox",
+                    name
+                ),
+                span,
+            )),
+            None => Err(Diagnostic::new(
+                &format!(
+                    "the size of the imported data \"${}\" is unknown in the sandbox",
+                    name
+                ),
+                span,
+            )
+            .with_note("specify the size of the imported data by \"(size n)\"")),
+        }
+    }
+
+    /// Reject the instruction which accesses the native memory in the sandbox mode.
+    fn check_sandbox_instruction(&self, name: &str, span: Span) -> Result<(), Diagnostic> {
+        if self.sandbox_mode.is_some() {
+            return Err(Diagnostic::new(
+                &format!("the instruction \"{}\" is not allowed in the sandbox", name),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Switch to a new (unreachable) block after the instruction which
+    /// does not fall through.
+    fn switch_to_unreachable_block(&mut self) -> LoweredValues {
+        let block = self.function_builder.create_block();
+        self.function_builder.switch_to_block(block);
+        None
+    }
+
+    /// Check the types of the values which fall through the end of a block, the zero
+    /// values are returned if the block does not fall through (i.e. the current block
+    /// is unreachable) so the jump or return can still be emitted.
+    fn check_fall_through(
+        &mut self,
+        values: LoweredValues,
+        expected: &[ValueType],
+        span: Span,
+    ) -> Result<Vec<Value>, Diagnostic> {
+        match values {
+            Some(values) => {
+                self.check_values(&values, expected, span)?;
+                Ok(values)
+            }
+            None => Ok(expected
+                .iter()
+                .map(|value_type| self.emit_zero(*value_type))
+                .collect()),
+        }
+    }
+
+    fn check_values(
+        &self,
+        values: &[Value],
+        expected: &[ValueType],
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let actual = values
+            .iter()
+            .map(|value| from_ir_type(self.function_builder.func.dfg.value_type(*value)))
+            .collect::<Vec<_>>();
+
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(Diagnostic::new(
+                &format!(
+                    "expect the values {}, found {}",
+                    format_types(expected),
+                    format_types(&actual)
+                ),
+                span,
+            ))
+        }
+    }
+
+    fn lower_sequence(
+        &mut self,
+        instructions: &[Instruction],
+    ) -> Result<LoweredValues, Diagnostic> {
+        let mut values = Some(vec![]);
+        for instruction in instructions {
+            values = self.lower_instruction(instruction)?;
+        }
+        Ok(values)
+    }
+
+    /// Lower the instruction which should produce exactly one value of the type.
+    fn lower_value(
+        &mut self,
+        instruction: &Instruction,
+        value_type: ValueType,
+    ) -> Result<Value, Diagnostic> {
+        match self.lower_instruction(instruction)? {
+            Some(values) => {
+                self.check_values(&values, &[value_type], instruction.span)?;
+                Ok(values[0])
+            }
+            None => Err(Diagnostic::new(
+                &format!("expect a value of {}", value_type.name()),
+                instruction.span,
+            )),
+        }
+    }
+
+    fn lower_values(
+        &mut self,
+        instructions: &[Instruction],
+        value_types: &[ValueType],
+        span: Span,
+    ) -> Result<Vec<Value>, Diagnostic> {
+        if instructions.len() != value_types.len() {
+            return Err(Diagnostic::new(
+                &format!(
+                    "expect {} value(s) {}, found {}",
+                    value_types.len(),
+                    format_types(value_types),
+                    instructions.len()
+                ),
+                span,
+            ));
+        }
+
+        instructions
+            .iter()
+            .zip(value_types)
+            .map(|(instruction, value_type)| self.lower_value(instruction, *value_type))
+            .collect()
+    }
+
+    fn lower_instruction(
+        &mut self,
+        instruction: &Instruction,
+    ) -> Result<LoweredValues, Diagnostic> {
+        let span = instruction.span;
+
+        let value = match &instruction.kind {
+            InstructionKind::Nop => return Ok(Some(vec![])),
+            InstructionKind::ImmI32(value) => self
+                .function_builder
+                .ins()
+                .iconst(types::I32, *value as i32 as i64),
+            InstructionKind::ImmI64(value) => self
+                .function_builder
+                .ins()
+                .iconst(types::I64, *value as i64),
+            InstructionKind::ImmF32(value) => self.function_builder.ins().f32const(*value),
+            InstructionKind::ImmF64(value) => self.function_builder.ins().f64const(*value),
+            InstructionKind::LocalLoad(name) => {
+                let (variable, _) = self.get_local(name, span)?;
+                self.function_builder.use_var(variable)
+            }
+            InstructionKind::LocalStore { name, value } => {
+                let (variable, value_type) = self.get_local(name, span)?;
+                let value = self.lower_value(value, value_type)?;
+                self.function_builder.def_var(variable, value);
+                return Ok(Some(vec![]));
+            }
+            InstructionKind::DataLoad {
+                load_type,
+                name,
+                offset,
+            } => {
+                self.check_sandbox_data_access(name, *offset, load_type.access_size(), span)?;
+                let (address, _) = self.get_data(name, span)?;
+                self.emit_load(*load_type, address, *offset)
+            }
+            InstructionKind::DataStore {
+                store_type,
+                name,
+                offset,
+                value,
+            } => {
+                self.check_sandbox_data_access(name, *offset, store_type.access_size(), span)?;
+                let value = self.lower_value(value, store_type.value_type())?;
+                let (address, writable) = self.get_data(name, span)?;
+                if !writable {
+                    return Err(Diagnostic::new(
+                        &format!("the data \"${}\" is read-only", name),
+                        span,
+                    )
+                    .with_note("declare the data by \"(read_write ...)\" to make it writable"));
+                }
+                self.emit_store(*store_type, address, *offset, value);
+                return Ok(Some(vec![]));
+            }
+            InstructionKind::MemoryLoad {
+                load_type,
+                address,
+                offset,
+            } => {
+                let address = self.lower_value(address, from_ir_type(self.pointer_type))?;
+                let address =
+                    self.emit_memory_address(address, *offset, load_type.access_size(), span)?;
+                self.emit_load(*load_type, address, *offset)
+            }
+            InstructionKind::MemoryStore {
+                store_type,
+                address,
+                offset,
+                value,
+            } => {
+                let address = self.lower_value(address, from_ir_type(self.pointer_type))?;
+                let value = self.lower_value(value, store_type.value_type())?;
+                let address =
+                    self.emit_memory_address(address, *offset, store_type.access_size(), span)?;
+                self.emit_store(*store_type, address, *offset, value);
+                return Ok(Some(vec![]));
+            }
+            InstructionKind::HostAddrFunction(name) => {
+                let func_id = self.get_function(name, span)?.func_id;
+                let func_ref = self.get_func_ref(func_id);
+                self.function_builder
+                    .ins()
+                    .func_addr(self.pointer_type, func_ref)
+            }
+            InstructionKind::HostAddrData { name, offset } => {
+                self.get_data_address(name, *offset, span)?
+            }
+            InstructionKind::Operation { opcode, operands } => {
+                if matches!(
+                    opcode,
+                    Opcode::StringArrayItem | Opcode::StringArrayLength | Opcode::StringLength
+                ) {
+                    self.check_sandbox_instruction(opcode.name(), span)?;
+                }
+                let args = self.lower_values(operands, opcode.param_types(), span)?;
+                self.emit_operation(*opcode, &args)
+            }
+            InstructionKind::Do(instructions) => return self.lower_sequence(instructions),
+            InstructionKind::If {
+                results,
+                weight,
+                condition,
+                consequent,
+                alternative,
+            } => {
+                let weight = self.get_branch_weight(*weight);
+                return self.lower_if(results, weight, condition, consequent, alternative);
+            }
+            InstructionKind::When {
+                weight,
+                condition,
+                body,
+            } => {
+                let weight = self.get_branch_weight(*weight);
+                return self.lower_when(weight, condition, body);
+            }
+            InstructionKind::For {
+                params,
+                results,
+                body,
+            } => return self.lower_for(params, results, body, span),
+            InstructionKind::Break(instructions) => {
+                let Some(current_loop) = self.loops.last() else {
+                    return Err(Diagnostic::new("\"break\" outside of \"for\"", span)
+                        .with_note("use \"break_fn\" to return from the function"));
+                };
+                let exit_block = current_loop.exit_block;
+                let results = current_loop.results.clone();
+                let values = self.lower_values(instructions, &results, span)?;
+                self.function_builder.ins().jump(exit_block, &values);
+                return Ok(self.switch_to_unreachable_block());
+            }
+            InstructionKind::Recur(instructions) => {
+                let Some(current_loop) = self.loops.last() else {
+                    return Err(Diagnostic::new("\"recur\" outside of \"for\"", span)
+                        .with_note("use \"recur_fn\" to jump to the start of the function"));
+                };
+                let header_block = current_loop.header_block;
+                let params = current_loop.params.clone();
+                self.emit_recur(instructions, &params, header_block, span)?;
+                return Ok(self.switch_to_unreachable_block());
+            }
+            InstructionKind::BreakFn(instructions) => {
+                let results = self.results.clone();
+                let values = self.lower_values(instructions, &results, span)?;
+                self.emit_return(&values);
+                return Ok(self.switch_to_unreachable_block());
+            }
+            InstructionKind::RecurFn(instructions) => {
+                let params = self.params.clone();
+                self.emit_recur(instructions, &params, self.body_block, span)?;
+                return Ok(self.switch_to_unreachable_block());
+            }
+            InstructionKind::Call { name, args } => {
+                let function_symbol = self.get_function(name, span)?;
+                if function_symbol.variadic {
+                    return self.lower_variadic_call(function_symbol, args, span);
+                }
+                let args = self.lower_values(args, &function_symbol.params, span)?;
+                let values = self.emit_call(function_symbol, args);
+                if function_symbol.noreturn {
+                    self.function_builder.ins().trap(TRAP_CODE_UNREACHABLE);
+                    return Ok(self.switch_to_unreachable_block());
+                }
+                return Ok(Some(values));
+            }
+            InstructionKind::DynCall {
+                params,
+                results,
+                callee,
+                args,
+            } => {
+                self.check_sandbox_instruction("dyncall", span)?;
+                let callee = self.lower_value(callee, from_ir_type(self.pointer_type))?;
+                let args = self.lower_values(args, params, span)?;
+                let signature = make_signature(self.module, params, results);
+                let sig_ref = self.function_builder.import_signature(signature);
+                let call = self
+                    .function_builder
+                    .ins()
+                    .call_indirect(sig_ref, callee, &args);
+                return Ok(Some(self.function_builder.inst_results(call).to_vec()));
+            }
+            InstructionKind::Clif {
+                params,
+                results,
+                text,
+                args,
+            } => {
+                self.check_sandbox_instruction("clif", span)?;
+                let args = self.lower_values(args, params, span)?;
+                let values = parse_inline_clif(text, params, results)
+                    .and_then(|function| {
+                        splice_inline_clif(&mut self.function_builder, &function, &args)
+                    })
+                    .map_err(|message| Diagnostic::new(&message, span))?;
+                self.check_values(&values, results, span)?;
+                return Ok(Some(values));
+            }
+            InstructionKind::Asm {
+                template,
+                inputs,
+                outputs,
+                clobbers,
+            } => {
+                self.check_sandbox_instruction("asm", span)?;
+                return self.lower_asm(template, inputs, outputs, clobbers, span);
+            }
+            InstructionKind::Panic(code) => {
+                self.function_builder
+                    .ins()
+                    .trap(TrapCode::unwrap_user(*code));
+                return Ok(self.switch_to_unreachable_block());
+            }
+            InstructionKind::Exit(code) => {
+                let code = self.lower_value(code, ValueType::I32)?;
+                let func_id = declare_exit_function(self.module, self.exit_mode)
+                    .map_err(|error| Diagnostic::new(&error.to_string(), span))?;
+                let func_ref = self.get_func_ref(func_id);
+                emit_noreturn_call(&mut self.function_builder, func_ref, &[code]);
+                return Ok(self.switch_to_unreachable_block());
+            }
+            InstructionKind::Abort => {
+                let func_id = declare_abort_function(self.module, self.exit_mode)
+                    .map_err(|error| Diagnostic::new(&error.to_string(), span))?;
+                let func_ref = self.get_func_ref(func_id);
+                emit_noreturn_call(&mut self.function_builder, func_ref, &[]);
+                return Ok(self.switch_to_unreachable_block());
+            }
+            InstructionKind::ReadCycleCounter => {
+                let func_id = declare_cycle_counter_function(self.module)
+                    .map_err(|error| Diagnostic::new(&error.to_string(), span))?;
+                let func_ref = self.get_func_ref(func_id);
+                emit_read_cycle_counter(&mut self.function_builder, func_ref)
+            }
+        };
+
+        Ok(Some(vec![value]))
+    }
+
+    /// Call the shim of the inline assembly with the inputs and the address
+    /// of a stack slot, and load the outputs from the slot, see `inline_asm.rs`.
+    fn lower_asm(
+        &mut self,
+        template: &[u8],
+        inputs: &[(String, Instruction)],
+        outputs: &[(String, ValueType)],
+        clobbers: &[String],
+        span: Span,
+    ) -> Result<LoweredValues, Diagnostic> {
+        let mut args = vec![];
+        for (register, instruction) in inputs {
+            let (_, value_type) =
+                parse_register(register).map_err(|message| Diagnostic::new(&message, span))?;
+            args.push(self.lower_value(instruction, value_type)?);
+        }
+
+        let input_names = inputs
+            .iter()
+            .map(|(register, _)| register.as_str())
+            .collect::<Vec<_>>();
+        let output_names = outputs
+            .iter()
+            .map(|(register, _)| register.as_str())
+            .collect::<Vec<_>>();
+        let clobber_names = clobbers.iter().map(String::as_str).collect::<Vec<_>>();
+        let func_id = define_inline_asm_function(
+            self.module,
+            template,
+            &input_names,
+            &output_names,
+            &clobber_names,
+        )
+        .map_err(|error| Diagnostic::new(&error.to_string(), span))?;
+        if !self.asm_functions.contains(&func_id) {
+            self.asm_functions.push(func_id);
+        }
+        let func_ref = self.get_func_ref(func_id);
+
+        if outputs.is_empty() {
+            self.function_builder.ins().call(func_ref, &args);
+            return Ok(Some(vec![]));
+        }
+
+        let stack_slot = self
+            .function_builder
+            .create_sized_stack_slot(StackSlotData::new(
+                StackSlotKind::ExplicitSlot,
+                outputs.len() as u32 * 8,
+                3,
+            ));
+        let pointer = self
+            .function_builder
+            .ins()
+            .stack_addr(self.pointer_type, stack_slot, 0);
+        args.push(pointer);
+        self.function_builder.ins().call(func_ref, &args);
+
+        let values = outputs
+            .iter()
+            .enumerate()
+            .map(|(index, (_, value_type))| {
+                self.function_builder.ins().stack_load(
+                    to_ir_type(*value_type),
+                    stack_slot,
+                    index as i32 * 8,
+                )
+            })
+            .collect();
+        Ok(Some(values))
+    }
+
+    /// Call the C variadic function, the arguments after the params are the
+    /// variadic arguments, and their types are the types of the values.
+    ///
+    /// The Cranelift signature has no variadic params, so the function is
+    /// called indirectly by the signature of the actual arguments, it is
+    /// compatible with the variadic calling convention of System V on
+    /// aarch64, and of x86_64 except that the floating-point arguments require
+    /// the number of the vector registers in `al`, which can not be set.
+    fn lower_variadic_call(
+        &mut self,
+        function_symbol: &FunctionSymbol,
+        args: &[Instruction],
+        span: Span,
+    ) -> Result<LoweredValues, Diagnostic> {
+        let fixed_count = function_symbol.params.len();
+        if args.len() < fixed_count {
+            return Err(Diagnostic::new(
+                &format!(
+                    "expect at least {} value(s) {}, found {}",
+                    fixed_count,
+                    format_types(&function_symbol.params),
+                    args.len()
+                ),
+                span,
+            ));
+        }
+
+        let mut signature = self
+            .module
+            .declarations()
+            .get_function_decl(function_symbol.func_id)
+            .signature
+            .clone();
+        if signature.call_conv == CallConv::AppleAarch64 {
+            return Err(Diagnostic::new(
+                "the variadic call is not supported by the calling convention of the target",
+                span,
+            ));
+        }
+
+        let mut values = self.lower_values(&args[..fixed_count], &function_symbol.params, span)?;
+        for arg in &args[fixed_count..] {
+            let value = match self.lower_instruction(arg)?.as_deref() {
+                Some([value]) => *value,
+                _ => {
+                    return Err(Diagnostic::new(
+                        "expect a value as the variadic argument",
+                        arg.span,
+                    ))
+                }
+            };
+            let value_type = self.function_builder.func.dfg.value_type(value);
+            if value_type.is_float() && self.module.isa().name() == "x64" {
+                return Err(Diagnostic::new(
+                    "the floating-point variadic argument is not supported on x86_64",
+                    arg.span,
+                ));
+            }
+            signature.params.push(AbiParam::new(value_type));
+            values.push(value);
+        }
+
+        let func_ref = self.get_func_ref(function_symbol.func_id);
+        let callee = self
+            .function_builder
+            .ins()
+            .func_addr(self.pointer_type, func_ref);
+        let sig_ref = self.function_builder.import_signature(signature);
+        let call = self
+            .function_builder
+            .ins()
+            .call_indirect(sig_ref, callee, &values);
+        Ok(Some(self.function_builder.inst_results(call).to_vec()))
+    }
+
+    /// Get the weight of the next `if` or `when`, the annotation in the source
+    /// takes precedence over the profile.
+    fn get_branch_weight(&mut self, weight: Option<BranchWeight>) -> Option<BranchWeight> {
+        let index = self.next_branch_index;
+        self.next_branch_index += 1;
+        weight.or_else(|| self.branch_profile?.get_weight(&self.function_name, index))
+    }
+
+    /// Branch to the blocks, the cold one (by the weight) is laid out at the
+    /// end of the function.
+    fn emit_brif(
+        &mut self,
+        condition: Value,
+        then_block: Block,
+        else_block: Block,
+        weight: Option<BranchWeight>,
+    ) {
+        match weight {
+            Some(weight) => emit_brif_with_weight(
+                &mut self.function_builder,
+                condition,
+                then_block,
+                &[],
+                else_block,
+                &[],
+                weight,
+            ),
+            None => {
+                self.function_builder
+                    .ins()
+                    .brif(condition, then_block, &[], else_block, &[]);
+            }
+        }
+    }
+
+    fn lower_if(
+        &mut self,
+        results: &[ValueType],
+        weight: Option<BranchWeight>,
+        condition: &Instruction,
+        consequent: &Instruction,
+        alternative: &Instruction,
+    ) -> Result<LoweredValues, Diagnostic> {
+        let condition = self.lower_value(condition, ValueType::I32)?;
+
+        let consequent_block = self.function_builder.create_block();
+        let alternative_block = self.function_builder.create_block();
+        let next_block = self.function_builder.create_block();
+        for value_type in results {
+            self.function_builder
+                .append_block_param(next_block, to_ir_type(*value_type));
+        }
+
+        self.emit_brif(condition, consequent_block, alternative_block, weight);
+
+        for (block, instruction) in [
+            (consequent_block, consequent),
+            (alternative_block, alternative),
+        ] {
+            self.function_builder.switch_to_block(block);
+            let values = self.lower_instruction(instruction)?;
+            let values = self.check_fall_through(values, results, instruction.span)?;
+            self.function_builder.ins().jump(next_block, &values);
+        }
+
+        self.function_builder.switch_to_block(next_block);
+        Ok(Some(
+            self.function_builder.block_params(next_block).to_vec(),
+        ))
+    }
+
+    fn lower_when(
+        &mut self,
+        weight: Option<BranchWeight>,
+        condition: &Instruction,
+        body: &[Instruction],
+    ) -> Result<LoweredValues, Diagnostic> {
+        let condition = self.lower_value(condition, ValueType::I32)?;
+        let body_block = self.function_builder.create_block();
+        let next_block = self.function_builder.create_block();
+
+        // the following instructions are not cold even if the body is
+        // always executed, so the cold path skips the body by its own block
+        if weight.is_some_and(|weight| weight.is_not_taken_cold()) {
+            let skip_block = self.function_builder.create_block();
+            self.emit_brif(condition, body_block, skip_block, weight);
+            self.function_builder.switch_to_block(skip_block);
+            self.function_builder.ins().jump(next_block, &[]);
+        } else {
+            self.emit_brif(condition, body_block, next_block, weight);
+        }
+
+        self.function_builder.switch_to_block(body_block);
+        self.lower_sequence(body)?;
+        self.function_builder.ins().jump(next_block, &[]);
+
+        self.function_builder.switch_to_block(next_block);
+        Ok(Some(vec![]))
+    }
+
+    fn lower_for(
+        &mut self,
+        params: &[(ast::LocalNode, Instruction)],
+        results: &[ValueType],
+        body: &[Instruction],
+        span: Span,
+    ) -> Result<LoweredValues, Diagnostic> {
+        // the initial values are evaluated before the parameters are in scope
+        let mut init_values = vec![];
+        for (param, init) in params {
+            init_values.push(self.lower_value(init, param.value_type)?);
+        }
+
+        let scope_start = self.locals.len();
+        let loop_params = params
+            .iter()
+            .zip(init_values)
+            .map(|((param, _), value)| {
+                let variable = self.declare_local(&param.name, param.value_type, value);
+                (variable, param.value_type)
+            })
+            .collect::<Vec<_>>();
+
+        let header_block = self.function_builder.create_block();
+        let exit_block = self.function_builder.create_block();
+        for value_type in results {
+            self.function_builder
+                .append_block_param(exit_block, to_ir_type(*value_type));
+        }
+
+        self.function_builder.ins().jump(header_block, &[]);
+        self.function_builder.switch_to_block(header_block);
+
+        self.loops.push(Loop {
+            header_block,
+            exit_block,
+            params: loop_params,
+            results: results.to_vec(),
+        });
+
+        let values = self.lower_sequence(body)?;
+        let values = self.check_fall_through(values, results, span)?;
+        self.function_builder.ins().jump(exit_block, &values);
+
+        self.loops.pop();
+        self.locals.truncate(scope_start);
+
+        self.function_builder.switch_to_block(exit_block);
+        Ok(Some(
+            self.function_builder.block_params(exit_block).to_vec(),
+        ))
+    }
+
+    /// Assign the new values to the parameters and jump to the start block.
+    fn emit_recur(
+        &mut self,
+        instructions: &[Instruction],
+        params: &[(Variable, ValueType)],
+        start_block: Block,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let value_types = params
+            .iter()
+            .map(|(_, value_type)| *value_type)
+            .collect::<Vec<_>>();
+        let values = self.lower_values(instructions, &value_types, span)?;
+
+        for ((variable, _), value) in params.iter().zip(values) {
+            self.function_builder.def_var(*variable, value);
+        }
+        self.function_builder.ins().jump(start_block, &[]);
+        Ok(())
+    }
+
+    fn emit_load(&mut self, load_type: LoadType, address: Value, offset: i32) -> Value {
+        let flags = MemFlags::new();
+        let ins = self.function_builder.ins();
+        match load_type {
+            LoadType::I8S => ins.sload8(types::I32, flags, address, offset),
+            LoadType::I8U => ins.uload8(types::I32, flags, address, offset),
+            LoadType::I16S => ins.sload16(types::I32, flags, address, offset),
+            LoadType::I16U => ins.uload16(types::I32, flags, address, offset),
+            LoadType::I32 => ins.load(types::I32, flags, address, offset),
+            LoadType::I32S => ins.sload32(flags, address, offset),
+            LoadType::I32U => ins.uload32(flags, address, offset),
+            LoadType::I64 => ins.load(types::I64, flags, address, offset),
+            LoadType::F32 => ins.load(types::F32, flags, address, offset),
+            LoadType::F64 => ins.load(types::F64, flags, address, offset),
+        }
+    }
+
+    fn emit_store(&mut self, store_type: StoreType, address: Value, offset: i32, value: Value) {
+        let flags = MemFlags::new();
+        let ins = self.function_builder.ins();
+        match store_type {
+            StoreType::I8 => ins.istore8(flags, value, address, offset),
+            StoreType::I16 => ins.istore16(flags, value, address, offset),
+            StoreType::I32 | StoreType::I64 | StoreType::F32 | StoreType::F64 => {
+                ins.store(flags, value, address, offset)
+            }
+        };
+    }
+
+    fn emit_operation(&mut self, opcode: Opcode, args: &[Value]) -> Value {
+        // the operations of the strings, which contain loops, see `program_args.rs`
+        match opcode {
+            Opcode::StringArrayItem => {
+                return emit_string_array_item(&mut self.function_builder, args[0], args[1])
+            }
+            Opcode::StringArrayLength => {
+                return emit_string_array_length(&mut self.function_builder, args[0])
+            }
+            Opcode::StringLength => return emit_string_length(&mut self.function_builder, args[0]),
+            _ => {}
+        }
+
+        let ins = self.function_builder.ins();
+
+        // the comparisons
+        let opt_condition = match opcode {
+            Opcode::EqI32 | Opcode::EqI64 => Some(ins.icmp(IntCC::Equal, args[0], args[1])),
+            Opcode::NeI32 | Opcode::NeI64 => Some(ins.icmp(IntCC::NotEqual, args[0], args[1])),
+            Opcode::LtI32S | Opcode::LtI64S => {
+                Some(ins.icmp(IntCC::SignedLessThan, args[0], args[1]))
+            }
+            Opcode::LtI32U | Opcode::LtI64U => {
+                Some(ins.icmp(IntCC::UnsignedLessThan, args[0], args[1]))
+            }
+            Opcode::GtI32S | Opcode::GtI64S => {
+                Some(ins.icmp(IntCC::SignedGreaterThan, args[0], args[1]))
+            }
+            Opcode::GtI32U | Opcode::GtI64U => {
+                Some(ins.icmp(IntCC::UnsignedGreaterThan, args[0], args[1]))
+            }
+            Opcode::LeI32S | Opcode::LeI64S => {
+                Some(ins.icmp(IntCC::SignedLessThanOrEqual, args[0], args[1]))
+            }
+            Opcode::LeI32U | Opcode::LeI64U => {
+                Some(ins.icmp(IntCC::UnsignedLessThanOrEqual, args[0], args[1]))
+            }
+            Opcode::GeI32S | Opcode::GeI64S => {
+                Some(ins.icmp(IntCC::SignedGreaterThanOrEqual, args[0], args[1]))
+            }
+            Opcode::GeI32U | Opcode::GeI64U => {
+                Some(ins.icmp(IntCC::UnsignedGreaterThanOrEqual, args[0], args[1]))
+            }
+            Opcode::EqzI32 | Opcode::EqzI64 => Some(ins.icmp_imm(IntCC::Equal, args[0], 0)),
+            Opcode::NezI32 | Opcode::NezI64 => Some(ins.icmp_imm(IntCC::NotEqual, args[0], 0)),
+            Opcode::EqF32 | Opcode::EqF64 => Some(ins.fcmp(FloatCC::Equal, args[0], args[1])),
+            Opcode::NeF32 | Opcode::NeF64 => Some(ins.fcmp(FloatCC::NotEqual, args[0], args[1])),
+            Opcode::LtF32 | Opcode::LtF64 => Some(ins.fcmp(FloatCC::LessThan, args[0], args[1])),
+            Opcode::GtF32 | Opcode::GtF64 => Some(ins.fcmp(FloatCC::GreaterThan, args[0], args[1])),
+            Opcode::LeF32 | Opcode::LeF64 => {
+                Some(ins.fcmp(FloatCC::LessThanOrEqual, args[0], args[1]))
+            }
+            Opcode::GeF32 | Opcode::GeF64 => {
+                Some(ins.fcmp(FloatCC::GreaterThanOrEqual, args[0], args[1]))
+            }
+            _ => None,
+        };
+
+        if let Some(condition) = opt_condition {
+            // the result of `icmp` and `fcmp` is `i8`
+            return self.function_builder.ins().uextend(types::I32, condition);
+        }
+
+        let ins = self.function_builder.ins();
+        match opcode {
+            // arithmetic
+            Opcode::AddI32 | Opcode::AddI64 => ins.iadd(args[0], args[1]),
+            Opcode::SubI32 | Opcode::SubI64 => ins.isub(args[0], args[1]),
+            Opcode::MulI32 | Opcode::MulI64 => ins.imul(args[0], args[1]),
+            Opcode::DivI32S | Opcode::DivI64S => ins.sdiv(args[0], args[1]),
+            Opcode::DivI32U | Opcode::DivI64U => ins.udiv(args[0], args[1]),
+            Opcode::RemI32S | Opcode::RemI64S => ins.srem(args[0], args[1]),
+            Opcode::RemI32U | Opcode::RemI64U => ins.urem(args[0], args[1]),
+            Opcode::NegI32 | Opcode::NegI64 => ins.ineg(args[0]),
+            Opcode::AbsI32 | Opcode::AbsI64 => ins.iabs(args[0]),
+
+            // bitwise
+            Opcode::AndI32 | Opcode::AndI64 => ins.band(args[0], args[1]),
+            Opcode::OrI32 | Opcode::OrI64 => ins.bor(args[0], args[1]),
+            Opcode::XorI32 | Opcode::XorI64 => ins.bxor(args[0], args[1]),
+            Opcode::NotI32 | Opcode::NotI64 => ins.bnot(args[0]),
+            Opcode::ShiftLeftI32 | Opcode::ShiftLeftI64 => ins.ishl(args[0], args[1]),
+            Opcode::ShiftRightI32S | Opcode::ShiftRightI64S => ins.sshr(args[0], args[1]),
+            Opcode::ShiftRightI32U | Opcode::ShiftRightI64U => ins.ushr(args[0], args[1]),
+            Opcode::RotateLeftI32 | Opcode::RotateLeftI64 => ins.rotl(args[0], args[1]),
+            Opcode::RotateRightI32 | Opcode::RotateRightI64 => ins.rotr(args[0], args[1]),
+            Opcode::CountLeadingZerosI32 | Opcode::CountLeadingZerosI64 => ins.clz(args[0]),
+            Opcode::CountLeadingOnesI32 | Opcode::CountLeadingOnesI64 => {
+                let value = ins.bnot(args[0]);
+                self.function_builder.ins().clz(value)
+            }
+            Opcode::CountTrailingZerosI32 | Opcode::CountTrailingZerosI64 => ins.ctz(args[0]),
+            Opcode::CountOnesI32 | Opcode::CountOnesI64 => ins.popcnt(args[0]),
+
+            // floating point
+            Opcode::AddF32 | Opcode::AddF64 => ins.fadd(args[0], args[1]),
+            Opcode::SubF32 | Opcode::SubF64 => ins.fsub(args[0], args[1]),
+            Opcode::MulF32 | Opcode::MulF64 => ins.fmul(args[0], args[1]),
+            Opcode::DivF32 | Opcode::DivF64 => ins.fdiv(args[0], args[1]),
+            Opcode::CopysignF32 | Opcode::CopysignF64 => ins.fcopysign(args[0], args[1]),
+            Opcode::MinF32 | Opcode::MinF64 => ins.fmin(args[0], args[1]),
+            Opcode::MaxF32 | Opcode::MaxF64 => ins.fmax(args[0], args[1]),
+            Opcode::SqrtF32 | Opcode::SqrtF64 => ins.sqrt(args[0]),
+            Opcode::NegF32 | Opcode::NegF64 => ins.fneg(args[0]),
+            Opcode::AbsF32 | Opcode::AbsF64 => ins.fabs(args[0]),
+            Opcode::CeilF32 | Opcode::CeilF64 => ins.ceil(args[0]),
+            Opcode::FloorF32 | Opcode::FloorF64 => ins.floor(args[0]),
+            Opcode::TruncF32 | Opcode::TruncF64 => ins.trunc(args[0]),
+            Opcode::RoundHalfToEvenF32 | Opcode::RoundHalfToEvenF64 => ins.nearest(args[0]),
+
+            // conversion
+            Opcode::TruncateI64ToI32 => ins.ireduce(types::I32, args[0]),
+            Opcode::ExtendI32SToI64 => ins.sextend(types::I64, args[0]),
+            Opcode::ExtendI32UToI64 => ins.uextend(types::I64, args[0]),
+            Opcode::PromoteF32ToF64 => ins.fpromote(types::F64, args[0]),
+            Opcode::DemoteF64ToF32 => ins.fdemote(types::F32, args[0]),
+            Opcode::ConvertF32ToI32S | Opcode::ConvertF64ToI32S => {
+                ins.fcvt_to_sint(types::I32, args[0])
+            }
+            Opcode::ConvertF32ToI32U | Opcode::ConvertF64ToI32U => {
+                ins.fcvt_to_uint(types::I32, args[0])
+            }
+            Opcode::ConvertF32ToI64S | Opcode::ConvertF64ToI64S => {
+                ins.fcvt_to_sint(types::I64, args[0])
+            }
+            Opcode::ConvertF32ToI64U | Opcode::ConvertF64ToI64U => {
+                ins.fcvt_to_uint(types::I64, args[0])
+            }
+            Opcode::ConvertI32SToF32 | Opcode::ConvertI64SToF32 => {
+                ins.fcvt_from_sint(types::F32, args[0])
+            }
+            Opcode::ConvertI32SToF64 | Opcode::ConvertI64SToF64 => {
+                ins.fcvt_from_sint(types::F64, args[0])
+            }
+            Opcode::ConvertI32UToF32 | Opcode::ConvertI64UToF32 => {
+                ins.fcvt_from_uint(types::F32, args[0])
+            }
+            Opcode::ConvertI32UToF64 | Opcode::ConvertI64UToF64 => {
+                ins.fcvt_from_uint(types::F64, args[0])
+            }
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_jit::JITModule;
+    use cranelift_object::{
+        object::{
+            read::elf::ElfFile64, Endianness, Object, ObjectSection, ObjectSymbol, RelocationKind,
+            RelocationTarget,
+        },
+        ObjectModule,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerOptions},
+        lowering::{assemble_module, assemble_module_hotswap, AssembledModule, SymbolTable},
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    fn assemble_jit(source: &str) -> (Generator<JITModule>, AssembledModule) {
+        extern "C" fn host_double(value: i32) -> i32 {
+            value * 2
+        }
+
+        let module = parse_module(source).unwrap();
+        let mut generator =
+            Generator::<JITModule>::new(vec![("host_double".to_owned(), host_double as *const u8)]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+        (generator, assembled_module)
+    }
+
+    fn get_function_ptr(
+        generator: &Generator<JITModule>,
+        assembled_module: &AssembledModule,
+        name: &str,
+    ) -> *const u8 {
+        generator
+            .module
+            .get_finalized_function(assembled_module.get_function_id(name).unwrap())
+    }
+
+    #[test]
+    fn test_lowering_control_flow() {
+        let source = r#"
+        (module $test
+            (import (function $host_double "host_double" (param i32) (result i32)))
+
+            // if, when and the operations
+            (function $max (param $a i32) (param $b i32) (result i32)
+                (code
+                    (if (result i32)
+                        (gt_i32_s (local_load $a) (local_load $b))
+                        (local_load $a)
+                        (local_load $b))))
+
+            (function $clamp_negative (param $a i32) (result i32)
+                (code
+                    (when (lt_i32_s (local_load $a) (imm_i32 0))
+                        (break_fn (imm_i32 0)))
+                    (local_load $a)))
+
+            // for, recur and break
+            (function $sum (param $n i32) (result i32)
+                (code
+                    (for (param $i i32 (imm_i32 1)) (param $acc i32 (imm_i32 0)) (result i32)
+                        (when (gt_i32_s (local_load $i) (local_load $n))
+                            (break (local_load $acc)))
+                        (recur
+                            (add_i32 (local_load $i) (imm_i32 1))
+                            (add_i32 (local_load $acc) (local_load $i))))))
+
+            // recur_fn, i.e. the tail call
+            (function $factorial (param $n i64) (param $acc i64) (result i64)
+                (code
+                    (if (result i64)
+                        (eqz_i64 (local_load $n))
+                        (local_load $acc)
+                        (recur_fn
+                            (sub_i64 (local_load $n) (imm_i64 1))
+                            (mul_i64 (local_load $acc) (local_load $n))))))
+
+            // call, dyncall, host_addr_function and the locals
+            (function $calls (param $a i32) (result i32) (local $t i32)
+                (code
+                    (local_store $t (call $max (local_load $a) (imm_i32 10)))
+                    (add_i32
+                        (call $host_double (local_load $t))
+                        (dyncall (param i32) (result i32)
+                            (host_addr_function $clamp_negative)
+                            (imm_i32 -5)))))
+
+            // the conversions and the floating point numbers
+            (function $average (param $a i32) (param $b i32) (result f64)
+                (code
+                    (div_f64
+                        (convert_i32_s_to_f64 (add_i32 (local_load $a) (local_load $b)))
+                        (imm_f64 2))))
+        )
+        "#;
+
+        let (generator, assembled_module) = assemble_jit(source);
+
+        let max: extern "C" fn(i32, i32) -> i32 =
+            unsafe { std::mem::transmute(get_function_ptr(&generator, &assembled_module, "max")) };
+        assert_eq!(max(3, 7), 7);
+        assert_eq!(max(-3, -7), -3);
+
+        let clamp_negative: extern "C" fn(i32) -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "clamp_negative",
+            ))
+        };
+        assert_eq!(clamp_negative(-9), 0);
+        assert_eq!(clamp_negative(9), 9);
+
+        let sum: extern "C" fn(i32) -> i32 =
+            unsafe { std::mem::transmute(get_function_ptr(&generator, &assembled_module, "sum")) };
+        assert_eq!(sum(100), 5050);
+        assert_eq!(sum(0), 0);
+
+        let factorial: extern "C" fn(i64, i64) -> i64 = unsafe {
+            std::mem::transmute(get_function_ptr(&generator, &assembled_module, "factorial"))
+        };
+        assert_eq!(factorial(10, 1), 3628800);
+
+        let calls: extern "C" fn(i32) -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(&generator, &assembled_module, "calls"))
+        };
+        assert_eq!(calls(3), 20);
+        assert_eq!(calls(15), 30);
+
+        let average: extern "C" fn(i32, i32) -> f64 = unsafe {
+            std::mem::transmute(get_function_ptr(&generator, &assembled_module, "average"))
+        };
+        assert_eq!(average(3, 4), 3.5);
+    }
+
+    #[test]
+    fn test_lowering_data_and_memory() {
+        let source = r#"
+        (module $test
+            (data $count (read_write i32 10))
+            (data $numbers (read_only bytes "\x01\x02\x03\xff"))
+            (data $buffer (uninit 16 8))
+            (data $table (align 16) (read_only i16 100 200 -1))
+            (data $name (read_only lstring "anna"))
+
+            (function $inc (result i32)
+                (code
+                    (data_store_i32 $count
+                        (add_i32 (data_load_i32 $count) (imm_i32 1)))
+                    (data_load_i32 $count)))
+
+            (function $get_number (param $index i64) (result i32)
+                (code
+                    (memory_load_i8_s
+                        (add_i64 (host_addr_data $numbers) (local_load $index)))))
+
+            (function $swap_halves (param $value i64) (result i64)
+                (code
+                    (memory_store_i64 (host_addr_data $buffer) (local_load $value))
+                    (memory_store_i32 (host_addr_data $buffer) 8
+                        (data_load_i32 $buffer))
+                    (data_load_i64 $buffer 4)))
+
+            (function $get_table_item (param $index i64) (result i32)
+                (code
+                    (memory_load_i16_s
+                        (add_i64 (host_addr_data $table) (mul_i64 (local_load $index) (imm_i64 2))))))
+
+            (function $get_name_length (result i32)
+                (code (data_load_i32 $name)))
+        )
+        "#;
+
+        let (generator, assembled_module) = assemble_jit(source);
+
+        let inc: extern "C" fn() -> i32 =
+            unsafe { std::mem::transmute(get_function_ptr(&generator, &assembled_module, "inc")) };
+        assert_eq!(inc(), 11);
+        assert_eq!(inc(), 12);
+
+        let get_number: extern "C" fn(i64) -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "get_number",
+            ))
+        };
+        assert_eq!(get_number(2), 3);
+        assert_eq!(get_number(3), -1);
+
+        let swap_halves: extern "C" fn(i64) -> i64 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "swap_halves",
+            ))
+        };
+        assert_eq!(swap_halves(0x1111_2222_3333_4444), 0x3333_4444_1111_2222);
+
+        let get_table_item: extern "C" fn(i64) -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "get_table_item",
+            ))
+        };
+        assert_eq!(get_table_item(1), 200);
+        assert_eq!(get_table_item(2), -1);
+
+        let get_name_length: extern "C" fn() -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "get_name_length",
+            ))
+        };
+        assert_eq!(get_name_length(), 4);
+    }
+
+    #[test]
+    fn test_lowering_address_addend() {
+        static CONFIG: [i64; 2] = [5, 9];
+
+        let source = r#"
+        (module $test
+            (import (data $config "config" (size 16)))
+            (data $third (read_only addr_data $table 8))
+            (data $table (read_only i32 10 20 30 40))
+            (data $limit (read_only addr_data $config 8))
+            (data $handlers (read_write addr_function $double $triple))
+
+            (function $double (param $x i32) (result i32)
+                (code (mul_i32 (local_load $x) (imm_i32 2))))
+            (function $triple (param $x i32) (result i32)
+                (code (mul_i32 (local_load $x) (imm_i32 3))))
+
+            (function $get_third (result i32)
+                (code (memory_load_i32 (data_load_i64 $third))))
+            (function $get_fourth (result i32)
+                (code (memory_load_i32 (host_addr_data $table 12))))
+            (function $get_limit (result i64)
+                (code
+                    (add_i64
+                        (memory_load_i64 (data_load_i64 $limit))
+                        (memory_load_i64 (host_addr_data $config 8)))))
+            (function $call_handler (param $index i64) (param $x i32) (result i32)
+                (code
+                    (dyncall (param i32) (result i32)
+                        (memory_load_i64
+                            (add_i64 (host_addr_data $handlers) (mul_i64 (local_load $index) (imm_i64 8))))
+                        (local_load $x))))
+
+            (function $main export (result i32)
+                (code
+                    (add_i32
+                        (add_i32 (call $get_third) (call $get_fourth))
+                        (call $call_handler (imm_i64 1) (imm_i32 2)))))
+        )
+        "#;
+
+        // JIT
+        let module = parse_module(source).unwrap();
+        let mut generator =
+            Generator::<JITModule>::new(vec![("config".to_owned(), CONFIG.as_ptr() as *const u8)]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let get_third: extern "C" fn() -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(&generator, &assembled_module, "get_third"))
+        };
+        let get_fourth: extern "C" fn() -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "get_fourth",
+            ))
+        };
+        let get_limit: extern "C" fn() -> i64 = unsafe {
+            std::mem::transmute(get_function_ptr(&generator, &assembled_module, "get_limit"))
+        };
+        let call_handler: extern "C" fn(i64, i32) -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "call_handler",
+            ))
+        };
+        assert_eq!(get_third(), 30);
+        assert_eq!(get_fourth(), 40);
+        assert_eq!(get_limit(), 18);
+        assert_eq!(call_handler(0, 7), 14);
+        assert_eq!(call_handler(1, 7), 21);
+
+        // object file, the offsets are the addends of the relocations
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        let elf_file = ElfFile64::<Endianness>::parse(module_binary.as_slice()).unwrap();
+        let mut relocations = elf_file
+            .sections()
+            .filter(|section| section.name().unwrap().starts_with(".data"))
+            .flat_map(|section| section.relocations().collect::<Vec<_>>())
+            .map(|(offset, relocation)| {
+                let RelocationTarget::Symbol(symbol_index) = relocation.target() else {
+                    unreachable!()
+                };
+                let symbol = elf_file.symbol_by_index(symbol_index).unwrap();
+                (
+                    symbol.name().unwrap().to_owned(),
+                    offset,
+                    relocation.addend(),
+                )
+            })
+            .collect::<Vec<_>>();
+        relocations.sort();
+        assert_eq!(
+            relocations,
+            vec![
+                ("config".to_owned(), 8, 8),
+                ("double".to_owned(), 0, 0),
+                ("table".to_owned(), 0, 8),
+                ("triple".to_owned(), 8, 0),
+            ]
+        );
+
+        let temp_folder = TempFolder::new("lowering_address_addend");
+        let object_file_path = temp_folder.file_path("test.o");
+        let exec_file_path = temp_folder.file_path("test.elf");
+        let module = parse_module(&source.replace(
+            r#"(import (data $config "config" (size 16)))"#,
+            "(data $config (read_only i64 5 9))",
+        ))
+        .unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(76));
+    }
+
+    #[test]
+    fn test_lowering_hotswap() {
+        let mut generator = Generator::<JITModule>::new_hotswap(vec![]);
+        let mut symbol_table = SymbolTable::default();
+
+        let mut assemble = |source: &str| {
+            let module = parse_module(source).unwrap();
+            let result = assemble_module_hotswap(&module, &mut generator, &mut symbol_table);
+            generator.module.finalize_definitions().unwrap();
+            result.map(|assembled_module| {
+                assembled_module
+                    .get_function_id("twice")
+                    .map(|func_id| generator.module.get_finalized_function(func_id))
+            })
+        };
+
+        let twice_ptr = assemble(
+            r#"(module $a
+                (function $get (result i32) (code (imm_i32 1)))
+                (function $twice (result i32) (code (mul_i32 (call $get) (imm_i32 2)))))"#,
+        )
+        .unwrap()
+        .unwrap();
+        let func_twice: extern "C" fn() -> i32 = unsafe { std::mem::transmute(twice_ptr) };
+        assert_eq!(func_twice(), 2);
+
+        // the finalized caller calls the new definition
+        assert!(assemble("(module $b (function $get (result i32) (code (imm_i32 5))))").is_ok());
+        assert_eq!(func_twice(), 10);
+
+        // the different signature
+        assert!(assemble("(module $c (function $get (result i64) (code (imm_i64 1))))").is_err());
+        assert_eq!(func_twice(), 10);
+
+        // the duplicate function in the same module
+        assert!(assemble(
+            r#"(module $d
+                (function $get (result i32) (code (imm_i32 7)))
+                (function $get (result i32) (code (imm_i32 8))))"#
+        )
+        .is_err());
+        assert_eq!(func_twice(), 10);
+    }
+
+    #[test]
+    fn test_lowering_extern_c() {
+        let source = r#"
+        (module $test
+            (extern-c "snprintf" variadic (params i64 i64 i64) (results i32))
+            (extern-c "labs" (params i64) (results i64) (library "c"))
+            (data $buffer (uninit 32 8))
+            (data $format (read_only cstring "%d-%ld-%s"))
+            (data $name (read_only cstring "anna"))
+
+            (function $format_numbers (result i32)
+                (code
+                    (call $snprintf
+                        (host_addr_data $buffer) (imm_i64 32) (host_addr_data $format)
+                        (imm_i32 -7) (call $labs (imm_i64 -42)) (host_addr_data $name))))
+
+            (function $get_buffer (result i64)
+                (code (host_addr_data $buffer)))
+        )
+        "#;
+
+        assert_eq!(parse_module(source).unwrap().get_libraries(), vec!["c"]);
+
+        let (generator, assembled_module) = assemble_jit(source);
+        let format_numbers: extern "C" fn() -> i32 = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "format_numbers",
+            ))
+        };
+        let get_buffer: extern "C" fn() -> *const std::ffi::c_char = unsafe {
+            std::mem::transmute(get_function_ptr(
+                &generator,
+                &assembled_module,
+                "get_buffer",
+            ))
+        };
+
+        assert_eq!(format_numbers(), 10);
+        let text = unsafe { std::ffi::CStr::from_ptr(get_buffer()) };
+        assert_eq!(text.to_str().unwrap(), "-7-42-anna");
+    }
+
+    #[test]
+    fn test_lowering_result_abi() {
+        #[repr(C)]
+        #[derive(Debug, PartialEq)]
+        struct Triple(i64, i64, i64);
+
+        extern "C" fn host_divmod(a: i32, b: i32, remainder: *mut i32) -> i32 {
+            unsafe { *remainder = a % b };
+            a / b
+        }
+
+        extern "C" fn host_triple(value: i64) -> Triple {
+            Triple(value, value * 2, value * 3)
+        }
+
+        let source = r#"
+        (module $test
+            (import (function $host_divmod out_pointers
+                (param i32) (param i32) (result i32) (result i32)))
+            (import (function $host_triple struct_return
+                (param i64) (result i64) (result i64) (result i64)))
+
+            (function $divmod export out_pointers
+                (param $a i32) (param $b i32) (result i32) (result i32)
+                (code (break_fn
+                    (div_i32_s (local_load $a) (local_load $b))
+                    (rem_i32_s (local_load $a) (local_load $b)))))
+
+            (function $divmod_by_host export out_pointers
+                (param $a i32) (param $b i32) (result i32) (result i32)
+                (code (call $host_divmod (local_load $a) (local_load $b))))
+
+            (function $triple export struct_return
+                (param $x i64) (result i64) (result i64) (result i64)
+                (code (break_fn
+                    (local_load $x)
+                    (add_i64 (local_load $x) (local_load $x))
+                    (mul_i64 (local_load $x) (imm_i64 3)))))
+
+            // the caller within the module receives the results as usual
+            (function $triple_by_call export struct_return
+                (param $x i64) (result i64) (result i64) (result i64)
+                (code (call $triple (local_load $x))))
+
+            (function $triple_by_host export struct_return
+                (param $x i64) (result i64) (result i64) (result i64)
+                (code (call $host_triple (local_load $x))))
+        )
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![
+            ("host_divmod".to_owned(), host_divmod as *const u8),
+            ("host_triple".to_owned(), host_triple as *const u8),
+        ]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        for name in ["divmod", "divmod_by_host"] {
+            let divmod: extern "C" fn(i32, i32, *mut i32) -> i32 = unsafe {
+                std::mem::transmute(get_function_ptr(&generator, &assembled_module, name))
+            };
+            let mut remainder = 0;
+            assert_eq!(divmod(23, 5, &mut remainder), 4);
+            assert_eq!(remainder, 3);
+        }
+
+        for name in ["triple", "triple_by_call", "triple_by_host"] {
+            let triple: extern "C" fn(i64) -> Triple = unsafe {
+                std::mem::transmute(get_function_ptr(&generator, &assembled_module, name))
+            };
+            assert_eq!(triple(7), Triple(7, 14, 21));
+        }
+    }
+
+    #[test]
+    fn test_lowering_errors() {
+        fn lowering_error(source: &str) -> (String, &str) {
+            let module = parse_module(source).unwrap();
+            let mut generator = Generator::<JITModule>::new(vec![]);
+            let error = assemble_module(&module, &mut generator).unwrap_err();
+            (error.message, &source[error.span.start..error.span.end])
+        }
+
+        assert_eq!(
+            lowering_error("(module $a (function $f (result i32) (code (imm_i64 1))))"),
+            (
+                "expect the values (i32), found (i64)".to_owned(),
+                "(function $f (result i32) (code (imm_i64 1)))"
+            )
+        );
+        assert_eq!(
+            lowering_error("(module $a (function $f (code (add_i32 (imm_i32 1) (imm_f32 2)))))"),
+            (
+                "expect the values (i32), found (f32)".to_owned(),
+                "(imm_f32 2)"
+            )
+        );
+        assert_eq!(
+            lowering_error("(module $a (function $f (code (local_load $x))))"),
+            (
+                "unknown local variable \"$x\"".to_owned(),
+                "(local_load $x)"
+            )
+        );
+        assert_eq!(
+            lowering_error("(module $a (function $f (code (call $g (imm_i32 1)))))"),
+            (
+                "unknown function \"$g\"".to_owned(),
+                "(call $g (imm_i32 1))"
+            )
+        );
+        assert_eq!(
+            lowering_error(
+                "(module $a (extern-c \"printf\" variadic (params i64) (results i32)) (function $f (code (call $printf))))"
+            ),
+            (
+                "expect at least 1 value(s) (i64), found 0".to_owned(),
+                "(call $printf)"
+            )
+        );
+        assert_eq!(
+            lowering_error("(module $a (function $f (code (break))))"),
+            ("\"break\" outside of \"for\"".to_owned(), "(break)")
+        );
+        assert_eq!(
+            lowering_error(
+                "(module $a (data $d (read_only i32 1)) (function $f (code (data_store_i32 $d (imm_i32 2)))))"
+            ),
+            (
+                "the data \"$d\" is read-only".to_owned(),
+                "(data_store_i32 $d (imm_i32 2))"
+            )
+        );
+        assert_eq!(
+            lowering_error(
+                "(module $a (function $f struct_return (result i32) (result i32) (code (break_fn (imm_i32 1) (imm_i32 2)))))"
+            ),
+            (
+                "the results (i32, i32) are returned in the registers by the C calling convention"
+                    .to_owned(),
+                "(function $f struct_return (result i32) (result i32) (code (break_fn (imm_i32 1) (imm_i32 2))))"
+            )
+        );
+        assert_eq!(
+            lowering_error("(module $a (function $f (code)) (function $f (code)))"),
+            (
+                "the function \"$f\" is defined more than once".to_owned(),
+                "(function $f (code))"
+            )
+        );
+    }
+
+    #[test]
+    fn test_lowering_object_file() {
+        let source = r#"
+        (module $hello
+            (import (function $puts "puts" (param i64) (result i32)))
+            (data $message (section ".rodata.message") (read_only cstring "Hello, assembler!"))
+
+            (function $main export (result i32)
+                (code
+                    (call $puts (host_addr_data $message))
+                    (imm_i32 7)))
+        )
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        // the data in the custom section
+        let elf_file = ElfFile64::<Endianness>::parse(module_binary.as_slice()).unwrap();
+        let section = elf_file.section_by_name(".rodata.message").unwrap();
+        assert_eq!(section.data().unwrap(), b"Hello, assembler!\0");
+
+        let folder = std::env::temp_dir();
+        let object_file_path = folder.join(format!("anc_test_lowering_{}.o", std::process::id()));
+        let exec_file_path = folder.join(format!("anc_test_lowering_{}.elf", std::process::id()));
+        std::fs::write(&object_file_path, module_binary).unwrap();
+
+        link_executable(
+            &[object_file_path.to_str().unwrap()],
+            exec_file_path.to_str().unwrap(),
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+
+        let output = Command::new(&exec_file_path).output().unwrap();
+        assert_eq!(output.status.code(), Some(7));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Hello, assembler!\n"
+        );
+
+        std::fs::remove_file(&object_file_path).unwrap();
+        std::fs::remove_file(&exec_file_path).unwrap();
+    }
+
+    #[test]
+    fn test_lowering_colocated_import() {
+        let emit = |source: &str| {
+            let module = parse_module(source).unwrap();
+            let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+            assemble_module(&module, &mut generator).unwrap();
+            generator.finish().unwrap().emit().unwrap()
+        };
+
+        let lib_binary = emit(
+            r#"
+            (module $lib
+                (function $get_number export (result i32)
+                    (code (imm_i32 11))))
+            "#,
+        );
+        let main_binary = emit(
+            r#"
+            (module $main
+                (import (function $get_number colocated (result i32)))
+                (import (function $abs (param i32) (result i32)))
+                (function $main export (result i32)
+                    (code (call $abs (call $get_number)))))
+            "#,
+        );
+
+        // the colocated function is called directly (the PLT32 relocation is resolved
+        // to the function itself by the linker), and the other is called through the GOT
+        let elf_file = ElfFile64::<Endianness>::parse(main_binary.as_slice()).unwrap();
+        let text_section = elf_file.section_by_name(".text").unwrap();
+        let mut relocation_kinds = text_section
+            .relocations()
+            .map(|(_, relocation)| {
+                let RelocationTarget::Symbol(symbol_index) = relocation.target() else {
+                    unreachable!()
+                };
+                let symbol = elf_file.symbol_by_index(symbol_index).unwrap();
+                (symbol.name().unwrap().to_owned(), relocation.kind())
+            })
+            .collect::<Vec<_>>();
+        relocation_kinds.sort_by(|left, right| left.0.cmp(&right.0));
+        assert_eq!(
+            relocation_kinds,
+            vec![
+                ("abs".to_owned(), RelocationKind::GotRelative),
+                ("get_number".to_owned(), RelocationKind::PltRelative)
+            ]
+        );
+
+        let temp_folder = TempFolder::new("lowering_colocated");
+        let lib_file_path = temp_folder.file_path("lib.o");
+        let main_file_path = temp_folder.file_path("main.o");
+        let exec_file_path = temp_folder.file_path("main.elf");
+        std::fs::write(&lib_file_path, lib_binary).unwrap();
+        std::fs::write(&main_file_path, main_binary).unwrap();
+
+        link_executable(
+            &[&main_file_path, &lib_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(11));
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    binemit::Reloc,
+    ir::{ExternalName, Function, Signature, UserExternalName, UserFuncName},
+    FinalizedMachReloc, FinalizedRelocTarget,
+};
+use cranelift_module::{FuncId, FuncOrDataId, Linkage, Module, ModuleError};
+
+use crate::{code_generator::Generator, validation::SymbolReferences};
+
+// The machine code functions
+// --------------------------
+//
+// The handwritten assembly kernels (e.g. the SIMD routines and the system
+// call stubs) and the functions which are compiled by the other compilers
+// can be defined by their machine code, so they live alongside the generated
+// functions, e.g. the x86_64 function `extern "C" fn(i64) -> i64` which adds
+// the data `counter` to the argument:
+//
+// ```rust
+// let func_id = generator.define_function_from_bytes(
+//     "add_counter",
+//     &signature,
+//     &[
+//         0x48, 0x8b, 0x05, 0x00, 0x00, 0x00, 0x00, // mov rax, [rip + counter@GOTPCREL]
+//         0x48, 0x8b, 0x00, // mov rax, [rax]
+//         0x48, 0x01, 0xf8, // add rax, rdi
+//         0xc3, // ret
+//     ],
+//     &[MachineCodeRelocation {
+//         offset: 3,
+//         kind: Reloc::X86GOTPCRel4,
+//         target: FuncOrDataId::Data(counter_id),
+//         addend: -4,
+//     }],
+//     true,
+// )?;
+// ```
+//
+// the code is copied as it is, so:
+//
+// - it should follow the calling convention of the signature, e.g. System V
+//   on Linux.
+// - the kinds of the relocations depend on the backend, e.g. the JIT module
+//   (which is not position-independent by default) resolves `Abs8` and the
+//   PC-relative calls, and the linker resolves the GOT and PLT relocations
+//   of the object files.
+// - there is no unwind information and no source map of the function, i.e.
+//   the stack walkers can not unwind through it unless it is a leaf function.
+//
+// ref:
+// - https://docs.rs/cranelift-module/latest/cranelift_module/trait.Module.html#tymethod.define_function_bytes
+
+/// The relocation of the machine code, i.e. the address of the target plus
+/// the addend is written at the offset of the code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineCodeRelocation {
+    pub offset: u32,
+    pub kind: Reloc,
+    pub target: FuncOrDataId,
+    pub addend: i64,
+}
+
+/// The number of bytes which are written by the relocation.
+fn get_relocation_size(kind: Reloc) -> u32 {
+    match kind {
+        Reloc::Abs8 | Reloc::RiscvCallPlt => 8,
+        _ => 4,
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Define the function by the precompiled machine code and its relocations.
+    pub fn define_function_from_bytes(
+        &mut self,
+        name: &str,
+        signature: &Signature,
+        bytes: &[u8],
+        relocations: &[MachineCodeRelocation],
+        export: bool,
+    ) -> Result<FuncId, ModuleError> {
+        let linkage = if export {
+            Linkage::Export
+        } else {
+            Linkage::Local
+        };
+
+        let func_id = self.module.declare_function(name, linkage, signature)?;
+
+        // the function is only used for resolving the names of the relocations
+        let mut func = Function::with_name_signature(
+            UserFuncName::user(0, func_id.as_u32()),
+            signature.clone(),
+        );
+        let mut symbol_references = SymbolReferences {
+            definition: FuncOrDataId::Func(func_id),
+            functions: vec![],
+            data: vec![],
+        };
+
+        let mut relocs = vec![];
+        for relocation in relocations {
+            if relocation.offset as usize + get_relocation_size(relocation.kind) as usize
+                > bytes.len()
+            {
+                return Err(ModuleError::Backend(anyhow::anyhow!(
+                    "the relocation at offset 0x{:x} is out of the machine code of the function \"{}\"",
+                    relocation.offset,
+                    name
+                )));
+            }
+
+            let user_name = match relocation.target {
+                FuncOrDataId::Func(target_id) => {
+                    symbol_references.functions.push((target_id, None));
+                    UserExternalName::new(0, target_id.as_u32())
+                }
+                FuncOrDataId::Data(target_id) => {
+                    symbol_references.data.push(target_id);
+                    UserExternalName::new(1, target_id.as_u32())
+                }
+            };
+            let name_ref = func.declare_imported_user_function(user_name);
+            relocs.push(FinalizedMachReloc {
+                offset: relocation.offset,
+                kind: relocation.kind,
+                target: FinalizedRelocTarget::ExternalName(ExternalName::User(name_ref)),
+                addend: relocation.addend,
+            });
+        }
+
+        self.check_position_independence(func_id, &func, &relocs)?;
+        let align = self.get_function_alignment(func_id, 16);
+        self.module
+            .define_function_bytes(func_id, &func, align, bytes, &relocs)?;
+        self.set_symbol_references(symbol_references);
+        Ok(func_id)
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_codegen::{
+        binemit::Reloc,
+        ir::{types, AbiParam},
+    };
+    use cranelift_jit::JITModule;
+    use cranelift_module::{FuncOrDataId, Module};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerOptions},
+        lowering::{assemble_module, AssembledModule},
+        machine_code::MachineCodeRelocation,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    // the kernels `add_counter` and `jump_double` are defined by the machine code
+    const SOURCE: &str = r#"
+    (module $kernels
+        (import (function $add_counter (param i64) (result i64)))
+        (import (function $jump_double (param i64) (result i64)))
+        (data $counter (read_write i64 5))
+        (function $double (param $x i64) (result i64)
+            (code (mul_i64 (local_load $x) (imm_i64 2))))
+        (function $main export (result i32)
+            (code
+                (truncate_i64_to_i32
+                    (add_i64
+                        (call $add_counter (imm_i64 10))
+                        (call $jump_double (imm_i64 3)))))))
+    "#;
+
+    /// Define the kernels, the JIT module refers to the data by the absolute
+    /// address, and the object module refers to it by the GOT.
+    fn define_kernels<T: Module>(
+        generator: &mut Generator<T>,
+        assembled_module: &AssembledModule,
+        is_pic: bool,
+    ) {
+        let mut signature = generator.module.make_signature();
+        signature.params.push(AbiParam::new(types::I64));
+        signature.returns.push(AbiParam::new(types::I64));
+
+        let counter_id = FuncOrDataId::Data(assembled_module.get_data_id("counter").unwrap());
+        let (code, relocation): (&[u8], _) = if is_pic {
+            (
+                &[
+                    0x48, 0x8b, 0x05, 0x00, 0x00, 0x00, 0x00, // mov rax, [counter@GOTPCREL]
+                    0x48, 0x8b, 0x00, // mov rax, [rax]
+                    0x48, 0x01, 0xf8, // add rax, rdi
+                    0xc3, // ret
+                ],
+                MachineCodeRelocation {
+                    offset: 3,
+                    kind: Reloc::X86GOTPCRel4,
+                    target: counter_id,
+                    addend: -4,
+                },
+            )
+        } else {
+            (
+                &[
+                    0x48, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, // movabs rax, counter
+                    0x48, 0x8b, 0x00, // mov rax, [rax]
+                    0x48, 0x01, 0xf8, // add rax, rdi
+                    0xc3, // ret
+                ],
+                MachineCodeRelocation {
+                    offset: 2,
+                    kind: Reloc::Abs8,
+                    target: counter_id,
+                    addend: 0,
+                },
+            )
+        };
+        generator
+            .define_function_from_bytes("add_counter", &signature, code, &[relocation], false)
+            .unwrap();
+
+        let double_id = assembled_module.get_function_id("double").unwrap();
+        generator
+            .define_function_from_bytes(
+                "jump_double",
+                &signature,
+                &[
+                    0xe9, 0x00, 0x00, 0x00, 0x00, // jmp double
+                ],
+                &[MachineCodeRelocation {
+                    offset: 1,
+                    kind: Reloc::X86CallPCRel4,
+                    target: FuncOrDataId::Func(double_id),
+                    addend: -4,
+                }],
+                false,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_machine_code() {
+        let module = parse_module(SOURCE).unwrap();
+
+        // JIT
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        define_kernels(&mut generator, &assembled_module, false);
+        generator.module.finalize_definitions().unwrap();
+
+        let func_main = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn() -> i32>(
+                generator
+                    .module
+                    .get_finalized_function(assembled_module.get_function_id("main").unwrap()),
+            )
+        };
+        assert_eq!(func_main(), 21);
+
+        // object file
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        define_kernels(&mut generator, &assembled_module, true);
+
+        let temp_folder = TempFolder::new("machine_code");
+        let object_file_path = temp_folder.file_path("kernels.o");
+        let exec_file_path = temp_folder.file_path("kernels.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(21));
+
+        // the relocation out of the code, and the absolute address in the
+        // position-independent code
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let double_id = assembled_module.get_function_id("double").unwrap();
+        let signature = generator.module.make_signature();
+        let relocation = MachineCodeRelocation {
+            offset: 2,
+            kind: Reloc::Abs8,
+            target: FuncOrDataId::Func(double_id),
+            addend: 0,
+        };
+
+        assert_eq!(
+            generator
+                .define_function_from_bytes("short", &signature, &[0x90; 9], std::slice::from_ref(&relocation), false)
+                .unwrap_err()
+                .to_string(),
+            "Backend error: the relocation at offset 0x2 is out of the machine code of the function \"short\""
+        );
+
+        generator.enable_position_independence_check();
+        assert_eq!(
+            generator
+                .define_function_from_bytes("absolute", &signature, &[0x90; 10], &[relocation], false)
+                .unwrap_err()
+                .to_string(),
+            "Backend error: the function \"absolute\" refers to \"double\" by the absolute address, which is not position-independent"
+        );
+    }
+}
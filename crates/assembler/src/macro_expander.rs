@@ -0,0 +1,426 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::Diagnostic,
+    lexer::{Span, Token, TokenKind},
+    parser::SExpr,
+};
+
+// The macro expander
+// ------------------
+//
+// The macros are defined in the module by `define-macro`, and are expanded
+// by `expand` anywhere in the module (including the module items), e.g.
+//
+// ```text
+// (module $app
+//     (define-macro $checked_load (param $address $limit)
+//         (if (result i32)
+//             (lt_i64_u $address $limit)
+//             (memory_load_i32 $address)
+//             (panic 1)))
+//
+//     (function $get (param $p i64) (result i32)
+//         (code (expand $checked_load (local_load $p) (imm_i64 4096)))))
+// ```
+//
+// The expansion works on the S-expressions, i.e. between the two stages of
+// the parser, the names of the macro parameters in the body are replaced by
+// the arguments (the S-expressions), and the result is expanded again, so
+// a macro can expand the other macros.
+//
+// - the macros are hygienic: the locals which are declared in the body
+//   (by `(param $name ...)` and `(local $name ...)`, e.g. the parameters of
+//   `for`) are renamed for each expansion, so they neither capture nor
+//   shadow the locals of the arguments.
+// - the spans are preserved: the nodes of the body keep the spans of the macro
+//   definition, and the arguments keep the spans of the call site, so the
+//   diagnostics point to the source text which the node comes from.
+// - the macros are local to the file (i.e. they are not exported by
+//   `(import (module ...))`), and the order of the definitions does not matter.
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+struct MacroDefinition {
+    params: Vec<String>,
+    body: SExpr,
+}
+
+/// Collect the macro definitions of the module and expand the macros,
+/// the returned module node contains no `define-macro` and `expand`.
+pub fn expand_macros(module: &SExpr) -> Result<SExpr, Diagnostic> {
+    let SExpr::List { items, span } = module else {
+        unreachable!()
+    };
+
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        expansion_count: 0,
+    };
+
+    let mut other_items = vec![];
+    for item in items {
+        if item.get_head_keyword() == Some("define-macro") {
+            expander.define(item)?;
+        } else {
+            other_items.push(item);
+        }
+    }
+
+    let items = other_items
+        .into_iter()
+        .map(|item| expander.expand(item, 0))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(SExpr::List { items, span: *span })
+}
+
+fn get_name(sexpr: Option<&SExpr>) -> Option<(&str, Span)> {
+    match sexpr {
+        Some(SExpr::Atom(Token {
+            kind: TokenKind::Name(name),
+            span,
+        })) => Some((name, *span)),
+        _ => None,
+    }
+}
+
+struct Expander {
+    macros: HashMap<String, MacroDefinition>,
+
+    // for generating the unique names of the locals of the expansions
+    expansion_count: usize,
+}
+
+impl Expander {
+    /// `(define-macro $name (param $a $b ...)? body)`
+    fn define(&mut self, sexpr: &SExpr) -> Result<(), Diagnostic> {
+        let SExpr::List { items, span } = sexpr else {
+            unreachable!()
+        };
+
+        let (name, name_span) = get_name(items.get(1)).ok_or_else(|| {
+            Diagnostic::new(
+                "expect the name of the macro, e.g. \"(define-macro $name ...)\"",
+                *span,
+            )
+        })?;
+
+        let mut position = 2;
+        let mut params: Vec<String> = vec![];
+        if let Some(
+            param_list @ SExpr::List {
+                items: param_items, ..
+            },
+        ) = items.get(position)
+        {
+            if param_list.get_head_keyword() == Some("param") {
+                for param_item in &param_items[1..] {
+                    let (param_name, param_span) = get_name(Some(param_item)).ok_or_else(|| {
+                        Diagnostic::new("expect the name of the macro parameter", param_item.span())
+                    })?;
+                    if params.iter().any(|existing| existing == param_name) {
+                        return Err(Diagnostic::new(
+                            &format!("duplicate macro parameter \"${}\"", param_name),
+                            param_span,
+                        ));
+                    }
+                    params.push(param_name.to_owned());
+                }
+                position += 1;
+            }
+        }
+
+        let body = match &items[position..] {
+            [body] => body.clone(),
+            _ => {
+                return Err(Diagnostic::new(
+                    &format!(
+                        "the body of the macro \"${}\" should be exactly one node",
+                        name
+                    ),
+                    *span,
+                )
+                .with_note("use \"(do ...)\" to group the instructions"))
+            }
+        };
+
+        if self.macros.contains_key(name) {
+            return Err(Diagnostic::new(
+                &format!("duplicate macro \"${}\"", name),
+                name_span,
+            ));
+        }
+
+        self.macros
+            .insert(name.to_owned(), MacroDefinition { params, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, sexpr: &SExpr, depth: usize) -> Result<SExpr, Diagnostic> {
+        let SExpr::List { items, span } = sexpr else {
+            return Ok(sexpr.clone());
+        };
+
+        match sexpr.get_head_keyword() {
+            Some("expand") => self.expand_call(items, *span, depth),
+            Some("define-macro") => Err(Diagnostic::new(
+                "the macros can only be defined in the module node",
+                *span,
+            )),
+            _ => Ok(SExpr::List {
+                items: items
+                    .iter()
+                    .map(|item| self.expand(item, depth))
+                    .collect::<Result<Vec<_>, _>>()?,
+                span: *span,
+            }),
+        }
+    }
+
+    /// `(expand $name arg...)`
+    fn expand_call(
+        &mut self,
+        items: &[SExpr],
+        span: Span,
+        depth: usize,
+    ) -> Result<SExpr, Diagnostic> {
+        let (name, name_span) = get_name(items.get(1)).ok_or_else(|| {
+            Diagnostic::new(
+                "expect the name of the macro, e.g. \"(expand $name ...)\"",
+                span,
+            )
+        })?;
+
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(Diagnostic::new(
+                &format!("the expansion of the macro \"${}\" is too deep", name),
+                span,
+            )
+            .with_note(&format!(
+                "the macros can be nested up to {} levels, check whether the macro expands itself",
+                MAX_EXPANSION_DEPTH
+            )));
+        }
+
+        let definition = self.macros.get(name).ok_or_else(|| {
+            Diagnostic::new(
+                &format!("the macro \"${}\" is not defined", name),
+                name_span,
+            )
+        })?;
+
+        let args = &items[2..];
+        if args.len() != definition.params.len() {
+            return Err(Diagnostic::new(
+                &format!(
+                    "the macro \"${}\" requires {} argument(s), found {}",
+                    name,
+                    definition.params.len(),
+                    args.len()
+                ),
+                span,
+            ));
+        }
+
+        self.expansion_count += 1;
+        let suffix = format!("::{}.{}", name, self.expansion_count);
+
+        let mut bound_names = vec![];
+        collect_bound_names(&definition.body, &definition.params, &mut bound_names);
+        let renamed_body = rename_locals(&definition.body, &bound_names, &suffix);
+
+        let bindings = definition
+            .params
+            .iter()
+            .map(|param| param.as_str())
+            .zip(args)
+            .collect::<HashMap<_, _>>();
+        let substituted_body = substitute(&renamed_body, &bindings);
+
+        self.expand(&substituted_body, depth + 1)
+    }
+}
+
+/// The keywords of the lists whose second item is the name of a local.
+const LOCAL_KEYWORDS: [&str; 4] = ["param", "local", "local_load", "local_store"];
+
+/// Collect the locals which are declared in the macro body, except the ones
+/// which are named by the arguments.
+fn collect_bound_names(sexpr: &SExpr, params: &[String], bound_names: &mut Vec<String>) {
+    if let SExpr::List { items, .. } = sexpr {
+        if matches!(sexpr.get_head_keyword(), Some("param" | "local")) {
+            if let Some((name, _)) = get_name(items.get(1)) {
+                if !params.iter().any(|param| param == name)
+                    && !bound_names.iter().any(|bound| bound == name)
+                {
+                    bound_names.push(name.to_owned());
+                }
+            }
+        }
+        for item in items {
+            collect_bound_names(item, params, bound_names);
+        }
+    }
+}
+
+fn rename_locals(sexpr: &SExpr, bound_names: &[String], suffix: &str) -> SExpr {
+    let SExpr::List { items, span } = sexpr else {
+        return sexpr.clone();
+    };
+
+    let is_local_list = sexpr
+        .get_head_keyword()
+        .is_some_and(|keyword| LOCAL_KEYWORDS.contains(&keyword));
+
+    let items = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| match get_name(Some(item)) {
+            Some((name, name_span))
+                if is_local_list && index == 1 && bound_names.iter().any(|b| b == name) =>
+            {
+                SExpr::Atom(Token {
+                    kind: TokenKind::Name(format!("{}{}", name, suffix)),
+                    span: name_span,
+                })
+            }
+            _ => rename_locals(item, bound_names, suffix),
+        })
+        .collect();
+
+    SExpr::List { items, span: *span }
+}
+
+fn substitute(sexpr: &SExpr, bindings: &HashMap<&str, &SExpr>) -> SExpr {
+    match sexpr {
+        SExpr::List { items, span } => SExpr::List {
+            items: items
+                .iter()
+                .map(|item| substitute(item, bindings))
+                .collect(),
+            span: *span,
+        },
+        SExpr::Atom(Token {
+            kind: TokenKind::Name(name),
+            ..
+        }) => match bindings.get(name.as_str()) {
+            Some(arg) => (*arg).clone(),
+            None => sexpr.clone(),
+        },
+        SExpr::Atom(_) => sexpr.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::InstructionKind, code_generator::Generator, diagnostic::Diagnostic, lexer::Span,
+        lowering::assemble_module, parser::parse_module,
+    };
+
+    #[test]
+    fn test_expand_macros() {
+        let source = r#"
+        (module $app
+            (function $main (result i32) (local $i i32)
+                (code
+                    (local_store $i (imm_i32 100))
+                    (add_i32
+                        (expand $sum_to (sub_i32 (local_load $i) (imm_i32 96)))
+                        (expand $twice (local_load $i)))))
+
+            // the local "$i" of the macro does not capture the one of the caller
+            (define-macro $sum_to (param $n)
+                (for (param $i i32 (imm_i32 1)) (param $acc i32 (imm_i32 0)) (result i32)
+                    (when (gt_i32_s (local_load $i) $n)
+                        (break (local_load $acc)))
+                    (recur
+                        (add_i32 (local_load $i) (imm_i32 1))
+                        (add_i32 (local_load $acc) (expand $twice (local_load $i))))))
+
+            (define-macro $twice (param $value)
+                (add_i32 $value $value))
+
+            // the macro which expands to a function
+            (define-macro $constant_function (param $name $value)
+                (function $name (result i32) (code $value)))
+
+            (expand $constant_function $answer (imm_i32 42)))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        assert_eq!(
+            module
+                .functions
+                .iter()
+                .map(|node| node.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["main", "answer"]
+        );
+
+        // the argument keeps the span of the call site
+        let start = source.find("(imm_i32 42)").unwrap();
+        let instruction = &module.functions[1].body[0];
+        assert_eq!(instruction.kind, InstructionKind::ImmI32(42));
+        assert_eq!(instruction.span, Span::new(start, start + 12));
+
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+        let get_function = |name: &str| -> extern "C" fn() -> i32 {
+            unsafe {
+                std::mem::transmute(
+                    generator
+                        .module
+                        .get_finalized_function(assembled_module.get_function_id(name).unwrap()),
+                )
+            }
+        };
+        // (1 + 2 + 3 + 4) * 2 + 100 * 2
+        assert_eq!(get_function("main")(), 220);
+        assert_eq!(get_function("answer")(), 42);
+
+        // the errors
+        fn parse_error(source: &str) -> (String, &str) {
+            let Diagnostic { message, span, .. } = parse_module(source).unwrap_err();
+            (message, &source[span.start..span.end])
+        }
+
+        assert_eq!(
+            parse_error("(module $a (function $f (code (expand $m))))"),
+            ("the macro \"$m\" is not defined".to_owned(), "$m")
+        );
+        assert_eq!(
+            parse_error(
+                "(module $a (define-macro $m (param $x) $x) (function $f (code (expand $m))))"
+            ),
+            (
+                "the macro \"$m\" requires 1 argument(s), found 0".to_owned(),
+                "(expand $m)"
+            )
+        );
+        assert_eq!(
+            parse_error(
+                "(module $a (define-macro $m (expand $m)) (function $f (code (expand $m))))"
+            )
+            .0,
+            "the expansion of the macro \"$m\" is too deep"
+        );
+
+        // the error in the body points to the macro definition
+        assert_eq!(
+            parse_error("(module $a (define-macro $m (add_i33)) (function $f (code (expand $m))))"),
+            ("unknown instruction \"add_i33\"".to_owned(), "add_i33")
+        );
+    }
+}
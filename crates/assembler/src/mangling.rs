@@ -0,0 +1,180 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::Signature;
+use cranelift_module::{DataId, FuncId, Linkage, Module, ModuleError};
+
+use crate::code_generator::Generator;
+
+// The name mangling
+// -----------------
+//
+// The functions and data of the XiaoXuan modules are namespaced by the module path,
+// e.g. `std::io::print`, the full name is mangled as the symbol name so the symbols
+// of different modules do not collide, the scheme is similar to the "nested name"
+// of the Itanium C++ ABI:
+//
+// ```text
+// symbol  = "_X" "N" segment+ "E"
+// segment = <length in decimal> <name bytes>
+// ```
+//
+// e.g.
+//
+// - `std::io::print` -> `_XN3std2io5printE`
+// - `app::main` -> `_XN3app4mainE`
+//
+// the segment can contain any character except "::" (the length is counted
+// in bytes), and the symbols which do not start with `_XN` (e.g. `main`
+// and the libc functions) are not mangled.
+//
+// ref:
+// - https://itanium-cxx-abi.github.io/cxx-abi/abi.html#mangle.nested-name
+
+pub const MANGLED_NAME_PREFIX: &str = "_XN";
+
+/// Mangle the full name, e.g. `mangle_name("std::io", "print")`,
+/// the module path can be empty.
+pub fn mangle_name(module_path: &str, name: &str) -> String {
+    let mut symbol = MANGLED_NAME_PREFIX.to_owned();
+
+    for segment in module_path
+        .split("::")
+        .filter(|segment| !segment.is_empty())
+        .chain([name])
+    {
+        symbol.push_str(&segment.len().to_string());
+        symbol.push_str(segment);
+    }
+
+    symbol.push('E');
+    symbol
+}
+
+/// Get the segments of the mangled symbol, e.g. `["std", "io", "print"]`,
+/// returns `None` if the symbol is not a valid mangled name.
+pub fn demangle_segments(symbol: &str) -> Option<Vec<&str>> {
+    let mut remain = symbol.strip_prefix(MANGLED_NAME_PREFIX)?;
+    let mut segments = vec![];
+
+    while !remain.starts_with('E') {
+        let digits_len = remain.find(|c: char| !c.is_ascii_digit())?;
+        let length = remain[..digits_len].parse::<usize>().ok()?;
+        if length == 0 {
+            return None;
+        }
+
+        remain = &remain[digits_len..];
+        segments.push(remain.get(..length)?);
+        remain = &remain[length..];
+    }
+
+    if remain.len() != 1 || segments.is_empty() {
+        return None;
+    }
+
+    Some(segments)
+}
+
+/// Demangle the symbol as the full name, e.g. `_XN3std2io5printE` -> `std::io::print`,
+/// the symbol is returned unchanged if it is not a mangled name.
+pub fn demangle(symbol: &str) -> String {
+    match demangle_segments(symbol) {
+        Some(segments) => segments.join("::"),
+        None => symbol.to_owned(),
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Declare the function with the mangled name of `module_path::name`.
+    pub fn declare_namespaced_function(
+        &mut self,
+        module_path: &str,
+        name: &str,
+        linkage: Linkage,
+        signature: &Signature,
+    ) -> Result<FuncId, ModuleError> {
+        self.module
+            .declare_function(&mangle_name(module_path, name), linkage, signature)
+    }
+
+    /// Declare the data with the mangled name of `module_path::name`.
+    pub fn declare_namespaced_data(
+        &mut self,
+        module_path: &str,
+        name: &str,
+        linkage: Linkage,
+        writable: bool,
+        tls: bool,
+    ) -> Result<DataId, ModuleError> {
+        self.module
+            .declare_data(&mangle_name(module_path, name), linkage, writable, tls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam};
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        mangling::{demangle, demangle_segments, mangle_name},
+    };
+
+    #[test]
+    fn test_mangling() {
+        assert_eq!(mangle_name("std::io", "print"), "_XN3std2io5printE");
+        assert_eq!(mangle_name("", "main"), "_XN4mainE");
+        assert_eq!(mangle_name("app", "数"), "_XN3app3数E");
+
+        assert_eq!(
+            demangle_segments("_XN3std2io5printE"),
+            Some(vec!["std", "io", "print"])
+        );
+        assert_eq!(demangle("_XN3std2io5printE"), "std::io::print");
+        assert_eq!(demangle("_XN3app3数E"), "app::数");
+        assert_eq!(demangle("_XN10a1b2c3d4e5E"), "a1b2c3d4e5");
+
+        // not mangled or invalid
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(demangle_segments("_XNE"), None);
+        assert_eq!(demangle_segments("_XN3stdio"), None);
+        assert_eq!(demangle_segments("_XN3stdE5print"), None);
+        assert_eq!(demangle_segments("_XN0E"), None);
+
+        // the functions of different modules do not collide
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        let mut func_sig = generator.module.make_signature();
+        func_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_0 = generator
+            .declare_namespaced_function("app::a", "get", Linkage::Export, &func_sig)
+            .unwrap();
+        let func_1 = generator
+            .declare_namespaced_function("app::b", "get", Linkage::Export, &func_sig)
+            .unwrap();
+        assert_ne!(func_0, func_1);
+
+        let data_0 = generator
+            .declare_namespaced_data("app::a", "count", Linkage::Export, true, false)
+            .unwrap();
+        assert_eq!(
+            generator
+                .module
+                .declarations()
+                .get_data_decl(data_0)
+                .name
+                .as_deref(),
+            Some("_XN3app1a5countE")
+        );
+    }
+}
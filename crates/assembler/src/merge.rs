@@ -0,0 +1,364 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{ExternalName, GlobalValueData, UserExternalName, UserFuncName};
+use cranelift_module::{DataId, FuncId, Linkage, Module, ModuleError};
+use cranelift_object::ObjectModule;
+
+use crate::code_generator::Generator;
+
+// The whole-module merge
+// ----------------------
+//
+// Merge several generators into one `ObjectModule` before emission, so that:
+//
+// - the calls between the modules become local (and colocated if the linkage of
+//   the callee is final, i.e. `Local`, `Hidden` or `Export`), the linker does not
+//   need to generate PLT entries for them.
+// - the dead code elimination can see the whole program.
+//
+// the declarations are merged by name:
+//
+// - the declarations with the same name (except the `Local` ones) are merged
+//   into one, e.g. `Import` + `Export` -> `Export`, the signatures must be the same.
+// - the `Local` declarations are private to their source module, they are
+//   declared as anonymous ones if the name has already been taken.
+//
+// the functions and data are re-created from the pre-emission IR of the source
+// generators (i.e. `clif_functions` and `data_definitions`), and the `FuncId`s
+// and `DataId`s which are referenced by the IR are rewritten.
+//
+// Note that the source map and the debug information of the source generators
+// are not merged.
+
+/// The id mapping of a source generator, i.e. old id -> new id.
+struct IdMapping {
+    func_ids: Vec<FuncId>,
+    data_ids: Vec<DataId>,
+}
+
+impl Generator<ObjectModule> {
+    /// Merge the generators into a new generator.
+    pub fn merge<T: Module>(
+        module_name: &str,
+        opt_platform: Option<&str>,
+        generators: &[&Generator<T>],
+    ) -> Result<Self, ModuleError> {
+        let mut merged = Generator::<ObjectModule>::new(module_name, opt_platform);
+
+        let mut mappings = generators
+            .iter()
+            .map(|generator| {
+                let declarations = generator.module.declarations();
+                IdMapping {
+                    func_ids: vec![FuncId::from_u32(0); declarations.get_functions().count()],
+                    data_ids: vec![DataId::from_u32(0); declarations.get_data_objects().count()],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // declare the non-local functions and data first, so that the
+        // local ones can be detected that whether their names are taken.
+        for local_pass in [false, true] {
+            for (generator, mapping) in generators.iter().zip(mappings.iter_mut()) {
+                let declarations = generator.module.declarations();
+
+                for (func_id, declaration) in declarations.get_functions() {
+                    if (declaration.linkage == Linkage::Local) != local_pass {
+                        continue;
+                    }
+
+                    let new_func_id = match &declaration.name {
+                        Some(name) if !(local_pass && merged.module.get_name(name).is_some()) => {
+                            merged.module.declare_function(
+                                name,
+                                declaration.linkage,
+                                &declaration.signature,
+                            )?
+                        }
+                        _ => merged
+                            .module
+                            .declare_anonymous_function(&declaration.signature)?,
+                    };
+                    mapping.func_ids[func_id.as_u32() as usize] = new_func_id;
+                }
+
+                for (data_id, declaration) in declarations.get_data_objects() {
+                    if (declaration.linkage == Linkage::Local) != local_pass {
+                        continue;
+                    }
+
+                    let new_data_id = match &declaration.name {
+                        Some(name) if !(local_pass && merged.module.get_name(name).is_some()) => {
+                            merged.module.declare_data(
+                                name,
+                                declaration.linkage,
+                                declaration.writable,
+                                declaration.tls,
+                            )?
+                        }
+                        _ => merged
+                            .module
+                            .declare_anonymous_data(declaration.writable, declaration.tls)?,
+                    };
+                    mapping.data_ids[data_id.as_u32() as usize] = new_data_id;
+                }
+            }
+        }
+
+        // define data
+        for (generator, mapping) in generators.iter().zip(mappings.iter()) {
+            for (data_id, data_definition) in &generator.data_definitions {
+                merged.define_data_content(
+                    mapping.data_ids[data_id.as_u32() as usize],
+                    data_definition.clone(),
+                )?;
+            }
+        }
+
+        // define functions
+        for (generator, mapping) in generators.iter().zip(mappings.iter()) {
+            for (func_id, clif_text) in &generator.clif_functions {
+                let mut functions = cranelift_reader::parse_functions(clif_text).map_err(|e| {
+                    ModuleError::Backend(anyhow::anyhow!("Failed to parse CLIF: {}", e))
+                })?;
+                let mut func = functions.remove(0);
+
+                let new_func_id = mapping.func_ids[func_id.as_u32() as usize];
+                func.name = UserFuncName::user(0, new_func_id.as_u32());
+
+                // rewrite the external names
+                let user_named_funcs = func
+                    .params
+                    .user_named_funcs()
+                    .iter()
+                    .map(|(name_ref, name)| (name_ref, name.clone()))
+                    .collect::<Vec<_>>();
+
+                for (name_ref, name) in user_named_funcs {
+                    let index = match name.namespace {
+                        0 => mapping.func_ids[name.index as usize].as_u32(),
+                        1 => mapping.data_ids[name.index as usize].as_u32(),
+                        _ => continue,
+                    };
+                    func.params.reset_user_func_name(
+                        name_ref,
+                        UserExternalName::new(name.namespace, index),
+                    );
+                }
+
+                // the callees and the data which are defined in the merged module
+                // can be accessed directly
+                let declarations = merged.module.declarations();
+                let is_final = |name: &ExternalName| match name {
+                    ExternalName::User(name_ref) => {
+                        let name = &func.params.user_named_funcs()[*name_ref];
+                        match name.namespace {
+                            0 => declarations
+                                .get_function_decl(FuncId::from_u32(name.index))
+                                .linkage
+                                .is_final(),
+                            1 => declarations
+                                .get_data_decl(DataId::from_u32(name.index))
+                                .linkage
+                                .is_final(),
+                            _ => false,
+                        }
+                    }
+                    _ => false,
+                };
+
+                let colocated_funcs = func
+                    .dfg
+                    .ext_funcs
+                    .iter()
+                    .filter(|(_, ext_func)| is_final(&ext_func.name))
+                    .map(|(func_ref, _)| func_ref)
+                    .collect::<Vec<_>>();
+
+                let colocated_global_values = func
+                    .global_values
+                    .iter()
+                    .filter(|(_, global_value)| {
+                        matches!(global_value, GlobalValueData::Symbol { name, tls: false, .. }
+                            if is_final(name))
+                    })
+                    .map(|(global_value, _)| global_value)
+                    .collect::<Vec<_>>();
+
+                for func_ref in colocated_funcs {
+                    func.dfg.ext_funcs[func_ref].colocated = true;
+                }
+
+                for global_value in colocated_global_values {
+                    if let GlobalValueData::Symbol { colocated, .. } =
+                        &mut func.global_values[global_value]
+                    {
+                        *colocated = true;
+                    }
+                }
+
+                merged.define_function(new_func_id, func)?;
+            }
+        }
+
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, MemFlags, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{code_generator::Generator, test_support::run_executable_binary_and_get_exit_code};
+
+    #[test]
+    fn test_merge_modules() {
+        // module "lib"
+        //
+        // ```rust
+        // static mut NUMBER: i32 = 11;   // local
+        // pub fn get_number() -> i32 {
+        //     NUMBER
+        // }
+        // ```
+        let mut generator_lib = Generator::<ObjectModule>::new("lib", None);
+
+        let data_number_id = generator_lib
+            .define_initialized_data(
+                "number",
+                11i32.to_le_bytes().to_vec(),
+                4,
+                false,
+                true,
+                false,
+            )
+            .unwrap();
+
+        let mut func_get_number_sig = generator_lib.module.make_signature();
+        func_get_number_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_get_number_id = generator_lib
+            .module
+            .declare_function("get_number", Linkage::Export, &func_get_number_sig)
+            .unwrap();
+
+        let mut func_get_number = Function::with_name_signature(
+            UserFuncName::user(0, func_get_number_id.as_u32()),
+            func_get_number_sig.clone(),
+        );
+
+        let data_number_gv = generator_lib
+            .module
+            .declare_data_in_func(data_number_id, &mut func_get_number);
+        let pointer_type = generator_lib.module.isa().pointer_type();
+
+        let mut function_builder = FunctionBuilder::new(
+            &mut func_get_number,
+            &mut generator_lib.function_builder_context,
+        );
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder
+            .ins()
+            .symbol_value(pointer_type, data_number_gv);
+        let value_1 = function_builder
+            .ins()
+            .load(types::I32, MemFlags::new(), value_0, 0);
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator_lib
+            .define_function(func_get_number_id, func_get_number)
+            .unwrap();
+
+        // module "main"
+        //
+        // ```rust
+        // static mut NUMBER: i32 = 2;    // local, the same name as the one in "lib"
+        // extern "C" fn get_number() -> i32;
+        // fn main() -> i32 {
+        //     get_number() + NUMBER
+        // }
+        // ```
+        let mut generator_main = Generator::<ObjectModule>::new("main", None);
+
+        let data_number_id = generator_main
+            .define_initialized_data("number", 2i32.to_le_bytes().to_vec(), 4, false, true, false)
+            .unwrap();
+
+        let func_get_number_id = generator_main
+            .module
+            .declare_function("get_number", Linkage::Import, &func_get_number_sig)
+            .unwrap();
+
+        let func_main_id = generator_main
+            .module
+            .declare_function("main", Linkage::Export, &func_get_number_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_get_number_sig.clone(),
+        );
+
+        let func_get_number_ref = generator_main
+            .module
+            .declare_func_in_func(func_get_number_id, &mut func_main);
+        let data_number_gv = generator_main
+            .module
+            .declare_data_in_func(data_number_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator_main.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let call_0 = function_builder.ins().call(func_get_number_ref, &[]);
+        let value_0 = function_builder.inst_results(call_0)[0];
+        let value_1 = function_builder
+            .ins()
+            .symbol_value(pointer_type, data_number_gv);
+        let value_2 = function_builder
+            .ins()
+            .load(types::I32, MemFlags::new(), value_1, 0);
+        let value_3 = function_builder.ins().iadd(value_0, value_2);
+        function_builder.ins().return_(&[value_3]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator_main
+            .define_function(func_main_id, func_main)
+            .unwrap();
+
+        // merge
+        let merged =
+            Generator::<ObjectModule>::merge("app", None, &[&generator_lib, &generator_main])
+                .unwrap();
+
+        // "get_number" is declared once, and it is called directly
+        let ir = merged.to_ir();
+        assert!(ir.contains("\nfunction 0 export get_number\n"));
+        assert!(ir.contains("\nfunction 1 export main\n"));
+        assert!(ir.contains("fn0 = colocated u0:0"));
+
+        let object_product = merged.finish().unwrap();
+        let module_binary = object_product.emit().unwrap();
+
+        let exit_code_opt =
+            run_executable_binary_and_get_exit_code(&module_binary, "test_merge_modules", false);
+        assert_eq!(exit_code_opt, Some(13));
+    }
+}
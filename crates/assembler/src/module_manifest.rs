@@ -0,0 +1,336 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Write;
+
+use cranelift_object::{
+    object::{read::File, Object, ObjectSection, SectionKind},
+    ObjectModule, ObjectProduct,
+};
+
+use crate::{
+    ast::{self, ValueType},
+    code_generator::Generator,
+};
+
+// The module manifest
+// -------------------
+//
+// The manifest describes the interface of a XiaoXuan module, i.e. the name,
+// the version and the exported functions and data, it is embedded in the
+// section `.anna.manifest` of the object file, so a loader can introspect
+// the compiled module (or the linked executable and shared library) without
+// the separate metadata files, e.g.
+//
+// ```rust
+// let manifest = ModuleManifest::from_module(&module, "1.0.0");
+// generator.embed_module_manifest(&manifest);
+// let binary = generator.finish()?.emit()?;
+//
+// // after linking
+// let manifests = read_module_manifests(&std::fs::read("app.elf")?)?;
+// ```
+//
+// the manifest is text, one item per line, e.g.
+//
+// ```text
+// name math
+// version 1.0.0
+// function add i32 i32 -> i32
+// function reset ->
+// data count
+// ```
+//
+// the section is not loadable (like `.comment`), the linker concatenates the
+// sections of all input objects, so each manifest is terminated by a '\0',
+// and `read_module_manifests()` returns the manifests in the order of the
+// input objects, check them by `$ readelf -p .anna.manifest anna.elf`.
+
+pub const MODULE_MANIFEST_SECTION_NAME: &str = ".anna.manifest";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedFunction {
+    pub name: String,
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleManifest {
+    pub name: String,
+    pub version: String,
+    pub functions: Vec<ExportedFunction>,
+
+    /// The names of the exported data.
+    pub data: Vec<String>,
+}
+
+impl ModuleManifest {
+    /// Collect the exported functions and data of the module.
+    pub fn from_module(module: &ast::Module, version: &str) -> Self {
+        ModuleManifest {
+            name: module.name.clone(),
+            version: version.to_owned(),
+            functions: module
+                .functions
+                .iter()
+                .filter(|function_node| function_node.export)
+                .map(|function_node| ExportedFunction {
+                    name: function_node.name.clone(),
+                    params: function_node
+                        .params
+                        .iter()
+                        .map(|local_node| local_node.value_type)
+                        .collect(),
+                    results: function_node.results.clone(),
+                })
+                .collect(),
+            data: module
+                .data
+                .iter()
+                .filter(|data_node| data_node.export)
+                .map(|data_node| data_node.name.clone())
+                .collect(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        writeln!(text, "name {}", self.name).unwrap();
+        writeln!(text, "version {}", self.version).unwrap();
+
+        for function in &self.functions {
+            write!(text, "function {}", function.name).unwrap();
+            for value_type in &function.params {
+                write!(text, " {}", value_type.name()).unwrap();
+            }
+            text.push_str(" ->");
+            for value_type in &function.results {
+                write!(text, " {}", value_type.name()).unwrap();
+            }
+            text.push('\n');
+        }
+
+        for name in &self.data {
+            writeln!(text, "data {}", name).unwrap();
+        }
+        text
+    }
+
+    /// Parse the text which is generated by `to_text()`.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut manifest = ModuleManifest::default();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let error = |message: &str| format!("{} at line {}", message, line_index + 1);
+
+            match (words.next(), words.next()) {
+                (Some("name"), Some(name)) => manifest.name = name.to_owned(),
+                (Some("version"), Some(version)) => manifest.version = version.to_owned(),
+                (Some("function"), Some(name)) => {
+                    let mut params = vec![];
+                    let mut results = vec![];
+                    let mut is_result = false;
+
+                    for word in words.by_ref() {
+                        if word == "->" && !is_result {
+                            is_result = true;
+                            continue;
+                        }
+                        let value_type = ValueType::from_name(word)
+                            .ok_or_else(|| error(&format!("unknown value type \"{}\"", word)))?;
+                        if is_result {
+                            results.push(value_type);
+                        } else {
+                            params.push(value_type);
+                        }
+                    }
+
+                    if !is_result {
+                        return Err(error("missing \"->\""));
+                    }
+
+                    manifest.functions.push(ExportedFunction {
+                        name: name.to_owned(),
+                        params,
+                        results,
+                    });
+                }
+                (Some("data"), Some(name)) => manifest.data.push(name.to_owned()),
+                (None, _) => continue,
+                _ => return Err(error("invalid manifest item")),
+            }
+
+            if words.next().is_some() {
+                return Err(error("unexpected trailing words"));
+            }
+        }
+
+        if manifest.name.is_empty() {
+            return Err("missing the module name".to_owned());
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl Generator<ObjectModule> {
+    /// Embed the manifest in the section `.anna.manifest` of the object file.
+    pub fn embed_module_manifest(&mut self, manifest: &ModuleManifest) {
+        let mut data = manifest.to_text().into_bytes();
+        data.push(0);
+
+        self.add_object_post_processor(move |product: &mut ObjectProduct| {
+            let object = &mut product.object;
+            let section_id = object.add_section(
+                vec![],
+                MODULE_MANIFEST_SECTION_NAME.as_bytes().to_vec(),
+                SectionKind::Other,
+            );
+            object.append_section_data(section_id, &data, 1);
+        });
+    }
+}
+
+/// Read the manifests in the object file or the linked binary, the binary
+/// without the section `.anna.manifest` has no manifests.
+pub fn read_module_manifests(binary: &[u8]) -> Result<Vec<ModuleManifest>, String> {
+    let file = File::parse(binary).map_err(|error| error.to_string())?;
+    let Some(section) = file.section_by_name(MODULE_MANIFEST_SECTION_NAME) else {
+        return Ok(vec![]);
+    };
+    let data = section.data().map_err(|error| error.to_string())?;
+
+    data.split(|byte| *byte == 0)
+        .filter(|text| !text.is_empty())
+        .map(|text| {
+            let text = std::str::from_utf8(text).map_err(|error| error.to_string())?;
+            ModuleManifest::from_text(text)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::ValueType,
+        code_generator::Generator,
+        linker::{link_executable, LinkerOptions},
+        lowering::assemble_module,
+        module_manifest::{read_module_manifests, ExportedFunction, ModuleManifest},
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    #[test]
+    fn test_module_manifest() {
+        let temp_folder = TempFolder::new("module_manifest");
+
+        let build = |source: &str, version: &str, file_name: &str| {
+            let module = parse_module(source).unwrap();
+            let manifest = ModuleManifest::from_module(&module, version);
+
+            let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+            assemble_module(&module, &mut generator).unwrap();
+            generator.embed_module_manifest(&manifest);
+
+            let object_file_path = temp_folder.file_path(file_name);
+            std::fs::write(
+                &object_file_path,
+                generator.finish().unwrap().emit().unwrap(),
+            )
+            .unwrap();
+            (manifest, object_file_path)
+        };
+
+        let (math_manifest, math_file_path) = build(
+            r#"
+            (module $math
+                (data $count export (read_write i32 0))
+                (function $square (param $x i32) (result i32)
+                    (code (mul_i32 (local_load $x) (local_load $x))))
+                (function $add export (param $a i32) (param $b i32) (result i32)
+                    (code (add_i32 (local_load $a) (local_load $b))))
+                (function $reset export (code)))
+            "#,
+            "1.2.0",
+            "math.o",
+        );
+
+        assert_eq!(
+            math_manifest,
+            ModuleManifest {
+                name: "math".to_owned(),
+                version: "1.2.0".to_owned(),
+                functions: vec![
+                    ExportedFunction {
+                        name: "add".to_owned(),
+                        params: vec![ValueType::I32, ValueType::I32],
+                        results: vec![ValueType::I32],
+                    },
+                    ExportedFunction {
+                        name: "reset".to_owned(),
+                        params: vec![],
+                        results: vec![],
+                    },
+                ],
+                data: vec!["count".to_owned()],
+            }
+        );
+        assert_eq!(
+            math_manifest.to_text(),
+            "\
+name math
+version 1.2.0
+function add i32 i32 -> i32
+function reset ->
+data count
+"
+        );
+        assert_eq!(
+            ModuleManifest::from_text(&math_manifest.to_text()).unwrap(),
+            math_manifest
+        );
+
+        // the object file
+        let math_binary = std::fs::read(&math_file_path).unwrap();
+        assert_eq!(
+            read_module_manifests(&math_binary).unwrap(),
+            vec![math_manifest.clone()]
+        );
+
+        // the linked executable contains the manifests of all objects
+        let (main_manifest, main_file_path) = build(
+            r#"
+            (module $main
+                (function $main export (result i32) (code (imm_i32 0))))
+            "#,
+            "0.1.0",
+            "main.o",
+        );
+
+        let exec_file_path = temp_folder.file_path("main.elf");
+        link_executable(
+            &[&main_file_path, &math_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+
+        let exec_binary = std::fs::read(&exec_file_path).unwrap();
+        assert_eq!(
+            read_module_manifests(&exec_binary).unwrap(),
+            vec![main_manifest, math_manifest]
+        );
+
+        assert!(ModuleManifest::from_text("name math\nfunction add i32 i8 -> i32").is_err());
+        assert!(ModuleManifest::from_text("version 1.0.0").is_err());
+    }
+}
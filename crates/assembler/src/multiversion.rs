@@ -0,0 +1,824 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    ir::{
+        condcodes::IntCC, types, AbiParam, Function, InstBuilder, MemFlags, Signature,
+        StackSlotData, StackSlotKind, UserFuncName, Value,
+    },
+    isa::{self, OwnedTargetIsa},
+    settings::Configurable,
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{DataDescription, FuncId, FuncOrDataId, Linkage, Module, ModuleError};
+
+use crate::{code_generator::Generator, validation::SymbolReferences};
+
+// The multi-versioned functions
+// -----------------------------
+//
+// The hot kernels (e.g. the checksums and the vector math) benefit from the
+// instructions of the newer CPUs, a multi-versioned function is compiled
+// several times with the different CPU feature sets (i.e. the ISA settings of
+// Cranelift), and the best version is selected at runtime, so one binary
+// runs on all CPUs of the target, e.g.
+//
+// ```clojure
+// (function $count_bits export (target_clones "avx2,bmi2" "popcnt") (param $x i64) (result i64)
+//     (code (count_ones_i64 (local_load $x))))
+// ```
+//
+// or `generator.set_function_versions(func_id, versions)` for the front ends,
+// the feature sets are listed from the most preferred, and the baseline
+// version (i.e. the ISA of the module) is always generated as the fallback.
+//
+// the function `count_bits` becomes a dispatcher which calls the selected
+// version through a pointer, e.g.
+//
+// ```text
+// count_bits.avx2_bmi2     ; the versions
+// count_bits.popcnt
+// count_bits.default
+// count_bits.dispatch      ; the pointer, it initially points to the resolver
+// count_bits.resolve       ; selects the version by `__anna_cpu_features()`,
+//                          ; updates the pointer, and calls the version
+// ```
+//
+// so the features are detected on the first call, rather than by the ELF
+// `ifunc` (which requires the dynamic linker, or the libc for the static
+// executables) or the constructors, it works in the JIT and the freestanding
+// executables. the concurrent first calls store the same pointer.
+//
+// `__anna_cpu_features()` returns the bits of `CpuFeature::bit()`, it reads the
+// CPUID (and the XCR0 for the AVX state which is enabled by the OS) on x86_64, and
+// `getauxval(AT_HWCAP)` on AArch64. note that the NEON (Advanced SIMD) is mandatory
+// on AArch64 and always used by Cranelift, so the NEON version is the baseline.
+//
+// ref:
+// - https://gcc.gnu.org/onlinedocs/gcc/Common-Function-Attributes.html#index-target_005fclones-function-attribute
+// - https://www.felixcloutier.com/x86/cpuid
+// - https://www.kernel.org/doc/html/latest/arch/arm64/elf_hwcaps.html
+
+/// The name of the function which detects the CPU features.
+pub const CPU_FEATURES_FUNCTION_NAME: &str = "__anna_cpu_features";
+
+const CPUID_FUNCTION_NAME: &str = "__anna_cpuid";
+const XGETBV_FUNCTION_NAME: &str = "__anna_xgetbv";
+
+/// The name of the baseline version.
+pub const DEFAULT_VERSION_NAME: &str = "default";
+
+/// `AT_HWCAP` of `getauxval()`.
+const AT_HWCAP: i64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuFeature {
+    // x86_64
+    Sse3,
+    Ssse3,
+    Sse41,
+    Sse42,
+    Popcnt,
+    Avx,
+    Avx2,
+    Fma,
+    Bmi1,
+    Bmi2,
+    Lzcnt,
+
+    // AArch64, the large system extensions (i.e. the atomic instructions)
+    Lse,
+}
+
+/// The register of the CPUID result which contains the feature bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CpuidWord {
+    /// `ECX` of the leaf 1.
+    Leaf1Ecx,
+
+    /// `EBX` of the leaf 7 (sub-leaf 0).
+    Leaf7Ebx,
+
+    /// `ECX` of the leaf 0x8000_0001.
+    ExtendedLeaf1Ecx,
+}
+
+const X86_FEATURES: [CpuFeature; 11] = [
+    CpuFeature::Sse3,
+    CpuFeature::Ssse3,
+    CpuFeature::Sse41,
+    CpuFeature::Sse42,
+    CpuFeature::Popcnt,
+    CpuFeature::Avx,
+    CpuFeature::Avx2,
+    CpuFeature::Fma,
+    CpuFeature::Bmi1,
+    CpuFeature::Bmi2,
+    CpuFeature::Lzcnt,
+];
+
+impl CpuFeature {
+    pub fn from_name(name: &str) -> Option<Self> {
+        X86_FEATURES
+            .iter()
+            .chain(&[CpuFeature::Lse])
+            .find(|feature| feature.name() == name)
+            .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuFeature::Sse3 => "sse3",
+            CpuFeature::Ssse3 => "ssse3",
+            CpuFeature::Sse41 => "sse4.1",
+            CpuFeature::Sse42 => "sse4.2",
+            CpuFeature::Popcnt => "popcnt",
+            CpuFeature::Avx => "avx",
+            CpuFeature::Avx2 => "avx2",
+            CpuFeature::Fma => "fma",
+            CpuFeature::Bmi1 => "bmi1",
+            CpuFeature::Bmi2 => "bmi2",
+            CpuFeature::Lzcnt => "lzcnt",
+            CpuFeature::Lse => "lse",
+        }
+    }
+
+    /// The name of the ISA (i.e. `TargetIsa::name()`) which has the feature.
+    pub fn isa_name(&self) -> &'static str {
+        match self {
+            CpuFeature::Lse => "aarch64",
+            _ => "x64",
+        }
+    }
+
+    /// The bit of the feature in the result of `__anna_cpu_features()`.
+    pub fn bit(&self) -> u64 {
+        1 << (*self as u32)
+    }
+
+    /// The ISA setting of Cranelift.
+    fn isa_flag(&self) -> &'static str {
+        match self {
+            CpuFeature::Sse3 => "has_sse3",
+            CpuFeature::Ssse3 => "has_ssse3",
+            CpuFeature::Sse41 => "has_sse41",
+            CpuFeature::Sse42 => "has_sse42",
+            CpuFeature::Popcnt => "has_popcnt",
+            CpuFeature::Avx => "has_avx",
+            CpuFeature::Avx2 => "has_avx2",
+            CpuFeature::Fma => "has_fma",
+            CpuFeature::Bmi1 => "has_bmi1",
+            CpuFeature::Bmi2 => "has_bmi2",
+            CpuFeature::Lzcnt => "has_lzcnt",
+            CpuFeature::Lse => "has_lse",
+        }
+    }
+
+    /// The features which Cranelift requires along with this feature,
+    /// e.g. the AVX2 instructions are only used if the AVX is enabled.
+    fn prerequisites(&self) -> &'static [CpuFeature] {
+        const SSE42: &[CpuFeature] = &[
+            CpuFeature::Sse3,
+            CpuFeature::Ssse3,
+            CpuFeature::Sse41,
+            CpuFeature::Sse42,
+        ];
+        const AVX: &[CpuFeature] = &[
+            CpuFeature::Sse3,
+            CpuFeature::Ssse3,
+            CpuFeature::Sse41,
+            CpuFeature::Sse42,
+            CpuFeature::Avx,
+        ];
+
+        match self {
+            CpuFeature::Ssse3 => &[CpuFeature::Sse3],
+            CpuFeature::Sse41 => &[CpuFeature::Sse3, CpuFeature::Ssse3],
+            CpuFeature::Sse42 | CpuFeature::Popcnt | CpuFeature::Avx => SSE42,
+            CpuFeature::Avx2 | CpuFeature::Fma => AVX,
+            _ => &[],
+        }
+    }
+
+    /// The location of the feature in the CPUID results, and whether the
+    /// feature requires the AVX state enabled by the OS.
+    fn get_cpuid_bit(&self) -> Option<(CpuidWord, u8, bool)> {
+        let bit = match self {
+            CpuFeature::Sse3 => (CpuidWord::Leaf1Ecx, 0, false),
+            CpuFeature::Ssse3 => (CpuidWord::Leaf1Ecx, 9, false),
+            CpuFeature::Sse41 => (CpuidWord::Leaf1Ecx, 19, false),
+            CpuFeature::Sse42 => (CpuidWord::Leaf1Ecx, 20, false),
+            CpuFeature::Popcnt => (CpuidWord::Leaf1Ecx, 23, false),
+            CpuFeature::Avx => (CpuidWord::Leaf1Ecx, 28, true),
+            CpuFeature::Fma => (CpuidWord::Leaf1Ecx, 12, true),
+            CpuFeature::Avx2 => (CpuidWord::Leaf7Ebx, 5, true),
+            CpuFeature::Bmi1 => (CpuidWord::Leaf7Ebx, 3, false),
+            CpuFeature::Bmi2 => (CpuidWord::Leaf7Ebx, 8, false),
+            CpuFeature::Lzcnt => (CpuidWord::ExtendedLeaf1Ecx, 5, false),
+            CpuFeature::Lse => return None,
+        };
+        Some(bit)
+    }
+}
+
+/// Get the mask of the features and their prerequisites, the version is
+/// selected if all bits of the mask are set.
+pub fn get_feature_mask(features: &[CpuFeature]) -> u64 {
+    features
+        .iter()
+        .flat_map(|feature| feature.prerequisites().iter().chain([feature]))
+        .fold(0, |mask, feature| mask | feature.bit())
+}
+
+/// Get the name of the version of the function, e.g. "count_bits.avx2_bmi2"
+/// and "count_bits.default".
+pub fn get_version_name(function_name: &str, features: &[CpuFeature]) -> String {
+    if features.is_empty() {
+        format!("{}.{}", function_name, DEFAULT_VERSION_NAME)
+    } else {
+        let names = features
+            .iter()
+            .map(|feature| feature.name().replace('.', ""))
+            .collect::<Vec<_>>();
+        format!("{}.{}", function_name, names.join("_"))
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Compile the function for each CPU feature set (the most preferred first)
+    /// and the baseline, the version is selected at runtime.
+    pub fn set_function_versions(&mut self, func_id: FuncId, versions: Vec<Vec<CpuFeature>>) {
+        self.function_versions.insert(func_id, versions);
+    }
+
+    /// Define the versions, the resolver and the dispatcher of the function.
+    pub(crate) fn define_function_versions(
+        &mut self,
+        func_id: FuncId,
+        func: Function,
+        versions: &[Vec<CpuFeature>],
+    ) -> Result<(), ModuleError> {
+        let function_name = self
+            .module
+            .declarations()
+            .get_function_decl(func_id)
+            .linkage_name(func_id)
+            .into_owned();
+        let signature = func.signature.clone();
+
+        // the versions, the baseline is the last one
+        let mut version_ids = vec![];
+        for features in versions.iter().map(Vec::as_slice).chain([&[][..]]) {
+            let version_id = self.module.declare_function(
+                &get_version_name(&function_name, features),
+                Linkage::Local,
+                &signature,
+            )?;
+
+            let mut func_version = func.clone();
+            func_version.name = UserFuncName::user(0, version_id.as_u32());
+
+            if features.is_empty() {
+                self.compile_function(version_id, func_version, None)?;
+            } else {
+                let isa = self.make_feature_isa(features)?;
+                self.compile_function(version_id, func_version, Some(&*isa))?;
+            }
+            version_ids.push((version_id, get_feature_mask(features)));
+        }
+
+        let cpu_features_id = self.get_cpu_features_function()?;
+        let pointer_type = self.module.isa().pointer_type();
+        let pointer_bytes = self.module.isa().pointer_bytes() as usize;
+
+        let resolver_id = self.module.declare_function(
+            &format!("{}.resolve", function_name),
+            Linkage::Local,
+            &signature,
+        )?;
+
+        // the pointer to the selected version
+        let dispatch_id = self.module.declare_data(
+            &format!("{}.dispatch", function_name),
+            Linkage::Local,
+            true,
+            false,
+        )?;
+        let mut data_description = DataDescription::new();
+        data_description.define(vec![0; pointer_bytes].into_boxed_slice());
+        data_description.set_align(pointer_bytes as u64);
+        let resolver_ref = self
+            .module
+            .declare_func_in_data(resolver_id, &mut data_description);
+        data_description.write_function_addr(0, resolver_ref);
+        self.module.define_data(dispatch_id, &data_description)?;
+        self.set_symbol_references(SymbolReferences::from_data(dispatch_id, &data_description));
+
+        // the resolver
+        let mut func_resolver = Function::with_name_signature(
+            UserFuncName::user(0, resolver_id.as_u32()),
+            signature.clone(),
+        );
+        let cpu_features_ref = self
+            .module
+            .declare_func_in_func(cpu_features_id, &mut func_resolver);
+        let version_refs = version_ids
+            .iter()
+            .map(|(version_id, mask)| {
+                (
+                    self.module
+                        .declare_func_in_func(*version_id, &mut func_resolver),
+                    *mask,
+                )
+            })
+            .collect::<Vec<_>>();
+        let dispatch_gv = self
+            .module
+            .declare_data_in_func(dispatch_id, &mut func_resolver);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_resolver, &mut self.function_builder_context);
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+        let params = function_builder.block_params(block).to_vec();
+
+        let call = function_builder.ins().call(cpu_features_ref, &[]);
+        let cpu_features = function_builder.inst_results(call)[0];
+
+        // the baseline is selected if no other version matches
+        let (baseline_ref, _) = version_refs.last().unwrap();
+        let mut target = function_builder
+            .ins()
+            .func_addr(pointer_type, *baseline_ref);
+        for (version_ref, mask) in version_refs.iter().rev().skip(1) {
+            let masked = function_builder.ins().band_imm(cpu_features, *mask as i64);
+            let matched = function_builder
+                .ins()
+                .icmp_imm(IntCC::Equal, masked, *mask as i64);
+            let address = function_builder.ins().func_addr(pointer_type, *version_ref);
+            target = function_builder.ins().select(matched, address, target);
+        }
+
+        let dispatch = function_builder
+            .ins()
+            .symbol_value(pointer_type, dispatch_gv);
+        function_builder
+            .ins()
+            .store(MemFlags::trusted(), target, dispatch, 0);
+        emit_forward_call(&mut function_builder, &signature, target, &params);
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+        self.compile_function(resolver_id, func_resolver, None)?;
+
+        // the dispatcher
+        let mut func_dispatcher = Function::with_name_signature(
+            UserFuncName::user(0, func_id.as_u32()),
+            signature.clone(),
+        );
+        let dispatch_gv = self
+            .module
+            .declare_data_in_func(dispatch_id, &mut func_dispatcher);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_dispatcher, &mut self.function_builder_context);
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+        let params = function_builder.block_params(block).to_vec();
+
+        let dispatch = function_builder
+            .ins()
+            .symbol_value(pointer_type, dispatch_gv);
+        let target = function_builder
+            .ins()
+            .load(pointer_type, MemFlags::trusted(), dispatch, 0);
+        emit_forward_call(&mut function_builder, &signature, target, &params);
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+        self.compile_function(func_id, func_dispatcher, None)
+    }
+
+    /// Build the ISA of the module with the features enabled.
+    fn make_feature_isa(&self, features: &[CpuFeature]) -> Result<OwnedTargetIsa, ModuleError> {
+        let isa = self.module.isa();
+        let mut isa_builder = isa::lookup(isa.triple().clone())
+            .map_err(|error| ModuleError::Backend(anyhow::anyhow!("{}", error)))?;
+
+        for value in isa.isa_flags() {
+            isa_builder
+                .set(value.name, &value.value_string())
+                .map_err(|error| ModuleError::Backend(anyhow::anyhow!("{}", error)))?;
+        }
+
+        for feature in features {
+            if feature.isa_name() != isa.name() {
+                return Err(ModuleError::Backend(anyhow::anyhow!(
+                    "the CPU feature \"{}\" is not supported by the target \"{}\"",
+                    feature.name(),
+                    isa.triple()
+                )));
+            }
+
+            for item in feature.prerequisites().iter().chain([feature]) {
+                isa_builder
+                    .enable(item.isa_flag())
+                    .map_err(|error| ModuleError::Backend(anyhow::anyhow!("{}", error)))?;
+            }
+        }
+
+        isa_builder
+            .finish(isa.flags().clone())
+            .map_err(|error| ModuleError::Backend(anyhow::anyhow!("{}", error)))
+    }
+
+    /// Get (or define) the function `__anna_cpu_features()`.
+    fn get_cpu_features_function(&mut self) -> Result<FuncId, ModuleError> {
+        if let Some(FuncOrDataId::Func(func_id)) = self.module.get_name(CPU_FEATURES_FUNCTION_NAME)
+        {
+            return Ok(func_id);
+        }
+
+        let mut signature = self.module.make_signature();
+        signature.returns.push(AbiParam::new(types::I64));
+        let func_id =
+            self.module
+                .declare_function(CPU_FEATURES_FUNCTION_NAME, Linkage::Local, &signature)?;
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), signature);
+
+        match self.module.isa().name() {
+            "x64" => self.build_x86_cpu_features_function(&mut func)?,
+            "aarch64" => self.build_aarch64_cpu_features_function(&mut func)?,
+            _ => {
+                return Err(ModuleError::Backend(anyhow::anyhow!(
+                    "the multi-versioned functions are not supported by the target \"{}\"",
+                    self.module.isa().triple()
+                )))
+            }
+        }
+
+        self.compile_function(func_id, func, None)?;
+        Ok(func_id)
+    }
+
+    fn build_x86_cpu_features_function(&mut self, func: &mut Function) -> Result<(), ModuleError> {
+        let pointer_type = self.module.isa().pointer_type();
+
+        // `fn __anna_cpuid(leaf: u32, registers: *mut [u32; 4])`, stores EAX, EBX, ECX and EDX.
+        let mut cpuid_signature = self.module.make_signature();
+        cpuid_signature.params.push(AbiParam::new(types::I32));
+        cpuid_signature.params.push(AbiParam::new(pointer_type));
+        let cpuid_id = self.define_function_from_bytes(
+            CPUID_FUNCTION_NAME,
+            &cpuid_signature,
+            &[
+                0x53, // push rbx
+                0x49, 0x89, 0xf0, // mov r8, rsi
+                0x89, 0xf8, // mov eax, edi
+                0x31, 0xc9, // xor ecx, ecx
+                0x0f, 0xa2, // cpuid
+                0x41, 0x89, 0x00, // mov [r8], eax
+                0x41, 0x89, 0x58, 0x04, // mov [r8+4], ebx
+                0x41, 0x89, 0x48, 0x08, // mov [r8+8], ecx
+                0x41, 0x89, 0x50, 0x0c, // mov [r8+12], edx
+                0x5b, // pop rbx
+                0xc3, // ret
+            ],
+            &[],
+            false,
+        )?;
+
+        // `fn __anna_xgetbv() -> u64`, reads XCR0.
+        let mut xgetbv_signature = self.module.make_signature();
+        xgetbv_signature.returns.push(AbiParam::new(types::I64));
+        let xgetbv_id = self.define_function_from_bytes(
+            XGETBV_FUNCTION_NAME,
+            &xgetbv_signature,
+            &[
+                0x31, 0xc9, // xor ecx, ecx
+                0x0f, 0x01, 0xd0, // xgetbv
+                0x48, 0xc1, 0xe2, 0x20, // shl rdx, 32
+                0x89, 0xc0, // mov eax, eax
+                0x48, 0x09, 0xd0, // or rax, rdx
+                0xc3, // ret
+            ],
+            &[],
+            false,
+        )?;
+
+        let cpuid_ref = self.module.declare_func_in_func(cpuid_id, func);
+        let xgetbv_ref = self.module.declare_func_in_func(xgetbv_id, func);
+
+        let mut function_builder = FunctionBuilder::new(func, &mut self.function_builder_context);
+        let registers_slot = function_builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            16,
+            2,
+        ));
+
+        let block_entry = function_builder.create_block();
+        let block_xgetbv = function_builder.create_block();
+        let block_features = function_builder.create_block();
+        let avx_state = function_builder.append_block_param(block_features, types::I32);
+
+        function_builder.switch_to_block(block_entry);
+        let registers = function_builder
+            .ins()
+            .stack_addr(pointer_type, registers_slot, 0);
+        let cpuid = |function_builder: &mut FunctionBuilder, leaf: u32, offset: i32| {
+            let value_leaf = function_builder.ins().iconst(types::I32, leaf as i64);
+            function_builder
+                .ins()
+                .call(cpuid_ref, &[value_leaf, registers]);
+            function_builder
+                .ins()
+                .load(types::I32, MemFlags::trusted(), registers, offset)
+        };
+
+        // the leaf 7 is not available on the old CPUs, whose results are
+        // the data of the highest leaf, and the extended leaf 0x8000_0001
+        // is always available on x86_64.
+        let max_leaf = cpuid(&mut function_builder, 0, 0);
+        let leaf1_ecx = cpuid(&mut function_builder, 1, 8);
+        let leaf7_ebx = cpuid(&mut function_builder, 7, 4);
+        let has_leaf7 =
+            function_builder
+                .ins()
+                .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, max_leaf, 7);
+        let zero = function_builder.ins().iconst(types::I32, 0);
+        let leaf7_ebx = function_builder.ins().select(has_leaf7, leaf7_ebx, zero);
+        let extended_leaf1_ecx = cpuid(&mut function_builder, 0x8000_0001, 8);
+
+        // the XCR0 is readable if OSXSAVE (bit 27) is set
+        let osxsave = function_builder.ins().band_imm(leaf1_ecx, 1 << 27);
+        function_builder
+            .ins()
+            .brif(osxsave, block_xgetbv, &[], block_features, &[zero]);
+
+        // the XMM (bit 1) and YMM (bit 2) states are enabled
+        function_builder.switch_to_block(block_xgetbv);
+        let call = function_builder.ins().call(xgetbv_ref, &[]);
+        let xcr0 = function_builder.inst_results(call)[0];
+        let states = function_builder.ins().band_imm(xcr0, 0b110);
+        let enabled = function_builder.ins().icmp_imm(IntCC::Equal, states, 0b110);
+        let enabled = function_builder.ins().uextend(types::I32, enabled);
+        function_builder.ins().jump(block_features, &[enabled]);
+
+        function_builder.switch_to_block(block_features);
+        let mut features = function_builder.ins().iconst(types::I64, 0);
+        for feature in X86_FEATURES {
+            let (word, bit, requires_avx_state) = feature.get_cpuid_bit().unwrap();
+            let value_word: Value = match word {
+                CpuidWord::Leaf1Ecx => leaf1_ecx,
+                CpuidWord::Leaf7Ebx => leaf7_ebx,
+                CpuidWord::ExtendedLeaf1Ecx => extended_leaf1_ecx,
+            };
+            let value = function_builder.ins().ushr_imm(value_word, bit as i64);
+            let mut value = function_builder.ins().band_imm(value, 1);
+            if requires_avx_state {
+                value = function_builder.ins().band(value, avx_state);
+            }
+            let value = function_builder.ins().uextend(types::I64, value);
+            let value = function_builder
+                .ins()
+                .ishl_imm(value, feature as u32 as i64);
+            features = function_builder.ins().bor(features, value);
+        }
+        function_builder.ins().return_(&[features]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+        Ok(())
+    }
+
+    fn build_aarch64_cpu_features_function(
+        &mut self,
+        func: &mut Function,
+    ) -> Result<(), ModuleError> {
+        let mut getauxval_signature = self.module.make_signature();
+        getauxval_signature.params.push(AbiParam::new(types::I64));
+        getauxval_signature.returns.push(AbiParam::new(types::I64));
+        let getauxval_id =
+            self.module
+                .declare_function("getauxval", Linkage::Import, &getauxval_signature)?;
+        let getauxval_ref = self.module.declare_func_in_func(getauxval_id, func);
+
+        let mut function_builder = FunctionBuilder::new(func, &mut self.function_builder_context);
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_type = function_builder.ins().iconst(types::I64, AT_HWCAP);
+        let call = function_builder.ins().call(getauxval_ref, &[value_type]);
+        let hwcap = function_builder.inst_results(call)[0];
+
+        // `HWCAP_ATOMICS` (bit 8)
+        let lse = function_builder.ins().ushr_imm(hwcap, 8);
+        let lse = function_builder.ins().band_imm(lse, 1);
+        let features = function_builder
+            .ins()
+            .ishl_imm(lse, CpuFeature::Lse as u32 as i64);
+        function_builder.ins().return_(&[features]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+        Ok(())
+    }
+}
+
+/// Call the target with the parameters of the function and return the results.
+fn emit_forward_call(
+    function_builder: &mut FunctionBuilder,
+    signature: &Signature,
+    target: Value,
+    params: &[Value],
+) {
+    let sig_ref = function_builder.import_signature(signature.clone());
+    let call = function_builder
+        .ins()
+        .call_indirect(sig_ref, target, params);
+    let results = function_builder.inst_results(call).to_vec();
+    function_builder.ins().return_(&results);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_jit::JITModule;
+    use cranelift_module::{FuncOrDataId, Module};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerOptions},
+        lowering::assemble_module,
+        multiversion::{
+            get_feature_mask, get_version_name, CpuFeature, CPU_FEATURES_FUNCTION_NAME,
+        },
+        object_dump::read_functions,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    const SOURCE: &str = r#"
+    (module $bits
+        (function $count_bits export (target_clones "avx2,bmi2" "popcnt")
+            (param $x i64) (result i64)
+            (code (count_ones_i64 (local_load $x))))
+        (function $main export (result i32)
+            (code (truncate_i64_to_i32 (call $count_bits (imm_i64 0x0f0f))))))
+    "#;
+
+    #[test]
+    fn test_multiversion() {
+        assert_eq!(CpuFeature::from_name("sse4.1"), Some(CpuFeature::Sse41));
+        assert_eq!(CpuFeature::from_name("avx3"), None);
+        assert_eq!(
+            get_feature_mask(&[CpuFeature::Popcnt]),
+            CpuFeature::Sse3.bit()
+                | CpuFeature::Ssse3.bit()
+                | CpuFeature::Sse41.bit()
+                | CpuFeature::Sse42.bit()
+                | CpuFeature::Popcnt.bit()
+        );
+        assert_eq!(
+            get_version_name("count_bits", &[CpuFeature::Avx2, CpuFeature::Bmi2]),
+            "count_bits.avx2_bmi2"
+        );
+        assert_eq!(get_version_name("count_bits", &[]), "count_bits.default");
+
+        let module = parse_module(SOURCE).unwrap();
+        let versions = [
+            vec![CpuFeature::Avx2, CpuFeature::Bmi2],
+            vec![CpuFeature::Popcnt],
+            vec![],
+        ];
+
+        // JIT, the dispatcher resolves the version on the first call
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let get_function = |name: &str| match generator.module.get_name(name) {
+            Some(FuncOrDataId::Func(func_id)) => generator.module.get_finalized_function(func_id),
+            _ => unreachable!(),
+        };
+        let Some(FuncOrDataId::Data(dispatch_id)) =
+            generator.module.get_name("count_bits.dispatch")
+        else {
+            unreachable!()
+        };
+        let dispatch_ptr = generator.module.get_finalized_data(dispatch_id).0 as *const *const u8;
+
+        let func_count_bits = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(
+                generator.module.get_finalized_function(
+                    assembled_module.get_function_id("count_bits").unwrap(),
+                ),
+            )
+        };
+        let func_cpu_features = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn() -> u64>(get_function(
+                CPU_FEATURES_FUNCTION_NAME,
+            ))
+        };
+
+        assert_eq!(unsafe { *dispatch_ptr }, get_function("count_bits.resolve"));
+        assert_eq!(func_count_bits(0x0f0f), 8);
+        assert_eq!(func_count_bits(-1), 64);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let detected_features = [
+                (CpuFeature::Sse3, is_x86_feature_detected!("sse3")),
+                (CpuFeature::Ssse3, is_x86_feature_detected!("ssse3")),
+                (CpuFeature::Sse41, is_x86_feature_detected!("sse4.1")),
+                (CpuFeature::Sse42, is_x86_feature_detected!("sse4.2")),
+                (CpuFeature::Popcnt, is_x86_feature_detected!("popcnt")),
+                (CpuFeature::Avx, is_x86_feature_detected!("avx")),
+                (CpuFeature::Avx2, is_x86_feature_detected!("avx2")),
+                (CpuFeature::Fma, is_x86_feature_detected!("fma")),
+                (CpuFeature::Bmi1, is_x86_feature_detected!("bmi1")),
+                (CpuFeature::Bmi2, is_x86_feature_detected!("bmi2")),
+                (CpuFeature::Lzcnt, is_x86_feature_detected!("lzcnt")),
+            ];
+            let features_mask = detected_features
+                .iter()
+                .filter(|(_, detected)| *detected)
+                .fold(0, |mask, (feature, _)| mask | feature.bit());
+            assert_eq!(func_cpu_features(), features_mask);
+
+            let expected_version = versions
+                .iter()
+                .find(|features| {
+                    let version_mask = get_feature_mask(features);
+                    version_mask & features_mask == version_mask
+                })
+                .unwrap();
+            assert_eq!(
+                unsafe { *dispatch_ptr },
+                get_function(&get_version_name("count_bits", expected_version))
+            );
+        }
+
+        // object file, each version is compiled with its own features
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        let binary = generator.finish().unwrap().emit().unwrap();
+
+        let functions = read_functions(&binary).unwrap();
+        let has_popcnt = |features: &[CpuFeature]| {
+            let name = get_version_name("count_bits", features);
+            let function = functions
+                .iter()
+                .find(|function| function.name == name)
+                .unwrap();
+            // popcnt r64, r/m64: F3 REX.W 0F B8 /r
+            function.machine_code.windows(4).any(|bytes| {
+                bytes[0] == 0xf3 && bytes[1] & 0xf8 == 0x48 && bytes[2..] == [0x0f, 0xb8]
+            })
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            // the AVX2 does not imply the POPCNT
+            assert!(!has_popcnt(&versions[0]));
+            assert!(has_popcnt(&versions[1]));
+            assert!(!has_popcnt(&versions[2]));
+        }
+
+        let temp_folder = TempFolder::new("multiversion");
+        let object_file_path = temp_folder.file_path("bits.o");
+        let exec_file_path = temp_folder.file_path("bits.elf");
+        std::fs::write(&object_file_path, binary).unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(8));
+
+        // the unknown features
+        assert_eq!(
+            parse_module(
+                r#"
+                (module $bits
+                    (function $test (target_clones "avx3") (code (nop))))
+                "#
+            )
+            .unwrap_err()
+            .message,
+            "unknown CPU feature \"avx3\""
+        );
+    }
+}
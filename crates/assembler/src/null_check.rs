@@ -0,0 +1,686 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Display;
+#[cfg(feature = "jit")]
+use std::sync::{
+    atomic::{AtomicPtr, Ordering},
+    Arc, Mutex, Once, OnceLock,
+};
+
+use cranelift_codegen::{
+    ir::{InstBuilder, MemFlags, Type, Value},
+    CompiledCode,
+};
+use cranelift_frontend::FunctionBuilder;
+#[cfg(feature = "jit")]
+use cranelift_jit::JITModule;
+use cranelift_module::{FuncId, Module};
+
+use crate::{
+    code_generator::Generator,
+    safety_check::{emit_null_check, TRAP_CODE_NULL_POINTER},
+    source_location::SourceLocation,
+};
+
+// The guard-page null checks
+// --------------------------
+//
+// The explicit null check (see `emit_null_check()`) costs a comparison and
+// a branch for each memory access. since the page zero of the address space
+// is never mapped (i.e. the guard page), a load/store through the null pointer
+// (with a small offset) faults anyway, so the check can be elided and the
+// fault is mapped back to the null-pointer dereference instead, e.g.
+//
+// ```clif
+// ;; explicit
+// block0(v0: i64):
+//     brif v0, block2, block1
+// block1 cold:
+//     trap user1
+// block2:
+//     v1 = load.i64 v0+8
+//
+// ;; guard page
+// block0(v0: i64):
+//     v1 = load.i64 user1 v0+8     ; faults with the trap code `user1`
+// ```
+//
+// the loads/stores are emitted by `emit_checked_load()` and `emit_checked_store()`
+// according to the mode of the generator, e.g.
+//
+// ```rust
+// generator.enable_guard_page_null_checks();
+// let null_check_mode = generator.null_check_mode;
+// // ...
+// let value_0 = emit_checked_load(&mut function_builder, null_check_mode, types::I64, value_ptr, 8);
+// ```
+//
+// the accesses whose offsets are out of the guard page (i.e. `offset >= GUARD_PAGE_SIZE`
+// or negative) still have the explicit checks, since they may land on the mapped pages.
+//
+// the offsets of both kinds of traps are collected when the functions are
+// compiled (see `Generator::null_check_sites`), in JIT mode, the faulting
+// address can be looked up by `lookup_null_dereference()`, or a signal handler
+// can be registered by `register_null_dereference_handler()`, which maps the
+// SIGSEGV (and SIGBUS) of the trapping loads/stores and the SIGILL of the
+// `trap` instructions (i.e. `ud2` on x86_64) to a `NullDereference`.
+//
+// the signal handler is installed once for the process, and the previous
+// actions (e.g. the stack overflow handler of the Rust runtime, or the handlers
+// of the host program) are saved, the faults which are not at the registered
+// sites are passed to them. the sites of a generator are registered until the
+// returned `NullDereferenceRegistration` is dropped.
+//
+// since locking is not async-signal-safe, the handler reads an immutable snapshot
+// of the registered sites through an atomic pointer, the snapshot is replaced
+// (under a mutex) when a generator is registered or unregistered.
+//
+// ref:
+// - https://docs.rs/cranelift-codegen/latest/cranelift_codegen/ir/struct.MemFlags.html#method.with_trap_code
+
+/// The size of the unmapped page zero, the accesses within it are guaranteed to fault.
+pub const GUARD_PAGE_SIZE: i32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NullCheckMode {
+    /// Check the pointer by a comparison and a branch before each access.
+    #[default]
+    Explicit,
+
+    /// Rely on the fault of the guard page.
+    GuardPage,
+}
+
+/// The code offsets of the null-pointer traps of a compiled function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NullCheckSites {
+    pub func_id: FuncId,
+
+    /// the offsets of the `trap` instructions (the explicit checks) and the
+    /// trapping loads/stores, relative to the start of the function, in ascending order.
+    pub code_offsets: Vec<u32>,
+}
+
+impl NullCheckSites {
+    /// Collect the null-pointer traps of the compiled function, returns `None`
+    /// if there is no trap.
+    pub(crate) fn new(
+        func_id: FuncId,
+        compiled_code: &CompiledCode,
+        entry_offset: u32,
+    ) -> Option<Self> {
+        let mut code_offsets = compiled_code
+            .buffer
+            .traps()
+            .iter()
+            .filter(|trap| trap.code == TRAP_CODE_NULL_POINTER)
+            .map(|trap| trap.offset + entry_offset)
+            .collect::<Vec<_>>();
+
+        if code_offsets.is_empty() {
+            None
+        } else {
+            code_offsets.sort_unstable();
+            Some(Self {
+                func_id,
+                code_offsets,
+            })
+        }
+    }
+}
+
+/// The language-level error of a fault at a null-pointer trap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NullDereference {
+    pub func_id: FuncId,
+    pub function_name: String,
+
+    /// the offset of the trap, relative to the start of the function.
+    pub code_offset: u32,
+    pub location: Option<SourceLocation>,
+}
+
+impl Display for NullDereference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "null pointer dereference in function \"{}\"",
+            self.function_name
+        )?;
+        match &self.location {
+            Some(location) => write!(f, " at line {}, column {}", location.line, location.column),
+            None => write!(f, " at offset 0x{:x}", self.code_offset),
+        }
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Elide the explicit null checks of `emit_checked_load()` and `emit_checked_store()`,
+    /// and rely on the fault of the guard page.
+    pub fn enable_guard_page_null_checks(&mut self) {
+        self.null_check_mode = NullCheckMode::GuardPage;
+    }
+
+    pub fn get_null_check_sites(&self, func_id: FuncId) -> Option<&NullCheckSites> {
+        self.null_check_sites
+            .iter()
+            .find(|item| item.func_id == func_id)
+    }
+}
+
+/// Get the flags of the access, the explicit check is emitted if the fault
+/// of the guard page can not be relied on.
+fn get_checked_flags(
+    function_builder: &mut FunctionBuilder,
+    null_check_mode: NullCheckMode,
+    address: Value,
+    offset: i32,
+) -> MemFlags {
+    if null_check_mode == NullCheckMode::GuardPage && (0..GUARD_PAGE_SIZE).contains(&offset) {
+        MemFlags::new().with_trap_code(Some(TRAP_CODE_NULL_POINTER))
+    } else {
+        emit_null_check(function_builder, address, TRAP_CODE_NULL_POINTER);
+        MemFlags::new()
+    }
+}
+
+/// Load a value of the `value_type` from `address + offset`, trap with
+/// `TRAP_CODE_NULL_POINTER` if the `address` is zero.
+pub fn emit_checked_load(
+    function_builder: &mut FunctionBuilder,
+    null_check_mode: NullCheckMode,
+    value_type: Type,
+    address: Value,
+    offset: i32,
+) -> Value {
+    let flags = get_checked_flags(function_builder, null_check_mode, address, offset);
+    function_builder
+        .ins()
+        .load(value_type, flags, address, offset)
+}
+
+/// Store the `value` to `address + offset`, trap with `TRAP_CODE_NULL_POINTER`
+/// if the `address` is zero.
+pub fn emit_checked_store(
+    function_builder: &mut FunctionBuilder,
+    null_check_mode: NullCheckMode,
+    value: Value,
+    address: Value,
+    offset: i32,
+) {
+    let flags = get_checked_flags(function_builder, null_check_mode, address, offset);
+    function_builder.ins().store(flags, value, address, offset);
+}
+
+/// The trap sites (i.e. the absolute addresses) of the registered functions
+/// and the handler, see `register_null_dereference_handler()`.
+#[cfg(feature = "jit")]
+struct RegisteredSites {
+    id: u64,
+    sites: Vec<(usize, NullDereference)>,
+    handler: fn(&NullDereference) -> !,
+}
+
+#[cfg(feature = "jit")]
+struct NullDereferenceRegistry {
+    next_id: u64,
+    entries: Vec<Arc<RegisteredSites>>,
+}
+
+#[cfg(feature = "jit")]
+impl NullDereferenceRegistry {
+    /// Replace the snapshot which is read by the signal handler.
+    fn publish(&self) {
+        let snapshot = Box::into_raw(Box::new(self.entries.clone()));
+        // the previous snapshot is leaked rather than freed, since the signal
+        // handler of another thread may be still reading it.
+        REGISTERED_SITES_SNAPSHOT.swap(snapshot, Ordering::AcqRel);
+    }
+}
+
+#[cfg(feature = "jit")]
+static NULL_DEREFERENCE_REGISTRY: Mutex<NullDereferenceRegistry> =
+    Mutex::new(NullDereferenceRegistry {
+        next_id: 0,
+        entries: vec![],
+    });
+
+/// The immutable snapshot of the registered sites, it is null before the
+/// first registration.
+#[cfg(feature = "jit")]
+static REGISTERED_SITES_SNAPSHOT: AtomicPtr<Vec<Arc<RegisteredSites>>> =
+    AtomicPtr::new(std::ptr::null_mut());
+
+#[cfg(feature = "jit")]
+static INSTALL_SIGNAL_HANDLER: Once = Once::new();
+
+/// The signals of the null-pointer traps, i.e. the faults of the trapping
+/// loads/stores and the `trap` instructions of the explicit checks.
+#[cfg(feature = "jit")]
+const HANDLED_SIGNALS: [libc::c_int; 3] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGILL];
+
+/// The actions of the handled signals before the handler is installed.
+#[cfg(feature = "jit")]
+static PREVIOUS_ACTIONS: OnceLock<[(libc::c_int, libc::sigaction); 3]> = OnceLock::new();
+
+/// The registered null-pointer traps of a generator, they are unregistered
+/// when it is dropped, so it should be dropped before the memory of the module
+/// is freed (i.e. `JITModule::free_memory()`).
+#[cfg(feature = "jit")]
+#[must_use = "the null-pointer traps are unregistered when it is dropped"]
+pub struct NullDereferenceRegistration {
+    id: u64,
+}
+
+#[cfg(feature = "jit")]
+impl Drop for NullDereferenceRegistration {
+    fn drop(&mut self) {
+        let mut registry = NULL_DEREFERENCE_REGISTRY.lock().unwrap();
+        registry.entries.retain(|entry| entry.id != self.id);
+        registry.publish();
+    }
+}
+
+#[cfg(feature = "jit")]
+impl Generator<JITModule> {
+    /// Find the null-pointer trap of the given faulting address.
+    ///
+    /// Note that it is only available after 'module.finalize_definitions()'.
+    pub fn lookup_null_dereference(&self, address: usize) -> Option<NullDereference> {
+        self.null_check_sites.iter().find_map(|sites| {
+            let start = self.module.get_finalized_function(sites.func_id) as usize;
+            let code_offset = u32::try_from(address.checked_sub(start)?).ok()?;
+            sites
+                .code_offsets
+                .binary_search(&code_offset)
+                .ok()
+                .map(|_| self.get_null_dereference(sites.func_id, code_offset))
+        })
+    }
+
+    /// Register the null-pointer traps of the finalized functions, and install
+    /// the signal handler of SIGSEGV, SIGBUS and SIGILL (only once for the process).
+    ///
+    /// The `handler` is called (in the signal context) with the error when
+    /// the fault is at a registered trap, it should terminate the process
+    /// (e.g. by `libc::_exit()`) since the faulting instruction can not be resumed,
+    /// the other faults are passed to the previous actions of the signals.
+    ///
+    /// The traps are unregistered when the returned registration is dropped.
+    ///
+    /// Note that it is only available after 'module.finalize_definitions()'.
+    pub fn register_null_dereference_handler(
+        &self,
+        handler: fn(&NullDereference) -> !,
+    ) -> NullDereferenceRegistration {
+        let mut sites = vec![];
+        for item in &self.null_check_sites {
+            let start = self.module.get_finalized_function(item.func_id) as usize;
+            for code_offset in &item.code_offsets {
+                sites.push((
+                    start + *code_offset as usize,
+                    self.get_null_dereference(item.func_id, *code_offset),
+                ));
+            }
+        }
+
+        let id = {
+            let mut registry = NULL_DEREFERENCE_REGISTRY.lock().unwrap();
+            let id = registry.next_id;
+            registry.next_id += 1;
+            registry
+                .entries
+                .push(Arc::new(RegisteredSites { id, sites, handler }));
+            registry.publish();
+            id
+        };
+
+        INSTALL_SIGNAL_HANDLER.call_once(|| unsafe {
+            // save the previous actions before installing, so they are available
+            // once the handler is called.
+            let mut previous_actions = HANDLED_SIGNALS.map(|signal| (signal, std::mem::zeroed()));
+            for (signal, previous_action) in &mut previous_actions {
+                libc::sigaction(*signal, std::ptr::null(), previous_action);
+            }
+            let _ = PREVIOUS_ACTIONS.set(previous_actions);
+
+            // the handler may run on the alternate signal stack, e.g. when
+            // the stack overflows.
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_fault as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            for signal in HANDLED_SIGNALS {
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        });
+
+        NullDereferenceRegistration { id }
+    }
+
+    fn get_null_dereference(&self, func_id: FuncId, code_offset: u32) -> NullDereference {
+        let function_name = self
+            .module
+            .declarations()
+            .get_function_decl(func_id)
+            .linkage_name(func_id)
+            .into_owned();
+        NullDereference {
+            func_id,
+            function_name,
+            code_offset,
+            location: self.source_map.lookup(func_id, code_offset).copied(),
+        }
+    }
+}
+
+#[cfg(feature = "jit")]
+extern "C" fn handle_fault(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    // the snapshot is never freed once it is published, see
+    // `NullDereferenceRegistry::publish()`.
+    let snapshot = REGISTERED_SITES_SNAPSHOT.load(Ordering::Acquire);
+    if let (Some(program_counter), Some(entries)) =
+        (unsafe { get_program_counter(context) }, unsafe {
+            snapshot.as_ref()
+        })
+    {
+        for entry in entries {
+            if let Some((_, null_dereference)) = entry
+                .sites
+                .iter()
+                .find(|(address, _)| *address == program_counter)
+            {
+                (entry.handler)(null_dereference);
+            }
+        }
+    }
+
+    unsafe { call_previous_action(signal, info, context) }
+}
+
+/// Pass the fault which is not a null-pointer trap to the previous action,
+/// if it is the default action (or ignored), the instruction faults again with
+/// the default action.
+#[cfg(feature = "jit")]
+unsafe fn call_previous_action(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    let opt_previous_action = PREVIOUS_ACTIONS
+        .get()
+        .and_then(|actions| actions.iter().find(|(item, _)| *item == signal))
+        .map(|(_, action)| *action);
+
+    match opt_previous_action {
+        Some(action)
+            if action.sa_sigaction != libc::SIG_DFL && action.sa_sigaction != libc::SIG_IGN =>
+        {
+            if action.sa_flags & libc::SA_SIGINFO != 0 {
+                let handler = std::mem::transmute::<
+                    libc::sighandler_t,
+                    extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void),
+                >(action.sa_sigaction);
+                handler(signal, info, context);
+            } else {
+                let handler = std::mem::transmute::<libc::sighandler_t, extern "C" fn(libc::c_int)>(
+                    action.sa_sigaction,
+                );
+                handler(signal);
+            }
+        }
+        _ => {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+}
+
+#[cfg(all(feature = "jit", target_os = "linux", target_arch = "x86_64"))]
+unsafe fn get_program_counter(context: *mut libc::c_void) -> Option<usize> {
+    let context = &*(context as *const libc::ucontext_t);
+    Some(context.uc_mcontext.gregs[libc::REG_RIP as usize] as usize)
+}
+
+#[cfg(all(feature = "jit", target_os = "linux", target_arch = "aarch64"))]
+unsafe fn get_program_counter(context: *mut libc::c_void) -> Option<usize> {
+    let context = &*(context as *const libc::ucontext_t);
+    Some(context.uc_mcontext.pc as usize)
+}
+
+#[cfg(all(
+    feature = "jit",
+    not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))
+))]
+unsafe fn get_program_counter(_context: *mut libc::c_void) -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{FuncId, Linkage, Module};
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        null_check::{emit_checked_load, NullDereference},
+    };
+
+    // ```rust
+    // fn NAME(ptr: *const i64) -> i64 {
+    //     *((ptr as usize + OFFSET) as *const i64)
+    // }
+    // ```
+    fn define_load_function(
+        generator: &mut Generator<JITModule>,
+        name: &str,
+        offset: i32,
+    ) -> FuncId {
+        let null_check_mode = generator.null_check_mode;
+
+        let mut func_sig = generator.module.make_signature();
+        func_sig.params.push(AbiParam::new(types::I64));
+        func_sig.returns.push(AbiParam::new(types::I64));
+
+        let func_id = generator
+            .module
+            .declare_function(name, Linkage::Local, &func_sig)
+            .unwrap();
+
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), func_sig);
+        let mut function_builder =
+            FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_ptr = function_builder.block_params(block)[0];
+        let value_0 = emit_checked_load(
+            &mut function_builder,
+            null_check_mode,
+            types::I64,
+            value_ptr,
+            offset,
+        );
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_id, func).unwrap();
+        func_id
+    }
+
+    fn exit_on_null_dereference(null_dereference: &NullDereference) -> ! {
+        let exit_code = if null_dereference.function_name == "get_value" {
+            21
+        } else {
+            22
+        };
+        unsafe { libc::_exit(exit_code) }
+    }
+
+    extern "C" fn exit_on_fault(_signal: libc::c_int) {
+        unsafe { libc::_exit(23) }
+    }
+
+    /// Run the function in a child process, returns the exit code, or the
+    /// negative signal number if the child is killed by a signal.
+    fn run_in_child_process(f: impl FnOnce()) -> i32 {
+        unsafe {
+            let pid = libc::fork();
+            if pid == 0 {
+                f();
+                libc::_exit(0);
+            }
+
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+            if libc::WIFEXITED(status) {
+                libc::WEXITSTATUS(status)
+            } else {
+                -libc::WTERMSIG(status)
+            }
+        }
+    }
+
+    #[test]
+    fn test_guard_page_null_checks() {
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        generator.enable_guard_page_null_checks();
+
+        let func_get_value_id = define_load_function(&mut generator, "get_value", 8);
+        let func_get_far_value_id = define_load_function(&mut generator, "get_far_value", 8192);
+
+        // the check of the far access is not elided
+        let clif = generator.dump_clif();
+        assert!(clif.contains("load.i64 user1 v0+8\n"));
+        assert_eq!(clif.matches("trap user1").count(), 1);
+
+        assert_eq!(
+            generator
+                .get_null_check_sites(func_get_value_id)
+                .unwrap()
+                .code_offsets
+                .len(),
+            1
+        );
+
+        generator.module.finalize_definitions().unwrap();
+
+        let func_get_value_ptr = generator.module.get_finalized_function(func_get_value_id);
+        let func_get_value: extern "C" fn(*const i64) -> i64 =
+            unsafe { std::mem::transmute(func_get_value_ptr) };
+        let func_get_far_value_ptr = generator
+            .module
+            .get_finalized_function(func_get_far_value_id);
+        let func_get_far_value: extern "C" fn(*const i64) -> i64 =
+            unsafe { std::mem::transmute(func_get_far_value_ptr) };
+
+        let mut numbers = vec![0i64; 1025];
+        numbers[1] = 11;
+        numbers[1024] = 13;
+        assert_eq!(func_get_value(numbers.as_ptr()), 11);
+        assert_eq!(func_get_far_value(numbers.as_ptr()), 13);
+
+        // map the address of the trap back to the function
+        let code_offset = generator
+            .get_null_check_sites(func_get_value_id)
+            .unwrap()
+            .code_offsets[0];
+        let null_dereference = generator
+            .lookup_null_dereference(func_get_value_ptr as usize + code_offset as usize)
+            .unwrap();
+        assert_eq!(null_dereference.func_id, func_get_value_id);
+        assert_eq!(
+            null_dereference.to_string(),
+            format!(
+                "null pointer dereference in function \"get_value\" at offset 0x{:x}",
+                code_offset
+            )
+        );
+        assert_eq!(
+            generator.lookup_null_dereference(func_get_value_ptr as usize),
+            None
+        );
+
+        // the faults which are not at the traps are passed to the previous handler
+        assert_eq!(
+            run_in_child_process(|| unsafe {
+                libc::signal(
+                    libc::SIGSEGV,
+                    exit_on_fault as *const () as libc::sighandler_t,
+                );
+                let _registration =
+                    generator.register_null_dereference_handler(exit_on_null_dereference);
+                libc::raise(libc::SIGSEGV);
+            }),
+            23
+        );
+
+        // dereference the null pointer in a child process, the fault is
+        // caught by the registered handler.
+        let registration = generator.register_null_dereference_handler(exit_on_null_dereference);
+        assert_eq!(
+            run_in_child_process(|| {
+                func_get_value(std::ptr::null());
+            }),
+            21
+        );
+
+        // the far access has the explicit check, which traps by an illegal
+        // instruction rather than a segmentation fault.
+        assert_eq!(
+            run_in_child_process(|| {
+                func_get_far_value(std::ptr::null());
+            }),
+            22
+        );
+
+        // the fault takes the previous action after unregistering
+        drop(registration);
+        assert_eq!(
+            run_in_child_process(|| {
+                func_get_value(std::ptr::null());
+            }),
+            -libc::SIGSEGV
+        );
+
+        // the traps of the explicit checks
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let func_get_value_id = define_load_function(&mut generator, "get_value", 8);
+        assert!(generator.dump_clif().contains("trap user1"));
+        generator.module.finalize_definitions().unwrap();
+
+        let func_get_value_ptr = generator.module.get_finalized_function(func_get_value_id);
+        let func_get_value: extern "C" fn(*const i64) -> i64 =
+            unsafe { std::mem::transmute(func_get_value_ptr) };
+        assert_eq!(func_get_value(numbers.as_ptr()), 11);
+
+        let _registration = generator.register_null_dereference_handler(exit_on_null_dereference);
+        assert_eq!(
+            run_in_child_process(|| {
+                func_get_value(std::ptr::null());
+            }),
+            21
+        );
+    }
+}
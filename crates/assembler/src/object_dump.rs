@@ -0,0 +1,457 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Write;
+
+use cranelift_object::object::{
+    read::File, Object, ObjectKind, ObjectSection, ObjectSymbol, RelocationKind, RelocationTarget,
+    SectionIndex, SymbolKind as ObjectSymbolKind,
+};
+
+use crate::disassembly::{decode_machine_code, MachineInstruction, OperandAddress};
+
+// The object dump
+// ---------------
+//
+// Dump the functions of an object file (or a linked executable), it is
+// similar to `$ objdump -d -r anna.o`, e.g.
+//
+// ```text
+// function main (section: .text, address: 0xc, size: 36 bytes)
+//   0000000c  55                             pushq   %rbp
+//   0000000d  48 89 e5                       movq    %rsp, %rbp
+//   00000010  48 8b 3d 00 00 00 00           movq    message@GOTPCREL(%rip), %rdi
+//   00000017  4c 8b 05 00 00 00 00           movq    puts@GOTPCREL(%rip), %r8
+//   0000001e  41 ff d0                       callq   *%r8
+//   00000021  bf 01 00 00 00                 movl    $1, %edi
+//   00000026  e8 00 00 00 00                 callq   inc
+//   ...
+// ```
+//
+// - the machine code is decoded into the instructions by the disassembler
+//   (see `decode_machine_code()` in `disassembly.rs`).
+// - the references of the instructions (i.e. the relocations, which are the
+//   call targets and the data addresses) are shown as the symbols in the
+//   operands, the references which can not be shown in the operands (e.g. the
+//   TLS relocations) are listed after the instructions, e.g.
+//   "; symbol counter + 4".
+// - the branch targets without relocations (e.g. the jumps inside the function,
+//   and the calls of the linked executable) are resolved by the symbol table,
+//   e.g. "main+0x1c".
+// - the architectures which are not supported by the disassembler are listed
+//   in rows of 16 bytes of the machine code, with the references of each row
+//   below it.
+// - the imported symbols are listed as "symbol" unless they are called through
+//   the PLT, since their kinds are unknown.
+
+/// The number of bytes in a row of the machine code.
+const ROW_SIZE: usize = 16;
+
+/// The width of the machine code column of the instructions (in bytes),
+/// the longer instructions shift the text.
+const INSTRUCTION_BYTES_WIDTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceKind {
+    /// A call target or a function address.
+    Function,
+    Data,
+
+    /// The imported symbol which is referred through the GOT, the object file
+    /// does not record whether it is a function or data.
+    Unknown,
+}
+
+/// The reference from the machine code to a symbol, i.e. a relocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// The address of the relocated bytes, in the same address space as
+    /// the function address.
+    pub address: u64,
+    pub kind: ReferenceKind,
+    pub target: String,
+    pub addend: i64,
+    pub relocation_kind: RelocationKind,
+
+    /// The target is shown as the operand of the instruction.
+    pub is_symbolized: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDump {
+    pub name: String,
+    pub section: String,
+
+    /// The offset in the section for the object file, or the virtual address
+    /// for the executable.
+    pub address: u64,
+    pub machine_code: Vec<u8>,
+    pub references: Vec<Reference>,
+
+    /// The decoded instructions, it is empty if the architecture is not
+    /// supported by the disassembler.
+    pub instructions: Vec<MachineInstruction>,
+}
+
+/// A defined symbol of the object file which the addresses are resolved to.
+struct DefinedSymbol {
+    section_index: SectionIndex,
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+/// Read the defined functions of the object file, in the order of address.
+pub fn read_functions(object_binary: &[u8]) -> Result<Vec<FunctionDump>, String> {
+    let file = File::parse(object_binary)
+        .map_err(|error| format!("failed to read the object file: {}", error))?;
+
+    let defined_symbols = file
+        .symbols()
+        .filter(|symbol| {
+            matches!(
+                symbol.kind(),
+                ObjectSymbolKind::Text | ObjectSymbolKind::Data
+            ) && !symbol.is_undefined()
+        })
+        .filter_map(|symbol| {
+            Some(DefinedSymbol {
+                section_index: symbol.section_index()?,
+                address: symbol.address(),
+                size: symbol.size(),
+                name: symbol
+                    .name()
+                    .ok()
+                    .filter(|name| !name.is_empty())?
+                    .to_owned(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut functions = vec![];
+    for symbol in file.symbols() {
+        if symbol.kind() != ObjectSymbolKind::Text || symbol.is_undefined() || symbol.size() == 0 {
+            continue;
+        }
+        let (Ok(name), Some(section_index)) = (symbol.name(), symbol.section_index()) else {
+            continue;
+        };
+        let section = file
+            .section_by_index(section_index)
+            .map_err(|error| format!("failed to read the section of \"{}\": {}", name, error))?;
+        let data = section
+            .data()
+            .map_err(|error| format!("failed to read the section of \"{}\": {}", name, error))?;
+
+        let start = (symbol.address() - section.address()) as usize;
+        let end = start + symbol.size() as usize;
+        let Some(machine_code) = data.get(start..end) else {
+            return Err(format!("the size of function \"{}\" is out of range", name));
+        };
+
+        let mut references = vec![];
+        for (offset, relocation) in section.relocations() {
+            let address = section.address() + offset;
+            if address < symbol.address() || address >= symbol.address() + symbol.size() {
+                continue;
+            }
+
+            let (target, target_kind) = match relocation.target() {
+                RelocationTarget::Symbol(symbol_index) => {
+                    let Ok(target_symbol) = file.symbol_by_index(symbol_index) else {
+                        continue;
+                    };
+                    let target_name = match target_symbol.kind() {
+                        // the (local) section symbols are referred by the addends
+                        ObjectSymbolKind::Section => target_symbol
+                            .section_index()
+                            .and_then(|index| file.section_by_index(index).ok())
+                            .and_then(|section| section.name().ok().map(|name| name.to_owned())),
+                        _ => target_symbol.name().ok().map(|name| name.to_owned()),
+                    };
+                    (target_name.unwrap_or_default(), target_symbol.kind())
+                }
+                RelocationTarget::Section(section_index) => (
+                    file.section_by_index(section_index)
+                        .ok()
+                        .and_then(|section| section.name().ok().map(|name| name.to_owned()))
+                        .unwrap_or_default(),
+                    ObjectSymbolKind::Section,
+                ),
+                _ => continue,
+            };
+
+            let kind = match target_kind {
+                _ if relocation.kind() == RelocationKind::PltRelative => ReferenceKind::Function,
+                ObjectSymbolKind::Text => ReferenceKind::Function,
+                ObjectSymbolKind::Unknown => ReferenceKind::Unknown,
+                _ => ReferenceKind::Data,
+            };
+
+            references.push(Reference {
+                address,
+                kind,
+                target,
+                addend: relocation.addend(),
+                relocation_kind: relocation.kind(),
+                is_symbolized: false,
+            });
+        }
+        references.sort_by_key(|reference| reference.address);
+
+        // the sections of the object file share the same addresses (i.e. from 0),
+        // so only the symbols of the same section are resolved.
+        let is_relocatable = file.kind() == ObjectKind::Relocatable;
+        let mut symbolize = |operand: &OperandAddress| {
+            let instruction_end = operand.instruction_address + operand.instruction_size as u64;
+
+            if let Some(reference) = references.iter_mut().find(|reference| {
+                !reference.is_symbolized
+                    && (operand.instruction_address..instruction_end).contains(&reference.address)
+            }) {
+                let text = match (reference.relocation_kind, operand.is_immediate) {
+                    (RelocationKind::Relative | RelocationKind::PltRelative, false) => {
+                        // the addend contains the distance from the relocated bytes
+                        // to the end of the instruction, i.e. the PC.
+                        let offset =
+                            reference.addend + (instruction_end - reference.address) as i64;
+                        format_symbol(&reference.target, offset)
+                    }
+                    (RelocationKind::GotRelative, false) => {
+                        format!("{}@GOTPCREL", reference.target)
+                    }
+                    (RelocationKind::Absolute, true) => {
+                        format_symbol(&reference.target, reference.addend)
+                    }
+                    _ => return None,
+                };
+                reference.is_symbolized = true;
+                return Some(text);
+            }
+
+            if operand.is_immediate {
+                return None;
+            }
+            defined_symbols
+                .iter()
+                .filter(|defined_symbol| {
+                    !is_relocatable || defined_symbol.section_index == section_index
+                })
+                .find(|defined_symbol| {
+                    operand.address >= defined_symbol.address
+                        && operand.address < defined_symbol.address + defined_symbol.size.max(1)
+                })
+                .map(|defined_symbol| {
+                    format_symbol(
+                        &defined_symbol.name,
+                        (operand.address - defined_symbol.address) as i64,
+                    )
+                })
+        };
+
+        let instructions = decode_machine_code(
+            file.architecture(),
+            machine_code,
+            symbol.address(),
+            &mut symbolize,
+        )
+        .unwrap_or_default();
+
+        functions.push(FunctionDump {
+            name: name.to_owned(),
+            section: section.name().unwrap_or_default().to_owned(),
+            address: symbol.address(),
+            machine_code: machine_code.to_vec(),
+            references,
+            instructions,
+        });
+    }
+
+    functions.sort_by_key(|function| (function.section.clone(), function.address));
+    Ok(functions)
+}
+
+/// Format the symbol and the offset as the GAS syntax, e.g. "inc", "message+0x4".
+fn format_symbol(name: &str, offset: i64) -> String {
+    match offset {
+        0 => name.to_owned(),
+        offset if offset < 0 => format!("{}-0x{:x}", name, offset.unsigned_abs()),
+        offset => format!("{}+0x{:x}", name, offset),
+    }
+}
+
+/// Format the functions as the dump text.
+pub fn format_functions(functions: &[FunctionDump]) -> String {
+    let mut text = String::new();
+
+    for function in functions {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        writeln!(
+            text,
+            "function {} (section: {}, address: 0x{:x}, size: {} bytes)",
+            function.name,
+            function.section,
+            function.address,
+            function.machine_code.len()
+        )
+        .unwrap();
+
+        if function.instructions.is_empty() {
+            write_rows(&mut text, function);
+        } else {
+            write_instructions(&mut text, function);
+        }
+    }
+
+    text
+}
+
+fn write_instructions(text: &mut String, function: &FunctionDump) {
+    for instruction in &function.instructions {
+        let start = (instruction.address - function.address) as usize;
+        let bytes = function.machine_code[start..start + instruction.size]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(
+            text,
+            "  {:08x}  {:<width$}  {}",
+            instruction.address,
+            bytes,
+            instruction.text,
+            width = INSTRUCTION_BYTES_WIDTH * 3 - 1
+        )
+        .unwrap();
+
+        let instruction_end = instruction.address + instruction.size as u64;
+        for reference in function.references.iter().filter(|reference| {
+            !reference.is_symbolized
+                && reference.address >= instruction.address
+                && reference.address < instruction_end
+        }) {
+            write!(text, "  ; {}", format_reference(reference)).unwrap();
+        }
+        text.push('\n');
+    }
+}
+
+fn write_rows(text: &mut String, function: &FunctionDump) {
+    for (index, row) in function.machine_code.chunks(ROW_SIZE).enumerate() {
+        let row_address = function.address + (index * ROW_SIZE) as u64;
+        let bytes = row
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(text, "  {:08x}  {}", row_address, bytes).unwrap();
+
+        for reference in function.references.iter().filter(|reference| {
+            reference.address >= row_address && reference.address < row_address + ROW_SIZE as u64
+        }) {
+            writeln!(
+                text,
+                "              ; {:08x}: {}",
+                reference.address,
+                format_reference(reference)
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Format the reference, e.g. "data count - 4".
+fn format_reference(reference: &Reference) -> String {
+    let kind = match reference.kind {
+        ReferenceKind::Function => "function",
+        ReferenceKind::Data => "data",
+        ReferenceKind::Unknown => "symbol",
+    };
+    let addend = match reference.addend {
+        0 => String::new(),
+        addend if addend < 0 => format!(" - {}", -addend),
+        addend => format!(" + {}", addend),
+    };
+    format!("{} {}{}", kind, reference.target, addend)
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        lowering::assemble_module,
+        object_dump::{format_functions, read_functions, ReferenceKind},
+        parser::parse_module,
+    };
+
+    #[test]
+    fn test_object_dump() {
+        let source = r#"
+        (module $app
+            (import (function $puts (param i64) (result i32)))
+            (data $message (read_only bytes "hello\0"))
+            (function $inc (param $a i32) (result i32)
+                (code (add_i32 (local_load $a) (imm_i32 1))))
+            (function $main export (result i32)
+                (code
+                    (call $puts (host_addr_data $message))
+                    (call $inc (imm_i32 1)))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        let functions = read_functions(&module_binary).unwrap();
+        assert_eq!(
+            functions
+                .iter()
+                .map(|function| function.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["inc", "main"]
+        );
+        assert!(functions[0].references.is_empty());
+
+        let references = functions[1]
+            .references
+            .iter()
+            .map(|reference| (reference.kind, reference.target.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            references,
+            vec![
+                (ReferenceKind::Data, "message"),
+                (ReferenceKind::Unknown, "puts"),
+                (ReferenceKind::Function, "inc"),
+            ]
+        );
+
+        let text = format_functions(&functions);
+        assert!(text.starts_with("function inc (section: .text, address: 0x0, size: "));
+        assert!(text.contains("  55                             pushq   %rbp\n"));
+        assert!(text.contains("  movq    message@GOTPCREL(%rip), %rdi\n"));
+        assert!(text.contains("  movq    puts@GOTPCREL(%rip), %r8\n"));
+        assert!(text.contains("  e8 00 00 00 00                 callq   inc\n"));
+        assert!(functions[1]
+            .references
+            .iter()
+            .all(|reference| reference.is_symbolized));
+
+        // the architecture which is not supported by the disassembler
+        let mut functions = functions;
+        functions[1].instructions.clear();
+        let text = format_functions(&functions);
+        assert!(text.contains(": function inc - 4\n"));
+
+        assert!(read_functions(b"not an object file").is_err());
+    }
+}
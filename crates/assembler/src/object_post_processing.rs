@@ -0,0 +1,119 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_object::{ObjectModule, ObjectProduct};
+
+use crate::code_generator::Generator;
+
+// The object post-processing
+// --------------------------
+//
+// The embedders can attach their own metadata (e.g. a serialized module
+// manifest, a custom ELF note, or the marker symbols) to the object file
+// without parsing and rewriting the emitted bytes, by registering the
+// post-processors which receive the object (of the `object` crate) before
+// it is emitted, e.g.
+//
+// ```rust
+// generator.add_object_post_processor(move |product: &mut ObjectProduct| {
+//     let object = &mut product.object;
+//     let section_id = object.add_section(vec![], b".anna.manifest".to_vec(), SectionKind::ReadOnlyData);
+//     object.append_section_data(section_id, &manifest, 1);
+// });
+//
+// let binary = generator.finish()?.emit()?;
+// ```
+//
+// the `ObjectProduct` also maps the `FuncId` and `DataId` to the symbols
+// of the object (i.e. `product.function_symbol(func_id)`), so the
+// post-processors can refer to the functions and data.
+//
+// the post-processors are run by `finish()` in the order of addition, after the
+// sections which are generated by the assembler (e.g. the `.eh_frame`, the
+// ELF notes and the debug information) are appended, and before the
+// address-significance table (which refers to all symbols of the object).
+//
+// ref:
+// - https://docs.rs/object/latest/object/write/struct.Object.html
+
+pub type ObjectPostProcessor = Box<dyn FnOnce(&mut ObjectProduct) + Send>;
+
+impl Generator<ObjectModule> {
+    /// Add a post-processor which is run by `finish()`.
+    pub fn add_object_post_processor(
+        &mut self,
+        post_processor: impl FnOnce(&mut ObjectProduct) + Send + 'static,
+    ) {
+        self.object_post_processors.push(Box::new(post_processor));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::{
+        object::{
+            read::File,
+            write::{Symbol, SymbolSection},
+            Object, ObjectSection, ObjectSymbol, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+        },
+        ObjectModule, ObjectProduct,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{code_generator::Generator, lowering::assemble_module, parser::parse_module};
+
+    #[test]
+    fn test_object_post_processor() {
+        let source = r#"
+        (module $main
+            (function $main export (result i32) (code (imm_i32 0))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let func_main_id = assembled_module.get_function_id("main").unwrap();
+
+        // add a section and a symbol which marks it
+        generator.add_object_post_processor(|product: &mut ObjectProduct| {
+            let object = &mut product.object;
+            let section_id = object.add_section(
+                vec![],
+                b".anna.manifest".to_vec(),
+                SectionKind::ReadOnlyData,
+            );
+            let offset = object.append_section_data(section_id, b"name = \"main\"", 1);
+            object.add_symbol(Symbol {
+                name: b"__anna_manifest".to_vec(),
+                value: offset,
+                size: 13,
+                kind: SymbolKind::Data,
+                scope: SymbolScope::Linkage,
+                weak: false,
+                section: SymbolSection::Section(section_id),
+                flags: SymbolFlags::None,
+            });
+        });
+
+        // the functions are mapped to the symbols
+        generator.add_object_post_processor(move |product: &mut ObjectProduct| {
+            let symbol_id = product.function_symbol(func_main_id);
+            assert_eq!(product.object.symbol(symbol_id).name, b"main");
+        });
+
+        let binary = generator.finish().unwrap().emit().unwrap();
+
+        let file = File::parse(binary.as_slice()).unwrap();
+        let section = file.section_by_name(".anna.manifest").unwrap();
+        assert_eq!(section.data().unwrap(), b"name = \"main\"");
+
+        let symbol = file
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("__anna_manifest"))
+            .unwrap();
+        assert_eq!(symbol.section_index(), Some(section.index()));
+        assert_eq!(symbol.size(), 13);
+    }
+}
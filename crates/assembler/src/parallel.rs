@@ -0,0 +1,308 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use cranelift_codegen::{control::ControlPlane, ir::Function, CompiledCode, Context};
+use cranelift_module::{FuncId, Module, ModuleError};
+
+use crate::{code_generator::Generator, compilation_cache::SharedCompilationCache};
+
+// The parallel compilation
+// ------------------------
+//
+// `Module::define_function()` compiles the function and then appends the machine
+// code to the module, the first step takes almost all the time, and it only
+// needs the `TargetIsa` (which is `Send + Sync`) and a `Context`, so the functions
+// can be compiled by multiple threads:
+//
+// 1. the functions are sorted by `FuncId`, i.e. the order of declaration.
+// 2. each worker thread owns a `Context`, and takes the next function from
+//    the shared queue until it is empty.
+// 3. after all workers are finished, the compiled code is appended to the module
+//    by `Module::define_function_bytes()` (see `define_compiled_function()`) in
+//    the order of declaration, so the output is the same as compiling the
+//    functions one by one.
+//
+// the compilation cache (if it is enabled) is shared by the workers. the
+// multi-versioned functions (see `multiversion.rs`) are compiled once per
+// version and a dispatcher, they are compiled in step 3 instead.
+//
+// ref:
+// - https://docs.rs/cranelift-codegen/latest/cranelift_codegen/struct.Context.html#method.compile
+// - https://docs.rs/cranelift-module/latest/cranelift_module/trait.Module.html#tymethod.define_function_bytes
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Compile the functions in parallel and define them in the module.
+    ///
+    /// - `threads`: the number of worker threads, `None` means the number of
+    ///   the available CPU cores.
+    ///
+    /// The functions are defined in the order of declaration (or the order of
+    /// the profile, see `set_function_profile()`). If some functions fail to
+    /// compile, the error of the first failed function (in the order of
+    /// definition) is returned, and the functions before it are still defined.
+    pub fn define_functions_in_parallel(
+        &mut self,
+        functions: Vec<(FuncId, Function)>,
+        threads: Option<usize>,
+    ) -> Result<(), ModuleError> {
+        let mut functions = functions;
+        functions.sort_by_key(|(func_id, _)| *func_id);
+        self.sort_by_function_profile(&mut functions, |(func_id, _)| *func_id);
+
+        for (func_id, func) in functions.iter_mut() {
+            self.run_function_passes(*func_id, func);
+            self.instrument_function(*func_id, func);
+            self.colocate_imported_functions(func);
+            self.redirect_deduplicated_data(func);
+        }
+
+        let threads = threads
+            .unwrap_or_else(|| {
+                thread::available_parallelism()
+                    .map(NonZeroUsize::get)
+                    .unwrap_or(1)
+            })
+            .clamp(1, functions.len().max(1));
+
+        let isa = self.module.isa();
+        let function_versions = &self.function_versions;
+        let disasm = self.listing.is_some();
+        let next_index = AtomicUsize::new(0);
+        let opt_compilation_cache = self.compilation_cache.as_mut().map(Mutex::new);
+
+        let mut results: Vec<Option<Result<CompiledCode, ModuleError>>> =
+            (0..functions.len()).map(|_| None).collect();
+
+        thread::scope(|scope| {
+            let workers = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut context = Context::new();
+                        context.set_disasm(disasm);
+
+                        let mut worker_results = vec![];
+
+                        loop {
+                            let index = next_index.fetch_add(1, Ordering::Relaxed);
+                            let Some((func_id, func)) = functions.get(index) else {
+                                break;
+                            };
+                            if function_versions.contains_key(func_id) {
+                                continue;
+                            }
+
+                            context.clear();
+                            context.func = func.clone();
+
+                            let result = match &opt_compilation_cache {
+                                Some(compilation_cache) => {
+                                    let mut shared_cache = SharedCompilationCache {
+                                        cache: compilation_cache,
+                                    };
+                                    context
+                                        .compile_with_cache(
+                                            isa,
+                                            &mut shared_cache,
+                                            &mut ControlPlane::default(),
+                                        )
+                                        .map(|(_, hit)| {
+                                            compilation_cache.lock().unwrap().record(hit)
+                                        })
+                                }
+                                None => context
+                                    .compile(isa, &mut ControlPlane::default())
+                                    .map(|_| ()),
+                            }
+                            .map_err(|e| ModuleError::Compilation(e.inner));
+
+                            worker_results.push((
+                                index,
+                                result.map(|_| context.take_compiled_code().unwrap()),
+                            ));
+                        }
+
+                        worker_results
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for worker in workers {
+                for (index, result) in worker.join().unwrap() {
+                    results[index] = Some(result);
+                }
+            }
+        });
+
+        // merge the compiled code into the module in the order of definition
+        for ((func_id, func), result) in functions.into_iter().zip(results) {
+            match result {
+                Some(result) => {
+                    let compiled_code = result?;
+                    self.define_compiled_function(func_id, &func, &compiled_code)?;
+                }
+                None => {
+                    let versions = self.function_versions[&func_id].clone();
+                    self.define_function_versions(func_id, func, &versions)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::{object::RelocationKind, ObjectModule};
+
+    use crate::{code_generator::Generator, multiversion::CpuFeature, object_dump::read_functions};
+
+    fn build_module(parallel: bool) -> Vec<u8> {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+
+        let mut func_sig = generator.module.make_signature();
+        func_sig.params.push(AbiParam::new(types::I32));
+        func_sig.returns.push(AbiParam::new(types::I32));
+
+        // build functions "add_N"
+        //
+        // ```rust
+        // fn add_N (a:i32) -> i32 {
+        //    a+N
+        // }
+        // ```
+        let mut functions = vec![];
+        for idx in 0..16 {
+            let func_id = generator
+                .module
+                .declare_function(&format!("add_{}", idx), Linkage::Export, &func_sig)
+                .unwrap();
+
+            let mut func = Function::with_name_signature(
+                UserFuncName::user(0, func_id.as_u32()),
+                func_sig.clone(),
+            );
+
+            let mut function_builder =
+                FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+            let block = function_builder.create_block();
+            function_builder.append_block_params_for_function_params(block);
+            function_builder.switch_to_block(block);
+
+            let value_0 = function_builder.block_params(block)[0];
+            let value_1 = function_builder.ins().iadd_imm(value_0, idx);
+            function_builder.ins().return_(&[value_1]);
+
+            function_builder.seal_all_blocks();
+            function_builder.finalize();
+
+            functions.push((func_id, func));
+        }
+
+        // build function "call_ext", which calls the colocated imported function "ext"
+        //
+        // ```rust
+        // fn call_ext (a:i32) -> i32 {
+        //    ext(a)
+        // }
+        // ```
+        let func_ext_id = generator
+            .import_function_colocated("ext", &func_sig)
+            .unwrap();
+        let func_call_ext_id = generator
+            .module
+            .declare_function("call_ext", Linkage::Export, &func_sig)
+            .unwrap();
+
+        let mut func = Function::with_name_signature(
+            UserFuncName::user(0, func_call_ext_id.as_u32()),
+            func_sig.clone(),
+        );
+        let func_ext_ref = generator
+            .module
+            .declare_func_in_func(func_ext_id, &mut func);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        let call = function_builder.ins().call(func_ext_ref, &[value_0]);
+        let value_1 = function_builder.inst_results(call)[0];
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        functions.push((func_call_ext_id, func));
+
+        // the multi-versioned function
+        generator.set_function_versions(functions[3].0, vec![vec![CpuFeature::Popcnt]]);
+
+        // define in the reverse order, the output should be the same
+        functions.reverse();
+
+        if parallel {
+            generator
+                .define_functions_in_parallel(functions, Some(4))
+                .unwrap();
+        } else {
+            functions.reverse();
+            for (func_id, func) in functions {
+                generator.define_function(func_id, func).unwrap();
+            }
+        }
+
+        assert_eq!(generator.clif_functions.len(), 21);
+        generator.finish().unwrap().emit().unwrap()
+    }
+
+    #[test]
+    fn test_parallel_define_functions() {
+        let module_binary = build_module(true);
+        assert_eq!(module_binary, build_module(false));
+
+        // the versions and the dispatcher of the multi-versioned function
+        let functions = read_functions(&module_binary).unwrap();
+        for name in ["add_3", "add_3.popcnt", "add_3.default", "add_3.resolve"] {
+            assert!(functions.iter().any(|function| function.name == name));
+        }
+
+        // the colocated imported function is called directly
+        let function = functions
+            .iter()
+            .find(|function| function.name == "call_ext")
+            .unwrap();
+        assert!(function
+            .references
+            .iter()
+            .all(|reference| reference.target == "ext"
+                && reference.relocation_kind != RelocationKind::GotRelative));
+        assert!(function
+            .instructions
+            .iter()
+            .any(|instruction| instruction.text == "callq   ext"));
+    }
+}
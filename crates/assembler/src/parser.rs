@@ -0,0 +1,1804 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::borrow::Cow;
+
+use crate::{
+    ast::{
+        DataKind, DataNode, DataValue, FunctionNode, ImportDataNode, ImportFunctionNode,
+        ImportModuleNode, ImportNode, Instruction, InstructionKind, LoadType, LocalNode, Module,
+        Opcode, ResultAbi, StoreType, ValueType,
+    },
+    branch_weight::BranchWeight,
+    conditional::{evaluate_conditionals, Conditions},
+    constant::{is_const_expression, Constants},
+    diagnostic::Diagnostic,
+    function_attribute::{FunctionAttributes, FunctionEffect},
+    inline_asm::{parse_register, parse_template},
+    lexer::{tokenize, Span, Token, TokenKind},
+    macro_expander::expand_macros,
+    multiversion::CpuFeature,
+    struct_type::FieldType,
+    test_harness::{find_assertion, ASSERT_FAILED_FUNCTION_NAME},
+};
+
+// The parser of the assembly text
+// -------------------------------
+//
+// The source text is parsed in two stages:
+//
+// 1. the tokens are grouped into the S-expressions (`SExpr`), i.e. the lists
+//    and the atoms, the comments are dropped.
+// 2. the S-expressions are converted into the typed AST (see `ast.rs`).
+//
+// the conditional blocks are evaluated (see `conditional.rs`) and the macros
+// are expanded (see `macro_expander.rs`) between the two stages.
+//
+// e.g.
+//
+// ```rust
+// let module = parse_module(&std::fs::read_to_string("app.ancasm")?)?;
+// ```
+
+const INSTRUCTION_NAMES_NOTE: &str = "the instruction names are listed in the document \"xiaoxuan_native_assembly_cranelift_ir_map.ods\"";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SExpr {
+    List { items: Vec<SExpr>, span: Span },
+    Atom(Token),
+}
+
+impl SExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            SExpr::List { span, .. } => *span,
+            SExpr::Atom(token) => token.span,
+        }
+    }
+
+    /// Get the keyword of the list, e.g. "module" of `(module $app ...)`.
+    pub fn get_head_keyword(&self) -> Option<&str> {
+        match self {
+            SExpr::List { items, .. } => match items.first() {
+                Some(SExpr::Atom(Token {
+                    kind: TokenKind::Identifier(identifier),
+                    ..
+                })) => Some(identifier),
+                _ => None,
+            },
+            SExpr::Atom(_) => None,
+        }
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            SExpr::List { .. } => match self.get_head_keyword() {
+                Some(keyword) => format!("list \"({} ...)\"", keyword),
+                None => "list".to_owned(),
+            },
+            SExpr::Atom(token) => match &token.kind {
+                TokenKind::Identifier(identifier) => format!("identifier \"{}\"", identifier),
+                TokenKind::Name(name) => format!("name \"${}\"", name),
+                TokenKind::Number(number) => format!("number \"{}\"", number),
+                TokenKind::String(_) => "string".to_owned(),
+                TokenKind::LeftParen | TokenKind::RightParen | TokenKind::Comment(_) => {
+                    unreachable!()
+                }
+            },
+        }
+    }
+}
+
+/// Group the tokens into S-expressions.
+pub fn parse_sexprs(tokens: &[Token]) -> Result<Vec<SExpr>, Diagnostic> {
+    // the stack of the unclosed lists, i.e. (the start position, the items)
+    let mut stack: Vec<(usize, Vec<SExpr>)> = vec![(0, vec![])];
+
+    for token in tokens {
+        match &token.kind {
+            TokenKind::Comment(_) => {}
+            TokenKind::LeftParen => stack.push((token.span.start, vec![])),
+            TokenKind::RightParen => {
+                if stack.len() == 1 {
+                    return Err(Diagnostic::new("unexpected ')'", token.span));
+                }
+                let (start, items) = stack.pop().unwrap();
+                stack.last_mut().unwrap().1.push(SExpr::List {
+                    items,
+                    span: Span::new(start, token.span.end),
+                });
+            }
+            _ => stack.last_mut().unwrap().1.push(SExpr::Atom(token.clone())),
+        }
+    }
+
+    if stack.len() > 1 {
+        let (start, _) = stack.pop().unwrap();
+        return Err(Diagnostic::new(
+            "the list is not closed, expect ')'",
+            Span::new(start, start + 1),
+        ));
+    }
+
+    Ok(stack.pop().unwrap().1)
+}
+
+/// Parse the source text of a module.
+pub fn parse_module(source: &str) -> Result<Module, Diagnostic> {
+    parse_module_at(source, 0)
+}
+
+/// Parse the source text of a module, the spans (including the ones of the errors)
+/// are shifted by the base offset, see `SourceFiles`.
+pub fn parse_module_at(source: &str, base_offset: usize) -> Result<Module, Diagnostic> {
+    parse_module_with_conditions(source, base_offset, &Conditions::default())
+}
+
+/// Parse the source text of a module, the conditional blocks are evaluated
+/// against the given target and features, see `conditional.rs`.
+pub fn parse_module_with_conditions(
+    source: &str,
+    base_offset: usize,
+    conditions: &Conditions,
+) -> Result<Module, Diagnostic> {
+    let shift = |mut diagnostic: Diagnostic| {
+        diagnostic.span = diagnostic.span.shift(base_offset as isize);
+        diagnostic
+    };
+
+    let mut tokens = tokenize(source).map_err(shift)?;
+    for token in &mut tokens {
+        token.span = token.span.shift(base_offset as isize);
+    }
+    let sexprs = parse_sexprs(&tokens)?;
+
+    match sexprs.as_slice() {
+        [sexpr] if sexpr.get_head_keyword() == Some("module") => {
+            let sexpr = evaluate_conditionals(sexpr, conditions)?;
+            convert_module(&expand_macros(&sexpr)?)
+        }
+        [] => Err(Diagnostic::new(
+            "expect the module node \"(module ...)\"",
+            Span::new(base_offset, base_offset),
+        )),
+        [sexpr, ..] if sexpr.get_head_keyword() != Some("module") => Err(Diagnostic::new(
+            &format!(
+                "expect the module node \"(module ...)\", found {}",
+                sexpr.describe()
+            ),
+            sexpr.span(),
+        )),
+        [_, sexpr, ..] => Err(Diagnostic::new(
+            "only one module node is allowed",
+            sexpr.span(),
+        )),
+        _ => unreachable!(),
+    }
+}
+
+/// The cursor of the items of a list.
+struct ListCursor<'a> {
+    items: &'a [SExpr],
+    position: usize,
+    span: Span,
+
+    /// The named constants for evaluating the numbers, see `constant.rs`.
+    constants: &'a Constants,
+}
+
+impl<'a> ListCursor<'a> {
+    /// Create the cursor of the list and skip the head keyword.
+    fn new(sexpr: &'a SExpr, constants: &'a Constants) -> Self {
+        match sexpr {
+            SExpr::List { items, span } => Self {
+                items,
+                position: 1,
+                span: *span,
+                constants,
+            },
+            SExpr::Atom(_) => unreachable!(),
+        }
+    }
+
+    /// Create the cursor of the child list.
+    fn enter(&self, sexpr: &'a SExpr) -> Self {
+        ListCursor::new(sexpr, self.constants)
+    }
+
+    fn keyword(&self) -> &'a str {
+        match &self.items[0] {
+            SExpr::Atom(Token {
+                kind: TokenKind::Identifier(identifier),
+                ..
+            }) => identifier,
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a SExpr> {
+        self.items.get(self.position)
+    }
+
+    fn is_end(&self) -> bool {
+        self.position >= self.items.len()
+    }
+
+    fn next(&mut self) -> Option<&'a SExpr> {
+        let item = self.items.get(self.position)?;
+        self.position += 1;
+        Some(item)
+    }
+
+    /// The span of the closing parenthesis.
+    fn end_span(&self) -> Span {
+        Span::new(self.span.end - 1, self.span.end)
+    }
+
+    fn error_expect(&self, expected: &str) -> Diagnostic {
+        match self.peek() {
+            Some(item) => Diagnostic::new(
+                &format!("expect {}, found {}", expected, item.describe()),
+                item.span(),
+            ),
+            None => Diagnostic::new(
+                &format!("expect {} in \"({} ...)\"", expected, self.keyword()),
+                self.end_span(),
+            ),
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), Diagnostic> {
+        match self.peek() {
+            Some(item) => Err(Diagnostic::new(
+                &format!(
+                    "unexpected {} in \"({} ...)\"",
+                    item.describe(),
+                    self.keyword()
+                ),
+                item.span(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<(String, Span), Diagnostic> {
+        match self.peek() {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::Name(name),
+                span,
+            })) => {
+                self.position += 1;
+                Ok((name.clone(), *span))
+            }
+            _ => Err(self.error_expect("a name")),
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Result<(&'a str, Span), Diagnostic> {
+        match self.peek() {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::Identifier(identifier),
+                span,
+            })) => {
+                self.position += 1;
+                Ok((identifier, *span))
+            }
+            _ => Err(self.error_expect("an identifier")),
+        }
+    }
+
+    /// Expect a number literal or a constant expression, the constant
+    /// expression is evaluated to the text of the number.
+    fn expect_number(&mut self) -> Result<(Cow<'a, str>, Span), Diagnostic> {
+        match self.peek() {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::Number(number),
+                span,
+            })) => {
+                self.position += 1;
+                Ok((Cow::Borrowed(number), *span))
+            }
+            Some(item)
+                if matches!(
+                    item,
+                    SExpr::Atom(Token {
+                        kind: TokenKind::Name(_),
+                        ..
+                    })
+                ) || is_const_expression(item, self.constants) =>
+            {
+                self.position += 1;
+                let value = self.constants.evaluate(item)?;
+                Ok((Cow::Owned(value.to_number_text()), item.span()))
+            }
+            _ => Err(self.error_expect("a number")),
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<&'a [u8], Diagnostic> {
+        match self.peek() {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::String(bytes),
+                ..
+            })) => {
+                self.position += 1;
+                Ok(bytes)
+            }
+            _ => Err(self.error_expect("a string")),
+        }
+    }
+
+    fn expect_list(&mut self) -> Result<&'a SExpr, Diagnostic> {
+        match self.peek() {
+            Some(item @ SExpr::List { .. }) => {
+                self.position += 1;
+                Ok(item)
+            }
+            _ => Err(self.error_expect("a list")),
+        }
+    }
+
+    /// Consume the identifier if it is the given keyword, e.g. `export`.
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::Identifier(identifier),
+                ..
+            })) if identifier == keyword => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Consume the number literal or the constant expression if present.
+    fn consume_number(&mut self) -> Result<Option<(Cow<'a, str>, Span)>, Diagnostic> {
+        match self.peek() {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::Number(_),
+                ..
+            })) => self.expect_number().map(Some),
+            Some(item) if is_const_expression(item, self.constants) => {
+                self.expect_number().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Consume the list if its head keyword is the given keyword.
+    fn consume_list(&mut self, keyword: &str) -> Option<&'a SExpr> {
+        match self.peek() {
+            Some(item) if item.get_head_keyword() == Some(keyword) => {
+                self.position += 1;
+                Some(item)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_value_type(&mut self) -> Result<ValueType, Diagnostic> {
+        let (identifier, span) = self
+            .expect_identifier()
+            .map_err(|_| self.error_expect("a value type"))?;
+        ValueType::from_name(identifier).ok_or_else(|| {
+            Diagnostic::new(
+                &format!(
+                    "unknown value type \"{}\", expect \"i32\", \"i64\", \"f32\" or \"f64\"",
+                    identifier
+                ),
+                span,
+            )
+        })
+    }
+}
+
+fn parse_integer(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i128::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i128::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse::<i128>().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// Parse the integer in the range of `[-2^(bits-1), 2^bits)`, the negative numbers
+/// are converted to the two's complement.
+fn parse_integer_bits(text: &str, bits: u32, span: Span) -> Result<u64, Diagnostic> {
+    match parse_integer(text) {
+        Some(value) if value >= -(1i128 << (bits - 1)) && value < (1i128 << bits) => {
+            Ok((value as u64) & (u64::MAX >> (64 - bits)))
+        }
+        Some(_) => Err(Diagnostic::new(
+            &format!(
+                "the number \"{}\" is out of range of {}-bit integer",
+                text, bits
+            ),
+            span,
+        )),
+        None => Err(Diagnostic::new(
+            &format!("invalid integer \"{}\"", text),
+            span,
+        )),
+    }
+}
+
+fn parse_float(text: &str, span: Span) -> Result<f64, Diagnostic> {
+    match parse_integer(text) {
+        Some(value) => Ok(value as f64),
+        None => text.parse::<f64>().map_err(|_| {
+            Diagnostic::new(&format!("invalid floating point number \"{}\"", text), span)
+        }),
+    }
+}
+
+fn parse_u32(text: &str, span: Span) -> Result<u32, Diagnostic> {
+    match parse_integer(text) {
+        Some(value) if (0..=u32::MAX as i128).contains(&value) => Ok(value as u32),
+        _ => Err(Diagnostic::new(
+            &format!("expect an unsigned 32-bit integer, found \"{}\"", text),
+            span,
+        )),
+    }
+}
+
+fn parse_u64(text: &str, span: Span) -> Result<u64, Diagnostic> {
+    match parse_integer(text) {
+        Some(value) if (0..=u64::MAX as i128).contains(&value) => Ok(value as u64),
+        _ => Err(Diagnostic::new(
+            &format!("expect an unsigned 64-bit integer, found \"{}\"", text),
+            span,
+        )),
+    }
+}
+
+fn parse_offset(text: &str, span: Span) -> Result<i32, Diagnostic> {
+    match parse_integer(text) {
+        Some(value) if (i32::MIN as i128..=i32::MAX as i128).contains(&value) => Ok(value as i32),
+        _ => Err(Diagnostic::new(
+            &format!("the offset \"{}\" is out of range of i32", text),
+            span,
+        )),
+    }
+}
+
+fn convert_module(sexpr: &SExpr) -> Result<Module, Diagnostic> {
+    // the constants and the struct types are defined before the other items
+    // are converted
+    let mut constants = Constants::new();
+    let SExpr::List { items, .. } = sexpr else {
+        unreachable!()
+    };
+    for item in items {
+        match item.get_head_keyword() {
+            Some("const") => constants.define(item)?,
+            Some("struct" | "union") => constants.declare_struct(item)?,
+            _ => {}
+        }
+    }
+
+    let mut cursor = ListCursor::new(sexpr, &constants);
+    let (name, _) = cursor.expect_name()?;
+
+    let mut imports = vec![];
+    let mut module_imports = vec![];
+    let mut data = vec![];
+    let mut functions = vec![];
+    let mut tests = vec![];
+
+    while let Some(item) = cursor.next() {
+        match item.get_head_keyword() {
+            Some("import") if get_import_keyword(item) == Some("module") => {
+                module_imports.push(convert_import_module(item, &constants)?)
+            }
+            Some("import") => imports.push(convert_import(item, &constants)?),
+            Some("extern-c") => imports.push(convert_extern_c(item, &constants)?),
+            Some("data") => data.push(convert_data(item, &constants)?),
+            Some("function") => functions.push(convert_function(item, &constants)?),
+            Some("test") => tests.push(convert_test(item, &constants)?),
+            Some("const" | "struct" | "union") => {}
+            _ => {
+                return Err(Diagnostic::new(
+                    &format!(
+                        "expect \"(import ...)\", \"(extern-c ...)\", \"(const ...)\", \"(struct ...)\", \"(data ...)\", \"(function ...)\" or \"(test ...)\", found {}",
+                        item.describe()
+                    ),
+                    item.span(),
+                ))
+            }
+        }
+    }
+
+    if let Some(assertion) = functions.iter().find_map(find_assertion) {
+        return Err(Diagnostic::new(
+            "the assertion is only allowed in the test function",
+            assertion.span,
+        ));
+    }
+
+    Ok(Module {
+        name,
+        imports,
+        module_imports,
+        data,
+        functions,
+        tests,
+        span: sexpr.span(),
+    })
+}
+
+/// Parse the type lists, e.g. `(param i32 i64) (result i32)`.
+fn convert_type_list(cursor: &mut ListCursor, keyword: &str) -> Result<Vec<ValueType>, Diagnostic> {
+    let mut types = vec![];
+    while let Some(item) = cursor.consume_list(keyword) {
+        let mut item_cursor = cursor.enter(item);
+        while !item_cursor.is_end() {
+            types.push(item_cursor.expect_value_type()?);
+        }
+    }
+    Ok(types)
+}
+
+/// Convert the optional weight of `if` and `when`, i.e. `(weight taken not_taken)`
+/// or `(weight probability)`.
+fn convert_optional_weight(cursor: &mut ListCursor) -> Result<Option<BranchWeight>, Diagnostic> {
+    let Some(item) = cursor.consume_list("weight") else {
+        return Ok(None);
+    };
+
+    let mut item_cursor = cursor.enter(item);
+    let (number, number_span) = item_cursor.expect_number()?;
+    let weight = match item_cursor.consume_number()? {
+        Some((second_number, second_number_span)) => BranchWeight::Counts {
+            taken: parse_u64(&number, number_span)?,
+            not_taken: parse_u64(&second_number, second_number_span)?,
+        },
+        None => {
+            let probability = parse_float(&number, number_span)?;
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(Diagnostic::new(
+                    &format!(
+                        "the probability \"{}\" of the branch is out of range of [0, 1]",
+                        number
+                    ),
+                    number_span,
+                ));
+            }
+            BranchWeight::Probability(probability)
+        }
+    };
+    item_cursor.expect_end()?;
+    Ok(Some(weight))
+}
+
+/// Get the keyword of the item of the import node, e.g. "function" of `(import (function ...))`.
+fn get_import_keyword(sexpr: &SExpr) -> Option<&str> {
+    match sexpr {
+        SExpr::List { items, .. } => items.get(1).and_then(|item| item.get_head_keyword()),
+        SExpr::Atom(_) => None,
+    }
+}
+
+fn convert_import_module(
+    sexpr: &SExpr,
+    constants: &Constants,
+) -> Result<ImportModuleNode, Diagnostic> {
+    let mut cursor = ListCursor::new(sexpr, constants);
+    let item = cursor.expect_list()?;
+    cursor.expect_end()?;
+
+    let mut item_cursor = cursor.enter(item);
+    let path = String::from_utf8_lossy(item_cursor.expect_string()?).into_owned();
+    item_cursor.expect_end()?;
+
+    Ok(ImportModuleNode {
+        path,
+        span: sexpr.span(),
+    })
+}
+
+/// `(extern-c "symbol" [variadic] [noreturn] [pure|readonly] (params type...)? (results type...)? (library "name")?)`,
+/// the C function is imported with the system calling convention, and the
+/// library is linked by the link step (see `ElfNote::new_library()`).
+fn convert_extern_c(sexpr: &SExpr, constants: &Constants) -> Result<ImportNode, Diagnostic> {
+    let mut cursor = ListCursor::new(sexpr, constants);
+    let symbol = String::from_utf8_lossy(cursor.expect_string()?).into_owned();
+    let variadic = cursor.consume_keyword("variadic");
+    let attributes = convert_function_attributes(&mut cursor);
+    let result_abi = convert_result_abi(&mut cursor);
+    let params = convert_type_list(&mut cursor, "params")?;
+    let results = convert_type_list(&mut cursor, "results")?;
+
+    let library = match cursor.consume_list("library") {
+        Some(item) => {
+            let mut item_cursor = cursor.enter(item);
+            let library = String::from_utf8_lossy(item_cursor.expect_string()?).into_owned();
+            item_cursor.expect_end()?;
+            Some(library)
+        }
+        None => None,
+    };
+    cursor.expect_end()?;
+
+    Ok(ImportNode::Function(ImportFunctionNode {
+        name: symbol.clone(),
+        symbol,
+        params,
+        results,
+        variadic,
+        library,
+        colocated: false,
+        result_abi,
+        attributes,
+        span: sexpr.span(),
+    }))
+}
+
+fn convert_import(sexpr: &SExpr, constants: &Constants) -> Result<ImportNode, Diagnostic> {
+    let mut cursor = ListCursor::new(sexpr, constants);
+    let item = cursor.expect_list()?;
+    cursor.expect_end()?;
+
+    let mut item_cursor = cursor.enter(item);
+    let span = sexpr.span();
+
+    match item.get_head_keyword() {
+        Some("function") => {
+            let (name, _) = item_cursor.expect_name()?;
+            let symbol = match item_cursor.peek() {
+                Some(SExpr::Atom(Token {
+                    kind: TokenKind::String(_),
+                    ..
+                })) => String::from_utf8_lossy(item_cursor.expect_string()?).into_owned(),
+                _ => name.clone(),
+            };
+            let colocated = item_cursor.consume_keyword("colocated");
+            let attributes = convert_function_attributes(&mut item_cursor);
+            let result_abi = convert_result_abi(&mut item_cursor);
+            let params = convert_type_list(&mut item_cursor, "param")?;
+            let results = convert_type_list(&mut item_cursor, "result")?;
+            item_cursor.expect_end()?;
+
+            Ok(ImportNode::Function(ImportFunctionNode {
+                name,
+                symbol,
+                params,
+                results,
+                variadic: false,
+                library: None,
+                colocated,
+                result_abi,
+                attributes,
+                span,
+            }))
+        }
+        Some("data") => {
+            let (name, _) = item_cursor.expect_name()?;
+            let symbol = match item_cursor.peek() {
+                Some(SExpr::Atom(Token {
+                    kind: TokenKind::String(_),
+                    ..
+                })) => String::from_utf8_lossy(item_cursor.expect_string()?).into_owned(),
+                _ => name.clone(),
+            };
+            let tls = item_cursor.consume_keyword("tls");
+
+            let size = match item_cursor.consume_list("size") {
+                Some(size_item) => {
+                    let mut size_cursor = item_cursor.enter(size_item);
+                    let (size, size_span) = size_cursor.expect_number()?;
+                    let size = parse_u32(&size, size_span)?;
+                    size_cursor.expect_end()?;
+                    Some(size)
+                }
+                None => None,
+            };
+
+            let align = match item_cursor.consume_list("align") {
+                Some(align_item) => {
+                    let mut align_cursor = item_cursor.enter(align_item);
+                    let align = parse_align(&mut align_cursor)?;
+                    align_cursor.expect_end()?;
+                    Some(align)
+                }
+                None => None,
+            };
+            item_cursor.expect_end()?;
+
+            Ok(ImportNode::Data(ImportDataNode {
+                name,
+                symbol,
+                tls,
+                size,
+                align,
+                span,
+            }))
+        }
+        _ => Err(Diagnostic::new(
+            &format!(
+                "expect \"(function ...)\", \"(data ...)\" or \"(module ...)\", found {}",
+                item.describe()
+            ),
+            item.span(),
+        )),
+    }
+}
+
+/// Parse the optional keyword of the ABI of the results, i.e. `struct_return`
+/// or `out_pointers`.
+/// `[noreturn] [pure|readonly]`
+fn convert_function_attributes(cursor: &mut ListCursor) -> FunctionAttributes {
+    let noreturn = cursor.consume_keyword("noreturn");
+    let effect = if cursor.consume_keyword("pure") {
+        FunctionEffect::Pure
+    } else if cursor.consume_keyword("readonly") {
+        FunctionEffect::ReadOnly
+    } else {
+        FunctionEffect::Any
+    };
+    FunctionAttributes { noreturn, effect }
+}
+
+fn convert_result_abi(cursor: &mut ListCursor) -> ResultAbi {
+    for name in ["struct_return", "out_pointers"] {
+        if cursor.consume_keyword(name) {
+            return ResultAbi::from_name(name).unwrap();
+        }
+    }
+    ResultAbi::Multiple
+}
+
+/// Parse the alignment, it should be a power of two.
+fn parse_align(cursor: &mut ListCursor) -> Result<u32, Diagnostic> {
+    let (align, align_span) = cursor.expect_number()?;
+    let align = parse_u32(&align, align_span)?;
+    if !align.is_power_of_two() {
+        return Err(Diagnostic::new(
+            "the alignment should be a power of two",
+            align_span,
+        ));
+    }
+    Ok(align)
+}
+
+/// Parse the number of the data type, e.g. "i16".
+fn convert_data_number(type_name: &str, number: &str, span: Span) -> Result<DataValue, Diagnostic> {
+    let value = match type_name {
+        "i8" => DataValue::I8(parse_integer_bits(number, 8, span)? as u8),
+        "i16" => DataValue::I16(parse_integer_bits(number, 16, span)? as u16),
+        "i32" => DataValue::I32(parse_integer_bits(number, 32, span)? as u32),
+        "i64" => DataValue::I64(parse_integer_bits(number, 64, span)?),
+        "f32" => DataValue::F32(parse_float(number, span)? as f32),
+        _ => DataValue::F64(parse_float(number, span)?),
+    };
+    Ok(value)
+}
+
+fn convert_data_value(cursor: &mut ListCursor) -> Result<DataValue, Diagnostic> {
+    let (type_name, type_span) = cursor.expect_identifier()?;
+
+    let value = match type_name {
+        "bytes" => match cursor.peek() {
+            Some(SExpr::Atom(Token {
+                kind: TokenKind::String(_),
+                ..
+            })) => DataValue::Bytes(cursor.expect_string()?.to_vec()),
+            _ => {
+                // the byte array, e.g. `bytes 0x7f 0x45 0x4c 0x46`
+                let mut bytes = vec![];
+                while !cursor.is_end() {
+                    let (number, span) = cursor.expect_number()?;
+                    bytes.push(parse_integer_bits(&number, 8, span)? as u8);
+                }
+                DataValue::Bytes(bytes)
+            }
+        },
+        "cstring" => {
+            let bytes = cursor.expect_string()?;
+            if let Some(position) = bytes.iter().position(|byte| *byte == 0) {
+                return Err(Diagnostic::new(
+                    &format!(
+                        "the NUL-terminated string contains the NUL byte at {}",
+                        position
+                    ),
+                    cursor.items[cursor.position - 1].span(),
+                ));
+            }
+            DataValue::CString(bytes.to_vec())
+        }
+        "lstring" => DataValue::LString(cursor.expect_string()?.to_vec()),
+        "zero" => {
+            let (size, size_span) = cursor.expect_number()?;
+            DataValue::Zero(parse_u32(&size, size_span)?)
+        }
+        "i8" | "i16" | "i32" | "i64" | "f32" | "f64" => {
+            // one number is a scalar, and more numbers are an array
+            let (number, span) = cursor.expect_number()?;
+            let first = convert_data_number(type_name, &number, span)?;
+            if cursor.is_end() {
+                first
+            } else {
+                let mut items = vec![first];
+                while !cursor.is_end() {
+                    let (number, span) = cursor.expect_number()?;
+                    items.push(convert_data_number(type_name, &number, span)?);
+                }
+                DataValue::Array(items)
+            }
+        }
+        "addr_data" | "addr_function" => {
+            // e.g. `addr_data $table 8 $buffer`, the offset is optional
+            let mut items = vec![];
+            loop {
+                let (name, _) = cursor.expect_name()?;
+                items.push(if type_name == "addr_data" {
+                    DataValue::DataAddress {
+                        name,
+                        offset: convert_optional_offset(cursor)?,
+                    }
+                } else {
+                    DataValue::FunctionAddress(name)
+                });
+                if cursor.is_end() {
+                    break;
+                }
+            }
+            if items.len() == 1 {
+                items.remove(0)
+            } else {
+                DataValue::Array(items)
+            }
+        }
+        _ => {
+            return Err(Diagnostic::new(
+                &format!(
+                    "unknown data type \"{}\", expect {}, {}, {} or \"zero\"",
+                    type_name,
+                    "\"i8\", \"i16\", \"i32\", \"i64\", \"f32\", \"f64\"",
+                    "\"bytes\", \"cstring\", \"lstring\"",
+                    "\"addr_data\", \"addr_function\""
+                ),
+                type_span,
+            ))
+        }
+    };
+
+    cursor.expect_end()?;
+    Ok(value)
+}
+
+fn convert_data(sexpr: &SExpr, constants: &Constants) -> Result<DataNode, Diagnostic> {
+    let mut cursor = ListCursor::new(sexpr, constants);
+    let (name, _) = cursor.expect_name()?;
+    let export = cursor.consume_keyword("export");
+
+    let align = match cursor.consume_list("align") {
+        Some(item) => {
+            let mut item_cursor = cursor.enter(item);
+            let align = parse_align(&mut item_cursor)?;
+            item_cursor.expect_end()?;
+            Some(align)
+        }
+        None => None,
+    };
+
+    let section = match cursor.consume_list("section") {
+        Some(item) => {
+            let mut item_cursor = cursor.enter(item);
+            let section = String::from_utf8_lossy(item_cursor.expect_string()?).into_owned();
+            item_cursor.expect_end()?;
+            Some((section, item.span()))
+        }
+        None => None,
+    };
+
+    let item = cursor.expect_list()?;
+    cursor.expect_end()?;
+
+    let mut item_cursor = cursor.enter(item);
+    let kind = match item.get_head_keyword() {
+        Some("read_only") => DataKind::ReadOnly(convert_data_value(&mut item_cursor)?),
+        Some("read_write") => DataKind::ReadWrite(convert_data_value(&mut item_cursor)?),
+        Some("uninit") => {
+            if let Some((_, section_span)) = section {
+                return Err(Diagnostic::new(
+                    "the custom section is not supported by the uninitialized data",
+                    section_span,
+                )
+                .with_note("use \"(read_write zero size)\" instead"));
+            }
+
+            let (size, size_span) = item_cursor.expect_number()?;
+            let size = parse_u32(&size, size_span)?;
+            let align = if item_cursor.is_end() {
+                1
+            } else {
+                parse_align(&mut item_cursor)?
+            };
+            item_cursor.expect_end()?;
+            DataKind::Uninit { size, align }
+        }
+        _ => {
+            return Err(Diagnostic::new(
+                &format!(
+                    "expect {} or \"(uninit ...)\", found {}",
+                    "\"(read_only ...)\", \"(read_write ...)\"",
+                    item.describe()
+                ),
+                item.span(),
+            ))
+        }
+    };
+
+    Ok(DataNode {
+        name,
+        export,
+        kind,
+        align,
+        section: section.map(|(section, _)| section),
+        span: sexpr.span(),
+    })
+}
+
+/// Parse the parameters or the locals, e.g. `(param $a i32)`.
+fn convert_local_list(
+    cursor: &mut ListCursor,
+    keyword: &str,
+) -> Result<Vec<LocalNode>, Diagnostic> {
+    let mut locals = vec![];
+    while let Some(item) = cursor.consume_list(keyword) {
+        let mut item_cursor = cursor.enter(item);
+        let (name, _) = item_cursor.expect_name()?;
+        let value_type = item_cursor.expect_value_type()?;
+        item_cursor.expect_end()?;
+
+        locals.push(LocalNode {
+            name,
+            value_type,
+            span: item.span(),
+        });
+    }
+    Ok(locals)
+}
+
+fn convert_function(sexpr: &SExpr, constants: &Constants) -> Result<FunctionNode, Diagnostic> {
+    let mut cursor = ListCursor::new(sexpr, constants);
+    let (name, _) = cursor.expect_name()?;
+    let export = cursor.consume_keyword("export");
+    let result_abi = convert_result_abi(&mut cursor);
+    let align = match cursor.consume_list("align") {
+        Some(item) => {
+            let mut item_cursor = cursor.enter(item);
+            let align = parse_align(&mut item_cursor)?;
+            item_cursor.expect_end()?;
+            Some(align)
+        }
+        None => None,
+    };
+    let target_clones = convert_target_clones(&mut cursor)?;
+    let params = convert_local_list(&mut cursor, "param")?;
+    let results = convert_type_list(&mut cursor, "result")?;
+    let locals = convert_local_list(&mut cursor, "local")?;
+
+    let code = cursor
+        .consume_list("code")
+        .ok_or_else(|| cursor.error_expect("\"(code ...)\""))?;
+    cursor.expect_end()?;
+
+    let mut code_cursor = cursor.enter(code);
+    let body = convert_instructions(&mut code_cursor)?;
+
+    Ok(FunctionNode {
+        name,
+        export,
+        result_abi,
+        align,
+        target_clones,
+        params,
+        results,
+        locals,
+        body,
+        span: sexpr.span(),
+    })
+}
+
+/// `(target_clones "avx2,fma" "popcnt")`, each string is a CPU feature set
+/// separated by commas.
+fn convert_target_clones(cursor: &mut ListCursor) -> Result<Vec<Vec<CpuFeature>>, Diagnostic> {
+    let Some(item) = cursor.consume_list("target_clones") else {
+        return Ok(vec![]);
+    };
+
+    let mut item_cursor = cursor.enter(item);
+    let mut target_clones = vec![];
+    while !item_cursor.is_end() {
+        let text = String::from_utf8_lossy(item_cursor.expect_string()?).into_owned();
+        let features = text
+            .split(',')
+            .map(|name| {
+                CpuFeature::from_name(name.trim()).ok_or_else(|| {
+                    Diagnostic::new(
+                        &format!("unknown CPU feature \"{}\"", name.trim()),
+                        item.span(),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        target_clones.push(features);
+    }
+
+    if target_clones.is_empty() {
+        return Err(Diagnostic::new(
+            "expect at least one CPU feature set",
+            item.span(),
+        ));
+    }
+    Ok(target_clones)
+}
+
+/// `(test $name (local $name type)... (code ...))`, see `test_harness.rs`.
+fn convert_test(sexpr: &SExpr, constants: &Constants) -> Result<FunctionNode, Diagnostic> {
+    let mut cursor = ListCursor::new(sexpr, constants);
+    let (name, _) = cursor.expect_name()?;
+    let locals = convert_local_list(&mut cursor, "local")?;
+
+    let code = cursor
+        .consume_list("code")
+        .ok_or_else(|| cursor.error_expect("\"(code ...)\""))?;
+    cursor.expect_end()?;
+
+    let mut code_cursor = cursor.enter(code);
+    let body = convert_instructions(&mut code_cursor)?;
+
+    Ok(FunctionNode {
+        name,
+        export: false,
+        result_abi: ResultAbi::Multiple,
+        align: None,
+        target_clones: vec![],
+        params: vec![],
+        results: vec![],
+        locals,
+        body,
+        span: sexpr.span(),
+    })
+}
+
+/// Convert the remaining items of the list as instructions.
+fn convert_instructions(cursor: &mut ListCursor) -> Result<Vec<Instruction>, Diagnostic> {
+    let mut instructions = vec![];
+    while !cursor.is_end() {
+        instructions.push(convert_instruction(
+            cursor.expect_list()?,
+            cursor.constants,
+        )?);
+    }
+    Ok(instructions)
+}
+
+fn convert_boxed_instruction(cursor: &mut ListCursor) -> Result<Box<Instruction>, Diagnostic> {
+    Ok(Box::new(convert_instruction(
+        cursor.expect_list()?,
+        cursor.constants,
+    )?))
+}
+
+/// Convert the inline assembly, e.g. `(asm "0f a2" (in "eax" (imm_i32 0)) (out "ebx"))`,
+/// see `inline_asm.rs`.
+fn convert_asm(cursor: &mut ListCursor) -> Result<InstructionKind, Diagnostic> {
+    let template_span = cursor.peek().map_or(cursor.end_span(), |item| item.span());
+    let template = parse_template(&String::from_utf8_lossy(cursor.expect_string()?))
+        .map_err(|message| Diagnostic::new(&message, template_span))?;
+
+    let mut inputs = vec![];
+    let mut input_numbers = vec![];
+    while let Some(item) = cursor.consume_list("in") {
+        let mut item_cursor = cursor.enter(item);
+        let (register, _) = expect_asm_register(&mut item_cursor, &mut input_numbers)?;
+        let value = convert_instruction(item_cursor.expect_list()?, item_cursor.constants)?;
+        item_cursor.expect_end()?;
+        inputs.push((register, value));
+    }
+
+    let mut outputs = vec![];
+    let mut output_numbers = vec![];
+    while let Some(item) = cursor.consume_list("out") {
+        let mut item_cursor = cursor.enter(item);
+        let (register, value_type) = expect_asm_register(&mut item_cursor, &mut output_numbers)?;
+        item_cursor.expect_end()?;
+        outputs.push((register, value_type));
+    }
+
+    let mut clobbers = vec![];
+    let mut clobber_numbers = vec![];
+    while let Some(item) = cursor.consume_list("clobber") {
+        let mut item_cursor = cursor.enter(item);
+        while !item_cursor.is_end() {
+            let (register, _) = expect_asm_register(&mut item_cursor, &mut clobber_numbers)?;
+            clobbers.push(register);
+        }
+    }
+
+    Ok(InstructionKind::Asm {
+        template,
+        inputs,
+        outputs,
+        clobbers,
+    })
+}
+
+/// Expect the register name of the inline assembly, and check that it is
+/// not used by the other operands of the same kind.
+fn expect_asm_register(
+    cursor: &mut ListCursor,
+    numbers: &mut Vec<u8>,
+) -> Result<(String, ValueType), Diagnostic> {
+    let span = cursor.peek().map_or(cursor.end_span(), |item| item.span());
+    let register = String::from_utf8_lossy(cursor.expect_string()?).into_owned();
+    let (number, value_type) =
+        parse_register(&register).map_err(|message| Diagnostic::new(&message, span))?;
+
+    if numbers.contains(&number) {
+        return Err(Diagnostic::new(
+            &format!(
+                "the register \"{}\" is used more than once in \"({} ...)\"",
+                register,
+                cursor.keyword()
+            ),
+            span,
+        ));
+    }
+    numbers.push(number);
+    Ok((register, value_type))
+}
+
+/// Convert the field access pseudo-instructions (see `struct_type.rs`) into the
+/// memory instructions, e.g. `(field-load $Point $y addr)` into `(memory_load_i32 addr 4)`.
+fn convert_field_access(
+    keyword: &str,
+    cursor: &mut ListCursor,
+    span: Span,
+) -> Result<InstructionKind, Diagnostic> {
+    let (struct_name, _) = cursor.expect_name()?;
+    let (path, path_span) = cursor.expect_name()?;
+    let (offset, field_type) =
+        cursor
+            .constants
+            .structs()
+            .resolve_field(&struct_name, &path, path_span)?;
+    let offset = i32::try_from(offset).map_err(|_| {
+        Diagnostic::new(
+            &format!(
+                "the offset of the field \"${}\" is out of range of i32",
+                path
+            ),
+            path_span,
+        )
+    })?;
+    let address = convert_boxed_instruction(cursor)?;
+
+    if keyword == "field-addr" {
+        let offset = Instruction {
+            kind: InstructionKind::ImmI64(offset as u64),
+            span,
+        };
+        return Ok(InstructionKind::Operation {
+            opcode: Opcode::AddI64,
+            operands: vec![*address, offset],
+        });
+    }
+
+    let FieldType::Scalar(scalar_type) = field_type else {
+        return Err(Diagnostic::new(
+            &format!(
+                "the field \"${}\" is not a scalar, only its address can be taken by \"field-addr\"",
+                path
+            ),
+            path_span,
+        ));
+    };
+
+    Ok(if keyword == "field-load" {
+        InstructionKind::MemoryLoad {
+            load_type: scalar_type.load_type(),
+            address,
+            offset,
+        }
+    } else {
+        InstructionKind::MemoryStore {
+            store_type: scalar_type.store_type(),
+            address,
+            offset,
+            value: convert_boxed_instruction(cursor)?,
+        }
+    })
+}
+
+/// Convert the assertion pseudo-instructions (see `test_harness.rs`), e.g.
+/// `(assert_eq_i32 left right)` into
+/// `(when (ne_i32 left right) (call $__anasm_assert_failed (imm_i64 offset)) (break_fn))`.
+fn convert_assertion(
+    keyword: &str,
+    cursor: &mut ListCursor,
+    span: Span,
+) -> Result<InstructionKind, Diagnostic> {
+    let condition = if keyword == "assert_true" {
+        let value = convert_instruction(cursor.expect_list()?, cursor.constants)?;
+        Instruction {
+            kind: InstructionKind::Operation {
+                opcode: Opcode::EqI32,
+                operands: vec![
+                    value,
+                    Instruction {
+                        kind: InstructionKind::ImmI32(0),
+                        span,
+                    },
+                ],
+            },
+            span,
+        }
+    } else {
+        // the assertion fails when the opposite comparison is true
+        let opcode = match keyword {
+            "assert_eq_i32" => Opcode::NeI32,
+            "assert_eq_i64" => Opcode::NeI64,
+            "assert_eq_f32" => Opcode::NeF32,
+            "assert_eq_f64" => Opcode::NeF64,
+            "assert_ne_i32" => Opcode::EqI32,
+            "assert_ne_i64" => Opcode::EqI64,
+            "assert_ne_f32" => Opcode::EqF32,
+            _ => Opcode::EqF64,
+        };
+        let left = convert_instruction(cursor.expect_list()?, cursor.constants)?;
+        let right = convert_instruction(cursor.expect_list()?, cursor.constants)?;
+        Instruction {
+            kind: InstructionKind::Operation {
+                opcode,
+                operands: vec![left, right],
+            },
+            span,
+        }
+    };
+
+    Ok(InstructionKind::When {
+        weight: None,
+        condition: Box::new(condition),
+        body: vec![
+            Instruction {
+                kind: InstructionKind::Call {
+                    name: ASSERT_FAILED_FUNCTION_NAME.to_owned(),
+                    args: vec![Instruction {
+                        kind: InstructionKind::ImmI64(span.start as u64),
+                        span,
+                    }],
+                },
+                span,
+            },
+            Instruction {
+                kind: InstructionKind::BreakFn(vec![]),
+                span,
+            },
+        ],
+    })
+}
+
+fn convert_instruction(sexpr: &SExpr, constants: &Constants) -> Result<Instruction, Diagnostic> {
+    let span = sexpr.span();
+    let keyword = sexpr
+        .get_head_keyword()
+        .ok_or_else(|| Diagnostic::new("expect an instruction, e.g. \"(add_i32 ...)\"", span))?;
+
+    let mut cursor = ListCursor::new(sexpr, constants);
+
+    let kind = match keyword {
+        "nop" => InstructionKind::Nop,
+        "imm_i32" | "imm_i64" | "imm_f32" | "imm_f64" => {
+            let (number, number_span) = cursor.expect_number()?;
+            match keyword {
+                "imm_i32" => {
+                    InstructionKind::ImmI32(parse_integer_bits(&number, 32, number_span)? as u32)
+                }
+                "imm_i64" => InstructionKind::ImmI64(parse_integer_bits(&number, 64, number_span)?),
+                "imm_f32" => InstructionKind::ImmF32(parse_float(&number, number_span)? as f32),
+                _ => InstructionKind::ImmF64(parse_float(&number, number_span)?),
+            }
+        }
+        "local_load" => InstructionKind::LocalLoad(cursor.expect_name()?.0),
+        "local_store" => {
+            let (name, _) = cursor.expect_name()?;
+            InstructionKind::LocalStore {
+                name,
+                value: convert_boxed_instruction(&mut cursor)?,
+            }
+        }
+        "host_addr_function" => InstructionKind::HostAddrFunction(cursor.expect_name()?.0),
+        "host_addr_data" => InstructionKind::HostAddrData {
+            name: cursor.expect_name()?.0,
+            offset: convert_optional_offset(&mut cursor)?,
+        },
+        "do" => InstructionKind::Do(convert_instructions(&mut cursor)?),
+        "if" => {
+            let results = convert_type_list(&mut cursor, "result")?;
+            InstructionKind::If {
+                results,
+                weight: convert_optional_weight(&mut cursor)?,
+                condition: convert_boxed_instruction(&mut cursor)?,
+                consequent: convert_boxed_instruction(&mut cursor)?,
+                alternative: convert_boxed_instruction(&mut cursor)?,
+            }
+        }
+        "when" => InstructionKind::When {
+            weight: convert_optional_weight(&mut cursor)?,
+            condition: convert_boxed_instruction(&mut cursor)?,
+            body: convert_instructions(&mut cursor)?,
+        },
+        "for" => {
+            let mut params = vec![];
+            while let Some(item) = cursor.consume_list("param") {
+                let mut item_cursor = cursor.enter(item);
+                let (name, _) = item_cursor.expect_name()?;
+                let value_type = item_cursor.expect_value_type()?;
+                let init = convert_instruction(item_cursor.expect_list()?, item_cursor.constants)?;
+                item_cursor.expect_end()?;
+
+                params.push((
+                    LocalNode {
+                        name,
+                        value_type,
+                        span: item.span(),
+                    },
+                    init,
+                ));
+            }
+
+            InstructionKind::For {
+                params,
+                results: convert_type_list(&mut cursor, "result")?,
+                body: convert_instructions(&mut cursor)?,
+            }
+        }
+        "break" => InstructionKind::Break(convert_instructions(&mut cursor)?),
+        "recur" => InstructionKind::Recur(convert_instructions(&mut cursor)?),
+        "break_fn" => InstructionKind::BreakFn(convert_instructions(&mut cursor)?),
+        "recur_fn" => InstructionKind::RecurFn(convert_instructions(&mut cursor)?),
+        "call" => InstructionKind::Call {
+            name: cursor.expect_name()?.0,
+            args: convert_instructions(&mut cursor)?,
+        },
+        "dyncall" => InstructionKind::DynCall {
+            params: convert_type_list(&mut cursor, "param")?,
+            results: convert_type_list(&mut cursor, "result")?,
+            callee: convert_boxed_instruction(&mut cursor)?,
+            args: convert_instructions(&mut cursor)?,
+        },
+        "clif" => InstructionKind::Clif {
+            params: convert_type_list(&mut cursor, "param")?,
+            results: convert_type_list(&mut cursor, "result")?,
+            text: String::from_utf8_lossy(cursor.expect_string()?).into_owned(),
+            args: convert_instructions(&mut cursor)?,
+        },
+        "asm" => convert_asm(&mut cursor)?,
+        "field-load" | "field-store" | "field-addr" => {
+            convert_field_access(keyword, &mut cursor, span)?
+        }
+        "assert_true" | "assert_eq_i32" | "assert_eq_i64" | "assert_eq_f32" | "assert_eq_f64"
+        | "assert_ne_i32" | "assert_ne_i64" | "assert_ne_f32" | "assert_ne_f64" => {
+            convert_assertion(keyword, &mut cursor, span)?
+        }
+        "exit" => InstructionKind::Exit(convert_boxed_instruction(&mut cursor)?),
+        "abort" => InstructionKind::Abort,
+        "read_cycle_counter" => InstructionKind::ReadCycleCounter,
+        "panic" => {
+            let (number, number_span) = cursor.expect_number()?;
+            match parse_integer(&number) {
+                Some(code @ 1..=255) => InstructionKind::Panic(code as u8),
+                _ => {
+                    return Err(Diagnostic::new(
+                        "the panic code should be 1 to 255",
+                        number_span,
+                    ))
+                }
+            }
+        }
+        _ => {
+            if let Some(suffix) = keyword.strip_prefix("data_load_") {
+                let load_type = convert_load_type(suffix, &cursor)?;
+                let (name, _) = cursor.expect_name()?;
+                let offset = convert_optional_offset(&mut cursor)?;
+                InstructionKind::DataLoad {
+                    load_type,
+                    name,
+                    offset,
+                }
+            } else if let Some(suffix) = keyword.strip_prefix("data_store_") {
+                let store_type = convert_store_type(suffix, &cursor)?;
+                let (name, _) = cursor.expect_name()?;
+                let offset = convert_optional_offset(&mut cursor)?;
+                InstructionKind::DataStore {
+                    store_type,
+                    name,
+                    offset,
+                    value: convert_boxed_instruction(&mut cursor)?,
+                }
+            } else if let Some(suffix) = keyword.strip_prefix("memory_load_") {
+                let load_type = convert_load_type(suffix, &cursor)?;
+                let address = convert_boxed_instruction(&mut cursor)?;
+                let offset = convert_optional_offset(&mut cursor)?;
+                InstructionKind::MemoryLoad {
+                    load_type,
+                    address,
+                    offset,
+                }
+            } else if let Some(suffix) = keyword.strip_prefix("memory_store_") {
+                let store_type = convert_store_type(suffix, &cursor)?;
+                let address = convert_boxed_instruction(&mut cursor)?;
+                let offset = convert_optional_offset(&mut cursor)?;
+                InstructionKind::MemoryStore {
+                    store_type,
+                    address,
+                    offset,
+                    value: convert_boxed_instruction(&mut cursor)?,
+                }
+            } else if let Some(opcode) = Opcode::from_name(keyword) {
+                let operands = convert_instructions(&mut cursor)?;
+                if operands.len() != opcode.param_types().len() {
+                    return Err(Diagnostic::new(
+                        &format!(
+                            "the instruction \"{}\" requires {} operand(s), found {}",
+                            keyword,
+                            opcode.param_types().len(),
+                            operands.len()
+                        ),
+                        span,
+                    ));
+                }
+                InstructionKind::Operation { opcode, operands }
+            } else {
+                return Err(Diagnostic::new(
+                    &format!("unknown instruction \"{}\"", keyword),
+                    cursor.items[0].span(),
+                )
+                .with_note(INSTRUCTION_NAMES_NOTE));
+            }
+        }
+    };
+
+    cursor.expect_end()?;
+    Ok(Instruction { kind, span })
+}
+
+fn convert_load_type(suffix: &str, cursor: &ListCursor) -> Result<LoadType, Diagnostic> {
+    LoadType::from_suffix(suffix).ok_or_else(|| {
+        Diagnostic::new(
+            &format!("unknown instruction \"{}\"", cursor.keyword()),
+            cursor.items[0].span(),
+        )
+        .with_note(INSTRUCTION_NAMES_NOTE)
+    })
+}
+
+fn convert_store_type(suffix: &str, cursor: &ListCursor) -> Result<StoreType, Diagnostic> {
+    StoreType::from_suffix(suffix).ok_or_else(|| {
+        Diagnostic::new(
+            &format!("unknown instruction \"{}\"", cursor.keyword()),
+            cursor.items[0].span(),
+        )
+        .with_note(INSTRUCTION_NAMES_NOTE)
+    })
+}
+
+fn convert_optional_offset(cursor: &mut ListCursor) -> Result<i32, Diagnostic> {
+    match cursor.consume_number()? {
+        Some((number, span)) => parse_offset(&number, span),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::{
+            DataKind, DataValue, ImportDataNode, ImportFunctionNode, ImportNode, InstructionKind,
+            LoadType, Opcode, ResultAbi, ValueType,
+        },
+        diagnostic::Diagnostic,
+        function_attribute::FunctionAttributes,
+        lexer::Span,
+        parser::parse_module,
+    };
+
+    #[test]
+    fn test_parse_module() {
+        let source = r#"
+        (module $app
+            (import (function $puts "puts" (param i64) (result i32)))
+            (import (function $rt_alloc colocated (param i64) (result i64)))
+            (import (data $errno tls (size 4)))
+
+            (data $count export (read_write i32 -1))
+            (data $message (read_only bytes "hi\0"))
+            (data $buffer (uninit 64 8))
+
+            // the function
+            (function $main export (param $a i32) (result i32) (local $b i64)
+                (code
+                    (local_store $b (data_load_i32_u $count 4))
+                    (if (result i32)
+                        (eqz_i32 (local_load $a))
+                        (imm_i32 0x10)
+                        (add_i32 (local_load $a) (imm_i32 1)))
+                )
+            )
+        )
+        "#;
+
+        let module = parse_module(source).unwrap();
+        assert_eq!(module.name, "app");
+        assert_eq!(
+            module.imports,
+            vec![
+                ImportNode::Function(ImportFunctionNode {
+                    name: "puts".to_owned(),
+                    symbol: "puts".to_owned(),
+                    params: vec![ValueType::I64],
+                    results: vec![ValueType::I32],
+                    variadic: false,
+                    library: None,
+                    colocated: false,
+                    result_abi: ResultAbi::Multiple,
+                    attributes: FunctionAttributes::default(),
+                    span: module.imports[0].span()
+                }),
+                ImportNode::Function(ImportFunctionNode {
+                    name: "rt_alloc".to_owned(),
+                    symbol: "rt_alloc".to_owned(),
+                    params: vec![ValueType::I64],
+                    results: vec![ValueType::I64],
+                    variadic: false,
+                    library: None,
+                    colocated: true,
+                    result_abi: ResultAbi::Multiple,
+                    attributes: FunctionAttributes::default(),
+                    span: module.imports[1].span()
+                }),
+                ImportNode::Data(ImportDataNode {
+                    name: "errno".to_owned(),
+                    symbol: "errno".to_owned(),
+                    tls: true,
+                    size: Some(4),
+                    align: None,
+                    span: module.imports[2].span()
+                })
+            ]
+        );
+
+        assert_eq!(
+            module
+                .data
+                .iter()
+                .map(|item| (item.name.as_str(), item.export, item.kind.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "count",
+                    true,
+                    DataKind::ReadWrite(DataValue::I32(0xffff_ffff))
+                ),
+                (
+                    "message",
+                    false,
+                    DataKind::ReadOnly(DataValue::Bytes(b"hi\0".to_vec()))
+                ),
+                ("buffer", false, DataKind::Uninit { size: 64, align: 8 }),
+            ]
+        );
+
+        let function = &module.functions[0];
+        assert_eq!(function.name, "main");
+        assert!(function.export);
+        assert_eq!(function.params[0].name, "a");
+        assert_eq!(function.results, vec![ValueType::I32]);
+        assert_eq!(function.locals[0].value_type, ValueType::I64);
+        assert_eq!(function.body.len(), 2);
+
+        match &function.body[0].kind {
+            InstructionKind::LocalStore { name, value } => {
+                assert_eq!(name, "b");
+                assert_eq!(
+                    value.kind,
+                    InstructionKind::DataLoad {
+                        load_type: LoadType::I32U,
+                        name: "count".to_owned(),
+                        offset: 4
+                    }
+                );
+            }
+            _ => panic!(),
+        }
+
+        match &function.body[1].kind {
+            InstructionKind::If {
+                results,
+                condition,
+                consequent,
+                ..
+            } => {
+                assert_eq!(results, &vec![ValueType::I32]);
+                assert!(matches!(
+                    condition.kind,
+                    InstructionKind::Operation {
+                        opcode: Opcode::EqzI32,
+                        ..
+                    }
+                ));
+                assert_eq!(consequent.kind, InstructionKind::ImmI32(16));
+
+                let start = source.find("(imm_i32 0x10)").unwrap();
+                assert_eq!(consequent.span, Span::new(start, start + 14));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_parse_data() {
+        let source = r#"
+        (module $app
+            (data $a (read_only i16 1 -2 0x7fff))
+            (data $b (read_only bytes 0x7f 0x45 0x4c 0x46))
+            (data $c (read_write cstring "hi"))
+            (data $d (align 8) (read_only lstring "abc"))
+            (data $e (section ".rodata.table") (read_only zero 3))
+            (data $f (align 64) (uninit 128 8))
+            (data $g (read_only addr_data $a 2 $b))
+            (data $h (read_write addr_function $main)))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let get_content = |index: usize| match &module.data[index].kind {
+            DataKind::ReadOnly(value) | DataKind::ReadWrite(value) => value.to_bytes(),
+            DataKind::Uninit { .. } => unreachable!(),
+        };
+
+        assert_eq!(get_content(0), vec![1, 0, 0xfe, 0xff, 0xff, 0x7f]);
+        assert_eq!(get_content(1), b"\x7fELF".to_vec());
+        assert_eq!(get_content(2), b"hi\0".to_vec());
+        assert_eq!(get_content(3), b"\x03\0\0\0abc".to_vec());
+        assert_eq!(get_content(4), vec![0, 0, 0]);
+        assert_eq!(
+            module
+                .data
+                .iter()
+                .map(|node| node.get_align())
+                .collect::<Vec<_>>(),
+            vec![2, 1, 1, 8, 1, 64, 8, 8]
+        );
+        assert_eq!(module.data[4].section.as_deref(), Some(".rodata.table"));
+
+        // the addresses are filled by the relocations
+        assert_eq!(get_content(6), vec![0; 16]);
+        assert_eq!(
+            module.data[6].kind,
+            DataKind::ReadOnly(DataValue::Array(vec![
+                DataValue::DataAddress {
+                    name: "a".to_owned(),
+                    offset: 2
+                },
+                DataValue::DataAddress {
+                    name: "b".to_owned(),
+                    offset: 0
+                },
+            ]))
+        );
+        assert_eq!(
+            module.data[7].kind,
+            DataKind::ReadWrite(DataValue::FunctionAddress("main".to_owned()))
+        );
+
+        fn parse_error(source: &str) -> (String, &str) {
+            let Diagnostic { message, span, .. } = parse_module(source).unwrap_err();
+            (message, &source[span.start..span.end])
+        }
+
+        assert_eq!(
+            parse_error("(module $a (data $d (read_only cstring \"a\\0b\")))"),
+            (
+                "the NUL-terminated string contains the NUL byte at 1".to_owned(),
+                "\"a\\0b\""
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (data $d (read_only bytes 1 256)))"),
+            (
+                "the number \"256\" is out of range of 8-bit integer".to_owned(),
+                "256"
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (data $d (align 3) (read_only i8 1)))"),
+            ("the alignment should be a power of two".to_owned(), "3")
+        );
+        assert_eq!(
+            parse_error("(module $a (data $d (section \".bss.x\") (uninit 8)))"),
+            (
+                "the custom section is not supported by the uninitialized data".to_owned(),
+                "(section \".bss.x\")"
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        fn parse_error(source: &str) -> (String, &str) {
+            let Diagnostic { message, span, .. } = parse_module(source).unwrap_err();
+            (message, &source[span.start..span.end])
+        }
+
+        assert_eq!(
+            parse_error("(module $a (function $f (code (imm_i32 10))"),
+            ("the list is not closed, expect ')'".to_owned(), "(")
+        );
+        assert_eq!(
+            parse_error("(module $a (function $f (code (add_i33))))"),
+            ("unknown instruction \"add_i33\"".to_owned(), "add_i33")
+        );
+        assert_eq!(
+            parse_error("(module $a (function $f (code (imm_i32 0x1_0000_0000))))"),
+            (
+                "the number \"0x100000000\" is out of range of 32-bit integer".to_owned(),
+                "0x1_0000_0000"
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (function $f (param $x i8) (code)))"),
+            (
+                "unknown value type \"i8\", expect \"i32\", \"i64\", \"f32\" or \"f64\"".to_owned(),
+                "i8"
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (function $f))"),
+            (
+                "expect \"(code ...)\" in \"(function ...)\"".to_owned(),
+                ")"
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (function $f (code (local_load 1))))"),
+            ("expect a name, found number \"1\"".to_owned(), "1")
+        );
+        assert_eq!(
+            parse_error("(module $a (function $f (code (add_i32 (imm_i32 1)))))"),
+            (
+                "the instruction \"add_i32\" requires 2 operand(s), found 1".to_owned(),
+                "(add_i32 (imm_i32 1))"
+            )
+        );
+        assert_eq!(
+            parse_error("(function $f)"),
+            (
+                "expect the module node \"(module ...)\", found list \"(function ...)\"".to_owned(),
+                "(function $f)"
+            )
+        );
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_module::{FuncId, Module, ModuleError};
+use cranelift_object::{
+    object::{write::Relocation, RelocationEncoding, RelocationFlags, RelocationKind, SectionKind},
+    ObjectProduct,
+};
+
+use crate::code_generator::Generator;
+
+// The patchable function entry
+// ----------------------------
+//
+// It is similar to the option `-fpatchable-function-entry=N` of GCC and Clang,
+// a number of NOP bytes are inserted at the entry of each function (i.e. before
+// the prologue), so the runtime tracing/live-patching frameworks can overwrite
+// them with a jump later.
+//
+// the layout of a function:
+//
+// ```text
+// function symbol -> | NOP ... NOP |   <-- the patchable entry
+//                    | prologue    |
+//                    | body        |
+// ```
+//
+// the addresses of the patchable entries are recorded in the section
+// `__patchable_function_entries` of the object file (one pointer per function),
+// which is the same as GCC, and the address of the patchable entry of a JIT
+// function is the address of the function itself.
+//
+// Note that the offsets of the source map, the debug information and the
+// listing include the patchable entry, but the FDE of the unwind information
+// does not cover it.
+//
+// ref:
+// - https://gcc.gnu.org/onlinedocs/gcc/Instrumentation-Options.html#index-fpatchable-function-entry
+
+const PATCHABLE_FUNCTION_ENTRIES_SECTION_NAME: &str = "__patchable_function_entries";
+
+/// Get the encoding of the (shortest) NOP instruction of the target.
+fn get_nop_instruction(isa: &dyn TargetIsa) -> Option<&'static [u8]> {
+    match isa.name() {
+        "x64" => Some(&[0x90]),
+        // `hint #0`
+        "aarch64" => Some(&[0x1f, 0x20, 0x03, 0xd5]),
+        // `addi x0, x0, 0`
+        "riscv64" => Some(&[0x13, 0x00, 0x00, 0x00]),
+        // `bcr 0, %r0`
+        "s390x" => Some(&[0x07, 0x00]),
+        _ => None,
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Insert `size` bytes of NOPs at the entry of each function which is defined
+    /// after calling this method.
+    ///
+    /// The size should be a multiple of the size of the NOP instruction
+    /// of the target, e.g. 4 for AArch64 and RISC-V.
+    pub fn enable_patchable_function_entry(&mut self, size: usize) -> Result<(), ModuleError> {
+        let isa = self.module.isa();
+
+        let nop = get_nop_instruction(isa).ok_or_else(|| {
+            ModuleError::Backend(anyhow::anyhow!(
+                "The patchable function entry is not supported on \"{}\".",
+                isa.triple()
+            ))
+        })?;
+
+        if !size.is_multiple_of(nop.len()) {
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "The size of the patchable function entry should be a multiple of {}.",
+                nop.len()
+            )));
+        }
+
+        self.patchable_entry = nop.repeat(size / nop.len());
+        Ok(())
+    }
+}
+
+/// Generate the section `__patchable_function_entries` for the given functions.
+pub(crate) fn write_patchable_entries_to_object(
+    product: &mut ObjectProduct,
+    func_ids: &[FuncId],
+    pointer_bytes: usize,
+) {
+    let symbols = func_ids
+        .iter()
+        .filter(|func_id| matches!(product.functions[**func_id], Some((_, true))))
+        .map(|func_id| product.function_symbol(*func_id))
+        .collect::<Vec<_>>();
+
+    if symbols.is_empty() {
+        return;
+    }
+
+    let object = &mut product.object;
+    let section_id = object.add_section(
+        vec![],
+        PATCHABLE_FUNCTION_ENTRIES_SECTION_NAME.as_bytes().to_vec(),
+        SectionKind::Data,
+    );
+    object.set_section_data(
+        section_id,
+        vec![0; symbols.len() * pointer_bytes],
+        pointer_bytes as u64,
+    );
+
+    for (idx, symbol) in symbols.into_iter().enumerate() {
+        object
+            .add_relocation(
+                section_id,
+                Relocation {
+                    offset: (idx * pointer_bytes) as u64,
+                    symbol,
+                    addend: 0,
+                    flags: RelocationFlags::Generic {
+                        kind: RelocationKind::Absolute,
+                        encoding: RelocationEncoding::Generic,
+                        size: (pointer_bytes * 8) as u8,
+                    },
+                },
+            )
+            .expect("failed to add relocation for the patchable entries");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::{
+        object::{read::elf::ElfFile64, Endianness, Object, ObjectSection, ObjectSymbol},
+        ObjectModule,
+    };
+
+    use crate::{code_generator::Generator, test_support::run_executable_binary_and_get_exit_code};
+
+    #[test]
+    fn test_patchable_function_entry() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        assert!(generator.enable_patchable_function_entry(5).is_ok());
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.ins().iconst(types::I32, 11);
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        let function_source_map = generator.source_map.get_function(func_main_id).unwrap();
+        assert_eq!(
+            function_source_map.code_size,
+            generator.function_sizes[0].code_size
+        );
+
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        let object_file = ElfFile64::<Endianness>::parse(module_binary.as_slice()).unwrap();
+        let symbol_main = object_file
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("main") && symbol.is_definition())
+            .unwrap();
+        let text = object_file
+            .section_by_index(symbol_main.section_index().unwrap())
+            .unwrap();
+        let start = symbol_main.address() as usize;
+        assert_eq!(&text.data().unwrap()[start..start + 5], &[0x90; 5]);
+
+        let section = object_file
+            .section_by_name("__patchable_function_entries")
+            .unwrap();
+        assert_eq!(section.size(), 8);
+        assert_eq!(section.relocations().count(), 1);
+
+        let exit_code_opt = run_executable_binary_and_get_exit_code(
+            &module_binary,
+            "test_patchable_function_entry",
+            false,
+        );
+        assert_eq!(exit_code_opt, Some(11));
+    }
+}
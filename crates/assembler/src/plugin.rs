@@ -0,0 +1,440 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::ffi::{c_char, CStr};
+
+use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, Signature, UserFuncName};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module, ModuleError};
+use cranelift_object::ObjectModule;
+
+use crate::{
+    ast, code_generator::Generator, data_bytes::ToDataBytes, lowering::AssembledModule,
+    module_manifest::ModuleManifest, validation::SymbolReferences,
+};
+
+// The plugin
+// ----------
+//
+// A plugin is a shared library (see `LinkerMode::Shared`) which is loaded by
+// the host application at runtime, i.e. `dlopen()`, the function
+// `define_plugin_entries()` generates the entry table of the plugin from the
+// exports of the module, so the host finds the functions by one symbol with
+// a stable ABI, e.g.
+//
+// ```rust
+// let assembled_module = assemble_module(&module, &mut generator)?;
+// generator.define_plugin_entries(&module, &assembled_module, "1.0.0")?;
+// ```
+//
+// and `$ anasm build --plugin` builds the plugin in one step.
+//
+// the exported symbols:
+//
+// - `__anna_plugin_init() -> i32`: calls the exported function `plugin_init`
+//   of the module (if any, it should be `() -> i32`), 0 means success.
+// - `__anna_plugin_fini()`: calls the exported function `plugin_fini` (if
+//   any, it should be `()`).
+// - `__anna_plugin_describe() -> *const c_char`: the module manifest (see
+//   `module_manifest.rs`), i.e. the name, the version and the signatures of
+//   the exported functions.
+// - `__anna_plugin`: the entry table, i.e. `PluginEntryTable`.
+//
+// the entry table consists of the pointer-sized words:
+//
+// ```text
+// abi_version      ; `PLUGIN_ABI_VERSION`
+// init             ; the address of `__anna_plugin_init`
+// fini             ; the address of `__anna_plugin_fini`
+// describe         ; the address of `__anna_plugin_describe`
+// function_count   ; the number of the exported functions
+// functions        ; the address of the array of `{name, address}`
+// ```
+//
+// the host checks the ABI version first, then calls `init`, looks up the
+// functions (the signatures are in the manifest), and calls `fini` before
+// `dlclose()`.
+
+pub const PLUGIN_ABI_VERSION: usize = 1;
+
+pub const PLUGIN_ENTRY_TABLE_NAME: &str = "__anna_plugin";
+pub const PLUGIN_INIT_FUNCTION_NAME: &str = "__anna_plugin_init";
+pub const PLUGIN_FINI_FUNCTION_NAME: &str = "__anna_plugin_fini";
+pub const PLUGIN_DESCRIBE_FUNCTION_NAME: &str = "__anna_plugin_describe";
+
+/// The exported function of the module which is called by `__anna_plugin_init`.
+pub const PLUGIN_INIT_HOOK_NAME: &str = "plugin_init";
+
+/// The exported function of the module which is called by `__anna_plugin_fini`.
+pub const PLUGIN_FINI_HOOK_NAME: &str = "plugin_fini";
+
+const PLUGIN_MANIFEST_DATA_NAME: &str = "__anna_plugin_manifest";
+const PLUGIN_FUNCTION_NAMES_DATA_NAME: &str = "__anna_plugin_function_names";
+const PLUGIN_FUNCTIONS_DATA_NAME: &str = "__anna_plugin_functions";
+
+/// The entry table of the plugin, the layout is the same as the
+/// data `__anna_plugin` of the plugin for the host target.
+#[repr(C)]
+pub struct PluginEntryTable {
+    pub abi_version: usize,
+    pub init: extern "C" fn() -> i32,
+    pub fini: extern "C" fn(),
+    pub describe: extern "C" fn() -> *const c_char,
+    pub function_count: usize,
+    pub functions: *const PluginFunction,
+}
+
+#[repr(C)]
+pub struct PluginFunction {
+    /// The NUL-terminated name.
+    pub name: *const c_char,
+    pub address: *const u8,
+}
+
+impl PluginEntryTable {
+    /// Read and parse the manifest of the plugin.
+    ///
+    /// # Safety
+    ///
+    /// The table should be the entry table of a loaded plugin.
+    pub unsafe fn get_manifest(&self) -> Result<ModuleManifest, String> {
+        let text = CStr::from_ptr((self.describe)());
+        ModuleManifest::from_text(&text.to_string_lossy())
+    }
+
+    /// Find the address of the exported function.
+    ///
+    /// # Safety
+    ///
+    /// The table should be the entry table of a loaded plugin.
+    pub unsafe fn find_function(&self, name: &str) -> Option<*const u8> {
+        std::slice::from_raw_parts(self.functions, self.function_count)
+            .iter()
+            .find(|function| CStr::from_ptr(function.name).to_bytes() == name.as_bytes())
+            .map(|function| function.address)
+    }
+}
+
+impl Generator<ObjectModule> {
+    /// Define the plugin functions and the entry table `__anna_plugin` for
+    /// the exports of the assembled module.
+    pub fn define_plugin_entries(
+        &mut self,
+        module: &ast::Module,
+        assembled_module: &AssembledModule,
+        version: &str,
+    ) -> Result<(), ModuleError> {
+        let pointer_type = self.module.isa().pointer_type();
+        let pointer_bytes = pointer_type.bytes() as usize;
+        let manifest = ModuleManifest::from_module(module, version);
+
+        // the functions
+        let mut init_sig = self.module.make_signature();
+        init_sig.returns.push(AbiParam::new(types::I32));
+        let fini_sig = self.module.make_signature();
+        let mut describe_sig = self.module.make_signature();
+        describe_sig.returns.push(AbiParam::new(pointer_type));
+
+        let init_hook_id = self.get_plugin_hook(
+            &manifest,
+            assembled_module,
+            PLUGIN_INIT_HOOK_NAME,
+            &init_sig,
+        )?;
+        let fini_hook_id = self.get_plugin_hook(
+            &manifest,
+            assembled_module,
+            PLUGIN_FINI_HOOK_NAME,
+            &fini_sig,
+        )?;
+
+        let init_id =
+            self.define_plugin_function(PLUGIN_INIT_FUNCTION_NAME, init_sig, init_hook_id, None)?;
+        let fini_id =
+            self.define_plugin_function(PLUGIN_FINI_FUNCTION_NAME, fini_sig, fini_hook_id, None)?;
+
+        let mut manifest_text = manifest.to_text().into_bytes();
+        manifest_text.push(0);
+        let manifest_data_id =
+            self.define_plugin_data(PLUGIN_MANIFEST_DATA_NAME, manifest_text, 1, |_, _| {})?;
+        let describe_id = self.define_plugin_function(
+            PLUGIN_DESCRIBE_FUNCTION_NAME,
+            describe_sig,
+            None,
+            Some(manifest_data_id),
+        )?;
+
+        // the array of `{name, address}`, the names are in one data object
+        let mut names = vec![];
+        let mut name_offsets = vec![];
+        for function in &manifest.functions {
+            name_offsets.push(names.len() as i64);
+            names.extend_from_slice(function.name.as_bytes());
+            names.push(0);
+        }
+        names.push(0);
+        let names_data_id =
+            self.define_plugin_data(PLUGIN_FUNCTION_NAMES_DATA_NAME, names, 1, |_, _| {})?;
+
+        let func_ids = manifest
+            .functions
+            .iter()
+            .map(|function| assembled_module.get_function_id(&function.name).unwrap())
+            .collect::<Vec<_>>();
+
+        // the data object can not be empty
+        let functions_data_id = self.define_plugin_data(
+            PLUGIN_FUNCTIONS_DATA_NAME,
+            vec![0; (func_ids.len() * 2).max(1) * pointer_bytes],
+            pointer_bytes as u64,
+            |module, data_description| {
+                let names_gv = module.declare_data_in_data(names_data_id, data_description);
+                for (index, func_id) in func_ids.iter().enumerate() {
+                    let offset = (index * 2 * pointer_bytes) as u32;
+                    let func_ref = module.declare_func_in_data(*func_id, data_description);
+                    data_description.write_data_addr(offset, names_gv, name_offsets[index]);
+                    data_description.write_function_addr(offset + pointer_bytes as u32, func_ref);
+                }
+            },
+        )?;
+
+        // the entry table
+        let endianness = self.module.isa().endianness();
+        let mut entry_table = vec![];
+        for word in [PLUGIN_ABI_VERSION, 0, 0, 0, func_ids.len(), 0] {
+            if pointer_bytes == 8 {
+                (word as u64).write_data_bytes(endianness, &mut entry_table);
+            } else {
+                (word as u32).write_data_bytes(endianness, &mut entry_table);
+            }
+        }
+
+        let entry_table_id =
+            self.module
+                .declare_data(PLUGIN_ENTRY_TABLE_NAME, Linkage::Export, false, false)?;
+        self.define_plugin_data_content(
+            entry_table_id,
+            entry_table,
+            pointer_bytes as u64,
+            |module, data_description| {
+                for (index, func_id) in [(1, init_id), (2, fini_id), (3, describe_id)] {
+                    let func_ref = module.declare_func_in_data(func_id, data_description);
+                    data_description.write_function_addr((index * pointer_bytes) as u32, func_ref);
+                }
+                let functions_gv = module.declare_data_in_data(functions_data_id, data_description);
+                data_description.write_data_addr((5 * pointer_bytes) as u32, functions_gv, 0);
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Get the hook function (i.e. `plugin_init` and `plugin_fini`) if it is
+    /// exported, and check its signature.
+    fn get_plugin_hook(
+        &self,
+        manifest: &ModuleManifest,
+        assembled_module: &AssembledModule,
+        name: &str,
+        expected_sig: &Signature,
+    ) -> Result<Option<FuncId>, ModuleError> {
+        if !manifest
+            .functions
+            .iter()
+            .any(|function| function.name == name)
+        {
+            return Ok(None);
+        }
+
+        let func_id = assembled_module.get_function_id(name).unwrap();
+        let decl = self.module.declarations().get_function_decl(func_id);
+        if decl.signature != *expected_sig {
+            return Err(ModuleError::IncompatibleSignature(
+                decl.linkage_name(func_id).into_owned(),
+                expected_sig.clone(),
+                decl.signature.clone(),
+            ));
+        }
+        Ok(Some(func_id))
+    }
+
+    /// Define the exported function which calls the hook (if any), or
+    /// returns the address of the data (if any), otherwise returns 0 (if
+    /// there are results).
+    fn define_plugin_function(
+        &mut self,
+        name: &str,
+        sig: Signature,
+        hook_id: Option<FuncId>,
+        data_id: Option<DataId>,
+    ) -> Result<FuncId, ModuleError> {
+        let func_id = self.module.declare_function(name, Linkage::Export, &sig)?;
+        let mut func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+        let hook_ref = hook_id.map(|hook_id| self.module.declare_func_in_func(hook_id, &mut func));
+        let data_gv = data_id.map(|data_id| self.module.declare_data_in_func(data_id, &mut func));
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func, &mut self.function_builder_context);
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let result_types = function_builder
+            .func
+            .signature
+            .returns
+            .iter()
+            .map(|abi_param| abi_param.value_type)
+            .collect::<Vec<_>>();
+
+        let results = if let Some(hook_ref) = hook_ref {
+            let call = function_builder.ins().call(hook_ref, &[]);
+            function_builder.inst_results(call).to_vec()
+        } else if let Some(data_gv) = data_gv {
+            vec![function_builder
+                .ins()
+                .symbol_value(result_types[0], data_gv)]
+        } else {
+            result_types
+                .iter()
+                .map(|result_type| function_builder.ins().iconst(*result_type, 0))
+                .collect()
+        };
+
+        function_builder.ins().return_(&results);
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        self.define_function(func_id, func)?;
+        Ok(func_id)
+    }
+
+    /// Define the local read-only data, the closure writes the relocations.
+    fn define_plugin_data(
+        &mut self,
+        name: &str,
+        content: Vec<u8>,
+        align: u64,
+        write_relocations: impl FnOnce(&mut ObjectModule, &mut DataDescription),
+    ) -> Result<DataId, ModuleError> {
+        let data_id = self
+            .module
+            .declare_data(name, Linkage::Local, false, false)?;
+        self.define_plugin_data_content(data_id, content, align, write_relocations)?;
+        Ok(data_id)
+    }
+
+    fn define_plugin_data_content(
+        &mut self,
+        data_id: DataId,
+        content: Vec<u8>,
+        align: u64,
+        write_relocations: impl FnOnce(&mut ObjectModule, &mut DataDescription),
+    ) -> Result<(), ModuleError> {
+        let mut data_description = DataDescription::new();
+        data_description.define(content.into_boxed_slice());
+        data_description.set_align(align);
+        write_relocations(&mut self.module, &mut data_description);
+
+        self.module.define_data(data_id, &data_description)?;
+        self.set_symbol_references(SymbolReferences::from_data(data_id, &data_description));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerMode, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        plugin::{PluginEntryTable, PLUGIN_ABI_VERSION, PLUGIN_ENTRY_TABLE_NAME},
+        test_support::TempFolder,
+    };
+
+    #[test]
+    fn test_plugin() {
+        let source = r#"
+        (module $counter
+            (data $count (read_write i32 0))
+            (function $plugin_init export (result i32)
+                (code
+                    (data_store_i32 $count (imm_i32 100))
+                    (imm_i32 0)))
+            (function $inc export (param $n i32) (result i32)
+                (code
+                    (data_store_i32 $count (add_i32 (data_load_i32 $count) (local_load $n)))
+                    (data_load_i32 $count))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator
+            .define_plugin_entries(&module, &assembled_module, "1.0.0")
+            .unwrap();
+
+        let temp_folder = TempFolder::new("plugin");
+        let object_file_path = temp_folder.file_path("counter.o");
+        let library_file_path = temp_folder.file_path("libcounter.so");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &library_file_path,
+            &LinkerOptions {
+                mode: LinkerMode::Shared,
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+
+        unsafe {
+            let library_file_path = CString::new(library_file_path).unwrap();
+            let handle = libc::dlopen(library_file_path.as_ptr(), libc::RTLD_NOW);
+            assert!(!handle.is_null());
+
+            let symbol_name = CString::new(PLUGIN_ENTRY_TABLE_NAME).unwrap();
+            let entry_table =
+                &*(libc::dlsym(handle, symbol_name.as_ptr()) as *const PluginEntryTable);
+            assert_eq!(entry_table.abi_version, PLUGIN_ABI_VERSION);
+            assert_eq!(entry_table.function_count, 2);
+
+            let manifest = entry_table.get_manifest().unwrap();
+            assert_eq!(manifest.name, "counter");
+            assert_eq!(manifest.version, "1.0.0");
+
+            assert_eq!((entry_table.init)(), 0);
+
+            let func_inc: extern "C" fn(i32) -> i32 =
+                std::mem::transmute(entry_table.find_function("inc").unwrap());
+            assert_eq!(func_inc(1), 101);
+            assert_eq!(func_inc(10), 111);
+            assert!(entry_table.find_function("count").is_none());
+
+            (entry_table.fini)();
+            assert_eq!(libc::dlclose(handle), 0);
+        }
+
+        // the hook with an incompatible signature
+        let module = parse_module(
+            "(module $bad (function $plugin_fini export (result i32) (code (imm_i32 0))))",
+        )
+        .unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        assert!(generator
+            .define_plugin_entries(&module, &assembled_module, "1.0.0")
+            .is_err());
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Display;
+
+use cranelift_codegen::{
+    binemit::Reloc,
+    ir::{ExternalName, Function},
+    FinalizedMachReloc, FinalizedRelocTarget,
+};
+use cranelift_module::{DataId, FuncId, Module, ModuleError};
+use cranelift_object::{
+    object::{
+        elf::{SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE},
+        read::File,
+        Object, ObjectSection, ObjectSymbol, RelocationKind, RelocationTarget, SectionFlags,
+        SymbolKind,
+    },
+    ObjectModule,
+};
+
+use crate::code_generator::Generator;
+
+// The position independence check
+// -------------------------------
+//
+// The code and the read-only data of a shared library are mapped at an
+// arbitrary address and shared between processes, so they must not contain
+// the absolute addresses (e.g. `R_X86_64_64`), which are patched by the dynamic
+// linker. otherwise the linker creates the text relocations (`DT_TEXTREL`,
+// i.e. the pages are made writable at load time) with a warning, or fails with
+// the message "relocation R_X86_64_32 against ... can not be used when making
+// a shared object; recompile with -fPIC", which does not tell which function
+// refers to the symbol.
+//
+// the absolute addresses come from:
+//
+// - the position-dependent code (see `Generator::new_static()`), which refers
+//   to the imported functions and data by the absolute addresses rather than
+//   the GOT entries.
+// - the sections which are added by the object post-processors (see
+//   `add_object_post_processor()`), e.g. a read-only table of the function
+//   addresses. (the read-only data objects with the addresses are placed in
+//   the writable section `.data.rel.ro` by Cranelift.)
+//
+// the functions which refer to the symbols by the absolute addresses fail
+// to define if the check is enabled, and the emitted object file (or the object
+// files which are generated by the other tools) can be checked before linking,
+// the read-only data sections which contain the absolute addresses can be
+// fixed by making them writable (the same as `.data.rel.ro` of GCC), e.g.
+//
+// ```rust
+// let mut generator = Generator::<ObjectModule>::new("number", None);
+// generator.enable_position_independence_check();
+// // ...
+// let mut binary = generator.finish()?.emit()?;
+// let references = fix_absolute_references(&mut binary)?;
+// if !references.is_empty() {
+//     // the code is not position-independent
+// }
+// ```
+//
+// ref:
+// - https://maskray.me/blog/2020-11-08-stack-unwinding#text-relocations
+// - https://www.akkadia.org/drepper/dsohowto.pdf
+
+/// The reference to a symbol by the absolute address in a section which is
+/// loaded read-only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbsoluteReference {
+    pub section: String,
+
+    /// The offset of the relocated bytes in the section.
+    pub offset: u64,
+
+    /// The name of the symbol, or the name of the section for the section symbols.
+    pub target: String,
+}
+
+impl Display for AbsoluteReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the section \"{}\" refers to \"{}\" by the absolute address at offset 0x{:x}",
+            self.section, self.target, self.offset
+        )
+    }
+}
+
+impl Generator<ObjectModule> {
+    /// Reject the functions which refer to the symbols by the absolute addresses,
+    /// i.e. the objects of the shared libraries.
+    pub fn enable_position_independence_check(&mut self) {
+        self.position_independence_check = true;
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Check the relocations of the compiled function (or the machine code
+    /// function) if the check is enabled.
+    pub(crate) fn check_position_independence(
+        &self,
+        func_id: FuncId,
+        func_source: &Function,
+        relocs: &[FinalizedMachReloc],
+    ) -> Result<(), ModuleError> {
+        if !self.position_independence_check {
+            return Ok(());
+        }
+
+        let declarations = self.module.declarations();
+        let user_named_funcs = func_source.params.user_named_funcs();
+
+        for reloc in relocs {
+            if !matches!(reloc.kind, Reloc::Abs4 | Reloc::Abs8) {
+                continue;
+            }
+
+            let target = match &reloc.target {
+                FinalizedRelocTarget::ExternalName(ExternalName::User(name_ref)) => {
+                    let user_name = &user_named_funcs[*name_ref];
+                    if user_name.namespace == 0 {
+                        let target_id = FuncId::from_u32(user_name.index);
+                        declarations
+                            .get_function_decl(target_id)
+                            .linkage_name(target_id)
+                            .into_owned()
+                    } else {
+                        let target_id = DataId::from_u32(user_name.index);
+                        declarations
+                            .get_data_decl(target_id)
+                            .linkage_name(target_id)
+                            .into_owned()
+                    }
+                }
+                FinalizedRelocTarget::ExternalName(name) => name.display(None).to_string(),
+                FinalizedRelocTarget::Func(_) => declarations
+                    .get_function_decl(func_id)
+                    .linkage_name(func_id)
+                    .into_owned(),
+            };
+
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "the function \"{}\" refers to \"{}\" by the absolute address, which is not position-independent",
+                declarations.get_function_decl(func_id).linkage_name(func_id),
+                target
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Check whether the section is loaded, and it is neither writable nor executable
+/// (i.e. the text relocations in it can be avoided by making it writable).
+fn is_read_only_data_section(flags: SectionFlags) -> bool {
+    matches!(flags, SectionFlags::Elf { sh_flags }
+        if sh_flags & SHF_ALLOC as u64 != 0
+            && sh_flags & (SHF_WRITE | SHF_EXECINSTR) as u64 == 0)
+}
+
+/// Find the references by the absolute addresses in the sections which are
+/// loaded read-only (i.e. the code and the read-only data) of the object file,
+/// in the order of sections and offsets.
+pub fn find_absolute_references(object_binary: &[u8]) -> Result<Vec<AbsoluteReference>, String> {
+    let file = File::parse(object_binary)
+        .map_err(|error| format!("failed to read the object file: {}", error))?;
+
+    let mut references = vec![];
+    for section in file.sections() {
+        let SectionFlags::Elf { sh_flags } = section.flags() else {
+            continue;
+        };
+        if sh_flags & SHF_ALLOC as u64 == 0 || sh_flags & SHF_WRITE as u64 != 0 {
+            continue;
+        }
+
+        let section_name = section.name().unwrap_or_default();
+        let mut section_references = section
+            .relocations()
+            .filter(|(_, relocation)| relocation.kind() == RelocationKind::Absolute)
+            .map(|(offset, relocation)| {
+                let target = match relocation.target() {
+                    RelocationTarget::Symbol(symbol_index) => {
+                        let symbol = file
+                            .symbol_by_index(symbol_index)
+                            .map_err(|error| error.to_string())?;
+                        match symbol.section_index() {
+                            Some(section_index) if symbol.kind() == SymbolKind::Section => file
+                                .section_by_index(section_index)
+                                .and_then(|section| section.name().map(str::to_owned))
+                                .map_err(|error| error.to_string())?,
+                            _ => symbol.name().unwrap_or_default().to_owned(),
+                        }
+                    }
+                    _ => String::new(),
+                };
+
+                Ok(AbsoluteReference {
+                    section: section_name.to_owned(),
+                    offset,
+                    target,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        section_references.sort_by_key(|reference| reference.offset);
+        references.append(&mut section_references);
+    }
+
+    Ok(references)
+}
+
+/// Make the read-only data sections which contain the absolute addresses
+/// writable (i.e. set the flag `SHF_WRITE`), returns the absolute references
+/// which can not be fixed, i.e. the references in the code.
+pub fn fix_absolute_references(object_binary: &mut [u8]) -> Result<Vec<AbsoluteReference>, String> {
+    let (section_indices, is_64, is_little_endian) = {
+        let file = File::parse(&*object_binary)
+            .map_err(|error| format!("failed to read the object file: {}", error))?;
+        let section_indices = file
+            .sections()
+            .filter(|section| {
+                is_read_only_data_section(section.flags())
+                    && section
+                        .relocations()
+                        .any(|(_, relocation)| relocation.kind() == RelocationKind::Absolute)
+            })
+            .map(|section| section.index().0)
+            .collect::<Vec<_>>();
+        (section_indices, file.is_64(), file.is_little_endian())
+    };
+
+    let read = |binary: &[u8], offset: usize, length: usize| {
+        let mut bytes = [0u8; 8];
+        bytes[..length].copy_from_slice(&binary[offset..offset + length]);
+        if !is_little_endian {
+            bytes[..length].reverse();
+        }
+        u64::from_le_bytes(bytes)
+    };
+
+    // the offsets of `e_shoff`, `e_shentsize` and `sh_flags`, see `Elf64_Ehdr`,
+    // `Elf32_Ehdr`, `Elf64_Shdr` and `Elf32_Shdr`.
+    let (section_headers_offset, entry_size, flags_size) = if is_64 {
+        (
+            read(object_binary, 0x28, 8),
+            read(object_binary, 0x3a, 2),
+            8,
+        )
+    } else {
+        (
+            read(object_binary, 0x20, 4),
+            read(object_binary, 0x2e, 2),
+            4,
+        )
+    };
+
+    for section_index in section_indices {
+        let offset = (section_headers_offset + section_index as u64 * entry_size + 8) as usize;
+        let flags = read(object_binary, offset, flags_size) | SHF_WRITE as u64;
+        let bytes = if is_little_endian {
+            flags.to_le_bytes()
+        } else {
+            flags.to_be_bytes()
+        };
+        let bytes = if is_little_endian {
+            &bytes[..flags_size]
+        } else {
+            &bytes[8 - flags_size..]
+        };
+        object_binary[offset..offset + flags_size].copy_from_slice(bytes);
+    }
+
+    find_absolute_references(object_binary)
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::{
+        object::{
+            elf::{DT_TEXTREL, SHF_WRITE},
+            read::File,
+            write::Relocation,
+            Object, ObjectSection, RelocationEncoding, RelocationFlags, RelocationKind,
+            SectionFlags, SectionKind,
+        },
+        ObjectModule, ObjectProduct,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerMode, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        position_independence::{
+            find_absolute_references, fix_absolute_references, AbsoluteReference,
+        },
+        test_support::TempFolder,
+    };
+
+    /// Check whether the shared library has the text relocations.
+    fn has_text_relocations(binary: &[u8]) -> bool {
+        let file = File::parse(binary).unwrap();
+        let data = file.section_by_name(".dynamic").unwrap().data().unwrap();
+        data.chunks_exact(16)
+            .any(|entry| u64::from_le_bytes(entry[..8].try_into().unwrap()) == DT_TEXTREL as u64)
+    }
+
+    #[test]
+    fn test_position_independence() {
+        let source = r#"
+        (module $number
+            (import (data $counter (size 4)))
+            (import (function $abs (param i32) (result i32)))
+            (function $get_number export (result i32)
+                (code (call $abs (data_load_i32 $counter)))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let temp_folder = TempFolder::new("position_independence");
+        let link_shared = |binary: &[u8]| {
+            let object_file_path = temp_folder.file_path("number.o");
+            let library_file_path = temp_folder.file_path("libnumber.so");
+            std::fs::write(&object_file_path, binary).unwrap();
+            link_executable(
+                &[&object_file_path],
+                &library_file_path,
+                &LinkerOptions {
+                    mode: LinkerMode::Shared,
+                    ..LinkerOptions::default()
+                },
+            )
+            .unwrap();
+            std::fs::read(&library_file_path).unwrap()
+        };
+
+        // the position-dependent code refers to the imports by the absolute addresses
+        let mut generator = Generator::<ObjectModule>::new_static(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        let mut binary = generator.finish().unwrap().emit().unwrap();
+
+        let references = find_absolute_references(&binary).unwrap();
+        assert_eq!(
+            references
+                .iter()
+                .map(|reference| (reference.section.as_str(), reference.target.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(".text", "counter"), (".text", "abs")]
+        );
+        assert!(references[0]
+            .to_string()
+            .starts_with("the section \".text\" refers to \"counter\" by the absolute address"));
+
+        // the code can not be fixed
+        assert_eq!(fix_absolute_references(&mut binary).unwrap(), references);
+        assert!(has_text_relocations(&link_shared(&binary)));
+
+        let mut generator = Generator::<ObjectModule>::new_static(&module.name, None);
+        generator.enable_position_independence_check();
+        assert_eq!(
+            assemble_module(&module, &mut generator)
+                .unwrap_err()
+                .message,
+            "Backend error: the function \"get_number\" refers to \"counter\" by the absolute address, which is not position-independent"
+        );
+
+        // the position-independent code with a read-only table of the function addresses
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        generator.enable_position_independence_check();
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let func_id = assembled_module.get_function_id("get_number").unwrap();
+
+        generator.add_object_post_processor(move |product: &mut ObjectProduct| {
+            let symbol_id = product.function_symbol(func_id);
+            let object = &mut product.object;
+            let section_id =
+                object.add_section(vec![], b".anna.table".to_vec(), SectionKind::ReadOnlyData);
+            let offset = object.append_section_data(section_id, &[0; 8], 8);
+            object
+                .add_relocation(
+                    section_id,
+                    Relocation {
+                        offset,
+                        symbol: symbol_id,
+                        addend: 0,
+                        flags: RelocationFlags::Generic {
+                            kind: RelocationKind::Absolute,
+                            encoding: RelocationEncoding::Generic,
+                            size: 64,
+                        },
+                    },
+                )
+                .unwrap();
+        });
+
+        let mut binary = generator.finish().unwrap().emit().unwrap();
+        assert_eq!(
+            find_absolute_references(&binary).unwrap(),
+            vec![AbsoluteReference {
+                section: ".anna.table".to_owned(),
+                offset: 0,
+                target: "get_number".to_owned(),
+            }]
+        );
+
+        // the table is made writable
+        assert_eq!(fix_absolute_references(&mut binary).unwrap(), vec![]);
+        let file = File::parse(binary.as_slice()).unwrap();
+        let SectionFlags::Elf { sh_flags } = file.section_by_name(".anna.table").unwrap().flags()
+        else {
+            unreachable!()
+        };
+        assert_ne!(sh_flags & SHF_WRITE as u64, 0);
+        assert!(!has_text_relocations(&link_shared(&binary)));
+    }
+}
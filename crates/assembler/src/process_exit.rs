@@ -0,0 +1,319 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{
+    types, AbiParam, FuncRef, Function, InstBuilder, Signature, TrapCode, UserFuncName, Value,
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{FuncId, FuncOrDataId, Linkage, Module, ModuleError};
+
+use crate::{code_generator::Generator, validation::SymbolReferences};
+
+// The process exit
+// ----------------
+//
+// The program terminates itself in the middle of a function by `exit(code)`
+// and `abort()`, instead of returning the exit code all the way up to `main()`,
+// i.e. the instructions `(exit code)` and `(abort)`, or `emit_exit()` and
+// `emit_abort()` for the front ends, e.g.
+//
+// ```rust
+// let exit_refs = generator.declare_exit_in_func(&mut func)?;
+// let mut function_builder = FunctionBuilder::new(&mut func, ...);
+// ...
+// exit_refs.emit_exit(&mut function_builder, value_code);
+// ```
+//
+// the functions are implemented by:
+//
+// - libc: `exit(code)` and `abort()` of libc (the default), so the `atexit()`
+//   handlers are run and the streams are flushed.
+// - syscall: the local functions `__anna_exit` and `__anna_abort` which invoke
+//   the system calls directly, for the freestanding executables (see
+//   `LinkerMode::Freestanding`), `abort()` sends `SIGABRT` to the process by
+//   `kill(getpid(), SIGABRT)`, and exits with 134 (i.e. 128 + SIGABRT) if the
+//   signal is ignored.
+//
+// the calls do not return, so they are followed by a `trap`, which terminates
+// the block and Cranelift removes the unreachable code after them, e.g.
+//
+// ```clif
+// call fn0(v1)
+// trap user5
+// ```
+//
+// ref:
+// - https://man7.org/linux/man-pages/man3/exit.3.html
+// - https://man7.org/linux/man-pages/man3/abort.3.html
+// - https://man7.org/linux/man-pages/man2/exit_group.2.html
+
+/// The trap code after the calls of `exit()` and `abort()`, which is never reached.
+pub const TRAP_CODE_UNREACHABLE: TrapCode = TrapCode::unwrap_user(5);
+
+pub const SYSCALL_EXIT_FUNCTION_NAME: &str = "__anna_exit";
+pub const SYSCALL_ABORT_FUNCTION_NAME: &str = "__anna_abort";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExitMode {
+    /// `exit(code)` and `abort()` of libc.
+    #[default]
+    Libc,
+
+    /// The system calls `exit_group` and `kill`.
+    Syscall,
+}
+
+/// The references of the exit functions in a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitRefs {
+    pub exit: FuncRef,
+    pub abort: FuncRef,
+}
+
+/// The machine code of the syscall function `__anna_exit(code)` or `__anna_abort()`.
+fn get_syscall_code(isa_name: &str, func_name: &str) -> Option<&'static [u8]> {
+    match (isa_name, func_name) {
+        ("x64", SYSCALL_EXIT_FUNCTION_NAME) => Some(&[
+            0xb8, 0xe7, 0x00, 0x00, 0x00, // mov eax, 231 (exit_group)
+            0x0f, 0x05, // syscall
+            0x0f, 0x0b, // ud2
+        ]),
+        ("x64", SYSCALL_ABORT_FUNCTION_NAME) => Some(&[
+            0xb8, 0x27, 0x00, 0x00, 0x00, // mov eax, 39 (getpid)
+            0x0f, 0x05, // syscall
+            0x89, 0xc7, // mov edi, eax
+            0xbe, 0x06, 0x00, 0x00, 0x00, // mov esi, 6 (SIGABRT)
+            0xb8, 0x3e, 0x00, 0x00, 0x00, // mov eax, 62 (kill)
+            0x0f, 0x05, // syscall
+            0xbf, 0x86, 0x00, 0x00, 0x00, // mov edi, 134
+            0xb8, 0xe7, 0x00, 0x00, 0x00, // mov eax, 231 (exit_group)
+            0x0f, 0x05, // syscall
+            0x0f, 0x0b, // ud2
+        ]),
+        ("aarch64", SYSCALL_EXIT_FUNCTION_NAME) => Some(&[
+            0xc8, 0x0b, 0x80, 0xd2, // mov x8, #94 (exit_group)
+            0x01, 0x00, 0x00, 0xd4, // svc #0
+            0x00, 0x00, 0x20, 0xd4, // brk #0
+        ]),
+        ("aarch64", SYSCALL_ABORT_FUNCTION_NAME) => Some(&[
+            0x88, 0x15, 0x80, 0xd2, // mov x8, #172 (getpid)
+            0x01, 0x00, 0x00, 0xd4, // svc #0
+            0xc1, 0x00, 0x80, 0xd2, // mov x1, #6 (SIGABRT)
+            0x28, 0x10, 0x80, 0xd2, // mov x8, #129 (kill)
+            0x01, 0x00, 0x00, 0xd4, // svc #0
+            0xc0, 0x10, 0x80, 0xd2, // mov x0, #134
+            0xc8, 0x0b, 0x80, 0xd2, // mov x8, #94 (exit_group)
+            0x01, 0x00, 0x00, 0xd4, // svc #0
+            0x00, 0x00, 0x20, 0xd4, // brk #0
+        ]),
+        _ => None,
+    }
+}
+
+/// Declare (or define, in the syscall mode) the function `exit(code: i32)`.
+pub(crate) fn declare_exit_function<T: Module>(
+    module: &mut T,
+    exit_mode: ExitMode,
+) -> Result<FuncId, ModuleError> {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+
+    match exit_mode {
+        ExitMode::Libc => module.declare_function("exit", Linkage::Import, &sig),
+        ExitMode::Syscall => define_syscall_function(module, SYSCALL_EXIT_FUNCTION_NAME, sig),
+    }
+}
+
+/// Declare (or define, in the syscall mode) the function `abort()`.
+pub(crate) fn declare_abort_function<T: Module>(
+    module: &mut T,
+    exit_mode: ExitMode,
+) -> Result<FuncId, ModuleError> {
+    let sig = module.make_signature();
+
+    match exit_mode {
+        ExitMode::Libc => module.declare_function("abort", Linkage::Import, &sig),
+        ExitMode::Syscall => define_syscall_function(module, SYSCALL_ABORT_FUNCTION_NAME, sig),
+    }
+}
+
+/// Define the local function by the machine code once, the later calls
+/// return the defined one.
+fn define_syscall_function<T: Module>(
+    module: &mut T,
+    name: &str,
+    sig: Signature,
+) -> Result<FuncId, ModuleError> {
+    if let Some(FuncOrDataId::Func(func_id)) = module.get_name(name) {
+        return Ok(func_id);
+    }
+
+    let isa = module.isa();
+    let code = get_syscall_code(isa.name(), name).ok_or_else(|| {
+        ModuleError::Backend(anyhow::anyhow!(
+            "The system calls are not supported on \"{}\".",
+            isa.triple()
+        ))
+    })?;
+
+    let func_id = module.declare_function(name, Linkage::Local, &sig)?;
+    let func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    module.define_function_bytes(func_id, &func, 16, code, &[])?;
+    Ok(func_id)
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Set the implementation of `exit()` and `abort()`, the default is `ExitMode::Libc`.
+    pub fn set_exit_mode(&mut self, exit_mode: ExitMode) {
+        self.exit_mode = exit_mode;
+    }
+
+    /// Declare the exit functions in the function.
+    pub fn declare_exit_in_func(&mut self, func: &mut Function) -> Result<ExitRefs, ModuleError> {
+        let exit_id = declare_exit_function(&mut self.module, self.exit_mode)?;
+        let abort_id = declare_abort_function(&mut self.module, self.exit_mode)?;
+        self.record_syscall_functions();
+
+        Ok(ExitRefs {
+            exit: self.module.declare_func_in_func(exit_id, func),
+            abort: self.module.declare_func_in_func(abort_id, func),
+        })
+    }
+
+    /// Record the definitions of the syscall functions (which are defined by
+    /// the machine code) for `validate()`.
+    pub(crate) fn record_syscall_functions(&mut self) {
+        for name in [SYSCALL_EXIT_FUNCTION_NAME, SYSCALL_ABORT_FUNCTION_NAME] {
+            if let Some(FuncOrDataId::Func(func_id)) = self.module.get_name(name) {
+                self.set_symbol_references(SymbolReferences {
+                    definition: FuncOrDataId::Func(func_id),
+                    functions: vec![],
+                    data: vec![],
+                });
+            }
+        }
+    }
+}
+
+impl ExitRefs {
+    /// Terminate the process with the exit code (an i32 value), the current
+    /// block is terminated, switch to another block to continue.
+    pub fn emit_exit(&self, function_builder: &mut FunctionBuilder, code: Value) {
+        emit_noreturn_call(function_builder, self.exit, &[code]);
+    }
+
+    /// Terminate the process abnormally (by `SIGABRT`).
+    pub fn emit_abort(&self, function_builder: &mut FunctionBuilder) {
+        emit_noreturn_call(function_builder, self.abort, &[]);
+    }
+}
+
+pub(crate) fn emit_noreturn_call(
+    function_builder: &mut FunctionBuilder,
+    func_ref: FuncRef,
+    args: &[Value],
+) {
+    function_builder.ins().call(func_ref, args);
+    function_builder.ins().trap(TRAP_CODE_UNREACHABLE);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::process::ExitStatusExt, process::Command};
+
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerMode, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        process_exit::ExitMode,
+        test_support::TempFolder,
+    };
+
+    #[test]
+    fn test_process_exit() {
+        // exits with 7, or aborts if there are arguments
+        let source = r#"
+        (module $main
+            (function $main export (param $argc i32) (param $argv i64) (param $envp i64) (result i32)
+                (code
+                    (when (ne_i32 (local_load $argc) (imm_i32 1))
+                        (abort))
+                    (exit (imm_i32 7))
+                    (imm_i32 0))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let temp_folder = TempFolder::new("process_exit");
+
+        let run = |exec_file_path: &str, args: &[&str]| {
+            let status = Command::new(exec_file_path).args(args).status().unwrap();
+            (status.code(), status.signal())
+        };
+
+        // libc
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        assert!(generator.dump_clif().contains("trap user5"));
+        assert!(generator.validate().is_ok());
+
+        let object_file_path = temp_folder.file_path("libc.o");
+        let exec_file_path = temp_folder.file_path("libc.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(run(&exec_file_path, &[]), (Some(7), None));
+        assert_eq!(run(&exec_file_path, &["foo"]), (None, Some(6)));
+
+        // syscall
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        generator.set_exit_mode(ExitMode::Syscall);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let func_wrapper_id = generator
+            .define_main_wrapper(
+                "__anna_main",
+                assembled_module.get_function_id("main").unwrap(),
+            )
+            .unwrap();
+        generator
+            .define_start_function("_start", func_wrapper_id)
+            .unwrap();
+        assert!(generator.validate().is_ok());
+
+        let object_file_path = temp_folder.file_path("syscall.o");
+        let exec_file_path = temp_folder.file_path("syscall.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions {
+                mode: LinkerMode::Freestanding,
+                library_paths: vec![],
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(run(&exec_file_path, &[]), (Some(7), None));
+        assert_eq!(run(&exec_file_path, &["foo"]), (None, Some(6)));
+    }
+}
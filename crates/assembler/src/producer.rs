@@ -0,0 +1,92 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_object::{object::SectionKind, ObjectProduct};
+
+// The producer metadata
+// ---------------------
+//
+// The name and version of the assembler and the main target options are recorded
+// in the object file, so the binaries can be traced back to the toolchain which
+// generated them:
+//
+// - the `.comment` section (like GCC and Clang do), the linker merges the `.comment`
+//   sections of all input objects, check it by `$ readelf -p .comment anna.elf`.
+// - the `DW_AT_producer` attribute of the compile unit of the debug information.
+//
+// e.g.
+//
+// "XiaoXuan Native Assembler 0.1.0 (x86_64-unknown-linux-gnu, opt_level=none, is_pic=true)"
+
+pub const PRODUCER_NAME: &str = "XiaoXuan Native Assembler";
+
+/// Get the producer string, i.e. the name, the version and the target options.
+pub fn get_producer(isa: &dyn TargetIsa) -> String {
+    let flags = isa.flags();
+    format!(
+        "{} {} ({}, opt_level={}, is_pic={})",
+        PRODUCER_NAME,
+        env!("CARGO_PKG_VERSION"),
+        isa.triple(),
+        flags.opt_level(),
+        flags.is_pic()
+    )
+}
+
+/// Write the producer string into the `.comment` section, the content is
+/// a leading '\0' followed by the null-terminated string (the same as GCC).
+pub(crate) fn write_comment_to_object(product: &mut ObjectProduct, producer: &str) {
+    let object = &mut product.object;
+    let section_id = object.add_section(vec![], b".comment".to_vec(), SectionKind::OtherString);
+
+    let mut data = vec![0];
+    data.extend_from_slice(producer.as_bytes());
+    data.push(0);
+    object.set_section_data(section_id, data, 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_module::Module;
+    use cranelift_object::ObjectModule;
+
+    use crate::{code_generator::Generator, producer::get_producer};
+
+    #[test]
+    fn test_producer() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_debug_info("main.anc", "/tmp");
+
+        let producer = get_producer(generator.module.isa());
+        assert_eq!(
+            producer,
+            format!(
+                "XiaoXuan Native Assembler {} (x86_64-unknown-linux-gnu, opt_level=none, is_pic=true)",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(generator.debug_info.as_ref().unwrap().producer, producer);
+
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        let object_file_path =
+            std::env::temp_dir().join(format!("anc_test_producer_{}.o", std::process::id()));
+        std::fs::write(&object_file_path, module_binary).unwrap();
+
+        let output = Command::new("readelf")
+            .args(["-p", ".comment"])
+            .arg(&object_file_path)
+            .output()
+            .unwrap();
+        let text = String::from_utf8_lossy(&output.stdout);
+        assert!(text.contains(&producer));
+
+        std::fs::remove_file(&object_file_path).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    cursor::{Cursor, FuncCursor},
+    ir::{Function, InstBuilder},
+};
+use cranelift_module::{Linkage, Module, ModuleError};
+use cranelift_object::ObjectModule;
+
+use crate::code_generator::Generator;
+
+// The gprof profiling
+// -------------------
+//
+// It mirrors the option `-pg` of GCC, which consists of two parts:
+//
+// 1. codegen: a call to `mcount()` (provided by glibc) is inserted at the entry of
+//    each function (after the prologue), `mcount()` records the call graph arc
+//    "caller -> callee" by the return address of itself and the return
+//    address of the current frame (so the frame pointers are required, they
+//    are always preserved by the `Generator`).
+// 2. link: the 'gcrt1.o' is used in place of 'Scrt1.o', which starts the
+//    profiling timer and writes the file 'gmon.out' at exit,
+//    see `LinkerOptions::profiling`.
+//
+// e.g.
+//
+// ```sh
+// $ ./anna.elf
+// $ gprof anna.elf gmon.out
+// ```
+//
+// ref:
+// - https://sourceware.org/binutils/docs/gprof/Implementation.html
+// - https://gcc.gnu.org/onlinedocs/gcc/Instrumentation-Options.html#index-pg
+
+const MCOUNT_FUNCTION_NAME: &str = "mcount";
+
+impl Generator<ObjectModule> {
+    /// Insert the `mcount()` calls into each function which is defined afterwards.
+    ///
+    /// The object file should be linked with `LinkerOptions::profiling` enabled.
+    pub fn enable_profiling(&mut self) -> Result<(), ModuleError> {
+        let mcount_sig = self.module.make_signature();
+        let mcount_id =
+            self.module
+                .declare_function(MCOUNT_FUNCTION_NAME, Linkage::Import, &mcount_sig)?;
+
+        self.profiling = Some(mcount_id);
+        Ok(())
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Insert the `mcount()` call before the first instruction of the function.
+    pub(crate) fn insert_profiling_call(&mut self, func: &mut Function) {
+        let Some(mcount_id) = self.profiling else {
+            return;
+        };
+
+        let Some(entry_block) = func.layout.entry_block() else {
+            return;
+        };
+
+        let mcount_ref = self.module.declare_func_in_func(mcount_id, func);
+
+        let mut cursor = FuncCursor::new(func);
+        cursor.goto_first_insertion_point(entry_block);
+        cursor.ins().call(mcount_ref, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write, process::Command};
+
+    use cranelift_codegen::ir::{
+        condcodes::IntCC, types, AbiParam, Function, InstBuilder, UserFuncName,
+    };
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerOptions},
+    };
+
+    #[test]
+    fn test_profiling() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.enable_profiling().unwrap();
+
+        // ```rust
+        // fn inc(a: i32) -> i32 {
+        //     a + 1
+        // }
+        // fn main() -> i32 {
+        //     let mut a = 0;
+        //     while a != 1000 {
+        //         a = inc(a);
+        //     }
+        //     a - 987
+        // }
+        // ```
+        let mut func_inc_sig = generator.module.make_signature();
+        func_inc_sig.params.push(AbiParam::new(types::I32));
+        func_inc_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_inc_id = generator
+            .module
+            .declare_function("inc", Linkage::Local, &func_inc_sig)
+            .unwrap();
+
+        let mut func_inc = Function::with_name_signature(
+            UserFuncName::user(0, func_inc_id.as_u32()),
+            func_inc_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_inc, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        let value_1 = function_builder.ins().iadd_imm(value_0, 1);
+        function_builder.ins().return_(&[value_1]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_inc_id, func_inc).unwrap();
+        assert!(generator.dump_clif().contains("call fn0()"));
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let func_inc_ref = generator
+            .module
+            .declare_func_in_func(func_inc_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block_0 = function_builder.create_block();
+        let block_1 = function_builder.create_block();
+        let block_2 = function_builder.create_block();
+        let block_3 = function_builder.create_block();
+        function_builder.append_block_param(block_1, types::I32);
+
+        function_builder.switch_to_block(block_0);
+        let value_0 = function_builder.ins().iconst(types::I32, 0);
+        function_builder.ins().jump(block_1, &[value_0]);
+
+        function_builder.switch_to_block(block_1);
+        let value_1 = function_builder.block_params(block_1)[0];
+        let value_2 = function_builder.ins().icmp_imm(IntCC::Equal, value_1, 1000);
+        function_builder
+            .ins()
+            .brif(value_2, block_3, &[], block_2, &[]);
+
+        function_builder.switch_to_block(block_2);
+        let call_0 = function_builder.ins().call(func_inc_ref, &[value_1]);
+        let value_3 = function_builder.inst_results(call_0)[0];
+        function_builder.ins().jump(block_1, &[value_3]);
+
+        function_builder.switch_to_block(block_3);
+        let value_4 = function_builder.ins().iadd_imm(value_1, -987);
+        function_builder.ins().return_(&[value_4]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        // link and run in a separate folder, since the 'gmon.out'
+        // is written to the current directory.
+        let folder =
+            std::env::temp_dir().join(format!("anc_test_profiling_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let object_file_path = folder.join("anna.o");
+        let exec_file_path = folder.join("anna.elf");
+
+        let mut file = File::create(&object_file_path).unwrap();
+        file.write_all(&module_binary).unwrap();
+
+        link_executable(
+            &[object_file_path.to_str().unwrap()],
+            exec_file_path.to_str().unwrap(),
+            &LinkerOptions {
+                profiling: true,
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+
+        let exit_code_opt = Command::new(&exec_file_path)
+            .current_dir(&folder)
+            .status()
+            .unwrap()
+            .code();
+        assert_eq!(exit_code_opt, Some(13));
+        assert!(folder.join("gmon.out").exists());
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{
+    types, AbiParam, Function, InstBuilder, MemFlags, Signature, UserFuncName, Value,
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{FuncId, Linkage, Module, ModuleError};
+
+use crate::code_generator::Generator;
+
+// The program arguments
+// ---------------------
+//
+// The CRT (e.g. 'Scrt1.o') calls the standard `main()` with the program
+// arguments and the environment variables, i.e.
+//
+// `extern "C" fn main(argc: i32, argv: *const *const u8, envp: *const *const u8) -> i32`
+//
+// where `argv` and `envp` are the NULL-terminated arrays of the NUL-terminated
+// strings, see `make_main_signature()`.
+//
+// the freestanding executables (see `define_start_function()`) get the
+// initial stack pointer instead, which points to `argc`, then `argv[0..argc]`,
+// `NULL`, `envp[..]` and `NULL`, the function `define_main_wrapper()` defines
+// the function which reads them from the stack and calls the standard `main()`, e.g.
+//
+// ```rust
+// let func_wrapper_id = generator.define_main_wrapper("__anna_main", func_main_id)?;
+// let func_start_id = generator.define_start_function("_start", func_wrapper_id)?;
+// ```
+//
+// so the same `main()` works in both the hosted and the freestanding executables.
+//
+// the arrays and the strings are accessed by the following operations,
+// instead of the hand-written pointer arithmetic:
+//
+// - `(string_array_item array index)`: the `array[index]`, there is no bounds check,
+//   the index should be less than `argc` (or the length of the array).
+// - `(string_array_length array)`: the number of the items before the `NULL`,
+//   e.g. the number of the environment variables.
+// - `(string_length string)`: the number of the bytes before the NUL, i.e. `strlen()`.
+//
+// e.g.
+//
+// ```clojure
+// (function $main export (param $argc i32) (param $argv i64) (param $envp i64) (result i32)
+//     (code (truncate_i64_to_i32
+//         (string_length (string_array_item (local_load $argv) (imm_i32 1))))))
+// ```
+//
+// ref:
+// - https://refspecs.linuxfoundation.org/elf/x86_64-abi-0.99.pdf (3.4 Process Initialization)
+// - https://en.cppreference.com/w/c/language/main_function
+
+/// The values of `main(argc, argv, envp)`.
+pub struct ProgramArgs {
+    /// The number of the arguments, `i32`.
+    pub argc: Value,
+
+    /// The address of the arguments, the pointer type.
+    pub argv: Value,
+
+    /// The address of the environment variables, the pointer type.
+    pub envp: Value,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// The signature of the standard `main(argc, argv, envp) -> i32`.
+    pub fn make_main_signature(&self) -> Signature {
+        let pointer_type = self.module.isa().pointer_type();
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(types::I32));
+        sig.params.push(AbiParam::new(pointer_type));
+        sig.params.push(AbiParam::new(pointer_type));
+        sig.returns.push(AbiParam::new(types::I32));
+        sig
+    }
+
+    /// Define the function `name`, i.e. `fn(stack: *const usize) -> i32`,
+    /// which reads the program arguments from the initial stack and calls
+    /// the standard `main(argc, argv, envp)`.
+    ///
+    /// The function is intended to be called by the start function, see
+    /// `define_start_function()`.
+    pub fn define_main_wrapper(
+        &mut self,
+        name: &str,
+        main_id: FuncId,
+    ) -> Result<FuncId, ModuleError> {
+        let main_sig = self.make_main_signature();
+        let main_decl = self.module.declarations().get_function_decl(main_id);
+
+        if main_decl.signature != main_sig {
+            return Err(ModuleError::IncompatibleSignature(
+                main_decl.linkage_name(main_id).into_owned(),
+                main_sig,
+                main_decl.signature.clone(),
+            ));
+        }
+
+        let pointer_type = self.module.isa().pointer_type();
+        let mut wrapper_sig = self.module.make_signature();
+        wrapper_sig.params.push(AbiParam::new(pointer_type));
+        wrapper_sig.returns.push(AbiParam::new(types::I32));
+
+        let wrapper_id = self
+            .module
+            .declare_function(name, Linkage::Local, &wrapper_sig)?;
+
+        let mut func_wrapper =
+            Function::with_name_signature(UserFuncName::user(0, wrapper_id.as_u32()), wrapper_sig);
+        let main_ref = self.module.declare_func_in_func(main_id, &mut func_wrapper);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_wrapper, &mut self.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let stack = function_builder.block_params(block)[0];
+        let program_args = emit_program_args_from_stack(&mut function_builder, stack);
+
+        let call = function_builder.ins().call(
+            main_ref,
+            &[program_args.argc, program_args.argv, program_args.envp],
+        );
+        let exit_code = function_builder.inst_results(call)[0];
+
+        function_builder.ins().return_(&[exit_code]);
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        self.define_function(wrapper_id, func_wrapper)?;
+        Ok(wrapper_id)
+    }
+}
+
+/// Read `argc`, `argv` and `envp` from the initial stack, i.e.
+/// `argv = stack + 1` and `envp = argv + argc + 1` (in the pointer size).
+pub fn emit_program_args_from_stack(
+    function_builder: &mut FunctionBuilder,
+    stack: Value,
+) -> ProgramArgs {
+    let pointer_type = function_builder.func.dfg.value_type(stack);
+    let pointer_bytes = pointer_type.bytes() as i64;
+
+    let argc_word = function_builder
+        .ins()
+        .load(pointer_type, MemFlags::trusted(), stack, 0);
+    let argc = if pointer_type == types::I32 {
+        argc_word
+    } else {
+        function_builder.ins().ireduce(types::I32, argc_word)
+    };
+
+    let argv = function_builder.ins().iadd_imm(stack, pointer_bytes);
+    let argv_bytes = function_builder.ins().imul_imm(argc_word, pointer_bytes);
+    let argv_end = function_builder.ins().iadd(argv, argv_bytes);
+    let envp = function_builder.ins().iadd_imm(argv_end, pointer_bytes);
+
+    ProgramArgs { argc, argv, envp }
+}
+
+/// Load the item `array[index]`, the index is `i32`.
+pub fn emit_string_array_item(
+    function_builder: &mut FunctionBuilder,
+    array: Value,
+    index: Value,
+) -> Value {
+    let pointer_type = function_builder.func.dfg.value_type(array);
+    let index = if pointer_type == types::I32 {
+        index
+    } else {
+        function_builder.ins().uextend(pointer_type, index)
+    };
+    let offset = function_builder
+        .ins()
+        .imul_imm(index, pointer_type.bytes() as i64);
+    let address = function_builder.ins().iadd(array, offset);
+    function_builder
+        .ins()
+        .load(pointer_type, MemFlags::new(), address, 0)
+}
+
+/// Count the items of the NULL-terminated array, the result is `i32`.
+pub fn emit_string_array_length(function_builder: &mut FunctionBuilder, array: Value) -> Value {
+    let block_loop = function_builder.create_block();
+    let block_next = function_builder.create_block();
+    let block_exit = function_builder.create_block();
+    let count = function_builder.append_block_param(block_loop, types::I32);
+    let result = function_builder.append_block_param(block_exit, types::I32);
+
+    let zero = function_builder.ins().iconst(types::I32, 0);
+    function_builder.ins().jump(block_loop, &[zero]);
+
+    function_builder.switch_to_block(block_loop);
+    let item = emit_string_array_item(function_builder, array, count);
+    function_builder
+        .ins()
+        .brif(item, block_next, &[], block_exit, &[count]);
+
+    function_builder.switch_to_block(block_next);
+    function_builder.seal_block(block_next);
+    let count_next = function_builder.ins().iadd_imm(count, 1);
+    function_builder.ins().jump(block_loop, &[count_next]);
+    function_builder.seal_block(block_loop);
+
+    function_builder.switch_to_block(block_exit);
+    function_builder.seal_block(block_exit);
+    result
+}
+
+/// Count the bytes of the NUL-terminated string, i.e. `strlen()`, the result
+/// is the pointer type.
+pub fn emit_string_length(function_builder: &mut FunctionBuilder, string: Value) -> Value {
+    let pointer_type = function_builder.func.dfg.value_type(string);
+
+    let block_loop = function_builder.create_block();
+    let block_next = function_builder.create_block();
+    let block_exit = function_builder.create_block();
+    let length = function_builder.append_block_param(block_loop, pointer_type);
+    let result = function_builder.append_block_param(block_exit, pointer_type);
+
+    let zero = function_builder.ins().iconst(pointer_type, 0);
+    function_builder.ins().jump(block_loop, &[zero]);
+
+    function_builder.switch_to_block(block_loop);
+    let address = function_builder.ins().iadd(string, length);
+    let byte = function_builder
+        .ins()
+        .uload8(types::I32, MemFlags::new(), address, 0);
+    function_builder
+        .ins()
+        .brif(byte, block_next, &[], block_exit, &[length]);
+
+    function_builder.switch_to_block(block_next);
+    function_builder.seal_block(block_next);
+    let length_next = function_builder.ins().iadd_imm(length, 1);
+    function_builder.ins().jump(block_loop, &[length_next]);
+    function_builder.seal_block(block_loop);
+
+    function_builder.switch_to_block(block_exit);
+    function_builder.seal_block(block_exit);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerMode, LinkerOptions},
+        lowering::assemble_module,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    #[test]
+    fn test_program_args() {
+        // the result is `strlen(argv[1]) + argc * 10 + count(envp) * 100`
+        let source = r#"
+        (module $main
+            (function $main export (param $argc i32) (param $argv i64) (param $envp i64) (result i32)
+                (code (add_i32
+                    (truncate_i64_to_i32
+                        (string_length (string_array_item (local_load $argv) (imm_i32 1))))
+                    (add_i32
+                        (mul_i32 (string_array_length (local_load $argv)) (imm_i32 10))
+                        (mul_i32 (string_array_length (local_load $envp)) (imm_i32 100)))))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let temp_folder = TempFolder::new("program_args");
+
+        let run = |exec_file_path: &str| {
+            Command::new(exec_file_path)
+                .arg("hello")
+                .env_clear()
+                .env("FOO", "bar")
+                .status()
+                .unwrap()
+                .code()
+        };
+
+        // hosted, i.e. called by the CRT
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+
+        let object_file_path = temp_folder.file_path("hosted.o");
+        let exec_file_path = temp_folder.file_path("hosted.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(run(&exec_file_path), Some(125));
+
+        // freestanding, i.e. called by the start function
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        let func_wrapper_id = generator
+            .define_main_wrapper(
+                "__anna_main",
+                assembled_module.get_function_id("main").unwrap(),
+            )
+            .unwrap();
+        generator
+            .define_start_function("_start", func_wrapper_id)
+            .unwrap();
+
+        let object_file_path = temp_folder.file_path("freestanding.o");
+        let exec_file_path = temp_folder.file_path("freestanding.elf");
+        std::fs::write(
+            &object_file_path,
+            generator.finish().unwrap().emit().unwrap(),
+        )
+        .unwrap();
+        link_executable(
+            &[&object_file_path],
+            &exec_file_path,
+            &LinkerOptions {
+                mode: LinkerMode::Freestanding,
+                library_paths: vec![],
+                ..LinkerOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(run(&exec_file_path), Some(125));
+    }
+}
@@ -0,0 +1,534 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+#[cfg(feature = "linker")]
+use std::path::PathBuf;
+
+use cranelift_module::{Linkage, Module, ModuleError};
+use cranelift_object::ObjectModule;
+
+use crate::code_generator::Generator;
+
+#[cfg(feature = "linker")]
+use crate::linker::{link_executable, LinkError, LinkerOptions};
+
+// The project
+// -----------
+//
+// A project consists of multiple modules (one `Generator<ObjectModule>` per module),
+// each module is emitted as an object file, and all object files are linked
+// as one executable file, e.g.
+//
+// ```rust
+// let mut project = Project::new("hello", None);
+// project.add_external_symbols(&["puts"]);
+//
+// let generator = project.add_module("main", &["format"]);
+// // ... declare and define functions of module "main"
+//
+// let generator = project.add_module("format", &[]);
+// // ... declare and define functions of module "format"
+//
+// project.build(&output_folder, &LinkerOptions::default())?;
+// ```
+//
+// the linking requires the feature `linker` (which is enabled by default),
+// `project.emit(&output_folder)` only emits the object files without it.
+//
+// before building, the symbols are resolved across the modules:
+//
+// - each imported symbol should be exported by another module
+//   (or be an external symbol, e.g. provided by the libc).
+// - each non-local symbol should be defined by only one module.
+// - the dependencies of the modules should exist and have no cycle, the modules
+//   are emitted (and passed to the linker) in the order of dependency, i.e.
+//   the dependencies first.
+
+pub struct ProjectModule {
+    pub name: String,
+
+    /// The names of the modules which this module depends on.
+    pub dependencies: Vec<String>,
+    pub generator: Generator<ObjectModule>,
+}
+
+pub struct Project {
+    pub name: String,
+    pub platform: Option<String>,
+    pub modules: Vec<ProjectModule>,
+
+    /// The symbols which are provided by the libraries, e.g. `puts` of the libc.
+    pub external_symbols: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProjectDiagnostic {
+    UnresolvedSymbol {
+        module_name: String,
+        symbol_name: String,
+    },
+    DuplicateSymbol {
+        symbol_name: String,
+        module_names: Vec<String>,
+    },
+    UnknownDependency {
+        module_name: String,
+        dependency_name: String,
+    },
+    DependencyCycle {
+        module_names: Vec<String>,
+    },
+}
+
+impl Display for ProjectDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectDiagnostic::UnresolvedSymbol {
+                module_name,
+                symbol_name,
+            } => write!(
+                f,
+                "the symbol \"{}\" imported by module \"{}\" is not exported by any module",
+                symbol_name, module_name
+            ),
+            ProjectDiagnostic::DuplicateSymbol {
+                symbol_name,
+                module_names,
+            } => write!(
+                f,
+                "the symbol \"{}\" is defined by multiple modules: {}",
+                symbol_name,
+                module_names.join(", ")
+            ),
+            ProjectDiagnostic::UnknownDependency {
+                module_name,
+                dependency_name,
+            } => write!(
+                f,
+                "the dependency \"{}\" of module \"{}\" is not found",
+                dependency_name, module_name
+            ),
+            ProjectDiagnostic::DependencyCycle { module_names } => write!(
+                f,
+                "the modules depend on each other: {}",
+                module_names.join(" -> ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Diagnostics(Vec<ProjectDiagnostic>),
+    Module(ModuleError),
+
+    /// Failed to write the debug information or emit the object file.
+    Emit(String),
+    Io(std::io::Error),
+
+    #[cfg(feature = "linker")]
+    Link(LinkError),
+}
+
+impl From<ModuleError> for ProjectError {
+    fn from(value: ModuleError) -> Self {
+        ProjectError::Module(value)
+    }
+}
+
+impl From<std::io::Error> for ProjectError {
+    fn from(value: std::io::Error) -> Self {
+        ProjectError::Io(value)
+    }
+}
+
+#[cfg(feature = "linker")]
+impl From<LinkError> for ProjectError {
+    fn from(value: LinkError) -> Self {
+        ProjectError::Link(value)
+    }
+}
+
+impl Project {
+    pub fn new(name: &str, opt_platform: Option<&str>) -> Self {
+        Self {
+            name: name.to_owned(),
+            platform: opt_platform.map(|platform| platform.to_owned()),
+            modules: vec![],
+            external_symbols: vec![],
+        }
+    }
+
+    pub fn add_external_symbols(&mut self, names: &[&str]) {
+        self.external_symbols
+            .extend(names.iter().map(|name| (*name).to_owned()));
+    }
+
+    /// Add a new module and return its generator.
+    pub fn add_module(
+        &mut self,
+        name: &str,
+        dependencies: &[&str],
+    ) -> &mut Generator<ObjectModule> {
+        self.modules.push(ProjectModule {
+            name: name.to_owned(),
+            dependencies: dependencies.iter().map(|name| (*name).to_owned()).collect(),
+            generator: Generator::<ObjectModule>::new(name, self.platform.as_deref()),
+        });
+        &mut self.modules.last_mut().unwrap().generator
+    }
+
+    pub fn get_module(&mut self, name: &str) -> Option<&mut Generator<ObjectModule>> {
+        self.modules
+            .iter_mut()
+            .find(|module| module.name == name)
+            .map(|module| &mut module.generator)
+    }
+
+    /// Check the symbols and the dependencies of the modules.
+    pub fn check(&self) -> Vec<ProjectDiagnostic> {
+        let mut diagnostics = vec![];
+
+        // symbol name -> the names of the modules which define it
+        let mut exports: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut exports_order: Vec<&str> = vec![];
+        let mut imports: Vec<(&str, &str)> = vec![];
+
+        for module in &self.modules {
+            let declarations = module.generator.module.declarations();
+            let symbols = declarations
+                .get_functions()
+                .filter_map(|(_, decl)| Some((decl.name.as_deref()?, decl.linkage)))
+                .chain(
+                    declarations
+                        .get_data_objects()
+                        .filter_map(|(_, decl)| Some((decl.name.as_deref()?, decl.linkage))),
+                );
+
+            for (name, linkage) in symbols {
+                match linkage {
+                    Linkage::Import => imports.push((&module.name, name)),
+                    Linkage::Local => {}
+                    _ => {
+                        let module_names = exports.entry(name).or_default();
+                        if module_names.is_empty() {
+                            exports_order.push(name);
+                        }
+                        module_names.push(&module.name);
+                    }
+                }
+            }
+        }
+
+        for (module_name, symbol_name) in imports {
+            if !exports.contains_key(symbol_name)
+                && !self.external_symbols.iter().any(|name| name == symbol_name)
+            {
+                diagnostics.push(ProjectDiagnostic::UnresolvedSymbol {
+                    module_name: module_name.to_owned(),
+                    symbol_name: symbol_name.to_owned(),
+                });
+            }
+        }
+
+        for symbol_name in exports_order {
+            let module_names = &exports[symbol_name];
+            if module_names.len() > 1 {
+                diagnostics.push(ProjectDiagnostic::DuplicateSymbol {
+                    symbol_name: symbol_name.to_owned(),
+                    module_names: module_names.iter().map(|name| (*name).to_owned()).collect(),
+                });
+            }
+        }
+
+        if let Err(diagnostic) = self.get_module_order() {
+            diagnostics.push(diagnostic);
+        }
+
+        diagnostics
+    }
+
+    /// Get the indices of the modules in the order of dependency, i.e. the
+    /// dependencies come before the modules which depend on them.
+    pub fn get_module_order(&self) -> Result<Vec<usize>, ProjectDiagnostic> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Unvisited,
+            Visiting,
+            Visited,
+        }
+
+        fn visit(
+            project: &Project,
+            index: usize,
+            states: &mut [State],
+            path: &mut Vec<usize>,
+            order: &mut Vec<usize>,
+        ) -> Result<(), ProjectDiagnostic> {
+            match states[index] {
+                State::Visited => return Ok(()),
+                State::Visiting => {
+                    let start = path.iter().position(|item| *item == index).unwrap();
+                    let mut module_names = path[start..]
+                        .iter()
+                        .map(|item| project.modules[*item].name.clone())
+                        .collect::<Vec<_>>();
+                    module_names.push(project.modules[index].name.clone());
+                    return Err(ProjectDiagnostic::DependencyCycle { module_names });
+                }
+                State::Unvisited => {}
+            }
+
+            states[index] = State::Visiting;
+            path.push(index);
+
+            let module = &project.modules[index];
+            for dependency_name in &module.dependencies {
+                let dependency_index = project
+                    .modules
+                    .iter()
+                    .position(|item| &item.name == dependency_name)
+                    .ok_or_else(|| ProjectDiagnostic::UnknownDependency {
+                        module_name: module.name.clone(),
+                        dependency_name: dependency_name.clone(),
+                    })?;
+                visit(project, dependency_index, states, path, order)?;
+            }
+
+            path.pop();
+            states[index] = State::Visited;
+            order.push(index);
+            Ok(())
+        }
+
+        let mut states = vec![State::Unvisited; self.modules.len()];
+        let mut order = vec![];
+        for index in 0..self.modules.len() {
+            visit(self, index, &mut states, &mut vec![], &mut order)?;
+        }
+
+        Ok(order)
+    }
+
+    /// Check the project, and emit the object files of the modules into the
+    /// output folder (i.e. `{output_folder}/{module name}.o`), it does not
+    /// require the linker, so the object files can be linked by other tools.
+    ///
+    /// Returns the paths of the object files in the order of dependency.
+    pub fn emit(self, output_folder: &Path) -> Result<Vec<String>, ProjectError> {
+        let diagnostics = self.check();
+        if !diagnostics.is_empty() {
+            return Err(ProjectError::Diagnostics(diagnostics));
+        }
+
+        let order = self
+            .get_module_order()
+            .map_err(|diagnostic| ProjectError::Diagnostics(vec![diagnostic]))?;
+
+        std::fs::create_dir_all(output_folder)?;
+
+        let mut modules = self.modules.into_iter().map(Some).collect::<Vec<_>>();
+        let mut object_file_paths = vec![];
+
+        for index in order {
+            let module = modules[index].take().unwrap();
+            let module_binary = module
+                .generator
+                .finish()
+                .map_err(|e| ProjectError::Emit(e.to_string()))?
+                .emit()
+                .map_err(|e| ProjectError::Emit(e.to_string()))?;
+
+            let object_file_path = output_folder.join(format!("{}.o", module.name));
+            std::fs::write(&object_file_path, module_binary)?;
+            object_file_paths.push(object_file_path.to_string_lossy().into_owned());
+        }
+
+        Ok(object_file_paths)
+    }
+
+    /// Emit the object files (see `emit()`), and link them as the executable
+    /// file `{output_folder}/{project name}`.
+    ///
+    /// Returns the path of the executable file.
+    #[cfg(feature = "linker")]
+    pub fn build(
+        self,
+        output_folder: &Path,
+        linker_options: &LinkerOptions,
+    ) -> Result<PathBuf, ProjectError> {
+        let exec_file_path = output_folder.join(&self.name);
+        let object_file_paths = self.emit(output_folder)?;
+
+        link_executable(
+            &object_file_paths
+                .iter()
+                .map(|path| path.as_str())
+                .collect::<Vec<_>>(),
+            &exec_file_path.to_string_lossy(),
+            linker_options,
+        )?;
+
+        Ok(exec_file_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::LinkerOptions,
+        project::{Project, ProjectDiagnostic},
+    };
+
+    /// Define the function `name` which returns `callee() + value`,
+    /// or `value` if `callee` is None.
+    fn define_function(
+        generator: &mut Generator<ObjectModule>,
+        name: &str,
+        callee: Option<&str>,
+        value: i64,
+    ) {
+        let mut func_sig = generator.module.make_signature();
+        func_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_id = generator
+            .module
+            .declare_function(name, Linkage::Export, &func_sig)
+            .unwrap();
+
+        let opt_callee_id = callee.map(|callee_name| {
+            generator
+                .module
+                .declare_function(callee_name, Linkage::Import, &func_sig)
+                .unwrap()
+        });
+
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), func_sig);
+        let opt_callee_ref = opt_callee_id
+            .map(|callee_id| generator.module.declare_func_in_func(callee_id, &mut func));
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = match opt_callee_ref {
+            Some(callee_ref) => {
+                let call_0 = function_builder.ins().call(callee_ref, &[]);
+                let value_1 = function_builder.inst_results(call_0)[0];
+                function_builder.ins().iadd_imm(value_1, value)
+            }
+            None => function_builder.ins().iconst(types::I32, value),
+        };
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_id, func).unwrap();
+    }
+
+    #[test]
+    fn test_project_check() {
+        let mut project = Project::new("test", None);
+        define_function(
+            project.add_module("main", &["number", "util"]),
+            "main",
+            Some("get_number"),
+            1,
+        );
+        define_function(
+            project.add_module("number", &["main"]),
+            "get_number",
+            None,
+            1,
+        );
+        define_function(project.add_module("number2", &[]), "get_number", None, 2);
+        define_function(
+            project.add_module("other", &[]),
+            "other",
+            Some("missing"),
+            1,
+        );
+        define_function(project.add_module("abs", &[]), "call_abs", Some("abs"), 1);
+        project.add_external_symbols(&["abs"]);
+
+        assert_eq!(
+            project.check(),
+            vec![
+                ProjectDiagnostic::UnresolvedSymbol {
+                    module_name: "other".to_owned(),
+                    symbol_name: "missing".to_owned()
+                },
+                ProjectDiagnostic::DuplicateSymbol {
+                    symbol_name: "get_number".to_owned(),
+                    module_names: vec!["number".to_owned(), "number2".to_owned()]
+                },
+                ProjectDiagnostic::DependencyCycle {
+                    module_names: vec!["main".to_owned(), "number".to_owned(), "main".to_owned()]
+                }
+            ]
+        );
+
+        project.modules[1].dependencies.clear();
+        assert_eq!(
+            project.get_module_order(),
+            Err(ProjectDiagnostic::UnknownDependency {
+                module_name: "main".to_owned(),
+                dependency_name: "util".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn test_project_build() {
+        // ```rust
+        // // module "main"
+        // pub fn main() -> i32 { get_number() + 2 }
+        //
+        // // module "number"
+        // pub fn get_number() -> i32 { 11 }
+        // ```
+        let mut project = Project::new("anna.elf", None);
+        define_function(
+            project.add_module("main", &["number"]),
+            "main",
+            Some("get_number"),
+            2,
+        );
+        define_function(project.add_module("number", &[]), "get_number", None, 11);
+
+        assert!(project.check().is_empty());
+        assert_eq!(project.get_module_order(), Ok(vec![1, 0]));
+
+        let output_folder =
+            std::env::temp_dir().join(format!("anc_test_project_{}", std::process::id()));
+        let exec_file_path = project
+            .build(&output_folder, &LinkerOptions::default())
+            .unwrap();
+
+        assert!(output_folder.join("main.o").exists());
+        assert!(output_folder.join("number.o").exists());
+
+        let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
+        assert_eq!(exit_code_opt, Some(13));
+
+        std::fs::remove_dir_all(&output_folder).unwrap();
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Write;
+
+use cranelift_jit::JITModule;
+
+use crate::{
+    ast::Opcode,
+    code_generator::Generator,
+    lowering::assemble_module,
+    parser::parse_module,
+    test_support::{assemble_source_and_run, ExecutionOptions},
+};
+
+// The property-based tests
+// ------------------------
+//
+// Generate the random (but well-typed and terminating) function bodies, which
+// consist of the arithmetic, the branches and the loops, compile them by both
+// backends (i.e. the JIT and the object file), and compare the results with
+// the evaluation of the reference interpreter, to hunt the miscompilations
+// systematically, e.g.
+//
+// ```rust
+// run_property_test(seed, 100).unwrap();
+// ```
+//
+// the generated functions have the signature `(param $p0 i32) (param $p1 i32) (result i32)`,
+// e.g.
+//
+// ```text
+// (function $f0 (param $p0 i32) (param $p1 i32) (result i32)
+//     (code
+//         (if (result i32) (lt_i32_s (local_load $p0) (imm_i32 7))
+//             (for (param $i1 i32 (imm_i32 0)) (param $acc1 i32 (local_load $p1)) (result i32)
+//                 (when (ge_i32_s (local_load $i1) (imm_i32 3))
+//                     (break (local_load $acc1)))
+//                 (recur
+//                     (add_i32 (local_load $i1) (imm_i32 1))
+//                     (xor_i32 (local_load $acc1) (local_load $i1))))
+//             (imm_i32 -1))))
+// ```
+//
+// - the random numbers are generated by the xorshift generator from the seed,
+//   so a failed case can be reproduced by its seed.
+// - the operations which trap (e.g. the division by zero) are not generated.
+// - the object file is linked and run by `test_support.rs`, the results are
+//   printed by `printf()`.
+
+/// The random number generator (xorshift64*).
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // the state must not be zero
+        Self {
+            state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Generate a number in the range `0..bound`.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// Generate an `i32`, the small numbers and the boundaries are preferred
+    /// since they trigger more edge cases.
+    pub fn next_i32(&mut self) -> i32 {
+        match self.below(4) {
+            0 => self.below(16) as i32 - 8,
+            1 => [0, 1, -1, 31, 32, i32::MAX, i32::MIN][self.below(7)],
+            _ => self.next_u64() as i32,
+        }
+    }
+}
+
+const BINARY_OPCODES: [Opcode; 18] = [
+    Opcode::AddI32,
+    Opcode::SubI32,
+    Opcode::MulI32,
+    Opcode::AndI32,
+    Opcode::OrI32,
+    Opcode::XorI32,
+    Opcode::ShiftLeftI32,
+    Opcode::ShiftRightI32S,
+    Opcode::ShiftRightI32U,
+    Opcode::RotateLeftI32,
+    Opcode::RotateRightI32,
+    Opcode::EqI32,
+    Opcode::NeI32,
+    Opcode::LtI32S,
+    Opcode::LtI32U,
+    Opcode::GtI32S,
+    Opcode::GeI32U,
+    Opcode::LeI32S,
+];
+
+const UNARY_OPCODES: [Opcode; 5] = [
+    Opcode::NegI32,
+    Opcode::NotI32,
+    Opcode::EqzI32,
+    Opcode::CountOnesI32,
+    Opcode::CountLeadingZerosI32,
+];
+
+/// The `i32` expression of the generated function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Imm(i32),
+
+    /// The parameter or the variable of the enclosing loop.
+    Local(String),
+    Unary(Opcode, Box<Expr>),
+    Binary(Opcode, Box<Expr>, Box<Expr>),
+    If {
+        condition: Box<Expr>,
+        consequent: Box<Expr>,
+        alternative: Box<Expr>,
+    },
+
+    /// The counted loop, i.e. `acc = init; for i in 0..count { acc = body }`,
+    /// the variables are `$i{depth}` and `$acc{depth}`.
+    Loop {
+        depth: usize,
+        count: i32,
+        init: Box<Expr>,
+        body: Box<Expr>,
+    },
+}
+
+/// The names of the parameters of the generated functions.
+const PARAM_NAMES: [&str; 2] = ["p0", "p1"];
+
+/// Generate a random expression, the `max_depth` limits the nesting levels.
+pub fn generate_expr(rng: &mut Rng, max_depth: usize) -> Expr {
+    let mut locals = PARAM_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    generate_expr_in(rng, max_depth, 0, &mut locals)
+}
+
+fn generate_expr_in(
+    rng: &mut Rng,
+    max_depth: usize,
+    loop_depth: usize,
+    locals: &mut Vec<String>,
+) -> Expr {
+    let choice = if max_depth == 0 {
+        rng.below(2)
+    } else {
+        rng.below(8)
+    };
+
+    match choice {
+        0 => Expr::Imm(rng.next_i32()),
+        1 => Expr::Local(locals[rng.below(locals.len())].clone()),
+        2 => Expr::Unary(
+            UNARY_OPCODES[rng.below(UNARY_OPCODES.len())],
+            Box::new(generate_expr_in(rng, max_depth - 1, loop_depth, locals)),
+        ),
+        3 => Expr::If {
+            condition: Box::new(generate_expr_in(rng, max_depth - 1, loop_depth, locals)),
+            consequent: Box::new(generate_expr_in(rng, max_depth - 1, loop_depth, locals)),
+            alternative: Box::new(generate_expr_in(rng, max_depth - 1, loop_depth, locals)),
+        },
+        4 if loop_depth < 2 => {
+            let depth = loop_depth + 1;
+            let init = generate_expr_in(rng, max_depth - 1, loop_depth, locals);
+
+            locals.push(format!("i{}", depth));
+            locals.push(format!("acc{}", depth));
+            let body = generate_expr_in(rng, max_depth - 1, depth, locals);
+            locals.truncate(locals.len() - 2);
+
+            Expr::Loop {
+                depth,
+                count: rng.below(6) as i32,
+                init: Box::new(init),
+                body: Box::new(body),
+            }
+        }
+        _ => Expr::Binary(
+            BINARY_OPCODES[rng.below(BINARY_OPCODES.len())],
+            Box::new(generate_expr_in(rng, max_depth - 1, loop_depth, locals)),
+            Box::new(generate_expr_in(rng, max_depth - 1, loop_depth, locals)),
+        ),
+    }
+}
+
+impl Expr {
+    /// Convert the expression into the source text.
+    pub fn to_source(&self) -> String {
+        match self {
+            Expr::Imm(value) => format!("(imm_i32 {})", value),
+            Expr::Local(name) => format!("(local_load ${})", name),
+            Expr::Unary(opcode, operand) => format!("({} {})", opcode.name(), operand.to_source()),
+            Expr::Binary(opcode, left, right) => format!(
+                "({} {} {})",
+                opcode.name(),
+                left.to_source(),
+                right.to_source()
+            ),
+            Expr::If {
+                condition,
+                consequent,
+                alternative,
+            } => format!(
+                "(if (result i32) {} {} {})",
+                condition.to_source(),
+                consequent.to_source(),
+                alternative.to_source()
+            ),
+            Expr::Loop {
+                depth,
+                count,
+                init,
+                body,
+            } => format!(
+                "(for (param $i{depth} i32 (imm_i32 0)) (param $acc{depth} i32 {}) (result i32) \
+                (when (ge_i32_s (local_load $i{depth}) (imm_i32 {count})) (break (local_load $acc{depth}))) \
+                (recur (add_i32 (local_load $i{depth}) (imm_i32 1)) {}))",
+                init.to_source(),
+                body.to_source()
+            ),
+        }
+    }
+
+    /// Evaluate the expression by the reference interpreter, the `locals`
+    /// are the values of the parameters and the loop variables.
+    pub fn evaluate(&self, locals: &mut Vec<(String, i32)>) -> i32 {
+        match self {
+            Expr::Imm(value) => *value,
+            Expr::Local(name) => {
+                locals
+                    .iter()
+                    .rev()
+                    .find(|(local_name, _)| local_name == name)
+                    .unwrap()
+                    .1
+            }
+            Expr::Unary(opcode, operand) => {
+                let value = operand.evaluate(locals);
+                match opcode {
+                    Opcode::NegI32 => value.wrapping_neg(),
+                    Opcode::NotI32 => !value,
+                    Opcode::EqzI32 => (value == 0) as i32,
+                    Opcode::CountOnesI32 => value.count_ones() as i32,
+                    Opcode::CountLeadingZerosI32 => value.leading_zeros() as i32,
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Binary(opcode, left, right) => {
+                let left = left.evaluate(locals);
+                let right = right.evaluate(locals);
+                // the shift amount is masked by the bit width, as Cranelift does
+                let amount = (right as u32) % 32;
+                match opcode {
+                    Opcode::AddI32 => left.wrapping_add(right),
+                    Opcode::SubI32 => left.wrapping_sub(right),
+                    Opcode::MulI32 => left.wrapping_mul(right),
+                    Opcode::AndI32 => left & right,
+                    Opcode::OrI32 => left | right,
+                    Opcode::XorI32 => left ^ right,
+                    Opcode::ShiftLeftI32 => left << amount,
+                    Opcode::ShiftRightI32S => left >> amount,
+                    Opcode::ShiftRightI32U => ((left as u32) >> amount) as i32,
+                    Opcode::RotateLeftI32 => (left as u32).rotate_left(amount) as i32,
+                    Opcode::RotateRightI32 => (left as u32).rotate_right(amount) as i32,
+                    Opcode::EqI32 => (left == right) as i32,
+                    Opcode::NeI32 => (left != right) as i32,
+                    Opcode::LtI32S => (left < right) as i32,
+                    Opcode::LtI32U => ((left as u32) < (right as u32)) as i32,
+                    Opcode::GtI32S => (left > right) as i32,
+                    Opcode::GeI32U => ((left as u32) >= (right as u32)) as i32,
+                    Opcode::LeI32S => (left <= right) as i32,
+                    _ => unreachable!(),
+                }
+            }
+            Expr::If {
+                condition,
+                consequent,
+                alternative,
+            } => {
+                if condition.evaluate(locals) != 0 {
+                    consequent.evaluate(locals)
+                } else {
+                    alternative.evaluate(locals)
+                }
+            }
+            Expr::Loop {
+                depth,
+                count,
+                init,
+                body,
+            } => {
+                let mut acc = init.evaluate(locals);
+                for i in 0..*count {
+                    locals.push((format!("i{}", depth), i));
+                    locals.push((format!("acc{}", depth), acc));
+                    acc = body.evaluate(locals);
+                    locals.truncate(locals.len() - 2);
+                }
+                acc
+            }
+        }
+    }
+}
+
+/// Get the source text of the function of the expression.
+pub fn get_function_source(name: &str, expr: &Expr) -> String {
+    format!(
+        "(function ${} (param $p0 i32) (param $p1 i32) (result i32) (code {}))",
+        name,
+        expr.to_source()
+    )
+}
+
+/// Generate the functions from the seed, compile them by both backends, and
+/// compare the results of some arguments with the reference interpreter.
+///
+/// Returns the description of the first mismatch, which contains the seed
+/// and the source text of the function.
+pub fn run_property_test(seed: u64, case_count: usize) -> Result<(), String> {
+    const MAX_DEPTH: usize = 4;
+
+    let mut rng = Rng::new(seed);
+    let exprs = (0..case_count)
+        .map(|_| generate_expr(&mut rng, MAX_DEPTH))
+        .collect::<Vec<_>>();
+    let args = (0..4)
+        .map(|_| (rng.next_i32(), rng.next_i32()))
+        .collect::<Vec<_>>();
+
+    let function_sources = exprs
+        .iter()
+        .enumerate()
+        .map(|(index, expr)| get_function_source(&format!("f{}", index), expr))
+        .collect::<Vec<_>>();
+
+    // the expected results
+    let expected = exprs
+        .iter()
+        .map(|expr| {
+            args.iter()
+                .map(|(p0, p1)| {
+                    let mut locals = vec![("p0".to_owned(), *p0), ("p1".to_owned(), *p1)];
+                    expr.evaluate(&mut locals)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let report = |backend: &str, index: usize, arg_index: usize, actual: &str| {
+        let (p0, p1) = args[arg_index];
+        format!(
+            "{} mismatch (seed: {}, case: {}, args: {}, {}), expected {}, actual {}:\n{}",
+            backend,
+            seed,
+            index,
+            p0,
+            p1,
+            expected[index][arg_index],
+            actual,
+            function_sources[index]
+        )
+    };
+
+    // the JIT
+    let jit_source = format!("(module $property {})", function_sources.join("\n"));
+    let module = parse_module(&jit_source)
+        .map_err(|diagnostic| format!("seed {}: {}", seed, diagnostic.message))?;
+    let mut generator = Generator::<JITModule>::new(vec![]);
+    let assembled_module = assemble_module(&module, &mut generator)
+        .map_err(|diagnostic| format!("seed {}: {}", seed, diagnostic.message))?;
+    generator.module.finalize_definitions().unwrap();
+
+    for (index, expected_values) in expected.iter().enumerate() {
+        let func_id = assembled_module
+            .get_function_id(&format!("f{}", index))
+            .unwrap();
+        let function: extern "C" fn(i32, i32) -> i32 =
+            unsafe { std::mem::transmute(generator.module.get_finalized_function(func_id)) };
+
+        for (arg_index, ((p0, p1), expected_value)) in args.iter().zip(expected_values).enumerate()
+        {
+            let actual = function(*p0, *p1);
+            if actual != *expected_value {
+                return Err(report("JIT", index, arg_index, &actual.to_string()));
+            }
+        }
+    }
+
+    // the object file, the main function prints the results line by line
+    let mut calls = String::new();
+    for index in 0..exprs.len() {
+        for (p0, p1) in &args {
+            writeln!(
+                calls,
+                "(call $printf (host_addr_data $format) (call $f{} (imm_i32 {}) (imm_i32 {})))",
+                index, p0, p1
+            )
+            .unwrap();
+        }
+    }
+    let object_source = format!(
+        r#"(module $anasm_property_{}
+            (extern-c "printf" variadic (params i64) (results i32))
+            (data $format (read_only bytes "%d\n\0"))
+            {}
+            (function $main export (result i32) (code {} (imm_i32 0))))"#,
+        seed,
+        function_sources.join("\n"),
+        calls
+    );
+    let result = assemble_source_and_run(&object_source, false, &ExecutionOptions::default())
+        .map_err(|diagnostic| format!("seed {}: {}", seed, diagnostic.message))?;
+    let mut lines = result.stdout.lines();
+
+    for (index, expected_values) in expected.iter().enumerate() {
+        for (arg_index, expected_value) in expected_values.iter().enumerate() {
+            let actual = lines.next().unwrap_or_default();
+            if actual != expected_value.to_string() {
+                return Err(report("object", index, arg_index, actual));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        parser::parse_module,
+        property_testing::{generate_expr, get_function_source, run_property_test, Expr, Rng},
+    };
+
+    #[test]
+    fn test_property_testing() {
+        // the generation is deterministic
+        let mut rng_0 = Rng::new(7);
+        let mut rng_1 = Rng::new(7);
+        assert_eq!(generate_expr(&mut rng_0, 4), generate_expr(&mut rng_1, 4));
+
+        // the generated functions are well-typed
+        let mut rng = Rng::new(11);
+        for index in 0..20 {
+            let expr = generate_expr(&mut rng, 4);
+            let source = format!(
+                "(module $app {})",
+                get_function_source(&format!("f{}", index), &expr)
+            );
+            assert!(parse_module(&source).is_ok(), "{}", source);
+        }
+
+        // the reference interpreter
+        let expr = Expr::Loop {
+            depth: 1,
+            count: 4,
+            init: Box::new(Expr::Local("p0".to_owned())),
+            body: Box::new(Expr::Binary(
+                crate::ast::Opcode::AddI32,
+                Box::new(Expr::Local("acc1".to_owned())),
+                Box::new(Expr::Local("i1".to_owned())),
+            )),
+        };
+        let mut locals = vec![("p0".to_owned(), 10), ("p1".to_owned(), 0)];
+        assert_eq!(expr.evaluate(&mut locals), 16);
+
+        for seed in 0..4 {
+            run_property_test(seed, 25).unwrap();
+        }
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    ast::{ImportModuleNode, ImportNode, Module},
+    conditional::Conditions,
+    diagnostic::Diagnostic,
+    lexer::Span,
+    parser::parse_module_with_conditions,
+};
+
+// The module resolver
+// -------------------
+//
+// A program can be split across files, a file imports the other files by
+// `(import (module "path"))`, e.g.
+//
+// ```text
+// (module $app
+//     (import (module "lib/math"))     // i.e. "lib/math.ancasm"
+//     (function $main export (result i32)
+//         (code (call $square (imm_i32 3)))))
+// ```
+//
+// the imported file is searched in the folder of the importing file first,
+// and then the search paths in order. The imports, data and functions of the
+// imported files are merged into the root module (each file is merged only
+// once, and the identical imports are merged into one), and the cyclic
+// imports are reported as errors.
+//
+// Each file occupies a range of the offsets of the `SourceFiles`, so the spans of
+// the merged module (and the diagnostics of the lowering) identify the files, e.g.
+//
+// ```rust
+// let mut source_files = SourceFiles::new();
+// let module = resolve_module("app.ancasm", &source, &search_paths, &conditions, &mut source_files)
+//     .map_err(|diagnostic| source_files.render(&diagnostic))?;
+// ```
+
+const SOURCE_FILE_EXTENSION: &str = "ancasm";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub file_path: String,
+    pub source: String,
+    pub base_offset: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct SourceFiles {
+    files: Vec<SourceFile>,
+}
+
+impl SourceFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the file and return its base offset, there is a gap of one
+    /// byte between the files, so the empty span at the end of a file
+    /// does not belong to the next one.
+    pub fn add(&mut self, file_path: &str, source: &str) -> usize {
+        let base_offset = self
+            .files
+            .last()
+            .map(|file| file.base_offset + file.source.len() + 1)
+            .unwrap_or(0);
+        self.files.push(SourceFile {
+            file_path: file_path.to_owned(),
+            source: source.to_owned(),
+            base_offset,
+        });
+        base_offset
+    }
+
+    pub fn get_files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Find the file which contains the span, and convert the span to the
+    /// offsets of the file.
+    pub fn locate(&self, span: Span) -> Option<(&SourceFile, Span)> {
+        self.files
+            .iter()
+            .rev()
+            .find(|file| file.base_offset <= span.start)
+            .map(|file| (file, span.shift(-(file.base_offset as isize))))
+    }
+
+    /// Render the diagnostic with the snippet of the file which contains it.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        match self.locate(diagnostic.span) {
+            Some((file, span)) => Diagnostic {
+                span,
+                ..diagnostic.clone()
+            }
+            .render(&file.file_path, &file.source),
+            None => format!("error: {}\n", diagnostic.message),
+        }
+    }
+}
+
+/// Parse the source text and merge the modules which are imported
+/// (directly or indirectly) by `(import (module "path"))`, all files are
+/// parsed with the same conditions (i.e. the target and the features).
+pub fn resolve_module(
+    file_path: &str,
+    source: &str,
+    search_paths: &[PathBuf],
+    conditions: &Conditions,
+    source_files: &mut SourceFiles,
+) -> Result<Module, Diagnostic> {
+    let base_offset = source_files.add(file_path, source);
+    let mut module = parse_module_with_conditions(source, base_offset, conditions)?;
+
+    let mut resolver = Resolver {
+        search_paths,
+        conditions,
+        source_files,
+        import_chain: vec![canonicalize(Path::new(file_path))],
+        merged_files: vec![],
+    };
+
+    let module_imports = std::mem::take(&mut module.module_imports);
+    resolver.resolve_imports(Path::new(file_path), &module_imports, &mut module)?;
+    Ok(module)
+}
+
+fn canonicalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+struct Resolver<'a> {
+    search_paths: &'a [PathBuf],
+    conditions: &'a Conditions,
+    source_files: &'a mut SourceFiles,
+
+    // the files which are being resolved, for detecting the cycles
+    import_chain: Vec<PathBuf>,
+    merged_files: Vec<PathBuf>,
+}
+
+impl Resolver<'_> {
+    fn resolve_imports(
+        &mut self,
+        file_path: &Path,
+        module_imports: &[ImportModuleNode],
+        root: &mut Module,
+    ) -> Result<(), Diagnostic> {
+        for node in module_imports {
+            let import_path = self.find_file(file_path, &node.path).ok_or_else(|| {
+                Diagnostic::new(
+                    &format!("can not find the module \"{}\"", node.path),
+                    node.span,
+                )
+                .with_note(&format!(
+                    "the module is searched in the folder of the importing file and the search paths: {}",
+                    self.describe_search_paths()
+                ))
+            })?;
+            let canonical_path = canonicalize(&import_path);
+
+            if self.import_chain.contains(&canonical_path) {
+                let chain = self
+                    .import_chain
+                    .iter()
+                    .chain([&canonical_path])
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>();
+                return Err(Diagnostic::new(
+                    &format!("the module \"{}\" is imported cyclically", node.path),
+                    node.span,
+                )
+                .with_note(&format!("the import chain: {}", chain.join(" -> "))));
+            }
+
+            if self.merged_files.contains(&canonical_path) {
+                continue;
+            }
+
+            let source = std::fs::read_to_string(&import_path).map_err(|error| {
+                Diagnostic::new(
+                    &format!(
+                        "failed to read the module \"{}\": {}",
+                        import_path.to_string_lossy(),
+                        error
+                    ),
+                    node.span,
+                )
+            })?;
+            let import_path_text = import_path.to_string_lossy().into_owned();
+            let base_offset = self.source_files.add(&import_path_text, &source);
+            let mut module = parse_module_with_conditions(&source, base_offset, self.conditions)?;
+
+            self.import_chain.push(canonical_path.clone());
+            let module_imports = std::mem::take(&mut module.module_imports);
+            self.resolve_imports(&import_path, &module_imports, root)?;
+            self.import_chain.pop();
+
+            merge_module(root, module);
+            self.merged_files.push(canonical_path);
+        }
+
+        Ok(())
+    }
+
+    /// Find the file in the folder of the importing file and the search paths.
+    fn find_file(&self, importing_file_path: &Path, path: &str) -> Option<PathBuf> {
+        let mut relative_path = PathBuf::from(path);
+        if relative_path.extension().is_none() {
+            relative_path.set_extension(SOURCE_FILE_EXTENSION);
+        }
+
+        let importing_folder = importing_file_path
+            .parent()
+            .map(|folder| folder.to_path_buf())
+            .unwrap_or_default();
+
+        std::iter::once(&importing_folder)
+            .chain(self.search_paths)
+            .map(|folder| folder.join(&relative_path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    fn describe_search_paths(&self) -> String {
+        if self.search_paths.is_empty() {
+            "(none)".to_owned()
+        } else {
+            self.search_paths
+                .iter()
+                .map(|path| format!("\"{}\"", path.to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    }
+}
+
+/// Merge the imports, data, functions and tests of the imported module into the root
+/// module, the duplicate definitions are left to be reported by the lowering.
+fn merge_module(root: &mut Module, module: Module) {
+    for import in module.imports {
+        if !root
+            .imports
+            .iter()
+            .any(|existing| is_same_import(existing, &import))
+        {
+            root.imports.push(import);
+        }
+    }
+    root.data.extend(module.data);
+    root.functions.extend(module.functions);
+    root.tests.extend(module.tests);
+}
+
+/// Check whether two imports are identical except the spans.
+fn is_same_import(left: &ImportNode, right: &ImportNode) -> bool {
+    match (left, right) {
+        (ImportNode::Function(left), ImportNode::Function(right)) => {
+            left.name == right.name
+                && left.symbol == right.symbol
+                && left.params == right.params
+                && left.results == right.results
+                && left.result_abi == right.result_abi
+        }
+        (ImportNode::Data(left), ImportNode::Data(right)) => {
+            left.name == right.name && left.symbol == right.symbol && left.tls == right.tls
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        conditional::Conditions,
+        lowering::assemble_module,
+        resolver::{resolve_module, SourceFiles},
+    };
+
+    #[test]
+    fn test_resolve_module() {
+        let folder =
+            std::env::temp_dir().join(format!("anc_test_resolve_module_{}", std::process::id()));
+        let lib_folder = folder.join("lib");
+        std::fs::create_dir_all(&lib_folder).unwrap();
+
+        // "square" and "double" both import "abs", "square" is found by the search path
+        std::fs::write(
+            lib_folder.join("square.ancasm"),
+            r#"(module $square
+                (import (module "common"))
+                (import (function $abs "abs" (param i32) (result i32)))
+                (function $square (param $a i32) (result i32)
+                    (code (mul_i32 (call $abs (local_load $a)) (call $abs (local_load $a))))))"#,
+        )
+        .unwrap();
+        std::fs::write(
+            lib_folder.join("common.ancasm"),
+            r#"(module $common
+                (import (function $abs "abs" (param i32) (result i32)))
+                (function $double (param $a i32) (result i32)
+                    (code (add_i32 (call $abs (local_load $a)) (call $abs (local_load $a))))))"#,
+        )
+        .unwrap();
+
+        let main_file_path = folder.join("main.ancasm");
+        let main_source = r#"(module $app
+            (import (module "square"))
+            (import (module "lib/common.ancasm"))
+            (function $main (result i32)
+                (code (add_i32 (call $square (imm_i32 -3)) (call $double (imm_i32 -4))))))"#;
+
+        let mut source_files = SourceFiles::new();
+        let module = resolve_module(
+            main_file_path.to_str().unwrap(),
+            main_source,
+            std::slice::from_ref(&lib_folder),
+            &Conditions::host(),
+            &mut source_files,
+        )
+        .unwrap();
+
+        assert_eq!(source_files.get_files().len(), 3);
+        assert_eq!(module.imports.len(), 1);
+        assert_eq!(
+            module
+                .functions
+                .iter()
+                .map(|node| node.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["main", "double", "square"]
+        );
+
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+        let func_main: extern "C" fn() -> i32 = unsafe {
+            std::mem::transmute(
+                generator
+                    .module
+                    .get_finalized_function(assembled_module.get_function_id("main").unwrap()),
+            )
+        };
+        assert_eq!(func_main(), 17);
+
+        // the error of the imported file is located in the file
+        std::fs::write(
+            lib_folder.join("common.ancasm"),
+            "(module $common\n    (function $double (code (add_i33))))",
+        )
+        .unwrap();
+        let mut source_files = SourceFiles::new();
+        let diagnostic = resolve_module(
+            main_file_path.to_str().unwrap(),
+            main_source,
+            std::slice::from_ref(&lib_folder),
+            &Conditions::host(),
+            &mut source_files,
+        )
+        .unwrap_err();
+        let text = source_files.render(&diagnostic);
+        assert!(text.starts_with(&format!(
+            "error: unknown instruction \"add_i33\"\n --> {}:2:30\n",
+            lib_folder.join("common.ancasm").to_str().unwrap()
+        )));
+
+        // the cyclic imports
+        std::fs::write(
+            lib_folder.join("common.ancasm"),
+            "(module $common (import (module \"square\")))",
+        )
+        .unwrap();
+        let mut source_files = SourceFiles::new();
+        let diagnostic = resolve_module(
+            main_file_path.to_str().unwrap(),
+            main_source,
+            std::slice::from_ref(&lib_folder),
+            &Conditions::host(),
+            &mut source_files,
+        )
+        .unwrap_err();
+        assert_eq!(
+            diagnostic.message,
+            "the module \"square\" is imported cyclically"
+        );
+
+        // the missing module
+        let mut source_files = SourceFiles::new();
+        let diagnostic = resolve_module(
+            main_file_path.to_str().unwrap(),
+            main_source,
+            &[],
+            &Conditions::host(),
+            &mut source_files,
+        )
+        .unwrap_err();
+        assert_eq!(diagnostic.message, "can not find the module \"square\"");
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
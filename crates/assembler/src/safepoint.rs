@@ -0,0 +1,282 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{
+    cursor::{Cursor, FuncCursor},
+    dominator_tree::DominatorTree,
+    flowgraph::ControlFlowGraph,
+    ir::{types, FuncRef, Function, GlobalValue, Inst, InstBuilder, MemFlags, Type},
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{DataId, FuncId, Linkage, Module, ModuleError};
+
+use crate::code_generator::Generator;
+
+// The safepoint polling
+// ---------------------
+//
+// The long-running loops of the generated code can not be interrupted by the
+// GC (or the cooperative cancellation) unless they call the runtime, so the
+// safepoint polls are inserted at the function entry and the loop back-edges:
+//
+// ```clif
+// block1:
+//     ...
+//     v10 = symbol_value.i64 gv0      ; the poll flag
+//     v11 = load.i8 notrap aligned v10
+//     brif v11, block3, block2
+//
+// block3 cold:
+//     call fn0()                      ; the poll handler
+//     jump block2
+//
+// block2:
+//     jump block1(...)                ; the back-edge
+// ```
+//
+// the poll flag (a byte) and the poll handler are supplied by the runtime, the
+// runtime sets the flag to request a safepoint, and the handler is a regular
+// call, so the GC references which are live across it are recorded in the stack
+// maps (see `stack_map.rs`).
+//
+// Note that the stack maps are generated by `FunctionBuilder::finalize()`, so
+// the polls are inserted before finalizing the function, e.g.
+//
+// ```rust
+// let safepoint_poll = generator.declare_safepoint_poll_in_func(&mut func).unwrap();
+// let mut function_builder = FunctionBuilder::new(&mut func, ...);
+// ...
+// function_builder.seal_all_blocks();
+// safepoint_poll.insert_polls(&mut function_builder);
+// function_builder.finalize();
+// ```
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SafepointPollSymbols {
+    pub flag: DataId,
+    pub handler: FuncId,
+}
+
+/// The references of the poll flag and the poll handler in a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SafepointPoll {
+    pub flag: GlobalValue,
+    pub handler: FuncRef,
+    pub pointer_type: Type,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Enable the safepoint polling, the poll flag (a byte) and the poll handler
+    /// (`extern "C" fn()`) are declared as imported symbols.
+    pub fn enable_safepoint_polls(
+        &mut self,
+        flag_name: &str,
+        handler_name: &str,
+    ) -> Result<(), ModuleError> {
+        let flag = self
+            .module
+            .declare_data(flag_name, Linkage::Import, false, false)?;
+
+        let handler_sig = self.module.make_signature();
+        let handler = self
+            .module
+            .declare_function(handler_name, Linkage::Import, &handler_sig)?;
+
+        self.safepoint_poll = Some(SafepointPollSymbols { flag, handler });
+        Ok(())
+    }
+
+    /// Declare the poll flag and the poll handler in the function, returns `None`
+    /// if the safepoint polling is not enabled.
+    pub fn declare_safepoint_poll_in_func(&mut self, func: &mut Function) -> Option<SafepointPoll> {
+        let symbols = self.safepoint_poll?;
+        Some(SafepointPoll {
+            flag: self.module.declare_data_in_func(symbols.flag, func),
+            handler: self.module.declare_func_in_func(symbols.handler, func),
+            pointer_type: self.module.isa().pointer_type(),
+        })
+    }
+}
+
+impl SafepointPoll {
+    /// Insert the polls at the function entry and before the terminators of
+    /// the blocks which jump back to a loop header.
+    ///
+    /// All blocks should be filled and sealed, and it should be called
+    /// before `function_builder.finalize()`.
+    pub fn insert_polls(&self, function_builder: &mut FunctionBuilder) {
+        let func = &mut *function_builder.func;
+
+        let Some(entry_block) = func.layout.entry_block() else {
+            return;
+        };
+
+        let cfg = ControlFlowGraph::with_function(func);
+        let domtree = DominatorTree::with_function(func, &cfg);
+
+        // the back-edge is the edge whose target dominates its source.
+        let mut poll_points = func
+            .layout
+            .blocks()
+            .filter(|block| {
+                cfg.succ_iter(*block)
+                    .any(|successor| domtree.dominates(successor, *block, &func.layout))
+            })
+            .filter_map(|block| func.layout.last_inst(block))
+            .collect::<Vec<_>>();
+
+        if let Some(first_inst) = func.layout.first_inst(entry_block) {
+            poll_points.push(first_inst);
+        }
+
+        for inst in poll_points {
+            self.insert_poll_before(func, inst);
+        }
+    }
+
+    fn insert_poll_before(&self, func: &mut Function, inst: Inst) {
+        let block = func
+            .layout
+            .inst_block(inst)
+            .expect("the instruction is not in the layout");
+
+        let block_continue = func.dfg.make_block();
+        let block_poll = func.dfg.make_block();
+        func.layout.split_block(block_continue, inst);
+        func.layout.append_block(block_poll);
+        func.layout.set_cold(block_poll);
+
+        let mut cursor = FuncCursor::new(func);
+
+        cursor.goto_bottom(block);
+        let value_flag_addr = cursor.ins().symbol_value(self.pointer_type, self.flag);
+        let value_flag = cursor
+            .ins()
+            .load(types::I8, MemFlags::trusted(), value_flag_addr, 0);
+        cursor
+            .ins()
+            .brif(value_flag, block_poll, &[], block_continue, &[]);
+
+        cursor.goto_bottom(block_poll);
+        cursor.ins().call(self.handler, &[]);
+        cursor.ins().jump(block_continue, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+    use cranelift_codegen::ir::{
+        condcodes::IntCC, types, AbiParam, Function, InstBuilder, UserFuncName,
+    };
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+
+    use crate::code_generator::Generator;
+
+    static POLL_FLAG: AtomicU8 = AtomicU8::new(1);
+    static POLL_COUNT: AtomicU32 = AtomicU32::new(0);
+
+    extern "C" fn poll_handler() {
+        POLL_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_safepoint_polls() {
+        let symbols = vec![
+            ("poll_flag".to_owned(), POLL_FLAG.as_ptr() as *const u8),
+            ("poll_handler".to_owned(), poll_handler as *const u8),
+        ];
+        let mut generator = Generator::<JITModule>::new(symbols);
+        generator
+            .enable_safepoint_polls("poll_flag", "poll_handler")
+            .unwrap();
+
+        // ```rust
+        // fn main(obj: ref) -> ref {
+        //     let mut i = 0;
+        //     while i != 10 {
+        //         i += 1;
+        //     }
+        //     obj
+        // }
+        // ```
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.params.push(AbiParam::new(types::I64));
+        func_main_sig.returns.push(AbiParam::new(types::I64));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Local, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let safepoint_poll = generator
+            .declare_safepoint_poll_in_func(&mut func_main)
+            .unwrap();
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block_0 = function_builder.create_block();
+        let block_1 = function_builder.create_block();
+        let block_2 = function_builder.create_block();
+        let block_3 = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block_0);
+        function_builder.append_block_param(block_1, types::I32);
+
+        function_builder.switch_to_block(block_0);
+        let value_obj = function_builder.block_params(block_0)[0];
+        function_builder.declare_value_needs_stack_map(value_obj);
+        let value_0 = function_builder.ins().iconst(types::I32, 0);
+        function_builder.ins().jump(block_1, &[value_0]);
+
+        function_builder.switch_to_block(block_1);
+        let value_1 = function_builder.block_params(block_1)[0];
+        let value_2 = function_builder.ins().icmp_imm(IntCC::Equal, value_1, 10);
+        function_builder
+            .ins()
+            .brif(value_2, block_3, &[], block_2, &[]);
+
+        function_builder.switch_to_block(block_2);
+        let value_3 = function_builder.ins().iadd_imm(value_1, 1);
+        function_builder.ins().jump(block_1, &[value_3]);
+
+        function_builder.switch_to_block(block_3);
+        function_builder.ins().return_(&[value_obj]);
+
+        function_builder.seal_all_blocks();
+        safepoint_poll.insert_polls(&mut function_builder);
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        // the polls at the entry and the back-edge are safepoints
+        let stack_map = generator.get_stack_map(func_main_id).unwrap();
+        assert_eq!(stack_map.safepoints.len(), 2);
+
+        generator.module.finalize_definitions().unwrap();
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let func_main: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(func_main_ptr) };
+        assert_eq!(func_main(0x1234), 0x1234);
+        assert_eq!(POLL_COUNT.load(Ordering::SeqCst), 11);
+
+        // no poll is requested
+        POLL_FLAG.store(0, Ordering::SeqCst);
+        assert_eq!(func_main(0x1234), 0x1234);
+        assert_eq!(POLL_COUNT.load(Ordering::SeqCst), 11);
+    }
+}
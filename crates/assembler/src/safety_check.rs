@@ -0,0 +1,197 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{condcodes::IntCC, InstBuilder, TrapCode, Value};
+use cranelift_frontend::FunctionBuilder;
+
+// The safety checks
+// -----------------
+//
+// The canonical form of the null-pointer check and the index-bounds check,
+// the failure path is a cold block (which is placed at the end of the function
+// so the check is predicted as "not taken") that contains only a `trap`:
+//
+// ```clif
+// block0:
+//     ...
+//     brif v0, block2, block1     ; null check: v0 != 0
+//
+// block1 cold:
+//     trap user1
+//
+// block2:
+//     ...                          ; the following instructions
+// ```
+//
+// the checks are emitted by the functions `emit_null_check()` and `emit_bounds_check()`
+// with a `FunctionBuilder`, after the check, the builder is switched to the
+// continuation block, e.g.
+//
+// ```rust
+// emit_bounds_check(&mut function_builder, value_index, value_length, TRAP_CODE_INDEX_OUT_OF_BOUNDS);
+// let value_0 = function_builder.ins().load(...);
+// ```
+//
+// the trap codes of the XiaoXuan runtime are the user trap codes, which can be
+// recognized by the signal handler (e.g. by the trap information of the JIT code)
+// and the later analyses.
+
+/// The trap code of the null-pointer check.
+pub const TRAP_CODE_NULL_POINTER: TrapCode = TrapCode::unwrap_user(1);
+
+/// The trap code of the index-bounds check.
+pub const TRAP_CODE_INDEX_OUT_OF_BOUNDS: TrapCode = TrapCode::unwrap_user(2);
+
+/// Trap with the `trap_code` if the pointer `value` is zero.
+pub fn emit_null_check(function_builder: &mut FunctionBuilder, value: Value, trap_code: TrapCode) {
+    emit_check(function_builder, value, trap_code);
+}
+
+/// Trap with the `trap_code` unless `index < length` (both are unsigned integers
+/// of the same type).
+pub fn emit_bounds_check(
+    function_builder: &mut FunctionBuilder,
+    index: Value,
+    length: Value,
+    trap_code: TrapCode,
+) {
+    let value_in_bounds = function_builder
+        .ins()
+        .icmp(IntCC::UnsignedLessThan, index, length);
+    emit_check(function_builder, value_in_bounds, trap_code);
+}
+
+/// Trap with the `trap_code` unless `left == right` (both are integers of the same type).
+pub fn emit_equality_check(
+    function_builder: &mut FunctionBuilder,
+    left: Value,
+    right: Value,
+    trap_code: TrapCode,
+) {
+    let value_equal = function_builder.ins().icmp(IntCC::Equal, left, right);
+    emit_check(function_builder, value_equal, trap_code);
+}
+
+/// Continue if the `condition` is non-zero, otherwise branch to a cold trapping block.
+fn emit_check(function_builder: &mut FunctionBuilder, condition: Value, trap_code: TrapCode) {
+    let block_trap = function_builder.create_block();
+    let block_continue = function_builder.create_block();
+    function_builder.set_cold_block(block_trap);
+
+    function_builder
+        .ins()
+        .brif(condition, block_continue, &[], block_trap, &[]);
+
+    // both blocks have only one predecessor
+    function_builder.seal_block(block_trap);
+    function_builder.seal_block(block_continue);
+
+    function_builder.switch_to_block(block_trap);
+    function_builder.ins().trap(trap_code);
+
+    function_builder.switch_to_block(block_continue);
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::ObjectModule;
+
+    use crate::{
+        code_generator::Generator,
+        safety_check::{
+            emit_bounds_check, emit_null_check, TRAP_CODE_INDEX_OUT_OF_BOUNDS,
+            TRAP_CODE_NULL_POINTER,
+        },
+        test_support::run_executable_binary_and_get_exit_code,
+    };
+
+    // ```rust
+    // fn main() -> i32 {
+    //     let index = INDEX;
+    //     bounds_check(index, 4);
+    //     let ptr = 0x1000 as *const u8;
+    //     null_check(ptr);
+    //     index + 11
+    // }
+    // ```
+    fn build_main(index: i64) -> Vec<u8> {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+        function_builder.seal_block(block);
+
+        let value_0 = function_builder.ins().iconst(types::I32, index);
+        let value_1 = function_builder.ins().iconst(types::I32, 4);
+        emit_bounds_check(
+            &mut function_builder,
+            value_0,
+            value_1,
+            TRAP_CODE_INDEX_OUT_OF_BOUNDS,
+        );
+
+        let value_2 = function_builder.ins().iconst(pointer_type, 0x1000);
+        emit_null_check(&mut function_builder, value_2, TRAP_CODE_NULL_POINTER);
+
+        let value_3 = function_builder.ins().iadd_imm(value_0, 11);
+        function_builder.ins().return_(&[value_3]);
+
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        let clif = generator.dump_clif();
+        assert!(clif.contains("cold:"));
+        assert!(clif.contains("trap user1"));
+        assert!(clif.contains("trap user2"));
+
+        generator.finish().unwrap().emit().unwrap()
+    }
+
+    #[test]
+    fn test_safety_check() {
+        let module_binary = build_main(2);
+        assert_eq!(
+            run_executable_binary_and_get_exit_code(
+                &module_binary,
+                "test_safety_check_pass",
+                false
+            ),
+            Some(13)
+        );
+
+        // the process is killed by the signal SIGILL
+        let module_binary = build_main(4);
+        assert_eq!(
+            run_executable_binary_and_get_exit_code(
+                &module_binary,
+                "test_safety_check_trap",
+                false
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{types, GlobalValue, InstBuilder, MemFlags, TrapCode, Type, Value};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{DataId, Linkage, Module, ModuleError};
+
+use crate::{code_generator::Generator, safety_check::emit_bounds_check};
+
+// The sandbox
+// -----------
+//
+// The untrusted modules (e.g. the scripts which are loaded by the host
+// process) can be assembled in the sandbox mode, the address of
+// `memory_load_*` and `memory_store_*` is an offset in the "linear memory"
+// which is provided by the host, instead of a native address, i.e.
+//
+// ```rust
+// #[repr(C)]
+// struct SandboxMemory {
+//     base: *mut u8,
+//     length: u64,
+// }
+//
+// static __anna_sandbox_memory: SandboxMemory;
+// ```
+//
+// there are two modes:
+//
+// - `SandboxMode::BoundsChecked`: each access checks `address + offset + size <= length`,
+//   and traps with `heap_oob` otherwise, i.e.
+//
+//   ```clif
+//   v1 = uadd_overflow_trap v0, size_and_offset_minus_1, heap_oob
+//   v2 = load.i64 notrap aligned v_memory+8     ; length
+//   v3 = icmp ult v1, v2
+//   brif v3, block_continue, block_trap
+//   ...
+//   v4 = load.i64 notrap aligned v_memory      ; base
+//   v5 = iadd v4, v0
+//   v6 = load.i32 v5+offset
+//   ```
+//
+// - `SandboxMode::Guarded`: the address is truncated to 32 bits and there
+//   is no check, the host reserves `SANDBOX_GUARDED_REGION_SIZE` (8 GiB, i.e. the 4 GiB
+//   addressable memory and the guard region for the offsets) bytes of the
+//   address space, and maps the pages beyond `length` as inaccessible, so an
+//   out-of-bounds access faults (the same as the WebAssembly runtimes). It
+//   requires the 64-bit target.
+//
+// the other ways to access the native memory are rejected in the sandbox mode:
+//
+// - `dyncall`, the inline Cranelift IR `clif`, and the string operations (e.g.
+//   `string_length`), which dereference the native addresses.
+// - the data accesses out of the data objects (i.e. the offset is checked
+//   statically), and the accesses of the imported data without the size.
+//
+// the calls of the imported functions (i.e. the host API) are allowed.
+//
+// ref:
+// - https://webassembly.github.io/spec/core/exec/runtime.html#memory-instances
+// - https://docs.wasmtime.dev/contributing-architecture.html#linear-memory
+
+/// The name of the data which describes the linear memory, i.e. `SandboxMemory`.
+pub const SANDBOX_MEMORY_NAME: &str = "__anna_sandbox_memory";
+
+/// The size of the address space which is reserved for the linear memory
+/// in the guarded mode.
+pub const SANDBOX_GUARDED_REGION_SIZE: u64 = 8 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SandboxMode {
+    /// Check the bounds before each access.
+    BoundsChecked,
+
+    /// Truncate the address to 32 bits, the out-of-bounds accesses fault in
+    /// the guard region.
+    Guarded,
+}
+
+/// The layout of the data `__anna_sandbox_memory` which is provided by the host.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SandboxMemory {
+    pub base: *mut u8,
+    pub length: u64,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Confine the memory accesses of the functions which are lowered
+    /// afterwards to the linear memory.
+    pub fn enable_sandbox(&mut self, sandbox_mode: SandboxMode) {
+        self.sandbox_mode = Some(sandbox_mode);
+    }
+}
+
+/// Declare the imported data `__anna_sandbox_memory`.
+pub(crate) fn declare_sandbox_memory<M: Module>(module: &mut M) -> Result<DataId, ModuleError> {
+    module.declare_data(SANDBOX_MEMORY_NAME, Linkage::Import, false, false)
+}
+
+/// Translate the address (an offset in the linear memory) of the access into
+/// the native address, the `offset` (which is non-negative) of the access is
+/// not added, i.e. the caller emits `load native_address+offset`.
+pub(crate) fn emit_sandbox_address(
+    function_builder: &mut FunctionBuilder,
+    sandbox_mode: SandboxMode,
+    memory: GlobalValue,
+    address: Value,
+    offset: u32,
+    access_size: u32,
+) -> Value {
+    let pointer_type: Type = function_builder.func.dfg.value_type(address);
+    let flags = MemFlags::trusted();
+    let value_memory = function_builder.ins().symbol_value(pointer_type, memory);
+
+    let address = match sandbox_mode {
+        SandboxMode::BoundsChecked => {
+            // the last byte of the access is less than the length
+            let value_extent = function_builder
+                .ins()
+                .iconst(pointer_type, (offset + access_size - 1) as i64);
+            let value_last = function_builder.ins().uadd_overflow_trap(
+                address,
+                value_extent,
+                TrapCode::HEAP_OUT_OF_BOUNDS,
+            );
+            // the field `length` is at the offset 8 since `u64` is 8-byte aligned
+            let value_length = function_builder
+                .ins()
+                .load(types::I64, flags, value_memory, 8);
+            let value_length = if pointer_type == types::I64 {
+                value_length
+            } else {
+                function_builder.ins().ireduce(pointer_type, value_length)
+            };
+            emit_bounds_check(
+                function_builder,
+                value_last,
+                value_length,
+                TrapCode::HEAP_OUT_OF_BOUNDS,
+            );
+            address
+        }
+        SandboxMode::Guarded => function_builder.ins().band_imm(address, 0xffff_ffff),
+    };
+
+    let value_base = function_builder
+        .ins()
+        .load(pointer_type, flags, value_memory, 0);
+    function_builder.ins().iadd(value_base, address)
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        lowering::assemble_module,
+        parser::parse_module,
+        sandbox::{SandboxMemory, SandboxMode, SANDBOX_GUARDED_REGION_SIZE, SANDBOX_MEMORY_NAME},
+    };
+
+    // the result is `memory[address] + memory[address + 4]` after storing
+    // `memory[address + 4] = 7`
+    const SOURCE: &str = r#"
+    (module $script
+        (function $test (param $address i64) (result i32)
+            (code
+                (memory_store_i32 (local_load $address) 4 (imm_i32 7))
+                (add_i32
+                    (memory_load_i32 (local_load $address))
+                    (memory_load_i32 (local_load $address) 4)))))
+    "#;
+
+    fn build(
+        sandbox_mode: SandboxMode,
+        sandbox_memory: &SandboxMemory,
+    ) -> (Generator<JITModule>, extern "C" fn(i64) -> i32) {
+        let module = parse_module(SOURCE).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![(
+            SANDBOX_MEMORY_NAME.to_owned(),
+            sandbox_memory as *const SandboxMemory as *const u8,
+        )]);
+        generator.enable_sandbox(sandbox_mode);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+
+        generator.module.finalize_definitions().unwrap();
+        let func_test_ptr = generator
+            .module
+            .get_finalized_function(assembled_module.get_function_id("test").unwrap());
+        let func_test =
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i32>(func_test_ptr) };
+        (generator, func_test)
+    }
+
+    #[test]
+    fn test_sandbox() {
+        // the bounds-checked mode
+        let mut memory = vec![0u8; 16];
+        memory[0..4].copy_from_slice(&5i32.to_ne_bytes());
+        let sandbox_memory = SandboxMemory {
+            base: memory.as_mut_ptr(),
+            length: memory.len() as u64,
+        };
+        let (generator, func_test) = build(SandboxMode::BoundsChecked, &sandbox_memory);
+        assert!(generator.dump_clif().contains("trap heap_oob"));
+        assert_eq!(func_test(0), 12);
+        assert_eq!(memory[4..8], 7i32.to_ne_bytes());
+
+        // the guarded mode, the high bits of the address are ignored
+        let region = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                SANDBOX_GUARDED_REGION_SIZE as usize,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(region, libc::MAP_FAILED);
+        assert_eq!(
+            unsafe { libc::mprotect(region, 4096, libc::PROT_READ | libc::PROT_WRITE) },
+            0
+        );
+
+        let sandbox_memory = SandboxMemory {
+            base: region as *mut u8,
+            length: 4096,
+        };
+        let (generator, func_test) = build(SandboxMode::Guarded, &sandbox_memory);
+        assert!(!generator.dump_clif().contains("heap_oob"));
+        assert_eq!(func_test(0x1_0000_0010), 7);
+        assert_eq!(unsafe { *(region as *const i32).add(5) }, 7);
+        unsafe { libc::munmap(region, SANDBOX_GUARDED_REGION_SIZE as usize) };
+
+        // the native memory accesses are rejected
+        let module = parse_module(
+            r#"
+            (module $script
+                (data $count (read_write i32 0))
+                (function $test (result i32) (code (data_load_i32 $count 4))))
+            "#,
+        )
+        .unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        generator.enable_sandbox(SandboxMode::BoundsChecked);
+        assert_eq!(
+            assemble_module(&module, &mut generator)
+                .unwrap_err()
+                .message,
+            "the access of the data \"$count\" is out of bounds in the sandbox"
+        );
+
+        let module = parse_module(
+            r#"
+            (module $script
+                (function $test (param $s i64) (result i64) (code (string_length (local_load $s)))))
+            "#,
+        )
+        .unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        generator.enable_sandbox(SandboxMode::BoundsChecked);
+        assert_eq!(
+            assemble_module(&module, &mut generator)
+                .unwrap_err()
+                .message,
+            "the instruction \"string_length\" is not allowed in the sandbox"
+        );
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::{Display, Write};
+
+use cranelift_codegen::{ir::Function, CompiledCode};
+use cranelift_module::{FuncId, Module, ModuleError};
+
+use crate::code_generator::Generator;
+
+// The size budget of functions
+// ----------------------------
+//
+// The oversized (generated) functions hurt the I-cache behavior, and the
+// oversized stack frames may overflow the stack of the threads, the sizes of
+// the compiled functions are recorded (see `Generator::function_sizes`),
+// and `Generator::check_size_budget()` reports the functions which exceed
+// the given thresholds, e.g. to flag them in CI.
+//
+// the embedded targets which must bound the stack depth can also fail the
+// build by `Generator::set_frame_size_limit()`, and list the frames (with the
+// size of the stack slots and the spills) by `get_stack_frame_report()`.
+//
+// e.g.
+//
+// ```rust
+// let diagnostics = generator.check_size_budget(&SizeBudget {
+//     max_code_size: Some(4096),
+//     max_frame_size: Some(1024),
+// });
+// for diagnostic in &diagnostics {
+//     eprintln!("warning: {}", diagnostic);
+// }
+// ```
+
+/// The sizes of a compiled function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionSize {
+    pub func_id: FuncId,
+
+    /// the size of the machine code, in bytes.
+    pub code_size: u32,
+
+    /// the size of the stack frame (excluding the return address and the
+    /// frame pointer), in bytes.
+    pub frame_size: u32,
+
+    /// the size of the explicit stack slots (e.g. the local arrays and the
+    /// structs) in the frame, in bytes.
+    pub stack_slots_size: u32,
+
+    /// the size of the spill slots (the values which do not fit in the
+    /// registers) and the saved callee-saved registers in the frame, in bytes.
+    pub spill_size: u32,
+}
+
+impl FunctionSize {
+    pub(crate) fn new(
+        func_id: FuncId,
+        func_source: &Function,
+        compiled_code: &CompiledCode,
+    ) -> Self {
+        let stack_slots_size = compiled_code
+            .sized_stackslot_offsets
+            .iter()
+            .map(|(stack_slot, offset)| offset + func_source.sized_stack_slots[stack_slot].size)
+            .max()
+            .unwrap_or(0);
+
+        Self {
+            func_id,
+            code_size: compiled_code.buffer.total_size(),
+            frame_size: compiled_code.frame_size,
+            stack_slots_size,
+            spill_size: compiled_code.frame_size.saturating_sub(stack_slots_size),
+        }
+    }
+}
+
+/// The thresholds, `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeBudget {
+    pub max_code_size: Option<u32>,
+    pub max_frame_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeDiagnosticKind {
+    CodeSize,
+    FrameSize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeDiagnostic {
+    pub func_id: FuncId,
+    pub name: String,
+    pub kind: SizeDiagnosticKind,
+    pub size: u32,
+    pub limit: u32,
+}
+
+impl Display for SizeDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            SizeDiagnosticKind::CodeSize => "machine code",
+            SizeDiagnosticKind::FrameSize => "stack frame",
+        };
+
+        write!(
+            f,
+            "the {} size of function \"{}\" is {} bytes, exceeds the limit {} bytes",
+            kind, self.name, self.size, self.limit
+        )
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Fail `define_function()` if the stack frame of the function is larger
+    /// than the limit (in bytes), `None` means unlimited.
+    pub fn set_frame_size_limit(&mut self, limit: Option<u32>) {
+        self.frame_size_limit = limit;
+    }
+
+    pub fn get_function_size(&self, func_id: FuncId) -> Option<&FunctionSize> {
+        self.function_sizes
+            .iter()
+            .find(|item| item.func_id == func_id)
+    }
+
+    /// List the stack frames of the compiled functions, the largest first, e.g.
+    ///
+    /// ```text
+    ///      272      256       16 large
+    ///        0        0        0 small
+    /// ```
+    ///
+    /// the columns are the frame size, the size of the stack slots, the size of
+    /// the spill slots and the saved registers, and the name.
+    pub fn get_stack_frame_report(&self) -> String {
+        let mut function_sizes = self.function_sizes.iter().collect::<Vec<_>>();
+        function_sizes.sort_by_key(|item| std::cmp::Reverse(item.frame_size));
+
+        let mut text = String::new();
+        for function_size in function_sizes {
+            writeln!(
+                text,
+                "{:8} {:8} {:8} {}",
+                function_size.frame_size,
+                function_size.stack_slots_size,
+                function_size.spill_size,
+                self.get_function_name(function_size.func_id)
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    /// Check the frame size of the compiled function by the limit.
+    pub(crate) fn check_frame_size_limit(
+        &self,
+        func_id: FuncId,
+        compiled_code: &CompiledCode,
+    ) -> Result<(), ModuleError> {
+        match self.frame_size_limit {
+            Some(limit) if compiled_code.frame_size > limit => {
+                let diagnostic = SizeDiagnostic {
+                    func_id,
+                    name: self.get_function_name(func_id),
+                    kind: SizeDiagnosticKind::FrameSize,
+                    size: compiled_code.frame_size,
+                    limit,
+                };
+                Err(ModuleError::Backend(anyhow::anyhow!("{}", diagnostic)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn get_function_name(&self, func_id: FuncId) -> String {
+        self.module
+            .declarations()
+            .get_function_decl(func_id)
+            .linkage_name(func_id)
+            .into_owned()
+    }
+
+    /// Report the compiled functions which exceed the size budget,
+    /// in the order of definition.
+    pub fn check_size_budget(&self, budget: &SizeBudget) -> Vec<SizeDiagnostic> {
+        let mut diagnostics = vec![];
+
+        for function_size in &self.function_sizes {
+            let name = self.get_function_name(function_size.func_id);
+
+            for (kind, size, opt_limit) in [
+                (
+                    SizeDiagnosticKind::CodeSize,
+                    function_size.code_size,
+                    budget.max_code_size,
+                ),
+                (
+                    SizeDiagnosticKind::FrameSize,
+                    function_size.frame_size,
+                    budget.max_frame_size,
+                ),
+            ] {
+                if let Some(limit) = opt_limit {
+                    if size > limit {
+                        diagnostics.push(SizeDiagnostic {
+                            func_id: function_size.func_id,
+                            name: name.clone(),
+                            kind,
+                            size,
+                            limit,
+                        });
+                    }
+                }
+            }
+        }
+
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{
+        types, AbiParam, Function, InstBuilder, StackSlotData, StackSlotKind, UserFuncName,
+    };
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{FuncOrDataId, Linkage, Module, ModuleError};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        size_budget::{SizeBudget, SizeDiagnosticKind},
+    };
+
+    /// Build the function which has a stack slot, e.g. (stack size 256)
+    ///
+    /// ```rust
+    /// fn large() -> i32 {
+    ///     let buf = [0_u8; 256];
+    ///     buf[0]
+    /// }
+    /// ```
+    fn define_function_with_stack_slot(
+        generator: &mut Generator<ObjectModule>,
+        name: &str,
+        stack_size: u32,
+    ) -> Result<(), ModuleError> {
+        let mut func_sig = generator.module.make_signature();
+        func_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_id = generator
+            .module
+            .declare_function(name, Linkage::Export, &func_sig)
+            .unwrap();
+
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), func_sig);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = if stack_size > 0 {
+            let stack_slot = function_builder.create_sized_stack_slot(StackSlotData::new(
+                StackSlotKind::ExplicitSlot,
+                stack_size,
+                0,
+            ));
+            function_builder.ins().stack_load(types::I32, stack_slot, 0)
+        } else {
+            function_builder.ins().iconst(types::I32, 0)
+        };
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_id, func)
+    }
+
+    #[test]
+    fn test_check_size_budget() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+
+        // build functions "small" and "large"
+        for (name, stack_size) in [("small", 0), ("large", 256)] {
+            define_function_with_stack_slot(&mut generator, name, stack_size).unwrap();
+        }
+        assert_eq!(generator.function_sizes.len(), 2);
+        assert_eq!(generator.function_sizes[0].frame_size, 0);
+        assert!(generator.function_sizes[1].frame_size >= 256);
+
+        // unlimited
+        assert!(generator
+            .check_size_budget(&SizeBudget::default())
+            .is_empty());
+
+        let diagnostics = generator.check_size_budget(&SizeBudget {
+            max_code_size: Some(generator.function_sizes[0].code_size),
+            max_frame_size: Some(128),
+        });
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].name, "large");
+        assert_eq!(diagnostics[0].kind, SizeDiagnosticKind::CodeSize);
+        assert_eq!(diagnostics[1].name, "large");
+        assert_eq!(diagnostics[1].kind, SizeDiagnosticKind::FrameSize);
+        assert_eq!(diagnostics[1].limit, 128);
+        assert!(diagnostics[1]
+            .to_string()
+            .starts_with("the stack frame size of function \"large\" is "));
+    }
+
+    #[test]
+    fn test_frame_size_limit() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+        generator.set_frame_size_limit(Some(512));
+
+        define_function_with_stack_slot(&mut generator, "small", 0).unwrap();
+        define_function_with_stack_slot(&mut generator, "large", 256).unwrap();
+
+        let Some(FuncOrDataId::Func(func_large_id)) = generator.module.get_name("large") else {
+            unreachable!()
+        };
+        let function_size = generator.get_function_size(func_large_id).unwrap();
+        assert_eq!(function_size.stack_slots_size, 256);
+        assert_eq!(
+            function_size.spill_size,
+            function_size.frame_size - function_size.stack_slots_size
+        );
+
+        // the largest frame is listed first
+        let report = generator.get_stack_frame_report();
+        assert!(report.lines().next().unwrap().ends_with(" large"));
+        assert_eq!(report.lines().count(), 2);
+
+        let error = define_function_with_stack_slot(&mut generator, "huge", 1024).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("the stack frame size of function \"huge\" is "));
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{fmt::Write, path::Path};
+
+use cranelift_object::object::{read::File, Object, ObjectSection, SectionKind};
+
+use crate::{
+    object_dump::{format_functions, read_functions},
+    symbol_listing::{format_symbols, read_symbols},
+};
+
+// The golden-object snapshots
+// ---------------------------
+//
+// The object file is converted into the normalized text (i.e. the snapshot),
+// and compared with the golden file which is checked in with the tests, so the
+// regressions of the code generation are caught deterministically, e.g.
+//
+// ```rust
+// let module_binary = generator.finish().unwrap().emit().unwrap();
+// assert_snapshot(&module_binary, Path::new("tests/snapshots/inc.snap")).unwrap();
+// ```
+//
+// the snapshot consists of:
+//
+// - the sections, i.e. the names, the kinds and the sizes, and the content of
+//   the data sections.
+// - the symbols (see `symbol_listing.rs`), sorted by name.
+// - the functions, i.e. the machine code and the references (see `object_dump.rs`).
+//
+// the normalization:
+//
+// - the contents which depend on the toolchain version or the host (i.e. the
+//   `.comment` section, the version note and the debug information) are omitted,
+//   only their names are listed.
+// - the symbols are sorted by name, so the order of declaration does not matter.
+//
+// the golden file is created if it does not exist, and it is overwritten when
+// the environment variable `ANASM_UPDATE_SNAPSHOTS` is set (e.g. to "1"), after
+// the code generation is changed intentionally.
+
+pub const UPDATE_SNAPSHOTS_ENV_NAME: &str = "ANASM_UPDATE_SNAPSHOTS";
+
+/// The number of bytes in a row of the section content.
+const ROW_SIZE: usize = 16;
+
+/// Check whether the content of the section depends on the toolchain
+/// version or the host.
+fn is_volatile_section(name: &str) -> bool {
+    name == ".comment" || name == ".note.xiaoxuan.version" || name.starts_with(".debug_")
+}
+
+/// Convert the object file into the normalized snapshot text.
+pub fn object_snapshot(object_binary: &[u8]) -> Result<String, String> {
+    let file = File::parse(object_binary)
+        .map_err(|error| format!("failed to read the object file: {}", error))?;
+    let mut text = String::new();
+
+    text.push_str(";; sections\n");
+    for section in file.sections() {
+        let name = section.name().unwrap_or_default();
+        if name.is_empty() || section.kind() == SectionKind::Metadata {
+            // the symbol table, the string tables and the relocation sections
+            continue;
+        }
+        if is_volatile_section(name) {
+            writeln!(text, "{} (omitted)", name).unwrap();
+            continue;
+        }
+
+        writeln!(
+            text,
+            "{} ({:?}, {} bytes)",
+            name,
+            section.kind(),
+            section.size()
+        )
+        .unwrap();
+
+        if matches!(
+            section.kind(),
+            SectionKind::Data | SectionKind::ReadOnlyData | SectionKind::ReadOnlyString
+        ) {
+            let data = section.data().unwrap_or_default();
+            for (index, row) in data.chunks(ROW_SIZE).enumerate() {
+                let bytes = row
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(text, "  {:08x}  {}", index * ROW_SIZE, bytes).unwrap();
+            }
+        }
+    }
+
+    let mut symbols = read_symbols(object_binary)?;
+    symbols.sort_by(|left, right| left.name.cmp(&right.name));
+    text.push_str("\n;; symbols\n");
+    text.push_str(&format_symbols(&symbols));
+
+    text.push_str("\n;; functions\n");
+    text.push_str(&format_functions(&read_functions(object_binary)?));
+
+    Ok(text)
+}
+
+/// Compare the snapshot of the object file with the golden file, returns the
+/// line diff if they are different.
+///
+/// The golden file is written (and the comparison passes) if it does not exist
+/// or the environment variable `ANASM_UPDATE_SNAPSHOTS` is set.
+pub fn assert_snapshot(object_binary: &[u8], golden_file_path: &Path) -> Result<(), String> {
+    let snapshot = object_snapshot(object_binary)?;
+
+    let is_update = std::env::var_os(UPDATE_SNAPSHOTS_ENV_NAME).is_some();
+    if is_update || !golden_file_path.exists() {
+        if let Some(folder) = golden_file_path.parent() {
+            std::fs::create_dir_all(folder).map_err(|error| error.to_string())?;
+        }
+        return std::fs::write(golden_file_path, snapshot).map_err(|error| {
+            format!(
+                "failed to write the golden file \"{}\": {}",
+                golden_file_path.display(),
+                error
+            )
+        });
+    }
+
+    let golden = std::fs::read_to_string(golden_file_path).map_err(|error| {
+        format!(
+            "failed to read the golden file \"{}\": {}",
+            golden_file_path.display(),
+            error
+        )
+    })?;
+
+    if golden == snapshot {
+        Ok(())
+    } else {
+        Err(format!(
+            "the object does not match the golden file \"{}\" (set {} to update it):\n{}",
+            golden_file_path.display(),
+            UPDATE_SNAPSHOTS_ENV_NAME,
+            diff_lines(&golden, &snapshot)
+        ))
+    }
+}
+
+/// Generate the line diff of two texts by the longest common subsequence,
+/// the removed lines are prefixed with "-", the added lines with "+", and
+/// the unchanged lines are omitted except the headers (i.e. ";; ...").
+pub fn diff_lines(old: &str, new: &str) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+
+    // lengths[i][j] is the length of the LCS of old_lines[i..] and new_lines[j..]
+    let mut lengths = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            lengths[i][j] = if old_lines[i] == new_lines[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut text = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old_lines.len() || j < new_lines.len() {
+        if i < old_lines.len() && j < new_lines.len() && old_lines[i] == new_lines[j] {
+            if old_lines[i].starts_with(";; ") {
+                writeln!(text, " {}", old_lines[i]).unwrap();
+            }
+            i += 1;
+            j += 1;
+        } else if i < old_lines.len()
+            && (j == new_lines.len() || lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            writeln!(text, "-{}", old_lines[i]).unwrap();
+            i += 1;
+        } else {
+            writeln!(text, "+{}", new_lines[j]).unwrap();
+            j += 1;
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        lowering::assemble_module,
+        parser::parse_module,
+        snapshot::{assert_snapshot, diff_lines, object_snapshot},
+    };
+
+    fn emit(source: &str) -> Vec<u8> {
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        generator.finish().unwrap().emit().unwrap()
+    }
+
+    #[test]
+    fn test_snapshot() {
+        assert_eq!(
+            diff_lines(";; a\n1\n2\n3\n", ";; a\n1\n4\n3\n5\n"),
+            " ;; a\n-2\n+4\n+5\n"
+        );
+
+        let source = r#"
+        (module $app
+            (data $count export (read_write i32 11))
+            (function $inc (param $a i32) (result i32)
+                (code (add_i32 (local_load $a) (imm_i32 1))))
+            (function $main export (result i32)
+                (code (call $inc (data_load_i32 $count)))))
+        "#;
+        let module_binary = emit(source);
+
+        let snapshot = object_snapshot(&module_binary).unwrap();
+        assert!(snapshot.starts_with(";; sections\n"));
+        assert!(snapshot.contains(".comment (omitted)\n"));
+        assert!(snapshot.contains("\n  00000000  0b 00 00 00\n"));
+        assert!(snapshot.contains("\nfunction main (section: .text"));
+
+        // the same input generates the same snapshot
+        assert_eq!(object_snapshot(&emit(source)).unwrap(), snapshot);
+
+        let folder = std::env::temp_dir().join(format!("anc_test_snapshot_{}", std::process::id()));
+        let golden_file_path = folder.join("app.snap");
+
+        // the golden file is created at the first time
+        assert_snapshot(&module_binary, &golden_file_path).unwrap();
+        assert_snapshot(&module_binary, &golden_file_path).unwrap();
+
+        let changed_binary = emit(&source.replace("(read_write i32 11)", "(read_write i32 12)"));
+        let message = assert_snapshot(&changed_binary, &golden_file_path).unwrap_err();
+        assert!(message.contains("\n-  00000000  0b 00 00 00\n+  00000000  0c 00 00 00\n"));
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
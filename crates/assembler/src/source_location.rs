@@ -0,0 +1,178 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::SourceLoc;
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::FuncId;
+
+// The source location tracking
+// ----------------------------
+//
+// Cranelift attaches a `SourceLoc` (an opaque u32) to each IR instruction, and
+// records the "machine code range -> SourceLoc" mapping after compilation.
+//
+// In this crate the `SourceLoc` is the index of the location table of `SourceMap`,
+// each entry of the table is a `SourceLocation` (file id, line, column), e.g.
+//
+// ```rust
+// let file_id = generator.source_map.add_source_file("main.anca");
+//
+// // attach the location to the following instructions
+// generator.source_map.set_source_location(&mut function_builder, file_id, 2, 4);
+// let value_0 = function_builder.ins().iconst(types::I32, 11);
+// ...
+// ```
+//
+// then the mapping is collected by `Generator::define_function()`, and it is used by:
+//
+// - the DWARF generator (the `.debug_line` section).
+// - `Generator<JITModule>::lookup_source_location()` (the stack traces of JIT code).
+
+/// The source location of an instruction, i.e. the "file:line:column" in
+/// the XiaoXuan Native Assembly source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file_id: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A continuous range of the machine code of a function which is generated
+/// from the same source location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineMapping {
+    /// the start offset of the machine code, relative to the start of the function.
+    pub start: u32,
+
+    /// the end offset (exclusive) of the machine code, relative to the start of the function.
+    pub end: u32,
+
+    pub source_loc: SourceLoc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSourceMap {
+    pub func_id: FuncId,
+
+    /// the size of the machine code of the function.
+    pub code_size: u32,
+    pub line_mappings: Vec<LineMapping>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    source_files: Vec<String>,
+    source_locations: Vec<SourceLocation>,
+    functions: Vec<FunctionSourceMap>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a source file and return its id, the file which already exists
+    /// returns the existing id.
+    pub fn add_source_file(&mut self, path: &str) -> u32 {
+        match self.source_files.iter().position(|item| item == path) {
+            Some(idx) => idx as u32,
+            None => {
+                self.source_files.push(path.to_owned());
+                (self.source_files.len() - 1) as u32
+            }
+        }
+    }
+
+    pub fn get_source_file(&self, file_id: u32) -> Option<&str> {
+        self.source_files.get(file_id as usize).map(|s| s.as_str())
+    }
+
+    pub fn get_source_files(&self) -> &[String] {
+        &self.source_files
+    }
+
+    /// Add a source location and return the `SourceLoc` which can be
+    /// attached to the IR instructions.
+    pub fn add_source_location(&mut self, file_id: u32, line: u32, column: u32) -> SourceLoc {
+        let location = SourceLocation {
+            file_id,
+            line,
+            column,
+        };
+
+        // the last location is most likely to be reused since the instructions
+        // are usually generated line by line.
+        if let Some(idx) = self
+            .source_locations
+            .iter()
+            .rposition(|item| item == &location)
+        {
+            return SourceLoc::new(idx as u32);
+        }
+
+        self.source_locations.push(location);
+        SourceLoc::new((self.source_locations.len() - 1) as u32)
+    }
+
+    pub fn get_source_location(&self, source_loc: SourceLoc) -> Option<&SourceLocation> {
+        if source_loc.is_default() {
+            None
+        } else {
+            self.source_locations.get(source_loc.bits() as usize)
+        }
+    }
+
+    /// Attach the source location to the instructions which will be
+    /// inserted by the function builder subsequently.
+    pub fn set_source_location(
+        &mut self,
+        function_builder: &mut FunctionBuilder,
+        file_id: u32,
+        line: u32,
+        column: u32,
+    ) -> SourceLoc {
+        let source_loc = self.add_source_location(file_id, line, column);
+        function_builder.set_srcloc(source_loc);
+        source_loc
+    }
+
+    /// Detach the source location, the instructions inserted subsequently
+    /// have no source location.
+    pub fn clear_source_location(&self, function_builder: &mut FunctionBuilder) {
+        function_builder.set_srcloc(SourceLoc::default());
+    }
+
+    pub fn get_function(&self, func_id: FuncId) -> Option<&FunctionSourceMap> {
+        self.functions.iter().find(|item| item.func_id == func_id)
+    }
+
+    pub fn get_functions(&self) -> &[FunctionSourceMap] {
+        &self.functions
+    }
+
+    /// Find the source location of the machine code.
+    ///
+    /// - `code_offset`: the offset of the machine code, relative to the start of the function.
+    pub fn lookup(&self, func_id: FuncId, code_offset: u32) -> Option<&SourceLocation> {
+        let function = self.get_function(func_id)?;
+        function
+            .line_mappings
+            .iter()
+            .find(|item| item.start <= code_offset && code_offset < item.end)
+            .and_then(|item| self.get_source_location(item.source_loc))
+    }
+
+    pub(crate) fn set_function(&mut self, function_source_map: FunctionSourceMap) {
+        match self
+            .functions
+            .iter_mut()
+            .find(|item| item.func_id == function_source_map.func_id)
+        {
+            Some(item) => *item = function_source_map,
+            None => self.functions.push(function_source_map),
+        }
+    }
+}
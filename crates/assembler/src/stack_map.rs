@@ -0,0 +1,220 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::{ir::Type, CompiledCode};
+#[cfg(feature = "jit")]
+use cranelift_jit::JITModule;
+use cranelift_module::{FuncId, Module};
+
+use crate::code_generator::Generator;
+
+// The GC stack maps
+// -----------------
+//
+// A precise garbage collector needs to know which stack slots of each native
+// frame hold the GC references when the execution is paused at a safepoint
+// (i.e. a call, the GC can only be triggered by calling the runtime).
+//
+// the SSA values are marked as GC references by the front end when building
+// the function, e.g.
+//
+// ```rust
+// let value_0 = function_builder.ins().call(...);  // returns a GC reference
+// function_builder.declare_value_needs_stack_map(value_0);
+// ```
+//
+// Cranelift spills the marked values which are live across a call to the stack,
+// and generates a stack map for the call, the stack maps are collected by
+// `Generator::define_function()` (see `Generator::stack_maps`), each safepoint
+// records:
+//
+// - the offset of the return address (relative to the start of the function),
+//   i.e. the address which is found by the stack walker.
+// - the size of the active frame.
+// - the locations of the GC references, i.e. the offsets relative to the SP.
+//
+// ref:
+// - https://docs.rs/cranelift-frontend/latest/cranelift_frontend/struct.FunctionBuilder.html#method.declare_value_needs_stack_map
+
+/// A GC reference on the stack frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackMapEntry {
+    /// the type of the reference, e.g. `i64`.
+    pub ty: Type,
+
+    /// the offset relative to the SP at the safepoint.
+    pub sp_offset: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Safepoint {
+    /// the offset of the return address of the call, relative to the start of the function.
+    pub code_offset: u32,
+
+    /// the size of the active frame (from the SP to the return address).
+    pub frame_size: u32,
+    pub entries: Vec<StackMapEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionStackMap {
+    pub func_id: FuncId,
+
+    /// the safepoints which are sorted by the code offset.
+    pub safepoints: Vec<Safepoint>,
+}
+
+impl FunctionStackMap {
+    /// Collect the safepoints of the compiled function, returns `None` if
+    /// there is no GC reference.
+    pub(crate) fn new(
+        func_id: FuncId,
+        compiled_code: &CompiledCode,
+        entry_offset: u32,
+    ) -> Option<Self> {
+        let safepoints = compiled_code
+            .buffer
+            .user_stack_maps()
+            .iter()
+            .map(|(code_offset, frame_size, stack_map)| Safepoint {
+                code_offset: code_offset + entry_offset,
+                frame_size: *frame_size,
+                entries: stack_map
+                    .entries()
+                    .map(|(ty, sp_offset)| StackMapEntry { ty, sp_offset })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+
+        if safepoints.is_empty() {
+            None
+        } else {
+            Some(Self {
+                func_id,
+                safepoints,
+            })
+        }
+    }
+
+    pub fn get_safepoint(&self, code_offset: u32) -> Option<&Safepoint> {
+        self.safepoints
+            .binary_search_by_key(&code_offset, |safepoint| safepoint.code_offset)
+            .ok()
+            .map(|idx| &self.safepoints[idx])
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    pub fn get_stack_map(&self, func_id: FuncId) -> Option<&FunctionStackMap> {
+        self.stack_maps.iter().find(|item| item.func_id == func_id)
+    }
+}
+
+#[cfg(feature = "jit")]
+impl Generator<JITModule> {
+    /// Find the function and the safepoint of the given return address,
+    /// it is used by the GC to walk the native frames.
+    ///
+    /// Note that it is only available after 'module.finalize_definitions()'.
+    pub fn lookup_safepoint(&self, return_address: usize) -> Option<(FuncId, &Safepoint)> {
+        self.stack_maps.iter().find_map(|stack_map| {
+            let start = self.module.get_finalized_function(stack_map.func_id) as usize;
+            let code_offset = u32::try_from(return_address.checked_sub(start)?).ok()?;
+            stack_map
+                .get_safepoint(code_offset)
+                .map(|safepoint| (stack_map.func_id, safepoint))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+
+    use crate::code_generator::Generator;
+
+    extern "C" fn gc() {}
+
+    #[test]
+    fn test_stack_map() {
+        let symbols = vec![("gc".to_owned(), gc as *const u8)];
+        let mut generator = Generator::<JITModule>::new(symbols);
+
+        // ```rust
+        // fn main(obj: ref) -> ref {
+        //     gc();
+        //     obj
+        // }
+        // ```
+        let func_gc_sig = generator.module.make_signature();
+        let func_gc_id = generator
+            .module
+            .declare_function("gc", Linkage::Import, &func_gc_sig)
+            .unwrap();
+
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.params.push(AbiParam::new(types::I64));
+        func_main_sig.returns.push(AbiParam::new(types::I64));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Local, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let func_gc_ref = generator
+            .module
+            .declare_func_in_func(func_gc_id, &mut func_main);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.block_params(block)[0];
+        function_builder.declare_value_needs_stack_map(value_0);
+        function_builder.ins().call(func_gc_ref, &[]);
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+
+        let stack_map = generator.get_stack_map(func_main_id).unwrap();
+        assert_eq!(stack_map.safepoints.len(), 1);
+
+        let safepoint = stack_map.safepoints[0].clone();
+        assert_eq!(safepoint.entries.len(), 1);
+        assert_eq!(safepoint.entries[0].ty, types::I64);
+        assert!(safepoint.entries[0].sp_offset < safepoint.frame_size);
+
+        generator.module.finalize_definitions().unwrap();
+
+        let func_main_ptr = generator.module.get_finalized_function(func_main_id);
+        let return_address = func_main_ptr as usize + safepoint.code_offset as usize;
+        assert_eq!(
+            generator.lookup_safepoint(return_address),
+            Some((func_main_id, &safepoint))
+        );
+        assert!(generator.lookup_safepoint(return_address + 1).is_none());
+
+        let func_main: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(func_main_ptr) };
+        assert_eq!(func_main(0x1234), 0x1234);
+    }
+}
@@ -0,0 +1,485 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{types, Type};
+
+use crate::{
+    ast::{LoadType, StoreType},
+    constant::{ConstValue, Constants},
+    diagnostic::Diagnostic,
+    layout::{DataType, StructLayout},
+    lexer::{Span, Token, TokenKind},
+    parser::SExpr,
+};
+
+// The struct types
+// ----------------
+//
+// The structs and unions are declared in the module, and the fields are
+// accessed by the pseudo-instructions `field-load`, `field-store` and
+// `field-addr`, e.g.
+//
+// ```text
+// (module $app
+//     (struct $Point (field $x i32) (field $y i32))
+//     (struct $Shape (field $tag u8) (field $origin $Point) (field $sizes (array f64 2)))
+//     (union $Value (field $integer i64) (field $float f64))
+//
+//     (function $get_y (param $shape i64) (result i32)
+//         (code (field-load $Shape $origin.y (local_load $shape))))
+//
+//     (function $set_tag (param $shape i64) (param $tag i32)
+//         (code (field-store $Shape $tag (local_load $shape) (local_load $tag))))
+//
+//     (function $get_sizes (param $shape i64) (result i64)
+//         (code (field-addr $Shape $sizes (local_load $shape)))))
+// ```
+//
+// - the field types are the scalars `i8`, `u8`, `i16`, `u16`, `i32`, `i64`, `f32`
+//   and `f64`, the structs (or unions) which are declared before, and the
+//   arrays `(array type length)`.
+// - the layouts are calculated by `layout.rs` (i.e. the C rules).
+// - the field is specified by the path, e.g. `$origin.y` is the field `y` of
+//   the field `origin`.
+// - `field-load` and `field-store` are converted into `memory_load_*` and
+//   `memory_store_*` with the offset of the field, the 8-bit and 16-bit fields are
+//   extended to `i32` by the signedness of the field type. `field-addr` is
+//   converted into `(add_i64 address (imm_i64 offset))`, so the fields of any
+//   type (including the arrays) can be addressed.
+// - the struct types can also be used by `sizeof` and `alignof` of the
+//   constant expressions, e.g. `(sizeof $Shape)`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "i8" => Some(ScalarType::I8),
+            "u8" => Some(ScalarType::U8),
+            "i16" => Some(ScalarType::I16),
+            "u16" => Some(ScalarType::U16),
+            "i32" => Some(ScalarType::I32),
+            "i64" => Some(ScalarType::I64),
+            "f32" => Some(ScalarType::F32),
+            "f64" => Some(ScalarType::F64),
+            _ => None,
+        }
+    }
+
+    pub fn ir_type(&self) -> Type {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => types::I8,
+            ScalarType::I16 | ScalarType::U16 => types::I16,
+            ScalarType::I32 => types::I32,
+            ScalarType::I64 => types::I64,
+            ScalarType::F32 => types::F32,
+            ScalarType::F64 => types::F64,
+        }
+    }
+
+    pub fn load_type(&self) -> LoadType {
+        match self {
+            ScalarType::I8 => LoadType::I8S,
+            ScalarType::U8 => LoadType::I8U,
+            ScalarType::I16 => LoadType::I16S,
+            ScalarType::U16 => LoadType::I16U,
+            ScalarType::I32 => LoadType::I32,
+            ScalarType::I64 => LoadType::I64,
+            ScalarType::F32 => LoadType::F32,
+            ScalarType::F64 => LoadType::F64,
+        }
+    }
+
+    pub fn store_type(&self) -> StoreType {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => StoreType::I8,
+            ScalarType::I16 | ScalarType::U16 => StoreType::I16,
+            ScalarType::I32 => StoreType::I32,
+            ScalarType::I64 => StoreType::I64,
+            ScalarType::F32 => StoreType::F32,
+            ScalarType::F64 => StoreType::F64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldType {
+    Scalar(ScalarType),
+
+    /// The name of the struct (or union) type.
+    Struct(String),
+    Array(Box<FieldType>, u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructType {
+    pub fields: Vec<(String, FieldType)>,
+    pub layout: StructLayout,
+}
+
+/// The struct (and union) types of a module, in the order of declaration.
+#[derive(Debug, Default)]
+pub struct StructTypes {
+    items: Vec<(String, StructType)>,
+}
+
+impl StructTypes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StructType> {
+        self.items
+            .iter()
+            .find(|(item_name, _)| item_name == name)
+            .map(|(_, item)| item)
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, struct_type: StructType) {
+        self.items.push((name.to_owned(), struct_type));
+    }
+
+    /// `(struct $Name (field $name type)*)` or `(union $Name (field $name type)*)`,
+    /// the lengths of the arrays can be the constant expressions.
+    pub(crate) fn convert_declaration(
+        &self,
+        sexpr: &SExpr,
+        constants: &Constants,
+    ) -> Result<(String, StructType), Diagnostic> {
+        let SExpr::List { items, span } = sexpr else {
+            unreachable!()
+        };
+        let is_union = sexpr.get_head_keyword() == Some("union");
+
+        let (name, name_span) = get_name(items.get(1)).ok_or_else(|| {
+            Diagnostic::new(
+                "expect the name of the type, e.g. \"(struct $Name ...)\"",
+                *span,
+            )
+        })?;
+
+        if self.get(name).is_some() {
+            return Err(Diagnostic::new(
+                &format!("duplicate struct type \"${}\"", name),
+                name_span,
+            ));
+        }
+
+        let mut fields: Vec<(String, FieldType)> = vec![];
+        for item in &items[2..] {
+            let (field_name, field_name_span, field_type) = match item {
+                SExpr::List {
+                    items: field_items, ..
+                } if item.get_head_keyword() == Some("field") => {
+                    let (field_name, field_name_span) =
+                        get_name(field_items.get(1)).ok_or_else(|| {
+                            Diagnostic::new("expect the name of the field", item.span())
+                        })?;
+                    let [_, _, field_type] = field_items.as_slice() else {
+                        return Err(Diagnostic::new(
+                            &format!(
+                                "expect \"(field ${} type)\", e.g. \"(field ${} i32)\"",
+                                field_name, field_name
+                            ),
+                            item.span(),
+                        ));
+                    };
+                    (
+                        field_name,
+                        field_name_span,
+                        self.convert_field_type(field_type, constants)?,
+                    )
+                }
+                _ => {
+                    return Err(Diagnostic::new(
+                        &format!("expect \"(field ...)\", found {}", item.describe()),
+                        item.span(),
+                    ))
+                }
+            };
+
+            if fields.iter().any(|(existing, _)| existing == field_name) {
+                return Err(Diagnostic::new(
+                    &format!("duplicate field \"${}\"", field_name),
+                    field_name_span,
+                ));
+            }
+            fields.push((field_name.to_owned(), field_type));
+        }
+
+        let layout_fields = fields
+            .iter()
+            .map(|(field_name, field_type)| (field_name.clone(), self.get_data_type(field_type)))
+            .collect();
+        let layout = if is_union {
+            StructLayout::new_union(layout_fields)
+        } else {
+            StructLayout::new_struct(layout_fields)
+        };
+
+        Ok((name.to_owned(), StructType { fields, layout }))
+    }
+
+    fn convert_field_type(
+        &self,
+        sexpr: &SExpr,
+        constants: &Constants,
+    ) -> Result<FieldType, Diagnostic> {
+        let span = sexpr.span();
+        match sexpr {
+            SExpr::Atom(Token {
+                kind: TokenKind::Identifier(identifier),
+                ..
+            }) => ScalarType::from_name(identifier)
+                .map(FieldType::Scalar)
+                .ok_or_else(|| {
+                    Diagnostic::new(
+                        &format!(
+                            "unknown field type \"{}\", expect {}",
+                            identifier,
+                            "\"i8\", \"u8\", \"i16\", \"u16\", \"i32\", \"i64\", \"f32\" or \"f64\""
+                        ),
+                        span,
+                    )
+                }),
+            SExpr::Atom(Token {
+                kind: TokenKind::Name(name),
+                ..
+            }) => match self.get(name) {
+                Some(_) => Ok(FieldType::Struct(name.clone())),
+                None => Err(Diagnostic::new(
+                    &format!("the struct type \"${}\" is not declared", name),
+                    span,
+                )
+                .with_note("the struct types should be declared before they are used")),
+            },
+            SExpr::List { items, .. } if sexpr.get_head_keyword() == Some("array") => {
+                let [_, element, length] = items.as_slice() else {
+                    return Err(Diagnostic::new("expect \"(array type length)\"", span));
+                };
+                let element = self.convert_field_type(element, constants)?;
+                let length = match constants.evaluate(length)? {
+                    ConstValue::Integer(value) if (0..=u32::MAX as i128).contains(&value) => {
+                        value as u32
+                    }
+                    _ => {
+                        return Err(Diagnostic::new(
+                            "the length of the array should be an unsigned 32-bit integer",
+                            length.span(),
+                        ))
+                    }
+                };
+                Ok(FieldType::Array(Box::new(element), length))
+            }
+            _ => Err(Diagnostic::new(
+                &format!(
+                    "expect a field type, e.g. \"i32\", \"$Name\" or \"(array ...)\", found {}",
+                    sexpr.describe()
+                ),
+                span,
+            )),
+        }
+    }
+
+    /// Get the data type (for calculating the layout) of the field type.
+    pub fn get_data_type(&self, field_type: &FieldType) -> DataType {
+        match field_type {
+            FieldType::Scalar(scalar_type) => DataType::Scalar(scalar_type.ir_type()),
+            FieldType::Struct(name) => DataType::Struct(self.get(name).unwrap().layout.clone()),
+            FieldType::Array(element, length) => {
+                DataType::Array(Box::new(self.get_data_type(element)), *length)
+            }
+        }
+    }
+
+    /// Get the offset and the type of the field by the path, e.g. "origin.y".
+    pub fn resolve_field(
+        &self,
+        struct_name: &str,
+        path: &str,
+        span: Span,
+    ) -> Result<(u32, &FieldType), Diagnostic> {
+        let mut struct_type = self.get(struct_name).ok_or_else(|| {
+            Diagnostic::new(
+                &format!("the struct type \"${}\" is not declared", struct_name),
+                span,
+            )
+        })?;
+        let mut current_name = struct_name;
+        let mut offset = 0;
+
+        let mut names = path.split('.').peekable();
+        while let Some(name) = names.next() {
+            let (_, field_type) = struct_type
+                .fields
+                .iter()
+                .find(|(field_name, _)| field_name == name)
+                .ok_or_else(|| {
+                    Diagnostic::new(
+                        &format!(
+                            "the struct type \"${}\" has no field \"${}\"",
+                            current_name, name
+                        ),
+                        span,
+                    )
+                })?;
+            offset += struct_type.layout.get_field(name).unwrap().offset;
+
+            if names.peek().is_none() {
+                return Ok((offset, field_type));
+            }
+
+            match field_type {
+                FieldType::Struct(field_struct_name) => {
+                    current_name = field_struct_name;
+                    struct_type = self.get(field_struct_name).unwrap();
+                }
+                _ => {
+                    return Err(Diagnostic::new(
+                        &format!(
+                            "the field \"${}\" of the struct type \"${}\" is not a struct",
+                            name, current_name
+                        ),
+                        span,
+                    ))
+                }
+            }
+        }
+
+        unreachable!()
+    }
+}
+
+fn get_name(sexpr: Option<&SExpr>) -> Option<(&str, Span)> {
+    match sexpr {
+        Some(SExpr::Atom(Token {
+            kind: TokenKind::Name(name),
+            span,
+        })) => Some((name, *span)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator, diagnostic::Diagnostic, lowering::assemble_module,
+        parser::parse_module,
+    };
+
+    #[test]
+    fn test_struct_types() {
+        let source = r#"
+        (module $app
+            (struct $Point (field $x i32) (field $y i32))
+            (const $SIZE_COUNT 2)
+            (struct $Shape
+                (field $tag u8)
+                (field $origin $Point)
+                (field $sizes (array f64 $SIZE_COUNT))
+                (field $level i16))
+            (union $Value (field $integer i64) (field $bytes (array u8 12)))
+            (const $SHAPE_SIZE (sizeof $Shape))
+
+            (data $shape (uninit $SHAPE_SIZE (alignof $Shape)))
+
+            (function $main (result i32)
+                (code
+                    (field-store $Shape $tag (host_addr_data $shape) (imm_i32 200))
+                    (field-store $Shape $origin.y (host_addr_data $shape) (imm_i32 7))
+                    (field-store $Shape $level (host_addr_data $shape) (imm_i32 -3))
+                    (add_i32
+                        (field-load $Shape $tag (host_addr_data $shape))
+                        (add_i32
+                            (field-load $Shape $origin.y (host_addr_data $shape))
+                            (field-load $Shape $level (host_addr_data $shape))))))
+
+            (function $sizes_offset (result i64)
+                (code
+                    (sub_i64
+                        (field-addr $Shape $sizes (host_addr_data $shape))
+                        (host_addr_data $shape))))
+
+            (function $sizes (result i32)
+                (code (imm_i32 (add (mul $SHAPE_SIZE 100) (sizeof $Value))))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+        let get_function_ptr = |name: &str| {
+            generator
+                .module
+                .get_finalized_function(assembled_module.get_function_id(name).unwrap())
+        };
+
+        let main: extern "C" fn() -> i32 = unsafe { std::mem::transmute(get_function_ptr("main")) };
+        let sizes_offset: extern "C" fn() -> i64 =
+            unsafe { std::mem::transmute(get_function_ptr("sizes_offset")) };
+        let sizes: extern "C" fn() -> i32 =
+            unsafe { std::mem::transmute(get_function_ptr("sizes")) };
+
+        // the "tag" is unsigned and the "level" is signed
+        assert_eq!(main(), 200 + 7 - 3);
+        assert_eq!(sizes_offset(), 16);
+        // Shape: u8 @0, Point @4, [f64; 2] @16, i16 @32, size 40
+        // Value: size 16 (the 12 bytes aligned to 8)
+        assert_eq!(sizes(), 40 * 100 + 16);
+
+        // the errors
+        fn parse_error(source: &str) -> (String, &str) {
+            let Diagnostic { message, span, .. } = parse_module(source).unwrap_err();
+            (message, &source[span.start..span.end])
+        }
+
+        assert_eq!(
+            parse_error("(module $a (function $f (code (field-addr $P $x (imm_i64 0)))))"),
+            ("the struct type \"$P\" is not declared".to_owned(), "$x")
+        );
+        assert_eq!(
+            parse_error(
+                "(module $a (struct $P (field $x i32)) (function $f (code (field-addr $P $x.y (imm_i64 0)))))"
+            ),
+            (
+                "the field \"$x\" of the struct type \"$P\" is not a struct".to_owned(),
+                "$x.y"
+            )
+        );
+        assert_eq!(
+            parse_error(
+                "(module $a (struct $P (field $x i32)) (function $f (code (field-addr $P $z (imm_i64 0)))))"
+            ),
+            ("the struct type \"$P\" has no field \"$z\"".to_owned(), "$z")
+        );
+        assert_eq!(
+            parse_error(
+                "(module $a (struct $P (field $x (array i32 2))) (function $f (result i32) (code (field-load $P $x (imm_i64 0)))))"
+            ),
+            (
+                "the field \"$x\" is not a scalar, only its address can be taken by \"field-addr\"".to_owned(),
+                "$x"
+            )
+        );
+        assert_eq!(
+            parse_error("(module $a (struct $P (field $q $Q)) (struct $Q (field $x i32)))"),
+            ("the struct type \"$Q\" is not declared".to_owned(), "$Q")
+        );
+    }
+}
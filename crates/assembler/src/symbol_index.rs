@@ -0,0 +1,312 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::{Display, Write};
+
+use cranelift_module::Module;
+
+use crate::code_generator::Generator;
+
+// The symbol index
+// ----------------
+//
+// The `FuncId` and `DataId` are assigned by the module in the order of
+// declaration, starting from 0, and the symbols of the object file are added
+// in the same order (the generator never declares the symbols in the order
+// of a hash map), so for the same input:
+//
+// - the ids of the functions and data are the same across the runs.
+// - the order of the symbol table is the order of declaration, except that
+//   the local symbols precede the global ones (required by ELF).
+//
+// `Generator::get_symbol_index()` takes the "id -> name" map of the module,
+// it can be persisted as text alongside the object file, e.g.
+//
+// ```text
+// function 0 main
+// function 1 puts
+// data 0 count
+// ```
+//
+// and `SymbolIndex::diff()` compares it with the map of a later build, the
+// changes explain the differences of the binaries (e.g. the relocations and
+// the symbol indices shift if a function is inserted), and an empty diff
+// confirms that the ids are stable.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolIndexKind {
+    Function,
+    Data,
+}
+
+impl SymbolIndexKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolIndexKind::Function => "function",
+            SymbolIndexKind::Data => "data",
+        }
+    }
+}
+
+/// The names of the functions and data, the index is the id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolIndex {
+    pub functions: Vec<String>,
+    pub data: Vec<String>,
+}
+
+/// The symbol which is added, removed or has a different id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolIndexChange {
+    pub kind: SymbolIndexKind,
+    pub name: String,
+
+    /// The id of the previous build, `None` if the symbol is added.
+    pub previous: Option<u32>,
+
+    /// The id of the current build, `None` if the symbol is removed.
+    pub current: Option<u32>,
+}
+
+impl Display for SymbolIndexChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = self.kind.name();
+        match (self.previous, self.current) {
+            (Some(previous), Some(current)) => write!(
+                f,
+                "{} \"{}\" is moved from {} to {}",
+                kind, self.name, previous, current
+            ),
+            (None, Some(current)) => {
+                write!(f, "{} \"{}\" is added as {}", kind, self.name, current)
+            }
+            (Some(previous), None) => {
+                write!(f, "{} \"{}\" is removed from {}", kind, self.name, previous)
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+impl SymbolIndex {
+    /// Format the index as text, one symbol per line, i.e. "kind id name".
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (kind, names) in [
+            (SymbolIndexKind::Function, &self.functions),
+            (SymbolIndexKind::Data, &self.data),
+        ] {
+            for (id, name) in names.iter().enumerate() {
+                writeln!(text, "{} {} {}", kind.name(), id, name).unwrap();
+            }
+        }
+        text
+    }
+
+    /// Parse the text which is generated by `to_text()`.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut symbol_index = SymbolIndex::default();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let mut parts = line.splitn(3, ' ');
+            let (Some(kind), Some(id), Some(name)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(format!("invalid symbol index at line {}", line_index + 1));
+            };
+
+            let names = match kind {
+                "function" => &mut symbol_index.functions,
+                "data" => &mut symbol_index.data,
+                _ => {
+                    return Err(format!(
+                        "unknown symbol kind \"{}\" at line {}",
+                        kind,
+                        line_index + 1
+                    ))
+                }
+            };
+
+            // the ids are consecutive
+            if id.parse::<usize>() != Ok(names.len()) {
+                return Err(format!(
+                    "unexpected symbol id \"{}\" at line {}",
+                    id,
+                    line_index + 1
+                ));
+            }
+
+            names.push(name.to_owned());
+        }
+
+        Ok(symbol_index)
+    }
+
+    /// Compare with the index of the previous build, the changes are in the
+    /// order of the current ids, then the removed symbols.
+    pub fn diff(&self, previous: &SymbolIndex) -> Vec<SymbolIndexChange> {
+        let mut changes = vec![];
+
+        for (kind, current_names, previous_names) in [
+            (
+                SymbolIndexKind::Function,
+                &self.functions,
+                &previous.functions,
+            ),
+            (SymbolIndexKind::Data, &self.data, &previous.data),
+        ] {
+            let find = |names: &[String], name: &str| {
+                names
+                    .iter()
+                    .position(|item| item == name)
+                    .map(|id| id as u32)
+            };
+
+            for (id, name) in current_names.iter().enumerate() {
+                let previous_id = find(previous_names, name);
+                if previous_id != Some(id as u32) {
+                    changes.push(SymbolIndexChange {
+                        kind,
+                        name: name.clone(),
+                        previous: previous_id,
+                        current: Some(id as u32),
+                    });
+                }
+            }
+
+            for (id, name) in previous_names.iter().enumerate() {
+                if find(current_names, name).is_none() {
+                    changes.push(SymbolIndexChange {
+                        kind,
+                        name: name.clone(),
+                        previous: Some(id as u32),
+                        current: None,
+                    });
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Get the names of the declared functions and data by their ids.
+    pub fn get_symbol_index(&self) -> SymbolIndex {
+        let declarations = self.module.declarations();
+
+        SymbolIndex {
+            functions: declarations
+                .get_functions()
+                .map(|(func_id, decl)| decl.linkage_name(func_id).into_owned())
+                .collect(),
+            data: declarations
+                .get_data_objects()
+                .map(|(data_id, decl)| decl.linkage_name(data_id).into_owned())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        lowering::assemble_module,
+        parser::parse_module,
+        symbol_index::{SymbolIndex, SymbolIndexChange, SymbolIndexKind},
+        symbol_listing::read_symbols,
+    };
+
+    fn build(source: &str) -> (SymbolIndex, Vec<u8>) {
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        generator.enable_reproducible_build();
+        assemble_module(&module, &mut generator).unwrap();
+        let symbol_index = generator.get_symbol_index();
+        (symbol_index, generator.finish().unwrap().emit().unwrap())
+    }
+
+    #[test]
+    fn test_symbol_index() {
+        let source = r#"
+        (module $main
+            (import (function $puts (param i64) (result i32)))
+            (data $count export (read_write i32 0))
+            (function $inc (result i32) (code (imm_i32 1)))
+            (function $main export (result i32) (code (call $inc))))
+        "#;
+
+        let (symbol_index, binary) = build(source);
+        assert_eq!(
+            symbol_index.to_text(),
+            "\
+function 0 puts
+function 1 inc
+function 2 main
+data 0 count
+"
+        );
+        assert_eq!(
+            SymbolIndex::from_text(&symbol_index.to_text()).unwrap(),
+            symbol_index
+        );
+
+        // stable across the runs
+        let (symbol_index_again, binary_again) = build(source);
+        assert!(symbol_index_again.diff(&symbol_index).is_empty());
+        assert_eq!(binary_again, binary);
+
+        // the symbol table is in the order of declaration, the locals first
+        let names = read_symbols(&binary)
+            .unwrap()
+            .into_iter()
+            .map(|symbol| symbol.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["inc", "puts", "count", "main"]);
+
+        // insert a function
+        let (symbol_index_changed, _) = build(&source.replace(
+            "(function $inc",
+            "(function $dec (result i32) (code (imm_i32 -1))) (function $inc",
+        ));
+        let changes = symbol_index_changed.diff(&symbol_index);
+        assert_eq!(
+            changes,
+            vec![
+                SymbolIndexChange {
+                    kind: SymbolIndexKind::Function,
+                    name: "dec".to_owned(),
+                    previous: None,
+                    current: Some(1),
+                },
+                SymbolIndexChange {
+                    kind: SymbolIndexKind::Function,
+                    name: "inc".to_owned(),
+                    previous: Some(1),
+                    current: Some(2),
+                },
+                SymbolIndexChange {
+                    kind: SymbolIndexKind::Function,
+                    name: "main".to_owned(),
+                    previous: Some(2),
+                    current: Some(3),
+                },
+            ]
+        );
+        assert_eq!(
+            changes[1].to_string(),
+            "function \"inc\" is moved from 1 to 2"
+        );
+
+        assert!(SymbolIndex::from_text("function 1 main").is_err());
+    }
+}
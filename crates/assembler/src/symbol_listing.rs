@@ -0,0 +1,246 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::fmt::Write;
+
+use cranelift_object::object::{
+    read::File, Object, ObjectSection, ObjectSymbol, SymbolKind as ObjectSymbolKind, SymbolSection,
+};
+
+// The symbol listing
+// ------------------
+//
+// List the symbols of an object file, it is similar to `$ nm -S anna.o`, but
+// the binutils are not required, e.g.
+//
+// ```text
+// 0000000000000000 0000000000000013 local    function .text inc
+//                                   import   unknown        puts
+// 0000000000000000 0000000000000004 export   data     .data count
+// 0000000000000020 0000000000000027 export   function .text main
+// ```
+//
+// the columns are the address (i.e. the offset in the section), the size,
+// the scope, the kind, the section and the name.
+//
+// - the section symbols and the file symbols are omitted.
+// - the imported symbols have no address, size and section, and their kind is
+//   usually "unknown" since the object file does not record it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Data,
+
+    /// The thread-local data.
+    Tls,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolScope {
+    /// The symbol is defined and only visible in the object file.
+    Local,
+
+    /// The symbol is defined and visible to the other object files.
+    Export,
+
+    /// The symbol is undefined, i.e. it is defined by the other object files
+    /// or the shared libraries.
+    Import,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolEntry {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub scope: SymbolScope,
+
+    /// The name of the section which contains the symbol, it is `None`
+    /// for the imported symbols.
+    pub section: Option<String>,
+    pub address: u64,
+    pub size: u64,
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Data => "data",
+            SymbolKind::Tls => "tls",
+            SymbolKind::Unknown => "unknown",
+        }
+    }
+}
+
+impl SymbolScope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolScope::Local => "local",
+            SymbolScope::Export => "export",
+            SymbolScope::Import => "import",
+        }
+    }
+}
+
+/// Read the symbols of the object file, in the order of the symbol table.
+pub fn read_symbols(object_binary: &[u8]) -> Result<Vec<SymbolEntry>, String> {
+    let file = File::parse(object_binary)
+        .map_err(|error| format!("failed to read the object file: {}", error))?;
+
+    let symbols = file
+        .symbols()
+        .filter(|symbol| {
+            !matches!(
+                symbol.kind(),
+                ObjectSymbolKind::Section | ObjectSymbolKind::File
+            )
+        })
+        .filter_map(|symbol| {
+            let name = symbol.name().ok().filter(|name| !name.is_empty())?;
+
+            let kind = match symbol.kind() {
+                ObjectSymbolKind::Text => SymbolKind::Function,
+                ObjectSymbolKind::Data => SymbolKind::Data,
+                ObjectSymbolKind::Tls => SymbolKind::Tls,
+                _ => SymbolKind::Unknown,
+            };
+
+            let scope = if symbol.is_undefined() {
+                SymbolScope::Import
+            } else if symbol.is_global() {
+                SymbolScope::Export
+            } else {
+                SymbolScope::Local
+            };
+
+            let section = match symbol.section() {
+                SymbolSection::Section(section_index) => file
+                    .section_by_index(section_index)
+                    .ok()
+                    .and_then(|section| section.name().ok().map(|name| name.to_owned())),
+                _ => None,
+            };
+
+            Some(SymbolEntry {
+                name: name.to_owned(),
+                kind,
+                scope,
+                section,
+                address: symbol.address(),
+                size: symbol.size(),
+            })
+        })
+        .collect();
+
+    Ok(symbols)
+}
+
+/// Format the symbols as the listing text, one symbol per line.
+pub fn format_symbols(symbols: &[SymbolEntry]) -> String {
+    let section_width = symbols
+        .iter()
+        .filter_map(|symbol| symbol.section.as_ref().map(|section| section.len()))
+        .max()
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    for symbol in symbols {
+        if symbol.scope == SymbolScope::Import {
+            write!(text, "{:16} {:16}", "", "").unwrap();
+        } else {
+            write!(text, "{:016x} {:016x}", symbol.address, symbol.size).unwrap();
+        }
+        writeln!(
+            text,
+            " {:8} {:8} {:section_width$} {}",
+            symbol.scope.name(),
+            symbol.kind.name(),
+            symbol.section.as_deref().unwrap_or(""),
+            symbol.name
+        )
+        .unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        lowering::assemble_module,
+        parser::parse_module,
+        symbol_listing::{read_symbols, SymbolKind, SymbolScope},
+    };
+
+    #[test]
+    fn test_symbol_listing() {
+        let source = r#"
+        (module $app
+            (import (function $puts (param i64) (result i32)))
+            (data $count export (read_write i32 11))
+            (function $inc (param $a i32) (result i32)
+                (code (add_i32 (local_load $a) (data_load_i32 $count))))
+            (function $main export (result i32)
+                (code
+                    (call $puts (host_addr_data $count))
+                    (call $inc (imm_i32 1)))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+        let module_binary = generator.finish().unwrap().emit().unwrap();
+
+        let symbols = read_symbols(&module_binary).unwrap();
+        let summary = symbols
+            .iter()
+            .map(|symbol| {
+                (
+                    symbol.name.as_str(),
+                    symbol.scope,
+                    symbol.kind,
+                    symbol.section.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "inc",
+                    SymbolScope::Local,
+                    SymbolKind::Function,
+                    Some(".text")
+                ),
+                ("puts", SymbolScope::Import, SymbolKind::Unknown, None),
+                (
+                    "count",
+                    SymbolScope::Export,
+                    SymbolKind::Data,
+                    Some(".data")
+                ),
+                (
+                    "main",
+                    SymbolScope::Export,
+                    SymbolKind::Function,
+                    Some(".text")
+                ),
+            ]
+        );
+        assert!(symbols
+            .iter()
+            .filter(|symbol| symbol.scope != SymbolScope::Import)
+            .all(|symbol| symbol.size > 0));
+
+        assert!(read_symbols(b"not an object file").is_err());
+    }
+}
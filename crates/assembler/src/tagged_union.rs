@@ -0,0 +1,329 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{condcodes::IntCC, Block, InstBuilder, MemFlags, Type, Value};
+use cranelift_frontend::{FunctionBuilder, Switch};
+
+use crate::layout::{DataType, StructLayout};
+
+// The tagged union
+// ----------------
+//
+// The lowering of the enums (the discriminated unions), the layout is the same as
+// the C struct:
+//
+// ```c
+// struct {
+//     tag_type tag;            // the index of the variant
+//     union {
+//         struct { ... } variant_0;
+//         struct { ... } variant_1;
+//         ...
+//     } payload;
+// }
+// ```
+//
+// which is also the layout of the Rust `#[repr(C, u32)] enum`, e.g.
+//
+// ```rust
+// #[repr(C, u32)]
+// enum Shape {
+//     Square { side: i32 },      // tag = 0
+//     Rect { w: i32, h: i64 },   // tag = 1
+// }
+//
+// // size = 24, tag offset = 0, payload offset = 8
+// ```
+//
+// the tag is placed at offset 0, and the payload is placed at the offset which is
+// aligned to the maximum alignment of the variants.
+//
+// ref:
+// - https://doc.rust-lang.org/reference/type-layout.html#combining-primitive-representations-of-enums-with-fields-and-reprc
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedUnionLayout {
+    pub tag_type: Type,
+    pub payload_offset: u32,
+    pub size: u32,
+    pub align: u32,
+
+    /// The names and the payloads of the variants, the tag of
+    /// a variant is its index.
+    pub variants: Vec<(String, StructLayout)>,
+}
+
+impl TaggedUnionLayout {
+    pub fn new(tag_type: Type, variants: Vec<(String, StructLayout)>) -> Self {
+        let payload_layout = StructLayout::new_union(
+            variants
+                .iter()
+                .map(|(name, payload)| (name.to_owned(), DataType::Struct(payload.clone())))
+                .collect(),
+        );
+
+        let layout = StructLayout::new_struct(vec![
+            ("tag".to_owned(), DataType::Scalar(tag_type)),
+            ("payload".to_owned(), DataType::Struct(payload_layout)),
+        ]);
+
+        Self {
+            tag_type,
+            payload_offset: layout.fields[1].offset,
+            size: layout.size,
+            align: layout.align,
+            variants,
+        }
+    }
+
+    /// Get the tag (i.e. the index) of the variant by name.
+    pub fn get_tag(&self, variant_name: &str) -> Option<u32> {
+        self.variants
+            .iter()
+            .position(|(name, _)| name == variant_name)
+            .map(|index| index as u32)
+    }
+
+    fn expect_tag(&self, variant_name: &str) -> u32 {
+        self.get_tag(variant_name)
+            .unwrap_or_else(|| panic!("the variant \"{}\" is not found", variant_name))
+    }
+
+    pub fn emit_load_tag(&self, function_builder: &mut FunctionBuilder, ptr: Value) -> Value {
+        function_builder
+            .ins()
+            .load(self.tag_type, MemFlags::trusted(), ptr, 0)
+    }
+
+    pub fn emit_store_tag(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        ptr: Value,
+        variant_name: &str,
+    ) {
+        let tag = self.expect_tag(variant_name);
+        let value_tag = function_builder.ins().iconst(self.tag_type, tag as i64);
+        function_builder
+            .ins()
+            .store(MemFlags::trusted(), value_tag, ptr, 0);
+    }
+
+    /// Returns an i8 value, `1` if the tagged union is the specified variant.
+    pub fn emit_is_variant(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        ptr: Value,
+        variant_name: &str,
+    ) -> Value {
+        let tag = self.expect_tag(variant_name);
+        let value_tag = self.emit_load_tag(function_builder, ptr);
+        function_builder
+            .ins()
+            .icmp_imm(IntCC::Equal, value_tag, tag as i64)
+    }
+
+    /// Jump to the block of the variant according to the tag, the `variant_blocks`
+    /// are in the order of the variants, the `otherwise_block` is for
+    /// the invalid tags.
+    pub fn emit_branch_on_tag(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        ptr: Value,
+        variant_blocks: &[Block],
+        otherwise_block: Block,
+    ) {
+        assert_eq!(variant_blocks.len(), self.variants.len());
+
+        let value_tag = self.emit_load_tag(function_builder, ptr);
+        let mut switch = Switch::new();
+        for (index, block) in variant_blocks.iter().enumerate() {
+            switch.set_entry(index as u128, *block);
+        }
+        switch.emit(function_builder, value_tag, otherwise_block);
+    }
+
+    /// Load the scalar field of the payload of the variant, the tag is not checked.
+    pub fn emit_load_payload_field(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        ptr: Value,
+        variant_name: &str,
+        path: &str,
+    ) -> Value {
+        let payload = &self.variants[self.expect_tag(variant_name) as usize].1;
+        let value_payload = function_builder
+            .ins()
+            .iadd_imm(ptr, self.payload_offset as i64);
+        payload.emit_load_field(function_builder, value_payload, path)
+    }
+
+    /// Store the scalar field of the payload of the variant, the tag is not changed.
+    pub fn emit_store_payload_field(
+        &self,
+        function_builder: &mut FunctionBuilder,
+        ptr: Value,
+        variant_name: &str,
+        path: &str,
+        value: Value,
+    ) {
+        let payload = &self.variants[self.expect_tag(variant_name) as usize].1;
+        let value_payload = function_builder
+            .ins()
+            .iadd_imm(ptr, self.payload_offset as i64);
+        payload.emit_store_field(function_builder, value_payload, path, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        layout::{DataType, StructLayout},
+        tagged_union::TaggedUnionLayout,
+    };
+
+    #[allow(dead_code)]
+    #[repr(C, u32)]
+    enum Shape {
+        Square { side: i32 },
+        Rect { w: i32, h: i64 },
+    }
+
+    #[test]
+    fn test_tagged_union() {
+        let shape_layout = TaggedUnionLayout::new(
+            types::I32,
+            vec![
+                (
+                    "Square".to_owned(),
+                    StructLayout::new_struct(vec![(
+                        "side".to_owned(),
+                        DataType::Scalar(types::I32),
+                    )]),
+                ),
+                (
+                    "Rect".to_owned(),
+                    StructLayout::new_struct(vec![
+                        ("w".to_owned(), DataType::Scalar(types::I32)),
+                        ("h".to_owned(), DataType::Scalar(types::I64)),
+                    ]),
+                ),
+            ],
+        );
+
+        assert_eq!(shape_layout.size as usize, std::mem::size_of::<Shape>());
+        assert_eq!(shape_layout.align as usize, std::mem::align_of::<Shape>());
+        assert_eq!(shape_layout.payload_offset, 8);
+        assert_eq!(shape_layout.get_tag("Rect"), Some(1));
+        assert_eq!(shape_layout.get_tag("Circle"), None);
+
+        // ```rust
+        // fn area(shape: &mut Shape) -> i64 {
+        //     let a = match shape {
+        //         Square { side } => side * side,
+        //         Rect { w, h } => w * h,
+        //         _ => -1
+        //     };
+        //     *shape = Square { side: 5 };
+        //     a
+        // }
+        // ```
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let pointer_type = generator.module.isa().pointer_type();
+
+        let mut func_area_sig = generator.module.make_signature();
+        func_area_sig.params.push(AbiParam::new(pointer_type));
+        func_area_sig.returns.push(AbiParam::new(types::I64));
+
+        let func_area_id = generator
+            .module
+            .declare_function("area", Linkage::Local, &func_area_sig)
+            .unwrap();
+
+        let mut func_area = Function::with_name_signature(
+            UserFuncName::user(0, func_area_id.as_u32()),
+            func_area_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_area, &mut generator.function_builder_context);
+
+        let block_0 = function_builder.create_block();
+        let block_square = function_builder.create_block();
+        let block_rect = function_builder.create_block();
+        let block_otherwise = function_builder.create_block();
+        let block_tail = function_builder.create_block();
+        function_builder.append_block_param(block_tail, types::I64);
+
+        function_builder.append_block_params_for_function_params(block_0);
+        function_builder.switch_to_block(block_0);
+        let value_ptr = function_builder.block_params(block_0)[0];
+        shape_layout.emit_branch_on_tag(
+            &mut function_builder,
+            value_ptr,
+            &[block_square, block_rect],
+            block_otherwise,
+        );
+
+        function_builder.switch_to_block(block_square);
+        let value_0 = shape_layout.emit_load_payload_field(
+            &mut function_builder,
+            value_ptr,
+            "Square",
+            "side",
+        );
+        let value_1 = function_builder.ins().imul(value_0, value_0);
+        let value_2 = function_builder.ins().sextend(types::I64, value_1);
+        function_builder.ins().jump(block_tail, &[value_2]);
+
+        function_builder.switch_to_block(block_rect);
+        let value_3 =
+            shape_layout.emit_load_payload_field(&mut function_builder, value_ptr, "Rect", "w");
+        let value_4 = function_builder.ins().sextend(types::I64, value_3);
+        let value_5 =
+            shape_layout.emit_load_payload_field(&mut function_builder, value_ptr, "Rect", "h");
+        let value_6 = function_builder.ins().imul(value_4, value_5);
+        function_builder.ins().jump(block_tail, &[value_6]);
+
+        function_builder.switch_to_block(block_otherwise);
+        let value_7 = function_builder.ins().iconst(types::I64, -1);
+        function_builder.ins().jump(block_tail, &[value_7]);
+
+        function_builder.switch_to_block(block_tail);
+        shape_layout.emit_store_tag(&mut function_builder, value_ptr, "Square");
+        let value_8 = function_builder.ins().iconst(types::I32, 5);
+        shape_layout.emit_store_payload_field(
+            &mut function_builder,
+            value_ptr,
+            "Square",
+            "side",
+            value_8,
+        );
+        let value_9 = function_builder.block_params(block_tail)[0];
+        function_builder.ins().return_(&[value_9]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_area_id, func_area).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let func_area_ptr = generator.module.get_finalized_function(func_area_id);
+        let func_area: extern "C" fn(*mut Shape) -> i64 =
+            unsafe { std::mem::transmute(func_area_ptr) };
+
+        let mut shape = Shape::Rect { w: 3, h: 7 };
+        assert_eq!(func_area(&mut shape), 21);
+        assert!(matches!(shape, Shape::Square { side: 5 }));
+        assert_eq!(func_area(&mut shape), 25);
+    }
+}
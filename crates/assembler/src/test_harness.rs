@@ -0,0 +1,204 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use crate::{
+    ast::{
+        FunctionNode, ImportFunctionNode, ImportNode, Instruction, InstructionKind, Module,
+        ResultAbi, ValueType,
+    },
+    function_attribute::FunctionAttributes,
+    visitor::{walk_instruction, Visitor},
+};
+
+// The test harness
+// ----------------
+//
+// The source file can contain the test functions, which consist of the
+// local variables and the code, e.g.
+//
+// ```text
+// (module $app
+//     (function $inc (param $a i32) (result i32)
+//         (code (add_i32 (local_load $a) (imm_i32 1))))
+//     (test $inc_one (local $b i32)
+//         (code
+//             (local_store $b (call $inc (imm_i32 1)))
+//             (assert_eq_i32 (local_load $b) (imm_i32 2))
+//             (assert_true (gt_i32_s (local_load $b) (imm_i32 0))))))
+// ```
+//
+// the assertion pseudo-instructions:
+//
+// - `(assert_true value)`, the `i32` value is not zero.
+// - `(assert_eq_i32 left right)`, also `assert_eq_i64`, `assert_eq_f32` and `assert_eq_f64`.
+// - `(assert_ne_i32 left right)`, also `assert_ne_i64`, `assert_ne_f32` and `assert_ne_f64`.
+//
+// an assertion is converted into
+// `(when (eq_i32 value (imm_i32 0)) (call $__anasm_assert_failed (imm_i64 offset)) (break_fn))`,
+// i.e. the test function calls the host function with the source offset of
+// the assertion and returns immediately when the assertion fails.
+//
+// - the test functions are ignored when the module is assembled normally,
+//   `build_test_module()` converts them into the ordinary functions for the
+//   test runner (i.e. `$ anasm test`).
+// - the assertions are only allowed in the test functions.
+
+/// The name (and the symbol) of the host function which is called when
+/// an assertion fails, the signature is `(offset: i64)`.
+pub const ASSERT_FAILED_FUNCTION_NAME: &str = "__anasm_assert_failed";
+
+/// Get the name of the function of the test in the test module, the prefix
+/// avoids the conflicts with the ordinary functions.
+pub fn get_test_function_name(test_name: &str) -> String {
+    format!("test.{}", test_name)
+}
+
+/// Build the module for running the tests, i.e. the tests are appended to
+/// the functions (the names are from `get_test_function_name()`), and the
+/// assertion function is imported.
+pub fn build_test_module(module: &Module) -> Module {
+    let mut test_module = module.clone();
+
+    test_module
+        .imports
+        .push(ImportNode::Function(ImportFunctionNode {
+            name: ASSERT_FAILED_FUNCTION_NAME.to_owned(),
+            symbol: ASSERT_FAILED_FUNCTION_NAME.to_owned(),
+            params: vec![ValueType::I64],
+            results: vec![],
+            variadic: false,
+            library: None,
+            colocated: false,
+            result_abi: ResultAbi::Multiple,
+            attributes: FunctionAttributes::default(),
+            span: module.span,
+        }));
+
+    for test in std::mem::take(&mut test_module.tests) {
+        test_module.functions.push(FunctionNode {
+            name: get_test_function_name(&test.name),
+            ..test
+        });
+    }
+
+    test_module
+}
+
+/// Find the first assertion of the function, it is used to report the
+/// assertions outside the test functions.
+pub(crate) fn find_assertion(function: &FunctionNode) -> Option<&Instruction> {
+    struct AssertionFinder<'ast>(Option<&'ast Instruction>);
+
+    impl<'ast> Visitor<'ast> for AssertionFinder<'ast> {
+        fn visit_instruction(&mut self, instruction: &'ast Instruction) {
+            if self.0.is_some() {
+                return;
+            }
+            match &instruction.kind {
+                InstructionKind::Call { name, .. } if name == ASSERT_FAILED_FUNCTION_NAME => {
+                    self.0 = Some(instruction);
+                }
+                _ => walk_instruction(self, instruction),
+            }
+        }
+    }
+
+    let mut finder = AssertionFinder(None);
+    finder.visit_function(function);
+    finder.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use cranelift_jit::JITModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        lowering::assemble_module,
+        parser::parse_module,
+        test_harness::{build_test_module, get_test_function_name, ASSERT_FAILED_FUNCTION_NAME},
+    };
+
+    thread_local! {
+        static FAILED_OFFSETS: RefCell<Vec<i64>> = const { RefCell::new(vec![]) };
+    }
+
+    extern "C" fn assert_failed(offset: i64) {
+        FAILED_OFFSETS.with(|offsets| offsets.borrow_mut().push(offset));
+    }
+
+    #[test]
+    fn test_test_harness() {
+        let source = r#"
+        (module $app
+            (function $inc (param $a i32) (result i32)
+                (code (add_i32 (local_load $a) (imm_i32 1))))
+            (test $inc_one (local $b i32)
+                (code
+                    (local_store $b (call $inc (imm_i32 1)))
+                    (assert_eq_i32 (local_load $b) (imm_i32 2))
+                    (assert_true (gt_i32_s (local_load $b) (imm_i32 0)))
+                    (assert_ne_f64 (imm_f64 1.5) (imm_f64 2.5))))
+            (test $inc_wrong
+                (code
+                    (assert_eq_i64 (imm_i64 1) (imm_i64 1))
+                    (assert_eq_i32 (call $inc (imm_i32 1)) (imm_i32 3))
+                    (assert_true (imm_i32 0)))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+        assert_eq!(module.functions.len(), 1);
+        assert_eq!(module.tests.len(), 2);
+
+        // the tests are ignored by the normal assembling
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        assert!(assembled_module
+            .get_function_id(&get_test_function_name("inc_one"))
+            .is_none());
+
+        let test_module = build_test_module(&module);
+        let mut generator = Generator::<JITModule>::new(vec![(
+            ASSERT_FAILED_FUNCTION_NAME.to_owned(),
+            assert_failed as *const u8,
+        )]);
+        let assembled_module = assemble_module(&test_module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let run_test = |name: &str| -> Vec<i64> {
+            let test_fn: extern "C" fn() = unsafe {
+                std::mem::transmute(
+                    generator.module.get_finalized_function(
+                        assembled_module
+                            .get_function_id(&get_test_function_name(name))
+                            .unwrap(),
+                    ),
+                )
+            };
+            test_fn();
+            FAILED_OFFSETS.with(|offsets| offsets.take())
+        };
+
+        assert_eq!(run_test("inc_one"), vec![]);
+
+        // only the first failed assertion is reported
+        let offset = source
+            .find("(assert_eq_i32 (call $inc (imm_i32 1)) (imm_i32 3))")
+            .unwrap();
+        assert_eq!(run_test("inc_wrong"), vec![offset as i64]);
+
+        // the assertions outside the test functions
+        let error = parse_module("(module $app (function $f (code (assert_true (imm_i32 1)))))")
+            .unwrap_err();
+        assert_eq!(
+            error.message,
+            "the assertion is only allowed in the test function"
+        );
+    }
+}
@@ -0,0 +1,900 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use cranelift_object::ObjectModule;
+
+use crate::{
+    code_generator::Generator,
+    diagnostic::Diagnostic,
+    linker::{get_linker_args, run_linker, LinkError, LinkerOptions},
+    lowering::assemble_module,
+    parser::parse_module,
+};
+
+// The test support
+// ----------------
+//
+// The helpers for the end-to-end tests, i.e. compile, link and run the
+// program, and check the exit code. They are used by the tests of this crate,
+// and the downstream language implementations can reuse them for their own
+// tests by enabling the feature `test-support`, e.g.
+//
+// ```toml
+// [dev-dependencies]
+// assembler = { path = "...", features = ["test-support"] }
+// ```
+//
+// ```rust
+// let source = "(module $app (function $main export (result i32) (code (imm_i32 11))))";
+// assert_eq!(assemble_source_and_get_exit_code(source, false), Ok(Some(11)));
+// ```
+//
+// - the programs are linked by `ld` with the system libc (i.e. the glibc), or
+//   statically with the musl libc (which is located at `/usr/lib/musl/lib`).
+// - the intermediate files are written to a unique temporary folder (see
+//   `TempFolder`) for each run, and it is removed after running. When the
+//   environment variable `ANASM_KEEP_TEST_ARTIFACTS` is set (e.g. to "1"), the
+//   folder of the failed test (i.e. the test is panicking) is kept for
+//   inspecting the object file and the executable, and the folders of the
+//   passed tests are still removed.
+// - the output of the program (i.e. the stdout and the stderr) is captured,
+//   and the input (i.e. the stdin) is provided by the caller, see `ExecutionResult`.
+// - the program is killed when it runs longer than the timeout (60 seconds
+//   by default), and the memory and the CPU time can be limited by the
+//   resource limits, see `ExecutionOptions`.
+// - the programs of the foreign architectures (e.g. aarch64 and riscv64) are
+//   linked by the cross linker and run by the QEMU user-mode emulator, e.g.
+//   `$ qemu-aarch64 -L /usr/aarch64-linux-gnu ./anna.elf`, see `CrossTarget`.
+// - the helpers panic when the files can not be written or linked, since they
+//   are intended for the tests.
+
+pub const KEEP_ARTIFACTS_ENV_NAME: &str = "ANASM_KEEP_TEST_ARTIFACTS";
+
+/// The temporary folder of the intermediate files of a run, the name is unique
+/// among the processes and the runs (i.e. the process id and the serial number),
+/// and the folder is removed when it is dropped, unless it is dropped by the
+/// unwinding of a panic and the environment variable `ANASM_KEEP_TEST_ARTIFACTS`
+/// is set.
+pub struct TempFolder {
+    path: PathBuf,
+}
+
+impl TempFolder {
+    pub fn new(prefix: &str) -> Self {
+        static TEMP_FOLDER_SERIAL: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "{}_{}_{}",
+            prefix,
+            std::process::id(),
+            TEMP_FOLDER_SERIAL.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the path of the file in the folder.
+    pub fn file_path(&self, filename: &str) -> String {
+        self.path.join(filename).to_str().unwrap().to_owned()
+    }
+}
+
+impl Drop for TempFolder {
+    fn drop(&mut self) {
+        if std::thread::panicking() && std::env::var_os(KEEP_ARTIFACTS_ENV_NAME).is_some() {
+            eprintln!("the test artifacts are kept in \"{}\"", self.path.display());
+        } else {
+            // the error is ignored since it may be dropped during the panicking
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+pub fn link_single_object_file_as_executable_file(
+    object_file_path: &str,
+    external_library_folder_path: Option<&str>,
+    external_library_link_name: Option<&str>,
+    output_file_path: &str,
+) -> Result<(), LinkError> {
+    // linking examples
+    // ----------------
+    //
+    // link the object file with GCC:
+    //
+    // `$ gcc -o anna.elf anna.o`
+    //
+    // link the object file with binutils 'ld':
+    //
+    // ```sh
+    // ld \
+    //     -dynamic-linker /lib64/ld-linux-x86-64.so.2 \
+    //     -pie \
+    //     -o anna.elf \
+    //     /usr/lib/Scrt1.o \
+    //     /usr/lib/crti.o \
+    //     -L/lib/ \
+    //     -L/usr/lib \
+    //     anna.o \
+    //     -lc \
+    //     /usr/lib/crtn.o
+    // ```
+    //
+    // ref:
+    // check the result of command `$ gcc -v -o anna.elf anna.o`
+
+    // Mini FAQ about the misc libc/gcc crt files
+    // ------------------------------------------
+    //
+    // From: https://dev.gentoo.org/~vapier/crt.txt
+    //
+    // Some definitions:
+    // - PIC - position independent code (-fPIC)
+    // - PIE - position independent executable (-fPIE -pie)
+    // - crt - C runtime
+    //
+    // - crt0.o crt1.o etc...
+    //   Some systems use crt0.o, while some use crt1.o (and a few even use crt2.o
+    //   or higher).  Most likely due to a transitionary phase that some targets
+    //   went through.  The specific number is otherwise entirely arbitrary -- look
+    //   at the internal gcc port code to figure out what your target expects.  All
+    //   that matters is that whatever gcc has encoded, your C library better use
+    //   the same name.
+    //
+    //   This object is expected to contain the _start symbol which takes care of
+    //   bootstrapping the initial execution of the program.  What exactly that
+    //   entails is highly libc dependent and as such, the object is provided by
+    //   the C library and cannot be mixed with other ones.
+    //
+    //   On uClibc/glibc systems, this object initializes very early ABI requirements
+    //   (like the stack or frame pointer), setting up the argc/argv/env values, and
+    //   then passing pointers to the init/fini/main funcs to the internal libc main
+    //   which in turn does more general bootstrapping before finally calling the real
+    //   main function.
+    //
+    //   glibc ports call this file 'start.S' while uClibc ports call this crt0.S or
+    //   crt1.S (depending on what their gcc expects).
+    //
+    // - crti.o
+    //   Defines the function prologs for the .init and .fini sections (with the _init
+    //   and _fini symbols respectively).  This way they can be called directly.  These
+    //   symbols also trigger the linker to generate DT_INIT/DT_FINI dynamic ELF tags.
+    //
+    //   These are to support the old style constructor/destructor system where all
+    //   .init/.fini sections get concatenated at link time.  Not to be confused with
+    //   newer prioritized constructor/destructor .init_array/.fini_array sections and
+    //   DT_INIT_ARRAY/DT_FINI_ARRAY ELF tags.
+    //
+    //   glibc ports used to call this 'initfini.c', but now use 'crti.S'.  uClibc
+    //   also uses 'crti.S'.
+    //
+    // - crtn.o
+    //   Defines the function epilogs for the .init/.fini sections.  See crti.o.
+    //
+    //   glibc ports used to call this 'initfini.c', but now use 'crtn.S'.  uClibc
+    //   also uses 'crtn.S'.
+    //
+    // - Scrt1.o
+    //   Used in place of crt1.o when generating PIEs.
+    // - gcrt1.o
+    //   Used in place of crt1.o when generating code with profiling information.
+    //   Compile with -pg.  Produces output suitable for the gprof util.
+    // - Mcrt1.o
+    //   Like gcrt1.o, but is used with the prof utility.  glibc installs this as
+    //   a dummy file as it's useless on linux systems.
+    //
+    // - crtbegin.o
+    //   GCC uses this to find the start of the constructors.
+    // - crtbeginS.o
+    //   Used in place of crtbegin.o when generating shared objects/PIEs.
+    // - crtbeginT.o
+    //   Used in place of crtbegin.o when generating static executables.
+    // - crtend.o
+    //   GCC uses this to find the start of the destructors.
+    // - crtendS.o
+    //   Used in place of crtend.o when generating shared objects/PIEs.
+    //
+    // General linking order:
+    //
+    // ```
+    // crt1.o crti.o crtbegin.o
+    //     [-L paths] [user objects] [gcc libs] [C libs] [gcc libs]
+    //     crtend.o crtn.o
+    // ```
+    //
+    // More references:
+    // - http://gcc.gnu.org/onlinedocs/gccint/Initialization.html
+    // - https://stackoverflow.com/a/16436294/23069938
+    //
+    // Note that the file 'Scrt1.o' is owned by package 'glibc', check:
+    // `$ pacman -Qo Scrt1.o`
+    // `$ pacman -Ql glibc | grep crt`
+
+    // shared library names
+    // --------------------
+    //
+    // Shared libraries essentially have three names:
+    //
+    // - soname (logical name): The soname follows this naming scheme:
+    //   `lib<library name>.so.<version number>`.
+    //   e.g. `libcurl.so.4`,
+    // - real name: That has a base filename consisting of the soname plus
+    //   `.<minor number>.<release number>` (although the .<release number> is optional.
+    //   e.g. `libcurl.so.4.8.0`
+    // - link name: The link name is the soname without any version numbering.
+    //   e.g. `libcurl.so`. The 'lib' and '.so' can be omitted when pass the link name to `GCC` and `ld`.
+    //
+    // P.S., generate a shared library with soname specified:
+    // `gcc -Wall -g -fpic -shared -Wl,-soname,libtest0.so.1 -o libtest0.so.1.0.0 libtest0.c``
+
+    let mut args = vec![
+        "--dynamic-linker",
+        "/lib64/ld-linux-x86-64.so.2",
+        "-pie",
+        "-o",
+        output_file_path,
+        "/usr/lib/Scrt1.o",
+        "/usr/lib/crti.o",
+        "-L/lib/",
+        "-L/usr/lib",
+    ];
+
+    if let Some(lib_path_str) = external_library_folder_path {
+        args.push("-L");
+        args.push(lib_path_str);
+    }
+
+    args.push(object_file_path);
+
+    if let Some(lib_linkname_str) = external_library_link_name {
+        args.push("-l");
+        args.push(lib_linkname_str);
+    }
+
+    args.push("-lc");
+    args.push("/usr/lib/crtn.o");
+
+    // Command::new("/usr/bin/ld").args(args).status()
+    run_linker(
+        "ld",
+        &args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>(),
+    )
+}
+
+pub fn static_link_single_object_file_as_executable_file_with_musl(
+    object_file_path: &str,
+    usr_lib_musl_lib_path: Option<&str>,
+    external_library_object_file_path: Option<&str>,
+    output_file_path: &str,
+) -> Result<(), LinkError> {
+    // linking with MUSL
+    // -----------------
+    //
+    // ```sh
+    // ld \
+    //     -dynamic-linker /lib/ld-musl-x86_64.so.1 \
+    //     -nostdlib \
+    //     -pie \
+    //     -o test_libc.elf \
+    //     /usr/lib/musl/lib/Scrt1.o \
+    //     /usr/lib/musl/lib/crti.o \
+    //     -L/usr/lib/musl/lib \
+    //     test_libc.o \
+    //     -lc \
+    //     /usr/lib/musl/lib/crtn.o
+    // ```
+    //
+    // and check the dynamic link list:
+    //
+    // `$ /lib/ld-musl-x86_64.so.1 --list test_libc.elf`
+    //
+    // replace the "-pie" above with "-static" to generate static linking
+    // executable file.
+    //
+    // ref:
+    // check the result of command `$ musl-gcc -v -o test_libc.elf test_libc.o`
+
+    let musl_lib = usr_lib_musl_lib_path.unwrap_or("/usr/lib/musl/lib");
+
+    let mut args = vec![
+        // "--dynamic-linker",
+        // "/lib/ld-musl-x86_64.so.1",
+        // "-pie",
+        "-nostdlib".to_owned(),
+        "-static".to_owned(),
+        "-o".to_owned(),
+        output_file_path.to_owned(),
+        format!("{musl_lib}/Scrt1.o"),
+        format!("{musl_lib}/crti.o"),
+        format!("-L{musl_lib}"),
+        object_file_path.to_owned(),
+    ];
+
+    if let Some(lib_object_file_path) = external_library_object_file_path {
+        args.push(lib_object_file_path.to_owned());
+    }
+
+    args.push("-lc".to_owned());
+    args.push(format!("{musl_lib}/crtn.o"));
+
+    run_linker("ld", &args)
+}
+
+/// The default wall-clock time limit of the program.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The interval of checking whether the program has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionOptions {
+    /// The input of the program.
+    pub stdin: Vec<u8>,
+
+    /// The wall-clock time limit, the program is killed and the helper panics
+    /// when it is exceeded, e.g. the miscompiled program loops forever.
+    pub timeout: Duration,
+
+    /// The limit of the address space in bytes (i.e. `RLIMIT_AS`), the memory
+    /// allocation of the program fails when it is exceeded.
+    pub memory_limit: Option<u64>,
+
+    /// The limit of the CPU time in seconds (i.e. `RLIMIT_CPU`), the program
+    /// is terminated by the signal `SIGXCPU` when it is exceeded.
+    pub cpu_time_limit: Option<u64>,
+
+    /// The target triple of the object file, e.g. "aarch64-unknown-linux-gnu",
+    /// the program is linked by the cross linker and run by the QEMU user-mode
+    /// emulator if the architecture differs from the host, see `CrossTarget`.
+    ///
+    /// It is `None` for the host.
+    pub target: Option<String>,
+}
+
+impl Default for ExecutionOptions {
+    fn default() -> Self {
+        Self {
+            stdin: vec![],
+            timeout: DEFAULT_TIMEOUT,
+            memory_limit: None,
+            cpu_time_limit: None,
+            target: None,
+        }
+    }
+}
+
+/// The toolchain for running the programs of a foreign architecture,
+/// i.e. the cross linker, the QEMU user-mode emulator and the sysroot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrossTarget {
+    /// The 'ld' of the cross binutils, e.g. "aarch64-linux-gnu-ld".
+    pub linker: String,
+
+    /// The emulator, e.g. "qemu-aarch64".
+    pub emulator: String,
+
+    /// The folder of the target libraries, e.g. '/usr/aarch64-linux-gnu', the
+    /// CRT object files and the libc are located in its 'lib' folder, and the
+    /// emulator loads the dynamic linker and the shared libraries from it
+    /// (i.e. the `-L` argument of QEMU).
+    pub sysroot: String,
+
+    /// The path of the dynamic linker of the target.
+    pub dynamic_linker: String,
+}
+
+/// Get the architecture of the target triple, e.g. "aarch64" for
+/// "aarch64-unknown-linux-gnu", and "riscv64" for "riscv64gc-unknown-linux-gnu".
+fn get_target_architecture(triple: &str) -> &str {
+    match triple.split('-').next().unwrap_or_default() {
+        "riscv64gc" => "riscv64",
+        architecture => architecture,
+    }
+}
+
+/// Check whether the programs of the target can be run by the host directly.
+pub fn is_host_target(triple: &str) -> bool {
+    get_target_architecture(triple) == std::env::consts::ARCH
+}
+
+impl CrossTarget {
+    /// Get the toolchain which is installed by the Debian packages, e.g.
+    /// `binutils-aarch64-linux-gnu`, `libc6-dev-arm64-cross` and `qemu-user`.
+    ///
+    /// Returns `None` if the architecture is not supported, only the glibc
+    /// targets are supported.
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        if triple.ends_with("-musl") {
+            return None;
+        }
+
+        let (gnu_triple, dynamic_linker) = match get_target_architecture(triple) {
+            "x86_64" => ("x86_64-linux-gnu", "/lib64/ld-linux-x86-64.so.2"),
+            "aarch64" => ("aarch64-linux-gnu", "/lib/ld-linux-aarch64.so.1"),
+            "riscv64" => ("riscv64-linux-gnu", "/lib/ld-linux-riscv64-lp64d.so.1"),
+            _ => return None,
+        };
+
+        Some(Self {
+            linker: format!("{}-ld", gnu_triple),
+            emulator: format!("qemu-{}", get_target_architecture(triple)),
+            sysroot: format!("/usr/{}", gnu_triple),
+            dynamic_linker: dynamic_linker.to_owned(),
+        })
+    }
+
+    /// Link the object file (with the user library if `library` is the folder
+    /// and the link name) as a dynamically linked executable.
+    pub fn link(
+        &self,
+        object_file_path: &str,
+        library: Option<(&str, &str)>,
+        output_file_path: &str,
+    ) -> Result<(), LinkError> {
+        let library_folder_path = format!("{}/lib", self.sysroot);
+
+        let mut linker_options = LinkerOptions {
+            crt_folder: library_folder_path.clone(),
+            gcc_crt_folder: None,
+            dynamic_linker: self.dynamic_linker.clone(),
+            library_paths: vec![library_folder_path],
+            ..LinkerOptions::default()
+        };
+        if let Some((folder_path, link_name)) = library {
+            linker_options.library_paths.push(folder_path.to_owned());
+            linker_options.libraries.push(link_name.to_owned());
+        }
+
+        let args = get_linker_args(&[object_file_path], output_file_path, &linker_options);
+        run_linker(&self.linker, &args)
+    }
+}
+
+/// The result of running the executable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionResult {
+    /// It is `None` if the program is terminated by a signal.
+    pub exit_code: Option<i32>,
+
+    /// The captured output, the invalid UTF-8 sequences are replaced
+    /// with `U+FFFD`.
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Read the pipe to the end by another thread, otherwise both processes may
+/// block when the pipe is full.
+fn spawn_reader(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buffer = vec![];
+        // the output is incomplete if the program is killed
+        let _ = pipe.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+/// Run the executable file (by the command, which may be the emulator)
+/// with the options, and capture the output.
+///
+/// Returns the error message if the program times out.
+fn execute_file(
+    mut command: Command,
+    exec_file_path: &str,
+    options: &ExecutionOptions,
+) -> Result<ExecutionResult, String> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let limits = [
+        (libc::RLIMIT_AS, options.memory_limit),
+        (libc::RLIMIT_CPU, options.cpu_time_limit),
+    ];
+    if limits.iter().any(|(_, limit)| limit.is_some()) {
+        // SAFETY: only the async-signal-safe function `setrlimit()` is
+        // called in the child process.
+        unsafe {
+            command.pre_exec(move || {
+                for (resource, limit) in limits {
+                    if let Some(value) = limit {
+                        // both the soft limit and the hard limit are set
+                        let rlimit = libc::rlimit {
+                            rlim_cur: value,
+                            rlim_max: value,
+                        };
+                        if libc::setrlimit(resource, &rlimit) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    let mut child = command.spawn().unwrap();
+
+    let mut child_stdin = child.stdin.take().unwrap();
+    let input = options.stdin.clone();
+    let writer = std::thread::spawn(move || {
+        // the program may exit without reading all the input
+        let _ = child_stdin.write_all(&input);
+    });
+    let stdout_reader = spawn_reader(child.stdout.take().unwrap());
+    let stderr_reader = spawn_reader(child.stderr.take().unwrap());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() >= options.timeout {
+            // the pipes are closed after the program is killed, so the
+            // threads terminate as well
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return Err(format!(
+                "the program \"{}\" timed out after {} ms",
+                exec_file_path,
+                options.timeout.as_millis()
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    writer.join().unwrap();
+    let stdout = stdout_reader.join().unwrap();
+    let stderr = stderr_reader.join().unwrap();
+
+    Ok(ExecutionResult {
+        exit_code: status.code(),
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+    })
+}
+
+/// Link the object file (with the user library if `library` is the folder and
+/// the link name), run it with the options, and capture the output.
+fn link_and_execute(
+    binary: &[u8],
+    program_name: &str,
+    static_link: bool,
+    library: Option<(&str, &str)>,
+    options: &ExecutionOptions,
+) -> ExecutionResult {
+    let folder = TempFolder::new(&format!("anasm_test_{}", program_name));
+
+    // write object file `*.o`
+    let object_file_path = folder.file_path(&format!("{}.o", program_name));
+    let mut file = File::create(&object_file_path).unwrap();
+    file.write_all(binary).unwrap();
+
+    // link file as `*.elf`
+    let exec_file_path = folder.file_path(&format!("{}.elf", program_name));
+
+    let cross_target = options.target.as_deref().and_then(|triple| {
+        if is_host_target(triple) {
+            None
+        } else {
+            Some(CrossTarget::from_triple(triple).unwrap_or_else(|| {
+                panic!("the target \"{}\" can not be run by the emulator", triple)
+            }))
+        }
+    });
+
+    let link_result = if let Some(cross_target) = &cross_target {
+        assert!(
+            !static_link,
+            "the static linking is not supported by the cross target"
+        );
+        cross_target.link(&object_file_path, library, &exec_file_path)
+    } else if static_link {
+        let library_object_file_path =
+            library.map(|(folder_path, link_name)| format!("{}/lib{}.o", folder_path, link_name));
+
+        static_link_single_object_file_as_executable_file_with_musl(
+            &object_file_path,
+            None,
+            library_object_file_path.as_deref(),
+            &exec_file_path,
+        )
+    } else {
+        link_single_object_file_as_executable_file(
+            &object_file_path,
+            library.map(|(folder_path, _)| folder_path),
+            library.map(|(_, link_name)| link_name),
+            &exec_file_path,
+        )
+    };
+    if let Err(error) = link_result {
+        panic!(
+            "failed to link the object file \"{}\": {}",
+            object_file_path, error
+        );
+    }
+
+    // Run the executable file and get the exit code, e.g.
+    // `$ ./anna.elf`
+    // `$ echo $?`
+    //
+    // or run it by the emulator, e.g.
+    // `$ qemu-aarch64 -L /usr/aarch64-linux-gnu ./anna.elf`
+
+    let mut command = match &cross_target {
+        Some(cross_target) => {
+            let mut command = Command::new(&cross_target.emulator);
+            command.args(["-L", &cross_target.sysroot, &exec_file_path]);
+            command
+        }
+        None => Command::new(&exec_file_path),
+    };
+
+    // the shared library is located by `LD_LIBRARY_PATH`
+    if let Some((folder_path, _)) = library.filter(|_| !static_link) {
+        command.env("LD_LIBRARY_PATH", folder_path);
+    }
+    // the folder is removed after the result is returned (or the panic)
+    execute_file(command, &exec_file_path, options).unwrap_or_else(|message| panic!("{}", message))
+}
+
+/// Link the object file (as a dynamically linked executable, or statically
+/// linked with musl if `static_link` is true), run it with the options, and
+/// capture the exit code and the output.
+///
+/// Panics if the program times out.
+pub fn run_executable_binary(
+    binary: &[u8],
+    program_name: &str,
+    static_link: bool,
+    options: &ExecutionOptions,
+) -> ExecutionResult {
+    link_and_execute(binary, program_name, static_link, None, options)
+}
+
+/// Link the object file with the user library, run it with the options, and
+/// capture the exit code and the output.
+///
+/// The library folder should contain the shared library `lib{name}.so`, and
+/// the object file `lib{name}.o` for the static linking.
+pub fn run_executable_binary_with_library(
+    binary: &[u8],
+    program_name: &str,
+    static_link: bool,
+    library_folder_path: &str,
+    library_link_name: &str,
+    options: &ExecutionOptions,
+) -> ExecutionResult {
+    link_and_execute(
+        binary,
+        program_name,
+        static_link,
+        Some((library_folder_path, library_link_name)),
+        options,
+    )
+}
+
+/// Link the object file, run it with the default options, and return the exit code,
+/// see `run_executable_binary()`.
+pub fn run_executable_binary_and_get_exit_code(
+    binary: &[u8],
+    program_name: &str,
+    static_link: bool,
+) -> Option<i32> {
+    run_executable_binary(
+        binary,
+        program_name,
+        static_link,
+        &ExecutionOptions::default(),
+    )
+    .exit_code
+}
+
+/// Link the object file with the user library, run it with the default options,
+/// and return the exit code, see `run_executable_binary_with_library()`.
+pub fn run_executable_binary_and_get_exit_code_with_library(
+    binary: &[u8],
+    program_name: &str,
+    static_link: bool,
+    library_folder_path: &str,
+    library_link_name: &str,
+) -> Option<i32> {
+    run_executable_binary_with_library(
+        binary,
+        program_name,
+        static_link,
+        library_folder_path,
+        library_link_name,
+        &ExecutionOptions::default(),
+    )
+    .exit_code
+}
+
+/// Assemble the source into an object file, link it as an executable, run it
+/// with the options, and capture the exit code and the output. The name of the
+/// module is used as the program name.
+pub fn assemble_source_and_run(
+    source: &str,
+    static_link: bool,
+    options: &ExecutionOptions,
+) -> Result<ExecutionResult, Diagnostic> {
+    let module = parse_module(source)?;
+
+    let target = options.target.as_deref();
+    let mut generator = if static_link {
+        Generator::<ObjectModule>::new_static(&module.name, target)
+    } else {
+        Generator::<ObjectModule>::new(&module.name, target)
+    };
+    assemble_module(&module, &mut generator)?;
+    let module_binary = generator.finish().unwrap().emit().unwrap();
+
+    Ok(run_executable_binary(
+        &module_binary,
+        &module.name,
+        static_link,
+        options,
+    ))
+}
+
+/// Assemble the source, run it with the default options, and return the exit code,
+/// see `assemble_source_and_run()`.
+pub fn assemble_source_and_get_exit_code(
+    source: &str,
+    static_link: bool,
+) -> Result<Option<i32>, Diagnostic> {
+    assemble_source_and_run(source, static_link, &ExecutionOptions::default())
+        .map(|result| result.exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, time::Duration};
+
+    use pretty_assertions::assert_eq;
+
+    use crate::test_support::{
+        assemble_source_and_get_exit_code, assemble_source_and_run, is_host_target, CrossTarget,
+        ExecutionOptions, ExecutionResult, TempFolder,
+    };
+
+    #[test]
+    fn test_test_support() {
+        let source = r#"
+        (module $test_support_app
+            (function $inc (param $a i32) (result i32)
+                (code (add_i32 (local_load $a) (imm_i32 1))))
+            (function $main export (result i32)
+                (code (call $inc (imm_i32 10)))))
+        "#;
+        assert_eq!(
+            assemble_source_and_get_exit_code(source, false),
+            Ok(Some(11))
+        );
+
+        let error =
+            assemble_source_and_get_exit_code("(module $app (function $main", false).unwrap_err();
+        assert!(!error.message.is_empty());
+
+        // echo the next character of the input, and write a message to the stderr
+        let source = r#"
+        (module $test_support_echo
+            (import (function $getchar (result i32)))
+            (import (function $putchar (param i32) (result i32)))
+            (import (function $write (param i32 i64 i64) (result i64)))
+            (data $message (read_only bytes "done\n"))
+            (function $main export (result i32)
+                (code
+                    (call $putchar (add_i32 (call $getchar) (imm_i32 1)))
+                    (call $write (imm_i32 2) (host_addr_data $message) (imm_i64 5))
+                    (imm_i32 3))))
+        "#;
+        assert_eq!(
+            assemble_source_and_run(
+                source,
+                false,
+                &ExecutionOptions {
+                    stdin: b"a".to_vec(),
+                    ..ExecutionOptions::default()
+                }
+            )
+            .unwrap(),
+            ExecutionResult {
+                exit_code: Some(3),
+                stdout: "b".to_owned(),
+                stderr: "done\n".to_owned(),
+            }
+        );
+
+        // the temporary folders are unique and removed when dropped
+        let folder_0 = TempFolder::new("anasm_test_support");
+        let folder_1 = TempFolder::new("anasm_test_support");
+        assert_ne!(folder_0.path(), folder_1.path());
+        let folder_path = folder_0.path().to_owned();
+        std::fs::write(folder_0.file_path("a.o"), b"").unwrap();
+        drop(folder_0);
+        assert!(!folder_path.exists());
+
+        // the infinite loop
+        let source = r#"
+        (module $test_support_loop
+            (function $main export (result i32)
+                (code
+                    (for (result i32)
+                        (recur)))))
+        "#;
+        let options = ExecutionOptions {
+            timeout: Duration::from_millis(200),
+            ..ExecutionOptions::default()
+        };
+        let message = std::panic::catch_unwind(|| assemble_source_and_run(source, false, &options))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(message.ends_with("test_support_loop.elf\" timed out after 200 ms"));
+
+        // the CPU time limit
+        let options = ExecutionOptions {
+            cpu_time_limit: Some(1),
+            ..ExecutionOptions::default()
+        };
+        assert_eq!(
+            assemble_source_and_run(source, false, &options)
+                .unwrap()
+                .exit_code,
+            None
+        );
+
+        // the cross target
+        assert!(is_host_target("x86_64-unknown-linux-gnu"));
+        assert_eq!(
+            CrossTarget::from_triple("riscv64gc-unknown-linux-gnu"),
+            Some(CrossTarget {
+                linker: "riscv64-linux-gnu-ld".to_owned(),
+                emulator: "qemu-riscv64".to_owned(),
+                sysroot: "/usr/riscv64-linux-gnu".to_owned(),
+                dynamic_linker: "/lib/ld-linux-riscv64-lp64d.so.1".to_owned(),
+            })
+        );
+        assert_eq!(CrossTarget::from_triple("aarch64-unknown-linux-musl"), None);
+
+        // the emulator is optional on the host
+        let cross_target = CrossTarget::from_triple("aarch64-unknown-linux-gnu").unwrap();
+        let has_toolchain = [&cross_target.linker, &cross_target.emulator]
+            .iter()
+            .all(|program| Command::new(program).arg("--version").output().is_ok());
+        if has_toolchain {
+            let options = ExecutionOptions {
+                target: Some("aarch64-unknown-linux-gnu".to_owned()),
+                ..ExecutionOptions::default()
+            };
+            let source = r#"
+            (module $test_support_cross
+                (function $main export (result i32)
+                    (code (mul_i32 (imm_i32 6) (imm_i32 7)))))
+            "#;
+            assert_eq!(
+                assemble_source_and_run(source, false, &options)
+                    .unwrap()
+                    .exit_code,
+                Some(42)
+            );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::isa::{unwind::systemv, TargetIsa};
+use cranelift_module::FuncId;
+use cranelift_object::ObjectProduct;
+use gimli::{
+    write::{Address, CommonInformationEntry, FrameTable, Sections},
+    RunTimeEndian,
+};
+
+use crate::debug_info::{get_endian, write_sections_to_object, RelocatableWriter};
+
+// The unwind information (.eh_frame)
+// ----------------------------------
+//
+// The `.eh_frame` section contains a CIE (Common Information Entry) and
+// a list of FDEs (Frame Description Entry), each FDE describes how to restore
+// the registers (i.e. the "call frame") of the caller at any instruction of a function.
+//
+// It is used by:
+//
+// - the C++/Rust exception handling (unwinding), e.g. a Rust panic unwinds through
+//   the generated functions.
+// - `backtrace()` of glibc, `gdb`, `perf --call-graph dwarf` etc., they walk the stack
+//   without the help of frame pointers.
+//
+// check the section with command:
+// `$ readelf --debug-dump=frames anna.o`
+//
+// ref:
+// - https://refspecs.linuxfoundation.org/LSB_5.0.0/LSB-Core-generic/LSB-Core-generic/ehframechpt.html
+// - https://docs.rs/gimli/latest/gimli/write/struct.FrameTable.html
+
+/// The System V unwind information of the functions.
+pub struct UnwindTable {
+    cie: Option<CommonInformationEntry>,
+    endian: RunTimeEndian,
+    /// the unwind information and the offset of the first instruction
+    /// (i.e. the size of the patchable entry) of the functions.
+    functions: Vec<(FuncId, systemv::UnwindInfo, u32)>,
+}
+
+impl UnwindTable {
+    pub fn new(isa: &dyn TargetIsa) -> Self {
+        let cie = isa.create_systemv_cie().map(|mut cie| {
+            // use PC-relative addresses so that the section does not need
+            // dynamic relocations in the position-independent executables
+            // and shared libraries.
+            cie.fde_address_encoding = gimli::DW_EH_PE_pcrel | gimli::DW_EH_PE_sdata4;
+            cie
+        });
+
+        Self {
+            cie,
+            endian: get_endian(isa),
+            functions: vec![],
+        }
+    }
+
+    pub fn add_function(
+        &mut self,
+        func_id: FuncId,
+        unwind_info: systemv::UnwindInfo,
+        entry_offset: u32,
+    ) {
+        self.functions.retain(|(id, _, _)| *id != func_id);
+        self.functions.push((func_id, unwind_info, entry_offset));
+    }
+
+    pub fn get_function(&self, func_id: FuncId) -> Option<&systemv::UnwindInfo> {
+        self.functions
+            .iter()
+            .find(|(id, _, _)| *id == func_id)
+            .map(|(_, unwind_info, _)| unwind_info)
+    }
+
+    /// Generate the `.eh_frame` section and append it to the object.
+    pub fn write_to_object(&self, product: &mut ObjectProduct) -> gimli::write::Result<()> {
+        // the target ISA does not support System V unwind information (e.g. Windows)
+        let Some(cie) = &self.cie else {
+            return Ok(());
+        };
+
+        let functions = self
+            .functions
+            .iter()
+            .filter(|(func_id, _, _)| matches!(product.functions[*func_id], Some((_, true))))
+            .collect::<Vec<_>>();
+
+        if functions.is_empty() {
+            return Ok(());
+        }
+
+        let symbols = functions
+            .iter()
+            .map(|(func_id, _, _)| product.function_symbol(*func_id))
+            .collect::<Vec<_>>();
+
+        let mut frame_table = FrameTable::default();
+        let cie_id = frame_table.add_cie(cie.clone());
+
+        // the NOPs of the patchable entry are not covered by the FDE, since
+        // the offsets of the instructions of `UnwindInfo` can not be changed.
+        for (idx, (_, unwind_info, entry_offset)) in functions.iter().enumerate() {
+            let fde = unwind_info.to_fde(Address::Symbol {
+                symbol: idx,
+                addend: *entry_offset as i64,
+            });
+            frame_table.add_fde(cie_id, fde);
+        }
+
+        let mut sections = Sections::new(RelocatableWriter::new(self.endian));
+        // note that the terminator (a zero length entry) of the `.eh_frame` section
+        // is provided by the `crtend.o` when linking, it should not be added here.
+        frame_table.write_eh_frame(&mut sections.eh_frame)?;
+
+        write_sections_to_object(&mut product.object, &sections, &symbols);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+    use cranelift_frontend::FunctionBuilder;
+    use cranelift_module::{Linkage, Module};
+    use cranelift_object::{
+        object::{read::elf::ElfFile64, Endianness, Object, ObjectSection, RelocationKind},
+        ObjectModule,
+    };
+
+    use crate::{code_generator::Generator, test_support::run_executable_binary_and_get_exit_code};
+
+    #[test]
+    fn test_unwind_info_eh_frame() {
+        let mut generator = Generator::<ObjectModule>::new("main", None);
+
+        // build function "main"
+        //
+        // ```rust
+        // fn main () -> i32 {
+        //    11
+        // }
+        // ```
+        let mut func_main_sig = generator.module.make_signature();
+        func_main_sig.returns.push(AbiParam::new(types::I32));
+
+        let func_main_id = generator
+            .module
+            .declare_function("main", Linkage::Export, &func_main_sig)
+            .unwrap();
+
+        let mut func_main = Function::with_name_signature(
+            UserFuncName::user(0, func_main_id.as_u32()),
+            func_main_sig,
+        );
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_main, &mut generator.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.switch_to_block(block);
+
+        let value_0 = function_builder.ins().iconst(types::I32, 11);
+        function_builder.ins().return_(&[value_0]);
+
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        generator.define_function(func_main_id, func_main).unwrap();
+        assert!(generator.unwind_table.get_function(func_main_id).is_some());
+
+        let object_product = generator.finish().unwrap();
+        let module_binary = object_product.emit().unwrap();
+
+        // check the FDE of function "main"
+        let object_file = ElfFile64::<Endianness>::parse(module_binary.as_slice()).unwrap();
+        let eh_frame = object_file.section_by_name(".eh_frame").unwrap();
+        let relocations = eh_frame.relocations().collect::<Vec<_>>();
+        assert_eq!(relocations.len(), 1);
+        assert_eq!(relocations[0].1.kind(), RelocationKind::Relative);
+
+        // the linker should accept the `.eh_frame` section
+        let exit_code_opt = run_executable_binary_and_get_exit_code(
+            &module_binary,
+            "test_unwind_info_eh_frame",
+            false,
+        );
+
+        assert_eq!(exit_code_opt, Some(11));
+    }
+}
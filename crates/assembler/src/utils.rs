@@ -4,252 +4,7 @@
 // the Mozilla Public License version 2.0 and additional exceptions,
 // more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
 
-use std::{
-    fs::File,
-    io::Write,
-    path::PathBuf,
-    process::{Command, ExitStatus},
-};
-
-fn get_temp_file_fullpath(filename: &str) -> String {
-    let mut dir = std::env::temp_dir();
-    dir.push(filename);
-    dir.to_str().unwrap().to_owned()
-}
-
-fn link_single_object_file_as_executable_file(
-    object_file_path: &str,
-    external_library_folder_path: Option<&str>,
-    external_library_link_name: Option<&str>,
-    output_file_path: &str,
-) -> std::io::Result<ExitStatus> {
-    // linking examples
-    // ----------------
-    //
-    // link the object file with GCC:
-    //
-    // `$ gcc -o anna.elf anna.o`
-    //
-    // link the object file with binutils 'ld':
-    //
-    // ```sh
-    // ld \
-    //     -dynamic-linker /lib64/ld-linux-x86-64.so.2 \
-    //     -pie \
-    //     -o anna.elf \
-    //     /usr/lib/Scrt1.o \
-    //     /usr/lib/crti.o \
-    //     -L/lib/ \
-    //     -L/usr/lib \
-    //     anna.o \
-    //     -lc \
-    //     /usr/lib/crtn.o
-    // ```
-    //
-    // ref:
-    // check the result of command `$ gcc -v -o anna.elf anna.o`
-
-    // Mini FAQ about the misc libc/gcc crt files
-    // ------------------------------------------
-    //
-    // From: https://dev.gentoo.org/~vapier/crt.txt
-    //
-    // Some definitions:
-    // - PIC - position independent code (-fPIC)
-    // - PIE - position independent executable (-fPIE -pie)
-    // - crt - C runtime
-    //
-    // - crt0.o crt1.o etc...
-    //   Some systems use crt0.o, while some use crt1.o (and a few even use crt2.o
-    //   or higher).  Most likely due to a transitionary phase that some targets
-    //   went through.  The specific number is otherwise entirely arbitrary -- look
-    //   at the internal gcc port code to figure out what your target expects.  All
-    //   that matters is that whatever gcc has encoded, your C library better use
-    //   the same name.
-    //
-    //   This object is expected to contain the _start symbol which takes care of
-    //   bootstrapping the initial execution of the program.  What exactly that
-    //   entails is highly libc dependent and as such, the object is provided by
-    //   the C library and cannot be mixed with other ones.
-    //
-    //   On uClibc/glibc systems, this object initializes very early ABI requirements
-    //   (like the stack or frame pointer), setting up the argc/argv/env values, and
-    //   then passing pointers to the init/fini/main funcs to the internal libc main
-    //   which in turn does more general bootstrapping before finally calling the real
-    //   main function.
-    //
-    //   glibc ports call this file 'start.S' while uClibc ports call this crt0.S or
-    //   crt1.S (depending on what their gcc expects).
-    //
-    // - crti.o
-    //   Defines the function prologs for the .init and .fini sections (with the _init
-    //   and _fini symbols respectively).  This way they can be called directly.  These
-    //   symbols also trigger the linker to generate DT_INIT/DT_FINI dynamic ELF tags.
-    //
-    //   These are to support the old style constructor/destructor system where all
-    //   .init/.fini sections get concatenated at link time.  Not to be confused with
-    //   newer prioritized constructor/destructor .init_array/.fini_array sections and
-    //   DT_INIT_ARRAY/DT_FINI_ARRAY ELF tags.
-    //
-    //   glibc ports used to call this 'initfini.c', but now use 'crti.S'.  uClibc
-    //   also uses 'crti.S'.
-    //
-    // - crtn.o
-    //   Defines the function epilogs for the .init/.fini sections.  See crti.o.
-    //
-    //   glibc ports used to call this 'initfini.c', but now use 'crtn.S'.  uClibc
-    //   also uses 'crtn.S'.
-    //
-    // - Scrt1.o
-    //   Used in place of crt1.o when generating PIEs.
-    // - gcrt1.o
-    //   Used in place of crt1.o when generating code with profiling information.
-    //   Compile with -pg.  Produces output suitable for the gprof util.
-    // - Mcrt1.o
-    //   Like gcrt1.o, but is used with the prof utility.  glibc installs this as
-    //   a dummy file as it's useless on linux systems.
-    //
-    // - crtbegin.o
-    //   GCC uses this to find the start of the constructors.
-    // - crtbeginS.o
-    //   Used in place of crtbegin.o when generating shared objects/PIEs.
-    // - crtbeginT.o
-    //   Used in place of crtbegin.o when generating static executables.
-    // - crtend.o
-    //   GCC uses this to find the start of the destructors.
-    // - crtendS.o
-    //   Used in place of crtend.o when generating shared objects/PIEs.
-    //
-    // General linking order:
-    //
-    // ```
-    // crt1.o crti.o crtbegin.o
-    //     [-L paths] [user objects] [gcc libs] [C libs] [gcc libs]
-    //     crtend.o crtn.o
-    // ```
-    //
-    // More references:
-    // - http://gcc.gnu.org/onlinedocs/gccint/Initialization.html
-    // - https://stackoverflow.com/a/16436294/23069938
-    //
-    // Note that the file 'Scrt1.o' is owned by package 'glibc', check:
-    // `$ pacman -Qo Scrt1.o`
-    // `$ pacman -Ql glibc | grep crt`
-
-    // shared library names
-    // --------------------
-    //
-    // Shared libraries essentially have three names:
-    //
-    // - soname (logical name): The soname follows this naming scheme:
-    //   `lib<library name>.so.<version number>`.
-    //   e.g. `libcurl.so.4`,
-    // - real name: That has a base filename consisting of the soname plus
-    //   `.<minor number>.<release number>` (although the .<release number> is optional.
-    //   e.g. `libcurl.so.4.8.0`
-    // - link name: The link name is the soname without any version numbering.
-    //   e.g. `libcurl.so`. The 'lib' and '.so' can be omitted when pass the link name to `GCC` and `ld`.
-    //
-    // P.S., generate a shared library with soname specified:
-    // `gcc -Wall -g -fpic -shared -Wl,-soname,libtest0.so.1 -o libtest0.so.1.0.0 libtest0.c``
-
-    let mut args = vec![];
-
-    args.push("--dynamic-linker");
-    args.push("/lib64/ld-linux-x86-64.so.2");
-    args.push("-pie");
-    args.push("-o");
-    args.push(output_file_path);
-    args.push("/usr/lib/Scrt1.o");
-    args.push("/usr/lib/crti.o");
-    args.push("-L/lib/");
-    args.push("-L/usr/lib");
-
-    if let Some(lib_path_str) = external_library_folder_path {
-        args.push("-L");
-        args.push(lib_path_str);
-    }
-
-    args.push(object_file_path);
-
-    if let Some(lib_linkname_str) = external_library_link_name {
-        args.push("-l");
-        args.push(lib_linkname_str);
-    }
-
-    args.push("-lc");
-    args.push("/usr/lib/crtn.o");
-
-    // Command::new("/usr/bin/ld").args(args).status()
-    Command::new("ld").args(args).status()
-}
-
-fn static_link_single_object_file_as_executable_file_with_musl(
-    object_file_path: &str,
-    usr_lib_musl_lib_path: Option<&str>,
-    external_library_object_file_path: Option<&str>,
-    output_file_path: &str,
-) -> std::io::Result<ExitStatus> {
-    // linking with MUSL
-    // -----------------
-    //
-    // ```sh
-    // ld \
-    //     -dynamic-linker /lib/ld-musl-x86_64.so.1 \
-    //     -nostdlib \
-    //     -pie \
-    //     -o test_libc.elf \
-    //     /usr/lib/musl/lib/Scrt1.o \
-    //     /usr/lib/musl/lib/crti.o \
-    //     -L/usr/lib/musl/lib \
-    //     test_libc.o \
-    //     -lc \
-    //     /usr/lib/musl/lib/crtn.o
-    // ```
-    //
-    // and check the dynamic link list:
-    //
-    // `$ /lib/ld-musl-x86_64.so.1 --list test_libc.elf`
-    //
-    // replace the "-pie" above with "-static" to generate static linking
-    // executable file.
-    //
-    // ref:
-    // check the result of command `$ musl-gcc -v -o test_libc.elf test_libc.o`
-
-    let musl_lib = if let Some(p) = usr_lib_musl_lib_path {
-        p
-    } else {
-        "/usr/lib/musl/lib"
-    };
-
-    let mut args = vec![];
-
-    // args.push("--dynamic-linker");
-    // args.push("/lib/ld-musl-x86_64.so.1");
-    // args.push("-pie");
-    args.push("-nostdlib".to_owned());
-    args.push("-static".to_owned());
-    args.push("-o".to_owned());
-    args.push(output_file_path.to_owned());
-    args.push(format!("{musl_lib}/Scrt1.o"));
-    args.push(format!("{musl_lib}/crti.o"));
-    args.push(format!("-L{musl_lib}"));
-    args.push(object_file_path.to_owned());
-
-    if let Some(lib_object_file_path) = external_library_object_file_path {
-        args.push(lib_object_file_path.to_owned());
-    }
-
-    args.push("-lc".to_owned());
-    args.push(format!("{musl_lib}/crtn.o"));
-
-    Command::new("ld").args(args).status()
-}
-
-fn delete_file(filepath: &str) {
-    std::fs::remove_file(filepath).unwrap();
-}
+use crate::test_support::run_executable_binary_and_get_exit_code_with_library;
 
 fn get_tests_lib_folder_path() -> String {
     let mut pwd = std::env::current_dir().unwrap();
@@ -278,102 +33,18 @@ fn get_tests_lib_folder_path() -> String {
     pwd.to_str().unwrap().to_string()
 }
 
-fn get_tests_lib_file_path(filename: &str) -> String {
-    let mut folder = PathBuf::from(get_tests_lib_folder_path());
-    folder.push(filename);
-    folder.to_str().unwrap().to_string()
-}
-
-fn run_executable_binary_and_get_exit_code(
-    binary: &[u8],
-    program_name: &str,
-    static_link: bool,
-) -> Option<i32> {
-    // write object file `*.o`
-    let object_file_path = get_temp_file_fullpath(&format!("{}.o", program_name));
-    let mut file = File::create(&object_file_path).unwrap();
-    file.write_all(&binary).unwrap();
-
-    // link file as `*.elf`
-    let exec_file_path = get_temp_file_fullpath(&format!("{}.elf", program_name));
-
-    if static_link {
-        static_link_single_object_file_as_executable_file_with_musl(
-            &object_file_path,
-            None,
-            None,
-            &exec_file_path,
-        )
-        .unwrap();
-    } else {
-        link_single_object_file_as_executable_file(&object_file_path, None, None, &exec_file_path)
-            .unwrap();
-    }
-
-    // Run the executable file and get the exit code, e.g.
-    // `$ ./anna.elf`
-    // `$ echo $?`
-
-    // run executable file and get exit code
-    let exit_code_opt = Command::new(&exec_file_path).status().unwrap().code();
-
-    // clean up
-    delete_file(&object_file_path);
-    delete_file(&exec_file_path);
-
-    exit_code_opt
-}
-
 fn run_executable_binary_and_get_exit_code_with_libtest0(
     binary: &[u8],
     program_name: &str,
     static_link: bool,
 ) -> Option<i32> {
-    // write object file `*.o`
-    let object_file_path = get_temp_file_fullpath(&format!("{}.o", program_name));
-    let mut file = File::create(&object_file_path).unwrap();
-    file.write_all(&binary).unwrap();
-
-    // link file as `*.elf`
-    let exec_file_path = get_temp_file_fullpath(&format!("{}.elf", program_name));
-
-    let exit_code_opt = if static_link {
-        let user_lib_object_filename = "libtest0.o";
-        let user_lib_object_filepath = get_tests_lib_file_path(&user_lib_object_filename);
-
-        static_link_single_object_file_as_executable_file_with_musl(
-            &object_file_path,
-            None,
-            Some(&user_lib_object_filepath),
-            &exec_file_path,
-        )
-        .unwrap();
-
-        Command::new(&exec_file_path).status().unwrap().code()
-    } else {
-        let user_lib_folder_path = get_tests_lib_folder_path();
-        let user_lib_linkname = "test0";
-        link_single_object_file_as_executable_file(
-            &object_file_path,
-            Some(&user_lib_folder_path),
-            Some(user_lib_linkname),
-            &exec_file_path,
-        )
-        .unwrap();
-
-        // run executable file and get exit code
-        Command::new(&exec_file_path)
-            .env("LD_LIBRARY_PATH", &user_lib_folder_path)
-            .status()
-            .unwrap()
-            .code()
-    };
-
-    // clean up
-    delete_file(&object_file_path);
-    delete_file(&exec_file_path);
-
-    exit_code_opt
+    run_executable_binary_and_get_exit_code_with_library(
+        binary,
+        program_name,
+        static_link,
+        &get_tests_lib_folder_path(),
+        "test0",
+    )
 }
 
 #[cfg(test)]
@@ -388,11 +59,8 @@ mod tests {
     use cranelift_object::ObjectModule;
 
     use crate::{
-        code_generator::Generator,
-        utils::{
-            run_executable_binary_and_get_exit_code,
-            run_executable_binary_and_get_exit_code_with_libtest0,
-        },
+        code_generator::Generator, test_support::run_executable_binary_and_get_exit_code,
+        utils::run_executable_binary_and_get_exit_code_with_libtest0,
     };
 
     #[test]
@@ -0,0 +1,425 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use std::{collections::HashSet, fmt::Display};
+
+use cranelift_codegen::ir::{ExternalName, Function, GlobalValueData, Signature};
+use cranelift_module::{
+    DataDescription, DataId, FuncId, FuncOrDataId, Linkage, Module, ModuleRelocTarget,
+};
+
+use crate::code_generator::Generator;
+
+// The module validation
+// ---------------------
+//
+// Some mistakes of the module are not reported by Cranelift when the functions
+// and data are defined, they surface as the confusing errors of the linker
+// (e.g. "undefined reference to ...") or even the crashes at runtime, so
+// `Generator::validate()` checks the module before emission:
+//
+// - the local functions and data which are declared but not defined.
+// - the exported functions and data which are never defined.
+// - the calls (and the function addresses) whose signatures differ from the
+//   declarations of the callees, e.g. the functions which are imported from
+//   the CLIF text (see `define_function_from_clif()`).
+// - the imported functions and data which are never referenced (a warning).
+//
+// the references are collected when the functions and data are defined,
+// see `Generator::symbol_references`, e.g.
+//
+// ```rust
+// let report = generator.validate();
+// if !report.is_ok() {
+//     eprintln!("{}", report);
+// }
+// ```
+
+/// The symbols which are referenced by a defined function or data object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolReferences {
+    pub definition: FuncOrDataId,
+
+    /// the referenced functions and the signatures of the references,
+    /// the signature is `None` for the references of the data objects (i.e. the addresses).
+    pub functions: Vec<(FuncId, Option<Signature>)>,
+    pub data: Vec<DataId>,
+}
+
+impl SymbolReferences {
+    pub(crate) fn from_function(func_id: FuncId, func: &Function) -> Self {
+        let user_named_funcs = func.params.user_named_funcs();
+        let get_user_name = |name: &ExternalName| match name {
+            ExternalName::User(name_ref) => Some(&user_named_funcs[*name_ref]),
+            _ => None,
+        };
+
+        let functions = func
+            .dfg
+            .ext_funcs
+            .values()
+            .filter_map(|ext_func| {
+                let user_name = get_user_name(&ext_func.name)?;
+                (user_name.namespace == 0).then(|| {
+                    (
+                        FuncId::from_u32(user_name.index),
+                        Some(func.dfg.signatures[ext_func.signature].clone()),
+                    )
+                })
+            })
+            .collect();
+
+        let data = func
+            .global_values
+            .values()
+            .filter_map(|global_value| match global_value {
+                GlobalValueData::Symbol { name, .. } => {
+                    let user_name = get_user_name(name)?;
+                    (user_name.namespace == 1).then(|| DataId::from_u32(user_name.index))
+                }
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            definition: FuncOrDataId::Func(func_id),
+            functions,
+            data,
+        }
+    }
+
+    pub(crate) fn from_data(data_id: DataId, data_description: &DataDescription) -> Self {
+        let get_user_name = |target: &ModuleRelocTarget| match target {
+            ModuleRelocTarget::User { namespace, index } => Some((*namespace, *index)),
+            _ => None,
+        };
+
+        let functions = data_description
+            .function_decls
+            .values()
+            .filter_map(|target| match get_user_name(target)? {
+                (0, index) => Some((FuncId::from_u32(index), None)),
+                _ => None,
+            })
+            .collect();
+
+        let data = data_description
+            .data_decls
+            .values()
+            .filter_map(|target| match get_user_name(target)? {
+                (1, index) => Some(DataId::from_u32(index)),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            definition: FuncOrDataId::Data(data_id),
+            functions,
+            data,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// A local (or hidden) function or data object which is declared but not defined.
+    UndefinedLocal { symbol: String },
+
+    /// An exported function or data object which is never defined.
+    UndefinedExport { symbol: String },
+
+    /// The signature of a call (or a function address) differs from
+    /// the declaration of the callee.
+    SignatureMismatch {
+        caller: String,
+        callee: String,
+        declared: Signature,
+        referenced: Signature,
+    },
+
+    /// An imported function or data object which is never referenced.
+    UnreferencedImport { symbol: String },
+}
+
+impl ValidationIssue {
+    /// The unreferenced imports are harmless, the others are errors.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, ValidationIssue::UnreferencedImport { .. })
+    }
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::UndefinedLocal { symbol } => write!(
+                f,
+                "error: the local symbol \"{}\" is declared but not defined",
+                symbol
+            ),
+            ValidationIssue::UndefinedExport { symbol } => write!(
+                f,
+                "error: the exported symbol \"{}\" is never defined",
+                symbol
+            ),
+            ValidationIssue::SignatureMismatch {
+                caller,
+                callee,
+                declared,
+                referenced,
+            } => write!(
+                f,
+                "error: the function \"{}\" refers to \"{}\" with the signature \"{}\", but it is declared as \"{}\"",
+                caller, callee, referenced, declared
+            ),
+            ValidationIssue::UnreferencedImport { symbol } => write!(
+                f,
+                "warning: the imported symbol \"{}\" is never referenced",
+                symbol
+            ),
+        }
+    }
+}
+
+/// The issues of the module, in the order of declaration.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Check whether there is no error (the warnings are allowed).
+    pub fn is_ok(&self) -> bool {
+        !self.issues.iter().any(ValidationIssue::is_error)
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Record (or replace, for the redefinition) the references of a definition.
+    pub(crate) fn set_symbol_references(&mut self, symbol_references: SymbolReferences) {
+        match self
+            .symbol_references
+            .iter_mut()
+            .find(|item| item.definition == symbol_references.definition)
+        {
+            Some(item) => *item = symbol_references,
+            None => self.symbol_references.push(symbol_references),
+        }
+    }
+
+    /// Check the declarations, definitions and references of the module.
+    pub fn validate(&self) -> ValidationReport {
+        let declarations = self.module.declarations();
+
+        let defined = self
+            .symbol_references
+            .iter()
+            .map(|item| item.definition)
+            .collect::<HashSet<_>>();
+
+        let referenced = self
+            .symbol_references
+            .iter()
+            .flat_map(|item| {
+                item.functions
+                    .iter()
+                    .map(|(func_id, _)| FuncOrDataId::Func(*func_id))
+                    .chain(item.data.iter().map(|data_id| FuncOrDataId::Data(*data_id)))
+            })
+            .collect::<HashSet<_>>();
+
+        // the coverage counters are defined by `finish()`.
+        let pending = self
+            .coverage
+            .as_ref()
+            .map(|coverage| FuncOrDataId::Data(coverage.counters_data_id));
+
+        let get_issue = |id: FuncOrDataId, linkage: Linkage, symbol: String| match linkage {
+            Linkage::Import if !referenced.contains(&id) => {
+                Some(ValidationIssue::UnreferencedImport { symbol })
+            }
+            Linkage::Local | Linkage::Hidden if !defined.contains(&id) && Some(id) != pending => {
+                Some(ValidationIssue::UndefinedLocal { symbol })
+            }
+            Linkage::Export | Linkage::Preemptible if !defined.contains(&id) => {
+                Some(ValidationIssue::UndefinedExport { symbol })
+            }
+            _ => None,
+        };
+
+        let mut issues = vec![];
+
+        for (func_id, decl) in declarations.get_functions() {
+            let id = FuncOrDataId::Func(func_id);
+            issues.extend(get_issue(
+                id,
+                decl.linkage,
+                decl.linkage_name(func_id).into_owned(),
+            ));
+        }
+
+        for (data_id, decl) in declarations.get_data_objects() {
+            let id = FuncOrDataId::Data(data_id);
+            issues.extend(get_issue(
+                id,
+                decl.linkage,
+                decl.linkage_name(data_id).into_owned(),
+            ));
+        }
+
+        for item in &self.symbol_references {
+            let FuncOrDataId::Func(caller_id) = item.definition else {
+                continue;
+            };
+
+            for (callee_id, opt_signature) in &item.functions {
+                let callee_decl = declarations.get_function_decl(*callee_id);
+                let Some(signature) = opt_signature.as_ref() else {
+                    continue;
+                };
+
+                let issue = ValidationIssue::SignatureMismatch {
+                    caller: declarations
+                        .get_function_decl(caller_id)
+                        .linkage_name(caller_id)
+                        .into_owned(),
+                    callee: callee_decl.linkage_name(*callee_id).into_owned(),
+                    declared: callee_decl.signature.clone(),
+                    referenced: signature.clone(),
+                };
+                if *signature != callee_decl.signature && !issues.contains(&issue) {
+                    issues.push(issue);
+                }
+            }
+        }
+
+        ValidationReport { issues }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_codegen::ir::{types, AbiParam};
+    use cranelift_module::{FuncOrDataId, Linkage, Module};
+    use cranelift_object::ObjectModule;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::{DataDefinition, Generator},
+        lowering::assemble_module,
+        parser::parse_module,
+        validation::ValidationIssue,
+    };
+
+    #[test]
+    fn test_validate() {
+        let source = r#"
+        (module $main
+            (import (function $abs "abs" (param i32) (result i32)))
+            (import (function $labs "labs" (param i64) (result i64)))
+            (import (data $counter "counter"))
+            (function $main export (result i32)
+                (code (call $abs (imm_i32 -11)))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        assemble_module(&module, &mut generator).unwrap();
+
+        let report = generator.validate();
+        assert!(report.is_ok());
+        assert_eq!(
+            report.issues,
+            vec![
+                ValidationIssue::UnreferencedImport {
+                    symbol: "labs".to_owned()
+                },
+                ValidationIssue::UnreferencedImport {
+                    symbol: "counter".to_owned()
+                },
+            ]
+        );
+
+        // the undefined symbols
+        let mut sig = generator.module.make_signature();
+        sig.returns.push(AbiParam::new(types::I32));
+        generator
+            .module
+            .declare_function("helper", Linkage::Local, &sig)
+            .unwrap();
+        generator
+            .module
+            .declare_function("get_number", Linkage::Export, &sig)
+            .unwrap();
+        generator
+            .module
+            .declare_data("buffer", Linkage::Local, true, false)
+            .unwrap();
+
+        // the signature mismatch, `abs` is called with `i64`
+        let mut long_sig = generator.module.make_signature();
+        long_sig.returns.push(AbiParam::new(types::I64));
+        let func_get_long_number_id = generator
+            .module
+            .declare_function("get_long_number", Linkage::Local, &long_sig)
+            .unwrap();
+        let Some(FuncOrDataId::Func(func_abs_id)) = generator.module.get_name("abs") else {
+            unreachable!()
+        };
+
+        let clif = format!(
+            r#"
+            function u0:{}() -> i64 system_v {{
+                sig0 = (i64) -> i64 system_v
+                fn0 = u0:{} sig0
+
+            block0:
+                v0 = iconst.i64 -11
+                v1 = call fn0(v0)
+                return v1
+            }}"#,
+            func_get_long_number_id.as_u32(),
+            func_abs_id.as_u32()
+        );
+        generator.define_function_from_clif(&clif).unwrap();
+
+        let report = generator.validate();
+        assert!(!report.is_ok());
+        assert_eq!(
+            report
+                .issues
+                .iter()
+                .filter(|issue| issue.is_error())
+                .map(|issue| issue.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "error: the local symbol \"helper\" is declared but not defined",
+                "error: the exported symbol \"get_number\" is never defined",
+                "error: the local symbol \"buffer\" is declared but not defined",
+                "error: the function \"get_long_number\" refers to \"abs\" with the signature \"(i64) -> i64 system_v\", but it is declared as \"(i32) -> i32 system_v\"",
+            ]
+        );
+
+        // define the missing data
+        let Some(FuncOrDataId::Data(data_id)) = generator.module.get_name("buffer") else {
+            unreachable!()
+        };
+        generator
+            .define_data_content(data_id, DataDefinition::Uninitialized { size: 8, align: 8 })
+            .unwrap();
+        assert_eq!(generator.validate().issues.len(), 5);
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_object::{
+    object::{
+        elf::{STB_GLOBAL, STT_FUNC, STV_PROTECTED},
+        SymbolFlags, SymbolKind, SymbolScope,
+    },
+    ObjectModule, ObjectProduct,
+};
+
+use crate::code_generator::Generator;
+
+// The protected visibility
+// ------------------------
+//
+// The exported (i.e. default visibility) symbols of a shared library can be
+// interposed by the definitions of the executable or the former libraries
+// (e.g. `LD_PRELOAD`), so the linker generates the PLT entries for the calls
+// between the exported functions of the library, even if they are in the same
+// object file.
+//
+// the protected visibility (`STV_PROTECTED`) keeps the symbols exported but
+// not interposable, so the calls within the library are bound directly, e.g.
+//
+// ```rust
+// let mut generator = Generator::<ObjectModule>::new("number", None);
+// generator.enable_protected_exports();
+// ```
+//
+// only the exported functions are made protected, since the references to
+// the protected data from the executable (i.e. by the copy relocations) are
+// rejected by the linker, see `LinkerOptions::symbolic` for binding the data.
+//
+// ref:
+// - https://maskray.me/blog/2021-05-16-elf-interposition-and-bsymbolic
+
+impl Generator<ObjectModule> {
+    /// Mark the exported functions as protected visibility.
+    pub fn enable_protected_exports(&mut self) {
+        self.protected_exports = true;
+    }
+}
+
+pub(crate) fn write_protected_visibility_to_object(product: &mut ObjectProduct) {
+    let symbol_ids = product
+        .functions
+        .values()
+        .filter_map(|item| match item {
+            Some((symbol_id, true)) => Some(*symbol_id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    for symbol_id in symbol_ids {
+        let symbol = product.object.symbol_mut(symbol_id);
+        if symbol.kind == SymbolKind::Text && symbol.scope == SymbolScope::Dynamic && !symbol.weak {
+            symbol.flags = SymbolFlags::Elf {
+                st_info: (STB_GLOBAL << 4) | STT_FUNC,
+                st_other: STV_PROTECTED,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_object::{
+        object::{
+            elf::STV_PROTECTED, read::File, Object, ObjectSymbol, ObjectSymbolTable,
+            RelocationTarget, SymbolFlags,
+        },
+        ObjectModule,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        code_generator::Generator,
+        linker::{link_executable, LinkerMode, LinkerOptions, SymbolicBinding},
+        lowering::assemble_module,
+        parser::parse_module,
+        test_support::TempFolder,
+    };
+
+    fn emit(protected_exports: bool) -> Vec<u8> {
+        let source = r#"
+        (module $number
+            (function $get_number export (result i32)
+                (code (imm_i32 11)))
+            (function $get_sum export (result i32)
+                (code (add_i32 (call $get_number) (imm_i32 2)))))
+        "#;
+        let module = parse_module(source).unwrap();
+        let mut generator = Generator::<ObjectModule>::new(&module.name, None);
+        if protected_exports {
+            generator.enable_protected_exports();
+        }
+        assemble_module(&module, &mut generator).unwrap();
+        generator.finish().unwrap().emit().unwrap()
+    }
+
+    /// Get the names of the symbols which are referred to by the dynamic
+    /// relocations (e.g. the PLT entries) of the shared library.
+    fn get_dynamic_relocation_symbols(binary: &[u8]) -> Vec<String> {
+        let file = File::parse(binary).unwrap();
+        let mut names = file
+            .dynamic_relocations()
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, relocation)| match relocation.target() {
+                RelocationTarget::Symbol(symbol_index) => file
+                    .dynamic_symbol_table()?
+                    .symbol_by_index(symbol_index)
+                    .ok()
+                    .map(|symbol| symbol.name().unwrap().to_owned()),
+                _ => None,
+            })
+            .filter(|name| name.starts_with("get_"))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_protected_exports() {
+        let protected_binary = emit(true);
+        let file = File::parse(protected_binary.as_slice()).unwrap();
+        let symbol = file.symbol_by_name("get_number").unwrap();
+        assert!(symbol.is_global());
+        assert!(matches!(
+            symbol.flags(),
+            SymbolFlags::Elf { st_other, .. } if st_other == STV_PROTECTED
+        ));
+
+        let temp_folder = TempFolder::new("visibility");
+        let default_file_path = temp_folder.file_path("default.o");
+        let protected_file_path = temp_folder.file_path("protected.o");
+        std::fs::write(&default_file_path, emit(false)).unwrap();
+        std::fs::write(&protected_file_path, protected_binary).unwrap();
+
+        let link = |object_file_path: &str, symbolic: SymbolicBinding| {
+            let library_file_path = temp_folder.file_path("libnumber.so");
+            link_executable(
+                &[object_file_path],
+                &library_file_path,
+                &LinkerOptions {
+                    mode: LinkerMode::Shared,
+                    symbolic,
+                    ..LinkerOptions::default()
+                },
+            )
+            .unwrap();
+            get_dynamic_relocation_symbols(&std::fs::read(&library_file_path).unwrap())
+        };
+
+        // the call of the interposable function goes through the PLT
+        assert_eq!(
+            link(&default_file_path, SymbolicBinding::Interposable),
+            vec!["get_number"]
+        );
+        assert!(link(&default_file_path, SymbolicBinding::Functions).is_empty());
+        assert!(link(&protected_file_path, SymbolicBinding::Interposable).is_empty());
+    }
+}
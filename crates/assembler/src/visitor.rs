@@ -0,0 +1,267 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use crate::ast::{
+    DataNode, FunctionNode, ImportModuleNode, ImportNode, Instruction, InstructionKind, LocalNode,
+    Module,
+};
+
+// The visitor of the AST
+// ----------------------
+//
+// The external tools (e.g. the linters, the documentation generators and the
+// static analyzers) can walk the AST which is parsed by `parse_module()`
+// without matching every node themselves, e.g. count the calls:
+//
+// ```rust
+// struct CallCounter {
+//     count: usize,
+// }
+//
+// impl<'ast> Visitor<'ast> for CallCounter {
+//     fn visit_instruction(&mut self, instruction: &'ast Instruction) {
+//         if let InstructionKind::Call { .. } = &instruction.kind {
+//             self.count += 1;
+//         }
+//         walk_instruction(self, instruction);
+//     }
+// }
+//
+// let mut counter = CallCounter { count: 0 };
+// counter.visit_module(&parse_module(source)?);
+// ```
+//
+// - every method of `Visitor` visits the children of the node by the
+//   corresponding `walk_*()` function by default, the overridden method should
+//   call it as well to continue walking into the children.
+// - the nodes are visited in the order of the source text, i.e. the operands
+//   of an instruction are visited before the next instruction.
+// - the test functions (see `test_harness.rs`) are visited by `visit_function()`
+//   after the ordinary functions.
+// - the lifetime `'ast` allows the visitor to keep the references of the nodes.
+
+pub trait Visitor<'ast> {
+    fn visit_module(&mut self, module: &'ast Module) {
+        walk_module(self, module);
+    }
+
+    fn visit_import(&mut self, _import: &'ast ImportNode) {}
+
+    fn visit_module_import(&mut self, _module_import: &'ast ImportModuleNode) {}
+
+    fn visit_data(&mut self, _data: &'ast DataNode) {}
+
+    fn visit_function(&mut self, function: &'ast FunctionNode) {
+        walk_function(self, function);
+    }
+
+    /// The parameters and the local variables of the functions, and the
+    /// parameters of `for`.
+    fn visit_local(&mut self, _local: &'ast LocalNode) {}
+
+    fn visit_instruction(&mut self, instruction: &'ast Instruction) {
+        walk_instruction(self, instruction);
+    }
+}
+
+pub fn walk_module<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, module: &'ast Module) {
+    for module_import in &module.module_imports {
+        visitor.visit_module_import(module_import);
+    }
+    for import in &module.imports {
+        visitor.visit_import(import);
+    }
+    for data in &module.data {
+        visitor.visit_data(data);
+    }
+    for function in module.functions.iter().chain(&module.tests) {
+        visitor.visit_function(function);
+    }
+}
+
+pub fn walk_function<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    function: &'ast FunctionNode,
+) {
+    for local in function.params.iter().chain(&function.locals) {
+        visitor.visit_local(local);
+    }
+    for instruction in &function.body {
+        visitor.visit_instruction(instruction);
+    }
+}
+
+/// Visit the child instructions (i.e. the operands and the bodies) of the instruction.
+pub fn walk_instruction<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    instruction: &'ast Instruction,
+) {
+    match &instruction.kind {
+        InstructionKind::Nop
+        | InstructionKind::ImmI32(_)
+        | InstructionKind::ImmI64(_)
+        | InstructionKind::ImmF32(_)
+        | InstructionKind::ImmF64(_)
+        | InstructionKind::LocalLoad(_)
+        | InstructionKind::DataLoad { .. }
+        | InstructionKind::HostAddrFunction(_)
+        | InstructionKind::HostAddrData { .. }
+        | InstructionKind::Panic(_)
+        | InstructionKind::Abort
+        | InstructionKind::ReadCycleCounter => {}
+        InstructionKind::LocalStore { value, .. } | InstructionKind::DataStore { value, .. } => {
+            visitor.visit_instruction(value);
+        }
+        InstructionKind::MemoryLoad { address, .. } => visitor.visit_instruction(address),
+        InstructionKind::Exit(code) => visitor.visit_instruction(code),
+        InstructionKind::Asm { inputs, .. } => {
+            for (_, instruction) in inputs {
+                visitor.visit_instruction(instruction);
+            }
+        }
+        InstructionKind::MemoryStore { address, value, .. } => {
+            visitor.visit_instruction(address);
+            visitor.visit_instruction(value);
+        }
+        InstructionKind::Operation {
+            operands: instructions,
+            ..
+        }
+        | InstructionKind::Do(instructions)
+        | InstructionKind::Break(instructions)
+        | InstructionKind::Recur(instructions)
+        | InstructionKind::BreakFn(instructions)
+        | InstructionKind::RecurFn(instructions)
+        | InstructionKind::Call {
+            args: instructions, ..
+        }
+        | InstructionKind::Clif {
+            args: instructions, ..
+        } => {
+            for instruction in instructions {
+                visitor.visit_instruction(instruction);
+            }
+        }
+        InstructionKind::If {
+            condition,
+            consequent,
+            alternative,
+            ..
+        } => {
+            visitor.visit_instruction(condition);
+            visitor.visit_instruction(consequent);
+            visitor.visit_instruction(alternative);
+        }
+        InstructionKind::When {
+            condition, body, ..
+        } => {
+            visitor.visit_instruction(condition);
+            for instruction in body {
+                visitor.visit_instruction(instruction);
+            }
+        }
+        InstructionKind::For { params, body, .. } => {
+            for (local, init) in params {
+                visitor.visit_instruction(init);
+                visitor.visit_local(local);
+            }
+            for instruction in body {
+                visitor.visit_instruction(instruction);
+            }
+        }
+        InstructionKind::DynCall { callee, args, .. } => {
+            visitor.visit_instruction(callee);
+            for instruction in args {
+                visitor.visit_instruction(instruction);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        ast::{FunctionNode, Instruction, InstructionKind, LocalNode},
+        parser::parse_module,
+        visitor::{walk_function, walk_instruction, Visitor},
+    };
+
+    /// The linter which finds the unused local variables.
+    #[derive(Default)]
+    struct UnusedLocalLinter<'ast> {
+        declared: Vec<&'ast str>,
+        used: Vec<&'ast str>,
+        unused: Vec<String>,
+    }
+
+    impl<'ast> Visitor<'ast> for UnusedLocalLinter<'ast> {
+        fn visit_function(&mut self, function: &'ast FunctionNode) {
+            self.declared.clear();
+            self.used.clear();
+            walk_function(self, function);
+
+            for name in &self.declared {
+                if !self.used.contains(name) {
+                    self.unused.push(format!("{}::{}", function.name, name));
+                }
+            }
+        }
+
+        fn visit_local(&mut self, local: &'ast LocalNode) {
+            self.declared.push(&local.name);
+        }
+
+        fn visit_instruction(&mut self, instruction: &'ast Instruction) {
+            if let InstructionKind::LocalLoad(name) = &instruction.kind {
+                self.used.push(name);
+            }
+            walk_instruction(self, instruction);
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let source = r#"
+        (module $app
+            (import (function $print (param i32)))
+            (function $sum (param $n i32) (param $unused i32) (result i32) (local $temp i64)
+                (code
+                    (for (param $i i32 (imm_i32 0)) (param $acc i32 (imm_i32 0)) (result i32)
+                        (when (gt_i32_s (local_load $i) (local_load $n))
+                            (break (local_load $acc)))
+                        (recur
+                            (add_i32 (local_load $i) (imm_i32 1))
+                            (add_i32 (local_load $acc) (local_load $i))))))
+            (function $main (result i32)
+                (code
+                    (call $print (call $sum (imm_i32 10) (imm_i32 0)))
+                    (imm_i32 0))))
+        "#;
+
+        let module = parse_module(source).unwrap();
+
+        let mut linter = UnusedLocalLinter::default();
+        linter.visit_module(&module);
+        assert_eq!(linter.unused, vec!["sum::unused", "sum::temp"]);
+
+        // the closure-like visitor which collects the called functions in order
+        struct CallCollector<'ast>(Vec<&'ast str>);
+        impl<'ast> Visitor<'ast> for CallCollector<'ast> {
+            fn visit_instruction(&mut self, instruction: &'ast Instruction) {
+                if let InstructionKind::Call { name, .. } = &instruction.kind {
+                    self.0.push(name);
+                }
+                walk_instruction(self, instruction);
+            }
+        }
+
+        let mut collector = CallCollector(vec![]);
+        collector.visit_module(&module);
+        assert_eq!(collector.0, vec!["print", "sum"]);
+    }
+}
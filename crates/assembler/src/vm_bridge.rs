@@ -0,0 +1,390 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_codegen::ir::{
+    types, AbiParam, Function, InstBuilder, MemFlags, Signature, StackSlot, StackSlotData,
+    StackSlotKind, Type, UserFuncName, Value,
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{FuncId, Linkage, Module, ModuleError};
+
+use crate::code_generator::Generator;
+
+// The bridge functions of the XiaoXuan Core VM
+// --------------------------------------------
+//
+// The functions of the VM pass the arguments and the results by the operand
+// stack, each operand occupies 8 bytes (the 32-bit values take the low 4 bytes),
+// the bridge functions convert between the operand stack and the native
+// calling convention:
+//
+// 1. VM -> native: the VM calls the native function (e.g. the AOT compiled
+//    function) through the bridge:
+//
+//    ```rust
+//    extern "C" fn bridge(params: *const u64, results: *mut u64)
+//    ```
+//
+//    the bridge loads the arguments from `params`, calls the native function,
+//    and stores the results to `results`.
+//
+// 2. native -> VM: the native code (e.g. a callback of a C library) calls the
+//    VM function through the bridge, which has the native signature, e.g.
+//
+//    ```rust
+//    extern "C" fn bridge(a: i32, b: f64) -> f64
+//    ```
+//
+//    the bridge stores the arguments into a buffer (a stack slot), and calls
+//    the dispatcher of the VM runtime with the index of the target VM function:
+//
+//    ```rust
+//    extern "C" fn dispatcher(function_index: u32, params: *const u64, results: *mut u64)
+//    ```
+//
+//    then loads the results from the buffer and returns them.
+//
+// ref:
+// - https://github.com/hemashushu/xiaoxuan-core
+
+/// The size of an operand of the VM operand stack.
+pub const OPERAND_SIZE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmOperandType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl VmOperandType {
+    pub fn to_ir_type(self) -> Type {
+        match self {
+            VmOperandType::I32 => types::I32,
+            VmOperandType::I64 => types::I64,
+            VmOperandType::F32 => types::F32,
+            VmOperandType::F64 => types::F64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VmFunctionType {
+    pub params: Vec<VmOperandType>,
+    pub results: Vec<VmOperandType>,
+}
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    pub(crate) fn make_native_signature(&self, vm_function_type: &VmFunctionType) -> Signature {
+        let mut sig = self.module.make_signature();
+        sig.params.extend(
+            vm_function_type
+                .params
+                .iter()
+                .map(|item| AbiParam::new(item.to_ir_type())),
+        );
+        sig.returns.extend(
+            vm_function_type
+                .results
+                .iter()
+                .map(|item| AbiParam::new(item.to_ir_type())),
+        );
+        sig
+    }
+
+    /// Define the bridge function which is called by the VM, and calls the
+    /// given native function.
+    ///
+    /// The signature of the native function should match the VM function type.
+    pub fn define_vm_to_native_bridge(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        native_func_id: FuncId,
+        vm_function_type: &VmFunctionType,
+    ) -> Result<FuncId, ModuleError> {
+        let native_sig = self.make_native_signature(vm_function_type);
+        let native_decl = self.module.declarations().get_function_decl(native_func_id);
+
+        if native_decl.signature != native_sig {
+            return Err(ModuleError::IncompatibleSignature(
+                native_decl.linkage_name(native_func_id).into_owned(),
+                native_sig,
+                native_decl.signature.clone(),
+            ));
+        }
+
+        let pointer_type = self.module.isa().pointer_type();
+        let mut bridge_sig = self.module.make_signature();
+        bridge_sig.params.push(AbiParam::new(pointer_type));
+        bridge_sig.params.push(AbiParam::new(pointer_type));
+
+        let bridge_id = self.module.declare_function(name, linkage, &bridge_sig)?;
+
+        let mut func_bridge =
+            Function::with_name_signature(UserFuncName::user(0, bridge_id.as_u32()), bridge_sig);
+        let native_ref = self
+            .module
+            .declare_func_in_func(native_func_id, &mut func_bridge);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_bridge, &mut self.function_builder_context);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let value_params = function_builder.block_params(block)[0];
+        let value_results = function_builder.block_params(block)[1];
+        let flags = MemFlags::trusted();
+
+        let args = vm_function_type
+            .params
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| {
+                function_builder.ins().load(
+                    item.to_ir_type(),
+                    flags,
+                    value_params,
+                    (idx as u32 * OPERAND_SIZE) as i32,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let call = function_builder.ins().call(native_ref, &args);
+        let results = function_builder.inst_results(call).to_vec();
+
+        for (idx, value) in results.into_iter().enumerate() {
+            function_builder.ins().store(
+                flags,
+                value,
+                value_results,
+                (idx as u32 * OPERAND_SIZE) as i32,
+            );
+        }
+
+        function_builder.ins().return_(&[]);
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        self.define_function(bridge_id, func_bridge)?;
+        Ok(bridge_id)
+    }
+
+    /// Define the bridge function which has the native signature, and calls
+    /// the VM function (by the dispatcher of the VM runtime).
+    pub fn define_native_to_vm_bridge(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        dispatcher_name: &str,
+        function_index: u32,
+        vm_function_type: &VmFunctionType,
+    ) -> Result<FuncId, ModuleError> {
+        let pointer_type = self.module.isa().pointer_type();
+
+        let mut dispatcher_sig = self.module.make_signature();
+        dispatcher_sig.params.push(AbiParam::new(types::I32));
+        dispatcher_sig.params.push(AbiParam::new(pointer_type));
+        dispatcher_sig.params.push(AbiParam::new(pointer_type));
+
+        let dispatcher_id =
+            self.module
+                .declare_function(dispatcher_name, Linkage::Import, &dispatcher_sig)?;
+
+        let bridge_sig = self.make_native_signature(vm_function_type);
+        let bridge_id = self.module.declare_function(name, linkage, &bridge_sig)?;
+
+        let mut func_bridge =
+            Function::with_name_signature(UserFuncName::user(0, bridge_id.as_u32()), bridge_sig);
+        let dispatcher_ref = self
+            .module
+            .declare_func_in_func(dispatcher_id, &mut func_bridge);
+
+        let mut function_builder =
+            FunctionBuilder::new(&mut func_bridge, &mut self.function_builder_context);
+
+        let stack_slot = create_operand_buffer(&mut function_builder, vm_function_type);
+
+        let block = function_builder.create_block();
+        function_builder.append_block_params_for_function_params(block);
+        function_builder.switch_to_block(block);
+
+        let args = function_builder.block_params(block).to_vec();
+        emit_store_operands(&mut function_builder, stack_slot, &args);
+
+        let value_index = function_builder
+            .ins()
+            .iconst(types::I32, function_index as i64);
+        let value_buffer = function_builder
+            .ins()
+            .stack_addr(pointer_type, stack_slot, 0);
+        function_builder
+            .ins()
+            .call(dispatcher_ref, &[value_index, value_buffer, value_buffer]);
+
+        let results =
+            emit_load_operands(&mut function_builder, stack_slot, &vm_function_type.results);
+
+        function_builder.ins().return_(&results);
+        function_builder.seal_all_blocks();
+        function_builder.finalize();
+
+        self.define_function(bridge_id, func_bridge)?;
+        Ok(bridge_id)
+    }
+}
+
+/// Create the buffer (a stack slot) of the operands, it is shared by
+/// the arguments and the results.
+pub(crate) fn create_operand_buffer(
+    function_builder: &mut FunctionBuilder,
+    vm_function_type: &VmFunctionType,
+) -> StackSlot {
+    let operand_count = vm_function_type
+        .params
+        .len()
+        .max(vm_function_type.results.len())
+        .max(1) as u32;
+
+    function_builder.create_sized_stack_slot(StackSlotData::new(
+        StackSlotKind::ExplicitSlot,
+        operand_count * OPERAND_SIZE,
+        3,
+    ))
+}
+
+pub(crate) fn emit_store_operands(
+    function_builder: &mut FunctionBuilder,
+    stack_slot: StackSlot,
+    values: &[Value],
+) {
+    for (idx, value) in values.iter().enumerate() {
+        function_builder
+            .ins()
+            .stack_store(*value, stack_slot, (idx as u32 * OPERAND_SIZE) as i32);
+    }
+}
+
+pub(crate) fn emit_load_operands(
+    function_builder: &mut FunctionBuilder,
+    stack_slot: StackSlot,
+    operand_types: &[VmOperandType],
+) -> Vec<Value> {
+    operand_types
+        .iter()
+        .enumerate()
+        .map(|(idx, item)| {
+            function_builder.ins().stack_load(
+                item.to_ir_type(),
+                stack_slot,
+                (idx as u32 * OPERAND_SIZE) as i32,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use cranelift_codegen::ir::{types, AbiParam};
+    use cranelift_jit::JITModule;
+    use cranelift_module::{Linkage, Module};
+
+    use crate::{
+        code_generator::Generator,
+        vm_bridge::{VmFunctionType, VmOperandType},
+    };
+
+    static DISPATCHED: Mutex<Vec<u32>> = Mutex::new(vec![]);
+
+    extern "C" fn native_add(a: i32, b: i64) -> i64 {
+        a as i64 + b
+    }
+
+    extern "C" fn vm_dispatcher(function_index: u32, params: *const u64, results: *mut u64) {
+        DISPATCHED.lock().unwrap().push(function_index);
+
+        // the VM function: fn (a: i32, b: f64) -> f64 { a * b }
+        let (a, b) = unsafe { (*(params as *const i32), *(params.add(1) as *const f64)) };
+        unsafe { *(results as *mut f64) = a as f64 * b };
+    }
+
+    #[test]
+    fn test_vm_bridge() {
+        let symbols = vec![
+            ("native_add".to_owned(), native_add as *const u8),
+            ("vm_dispatcher".to_owned(), vm_dispatcher as *const u8),
+        ];
+        let mut generator = Generator::<JITModule>::new(symbols);
+
+        // VM -> native
+        let mut native_add_sig = generator.module.make_signature();
+        native_add_sig.params.push(AbiParam::new(types::I32));
+        native_add_sig.params.push(AbiParam::new(types::I64));
+        native_add_sig.returns.push(AbiParam::new(types::I64));
+
+        let native_add_id = generator
+            .module
+            .declare_function("native_add", Linkage::Import, &native_add_sig)
+            .unwrap();
+
+        let native_add_type = VmFunctionType {
+            params: vec![VmOperandType::I32, VmOperandType::I64],
+            results: vec![VmOperandType::I64],
+        };
+
+        let bridge_0_id = generator
+            .define_vm_to_native_bridge("bridge_0", Linkage::Local, native_add_id, &native_add_type)
+            .unwrap();
+
+        // the mismatched signature
+        assert!(generator
+            .define_vm_to_native_bridge(
+                "bridge_1",
+                Linkage::Local,
+                native_add_id,
+                &VmFunctionType::default()
+            )
+            .is_err());
+
+        // native -> VM
+        let vm_mul_type = VmFunctionType {
+            params: vec![VmOperandType::I32, VmOperandType::F64],
+            results: vec![VmOperandType::F64],
+        };
+
+        let bridge_2_id = generator
+            .define_native_to_vm_bridge(
+                "bridge_2",
+                Linkage::Local,
+                "vm_dispatcher",
+                7,
+                &vm_mul_type,
+            )
+            .unwrap();
+
+        generator.module.finalize_definitions().unwrap();
+
+        let bridge_0: extern "C" fn(*const u64, *mut u64) =
+            unsafe { std::mem::transmute(generator.module.get_finalized_function(bridge_0_id)) };
+        let params: [u64; 2] = [(-3i32) as u32 as u64, 10];
+        let mut results: [u64; 1] = [0];
+        bridge_0(params.as_ptr(), results.as_mut_ptr());
+        assert_eq!(results[0], 7);
+
+        let bridge_2: extern "C" fn(i32, f64) -> f64 =
+            unsafe { std::mem::transmute(generator.module.get_finalized_function(bridge_2_id)) };
+        assert_eq!(bridge_2(3, 1.5), 4.5);
+        assert_eq!(*DISPATCHED.lock().unwrap(), vec![7]);
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (c) 2024 Hemashushu <hippospark@gmail.com>, All rights reserved.
+//
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License version 2.0 and additional exceptions,
+// more details in file LICENSE, LICENSE.additional and CONTRIBUTING.
+
+use cranelift_module::{DataId, Linkage, Module, ModuleError};
+
+use crate::code_generator::{DataDefinition, Generator};
+
+// The placement of the zero data
+// ------------------------------
+//
+// by default, the uninitialized data (i.e. `define_uninitialized_data()`) is
+// always writable and is placed in `.bss`, which takes no space in the object
+// file, and the initialized data is placed in `.data` or `.rodata` with its
+// content even if the content is all zeros, e.g. `(read_only zero 4194304)`
+// takes 4 MiB in the file.
+//
+// the placement of the large zero data can be controlled:
+//
+// - `define_zero_data()` defines the zero data which can be read-only, the
+//   read-only one is placed in `.rodata` with the explicit zero bytes (i.e.
+//   the zero pages are mapped as read-only), and the writable one is placed
+//   in `.bss`.
+// - `enable_zero_data_in_bss(min_size)` moves the initialized data whose
+//   content is all zeros and whose size is at least `min_size` to `.bss`,
+//   including the read-only data, i.e. the read-only data becomes writable
+//   in memory, it trades the protection for the size of the binary.
+//
+// the data which contains the addresses (i.e. the relocations) or is placed
+// in a custom section (see `define_data_in_section()`) is not moved.
+//
+// ref:
+// - https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.sheader.html#special_sections
+
+impl<T> Generator<T>
+where
+    T: Module,
+{
+    /// Move the initialized data whose content is all zeros and whose size
+    /// is at least `min_size` bytes to `.bss`.
+    pub fn enable_zero_data_in_bss(&mut self, min_size: usize) {
+        self.zero_data_min_size = Some(min_size);
+    }
+
+    /// Define the zero data, the read-only data is placed in `.rodata` with
+    /// the explicit zero bytes (unless it is moved by `enable_zero_data_in_bss()`),
+    /// and the writable data is placed in `.bss`.
+    pub fn define_zero_data(
+        &mut self,
+        name: &str,
+        size: usize,
+        align: u64,
+        export: bool,
+        writable: bool,
+    ) -> Result<DataId, ModuleError> {
+        if writable {
+            return self.define_uninitialized_data(name, size, align, export, false);
+        }
+
+        let linkage = if export {
+            Linkage::Export
+        } else {
+            Linkage::Local
+        };
+
+        let data_id = self.module.declare_data(name, linkage, false, false)?;
+        self.define_data_content(
+            data_id,
+            DataDefinition::Initialized {
+                data: vec![0; size],
+                align,
+            },
+        )?;
+        Ok(data_id)
+    }
+
+    /// Convert the large initialized zero data to the uninitialized data,
+    /// it is called before the data is defined.
+    pub(crate) fn place_zero_data(&self, data_definition: DataDefinition) -> DataDefinition {
+        let Some(min_size) = self.zero_data_min_size else {
+            return data_definition;
+        };
+
+        let data_description = &self.data_description;
+        if data_description.custom_segment_section.is_some()
+            || !data_description.function_relocs.is_empty()
+            || !data_description.data_relocs.is_empty()
+        {
+            return data_definition;
+        }
+
+        match data_definition {
+            DataDefinition::Initialized { data, align }
+                if data.len() >= min_size && data.iter().all(|byte| *byte == 0) =>
+            {
+                DataDefinition::Uninitialized {
+                    size: data.len(),
+                    align,
+                }
+            }
+            _ => data_definition,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+    use cranelift_object::{
+        object::{File, Object, ObjectSection, ObjectSymbol, SectionKind},
+        ObjectModule,
+    };
+    use pretty_assertions::assert_eq;
+
+    use crate::{code_generator::Generator, lowering::assemble_module, parser::parse_module};
+
+    const TABLE_SIZE: usize = 1 << 20;
+
+    /// Get the kind of the section of each symbol.
+    fn get_section_kinds(binary: &[u8], names: &[&str]) -> Vec<SectionKind> {
+        let file = File::parse(binary).unwrap();
+        names
+            .iter()
+            .map(|name| {
+                let symbol = file
+                    .symbols()
+                    .find(|symbol| symbol.name() == Ok(name))
+                    .unwrap();
+                let section_index = symbol.section_index().unwrap();
+                file.section_by_index(section_index).unwrap().kind()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zero_data() {
+        let define_tables = |generator: &mut Generator<ObjectModule>| {
+            generator
+                .define_zero_data("read_only_table", TABLE_SIZE, 8, true, false)
+                .unwrap();
+            generator
+                .define_zero_data("writable_table", TABLE_SIZE, 8, true, true)
+                .unwrap();
+            generator
+                .define_initialized_data("small", vec![0; 16], 8, true, true, false)
+                .unwrap();
+            generator
+                .define_initialized_data("numbers", vec![1; TABLE_SIZE], 8, true, false, false)
+                .unwrap();
+        };
+        let names = ["read_only_table", "writable_table", "small", "numbers"];
+
+        // the read-only zero data takes the space of the file
+        let mut generator = Generator::<ObjectModule>::new("tables", None);
+        define_tables(&mut generator);
+        let binary = generator.finish().unwrap().emit().unwrap();
+        assert!(binary.len() > TABLE_SIZE * 2);
+        assert_eq!(
+            get_section_kinds(&binary, &names),
+            vec![
+                SectionKind::ReadOnlyData,
+                SectionKind::UninitializedData,
+                SectionKind::Data,
+                SectionKind::ReadOnlyData
+            ]
+        );
+
+        // the large zero data is moved to `.bss`
+        let mut generator = Generator::<ObjectModule>::new("tables", None);
+        generator.enable_zero_data_in_bss(4096);
+        define_tables(&mut generator);
+        let binary = generator.finish().unwrap().emit().unwrap();
+        assert!(binary.len() < TABLE_SIZE * 2);
+        assert_eq!(
+            get_section_kinds(&binary, &names),
+            vec![
+                SectionKind::UninitializedData,
+                SectionKind::UninitializedData,
+                SectionKind::Data,
+                SectionKind::ReadOnlyData
+            ]
+        );
+
+        // the assembly, the moved data is still zeros
+        let module = parse_module(
+            r#"
+            (module $tables
+                (data $table (read_only zero 1048576))
+                (function $get_last (result i64) (code (data_load_i64 $table 1048568))))
+            "#,
+        )
+        .unwrap();
+        let mut generator = Generator::<JITModule>::new(vec![]);
+        generator.enable_zero_data_in_bss(4096);
+        let assembled_module = assemble_module(&module, &mut generator).unwrap();
+        generator.module.finalize_definitions().unwrap();
+
+        let func_get_last = unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn() -> i64>(
+                generator
+                    .module
+                    .get_finalized_function(assembled_module.get_function_id("get_last").unwrap()),
+            )
+        };
+        assert_eq!(func_get_last(), 0);
+    }
+}